@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+# Fills the `<NAME>` KV namespace and D1 database id placeholders of a
+# wrangler.toml from the repository variable of the same name, so ids stay
+# per environment instead of being committed.
+#
+# usage: BINDING_IDS='${{ toJSON(vars) }}' fill-binding-ids.sh workers/<worker>/wrangler.toml
+set -euo pipefail
+
+config="$1"
+missing=0
+
+for name in $(grep -oE '"<[A-Z0-9_]+>"' "$config" | tr -d '"<>' | sort -u); do
+  value=$(jq -r --arg name "$name" '.[$name] // empty' <<<"${BINDING_IDS:-null}")
+  if [ -z "$value" ]; then
+    echo "::error file=$config::repository variable $name is not set"
+    missing=1
+    continue
+  fi
+  sed -i "s|\"<$name>\"|\"$value\"|g" "$config"
+done
+
+exit "$missing"
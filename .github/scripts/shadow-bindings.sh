@@ -0,0 +1,15 @@
+#!/usr/bin/env bash
+# Points a wrangler.toml at a shadow's own bindings, so copies of production
+# requests never write production KV or reach production queue consumers.
+# KV and D1 placeholders `<NAME>` become `<NAME_SHADOW>`, filled in by
+# fill-binding-ids.sh, and queue `name` becomes `name-shadow`.
+#
+# usage: shadow-bindings.sh workers/<worker>/wrangler.toml
+set -euo pipefail
+
+config="$1"
+
+sed -i -E \
+  -e 's/"<([A-Z0-9_]+)>"/"<\1_SHADOW>"/g' \
+  -e 's/^(queue = "[a-z0-9-]+)"/\1-shadow"/' \
+  "$config"
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::{Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+/// a request a worker made to the backend
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    pub method: Method,
+    pub path: String,
+    /// `Value::Null` for empty or non JSON bodies
+    pub body: Value,
+}
+
+#[derive(Default)]
+struct Inner {
+    responses: HashMap<(Method, String), Value>,
+    recorded: Vec<Recorded>,
+}
+
+/// stands in for the services a worker calls, answers with canned JSON and records every request
+///
+/// unknown routes are 404s, stopped when dropped
+pub struct MockBackend {
+    url: String,
+    inner: Arc<Mutex<Inner>>,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl MockBackend {
+    pub async fn start() -> Self {
+        let inner = Arc::new(Mutex::new(Inner::default()));
+        let app = Router::new().fallback(handle).with_state(inner.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("mock backend bind");
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.expect("mock backend");
+        });
+
+        Self { url, inner, server }
+    }
+
+    /// with a trailing slash, passed to the worker as `MOCK_BACKEND_URL`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn respond(&self, method: Method, path: &str, body: Value) -> &Self {
+        self.inner
+            .lock()
+            .unwrap()
+            .responses
+            .insert((method, path.to_string()), body);
+        self
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.inner.lock().unwrap().recorded.clone()
+    }
+
+    /// the first recorded request matching `pred`, queue consumers reach the backend late
+    pub async fn wait_for(
+        &self,
+        timeout: Duration,
+        pred: impl Fn(&Recorded) -> bool,
+    ) -> Option<Recorded> {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            if let Some(found) = self.requests().into_iter().find(&pred) {
+                return Some(found);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        None
+    }
+}
+
+impl Drop for MockBackend {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn handle(
+    State(inner): State<Arc<Mutex<Inner>>>,
+    method: Method,
+    uri: Uri,
+    body: String,
+) -> Response {
+    let path = uri.path().to_string();
+    let mut inner = inner.lock().unwrap();
+    let response = inner
+        .responses
+        .get(&(method.clone(), path.clone()))
+        .cloned();
+    inner.recorded.push(Recorded {
+        method,
+        path,
+        body: serde_json::from_str(&body).unwrap_or(Value::Null),
+    });
+
+    match response {
+        Some(body) => Json(body).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,31 @@
+//! end to end tests, each worker runs under `wrangler dev` built for `RunEnv::Mock`
+//!
+//! mock builds skip JWT signatures and use no-op treasuries, anything else a flow reaches
+//! outside the worker is served by a `MockBackend`. starting a worker builds it, so the tests
+//! only run with `YRAL_E2E=1`:
+//!
+//! ```sh
+//! npm ci && YRAL_E2E=1 cargo test -p yral-e2e-tests -- --test-threads 1
+//! ```
+
+pub mod backend;
+pub mod worker;
+
+pub use backend::{MockBackend, Recorded};
+pub use worker::WorkerProcess;
+
+/// whether the e2e tests were asked for, they pass without running otherwise
+pub fn enabled() -> bool {
+    std::env::var("YRAL_E2E").is_ok_and(|v| v == "1")
+}
+
+/// returns from the test unless `YRAL_E2E=1`
+#[macro_export]
+macro_rules! require_e2e {
+    () => {
+        if !$crate::enabled() {
+            eprintln!("skipped, set YRAL_E2E=1 to run the e2e tests");
+            return;
+        }
+    };
+}
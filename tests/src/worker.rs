@@ -0,0 +1,101 @@
+use std::{
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use tokio::process::{Child, Command};
+
+/// the first start builds the worker, later ones reuse the build
+const START_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// a worker under `wrangler dev`, stopped on drop
+pub struct WorkerProcess {
+    name: &'static str,
+    port: u16,
+    child: Child,
+    persist_to: PathBuf,
+}
+
+impl WorkerProcess {
+    /// builds `workers/{name}` with `ENV=mock` and waits for its `/healthz`
+    ///
+    /// `vars` are passed with `--var`, secrets included, local state starts empty
+    pub async fn start(name: &'static str, vars: &[(&str, &str)]) -> Self {
+        let port = free_port();
+        let persist_to = std::env::temp_dir().join(format!("yral-e2e-{name}-{port}"));
+        let mut cmd = Command::new("npx");
+        cmd.current_dir(workspace_root().join("workers").join(name))
+            .env("ENV", "mock")
+            .args(["wrangler", "dev", "--ip", "127.0.0.1"])
+            .args(["--port", &port.to_string()])
+            .args(["--inspector-port", &free_port().to_string()])
+            .arg("--persist-to")
+            .arg(&persist_to);
+        for (key, value) in vars {
+            cmd.arg("--var").arg(format!("{key}:{value}"));
+        }
+        let child = cmd
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap_or_else(|e| panic!("failed to start wrangler dev for {name}: {e}"));
+
+        let mut worker = Self {
+            name,
+            port,
+            child,
+            persist_to,
+        };
+        worker.wait_until_healthy().await;
+
+        worker
+    }
+
+    async fn wait_until_healthy(&mut self) {
+        let client = reqwest::Client::new();
+        let started = Instant::now();
+        loop {
+            if let Ok(res) = client.get(self.url("/healthz")).send().await {
+                if res.status().is_success() {
+                    return;
+                }
+            }
+            if let Some(status) = self.child.try_wait().expect("wrangler dev status") {
+                panic!("wrangler dev for {} exited with {status}", self.name);
+            }
+            assert!(
+                started.elapsed() < START_TIMEOUT,
+                "{} wasn't healthy after {START_TIMEOUT:?}",
+                self.name
+            );
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{path}", self.port)
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+        let _ = std::fs::remove_dir_all(&self.persist_to);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port()
+}
+
+fn workspace_root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("tests/ is in the workspace root")
+}
@@ -0,0 +1,108 @@
+//! vote -> balance -> withdraw against yral-hot-or-not
+
+use candid::Principal;
+use hon_worker_common::{
+    hon_game_vote_msg_v4, HoNGameVoteReqV4, HotOrNot, SatsBalanceInfoV2, SatsBalanceUpdateRequest,
+    VoteRequestV4,
+};
+use ic_agent::{identity::BasicIdentity, Identity};
+use num_bigint::{BigInt, BigUint};
+use serde_json::{json, Value};
+use yral_e2e_tests::{require_e2e, WorkerProcess};
+
+/// mock builds don't verify JWT signatures, the header still has to be there
+const JWT: &str = "Bearer e2e";
+
+async fn balance(worker: &WorkerProcess, user: Principal) -> BigUint {
+    reqwest::get(worker.url(&format!("/v2/balance/{user}")))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json::<SatsBalanceInfoV2>()
+        .await
+        .unwrap()
+        .balance
+}
+
+#[tokio::test]
+async fn vote_then_withdraw() {
+    require_e2e!();
+    let worker = WorkerProcess::start("yral-hot-or-not", &[]).await;
+    let client = reqwest::Client::new();
+    let identity = BasicIdentity::from_raw_key(&[7; 32]);
+    let user = identity.sender().unwrap();
+
+    let initial = balance(&worker, user).await;
+    client
+        .post(worker.url(&format!("/update_balance/{user}")))
+        .header("Authorization", JWT)
+        .json(&SatsBalanceUpdateRequest {
+            delta: BigInt::from(1000),
+            is_airdropped: false,
+        })
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let funded = balance(&worker, user).await;
+    assert_eq!(funded, initial + 1000u32);
+
+    // the vote wins when it matches the sentiment, paying 80% of the amount
+    let request = VoteRequestV4 {
+        publisher_principal: Principal::anonymous(),
+        post_id: "e2e-post".into(),
+        vote_amount: 100,
+        direction: HotOrNot::Hot,
+    };
+    let signature =
+        yral_identity::ic_agent::sign_message(&identity, hon_game_vote_msg_v4(request.clone()))
+            .unwrap();
+    let vote = HoNGameVoteReqV4 {
+        request,
+        fetched_sentiment: HotOrNot::Hot,
+        post_creator: None,
+        signature,
+    };
+    let res = client
+        .post(worker.url(&format!("/v4/vote/{user}")))
+        .header("Authorization", JWT)
+        .json(&vote)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success(), "vote: {}", res.status());
+    assert_eq!(balance(&worker, user).await, funded.clone() + 80u32);
+
+    // a second vote on the same post is refused and leaves the balance alone
+    let res = client
+        .post(worker.url(&format!("/v4/vote/{user}")))
+        .header("Authorization", JWT)
+        .json(&vote)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+    assert_eq!(balance(&worker, user).await, funded + 80u32);
+
+    // the treasury is a no-op in mock builds
+    let res: Value = client
+        .post(worker.url("/v2/transfer_ckbtc"))
+        .header("Authorization", JWT)
+        .json(&json!({
+            "amount": 50,
+            "memo_text": "e2e",
+            "recipient_principal": user.to_text(),
+        }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(res["success"], true);
+    assert_eq!(res["recipient"], user.to_text());
+}
@@ -0,0 +1,146 @@
+//! product events, aggregated by the `yral-analytics` worker
+//!
+//! producers send an `AnalyticsEvent` to `ANALYTICS_EVENTS_QUEUE`, either directly or through
+//! an `Outbox` when the event is recorded by a durable object, yral-analytics then forwards
+//! them to the `yral-activity` feeds and to `yral-rewards`
+
+use serde::{Deserialize, Serialize};
+
+use crate::trace::TraceId;
+
+pub const ANALYTICS_EVENTS_QUEUE: &str = "ANALYTICS_EVENTS";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AnalyticsEvent {
+    Vote {
+        user_principal: String,
+        post_id: String,
+        creator_principal: Option<String>,
+        vote_amount: u64,
+        won: bool,
+        /// sats won or lost
+        amount: u64,
+        /// unix millis
+        at: u64,
+        /// trace of the request that placed the vote
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    VideoPublished {
+        user_principal: String,
+        post_id: String,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    Claim {
+        user_principal: String,
+        /// pump-n-dump's DOLR, as a decimal string
+        amount: String,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    /// `user_principal` referred `referee_principal`
+    Referral {
+        user_principal: String,
+        referee_principal: String,
+        /// sats rewarded to the referrer
+        amount: u64,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    /// Stream failed to process `video_uid` and no upload picked it up
+    VideoUploadFailed {
+        user_principal: String,
+        video_uid: String,
+        reason: String,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    /// `user_principal` joined, through `referrer_principal`'s referral if set
+    Signup {
+        user_principal: String,
+        #[serde(default)]
+        referrer_principal: Option<String>,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+}
+
+impl AnalyticsEvent {
+    /// the serde tag, e.g. `video_published`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Vote { .. } => "vote",
+            Self::VideoPublished { .. } => "video_published",
+            Self::Claim { .. } => "claim",
+            Self::Referral { .. } => "referral",
+            Self::VideoUploadFailed { .. } => "video_upload_failed",
+            Self::Signup { .. } => "signup",
+        }
+    }
+
+    pub fn user_principal(&self) -> &str {
+        match self {
+            Self::Vote { user_principal, .. }
+            | Self::VideoPublished { user_principal, .. }
+            | Self::Claim { user_principal, .. }
+            | Self::Referral { user_principal, .. }
+            | Self::VideoUploadFailed { user_principal, .. }
+            | Self::Signup { user_principal, .. } => user_principal,
+        }
+    }
+
+    pub fn at(&self) -> u64 {
+        match self {
+            Self::Vote { at, .. }
+            | Self::VideoPublished { at, .. }
+            | Self::Claim { at, .. }
+            | Self::Referral { at, .. }
+            | Self::VideoUploadFailed { at, .. }
+            | Self::Signup { at, .. } => *at,
+        }
+    }
+
+    /// sends the event to `ANALYTICS_EVENTS_QUEUE`, for producers without an outbox
+    #[cfg(feature = "queue")]
+    pub async fn send(&self, env: &worker::Env) -> worker::Result<()> {
+        env.queue(ANALYTICS_EVENTS_QUEUE)?.send(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn votes_keep_their_wire_format() {
+        let event: AnalyticsEvent = serde_json::from_value(json!({
+            "event": "vote",
+            "user_principal": "2vxsx-fae",
+            "post_id": "1",
+            "creator_principal": null,
+            "vote_amount": 10,
+            "won": true,
+            "amount": 20,
+            "at": 1_700_000_000_000u64,
+        }))
+        .unwrap();
+
+        assert_eq!(event.kind(), "vote");
+        assert_eq!(event.user_principal(), "2vxsx-fae");
+        assert_eq!(event.at(), 1_700_000_000_000);
+    }
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+use worker::{Response, Result};
+
+/// json body of every error response
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ApiError {
+    /// stable identifier clients can match on, e.g. `InsufficientFunds`
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl ApiError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: impl Serialize) -> Self {
+        self.details = serde_json::to_value(details).ok().filter(|v| !v.is_null());
+        self
+    }
+
+    /// generic error for a status code, used in place of `Response::error`
+    pub fn from_status(status: u16, message: impl Into<String>) -> Self {
+        let code = match status {
+            400 => "BadRequest",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "NotFound",
+            409 => "Conflict",
+            413 => "PayloadTooLarge",
+            429 => "RateLimited",
+            503 => "Unavailable",
+            500..=599 => "Internal",
+            _ => "Error",
+        };
+        Self::new(code, message)
+    }
+
+    /// builds the envelope from a serde enum
+    /// the variant name is the code, a string payload is the message
+    /// and any other payload ends up in `details`
+    pub fn from_serialized(e: &impl Serialize) -> Self {
+        let value = match serde_json::to_value(e) {
+            Ok(v) => v,
+            Err(e) => return Self::new("Internal", e.to_string()),
+        };
+
+        match value {
+            Value::String(variant) if is_variant_name(&variant) => {
+                let message = humanize(&variant);
+                Self::new(variant, message)
+            }
+            // plain string errors
+            Value::String(message) => Self::new("Error", message),
+            Value::Object(map) if map.len() == 1 => {
+                let (variant, payload) = map.into_iter().next().unwrap();
+                match payload {
+                    Value::String(message) => Self::new(variant, message),
+                    payload => {
+                        let message = humanize(&variant);
+                        Self::new(variant, message).with_details(payload)
+                    }
+                }
+            }
+            other => Self::new("Error", "request failed").with_details(other),
+        }
+    }
+
+    /// inverse of `from_serialized`, for callers that still match on the worker's enum
+    pub fn to_enum<T: DeserializeOwned>(&self) -> Option<T> {
+        let payload = match &self.details {
+            Some(details) => details.clone(),
+            None => {
+                if let Ok(v) = serde_json::from_value(Value::String(self.code.clone())) {
+                    return Some(v);
+                }
+                Value::String(self.message.clone())
+            }
+        };
+        let mut map = Map::new();
+        map.insert(self.code.clone(), payload);
+
+        serde_json::from_value(Value::Object(map)).ok()
+    }
+
+    pub fn into_response(self, status: u16) -> Result<Response> {
+        Ok(Response::from_json(&self)?.with_status(status))
+    }
+}
+
+fn is_variant_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_uppercase()) && s.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// `InsufficientFunds` -> `insufficient funds`
+fn humanize(variant: &str) -> String {
+    let mut out = String::with_capacity(variant.len() + 4);
+    for (i, c) in variant.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push(' ');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// drop-in replacement for `Response::error` that responds with an `ApiError`
+pub fn error_resp(message: impl Into<String>, status: u16) -> Result<Response> {
+    ApiError::from_status(status, message).into_response(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    enum TestError {
+        InsufficientFunds,
+        Internal(String),
+        BelowMinimumBalance { floor: u64 },
+    }
+
+    #[test]
+    fn unit_variant_is_code_and_message() {
+        let e = ApiError::from_serialized(&TestError::InsufficientFunds);
+        assert_eq!(e, ApiError::new("InsufficientFunds", "insufficient funds"));
+    }
+
+    #[test]
+    fn string_payload_is_message() {
+        let e = ApiError::from_serialized(&TestError::Internal("boom".into()));
+        assert_eq!(e, ApiError::new("Internal", "boom"));
+    }
+
+    #[test]
+    fn struct_payload_is_details() {
+        let e = ApiError::from_serialized(&TestError::BelowMinimumBalance { floor: 10 });
+        assert_eq!(e.code, "BelowMinimumBalance");
+        assert_eq!(e.details, Some(json!({ "floor": 10 })));
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum RoundTrip {
+        InsufficientFunds,
+        Internal(String),
+        BelowMinimumBalance { floor: u64 },
+    }
+
+    #[test]
+    fn to_enum_round_trips() {
+        for e in [
+            RoundTrip::InsufficientFunds,
+            RoundTrip::Internal("boom".into()),
+            RoundTrip::BelowMinimumBalance { floor: 10 },
+        ] {
+            let api_err = ApiError::from_serialized(&e);
+            assert_eq!(api_err.to_enum::<RoundTrip>(), Some(e));
+        }
+    }
+
+    #[test]
+    fn plain_string_error() {
+        let e = ApiError::from_serialized(&"invalid principal");
+        assert_eq!(e, ApiError::new("Error", "invalid principal"));
+    }
+}
@@ -0,0 +1,311 @@
+//! disaster recovery snapshots of durable objects, taken by yral-backup
+//!
+//! a snapshot is every stored key with its encoded value, restoring writes them back unchanged.
+//! objects register in `BACKUP_REGISTRY` on their first write, yral-backup walks it on schedule
+//!
+//! ```ignore
+//! impl Backup for UserHonGameState { .. }
+//!
+//! async fn fetch(&self, mut req: Request) -> Result<Response> {
+//!     if let Some(res) = backup::handle(self, &mut req).await {
+//!         return res;
+//!     }
+//!     ..
+//! }
+//! ```
+
+use std::result::Result as StdResult;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::*;
+
+use crate::{api_error::ApiError, storage::SafeStorage, time::now_millis};
+
+/// KV of registered objects, keyed by `registry_key`
+pub const BACKUP_REGISTRY: &str = "BACKUP_REGISTRY";
+
+pub const EXPORT_PATH: &str = "/__export";
+pub const RESTORE_PATH: &str = "/__restore";
+
+pub const SNAPSHOT_CONTENT_TYPE: &str = "application/msgpack";
+
+/// set once the object is in the registry, left out of snapshots
+const REGISTERED_KEY: &str = "__backup_registered";
+
+const EXPORT_PAGE_SIZE: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub key: String,
+    /// as `SafeStorage` encoded it
+    #[serde(with = "serde_bytes")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// binding of the object's namespace, e.g. `USER_HON_GAME_STATE`
+    pub namespace: String,
+    /// hex id of the exported object
+    pub object_id: String,
+    /// unix millis
+    pub exported_at: u64,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        rmp_serde::to_vec(self).map_err(|e| Error::RustError(e.to_string()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(bytes).map_err(|e| Error::RustError(e.to_string()))
+    }
+
+    /// nothing stored, e.g. the object was erased since it registered
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// the target of a restore already holds state, restores only go into fresh objects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotEmpty;
+
+/// a per-user durable object that can be snapshotted and restored
+pub trait Backup {
+    /// binding of the object's namespace, the same in every worker binding it
+    const NAMESPACE: &'static str;
+
+    /// alarms aren't part of a snapshot, objects whose alarm works through stored state
+    /// (settlements, an outbox) get one this many millis after a restore
+    const RESTORE_ALARM_MS: Option<i64> = None;
+
+    fn backup_state(&self) -> &State;
+
+    fn backup_env(&self) -> &Env;
+
+    /// drops values cached in memory, storage was rewritten behind them
+    fn invalidate_cached_state(&self);
+}
+
+/// every key in `storage` but the registration marker
+pub async fn export(storage: &SafeStorage, namespace: &str, object_id: &str) -> Result<Snapshot> {
+    let mut entries = vec![];
+    let mut start = None::<String>;
+    loop {
+        let mut options = ListOptions::new().limit(EXPORT_PAGE_SIZE);
+        if let Some(start) = &start {
+            options = options.start(start);
+        }
+        let page = storage.list_bytes(options).await?;
+        let listed = page.len();
+        // `start` is inclusive, the next page starts right after the last key
+        start = page.last().map(|(key, _)| format!("{key}\0"));
+        entries.extend(
+            page.into_iter()
+                .filter(|(key, _)| key != REGISTERED_KEY)
+                .map(|(key, value)| SnapshotEntry { key, value }),
+        );
+
+        if listed < EXPORT_PAGE_SIZE {
+            break;
+        }
+    }
+
+    Ok(Snapshot {
+        namespace: namespace.to_string(),
+        object_id: object_id.to_string(),
+        exported_at: now_millis(),
+        entries,
+    })
+}
+
+/// writes every entry of `snapshot` into `storage`, which must be empty
+///
+/// returns the number of restored keys
+pub async fn restore(
+    storage: &mut SafeStorage,
+    snapshot: &Snapshot,
+) -> Result<StdResult<usize, NotEmpty>> {
+    let stored = storage.list_bytes(ListOptions::new().limit(2)).await?;
+    if stored.iter().any(|(key, _)| key != REGISTERED_KEY) {
+        return Ok(Err(NotEmpty));
+    }
+
+    let mut batch = storage.batch();
+    for entry in &snapshot.entries {
+        batch.put_bytes(&entry.key, entry.value.clone());
+    }
+    batch.commit(storage).await?;
+
+    Ok(Ok(snapshot.entries.len()))
+}
+
+/// key of an object in `BACKUP_REGISTRY`, also the prefix of its snapshots
+pub fn registry_key(namespace: &str, object_id: &str) -> String {
+    format!("{namespace}/{object_id}")
+}
+
+/// adds the object to the registry unless it's there already
+///
+/// the marker is erased with the rest of storage, erased objects register again on their next write
+pub async fn register<T: Backup>(this: &T) -> Result<()> {
+    let mut storage: SafeStorage = this.backup_state().storage().into();
+    if storage
+        .get::<bool>(REGISTERED_KEY)
+        .await?
+        .unwrap_or_default()
+    {
+        return Ok(());
+    }
+
+    let object_id = this.backup_state().id().to_string();
+    this.backup_env()
+        .kv(BACKUP_REGISTRY)?
+        .put(&registry_key(T::NAMESPACE, &object_id), "")?
+        .execute()
+        .await
+        .map_err(|e| Error::RustError(e.to_string()))?;
+    storage.put(REGISTERED_KEY, &true).await
+}
+
+/// answers `EXPORT_PATH` and `RESTORE_PATH`, `None` for any other request
+///
+/// other writes register the object first, a failed registration is retried on the next write
+pub async fn handle<T: Backup>(this: &T, req: &mut Request) -> Option<Result<Response>> {
+    let path = req.path();
+    match (req.method(), path.as_str()) {
+        (Method::Get, EXPORT_PATH) => Some(export_response(this).await),
+        (Method::Post, RESTORE_PATH) => Some(restore_response(this, req).await),
+        (Method::Get, _) => None,
+        _ => {
+            if let Err(e) = register(this).await {
+                console_warn!("failed to register {} for backups: {e}", T::NAMESPACE);
+            }
+            None
+        }
+    }
+}
+
+async fn export_response<T: Backup>(this: &T) -> Result<Response> {
+    let state = this.backup_state();
+    let storage: SafeStorage = state.storage().into();
+    let snapshot = export(&storage, T::NAMESPACE, &state.id().to_string()).await?;
+
+    let mut res = Response::from_bytes(snapshot.to_bytes()?)?;
+    res.headers_mut()
+        .set("Content-Type", SNAPSHOT_CONTENT_TYPE)?;
+    Ok(res)
+}
+
+async fn restore_response<T: Backup>(this: &T, req: &mut Request) -> Result<Response> {
+    let snapshot = match Snapshot::from_bytes(&req.bytes().await?) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return ApiError::new("InvalidSnapshot", e.to_string()).into_response(400),
+    };
+    if snapshot.namespace != T::NAMESPACE {
+        return ApiError::new(
+            "InvalidSnapshot",
+            format!("snapshot of {}, not {}", snapshot.namespace, T::NAMESPACE),
+        )
+        .into_response(400);
+    }
+
+    let mut storage: SafeStorage = this.backup_state().storage().into();
+    let restored = match restore(&mut storage, &snapshot).await? {
+        Ok(restored) => restored,
+        Err(NotEmpty) => {
+            return ApiError::new("NotEmpty", "the object already holds state").into_response(409);
+        }
+    };
+    this.invalidate_cached_state();
+    if let Some(offset) = T::RESTORE_ALARM_MS {
+        this.backup_state().storage().set_alarm(offset).await?;
+    }
+    // restored into another object, e.g. a fresh one for a recovery drill
+    if let Err(e) = register(this).await {
+        console_warn!("failed to register restored {}: {e}", T::NAMESPACE);
+    }
+
+    Response::from_json(&json!({
+        "object_id": this.backup_state().id().to_string(),
+        "restored": restored,
+        "exported_at": snapshot.exported_at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, memory_storage};
+
+    #[test]
+    fn snapshots_round_trip() {
+        let mut storage = memory_storage();
+        block_on(async {
+            storage.put("balance", &1000u64).await.unwrap();
+            storage.put("games-1", &"won".to_string()).await.unwrap();
+            storage.put(REGISTERED_KEY, &true).await.unwrap();
+        });
+
+        let snapshot = block_on(export(&storage, "USER_TEST", "abc")).unwrap();
+        let keys: Vec<_> = snapshot.entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["balance", "games-1"]);
+        let decoded = Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut fresh = memory_storage();
+        assert_eq!(block_on(restore(&mut fresh, &decoded)).unwrap(), Ok(2));
+        block_on(async {
+            assert_eq!(fresh.get::<u64>("balance").await.unwrap(), Some(1000));
+            assert_eq!(
+                fresh.get::<String>("games-1").await.unwrap().as_deref(),
+                Some("won")
+            );
+        });
+    }
+
+    #[test]
+    fn exports_every_page() {
+        let mut storage = memory_storage();
+        let count = EXPORT_PAGE_SIZE * 2 + 3;
+        block_on(async {
+            for i in 0..count {
+                storage.put(format!("key-{i:05}"), &i).await.unwrap();
+            }
+        });
+
+        let snapshot = block_on(export(&storage, "USER_TEST", "abc")).unwrap();
+        assert_eq!(snapshot.entries.len(), count);
+        assert_eq!(
+            snapshot.entries.last().unwrap().key,
+            format!("key-{:05}", count - 1)
+        );
+    }
+
+    #[test]
+    fn restores_only_into_empty_objects() {
+        let snapshot = Snapshot {
+            namespace: "USER_TEST".into(),
+            object_id: "abc".into(),
+            exported_at: 0,
+            entries: vec![],
+        };
+
+        let mut registered = memory_storage();
+        block_on(registered.put(REGISTERED_KEY, &true)).unwrap();
+        assert_eq!(
+            block_on(restore(&mut registered, &snapshot)).unwrap(),
+            Ok(0)
+        );
+
+        let mut used = memory_storage();
+        block_on(used.put("balance", &1u64)).unwrap();
+        assert_eq!(
+            block_on(restore(&mut used, &snapshot)).unwrap(),
+            Err(NotEmpty)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::result::Result as StdResult;
+
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use worker::{Request, Response, Result};
+
+use crate::api_error::ApiError;
+
+/// default limit for JSON request bodies
+pub const MAX_JSON_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BodyError {
+    TooLarge { limit: usize },
+    UnsupportedMediaType(String),
+    Invalid(String),
+}
+
+impl BodyError {
+    pub fn into_response(self) -> Result<Response> {
+        match self {
+            Self::TooLarge { limit } => {
+                ApiError::new("PayloadTooLarge", format!("body exceeds {limit} bytes"))
+                    .with_details(json!({ "limit": limit }))
+                    .into_response(413)
+            }
+            Self::UnsupportedMediaType(mime) => ApiError::new(
+                "UnsupportedMediaType",
+                format!("expected a JSON body, got {mime}"),
+            )
+            .into_response(415),
+            Self::Invalid(e) => ApiError::new("InvalidBody", e).into_response(400),
+        }
+    }
+}
+
+/// JSON media types, plus `text/plain` which browsers send for string bodies
+/// without an explicit content type. a missing content type is accepted as well
+fn is_json_media_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime == "application/json" || mime.ends_with("+json") || mime == "text/plain"
+}
+
+/// reads and parses a JSON body of at most `limit` bytes
+///
+/// the declared `Content-Length` is checked before reading anything,
+/// bodies without one are read until they exceed `limit`
+pub async fn read_json<T: DeserializeOwned>(
+    req: &mut Request,
+    limit: usize,
+) -> StdResult<T, BodyError> {
+    let header = |name: &str| req.headers().get(name).ok().flatten();
+    if let Some(content_type) = header("Content-Type") {
+        if !is_json_media_type(&content_type) {
+            return Err(BodyError::UnsupportedMediaType(content_type));
+        }
+    }
+    let declared_len = header("Content-Length").and_then(|len| len.parse::<usize>().ok());
+    if declared_len.is_some_and(|len| len > limit) {
+        return Err(BodyError::TooLarge { limit });
+    }
+
+    let mut body = Vec::with_capacity(declared_len.unwrap_or_default());
+    // no stream means no body, which fails to parse below
+    if let Ok(mut stream) = req.stream() {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| BodyError::Invalid(e.to_string()))?;
+            if body.len() + chunk.len() > limit {
+                return Err(BodyError::TooLarge { limit });
+            }
+            body.extend_from_slice(&chunk);
+        }
+    }
+
+    serde_json::from_slice(&body).map_err(|e| BodyError::Invalid(e.to_string()))
+}
+
+/// `read_json` with `MAX_JSON_BODY_BYTES`, returning the error response from the handler
+#[macro_export]
+macro_rules! json_body {
+    ($req:ident) => {
+        $crate::json_body!($req, $crate::body::MAX_JSON_BODY_BYTES)
+    };
+    ($req:ident, $limit:expr) => {{
+        match $crate::body::read_json(&mut $req, $limit).await {
+            Ok(body) => body,
+            Err(e) => return e.into_response(),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_json_and_untyped_bodies_only() {
+        assert!(is_json_media_type("application/json"));
+        assert!(is_json_media_type("Application/JSON; charset=utf-8"));
+        assert!(is_json_media_type("application/merge-patch+json"));
+        assert!(is_json_media_type("text/plain;charset=UTF-8"));
+        assert!(!is_json_media_type("application/x-www-form-urlencoded"));
+        assert!(!is_json_media_type("multipart/form-data; boundary=x"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{error::Error, fmt, future::Future};
+
+use serde::{Deserialize, Serialize};
+use worker::{Cache, Response, console_warn};
+
+use crate::time::now_millis;
+
+/// cache keys are urls, this host is never fetched
+const BREAKER_CACHE_PREFIX: &str = "https://circuit-breaker.internal/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// consecutive failures before the breaker opens
+    pub failure_threshold: u32,
+    /// how long the breaker stays open before letting a probe through
+    pub open_for_ms: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_for_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct BreakerState {
+    consecutive_failures: u32,
+    /// unix millis the breaker opened at
+    opened_at: Option<u64>,
+    /// unix millis the half open probe started at
+    probing_since: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Admission {
+    Closed,
+    Probe,
+    Rejected { retry_after_ms: u64 },
+}
+
+impl BreakerState {
+    fn admit(&self, now: u64, config: BreakerConfig) -> Admission {
+        let Some(opened_at) = self.opened_at else {
+            return Admission::Closed;
+        };
+        let retry_at = opened_at + config.open_for_ms;
+        if now < retry_at {
+            return Admission::Rejected {
+                retry_after_ms: retry_at - now,
+            };
+        }
+        // only one probe per open period, a stuck probe is retried after another period
+        match self.probing_since {
+            Some(since) if now < since + config.open_for_ms => Admission::Rejected {
+                retry_after_ms: since + config.open_for_ms - now,
+            },
+            _ => Admission::Probe,
+        }
+    }
+
+    fn on_failure(&mut self, now: u64, config: BreakerConfig) {
+        self.consecutive_failures += 1;
+        self.probing_since = None;
+        if self.opened_at.is_some() || self.consecutive_failures >= config.failure_threshold {
+            self.opened_at = Some(now);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BreakerError<E> {
+    /// the call was not attempted
+    Open {
+        key: String,
+        retry_after_ms: u64,
+    },
+    Call(E),
+}
+
+impl<E: fmt::Display> fmt::Display for BreakerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open {
+                key,
+                retry_after_ms,
+            } => write!(f, "circuit open for {key}, retry after {retry_after_ms}ms"),
+            Self::Call(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<E: Error> Error for BreakerError<E> {}
+
+/// Fails calls fast once a dependency keeps failing
+///
+/// after `failure_threshold` consecutive failures the breaker opens and calls are rejected
+/// for `open_for_ms`, then a single probe is let through (half open)
+/// a successful probe closes the breaker, a failed one reopens it
+///
+/// state is kept per key in the colo's Cache, shared by isolates on a best effort basis
+/// cache failures never block calls
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CircuitBreaker {
+    pub config: BreakerConfig,
+}
+
+impl CircuitBreaker {
+    pub const fn new(config: BreakerConfig) -> Self {
+        Self { config }
+    }
+
+    fn cache_key(key: &str) -> String {
+        format!("{BREAKER_CACHE_PREFIX}{key}")
+    }
+
+    async fn load(key: &str) -> BreakerState {
+        let cached = Cache::default().get(&Self::cache_key(key), false).await;
+        match cached {
+            Ok(Some(mut res)) => res.json().await.unwrap_or_default(),
+            Ok(None) => BreakerState::default(),
+            Err(e) => {
+                console_warn!("failed to load circuit breaker state for {key}: {e}");
+                BreakerState::default()
+            }
+        }
+    }
+
+    async fn store(&self, key: &str, state: &BreakerState) {
+        let res = async {
+            let cache = Cache::default();
+            if *state == BreakerState::default() {
+                cache.delete(&Self::cache_key(key), false).await?;
+                return Ok(());
+            }
+            let res = Response::from_json(state)?;
+            // outlives a few open periods so consecutive failures keep counting
+            res.headers().set(
+                "Cache-Control",
+                &format!("max-age={}", (self.config.open_for_ms / 1000).max(1) * 10),
+            )?;
+            cache.put(&Self::cache_key(key), res).await
+        }
+        .await;
+        if let Err(e) = res {
+            console_warn!("failed to store circuit breaker state for {key}: {e}");
+        }
+    }
+
+    /// runs `call` unless the breaker for `key` is open
+    /// only errors matching `is_failure` count towards opening the breaker
+    pub async fn call<T, E>(
+        &self,
+        key: &str,
+        is_failure: impl Fn(&E) -> bool,
+        call: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, BreakerError<E>> {
+        let mut state = Self::load(key).await;
+        let now = now_millis();
+        match state.admit(now, self.config) {
+            Admission::Closed => (),
+            Admission::Probe => {
+                state.probing_since = Some(now);
+                self.store(key, &state).await;
+            }
+            Admission::Rejected { retry_after_ms } => {
+                return Err(BreakerError::Open {
+                    key: key.to_string(),
+                    retry_after_ms,
+                });
+            }
+        }
+
+        match call.await {
+            Ok(v) => {
+                if state != BreakerState::default() {
+                    self.store(key, &BreakerState::default()).await;
+                }
+                Ok(v)
+            }
+            Err(e) if is_failure(&e) => {
+                state.on_failure(now_millis(), self.config);
+                self.store(key, &state).await;
+                Err(BreakerError::Call(e))
+            }
+            Err(e) => Err(BreakerError::Call(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: BreakerConfig = BreakerConfig {
+        failure_threshold: 3,
+        open_for_ms: 1000,
+    };
+
+    #[test]
+    fn opens_after_threshold() {
+        let mut state = BreakerState::default();
+        state.on_failure(0, CONFIG);
+        state.on_failure(0, CONFIG);
+        assert_eq!(state.admit(0, CONFIG), Admission::Closed);
+
+        state.on_failure(10, CONFIG);
+        assert_eq!(
+            state.admit(110, CONFIG),
+            Admission::Rejected {
+                retry_after_ms: 900
+            }
+        );
+    }
+
+    #[test]
+    fn single_probe_after_open_period() {
+        let mut state = BreakerState {
+            consecutive_failures: 3,
+            opened_at: Some(0),
+            probing_since: None,
+        };
+        assert_eq!(state.admit(1000, CONFIG), Admission::Probe);
+
+        state.probing_since = Some(1000);
+        assert!(matches!(
+            state.admit(1500, CONFIG),
+            Admission::Rejected { .. }
+        ));
+        // a stuck probe is retried
+        assert_eq!(state.admit(2000, CONFIG), Admission::Probe);
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let mut state = BreakerState {
+            consecutive_failures: 3,
+            opened_at: Some(0),
+            probing_since: Some(1000),
+        };
+        state.on_failure(1200, CONFIG);
+
+        assert_eq!(state.opened_at, Some(1200));
+        assert_eq!(state.probing_since, None);
+        assert!(matches!(
+            state.admit(1500, CONFIG),
+            Admission::Rejected { .. }
+        ));
+    }
+}
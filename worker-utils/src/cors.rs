@@ -0,0 +1,172 @@
+//! per environment CORS
+//!
+//! production only allows the origins listed in `CORS_ALLOWED_ORIGINS` (comma separated,
+//! `https://*.example.com` matches any subdomain), falling back to `DEFAULT_ALLOWED_ORIGINS`.
+//! every other environment allows any origin
+
+use worker::{Cors, Env, Method, Response, Result};
+
+use crate::environment::{RunEnv, env_kind};
+
+pub const ALLOWED_ORIGINS_VAR: &str = "CORS_ALLOWED_ORIGINS";
+
+pub const DEFAULT_ALLOWED_ORIGINS: &[&str] = &["https://yral.com", "https://*.yral.com"];
+
+const MAX_AGE_SECS: u32 = 86400;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    /// no browser origin at all, for routes only called server to server
+    pub fn none() -> Self {
+        Self::List(vec![])
+    }
+
+    pub fn list(origins: &[&str]) -> Self {
+        Self::List(origins.iter().map(|o| o.to_string()).collect())
+    }
+
+    /// parses a comma separated list, `*` allows any origin
+    pub fn parse(origins: &str) -> Self {
+        let origins: Vec<_> = origins
+            .split(',')
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+            .collect();
+        if origins.contains(&"*") {
+            return Self::Any;
+        }
+
+        Self::list(&origins)
+    }
+
+    pub fn allows(&self, origin: &str) -> bool {
+        let Self::List(origins) = self else {
+            return true;
+        };
+
+        origins.iter().any(|allowed| {
+            let Some((scheme, domain)) = allowed.split_once("://*.") else {
+                return allowed == origin;
+            };
+            origin
+                .strip_prefix(scheme)
+                .and_then(|o| o.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(domain))
+                .is_some_and(|sub| sub.ends_with('.') && sub.len() > 1)
+        })
+    }
+}
+
+pub struct CorsPolicy {
+    origins: AllowedOrigins,
+    /// path prefix -> origins, the longest matching prefix wins
+    overrides: Vec<(String, AllowedOrigins)>,
+}
+
+/// strict in production, permissive everywhere else
+pub fn cors_for_env(env: &Env) -> CorsPolicy {
+    CorsPolicy::new(allowed_origins(env))
+}
+
+/// origins allowed by the environment, for workers that build their own CORS layer
+pub fn allowed_origins(env: &Env) -> AllowedOrigins {
+    let production = env_kind() == RunEnv::Remote
+        && env
+            .var("ENVIRONMENT")
+            .is_ok_and(|v| v.to_string() == "production");
+    if !production {
+        return AllowedOrigins::Any;
+    }
+
+    env.var(ALLOWED_ORIGINS_VAR)
+        .map(|v| AllowedOrigins::parse(&v.to_string()))
+        .unwrap_or_else(|_| AllowedOrigins::list(DEFAULT_ALLOWED_ORIGINS))
+}
+
+impl CorsPolicy {
+    pub fn new(origins: AllowedOrigins) -> Self {
+        Self {
+            origins,
+            overrides: vec![],
+        }
+    }
+
+    /// uses `origins` for every path starting with `prefix`
+    pub fn with_override(mut self, prefix: impl Into<String>, origins: AllowedOrigins) -> Self {
+        self.overrides.push((prefix.into(), origins));
+        self
+    }
+
+    pub fn origins_for(&self, path: &str) -> &AllowedOrigins {
+        self.overrides
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(&self.origins, |(_, origins)| origins)
+    }
+
+    /// the CORS headers for a request to `path` from `origin`
+    /// allowed origins are echoed back, since the header can only hold one
+    pub fn cors(&self, path: &str, origin: Option<&str>) -> Cors {
+        let cors = Cors::new()
+            .with_methods([Method::Head, Method::Get, Method::Post, Method::Options])
+            .with_allowed_headers(vec!["*"])
+            .with_max_age(MAX_AGE_SECS);
+
+        match (self.origins_for(path), origin) {
+            (AllowedOrigins::Any, _) => cors.with_origins(["*"]),
+            (origins, Some(origin)) if origins.allows(origin) => cors.with_origins([origin]),
+            _ => cors,
+        }
+    }
+
+    /// applies `cors` to `res`, `path` and `origin` are taken from the request before routing it
+    pub fn apply(&self, path: &str, origin: Option<&str>, res: Response) -> Result<Response> {
+        let mut res = res.with_cors(&self.cors(path, origin))?;
+        if !matches!(self.origins_for(path), AllowedOrigins::Any) {
+            res.headers_mut().append("Vary", "Origin")?;
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_exact_and_wildcard_origins() {
+        let origins = AllowedOrigins::parse("https://yral.com, https://*.yral.com");
+        assert!(origins.allows("https://yral.com"));
+        assert!(origins.allows("https://pr-12.yral.com"));
+        assert!(!origins.allows("https://evilyral.com"));
+        assert!(!origins.allows("http://app.yral.com"));
+        assert!(!origins.allows("https://.yral.com"));
+
+        assert_eq!(
+            AllowedOrigins::parse("https://a.com,*"),
+            AllowedOrigins::Any
+        );
+    }
+
+    #[test]
+    fn longest_override_wins() {
+        let policy = CorsPolicy::new(AllowedOrigins::Any)
+            .with_override("/admin", AllowedOrigins::none())
+            .with_override("/admin/public", AllowedOrigins::list(&["https://yral.com"]));
+
+        assert_eq!(policy.origins_for("/balance/abc"), &AllowedOrigins::Any);
+        assert_eq!(policy.origins_for("/admin/limits"), &AllowedOrigins::none());
+        assert!(
+            policy
+                .origins_for("/admin/public/x")
+                .allows("https://yral.com")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::result::Result as StdResult;
+
+use serde::{Serialize, de::DeserializeOwned};
+use worker::{Env, Method, Response, Result, Stub};
+
+use crate::{RequestInitBuilder, api_error::ApiError, trace::TraceId};
+
+/// durable objects ignore the host, only the path is routed
+const DO_BASE_URL: &str = "http://fake_url.com";
+
+/// requests to durable objects addressed by namespace binding and name
+pub struct DoClient<'a> {
+    env: &'a Env,
+    name_header: Option<&'a str>,
+    trace: Option<&'a TraceId>,
+}
+
+impl<'a> DoClient<'a> {
+    pub fn new(env: &'a Env) -> Self {
+        Self {
+            env,
+            name_header: None,
+            trace: None,
+        }
+    }
+
+    /// sends the object's name in `header` on every request
+    /// for objects that need to know who they belong to
+    pub fn with_name_header(mut self, header: &'a str) -> Self {
+        self.name_header = Some(header);
+        self
+    }
+
+    /// forwards `trace` on every request
+    pub fn with_trace(mut self, trace: &'a TraceId) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    pub fn stub(&self, namespace: &str, name: &str) -> Result<Stub> {
+        self.env
+            .durable_object(namespace)?
+            .id_from_name(name)?
+            .get_stub()
+    }
+
+    /// sends `init` to `path` on the object, returning the raw response
+    pub async fn fetch(
+        &self,
+        namespace: &str,
+        name: &str,
+        path: &str,
+        init: &mut RequestInitBuilder,
+    ) -> Result<Response> {
+        if let Some(header) = self.name_header {
+            init.header(header, name)?;
+        }
+        if let Some(trace) = self.trace {
+            init.trace(trace)?;
+        }
+        let url = format!("{DO_BASE_URL}/{}", path.trim_start_matches('/'));
+        let req = init.request(&url)?;
+
+        self.stub(namespace, name)?.fetch_with_request(req).await
+    }
+
+    pub async fn get(&self, namespace: &str, name: &str, path: &str) -> Result<Response> {
+        self.fetch(namespace, name, path, &mut RequestInitBuilder::default())
+            .await
+    }
+
+    /// POSTs `req` as json, returning the raw response for routes that forward it
+    pub async fn post<Req: Serialize>(
+        &self,
+        namespace: &str,
+        name: &str,
+        path: &str,
+        req: &Req,
+    ) -> Result<Response> {
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Post).json(req)?;
+
+        self.fetch(namespace, name, path, &mut init).await
+    }
+
+    /// `post` with the response parsed as `Res`
+    /// non 2xx responses are returned with their status as an `ApiError`
+    pub async fn call<Req: Serialize, Res: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        name: &str,
+        path: &str,
+        req: &Req,
+    ) -> Result<StdResult<Res, (u16, ApiError)>> {
+        let mut res = self.post(namespace, name, path, req).await?;
+        let status = res.status_code();
+        if !(200..300).contains(&status) {
+            let body = res.text().await?;
+            let err =
+                serde_json::from_str(&body).unwrap_or_else(|_| ApiError::from_status(status, body));
+            return Ok(Err((status, err)));
+        }
+
+        Ok(Ok(res.json().await?))
+    }
+}
@@ -1,3 +1,6 @@
+use candid::Principal;
+use worker::{Env, console_warn};
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum RunEnv {
     Mock,
@@ -16,3 +19,147 @@ pub const fn env_kind() -> RunEnv {
         _ => RunEnv::Remote,
     }
 }
+
+impl RunEnv {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            RunEnv::Mock => "mock",
+            RunEnv::Local => "local",
+            RunEnv::Remote => "remote",
+        }
+    }
+}
+
+/// the deployment a worker runs as, picked by its `ENVIRONMENT` var
+///
+/// `env_kind()` says how the worker was built, the profile says which backends it talks to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Profile {
+    /// local and mock builds
+    Local,
+    /// `staging` and `development` deployments, no real funds move
+    Staging,
+    /// `prod-canary` deployments, serving a slice of production traffic
+    ProdCanary,
+    Production,
+}
+
+/// which treasury pays out rewards and withdrawals
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TreasuryKind {
+    /// transfers with the backend admin key
+    Admin,
+    /// transfers succeed without moving funds
+    NoOp,
+}
+
+/// backends of a profile, see `Profile::config`
+#[derive(Clone, Copy, Debug)]
+pub struct ProfileConfig {
+    pub metadata_server_url: &'static str,
+    /// mxzaz-hqaaa-aaaar-qaada-cai on mainnet
+    pub ckbtc_ledger: Principal,
+    pub dolr_ledger: Principal,
+    pub treasury: TreasuryKind,
+}
+
+const MAINNET_CKBTC_LEDGER: Principal = Principal::from_slice(&[0, 0, 0, 0, 2, 48, 0, 6, 1, 1]);
+const MAINNET_DOLR_LEDGER: Principal = Principal::from_slice(&[0, 0, 0, 0, 2, 0, 0, 43, 1, 1]);
+const METADATA_SERVER_URL: &str = "https://yral-metadata.fly.dev";
+
+const LOCAL: ProfileConfig = ProfileConfig {
+    metadata_server_url: "http://localhost:8001",
+    ckbtc_ledger: MAINNET_CKBTC_LEDGER,
+    dolr_ledger: MAINNET_DOLR_LEDGER,
+    treasury: TreasuryKind::NoOp,
+};
+
+const STAGING: ProfileConfig = ProfileConfig {
+    metadata_server_url: METADATA_SERVER_URL,
+    ckbtc_ledger: MAINNET_CKBTC_LEDGER,
+    dolr_ledger: MAINNET_DOLR_LEDGER,
+    treasury: TreasuryKind::NoOp,
+};
+
+const PRODUCTION: ProfileConfig = ProfileConfig {
+    metadata_server_url: METADATA_SERVER_URL,
+    ckbtc_ledger: MAINNET_CKBTC_LEDGER,
+    dolr_ledger: MAINNET_DOLR_LEDGER,
+    treasury: TreasuryKind::Admin,
+};
+
+impl Profile {
+    pub const ALL: [Self; 4] = [
+        Self::Local,
+        Self::Staging,
+        Self::ProdCanary,
+        Self::Production,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Staging => "staging",
+            Self::ProdCanary => "prod-canary",
+            Self::Production => "production",
+        }
+    }
+
+    /// an `ENVIRONMENT` value, `development` is an older name for staging
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "development" => Some(Self::Staging),
+            name => Self::ALL.into_iter().find(|p| p.as_str() == name),
+        }
+    }
+
+    /// remote deployments without `ENVIRONMENT` are production, unknown names are staging
+    pub fn current(env: &Env) -> Self {
+        if env_kind() != RunEnv::Remote {
+            return Self::Local;
+        }
+        let Ok(name) = env.var("ENVIRONMENT").map(|v| v.to_string()) else {
+            return Self::Production;
+        };
+
+        Self::parse(&name).unwrap_or_else(|| {
+            console_warn!("unknown ENVIRONMENT {name}, running as staging");
+            Self::Staging
+        })
+    }
+
+    pub const fn config(self) -> &'static ProfileConfig {
+        match self {
+            Self::Local => &LOCAL,
+            Self::Staging => &STAGING,
+            Self::ProdCanary | Self::Production => &PRODUCTION,
+        }
+    }
+}
+
+/// the config of the worker's current profile
+pub fn profile_config(env: &Env) -> &'static ProfileConfig {
+    Profile::current(env).config()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_round_trip_their_names() {
+        for profile in Profile::ALL {
+            assert_eq!(Profile::parse(profile.as_str()), Some(profile));
+        }
+        assert_eq!(Profile::parse("development"), Some(Profile::Staging));
+        assert_eq!(Profile::parse("prod"), None);
+    }
+
+    #[test]
+    fn only_production_profiles_move_funds() {
+        for profile in Profile::ALL {
+            let real = matches!(profile, Profile::ProdCanary | Profile::Production);
+            assert_eq!(profile.config().treasury == TreasuryKind::Admin, real);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! feature flags kept in the `FEATURE_FLAGS` KV namespace, one json `FlagConfig` per flag name
+//!
+//! ```ignore
+//! static V4_VOTE: Flag = Flag::new("hon_v4_vote").default_on();
+//!
+//! // in a handler, responds with 404 while the flag is off for the user
+//! require_flag!(ctx.env, V4_VOTE, user_principal);
+//! ```
+//!
+//! configs are cached per isolate for `FLAG_CACHE_TTL_MS`, so a kill takes at most that long
+
+use std::{cell::RefCell, collections::HashMap, collections::HashSet};
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::{Env, Response, Result};
+
+use crate::{
+    api_error::ApiError,
+    environment::{RunEnv, env_kind},
+    time::now_millis,
+};
+
+pub const FEATURE_FLAGS_KV: &str = "FEATURE_FLAGS";
+
+const FLAG_CACHE_TTL_MS: u64 = 30_000;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlagConfig {
+    /// kill switch, a disabled flag is off for everyone
+    pub enabled: bool,
+    /// share of principals (0-100) the flag is on for, `None` means everyone
+    #[serde(default)]
+    pub rollout_percent: Option<u8>,
+    /// principals that get the flag regardless of the rollout while it's enabled
+    #[serde(default)]
+    pub allow: HashSet<Principal>,
+}
+
+impl FlagConfig {
+    pub fn evaluate(&self, flag: &str, principal: Option<Principal>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let Some(percent) = self.rollout_percent else {
+            return true;
+        };
+        // requests without a principal only see fully rolled out flags
+        let Some(principal) = principal else {
+            return percent >= 100;
+        };
+
+        self.allow.contains(&principal) || bucket(flag, principal) < percent as u64
+    }
+}
+
+/// stable bucket in 0..100, salted with the flag so rollouts of different flags are independent
+pub fn bucket(flag: &str, principal: Principal) -> u64 {
+    let bytes = flag
+        .as_bytes()
+        .iter()
+        .chain(&[0xff])
+        .chain(principal.as_slice());
+    let hash = bytes.fold(FNV_OFFSET, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(FNV_PRIME)
+    });
+
+    hash % 100
+}
+
+thread_local! {
+    static FLAG_CACHE: RefCell<HashMap<&'static str, (Option<FlagConfig>, u64)>> = RefCell::default();
+}
+
+/// drops every cached config, the next read goes to KV
+pub fn invalidate_flag_cache() {
+    FLAG_CACHE.with_borrow_mut(|cache| cache.clear());
+}
+
+/// a feature flag, declared as a `static` next to the routes it guards
+pub struct Flag {
+    name: &'static str,
+    default: bool,
+}
+
+impl Flag {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            default: false,
+        }
+    }
+
+    /// on while the flag has no config or KV can't be read, for guarding features that already shipped
+    pub const fn default_on(mut self) -> Self {
+        self.default = true;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// `None` if the flag has no config, mock builds never read KV
+    pub async fn config(&self, env: &Env) -> Option<FlagConfig> {
+        let now = now_millis();
+        let cached = FLAG_CACHE.with_borrow(|cache| {
+            cache
+                .get(self.name)
+                .filter(|(_, fetched_at)| now.saturating_sub(*fetched_at) < FLAG_CACHE_TTL_MS)
+                .map(|(config, _)| config.clone())
+        });
+        if let Some(config) = cached {
+            return config;
+        }
+        if env_kind() == RunEnv::Mock {
+            return None;
+        }
+
+        let config = match self.fetch(env).await {
+            Ok(config) => config,
+            Err(e) => {
+                worker::console_warn!("failed to read feature flag {}: {e}", self.name);
+                None
+            }
+        };
+        FLAG_CACHE.with_borrow_mut(|cache| cache.insert(self.name, (config.clone(), now)));
+
+        config
+    }
+
+    async fn fetch(&self, env: &Env) -> Result<Option<FlagConfig>> {
+        let config = env
+            .kv(FEATURE_FLAGS_KV)?
+            .get(self.name)
+            .json::<FlagConfig>()
+            .await?;
+
+        Ok(config)
+    }
+
+    /// for flags that aren't rolled out per user
+    pub async fn enabled(&self, env: &Env) -> bool {
+        self.enabled_for_opt(env, None).await
+    }
+
+    pub async fn enabled_for(&self, env: &Env, principal: Principal) -> bool {
+        self.enabled_for_opt(env, Some(principal)).await
+    }
+
+    async fn enabled_for_opt(&self, env: &Env, principal: Option<Principal>) -> bool {
+        match self.config(env).await {
+            Some(config) => config.evaluate(self.name, principal),
+            None => self.default,
+        }
+    }
+
+    /// 404, callers can't tell a disabled feature from a missing route
+    pub fn disabled_response(&self) -> Result<Response> {
+        ApiError::new("FeatureDisabled", "this feature is not available")
+            .with_details(json!({ "flag": self.name }))
+            .into_response(404)
+    }
+}
+
+/// returns `Flag::disabled_response` from the handler while `flag` is off
+///
+/// `require_flag!(env, FLAG)` checks the flag globally, `require_flag!(env, FLAG, principal)` per user
+#[macro_export]
+macro_rules! require_flag {
+    ($env:expr, $flag:expr) => {
+        if !$flag.enabled(&$env).await {
+            return $flag.disabled_response();
+        }
+    };
+    ($env:expr, $flag:expr, $principal:expr) => {
+        if !$flag.enabled_for(&$env, $principal).await {
+            return $flag.disabled_response();
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollouts_follow_the_bucket() {
+        let user = Principal::from_slice(&[7; 29]);
+        let mut config = FlagConfig {
+            enabled: true,
+            rollout_percent: Some(0),
+            allow: HashSet::new(),
+        };
+        assert!(!config.evaluate("flag", Some(user)));
+
+        config.rollout_percent = Some(bucket("flag", user) as u8 + 1);
+        assert!(config.evaluate("flag", Some(user)));
+        assert!(!config.evaluate("flag", None));
+
+        config.rollout_percent = Some(0);
+        config.allow.insert(user);
+        assert!(config.evaluate("flag", Some(user)));
+
+        config.enabled = false;
+        assert!(!config.evaluate("flag", Some(user)));
+    }
+
+    #[test]
+    fn buckets_are_spread_evenly() {
+        let on = (0..1000u32)
+            .map(|i| Principal::from_slice(&i.to_be_bytes()))
+            .filter(|p| bucket("flag", *p) < 30)
+            .count();
+        assert!((230..370).contains(&on), "{on} of 1000 in a 30% rollout");
+    }
+}
@@ -0,0 +1,200 @@
+//! `/healthz` and `/readyz` handlers for uptime probes
+//!
+//! workers declare what they depend on once and route both paths to it
+//!
+//! ```ignore
+//! static HEALTH: HealthCheck = HealthCheck::new(
+//!     "yral-coin",
+//!     &[Dependency::DurableObject("USER_YRAL_COIN_STATE"), Dependency::Secret("JWT_PUBLIC_KEY")],
+//! );
+//!
+//! router
+//!     .get("/healthz", |_, _| HEALTH.healthz())
+//!     .get_async("/readyz", |_, ctx| async move { HEALTH.readyz(&ctx.env).await })
+//! ```
+
+use std::{result::Result as StdResult, time::Duration};
+
+use futures::future::join_all;
+use serde::Serialize;
+use worker::{Env, Response, Result};
+
+use crate::{RequestInitBuilder, environment::env_kind, time::now_millis};
+
+/// upstream pings slower than this count as failed
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Dependency {
+    DurableObject(&'static str),
+    #[cfg(feature = "queue")]
+    Queue(&'static str),
+    Kv(&'static str),
+    Bucket(&'static str),
+    #[cfg(feature = "d1")]
+    D1(&'static str),
+    Secret(&'static str),
+    Var(&'static str),
+    /// service binding to another worker
+    Service(&'static str),
+    /// GET `url` must answer 2xx within `UPSTREAM_TIMEOUT`
+    Upstream {
+        name: &'static str,
+        url: &'static str,
+    },
+}
+
+impl Dependency {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::DurableObject(name)
+            | Self::Kv(name)
+            | Self::Bucket(name)
+            | Self::Secret(name)
+            | Self::Var(name)
+            | Self::Service(name) => name,
+            #[cfg(feature = "queue")]
+            Self::Queue(name) => name,
+            #[cfg(feature = "d1")]
+            Self::D1(name) => name,
+            Self::Upstream { name, .. } => name,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::DurableObject(_) => "durable_object",
+            #[cfg(feature = "queue")]
+            Self::Queue(_) => "queue",
+            Self::Kv(_) => "kv",
+            Self::Bucket(_) => "r2_bucket",
+            #[cfg(feature = "d1")]
+            Self::D1(_) => "d1",
+            Self::Secret(_) => "secret",
+            Self::Var(_) => "var",
+            Self::Service(_) => "service",
+            Self::Upstream { .. } => "upstream",
+        }
+    }
+
+    async fn check(&self, env: &Env) -> StdResult<(), String> {
+        let present = match self {
+            Self::DurableObject(name) => env.durable_object(name).is_ok(),
+            #[cfg(feature = "queue")]
+            Self::Queue(name) => env.queue(name).is_ok(),
+            Self::Kv(name) => env.kv(name).is_ok(),
+            Self::Bucket(name) => env.bucket(name).is_ok(),
+            #[cfg(feature = "d1")]
+            Self::D1(name) => env.d1(name).is_ok(),
+            // only presence is reported, never the value
+            Self::Secret(name) => env.secret(name).is_ok(),
+            Self::Var(name) => env.var(name).is_ok(),
+            Self::Service(name) => env.service(name).is_ok(),
+            Self::Upstream { url, .. } => return ping(url).await,
+        };
+        if !present {
+            return Err("binding missing".into());
+        }
+
+        Ok(())
+    }
+}
+
+async fn ping(url: &str) -> StdResult<(), String> {
+    let res = RequestInitBuilder::default()
+        .timeout(UPSTREAM_TIMEOUT)
+        .fetch(url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = res.status_code();
+    if !(200..300).contains(&status) {
+        return Err(format!("status {status}"));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckReport {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub latency_ms: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    pub service: &'static str,
+    pub env: &'static str,
+    /// "ok" or "unavailable"
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckReport>,
+}
+
+impl HealthReport {
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+pub struct HealthCheck {
+    service: &'static str,
+    dependencies: &'static [Dependency],
+}
+
+impl HealthCheck {
+    pub const fn new(service: &'static str, dependencies: &'static [Dependency]) -> Self {
+        Self {
+            service,
+            dependencies,
+        }
+    }
+
+    fn report(&self, checks: Vec<CheckReport>) -> HealthReport {
+        let ok = checks.iter().all(|c| c.ok);
+        HealthReport {
+            service: self.service,
+            env: env_kind().as_str(),
+            status: if ok { "ok" } else { "unavailable" },
+            checks,
+        }
+    }
+
+    /// liveness, without touching any dependency
+    pub fn live(&self) -> HealthReport {
+        self.report(vec![])
+    }
+
+    pub fn healthz(&self) -> Result<Response> {
+        Response::from_json(&self.live())
+    }
+
+    /// checks every dependency concurrently
+    pub async fn check(&self, env: &Env) -> HealthReport {
+        let checks = join_all(self.dependencies.iter().map(|dep| async move {
+            let start = now_millis();
+            let res = dep.check(env).await;
+            CheckReport {
+                name: dep.name(),
+                kind: dep.kind(),
+                ok: res.is_ok(),
+                error: res.err(),
+                latency_ms: now_millis() - start,
+            }
+        }))
+        .await;
+
+        self.report(checks)
+    }
+
+    /// readiness, 503 if any dependency fails
+    pub async fn readyz(&self, env: &Env) -> Result<Response> {
+        let report = self.check(env).await;
+        let status = if report.is_ok() { 200 } else { 503 };
+
+        Ok(Response::from_json(&report)?.with_status(status))
+    }
+}
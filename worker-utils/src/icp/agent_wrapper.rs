@@ -1,6 +1,22 @@
-use crate::environment::{RunEnv, env_kind};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    circuit_breaker::{BreakerConfig, BreakerError, CircuitBreaker},
+    environment::{RunEnv, env_kind},
+    retry::{RetryPolicy, with_backoff_if},
+};
 use candid::Principal;
-use ic_agent::{Agent, AgentError, Identity};
+use ic_agent::{Agent, AgentError, Identity, identity::Secp256k1Identity};
+use worker::Env;
 
 pub const fn agent_url() -> &'static str {
     match env_kind() {
@@ -10,26 +26,121 @@ pub const fn agent_url() -> &'static str {
     }
 }
 
+thread_local! {
+    static PEM_IDENTITIES: RefCell<HashMap<String, Arc<Secp256k1Identity>>> = RefCell::default();
+}
+
+/// parses `pem` once per isolate, DOs constructing agents on every wake up share the identity
+pub fn identity_from_pem(pem: &str) -> worker::Result<Arc<Secp256k1Identity>> {
+    if let Some(id) = PEM_IDENTITIES.with_borrow(|ids| ids.get(pem).cloned()) {
+        return Ok(id);
+    }
+    let id = Arc::new(
+        Secp256k1Identity::from_pem(pem.as_bytes())
+            .map_err(|e| worker::Error::RustError(e.to_string()))?,
+    );
+    PEM_IDENTITIES.with_borrow_mut(|ids| ids.insert(pem.to_string(), id.clone()));
+
+    Ok(id)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AgentConfig {
+    /// defaults to `agent_url()`
+    pub url: Option<String>,
+    pub ingress_expiry: Option<Duration>,
+    /// how long update calls are polled for before timing out
+    pub max_polling_time: Option<Duration>,
+    pub breaker: BreakerConfig,
+}
+
+impl AgentConfig {
+    /// reads `IC_AGENT_URL` and `IC_AGENT_TIMEOUT_SECS`
+    /// e.g. `IC_AGENT_URL = "http://127.0.0.1:8080"` for a dfx replica on a custom port
+    pub fn from_env(env: &Env) -> Self {
+        let url = env.var("IC_AGENT_URL").ok().map(|v| v.to_string());
+        let max_polling_time = env
+            .var("IC_AGENT_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.to_string().parse().ok())
+            .map(Duration::from_secs);
+
+        Self {
+            url,
+            max_polling_time,
+            ..Default::default()
+        }
+    }
+}
+
+/// transport failures and timeouts, rejections are not worth retrying
+pub fn is_transient(e: &AgentError) -> bool {
+    matches!(
+        e,
+        AgentError::TransportError(_) | AgentError::TimeoutWaitingForResponse()
+    )
+}
+
 #[derive(Clone)]
-pub struct AgentWrapper(Agent);
+pub struct AgentWrapper {
+    agent: Agent,
+    breaker: CircuitBreaker,
+    /// local replicas need their root key fetched once per agent
+    root_key_fetched: Arc<AtomicBool>,
+}
 
 impl AgentWrapper {
     pub fn new(id: impl Identity + 'static) -> Self {
-        let agent = Agent::builder()
-            .with_url(agent_url())
-            .with_identity(id)
-            .build()
-            .unwrap();
-        Self(agent)
+        Self::with_config(Arc::new(id), AgentConfig::default())
+    }
+
+    pub fn with_config(id: Arc<dyn Identity>, config: AgentConfig) -> Self {
+        let mut builder = Agent::builder()
+            .with_url(config.url.as_deref().unwrap_or(agent_url()))
+            .with_arc_identity(id);
+        if let Some(ingress_expiry) = config.ingress_expiry {
+            builder = builder.with_ingress_expiry(ingress_expiry);
+        }
+        if let Some(max_polling_time) = config.max_polling_time {
+            builder = builder.with_max_polling_time(max_polling_time);
+        }
+
+        Self {
+            agent: builder.build().unwrap(),
+            breaker: CircuitBreaker::new(config.breaker),
+            root_key_fetched: Arc::default(),
+        }
+    }
+
+    pub fn with_breaker(mut self, config: BreakerConfig) -> Self {
+        self.breaker = CircuitBreaker::new(config);
+        self
+    }
+
+    /// runs `call` behind the circuit breaker of `canister`
+    /// fails fast while the canister keeps timing out, see `CircuitBreaker`
+    pub async fn guarded<T>(
+        &self,
+        canister: Principal,
+        call: impl Future<Output = Result<T, AgentError>>,
+    ) -> Result<T, BreakerError<AgentError>> {
+        self.breaker
+            .call(&canister.to_text(), is_transient, call)
+            .await
     }
 
     pub async fn get(&self) -> &Agent {
-        let agent = &self.0;
+        let agent = &self.agent;
         match env_kind() {
-            RunEnv::Local => agent
-                .fetch_root_key()
+            RunEnv::Local if !self.root_key_fetched.load(Ordering::Relaxed) => {
+                with_backoff_if(RetryPolicy::default(), is_transient, move || {
+                    agent.fetch_root_key()
+                })
                 .await
-                .expect("AGENT: fetch_root_key failed"),
+                .expect("AGENT: fetch_root_key failed");
+                self.root_key_fetched.store(true, Ordering::Relaxed);
+            }
+            RunEnv::Local => (),
             RunEnv::Mock => {
                 panic!("Calling ic-agent from mock env?!");
             }
@@ -38,10 +149,17 @@ impl AgentWrapper {
         agent
     }
 
-    pub async fn canister_controller(&self, canister: Principal) -> Result<Principal, AgentError> {
+    pub async fn canister_controller(
+        &self,
+        canister: Principal,
+    ) -> Result<Principal, BreakerError<AgentError>> {
         let res = self
-            .0
-            .read_state_canister_info(canister, "controllers")
+            .guarded(
+                canister,
+                with_backoff_if(RetryPolicy::default(), is_transient, move || {
+                    self.agent.read_state_canister_info(canister, "controllers")
+                }),
+            )
             .await?;
         let controllers: Vec<Principal> =
             ciborium::from_reader(res.as_slice()).expect("ic0 returned invalid controllers?!");
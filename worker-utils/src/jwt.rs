@@ -1,10 +1,18 @@
-use std::collections::HashSet;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
-use jsonwebtoken::DecodingKey;
-use serde::{Deserialize, Serialize};
-use worker::Request;
+use jsonwebtoken::{DecodingKey, jwk::JwkSet};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use worker::{Cache, Context, Env, Request, Response, console_error, console_warn};
 
-use crate::environment::{RunEnv, env_kind};
+use crate::{
+    RequestInitBuilder,
+    api_error::ApiError,
+    environment::{RunEnv, env_kind},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -12,21 +20,174 @@ pub struct Claims {
     pub exp: usize,
 }
 
+/// time based claim checks, all durations in seconds
+///
+/// the default checks nothing, matching tokens that never expire
+/// these are checked here instead of by `jsonwebtoken`, its clock panics on wasm
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JwtPolicy {
+    /// require `exp` and reject expired tokens
+    pub validate_exp: bool,
+    /// reject tokens used before their `nbf`, if present
+    pub validate_nbf: bool,
+    /// require `iat` and reject tokens issued longer ago
+    pub max_age_secs: Option<u64>,
+    /// allowed clock skew for every check
+    pub leeway_secs: u64,
+    /// unix time until which tokens without `exp` are still accepted, so issuers can roll out
+    /// expiring tokens before they're required
+    pub missing_exp_grace_until: Option<u64>,
+}
+
+/// end of the grace period for service tokens issued before `exp` was required, 2026-11-16 UTC
+pub const EXP_ROLLOUT_GRACE_UNTIL: u64 = 1_794_787_200;
+
+impl JwtPolicy {
+    /// validates `exp` and `nbf` with `leeway_secs` of clock skew
+    pub const fn expiring(leeway_secs: u64) -> Self {
+        Self {
+            validate_exp: true,
+            validate_nbf: true,
+            max_age_secs: None,
+            leeway_secs,
+            missing_exp_grace_until: None,
+        }
+    }
+
+    /// accepts tokens without `exp` until `until_secs`, expired ones are still rejected
+    pub const fn with_missing_exp_grace(mut self, until_secs: u64) -> Self {
+        self.missing_exp_grace_until = Some(until_secs);
+        self
+    }
+
+    pub const fn with_max_age(mut self, max_age_secs: u64) -> Self {
+        self.max_age_secs = Some(max_age_secs);
+        self
+    }
+
+    fn check(&self, claims: &TimeClaims, now: u64) -> Result<(), jsonwebtoken::errors::Error> {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let leeway = self.leeway_secs;
+        if self.validate_exp {
+            match claims.exp {
+                Some(exp) if exp.saturating_add(leeway) < now => {
+                    return Err(ErrorKind::ExpiredSignature.into());
+                }
+                Some(_) => {}
+                None if self
+                    .missing_exp_grace_until
+                    .is_some_and(|until| now < until) => {}
+                None => return Err(ErrorKind::MissingRequiredClaim("exp".into()).into()),
+            }
+        }
+        if self.validate_nbf {
+            if let Some(nbf) = claims.nbf {
+                if nbf > now.saturating_add(leeway) {
+                    return Err(ErrorKind::ImmatureSignature.into());
+                }
+            }
+        }
+        if let Some(max_age) = self.max_age_secs {
+            let iat = claims
+                .iat
+                .ok_or_else(|| ErrorKind::MissingRequiredClaim("iat".into()))?;
+            if iat > now.saturating_add(leeway) {
+                return Err(ErrorKind::ImmatureSignature.into());
+            }
+            if now.saturating_sub(iat) > max_age.saturating_add(leeway) {
+                return Err(ErrorKind::ExpiredSignature.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct TimeClaims {
+    exp: Option<u64>,
+    nbf: Option<u64>,
+    iat: Option<u64>,
+}
+
+/// unix time in seconds
+fn now_secs() -> u64 {
+    crate::time::now_millis() / 1000
+}
+
 pub fn verify_jwt(
     public_key_pem: &str,
     aud: String,
     jwt: &str,
 ) -> Result<(), jsonwebtoken::errors::Error> {
+    verify_jwt_with_audiences(public_key_pem, HashSet::from([aud]), jwt)
+}
+
+/// accepts a token issued for any of `audiences`
+pub fn verify_jwt_with_audiences(
+    public_key_pem: &str,
+    audiences: HashSet<String>,
+    jwt: &str,
+) -> Result<(), jsonwebtoken::errors::Error> {
+    decode_jwt_with_audiences::<Claims>(public_key_pem, audiences, jwt)?;
+
+    Ok(())
+}
+
+fn validation(audiences: HashSet<String>) -> jsonwebtoken::Validation {
     let mut validation = jsonwebtoken::Validation::default();
-    validation.aud = Some(HashSet::from([aud]));
+    validation.aud = Some(audiences);
     validation.algorithms = vec![jsonwebtoken::Algorithm::EdDSA];
     validation.validate_exp = false;
 
-    jsonwebtoken::decode::<Claims>(
+    validation
+}
+
+/// same as `verify_jwt_with_audiences`, returning the token's claims
+pub fn decode_jwt_with_audiences<T: DeserializeOwned>(
+    public_key_pem: &str,
+    audiences: HashSet<String>,
+    jwt: &str,
+) -> Result<T, jsonwebtoken::errors::Error> {
+    decode_jwt_with_policy(public_key_pem, audiences, JwtPolicy::default(), jwt)
+}
+
+/// same as `verify_jwt_with_policy`, returning the token's claims
+pub fn decode_jwt_with_policy<T: DeserializeOwned>(
+    public_key_pem: &str,
+    audiences: HashSet<String>,
+    policy: JwtPolicy,
+    jwt: &str,
+) -> Result<T, jsonwebtoken::errors::Error> {
+    let data = jsonwebtoken::decode::<serde_json::Value>(
         jwt,
         &DecodingKey::from_ed_pem(public_key_pem.as_bytes()).unwrap(),
-        &validation,
+        &validation(audiences),
     )?;
+    checked_claims(policy, data.claims)
+}
+
+fn checked_claims<T: DeserializeOwned>(
+    policy: JwtPolicy,
+    claims: serde_json::Value,
+) -> Result<T, jsonwebtoken::errors::Error> {
+    if policy != JwtPolicy::default() {
+        let time_claims: TimeClaims = serde_json::from_value(claims.clone())?;
+        policy.check(&time_claims, now_secs())?;
+    }
+
+    Ok(serde_json::from_value(claims)?)
+}
+
+/// verifies the signature and audience, then the time claims required by `policy`
+pub fn verify_jwt_with_policy(
+    public_key_pem: &str,
+    audiences: HashSet<String>,
+    policy: JwtPolicy,
+    jwt: &str,
+) -> Result<(), jsonwebtoken::errors::Error> {
+    decode_jwt_with_policy::<Claims>(public_key_pem, audiences, policy, jwt)?;
 
     Ok(())
 }
@@ -34,6 +195,16 @@ pub fn verify_jwt(
 pub fn verify_jwt_from_header(
     public_key_pem: &str,
     aud: String,
+    policy: JwtPolicy,
+    req: &Request,
+) -> Result<(), (String, u16)> {
+    verify_jwt_from_header_with_audiences(public_key_pem, HashSet::from([aud]), policy, req)
+}
+
+pub fn verify_jwt_from_header_with_audiences(
+    public_key_pem: &str,
+    audiences: HashSet<String>,
+    policy: JwtPolicy,
     req: &Request,
 ) -> Result<(), (String, u16)> {
     if env_kind() == RunEnv::Mock || env_kind() == RunEnv::Local {
@@ -41,20 +212,441 @@ pub fn verify_jwt_from_header(
         return Ok(());
     }
 
+    let jwt = bearer_token(req, "Authorization")?;
+    verify_jwt_with_policy(public_key_pem, audiences, policy, &jwt)
+        .map_err(|_| ("invalid JWT".to_string(), 401))
+}
+
+fn bearer_token(req: &Request, header: &str) -> Result<String, (String, u16)> {
     let jwt = req
         .headers()
-        .get("Authorization")
+        .get(header)
         .ok()
         .flatten()
-        .ok_or_else(|| ("missing Authorization header".to_string(), 401))?;
+        .ok_or_else(|| (format!("missing {header} header"), 401))?;
+
+    let Some(jwt) = jwt.strip_prefix("Bearer ") else {
+        return Err((format!("invalid {header} header"), 401));
+    };
 
-    let jwt = jwt.to_string();
-    if !jwt.starts_with("Bearer ") {
-        return Err(("invalid Authorization header".to_string(), 401));
+    Ok(jwt.to_string())
+}
+
+/// decodes the bearer token in `header`
+/// signatures are not verified in mock/local environments, but claims are still parsed
+pub fn claims_from_header_with_audiences<T: DeserializeOwned>(
+    public_key_pem: &str,
+    audiences: HashSet<String>,
+    policy: JwtPolicy,
+    req: &Request,
+    header: &str,
+) -> Result<T, (String, u16)> {
+    let jwt = bearer_token(req, header)?;
+
+    if env_kind() == RunEnv::Mock || env_kind() == RunEnv::Local {
+        let mut validation = validation(audiences);
+        validation.insecure_disable_signature_validation();
+        return jsonwebtoken::decode::<T>(&jwt, &DecodingKey::from_secret(&[]), &validation)
+            .map(|data| data.claims)
+            .map_err(|_| ("invalid JWT".to_string(), 401));
+    }
+
+    decode_jwt_with_policy(public_key_pem, audiences, policy, &jwt)
+        .map_err(|_| ("invalid JWT".to_string(), 401))
+}
+
+/// audience of yral-admin tokens, accepted by every worker with admin routes
+pub const ADMIN_JWT_AUD: &str = "yral-admin";
+/// admin tokens are short lived, they must also be freshly issued
+pub const ADMIN_JWT_POLICY: JwtPolicy = JwtPolicy::expiring(60).with_max_age(60 * 60);
+
+/// what an admin token allows, see `AdminClaims::has`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// read only access, implied by every other role
+    Viewer,
+    /// requeues and settlements
+    Operator,
+    /// balance adjustments, and reward campaigns in yral-rewards
+    Finance,
+    /// account deletions
+    Privacy,
+    /// reviews flagged videos, only checked by yral-moderation
+    Moderator,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Finance => "finance",
+            Self::Privacy => "privacy",
+            Self::Moderator => "moderator",
+        }
+    }
+}
+
+/// claims of an admin token, `sub` identifies the admin
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdminClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    /// the one operation a co-signer token approves, see `cosign` in yral-admin
+    #[serde(default)]
+    pub approves: Option<String>,
+}
+
+impl AdminClaims {
+    pub fn has(&self, role: Role) -> bool {
+        self.roles.contains(&role) || (role == Role::Viewer && !self.roles.is_empty())
     }
+}
+
+/// verifies the admin token in `header` and that it carries `role`
+pub async fn authorize(
+    keys: &JwtKeys<'_>,
+    req: &Request,
+    header: &str,
+    role: Role,
+) -> Result<AdminClaims, (ApiError, u16)> {
+    let claims: AdminClaims = keys
+        .claims_from_header(
+            HashSet::from([ADMIN_JWT_AUD.to_string()]),
+            ADMIN_JWT_POLICY,
+            req,
+            header,
+        )
+        .await
+        .map_err(|(msg, code)| (ApiError::from_status(code, msg), code))?;
+    if !claims.has(role) {
+        let err = ApiError::new("Forbidden", format!("requires the {} role", role.as_str()))
+            .with_details(serde_json::json!({ "required": role }));
+        return Err((err, 403));
+    }
+
+    Ok(claims)
+}
+
+/// signing keys published by the auth service, used instead of a baked in PEM
+/// so keys can be rotated without redeploying
+///
+/// the document is kept in the Cache API, after half of `ttl_secs`
+/// it is refreshed in the background while the cached copy keeps being served
+#[derive(Clone, Debug)]
+pub struct JwksClient {
+    url: String,
+    ttl_secs: u64,
+    /// minimum time between refreshes forced by an unknown `kid`, per isolate
+    refresh_cooldown_secs: u64,
+}
 
-    let jwt = &jwt[7..];
-    verify_jwt(public_key_pem, aud, jwt).map_err(|_| ("invalid JWT".to_string(), 401))
+const JWKS_FETCHED_AT_HEADER: &str = "X-Jwks-Fetched-At";
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// unix secs of the last forced refresh of each JWKS url
+    static JWKS_FORCED_REFRESHES: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+}
+
+impl JwksClient {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            ttl_secs: 10 * 60,
+            refresh_cooldown_secs: 30,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl_secs: u64) -> Self {
+        self.ttl_secs = ttl_secs;
+        self
+    }
+
+    pub fn with_refresh_cooldown(mut self, refresh_cooldown_secs: u64) -> Self {
+        self.refresh_cooldown_secs = refresh_cooldown_secs;
+        self
+    }
+
+    /// claims a forced refresh at `now`, unless one ran within the cooldown
+    /// tokens with made up `kid`s must not turn into a fetch each
+    fn claim_forced_refresh(&self, now: u64) -> bool {
+        JWKS_FORCED_REFRESHES.with_borrow_mut(|refreshes| {
+            let last = refreshes.get(&self.url).copied();
+            if last.is_some_and(|last| now.saturating_sub(last) < self.refresh_cooldown_secs) {
+                return false;
+            }
+            refreshes.insert(self.url.clone(), now);
+            true
+        })
+    }
+
+    /// fetches the document and stores it in the cache
+    pub async fn refresh(&self) -> worker::Result<JwkSet> {
+        let mut res = RequestInitBuilder::default()
+            .timeout(JWKS_FETCH_TIMEOUT)
+            .fetch(&self.url)
+            .await?;
+        if res.status_code() != 200 {
+            return Err(worker::Error::RustError(format!(
+                "JWKS fetch failed with {}",
+                res.status_code()
+            )));
+        }
+        let key_set: JwkSet = res.json().await?;
+
+        let cached = Response::from_json(&key_set)?;
+        cached
+            .headers()
+            .set(JWKS_FETCHED_AT_HEADER, &now_secs().to_string())?;
+        // kept past the TTL so a failing auth service doesn't lock everyone out
+        cached
+            .headers()
+            .set("Cache-Control", &format!("max-age={}", self.ttl_secs * 2))?;
+        Cache::default().put(&self.url, cached).await?;
+
+        Ok(key_set)
+    }
+
+    /// the cached key set, refreshed once stale
+    /// pass `ctx` to refresh in the background instead of on the request path
+    pub async fn key_set(&self, ctx: Option<&Context>) -> worker::Result<JwkSet> {
+        let Some(mut cached) = Cache::default().get(&self.url, false).await? else {
+            return self.refresh().await;
+        };
+        let fetched_at = cached
+            .headers()
+            .get(JWKS_FETCHED_AT_HEADER)?
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or_default();
+        let age = now_secs().saturating_sub(fetched_at);
+
+        if age >= self.ttl_secs {
+            return match self.refresh().await {
+                Ok(key_set) => Ok(key_set),
+                Err(e) => {
+                    console_warn!("failed to refresh JWKS, using stale copy: {e}");
+                    cached.json().await
+                }
+            };
+        }
+        if age >= self.ttl_secs / 2 {
+            if let Some(ctx) = ctx {
+                let this = self.clone();
+                ctx.wait_until(async move {
+                    if let Err(e) = this.refresh().await {
+                        console_warn!("background JWKS refresh failed: {e}");
+                    }
+                });
+            }
+        }
+
+        cached.json().await
+    }
+
+    fn decoding_key(key_set: &JwkSet, kid: Option<&str>) -> Option<DecodingKey> {
+        let jwk = match kid {
+            Some(kid) => key_set.find(kid)?,
+            // tokens without a `kid` are only accepted if there's no ambiguity
+            None if key_set.keys.len() == 1 => &key_set.keys[0],
+            None => return None,
+        };
+
+        DecodingKey::from_jwk(jwk).ok()
+    }
+
+    fn decode_with_key<T: DeserializeOwned>(
+        key: &DecodingKey,
+        audiences: HashSet<String>,
+        policy: JwtPolicy,
+        jwt: &str,
+    ) -> Result<T, jsonwebtoken::errors::Error> {
+        let data = jsonwebtoken::decode::<serde_json::Value>(jwt, key, &validation(audiences))?;
+        checked_claims(policy, data.claims)
+    }
+
+    /// decodes `jwt` with the key matching its `kid`
+    /// an unknown `kid` triggers a refresh, the key may have just been rotated in, at most once
+    /// per `refresh_cooldown_secs`
+    pub async fn decode<T: DeserializeOwned>(
+        &self,
+        audiences: HashSet<String>,
+        policy: JwtPolicy,
+        jwt: &str,
+        ctx: Option<&Context>,
+    ) -> Result<T, (String, u16)> {
+        let invalid = || ("invalid JWT".to_string(), 401);
+        let unavailable = |e: worker::Error| {
+            console_error!("failed to load JWKS: {e}");
+            ("signing keys unavailable".to_string(), 503)
+        };
+
+        let header = jsonwebtoken::decode_header(jwt).map_err(|_| invalid())?;
+        let kid = header.kid.as_deref();
+        let key_set = self.key_set(ctx).await.map_err(unavailable)?;
+        let key = match Self::decoding_key(&key_set, kid) {
+            Some(key) => key,
+            None if kid.is_some() && self.claim_forced_refresh(now_secs()) => {
+                let key_set = self.refresh().await.map_err(unavailable)?;
+                Self::decoding_key(&key_set, kid).ok_or_else(invalid)?
+            }
+            None => return Err(invalid()),
+        };
+
+        Self::decode_with_key(&key, audiences, policy, jwt).map_err(|_| invalid())
+    }
+
+    /// same as `claims_from_header_with_audiences`, with keys from the JWKS
+    pub async fn claims_from_header<T: DeserializeOwned>(
+        &self,
+        audiences: HashSet<String>,
+        policy: JwtPolicy,
+        req: &Request,
+        header: &str,
+        ctx: Option<&Context>,
+    ) -> Result<T, (String, u16)> {
+        let jwt = bearer_token(req, header)?;
+
+        if env_kind() == RunEnv::Mock || env_kind() == RunEnv::Local {
+            let mut validation = validation(audiences);
+            validation.insecure_disable_signature_validation();
+            return jsonwebtoken::decode::<T>(&jwt, &DecodingKey::from_secret(&[]), &validation)
+                .map(|data| data.claims)
+                .map_err(|_| ("invalid JWT".to_string(), 401));
+        }
+
+        self.decode(audiences, policy, &jwt, ctx).await
+    }
+
+    /// same as `verify_jwt_from_header_with_audiences`, with keys from the JWKS
+    pub async fn verify_from_header(
+        &self,
+        audiences: HashSet<String>,
+        policy: JwtPolicy,
+        req: &Request,
+        ctx: Option<&Context>,
+    ) -> Result<(), (String, u16)> {
+        if env_kind() == RunEnv::Mock || env_kind() == RunEnv::Local {
+            return Ok(());
+        }
+
+        let jwt = bearer_token(req, "Authorization")?;
+        self.decode::<Claims>(audiences, policy, &jwt, ctx).await?;
+
+        Ok(())
+    }
+}
+
+/// worker var with the url of the auth service's JWKS, see `JwtKeys::from_env`
+pub const JWKS_URL_VAR: &str = "JWKS_URL";
+
+/// the keys a worker verifies tokens with
+///
+/// with a JWKS, tokens are checked against its keys first and against the worker's PEM if that
+/// fails, so issuers can move to rotated keys one at a time. without one only the PEM is used
+#[derive(Clone, Debug)]
+pub struct JwtKeys<'a> {
+    public_key_pem: &'a str,
+    jwks: Option<JwksClient>,
+}
+
+impl<'a> JwtKeys<'a> {
+    pub fn new(public_key_pem: &'a str) -> Self {
+        Self {
+            public_key_pem,
+            jwks: None,
+        }
+    }
+
+    pub fn with_jwks(mut self, jwks: JwksClient) -> Self {
+        self.jwks = Some(jwks);
+        self
+    }
+
+    /// uses the JWKS at `JWKS_URL_VAR` if the worker sets it, `public_key_pem` stays the fallback
+    pub fn from_env(env: &Env, public_key_pem: &'a str) -> Self {
+        let keys = Self::new(public_key_pem);
+        match env.var(JWKS_URL_VAR).map(|v| v.to_string()) {
+            Ok(url) if !url.is_empty() => keys.with_jwks(JwksClient::new(url)),
+            _ => keys,
+        }
+    }
+
+    /// same as `claims_from_header_with_audiences`
+    /// if both the JWKS and the PEM reject the token, the JWKS error is returned
+    pub async fn claims_from_header<T: DeserializeOwned>(
+        &self,
+        audiences: HashSet<String>,
+        policy: JwtPolicy,
+        req: &Request,
+        header: &str,
+    ) -> Result<T, (String, u16)> {
+        let Some(jwks) = &self.jwks else {
+            return claims_from_header_with_audiences(
+                self.public_key_pem,
+                audiences,
+                policy,
+                req,
+                header,
+            );
+        };
+
+        match jwks
+            .claims_from_header(audiences.clone(), policy, req, header, None)
+            .await
+        {
+            Ok(claims) => Ok(claims),
+            Err(e) => claims_from_header_with_audiences(
+                self.public_key_pem,
+                audiences,
+                policy,
+                req,
+                header,
+            )
+            .map_err(|_| e),
+        }
+    }
+
+    /// same as `verify_jwt_from_header`
+    pub async fn verify_from_header(
+        &self,
+        aud: String,
+        policy: JwtPolicy,
+        req: &Request,
+    ) -> Result<(), (String, u16)> {
+        self.verify_from_header_with_audiences(HashSet::from([aud]), policy, req)
+            .await
+    }
+
+    /// same as `verify_jwt_from_header_with_audiences`
+    pub async fn verify_from_header_with_audiences(
+        &self,
+        audiences: HashSet<String>,
+        policy: JwtPolicy,
+        req: &Request,
+    ) -> Result<(), (String, u16)> {
+        let Some(jwks) = &self.jwks else {
+            return verify_jwt_from_header_with_audiences(
+                self.public_key_pem,
+                audiences,
+                policy,
+                req,
+            );
+        };
+
+        match jwks
+            .verify_from_header(audiences.clone(), policy, req, None)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                verify_jwt_from_header_with_audiences(self.public_key_pem, audiences, policy, req)
+                    .map_err(|_| e)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -72,6 +664,20 @@ mod tests {
         MCowBQYDK2VwAyEAwmK6SSAu2E9V7uynkCKEaj5nZJyTvNG4x0KohsRzLpg=
     -----END PUBLIC KEY-----";
 
+    #[test]
+    fn admin_roles_imply_viewer() {
+        let claims: AdminClaims =
+            serde_json::from_value(serde_json::json!({ "sub": "alice", "roles": ["operator"] }))
+                .unwrap();
+        assert!(claims.has(Role::Operator));
+        assert!(claims.has(Role::Viewer));
+        assert!(!claims.has(Role::Finance));
+
+        let claims: AdminClaims =
+            serde_json::from_value(serde_json::json!({ "sub": "bob" })).unwrap();
+        assert!(!claims.has(Role::Viewer));
+    }
+
     #[test]
     fn test_verify_jwt_no_expiry_check() {
         let aud = "test-audience".to_string();
@@ -109,4 +715,216 @@ mod tests {
         let result = verify_jwt(TEST_ED25519_PUBLIC_KEY_PEM, aud, &token);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_verify_jwt_with_audience_allowlist() {
+        let claims = Claims {
+            aud: "second-audience".to_string(),
+            exp: 1,
+        };
+        let token = encode(
+            &Header::new(Algorithm::EdDSA),
+            &claims,
+            &EncodingKey::from_ed_pem(TEST_ED25519_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+        let allowed = HashSet::from(["first-audience".to_string(), "second-audience".to_string()]);
+        let result = verify_jwt_with_audiences(TEST_ED25519_PUBLIC_KEY_PEM, allowed, &token);
+        assert!(result.is_ok());
+
+        let result = verify_jwt(
+            TEST_ED25519_PUBLIC_KEY_PEM,
+            "first-audience".to_string(),
+            &token,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_verify_jwt_with_expiring_policy() {
+        #[derive(Serialize)]
+        struct TimedClaims {
+            aud: String,
+            exp: u64,
+            nbf: u64,
+        }
+
+        let aud = "test-audience".to_string();
+        let now = now_secs();
+        let sign = |exp: u64, nbf: u64| {
+            encode(
+                &Header::new(Algorithm::EdDSA),
+                &TimedClaims {
+                    aud: aud.clone(),
+                    exp,
+                    nbf,
+                },
+                &EncodingKey::from_ed_pem(TEST_ED25519_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+            )
+            .unwrap()
+        };
+        let verify = |token: &str| {
+            verify_jwt_with_policy(
+                TEST_ED25519_PUBLIC_KEY_PEM,
+                HashSet::from([aud.clone()]),
+                JwtPolicy::expiring(60),
+                token,
+            )
+        };
+
+        assert!(verify(&sign(now + 3600, now)).is_ok());
+        // within the leeway
+        assert!(verify(&sign(now - 30, now + 30)).is_ok());
+        assert!(verify(&sign(now - 120, now - 3600)).is_err());
+        assert!(verify(&sign(now + 3600, now + 120)).is_err());
+    }
+
+    #[test]
+    fn test_missing_exp_grace() {
+        let claims = |exp| TimeClaims {
+            exp,
+            nbf: None,
+            iat: None,
+        };
+        let policy = JwtPolicy::expiring(60).with_missing_exp_grace(1_000);
+
+        assert!(policy.check(&claims(None), 999).is_ok());
+        assert!(policy.check(&claims(None), 1_000).is_err());
+        assert!(policy.check(&claims(Some(500)), 999).is_err());
+        assert!(JwtPolicy::expiring(60).check(&claims(None), 999).is_err());
+    }
+
+    #[test]
+    fn test_verify_jwt_max_age() {
+        #[derive(Serialize)]
+        struct IssuedClaims {
+            aud: String,
+            exp: u64,
+            iat: u64,
+        }
+
+        let aud = "test-audience".to_string();
+        let now = now_secs();
+        let policy = JwtPolicy::default().with_max_age(600);
+        let sign = |iat: u64| {
+            encode(
+                &Header::new(Algorithm::EdDSA),
+                &IssuedClaims {
+                    aud: aud.clone(),
+                    exp: 1,
+                    iat,
+                },
+                &EncodingKey::from_ed_pem(TEST_ED25519_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+            )
+            .unwrap()
+        };
+
+        let allowed = HashSet::from([aud.clone()]);
+        let result = verify_jwt_with_policy(
+            TEST_ED25519_PUBLIC_KEY_PEM,
+            allowed.clone(),
+            policy,
+            &sign(now),
+        );
+        assert!(result.is_ok());
+        let result = verify_jwt_with_policy(
+            TEST_ED25519_PUBLIC_KEY_PEM,
+            allowed,
+            policy,
+            &sign(now - 3600),
+        );
+        assert!(result.is_err());
+    }
+
+    fn test_key_set() -> JwkSet {
+        serde_json::from_value(serde_json::json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "kid": "key-1",
+                "x": "wmK6SSAu2E9V7uynkCKEaj5nZJyTvNG4x0KohsRzLpg",
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_jwks_decodes_with_the_matching_kid() {
+        let aud = "test-audience".to_string();
+        let sign = |kid: Option<&str>| {
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = kid.map(str::to_string);
+            encode(
+                &header,
+                &Claims {
+                    aud: aud.clone(),
+                    exp: 1,
+                },
+                &EncodingKey::from_ed_pem(TEST_ED25519_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+            )
+            .unwrap()
+        };
+        let key_set = test_key_set();
+
+        let key = JwksClient::decoding_key(&key_set, Some("key-1")).unwrap();
+        let claims: Claims = JwksClient::decode_with_key(
+            &key,
+            HashSet::from([aud.clone()]),
+            JwtPolicy::default(),
+            &sign(Some("key-1")),
+        )
+        .unwrap();
+        assert_eq!(claims.aud, aud);
+
+        assert!(JwksClient::decoding_key(&key_set, Some("key-2")).is_none());
+        // a single key is used for tokens without a `kid`
+        assert!(JwksClient::decoding_key(&key_set, None).is_some());
+        let mut two_keys = key_set.clone();
+        two_keys.keys.push(key_set.keys[0].clone());
+        assert!(JwksClient::decoding_key(&two_keys, None).is_none());
+    }
+
+    #[test]
+    fn test_jwks_forced_refresh_cooldown() {
+        let client = JwksClient::new("https://auth.test/jwks-cooldown").with_refresh_cooldown(30);
+
+        assert!(client.claim_forced_refresh(100));
+        assert!(!client.claim_forced_refresh(110));
+        assert!(!client.claim_forced_refresh(129));
+        assert!(client.claim_forced_refresh(130));
+        // tracked per url
+        assert!(JwksClient::new("https://auth.test/other-jwks").claim_forced_refresh(131));
+    }
+
+    #[test]
+    fn test_decode_jwt_claims() {
+        #[derive(Serialize, Deserialize)]
+        struct SubjectClaims {
+            aud: String,
+            exp: usize,
+            sub: String,
+        }
+
+        let claims = SubjectClaims {
+            aud: "test-audience".to_string(),
+            exp: 1,
+            sub: "admin-1".to_string(),
+        };
+        let token = encode(
+            &Header::new(Algorithm::EdDSA),
+            &claims,
+            &EncodingKey::from_ed_pem(TEST_ED25519_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+        let decoded: SubjectClaims = decode_jwt_with_audiences(
+            TEST_ED25519_PUBLIC_KEY_PEM,
+            HashSet::from(["test-audience".to_string()]),
+            &token,
+        )
+        .unwrap();
+        assert_eq!(decoded.sub, "admin-1");
+    }
 }
@@ -1,67 +1,161 @@
+use std::{pin::pin, time::Duration};
+
+use futures::future::{Either, select};
 use serde::Serialize;
 use worker::*;
 
+pub mod analytics;
+pub mod api_error;
+pub mod backup;
+pub mod body;
+pub mod circuit_breaker;
+pub mod cors;
+pub mod do_client;
 pub mod environment;
+pub mod error_report;
+pub mod flags;
+pub mod health;
 pub mod icp;
 pub mod jwt;
+pub mod lock;
+pub mod maintenance;
+pub mod metrics;
+pub mod moderation;
+pub mod notification;
+pub mod outbox;
+pub mod pagination;
+pub mod principal;
+pub mod retry;
+pub mod risk;
+pub mod search;
+pub mod secrets;
+#[cfg(feature = "yral-identity")]
+pub mod signed_req;
 pub mod storage;
+#[cfg(all(not(target_arch = "wasm32"), any(test, feature = "testing")))]
+pub mod testing;
+pub mod time;
+pub mod trace;
+pub mod webhooks;
 
 #[derive(Default)]
-pub struct RequestInitBuilder(RequestInit);
+pub struct RequestInitBuilder {
+    init: RequestInit,
+    query: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
 
 impl RequestInitBuilder {
     pub fn header(&mut self, k: &str, v: &str) -> Result<&mut Self> {
-        self.0.headers.set(k, v)?;
+        self.init.headers.set(k, v)?;
+        Ok(self)
+    }
+
+    /// sets every header in `headers`, keeping the ones already set
+    pub fn headers<'a>(
+        &mut self,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<&mut Self> {
+        for (k, v) in headers {
+            self.header(k, v)?;
+        }
         Ok(self)
     }
 
     pub fn replace_headers(&mut self, headers: Headers) -> &mut Self {
-        self.0.headers = headers;
+        self.init.headers = headers;
         self
     }
 
     pub fn method(&mut self, method: Method) -> &mut Self {
-        self.0.method = method;
+        self.init.method = method;
+        self
+    }
+
+    /// query params appended to the url by `request` and `fetch`
+    pub fn query(&mut self, params: &[(&str, &str)]) -> &mut Self {
+        self.query
+            .extend(params.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        self
+    }
+
+    /// forwards `trace` in `trace::TRACE_HEADER`
+    pub fn trace(&mut self, trace: &trace::TraceId) -> Result<&mut Self> {
+        self.header(trace::TRACE_HEADER, trace.as_str())
+    }
+
+    /// aborts `fetch` if no response arrives in time
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
         self
     }
 
     // pub fn redirect(&mut self, redirect: RequestRedirect) -> &mut Self {
-    //     self.0.redirect = redirect;
+    //     self.init.redirect = redirect;
     //     self
     // }
 
     // pub fn cf_props(&mut self, props: CfProperties) -> &mut Self {
-    //     self.0.cf = props;
+    //     self.init.cf = props;
     //     self
     // }
 
     pub fn json<T: Serialize>(&mut self, body: &T) -> Result<&mut Self> {
         let json = serde_json::to_string(body)?;
-        self.0.body = Some(wasm_bindgen::JsValue::from_str(&json));
+        self.init.body = Some(wasm_bindgen::JsValue::from_str(&json));
 
         self.header("Content-Type", "application/json; charset=utf-8")
     }
 
+    /// a raw text body, for payloads that aren't JSON
+    pub fn body(&mut self, body: String, content_type: &str) -> Result<&mut Self> {
+        self.init.body = Some(wasm_bindgen::JsValue::from_str(&body));
+
+        self.header("Content-Type", content_type)
+    }
+
     pub fn build(&self) -> &RequestInit {
-        &self.0
+        &self.init
     }
-}
 
-#[macro_export]
-macro_rules! parse_principal {
-    ($ctx:ident, $param:literal) => {{
-        let raw = $ctx.param($param).unwrap();
-        let Ok(principal) = candid::Principal::from_text(raw) else {
-            return Response::error(concat!("invalid ", $param), 400);
+    /// request to `url` with the query params appended
+    pub fn request(&self, url: &str) -> Result<Request> {
+        let mut url = Url::parse(url)?;
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.query);
+        }
+
+        Request::new_with_init(url.as_str(), &self.init)
+    }
+
+    /// sends the request with `Fetch`, honouring `timeout`
+    pub async fn fetch(&self, url: &str) -> Result<Response> {
+        let req = Fetch::Request(self.request(url)?);
+        let Some(timeout) = self.timeout else {
+            return req.send().await;
         };
 
-        principal
-    }};
+        let controller = AbortController::default();
+        let signal = controller.signal();
+        let send = pin!(req.send_with_signal(&signal));
+        let delay = pin!(Delay::from(timeout));
+        match select(send, delay).await {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => {
+                controller.abort();
+                Err(Error::RustError(format!(
+                    "request timed out after {}ms",
+                    timeout.as_millis()
+                )))
+            }
+        }
+    }
 }
 
+/// responds with `e` wrapped in an `api_error::ApiError` envelope
 pub fn err_to_resp<E>(status_code: u16, e: E) -> worker::Result<worker::Response>
 where
     E: Serialize,
 {
-    Ok(worker::Response::from_json(&e)?.with_status(status_code))
+    api_error::ApiError::from_serialized(&e).into_response(status_code)
 }
@@ -0,0 +1,333 @@
+//! mutual exclusion across worker instances, backed by the `LockObject` durable object
+//!
+//! the worker hosting the object enables the `lock` feature to export the class,
+//! every worker using it binds it in wrangler.toml
+//!
+//! ```toml
+//! [durable_objects]
+//! bindings = [{ name = "LOCKS", class_name = "LockObject" }]
+//!
+//! [[migrations]]
+//! tag = "..."
+//! new_classes = ["LockObject"]
+//! ```
+//!
+//! leases expire after their TTL, so a crashed holder never blocks others for longer than that.
+//! long running holders should `renew` before the TTL runs out
+
+use std::{result::Result as StdResult, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    api_error::ApiError,
+    do_client::DoClient,
+    storage::{SafeStorage, StorageCell},
+    time::now_millis,
+};
+
+/// the current holder of a lock
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub owner: String,
+    /// unix millis
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LeaseReq {
+    owner: String,
+    ttl_ms: u64,
+}
+
+/// lease bookkeeping, independent of the durable object so it can be tested natively
+pub struct LeaseState {
+    lease: StorageCell<Option<Lease>>,
+}
+
+impl Default for LeaseState {
+    fn default() -> Self {
+        Self {
+            lease: StorageCell::new("lock_lease", || None),
+        }
+    }
+}
+
+impl LeaseState {
+    /// current unexpired lease, if any
+    pub async fn holder(&mut self, storage: &SafeStorage) -> Result<Option<Lease>> {
+        let now = now_millis();
+        Ok(self
+            .lease
+            .read(storage)
+            .await?
+            .clone()
+            .filter(|lease| lease.expires_at > now))
+    }
+
+    /// takes the lock if it is free, expired or already held by `owner`
+    /// returns the lease that blocked it otherwise
+    pub async fn acquire(
+        &mut self,
+        storage: &mut SafeStorage,
+        owner: &str,
+        ttl_ms: u64,
+    ) -> Result<StdResult<Lease, Lease>> {
+        if let Some(held) = self.holder(storage).await? {
+            if held.owner != owner {
+                return Ok(Err(held));
+            }
+        }
+
+        let lease = Lease {
+            owner: owner.to_string(),
+            expires_at: now_millis() + ttl_ms,
+        };
+        self.lease.set(storage, Some(lease.clone())).await?;
+
+        Ok(Ok(lease))
+    }
+
+    /// extends a lease still held by `owner`
+    pub async fn renew(
+        &mut self,
+        storage: &mut SafeStorage,
+        owner: &str,
+        ttl_ms: u64,
+    ) -> Result<StdResult<Lease, Option<Lease>>> {
+        match self.holder(storage).await? {
+            Some(held) if held.owner == owner => {
+                Ok(self.acquire(storage, owner, ttl_ms).await?.map_err(Some))
+            }
+            held => Ok(Err(held)),
+        }
+    }
+
+    /// frees the lock if `owner` holds it, returns whether it did
+    pub async fn release(&mut self, storage: &mut SafeStorage, owner: &str) -> Result<bool> {
+        match self.holder(storage).await? {
+            Some(held) if held.owner == owner => {
+                self.lease.set(storage, None).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[cfg(feature = "lock")]
+fn lock_held(lease: Option<Lease>) -> Result<Response> {
+    ApiError::new("LockHeld", "lock is held by another owner")
+        .with_details(lease)
+        .into_response(409)
+}
+
+/// one instance per lock name
+#[cfg(feature = "lock")]
+#[durable_object]
+pub struct LockObject {
+    state: State,
+    env: Env,
+    lease: std::cell::RefCell<LeaseState>,
+}
+
+#[cfg(feature = "lock")]
+impl DurableObject for LockObject {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            state,
+            env,
+            lease: Default::default(),
+        }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let env = self.env.clone();
+        let router = Router::with_data(self);
+
+        // SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+        // because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+        #[allow(clippy::await_holding_refcell_ref)]
+        router
+            .post_async("/acquire", async |mut req, ctx| {
+                let LeaseReq { owner, ttl_ms } = req.json().await?;
+                let this = ctx.data;
+                let mut storage: SafeStorage = this.state.storage().into();
+                let res = this
+                    .lease
+                    .borrow_mut()
+                    .acquire(&mut storage, &owner, ttl_ms)
+                    .await?;
+                match res {
+                    Ok(lease) => Response::from_json(&lease),
+                    Err(held) => lock_held(Some(held)),
+                }
+            })
+            .post_async("/renew", async |mut req, ctx| {
+                let LeaseReq { owner, ttl_ms } = req.json().await?;
+                let this = ctx.data;
+                let mut storage: SafeStorage = this.state.storage().into();
+                let res = this
+                    .lease
+                    .borrow_mut()
+                    .renew(&mut storage, &owner, ttl_ms)
+                    .await?;
+                match res {
+                    Ok(lease) => Response::from_json(&lease),
+                    Err(held) => lock_held(held),
+                }
+            })
+            .post_async("/release", async |mut req, ctx| {
+                let LeaseReq { owner, .. } = req.json().await?;
+                let this = ctx.data;
+                let mut storage: SafeStorage = this.state.storage().into();
+                let released = this
+                    .lease
+                    .borrow_mut()
+                    .release(&mut storage, &owner)
+                    .await?;
+
+                Response::from_json(&released)
+            })
+            .run(req, env)
+            .await
+    }
+}
+
+/// client side handle of a named lock
+pub struct DistributedLock<'a> {
+    client: DoClient<'a>,
+    namespace: &'a str,
+    name: String,
+    owner: String,
+}
+
+impl<'a> DistributedLock<'a> {
+    /// `namespace` is the `LockObject` binding, `owner` identifies this holder
+    pub fn new(
+        env: &'a Env,
+        namespace: &'a str,
+        name: impl Into<String>,
+        owner: impl Into<String>,
+    ) -> Self {
+        Self {
+            client: DoClient::new(env),
+            namespace,
+            name: name.into(),
+            owner: owner.into(),
+        }
+    }
+
+    async fn send(&self, path: &str, ttl: Duration) -> Result<StdResult<Lease, ApiError>> {
+        let req = LeaseReq {
+            owner: self.owner.clone(),
+            ttl_ms: ttl.as_millis() as u64,
+        };
+        let res = self
+            .client
+            .call(self.namespace, &self.name, path, &req)
+            .await?;
+
+        match res {
+            Ok(lease) => Ok(Ok(lease)),
+            Err((409, err)) => Ok(Err(err)),
+            Err((status, err)) => Err(Error::RustError(format!(
+                "lock {} {path} failed with {status}: {}",
+                self.name, err.message
+            ))),
+        }
+    }
+
+    /// `Ok(None)` if someone else holds the lock
+    pub async fn acquire(&self, ttl: Duration) -> Result<Option<Lease>> {
+        Ok(self.send("acquire", ttl).await?.ok())
+    }
+
+    /// `Ok(None)` if the lease was lost, the caller must stop its work
+    pub async fn renew(&self, ttl: Duration) -> Result<Option<Lease>> {
+        Ok(self.send("renew", ttl).await?.ok())
+    }
+
+    pub async fn release(&self) -> Result<bool> {
+        let req = LeaseReq {
+            owner: self.owner.clone(),
+            ttl_ms: 0,
+        };
+        self.client
+            .call(self.namespace, &self.name, "release", &req)
+            .await?
+            .map_err(|(status, err)| {
+                Error::RustError(format!(
+                    "lock {} release failed with {status}: {}",
+                    self.name, err.message
+                ))
+            })
+    }
+
+    /// runs `f` while holding the lock, `Ok(None)` if it is held elsewhere
+    /// `f` must finish within `ttl`, or renew the lease itself
+    pub async fn run<T>(
+        &self,
+        ttl: Duration,
+        f: impl AsyncFnOnce() -> Result<T>,
+    ) -> Result<Option<T>> {
+        if self.acquire(ttl).await?.is_none() {
+            return Ok(None);
+        }
+        let res = f().await;
+        if let Err(e) = self.release().await {
+            console_error!("failed to release lock {}: {e}", self.name);
+        }
+
+        res.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{advance_clock, block_on, freeze_clock, memory_storage};
+
+    #[test]
+    fn second_owner_waits_for_expiry() {
+        let mut storage = memory_storage();
+        let mut lease = LeaseState::default();
+
+        let held = block_on(lease.acquire(&mut storage, "a", 1000))
+            .unwrap()
+            .unwrap();
+        let blocked = block_on(lease.acquire(&mut storage, "b", 1000)).unwrap();
+        assert_eq!(blocked, Err(held));
+
+        advance_clock(1000);
+        let taken = block_on(lease.acquire(&mut storage, "b", 1000)).unwrap();
+        assert_eq!(taken.unwrap().owner, "b");
+    }
+
+    #[test]
+    fn only_the_holder_renews_and_releases() {
+        freeze_clock();
+        let mut storage = memory_storage();
+        let mut lease = LeaseState::default();
+        block_on(lease.acquire(&mut storage, "a", 1000))
+            .unwrap()
+            .unwrap();
+
+        assert!(
+            block_on(lease.renew(&mut storage, "b", 1000))
+                .unwrap()
+                .is_err()
+        );
+        assert!(!block_on(lease.release(&mut storage, "b")).unwrap());
+
+        advance_clock(500);
+        let renewed = block_on(lease.renew(&mut storage, "a", 1000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(renewed.expires_at, now_millis() + 1000);
+
+        assert!(block_on(lease.release(&mut storage, "a")).unwrap());
+        assert!(block_on(lease.holder(&storage)).unwrap().is_none());
+    }
+}
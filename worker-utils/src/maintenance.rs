@@ -0,0 +1,200 @@
+//! platform wide maintenance switch, the `maintenance` key in the `FEATURE_FLAGS` KV namespace
+//!
+//! while it's on, mutating requests are rejected with 503 and reads keep working, admin,
+//! internal and webhook routes are exempt so operators and upstream callbacks keep working
+//!
+//! ```ignore
+//! let (method, path) = (req.method(), req.path());
+//! if let Some(notice) = MaintenanceNotice::check(&env, "yral-coin", method.as_ref(), &path, &[]).await {
+//!     return notice.into_response();
+//! }
+//! ```
+
+use std::{cell::RefCell, collections::HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::{Env, Response, Result};
+
+use crate::{
+    api_error::ApiError,
+    environment::{RunEnv, env_kind},
+    flags::FEATURE_FLAGS_KV,
+    time::now_millis,
+};
+
+pub const MAINTENANCE_KEY: &str = "maintenance";
+
+const MAINTENANCE_CACHE_TTL_MS: u64 = 10_000;
+
+/// `Retry-After` when the config doesn't say when maintenance ends
+const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+    /// workers under maintenance, empty means every worker
+    #[serde(default)]
+    pub workers: HashSet<String>,
+    /// shown to users
+    #[serde(default)]
+    pub message: Option<String>,
+    /// unix millis the maintenance is expected to end at
+    #[serde(default)]
+    pub ends_at: Option<u64>,
+}
+
+impl MaintenanceConfig {
+    pub fn applies_to(&self, worker: &str) -> bool {
+        self.enabled && (self.workers.is_empty() || self.workers.contains(worker))
+    }
+
+    pub fn retry_after_secs(&self, now: u64) -> u64 {
+        self.ends_at
+            .filter(|ends_at| *ends_at > now)
+            .map(|ends_at| (ends_at - now).div_ceil(1000))
+            .unwrap_or(DEFAULT_RETRY_AFTER_SECS)
+    }
+}
+
+/// path prefixes every worker exempts, on top of the ones it passes to `check`
+pub const EXEMPT_PREFIXES: &[&str] = &["/admin/", "/internal/", "/webhook"];
+
+/// reads go through, everything else is blocked
+pub fn is_mutating(method: &str) -> bool {
+    !matches!(method, "GET" | "HEAD" | "OPTIONS")
+}
+
+pub fn is_exempt(path: &str, exempt: &[&str]) -> bool {
+    EXEMPT_PREFIXES
+        .iter()
+        .chain(exempt)
+        .any(|prefix| path.starts_with(prefix))
+}
+
+thread_local! {
+    static MAINTENANCE_CACHE: RefCell<Option<(MaintenanceConfig, u64)>> = const { RefCell::new(None) };
+}
+
+/// cached per isolate for `MAINTENANCE_CACHE_TTL_MS`, unreadable configs count as no maintenance
+async fn maintenance_config(env: &Env) -> MaintenanceConfig {
+    let now = now_millis();
+    let cached = MAINTENANCE_CACHE.with_borrow(|cache| {
+        cache
+            .as_ref()
+            .filter(|(_, fetched_at)| now.saturating_sub(*fetched_at) < MAINTENANCE_CACHE_TTL_MS)
+            .map(|(config, _)| config.clone())
+    });
+    if let Some(config) = cached {
+        return config;
+    }
+
+    let config = match fetch_config(env).await {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            worker::console_warn!("failed to read maintenance config: {e}");
+            MaintenanceConfig::default()
+        }
+    };
+    MAINTENANCE_CACHE.with_borrow_mut(|cache| *cache = Some((config.clone(), now)));
+
+    config
+}
+
+async fn fetch_config(env: &Env) -> Result<Option<MaintenanceConfig>> {
+    let config = env
+        .kv(FEATURE_FLAGS_KV)?
+        .get(MAINTENANCE_KEY)
+        .json::<MaintenanceConfig>()
+        .await?;
+
+    Ok(config)
+}
+
+/// a rejected request, rendered by `into_response` or by axum workers themselves
+#[derive(Clone, Debug)]
+pub struct MaintenanceNotice {
+    pub retry_after_secs: u64,
+    pub error: ApiError,
+}
+
+impl MaintenanceNotice {
+    /// `Some` if `worker` is under maintenance and `method` mutates a route that isn't exempt,
+    /// mock builds never are
+    pub async fn check(
+        env: &Env,
+        worker: &str,
+        method: &str,
+        path: &str,
+        exempt: &[&str],
+    ) -> Option<Self> {
+        if env_kind() == RunEnv::Mock || !is_mutating(method) || is_exempt(path, exempt) {
+            return None;
+        }
+        let config = maintenance_config(env).await;
+        if !config.applies_to(worker) {
+            return None;
+        }
+
+        Some(Self::new(&config, worker, now_millis()))
+    }
+
+    pub fn new(config: &MaintenanceConfig, worker: &str, now: u64) -> Self {
+        let retry_after_secs = config.retry_after_secs(now);
+        let message = config
+            .message
+            .clone()
+            .unwrap_or_else(|| "down for maintenance, please try again later".into());
+        let error = ApiError::new("Maintenance", message).with_details(json!({
+            "worker": worker,
+            "ends_at": config.ends_at,
+            "retry_after_secs": retry_after_secs,
+        }));
+
+        Self {
+            retry_after_secs,
+            error,
+        }
+    }
+
+    pub fn into_response(self) -> Result<Response> {
+        let mut res = self.error.into_response(503)?;
+        res.headers_mut()
+            .set("Retry-After", &self.retry_after_secs.to_string())?;
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_writes_of_targeted_workers() {
+        let config = MaintenanceConfig {
+            enabled: true,
+            workers: HashSet::from(["yral-coin".to_string()]),
+            message: None,
+            ends_at: Some(90_500),
+        };
+        assert!(config.applies_to("yral-coin"));
+        assert!(!config.applies_to("yral-hot-or-not"));
+        assert!(is_mutating("POST") && !is_mutating("GET"));
+
+        let notice = MaintenanceNotice::new(&config, "yral-coin", 30_000);
+        assert_eq!(notice.retry_after_secs, 61);
+        assert_eq!(notice.error.code, "Maintenance");
+        assert_eq!(config.retry_after_secs(100_000), DEFAULT_RETRY_AFTER_SECS);
+    }
+
+    #[test]
+    fn exempts_admin_internal_and_listed_routes() {
+        assert!(is_exempt("/admin/adjust/abc", &[]));
+        assert!(is_exempt("/internal/settle", &[]));
+        assert!(is_exempt("/webhooks/stream", &[]));
+        assert!(is_exempt("/notify", &["/notify"]));
+        assert!(!is_exempt("/transfer/abc", &["/notify"]));
+        assert!(!is_exempt("/administrator", &[]));
+    }
+}
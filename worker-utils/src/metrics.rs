@@ -0,0 +1,191 @@
+use worker::{
+    AnalyticsEngineDataPointBuilder, AnalyticsEngineDataset, Env, console_log, console_warn,
+};
+
+use crate::environment::{RunEnv, env_kind};
+
+/// analytics engine binding metrics are written to
+pub const METRICS_BINDING: &str = "METRICS";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Histogram,
+}
+
+impl MetricKind {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// A single observation written by `Metrics::record`
+pub trait Metric {
+    fn name(&self) -> &str;
+
+    fn kind(&self) -> MetricKind;
+
+    /// low cardinality dimensions, e.g. outcome or reason
+    fn labels(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn value(&self) -> f64;
+}
+
+pub struct Counter<'a> {
+    pub name: &'a str,
+    pub labels: &'a [&'a str],
+    pub count: u64,
+}
+
+impl Metric for Counter<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn kind(&self) -> MetricKind {
+        MetricKind::Counter
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.labels.iter().map(|l| l.to_string()).collect()
+    }
+
+    fn value(&self) -> f64 {
+        self.count as f64
+    }
+}
+
+pub struct Histogram<'a> {
+    pub name: &'a str,
+    pub labels: &'a [&'a str],
+    pub value: f64,
+}
+
+impl Metric for Histogram<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn kind(&self) -> MetricKind {
+        MetricKind::Histogram
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.labels.iter().map(|l| l.to_string()).collect()
+    }
+
+    fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+/// Writes metrics to the `METRICS` analytics engine dataset
+///
+/// data points are indexed by metric name
+/// blobs are `[worker, environment, kind, ...labels]`, doubles are `[value]`
+///
+/// metrics are best effort, failures are logged and never surfaced
+pub struct Metrics {
+    dataset: Option<AnalyticsEngineDataset>,
+    worker: &'static str,
+    environment: String,
+}
+
+impl Metrics {
+    /// the environment tag is read from the `ENVIRONMENT` var, falling back to the build's `RunEnv`
+    /// outside of remote builds, metrics are only logged
+    pub fn new(env: &Env, worker: &'static str) -> Self {
+        let environment = env
+            .var("ENVIRONMENT")
+            .map(|v| v.to_string())
+            .unwrap_or_else(|_| env_kind().as_str().to_string());
+        let dataset = if env_kind() == RunEnv::Remote {
+            env.analytics_engine(METRICS_BINDING).ok()
+        } else {
+            None
+        };
+
+        Self {
+            dataset,
+            worker,
+            environment,
+        }
+    }
+
+    pub fn record(&self, metric: &impl Metric) {
+        let mut blobs = vec![
+            self.worker.to_string(),
+            self.environment.clone(),
+            metric.kind().as_str().to_string(),
+        ];
+        blobs.extend(metric.labels());
+
+        let Some(dataset) = self.dataset.as_ref() else {
+            console_log!("metric {} {:?} = {}", metric.name(), blobs, metric.value());
+            return;
+        };
+
+        let mut point = AnalyticsEngineDataPointBuilder::new().indexes([metric.name()]);
+        for blob in blobs {
+            point = point.add_blob(blob.as_str());
+        }
+        if let Err(e) = point.add_double(metric.value()).write_to(dataset) {
+            console_warn!("failed to write metric {}: {e}", metric.name());
+        }
+    }
+
+    /// increments the counter `name` by 1
+    pub fn counter(&self, name: &str, labels: &[&str]) {
+        self.count(name, labels, 1);
+    }
+
+    pub fn count(&self, name: &str, labels: &[&str], count: u64) {
+        self.record(&Counter {
+            name,
+            labels,
+            count,
+        });
+    }
+
+    pub fn histogram(&self, name: &str, labels: &[&str], value: f64) {
+        self.record(&Histogram {
+            name,
+            labels,
+            value,
+        });
+    }
+}
+
+#[cfg(feature = "yral-metrics")]
+pub mod yral {
+    //! transport for the typed events of `yral-metrics`
+
+    use yral_metrics::{
+        metric_sender::{
+            LocalMetricTx, js_spawn::JsSpawnMetricTx, mock::MaybeMockLocalMetricEventTx,
+            vectordb::VectorDbMetricTx,
+        },
+        metrics::EventSource,
+    };
+
+    use crate::environment::{RunEnv, env_kind};
+
+    pub type CfMetricTx =
+        LocalMetricTx<MaybeMockLocalMetricEventTx<JsSpawnMetricTx<VectorDbMetricTx>>>;
+
+    /// events are only sent from remote builds, mocked otherwise
+    pub fn event_tx(source: EventSource) -> CfMetricTx {
+        let ev_tx = if env_kind() == RunEnv::Remote {
+            MaybeMockLocalMetricEventTx::Real(JsSpawnMetricTx(VectorDbMetricTx::default()))
+        } else {
+            MaybeMockLocalMetricEventTx::default()
+        };
+
+        LocalMetricTx::new(source, ev_tx)
+    }
+}
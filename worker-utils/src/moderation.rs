@@ -0,0 +1,90 @@
+//! flagged videos, reviewed in the `yral-moderation` worker
+//!
+//! producers send a `ModerationFlag` to `MODERATION_FLAGS_QUEUE`, every flag on a post is folded
+//! into a single case
+
+use serde::{Deserialize, Serialize};
+
+pub const MODERATION_FLAGS_QUEUE: &str = "MODERATION_FLAGS";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum FlagSource {
+    /// the upload's NSFW gate
+    Nsfw {
+        /// 0 to 1
+        score: f64,
+    },
+    UserReport {
+        reporter_principal: String,
+        reason: String,
+    },
+}
+
+impl FlagSource {
+    /// the serde tag, e.g. `user_report`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Nsfw { .. } => "nsfw",
+            Self::UserReport { .. } => "user_report",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ModerationFlag {
+    /// canister the post lives on
+    pub user_canister: String,
+    pub post_id: u64,
+    /// cloudflare stream uid, deleted if the post is rejected
+    #[serde(default)]
+    pub video_uid: Option<String>,
+    /// notified if the post is rejected
+    #[serde(default)]
+    pub creator_principal: Option<String>,
+    #[serde(flatten)]
+    pub source: FlagSource,
+    /// unix millis
+    pub at: u64,
+}
+
+impl ModerationFlag {
+    /// same id for every flag on the post
+    pub fn case_id(&self) -> String {
+        format!("{}:{}", self.user_canister, self.post_id)
+    }
+
+    /// sends the flag to `MODERATION_FLAGS_QUEUE`
+    #[cfg(feature = "queue")]
+    pub async fn send(&self, env: &worker::Env) -> worker::Result<()> {
+        env.queue(MODERATION_FLAGS_QUEUE)?.send(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn flags_carry_their_source_inline() {
+        let flag = ModerationFlag {
+            user_canister: "2vxsx-fae".into(),
+            post_id: 7,
+            video_uid: None,
+            creator_principal: None,
+            source: FlagSource::Nsfw { score: 0.9 },
+            at: 1_700_000_000_000,
+        };
+        let value = serde_json::to_value(&flag).unwrap();
+
+        assert_eq!(value["source"], json!("nsfw"));
+        assert_eq!(value["score"], json!(0.9));
+        assert_eq!(
+            serde_json::from_value::<ModerationFlag>(value).unwrap(),
+            flag
+        );
+        assert_eq!(flag.case_id(), "2vxsx-fae:7");
+    }
+}
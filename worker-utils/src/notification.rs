@@ -0,0 +1,439 @@
+//! push notification jobs, delivered by the `yral-notifications` worker
+//!
+//! producers enqueue a `NotificationJob` on `NOTIFICATIONS_QUEUE` instead of calling
+//! yral-metadata themselves, the consumer batches them per user and applies preferences
+//!
+//! low priority notifications are held back and sent together in the user's next digest push
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+
+use crate::{time::now_millis, trace::TraceId};
+
+pub const NOTIFICATIONS_QUEUE: &str = "NOTIFICATIONS";
+
+const WALLET_DEEP_LINK: &str = "https://yral.com/wallet";
+const UPLOAD_DEEP_LINK: &str = "https://yral.com/upload";
+
+/// what users can mute in their preferences
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationCategory {
+    Uploads,
+    Rewards,
+    Wallet,
+}
+
+/// high priority notifications are pushed right away, low priority ones wait for the digest
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationPriority {
+    High,
+    Low,
+}
+
+/// languages notifications are rendered in, anything else falls back to english
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(from = "String", rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Hi,
+    Pt,
+}
+
+impl Locale {
+    /// the language of a BCP 47 tag, e.g. `es` for `es-MX`
+    pub fn from_tag(tag: &str) -> Self {
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "es" => Self::Es,
+            "hi" => Self::Hi,
+            "pt" => Self::Pt,
+            _ => Self::En,
+        }
+    }
+
+    /// title of a digest merging `count` notifications
+    pub fn digest_title(self, count: usize) -> String {
+        match self {
+            Self::En => format!("You have {count} new notifications"),
+            Self::Es => format!("Tienes {count} notificaciones nuevas"),
+            Self::Hi => format!("आपके {count} नए नोटिफ़िकेशन हैं"),
+            Self::Pt => format!("Você tem {count} novas notificações"),
+        }
+    }
+}
+
+impl From<String> for Locale {
+    fn from(tag: String) -> Self {
+        Self::from_tag(&tag)
+    }
+}
+
+/// title and body of one notification kind in one locale, `{field}`s are filled in on render
+struct Template {
+    locale: Locale,
+    kind: &'static str,
+    title: &'static str,
+    body: &'static str,
+}
+
+const fn template(
+    locale: Locale,
+    kind: &'static str,
+    title: &'static str,
+    body: &'static str,
+) -> Template {
+    Template {
+        locale,
+        kind,
+        title,
+        body,
+    }
+}
+
+#[rustfmt::skip]
+static TEMPLATES: &[Template] = &[
+    template(Locale::En, "video_uploaded_to_draft",
+        "Your AI video was generated and added to Drafts in Profile section!",
+        "Your AI video was generated and added to Drafts in Profile section!"),
+    template(Locale::En, "video_published",
+        "Your AI video has been published successfully",
+        "Your AI video has been published successfully"),
+    template(Locale::En, "referral_reward",
+        "You earned a referral reward",
+        "You have received a referral reward of {amount} SATS. User Joined {referee}"),
+    template(Locale::En, "coins_credited", "YRAL received", "{amount} YRAL were added to your wallet"),
+    template(Locale::En, "campaign_reward", "You earned a reward", "You have received a reward of {amount} YRAL"),
+    template(Locale::En, "withdrawal_completed", "Withdrawal complete", "{amount} {token} were sent to your wallet"),
+    template(Locale::En, "post_removed",
+        "Your video was removed",
+        "It was reviewed and found to break our community guidelines"),
+    template(Locale::En, "video_upload_error", "Your video couldn't be uploaded", "{reason}"),
+
+    template(Locale::Es, "video_uploaded_to_draft",
+        "¡Tu video de IA se generó y se añadió a Borradores en tu perfil!",
+        "¡Tu video de IA se generó y se añadió a Borradores en tu perfil!"),
+    template(Locale::Es, "video_published",
+        "Tu video de IA se publicó correctamente",
+        "Tu video de IA se publicó correctamente"),
+    template(Locale::Es, "referral_reward",
+        "Ganaste una recompensa por referido",
+        "Recibiste una recompensa por referido de {amount} SATS. Se unió el usuario {referee}"),
+    template(Locale::Es, "coins_credited", "YRAL recibidos", "Se añadieron {amount} YRAL a tu billetera"),
+    template(Locale::Es, "campaign_reward", "Ganaste una recompensa", "Recibiste una recompensa de {amount} YRAL"),
+    template(Locale::Es, "withdrawal_completed", "Retiro completado", "Se enviaron {amount} {token} a tu billetera"),
+    template(Locale::Es, "post_removed",
+        "Tu video fue eliminado",
+        "Fue revisado y no cumple nuestras normas de la comunidad"),
+    template(Locale::Es, "video_upload_error", "No se pudo subir tu video", "{reason}"),
+
+    template(Locale::Hi, "video_uploaded_to_draft",
+        "आपका AI वीडियो बन गया है और प्रोफ़ाइल में ड्राफ़्ट में जोड़ दिया गया है!",
+        "आपका AI वीडियो बन गया है और प्रोफ़ाइल में ड्राफ़्ट में जोड़ दिया गया है!"),
+    template(Locale::Hi, "video_published",
+        "आपका AI वीडियो सफलतापूर्वक प्रकाशित हो गया है",
+        "आपका AI वीडियो सफलतापूर्वक प्रकाशित हो गया है"),
+    template(Locale::Hi, "referral_reward",
+        "आपको रेफ़रल इनाम मिला",
+        "आपको {amount} SATS का रेफ़रल इनाम मिला है। जुड़ने वाले यूज़र {referee}"),
+    template(Locale::Hi, "coins_credited", "YRAL मिले", "आपके वॉलेट में {amount} YRAL जोड़े गए"),
+    template(Locale::Hi, "campaign_reward", "आपको इनाम मिला", "आपको {amount} YRAL का इनाम मिला है"),
+    template(Locale::Hi, "withdrawal_completed", "निकासी पूरी हुई", "{amount} {token} आपके वॉलेट में भेजे गए"),
+    template(Locale::Hi, "post_removed",
+        "आपका वीडियो हटा दिया गया",
+        "समीक्षा में यह हमारे कम्युनिटी दिशानिर्देशों के ख़िलाफ़ पाया गया"),
+    template(Locale::Hi, "video_upload_error", "आपका वीडियो अपलोड नहीं हो सका", "{reason}"),
+
+    template(Locale::Pt, "video_uploaded_to_draft",
+        "Seu vídeo de IA foi gerado e adicionado aos Rascunhos no seu perfil!",
+        "Seu vídeo de IA foi gerado e adicionado aos Rascunhos no seu perfil!"),
+    template(Locale::Pt, "video_published",
+        "Seu vídeo de IA foi publicado com sucesso",
+        "Seu vídeo de IA foi publicado com sucesso"),
+    template(Locale::Pt, "referral_reward",
+        "Você ganhou uma recompensa por indicação",
+        "Você recebeu uma recompensa por indicação de {amount} SATS. Usuário que entrou: {referee}"),
+    template(Locale::Pt, "coins_credited", "YRAL recebidos", "{amount} YRAL foram adicionados à sua carteira"),
+    template(Locale::Pt, "campaign_reward", "Você ganhou uma recompensa", "Você recebeu uma recompensa de {amount} YRAL"),
+    template(Locale::Pt, "withdrawal_completed", "Saque concluído", "{amount} {token} foram enviados para sua carteira"),
+    template(Locale::Pt, "post_removed",
+        "Seu vídeo foi removido",
+        "Ele foi analisado e viola nossas diretrizes da comunidade"),
+    template(Locale::Pt, "video_upload_error", "Não foi possível enviar seu vídeo", "{reason}"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notification {
+    VideoUploadedToDraft {
+        post_id: String,
+    },
+    VideoPublished {
+        post_id: String,
+    },
+    ReferralReward {
+        referee_principal: Principal,
+        amount: u64,
+    },
+    CoinsCredited {
+        amount: String,
+    },
+    CampaignReward {
+        amount: String,
+    },
+    /// `amount` of `token` left the user's wallet
+    WithdrawalCompleted {
+        amount: String,
+        token: String,
+    },
+    /// rejected in moderation
+    PostRemoved {
+        post_id: String,
+    },
+    /// Stream couldn't process the upload, `reason` is shown to the user
+    VideoUploadError {
+        video_uid: String,
+        reason: String,
+    },
+}
+
+impl Notification {
+    /// the serde tag, e.g. `coins_credited`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::VideoUploadedToDraft { .. } => "video_uploaded_to_draft",
+            Self::VideoPublished { .. } => "video_published",
+            Self::ReferralReward { .. } => "referral_reward",
+            Self::CoinsCredited { .. } => "coins_credited",
+            Self::CampaignReward { .. } => "campaign_reward",
+            Self::WithdrawalCompleted { .. } => "withdrawal_completed",
+            Self::PostRemoved { .. } => "post_removed",
+            Self::VideoUploadError { .. } => "video_upload_error",
+        }
+    }
+
+    pub fn category(&self) -> NotificationCategory {
+        match self {
+            Self::VideoUploadedToDraft { .. }
+            | Self::VideoPublished { .. }
+            | Self::PostRemoved { .. }
+            | Self::VideoUploadError { .. } => NotificationCategory::Uploads,
+            Self::ReferralReward { .. } | Self::CampaignReward { .. } => {
+                NotificationCategory::Rewards
+            }
+            Self::CoinsCredited { .. } | Self::WithdrawalCompleted { .. } => {
+                NotificationCategory::Wallet
+            }
+        }
+    }
+
+    pub fn priority(&self) -> NotificationPriority {
+        match self {
+            Self::VideoUploadedToDraft { .. }
+            | Self::VideoPublished { .. }
+            | Self::WithdrawalCompleted { .. }
+            | Self::PostRemoved { .. }
+            | Self::VideoUploadError { .. } => NotificationPriority::High,
+            Self::ReferralReward { .. }
+            | Self::CoinsCredited { .. }
+            | Self::CampaignReward { .. } => NotificationPriority::Low,
+        }
+    }
+
+    /// english title, see `title_in`
+    pub fn title(&self) -> String {
+        self.title_in(Locale::En)
+    }
+
+    /// english body, see `body_in`
+    pub fn body(&self) -> String {
+        self.body_in(Locale::En)
+    }
+
+    pub fn title_in(&self, locale: Locale) -> String {
+        self.render(self.template(locale).title)
+    }
+
+    pub fn body_in(&self, locale: Locale) -> String {
+        self.render(self.template(locale).body)
+    }
+
+    /// `locale`'s template, english if it has none for this kind
+    fn template(&self, locale: Locale) -> &'static Template {
+        let find = |locale| {
+            TEMPLATES
+                .iter()
+                .find(|t| t.locale == locale && t.kind == self.kind())
+        };
+        find(locale)
+            .or_else(|| find(Locale::En))
+            .expect("every notification kind has an english template")
+    }
+
+    fn render(&self, template: &str) -> String {
+        match self {
+            Self::VideoUploadedToDraft { .. }
+            | Self::VideoPublished { .. }
+            | Self::PostRemoved { .. } => template.to_string(),
+            Self::ReferralReward {
+                referee_principal,
+                amount,
+            } => template
+                .replace("{amount}", &amount.to_string())
+                .replace("{referee}", &referee_principal.to_text()),
+            Self::CoinsCredited { amount } | Self::CampaignReward { amount } => {
+                template.replace("{amount}", amount)
+            }
+            Self::WithdrawalCompleted { amount, token } => template
+                .replace("{amount}", amount)
+                .replace("{token}", token),
+            Self::VideoUploadError { reason, .. } => template.replace("{reason}", reason),
+        }
+    }
+
+    pub fn deeplink(&self) -> Option<&'static str> {
+        match self {
+            Self::CoinsCredited { .. }
+            | Self::CampaignReward { .. }
+            | Self::WithdrawalCompleted { .. } => Some(WALLET_DEEP_LINK),
+            Self::VideoUploadError { .. } => Some(UPLOAD_DEEP_LINK),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NotificationJob {
+    pub user_principal: Principal,
+    pub notification: Notification,
+    /// unix millis
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<TraceId>,
+}
+
+impl NotificationJob {
+    pub fn new(user_principal: Principal, notification: Notification) -> Self {
+        Self {
+            user_principal,
+            notification,
+            created_at: now_millis(),
+            trace_id: None,
+        }
+    }
+
+    pub fn with_trace(mut self, trace: &TraceId) -> Self {
+        self.trace_id = Some(trace.clone());
+        self
+    }
+
+    /// queues the job on `NOTIFICATIONS_QUEUE`
+    #[cfg(feature = "queue")]
+    pub async fn enqueue(&self, env: &worker::Env) -> worker::Result<()> {
+        env.queue(NOTIFICATIONS_QUEUE)?.send(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn jobs_are_tagged_by_type() {
+        let job = NotificationJob::new(
+            Principal::anonymous(),
+            Notification::CoinsCredited { amount: "5".into() },
+        );
+        let value = serde_json::to_value(&job).unwrap();
+        assert_eq!(
+            value["notification"],
+            json!({ "type": "coins_credited", "amount": "5" })
+        );
+        assert_eq!(value["notification"]["type"], job.notification.kind());
+        assert_eq!(job.notification.category(), NotificationCategory::Wallet);
+        assert_eq!(job.notification.deeplink(), Some(WALLET_DEEP_LINK));
+        assert_eq!(job.notification.priority(), NotificationPriority::Low);
+    }
+
+    #[test]
+    fn withdrawals_skip_the_digest() {
+        let notification = Notification::WithdrawalCompleted {
+            amount: "1000".into(),
+            token: "SATS".into(),
+        };
+        assert_eq!(notification.priority(), NotificationPriority::High);
+        assert_eq!(notification.category(), NotificationCategory::Wallet);
+        assert_eq!(notification.body(), "1000 SATS were sent to your wallet");
+    }
+
+    #[test]
+    fn every_kind_has_an_english_template() {
+        let notifications = [
+            Notification::VideoUploadedToDraft {
+                post_id: "1".into(),
+            },
+            Notification::VideoPublished {
+                post_id: "1".into(),
+            },
+            Notification::ReferralReward {
+                referee_principal: Principal::anonymous(),
+                amount: 5,
+            },
+            Notification::CoinsCredited { amount: "5".into() },
+            Notification::CampaignReward { amount: "5".into() },
+            Notification::WithdrawalCompleted {
+                amount: "5".into(),
+                token: "SATS".into(),
+            },
+            Notification::PostRemoved {
+                post_id: "1".into(),
+            },
+            Notification::VideoUploadError {
+                video_uid: "uid".into(),
+                reason: "reason".into(),
+            },
+        ];
+        for notification in notifications {
+            assert!(!notification.title().is_empty());
+            assert!(
+                !notification.body().contains('{'),
+                "{}",
+                notification.body()
+            );
+        }
+    }
+
+    #[test]
+    fn renders_in_the_locale_with_english_fallback() {
+        let notification = Notification::CoinsCredited { amount: "5".into() };
+        assert_eq!(
+            notification.body_in(Locale::from_tag("es-MX")),
+            "Se añadieron 5 YRAL a tu billetera"
+        );
+        assert_eq!(
+            notification.body_in(Locale::from_tag("fr")),
+            "5 YRAL were added to your wallet"
+        );
+        assert_eq!(
+            serde_json::from_value::<Locale>(json!("pt-BR")).unwrap(),
+            Locale::Pt
+        );
+    }
+
+    #[test]
+    fn upload_errors_show_the_reason() {
+        let notification = Notification::VideoUploadError {
+            video_uid: "uid".into(),
+            reason: "Unsupported codec".into(),
+        };
+        assert_eq!(notification.priority(), NotificationPriority::High);
+        assert_eq!(notification.category(), NotificationCategory::Uploads);
+        assert_eq!(notification.body(), "Unsupported codec");
+        assert_eq!(notification.deeplink(), Some(UPLOAD_DEEP_LINK));
+    }
+}
@@ -0,0 +1,275 @@
+//! events stored in durable object storage alongside the state change that produced them,
+//! delivered to a sink later by `drain`
+//!
+//! the owner calls `drain` from its alarm (or a queue consumer) and reschedules
+//! for `DrainReport::next_attempt_at`, so events survive sink outages and restarts
+
+use std::{fmt::Debug, marker::PhantomData};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use worker::{ListOptions, Result};
+
+use crate::{
+    retry::{RetryPolicy, random_unit},
+    storage::{SafeStorage, StorageCell, batch::WriteBatch},
+    time::now_millis,
+};
+
+/// entries delivered per `drain` call
+pub const DRAIN_BATCH_SIZE: usize = 64;
+
+/// where drained events go
+#[allow(async_fn_in_trait)]
+pub trait OutboxSink<E> {
+    async fn deliver(&self, event: &E) -> Result<()>;
+}
+
+#[cfg(feature = "queue")]
+impl<E: Serialize> OutboxSink<E> for worker::Queue {
+    async fn deliver(&self, event: &E) -> Result<()> {
+        self.send(event).await
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutboxEntry<E> {
+    pub event: E,
+    pub recorded_at: u64,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+/// outcome of a `drain` call
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    pub delivered: usize,
+    pub failed: usize,
+    /// moved to the dead letter prefix after exhausting their attempts
+    pub dead: usize,
+    /// when `drain` should run again, None if the outbox is empty
+    pub next_attempt_at: Option<u64>,
+}
+
+fn earliest(current: Option<u64>, at: u64) -> Option<u64> {
+    Some(current.map_or(at, |current| current.min(at)))
+}
+
+pub struct Outbox<E> {
+    prefix: String,
+    policy: RetryPolicy,
+    next_seq: StorageCell<u64>,
+    _event: PhantomData<E>,
+}
+
+impl<E: Serialize + DeserializeOwned + Clone + Debug> Outbox<E> {
+    /// entries live under `{prefix}:`, dead letters under `{prefix}_dead:`
+    pub fn new(prefix: impl AsRef<str>, policy: RetryPolicy) -> Self {
+        let prefix = prefix.as_ref().to_string();
+        Self {
+            next_seq: StorageCell::new(format!("{prefix}_seq"), || 0),
+            prefix,
+            policy,
+            _event: PhantomData,
+        }
+    }
+
+    fn entry_key(&self, seq: u64) -> String {
+        // zero padded so keys list in recording order
+        format!("{}:{seq:020}", self.prefix)
+    }
+
+    fn dead_key(&self, key: &str) -> String {
+        let seq = key.rsplit(':').next().unwrap_or(key);
+        format!("{}_dead:{seq}", self.prefix)
+    }
+
+    /// stages `event` in `batch`, so it is only recorded if the batch commits
+    /// `invalidate` if the batch fails to commit
+    pub async fn record_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        event: E,
+    ) -> Result<()> {
+        let seq = *self.next_seq.read(storage).await?;
+        let now = now_millis();
+        batch.put(
+            self.entry_key(seq),
+            &OutboxEntry {
+                event,
+                recorded_at: now,
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+            },
+        )?;
+        self.next_seq.set_in(batch, seq + 1)
+    }
+
+    /// records `event` on its own
+    pub async fn record(&mut self, storage: &mut SafeStorage, event: E) -> Result<()> {
+        let mut batch = storage.batch();
+        self.record_in(storage, &mut batch, event).await?;
+        if let Err(e) = batch.commit(storage).await {
+            self.invalidate();
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    pub fn invalidate(&mut self) {
+        self.next_seq.invalidate();
+    }
+
+    /// delivers due entries in recording order, at most `DRAIN_BATCH_SIZE` per call
+    /// failed entries are retried with the policy's backoff
+    pub async fn drain(
+        &mut self,
+        storage: &mut SafeStorage,
+        sink: &impl OutboxSink<E>,
+    ) -> Result<DrainReport> {
+        let prefix = format!("{}:", self.prefix);
+        let entries = storage
+            .list_with_options::<OutboxEntry<E>>(
+                ListOptions::new().prefix(&prefix).limit(DRAIN_BATCH_SIZE),
+            )
+            .await
+            .collect::<Result<Vec<_>>>()?;
+        let more = entries.len() == DRAIN_BATCH_SIZE;
+
+        let now = now_millis();
+        let mut report = DrainReport::default();
+        let mut batch = storage.batch();
+        let mut next_attempt_at = None;
+        for (key, mut entry) in entries {
+            if entry.next_attempt_at > now {
+                next_attempt_at = earliest(next_attempt_at, entry.next_attempt_at);
+                continue;
+            }
+
+            let err = match sink.deliver(&entry.event).await {
+                Ok(()) => {
+                    batch.delete(&key);
+                    report.delivered += 1;
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            entry.attempts += 1;
+            entry.last_error = Some(err.to_string());
+            if entry.attempts >= self.policy.max_attempts {
+                batch.put(self.dead_key(&key), &entry)?.delete(&key);
+                report.dead += 1;
+                continue;
+            }
+
+            entry.next_attempt_at = now + self.policy.delay_ms(entry.attempts, random_unit());
+            batch.put(&key, &entry)?;
+            report.failed += 1;
+            next_attempt_at = earliest(next_attempt_at, entry.next_attempt_at);
+        }
+        batch.commit(storage).await?;
+        // entries past this batch may already be due, but only come back right away
+        // if this batch made progress, otherwise a stuck head would spin the alarm
+        if more && report.delivered + report.dead > 0 {
+            next_attempt_at = Some(now);
+        }
+        report.next_attempt_at = next_attempt_at;
+
+        Ok(report)
+    }
+
+    /// entries that exhausted their attempts, for inspection or manual replay
+    pub async fn dead_letters(
+        &self,
+        storage: &SafeStorage,
+    ) -> Result<Vec<(String, OutboxEntry<E>)>> {
+        storage
+            .list_with_prefix(format!("{}_dead:", self.prefix))
+            .await
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::testing::{advance_clock, block_on, memory_storage};
+
+    /// fails the first `failures` deliveries
+    #[derive(Default)]
+    struct FlakySink {
+        failures: RefCell<u32>,
+        delivered: RefCell<Vec<u32>>,
+    }
+
+    impl OutboxSink<u32> for FlakySink {
+        async fn deliver(&self, event: &u32) -> Result<()> {
+            let mut failures = self.failures.borrow_mut();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(worker::Error::RustError("sink down".into()));
+            }
+            self.delivered.borrow_mut().push(*event);
+            Ok(())
+        }
+    }
+
+    fn outbox() -> Outbox<u32> {
+        Outbox::new("events", RetryPolicy::new(2).without_jitter())
+    }
+
+    #[test]
+    fn delivers_in_order_and_empties() {
+        let mut storage = memory_storage();
+        let mut outbox = outbox();
+        for event in [1, 2, 3] {
+            block_on(outbox.record(&mut storage, event)).unwrap();
+        }
+
+        let sink = FlakySink::default();
+        let report = block_on(outbox.drain(&mut storage, &sink)).unwrap();
+        assert_eq!(report.delivered, 3);
+        assert_eq!(report.next_attempt_at, None);
+        assert_eq!(*sink.delivered.borrow(), vec![1, 2, 3]);
+
+        let report = block_on(outbox.drain(&mut storage, &sink)).unwrap();
+        assert_eq!(report, DrainReport::default());
+    }
+
+    #[test]
+    fn retries_with_backoff_then_dead_letters() {
+        let mut storage = memory_storage();
+        let mut outbox = outbox();
+        block_on(outbox.record(&mut storage, 7)).unwrap();
+
+        let sink = FlakySink {
+            failures: RefCell::new(2),
+            ..Default::default()
+        };
+        let report = block_on(outbox.drain(&mut storage, &sink)).unwrap();
+        assert_eq!(report.failed, 1);
+        let retry_at = report.next_attempt_at.unwrap();
+        assert!(retry_at > now_millis());
+
+        // not due yet
+        let report = block_on(outbox.drain(&mut storage, &sink)).unwrap();
+        assert_eq!(report.failed, 0);
+        assert_eq!(report.next_attempt_at, Some(retry_at));
+
+        advance_clock(retry_at - now_millis());
+        let report = block_on(outbox.drain(&mut storage, &sink)).unwrap();
+        assert_eq!(report.dead, 1);
+        assert_eq!(report.next_attempt_at, None);
+
+        let dead = block_on(outbox.dead_letters(&storage)).unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].1.event, 7);
+        assert_eq!(dead[0].1.attempts, 2);
+    }
+}
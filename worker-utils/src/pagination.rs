@@ -0,0 +1,159 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::de::DeserializeOwned;
+use worker::{ListOptions, Result};
+
+use crate::storage::SafeStorage;
+
+pub const DEFAULT_MAX_PAGE_SIZE: usize = 100;
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// opaque cursor for the next page, None on the last page
+    pub next: Option<String>,
+}
+
+/// Pages through the storage keys under `prefix`
+///
+/// cursors are base64 encoded storage keys, clients should treat them as opaque
+/// raw keys under `prefix` are still accepted as cursors for older clients
+#[derive(Clone, Copy, Debug)]
+pub struct KeyCursorPager<'a> {
+    prefix: &'a str,
+    max_page_size: usize,
+    reverse: bool,
+}
+
+impl<'a> KeyCursorPager<'a> {
+    pub const fn new(prefix: &'a str) -> Self {
+        Self {
+            prefix,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            reverse: false,
+        }
+    }
+
+    pub const fn with_max_page_size(mut self, max_page_size: usize) -> Self {
+        self.max_page_size = max_page_size;
+        self
+    }
+
+    /// iterate from the last key backwards
+    pub const fn reversed(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    pub fn clamp_page_size(&self, page_size: usize) -> usize {
+        page_size.clamp(1, self.max_page_size.max(1))
+    }
+
+    pub fn encode_cursor(&self, key: &str) -> String {
+        URL_SAFE_NO_PAD.encode(key)
+    }
+
+    pub fn decode_cursor(&self, cursor: &str) -> Result<String> {
+        if let Some(key) = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|raw| String::from_utf8(raw).ok())
+            .filter(|key| key.starts_with(self.prefix))
+        {
+            return Ok(key);
+        }
+        if cursor.starts_with(self.prefix) {
+            return Ok(cursor.to_string());
+        }
+
+        Err(worker::Error::RustError("invalid cursor".into()))
+    }
+
+    /// fetches a page of at most `page_size` items
+    /// `map` receives each key with `prefix` stripped
+    pub async fn page<T: DeserializeOwned, R>(
+        &self,
+        storage: &SafeStorage,
+        page_size: usize,
+        cursor: Option<&str>,
+        mut map: impl FnMut(&str, T) -> R,
+    ) -> Result<Page<R>> {
+        let page_size = self.clamp_page_size(page_size);
+        let mut list_options = ListOptions::new()
+            .prefix(self.prefix)
+            .reverse(self.reverse)
+            .limit(page_size + 1);
+        let cursor = cursor.map(|c| self.decode_cursor(c)).transpose()?;
+        if let Some(cursor) = cursor.as_ref() {
+            // forward cursors are the first key of the next page (inclusive start)
+            // reverse cursors are the last key returned (exclusive end)
+            list_options = if self.reverse {
+                list_options.end(cursor.as_str())
+            } else {
+                list_options.start(cursor.as_str())
+            };
+        }
+
+        let mut entries = storage
+            .list_with_options::<T>(list_options)
+            .await
+            .collect::<Result<Vec<_>>>()?;
+        let next_key = if entries.len() > page_size {
+            let (next_key, _) = entries.pop().unwrap();
+            if self.reverse {
+                entries.last().map(|(k, _)| k.clone())
+            } else {
+                Some(next_key)
+            }
+        } else {
+            None
+        };
+
+        let items = entries
+            .into_iter()
+            .map(|(k, v)| map(&k[self.prefix.len()..], v))
+            .collect();
+
+        Ok(Page {
+            items,
+            next: next_key.map(|k| self.encode_cursor(&k)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let pager = KeyCursorPager::new("games-");
+        let cursor = pager.encode_cursor("games-aaaaa-aa-1");
+
+        assert_ne!(cursor, "games-aaaaa-aa-1");
+        assert_eq!(pager.decode_cursor(&cursor).unwrap(), "games-aaaaa-aa-1");
+    }
+
+    #[test]
+    fn legacy_raw_cursor_accepted() {
+        let pager = KeyCursorPager::new("games-");
+
+        assert_eq!(pager.decode_cursor("games-x-1").unwrap(), "games-x-1");
+    }
+
+    #[test]
+    fn cursor_outside_prefix_rejected() {
+        let pager = KeyCursorPager::new("games-");
+        let cursor = pager.encode_cursor("ledger-1");
+
+        assert!(pager.decode_cursor(&cursor).is_err());
+        assert!(pager.decode_cursor("ledger-1").is_err());
+    }
+
+    #[test]
+    fn page_size_clamped() {
+        let pager = KeyCursorPager::new("games-").with_max_page_size(10);
+
+        assert_eq!(pager.clamp_page_size(0), 1);
+        assert_eq!(pager.clamp_page_size(5), 5);
+        assert_eq!(pager.clamp_page_size(50), 10);
+    }
+}
@@ -0,0 +1,110 @@
+//! principal path params, rejected with the standard `ApiError` envelope
+//!
+//! ```ignore
+//! // worker Router handlers
+//! let (game_canister, token_root) = principals!(ctx, "game_canister", "token_root");
+//!
+//! // axum handlers
+//! async fn handler(Principals((user, post_owner)): Principals<(Principal, Principal)>) {}
+//! ```
+
+use std::result::Result as StdResult;
+
+use candid::Principal;
+use serde_json::json;
+use worker::RouteContext;
+
+use crate::api_error::ApiError;
+
+/// parses the path param `name`, a missing param is reported like an invalid one
+pub fn parse_principal(name: &str, raw: Option<&str>) -> StdResult<Principal, ApiError> {
+    raw.and_then(|raw| Principal::from_text(raw).ok())
+        .ok_or_else(|| {
+            ApiError::new("InvalidPrincipal", format!("invalid {name}"))
+                .with_details(json!({ "param": name }))
+        })
+}
+
+pub fn route_principal<D>(ctx: &RouteContext<D>, name: &str) -> StdResult<Principal, ApiError> {
+    parse_principal(name, ctx.param(name).map(String::as_str))
+}
+
+/// parses one or more principal path params, returning a 400 from the handler on failure
+///
+/// a single param gives a `Principal`, several give a tuple in the same order
+#[macro_export]
+macro_rules! principals {
+    ($ctx:ident, $param:literal) => {
+        match $crate::principal::route_principal(&$ctx, $param) {
+            Ok(principal) => principal,
+            Err(e) => return e.into_response(400),
+        }
+    };
+    ($ctx:ident, $($param:literal),+ $(,)?) => {
+        ($($crate::principals!($ctx, $param)),+)
+    };
+}
+
+#[cfg(feature = "axum")]
+pub use extract::{PrincipalRejection, Principals};
+
+#[cfg(feature = "axum")]
+mod extract {
+    use axum::{
+        Json, async_trait,
+        extract::{FromRequestParts, Path},
+        http::{StatusCode, request::Parts},
+        response::{IntoResponse, Response},
+    };
+    use serde::de::DeserializeOwned;
+
+    use crate::api_error::ApiError;
+
+    /// axum extractor for principal path params
+    ///
+    /// `T` is a `Principal`, a tuple of them or a struct keyed by param name
+    pub struct Principals<T>(pub T);
+
+    pub struct PrincipalRejection(pub ApiError);
+
+    impl IntoResponse for PrincipalRejection {
+        fn into_response(self) -> Response {
+            (StatusCode::BAD_REQUEST, Json(self.0)).into_response()
+        }
+    }
+
+    #[async_trait]
+    impl<S, T> FromRequestParts<S> for Principals<T>
+    where
+        S: Send + Sync,
+        T: DeserializeOwned + Send,
+    {
+        type Rejection = PrincipalRejection;
+
+        async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+            Path::<T>::from_request_parts(parts, state)
+                .await
+                .map(|Path(principals)| Self(principals))
+                .map_err(|e| PrincipalRejection(ApiError::new("InvalidPrincipal", e.body_text())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_missing_and_malformed_principals() {
+        let anonymous = Principal::anonymous().to_text();
+        assert_eq!(
+            parse_principal("user", Some(&anonymous)),
+            Ok(Principal::anonymous())
+        );
+
+        let err = parse_principal("user", Some("not-a-principal")).unwrap_err();
+        assert_eq!(err.code, "InvalidPrincipal");
+        assert_eq!(err.details, Some(json!({ "param": "user" })));
+        assert_eq!(parse_principal("user", None), Err(err));
+    }
+}
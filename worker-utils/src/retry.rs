@@ -0,0 +1,130 @@
+use std::{future::Future, time::Duration};
+
+/// exponential backoff between attempts, delays in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// total attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// randomizes each delay between half and all of it
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl RetryPolicy {
+    pub const fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            base_delay_ms: 100,
+            max_delay_ms: 2_000,
+            jitter: true,
+        }
+    }
+
+    pub const fn with_delays(mut self, base_delay_ms: u64, max_delay_ms: u64) -> Self {
+        self.base_delay_ms = base_delay_ms;
+        self.max_delay_ms = max_delay_ms;
+        self
+    }
+
+    pub const fn without_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    /// delay after the `attempt`th failure (starting at 1)
+    /// `unit` is a random number in [0, 1), only used with jitter
+    pub fn delay_ms(&self, attempt: u32, unit: f64) -> u64 {
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let delay = exp.min(self.max_delay_ms);
+        if !self.jitter {
+            return delay;
+        }
+
+        delay / 2 + (delay as f64 / 2.0 * unit) as u64
+    }
+}
+
+pub(crate) fn random_unit() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        worker::js_sys::Math::random()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        nanos as f64 / 1_000_000_000.0
+    }
+}
+
+/// retries `fut_factory` on every error, see `with_backoff_if`
+pub async fn with_backoff<T, E, F, Fut>(policy: RetryPolicy, fut_factory: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    with_backoff_if(policy, |_| true, fut_factory).await
+}
+
+/// runs `fut_factory` until it succeeds, `should_retry` rejects the error
+/// or `policy.max_attempts` is reached, returning the last error
+pub async fn with_backoff_if<T, E, F, Fut, P>(
+    policy: RetryPolicy,
+    mut should_retry: P,
+    mut fut_factory: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    P: FnMut(&E) -> bool,
+{
+    let mut attempt = 1;
+    loop {
+        let err = match fut_factory().await {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        if attempt >= policy.max_attempts || !should_retry(&err) {
+            return Err(err);
+        }
+
+        let delay = policy.delay_ms(attempt, random_unit());
+        worker::Delay::from(Duration::from_millis(delay)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_grows_and_caps() {
+        let policy = RetryPolicy::new(10)
+            .with_delays(100, 1_000)
+            .without_jitter();
+        assert_eq!(policy.delay_ms(1, 0.0), 100);
+        assert_eq!(policy.delay_ms(2, 0.0), 200);
+        assert_eq!(policy.delay_ms(4, 0.0), 800);
+        assert_eq!(policy.delay_ms(5, 0.0), 1_000);
+        assert_eq!(policy.delay_ms(40, 0.0), 1_000);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_half_to_full_delay() {
+        let policy = RetryPolicy::new(3).with_delays(100, 1_000);
+        assert_eq!(policy.delay_ms(2, 0.0), 100);
+        assert!(policy.delay_ms(2, 0.999) < 200);
+    }
+}
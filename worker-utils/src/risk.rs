@@ -0,0 +1,189 @@
+//! fraud signals, scored per principal by the `yral-risk` worker
+//!
+//! producers send a `RiskSignal` to `RISK_SIGNALS_QUEUE`, payouts consult the score first
+//!
+//! ```ignore
+//! // in a payout handler, responds with 403 while the principal's payouts are held
+//! require_low_risk!(ctx.env, user_principal);
+//! ```
+
+use std::{pin::pin, time::Duration};
+
+use candid::Principal;
+use futures::future::{Either, select};
+use serde::{Deserialize, Serialize};
+use worker::{Delay, Env, Method, Response, Result, console_warn};
+
+use crate::{RequestInitBuilder, api_error::ApiError};
+
+pub const RISK_SIGNALS_QUEUE: &str = "RISK_SIGNALS";
+
+/// service binding of `yral-risk`
+pub const RISK_SERVICE: &str = "YRAL_RISK";
+
+/// service token sent to `yral-risk`, minted for its `yral-risk` audience
+pub const RISK_TOKEN_SECRET: &str = "RISK_SERVICE_TOKEN";
+
+/// optional, set by clients to a stable id of the install
+pub const DEVICE_ID_HEADER: &str = "X-Yral-Device-Id";
+
+/// payouts go ahead without an assessment past this
+const ASSESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "signal", rename_all = "snake_case")]
+pub enum RiskSignal {
+    Vote {
+        user_principal: String,
+        /// unix millis
+        at: u64,
+    },
+    /// `user_principal` referred `referee`
+    Referral {
+        user_principal: String,
+        referee: String,
+        at: u64,
+    },
+    Withdrawal {
+        user_principal: String,
+        /// `sats` or `dolr`, amounts of different economies are never compared
+        economy: String,
+        /// in the economy's smallest unit, as a decimal string
+        amount: String,
+        at: u64,
+    },
+    Device {
+        user_principal: String,
+        device_id: String,
+        at: u64,
+    },
+}
+
+impl RiskSignal {
+    /// the serde tag, e.g. `withdrawal`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Vote { .. } => "vote",
+            Self::Referral { .. } => "referral",
+            Self::Withdrawal { .. } => "withdrawal",
+            Self::Device { .. } => "device",
+        }
+    }
+
+    pub fn user_principal(&self) -> &str {
+        match self {
+            Self::Vote { user_principal, .. }
+            | Self::Referral { user_principal, .. }
+            | Self::Withdrawal { user_principal, .. }
+            | Self::Device { user_principal, .. } => user_principal,
+        }
+    }
+
+    pub fn at(&self) -> u64 {
+        match self {
+            Self::Vote { at, .. }
+            | Self::Referral { at, .. }
+            | Self::Withdrawal { at, .. }
+            | Self::Device { at, .. } => *at,
+        }
+    }
+
+    /// sends the signal to `RISK_SIGNALS_QUEUE`
+    #[cfg(feature = "queue")]
+    pub async fn send(&self, env: &Env) -> Result<()> {
+        env.queue(RISK_SIGNALS_QUEUE)?.send(self).await
+    }
+}
+
+/// response of `yral-risk`'s `GET /risk/:principal`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RiskAssessment {
+    pub principal: String,
+    /// 0 to 100
+    pub score: u32,
+    /// what raised the score, e.g. `vote_velocity`
+    pub reasons: Vec<String>,
+    /// payouts are refused while this is set
+    pub hold_payouts: bool,
+}
+
+impl RiskAssessment {
+    /// the score and reasons stay internal, they'd show what to avoid to get past the checks
+    pub fn held_response(&self) -> Result<Response> {
+        ApiError::new("PayoutHeld", "payouts are held for review").into_response(403)
+    }
+}
+
+/// `yral-risk`'s assessment of `principal`, `None` if it couldn't be reached in time
+/// or `RISK_TOKEN_SECRET` isn't bound
+///
+/// payouts fail open, the scoring service going down must not stop every withdrawal
+pub async fn assess(env: &Env, principal: Principal) -> Option<RiskAssessment> {
+    match try_assess(env, principal).await {
+        Ok(assessment) => Some(assessment),
+        Err(e) => {
+            console_warn!("risk assessment of {principal} unavailable: {e}");
+            None
+        }
+    }
+}
+
+async fn try_assess(env: &Env, principal: Principal) -> Result<RiskAssessment> {
+    let token = env.secret(RISK_TOKEN_SECRET)?.to_string();
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Get)
+        .header("Authorization", &format!("Bearer {token}"))?;
+    let req = init.request(&format!("https://{RISK_SERVICE}/risk/{principal}"))?;
+
+    let fetch = pin!(env.service(RISK_SERVICE)?.fetch_request(req));
+    let delay = pin!(Delay::from(ASSESS_TIMEOUT));
+    let mut res = match select(fetch, delay).await {
+        Either::Left((res, _)) => res?,
+        Either::Right(_) => {
+            return Err(worker::Error::RustError(format!(
+                "timed out after {}ms",
+                ASSESS_TIMEOUT.as_millis()
+            )));
+        }
+    };
+    if res.status_code() != 200 {
+        return Err(worker::Error::RustError(format!(
+            "status {}",
+            res.status_code()
+        )));
+    }
+
+    res.json().await
+}
+
+/// returns `RiskAssessment::held_response` from the handler while `principal`'s payouts are held
+#[macro_export]
+macro_rules! require_low_risk {
+    ($env:expr, $principal:expr) => {
+        if let Some(assessment) = $crate::risk::assess(&$env, $principal).await {
+            if assessment.hold_payouts {
+                return assessment.held_response();
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_are_tagged() {
+        let signal = RiskSignal::Withdrawal {
+            user_principal: "2vxsx-fae".into(),
+            economy: "sats".into(),
+            amount: "1000".into(),
+            at: 1_700_000_000_000,
+        };
+        let value = serde_json::to_value(&signal).unwrap();
+
+        assert_eq!(value["signal"], "withdrawal");
+        assert_eq!(serde_json::from_value::<RiskSignal>(value).unwrap(), signal);
+        assert_eq!(signal.kind(), "withdrawal");
+    }
+}
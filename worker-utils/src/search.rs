@@ -0,0 +1,118 @@
+//! post search index updates, applied by the `yral-search` worker
+//!
+//! producers send a `SearchIndexEvent` to `SEARCH_INDEX_QUEUE` whenever a post's searchable
+//! fields change, events of a post carry `at` so late deliveries don't undo newer ones
+
+use serde::{Deserialize, Serialize};
+
+use crate::trace::TraceId;
+
+pub const SEARCH_INDEX_QUEUE: &str = "SEARCH_INDEX";
+
+/// the searchable fields of a post
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PostDocument {
+    /// the canister storing the post, post ids are only unique within it
+    pub canister_id: String,
+    pub post_id: String,
+    pub creator_principal: String,
+    pub video_uid: String,
+    pub description: String,
+    /// as the creator typed them, normalized by the indexer
+    pub hashtags: Vec<String>,
+    pub is_nsfw: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SearchIndexEvent {
+    PostPublished {
+        post: PostDocument,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    /// the creator changed the description or hashtags of a published post
+    PostEdited {
+        canister_id: String,
+        post_id: String,
+        description: String,
+        hashtags: Vec<String>,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+    /// banned or deleted, it's no longer returned by searches
+    PostRemoved {
+        canister_id: String,
+        post_id: String,
+        /// unix millis
+        at: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace_id: Option<TraceId>,
+    },
+}
+
+impl SearchIndexEvent {
+    /// the serde tag, e.g. `post_published`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::PostPublished { .. } => "post_published",
+            Self::PostEdited { .. } => "post_edited",
+            Self::PostRemoved { .. } => "post_removed",
+        }
+    }
+
+    /// the post's canister and id
+    pub fn post_key(&self) -> (&str, &str) {
+        match self {
+            Self::PostPublished { post, .. } => (&post.canister_id, &post.post_id),
+            Self::PostEdited {
+                canister_id,
+                post_id,
+                ..
+            }
+            | Self::PostRemoved {
+                canister_id,
+                post_id,
+                ..
+            } => (canister_id, post_id),
+        }
+    }
+
+    /// sends the event to `SEARCH_INDEX_QUEUE`
+    #[cfg(feature = "queue")]
+    pub async fn send(&self, env: &worker::Env) -> worker::Result<()> {
+        env.queue(SEARCH_INDEX_QUEUE)?.send(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn published_posts_nest_the_document() {
+        let event: SearchIndexEvent = serde_json::from_value(json!({
+            "event": "post_published",
+            "post": {
+                "canister_id": "aaaaa-aa",
+                "post_id": "1",
+                "creator_principal": "2vxsx-fae",
+                "video_uid": "abc",
+                "description": "sunset",
+                "hashtags": ["#Beach"],
+                "is_nsfw": false,
+            },
+            "at": 1_700_000_000_000u64,
+        }))
+        .unwrap();
+
+        assert_eq!(event.kind(), "post_published");
+        assert_eq!(event.post_key(), ("aaaaa-aa", "1"));
+    }
+}
@@ -0,0 +1,105 @@
+//! checks required secrets before handling an event, instead of panicking on first use
+//!
+//! ```ignore
+//! const SECRETS: &[&str] = &["BACKEND_ADMIN_KEY", "WEBHOOK_SIGNING_SECRET"];
+//!
+//! if let Err(e) = SecretSet::validate(&env, SECRETS) {
+//!     return e.into_response();
+//! }
+//! ```
+
+use std::{fmt, result::Result as StdResult};
+
+use serde_json::json;
+use worker::{Env, Response, Result};
+
+use crate::{
+    api_error::ApiError,
+    environment::{RunEnv, env_kind},
+};
+
+/// characters of a missing name kept in responses, the rest is masked
+const VISIBLE_PREFIX_LEN: usize = 4;
+
+pub struct SecretSet;
+
+impl SecretSet {
+    /// fails with every name in `names` that isn't bound as a secret
+    /// mock builds don't need secrets and always pass
+    pub fn validate(env: &Env, names: &[&'static str]) -> StdResult<(), MissingSecrets> {
+        if env_kind() == RunEnv::Mock {
+            return Ok(());
+        }
+
+        let missing: Vec<_> = names
+            .iter()
+            .copied()
+            .filter(|name| env.secret(name).is_err())
+            .collect();
+        if !missing.is_empty() {
+            return Err(MissingSecrets(missing));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MissingSecrets(pub Vec<&'static str>);
+
+impl MissingSecrets {
+    /// names with all but the first few characters masked, for responses
+    pub fn redacted(&self) -> Vec<String> {
+        self.0.iter().map(|name| redact(name)).collect()
+    }
+
+    /// the error envelope, listing only the redacted names
+    pub fn to_api_error(&self) -> ApiError {
+        ApiError::new(
+            "MissingConfiguration",
+            "worker is missing required configuration",
+        )
+        .with_details(json!({ "missing": self.redacted() }))
+    }
+
+    /// logs the full names and responds with 503 and the redacted ones
+    pub fn into_response(self) -> Result<Response> {
+        worker::console_error!("{self}");
+
+        self.to_api_error().into_response(503)
+    }
+}
+
+fn redact(name: &str) -> String {
+    let visible: String = name.chars().take(VISIBLE_PREFIX_LEN).collect();
+    format!("{visible}***")
+}
+
+impl fmt::Display for MissingSecrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "missing secrets: {}", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for MissingSecrets {}
+
+impl From<MissingSecrets> for worker::Error {
+    fn from(e: MissingSecrets) -> Self {
+        worker::Error::RustError(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_all_but_the_prefix() {
+        let missing = MissingSecrets(vec!["BACKEND_ADMIN_KEY", "KEY"]);
+        assert_eq!(missing.redacted(), vec!["BACK***", "KEY***"]);
+        assert_eq!(
+            missing.to_string(),
+            "missing secrets: BACKEND_ADMIN_KEY, KEY"
+        );
+    }
+}
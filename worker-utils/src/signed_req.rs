@@ -0,0 +1,66 @@
+use std::result::Result as StdResult;
+
+use candid::Principal;
+use serde::de::DeserializeOwned;
+use worker::{Request, Response, Result};
+use yral_identity::{Signature, msg_builder::Message};
+
+use crate::api_error::ApiError;
+
+/// A request body signed by its sender
+pub trait SignedMessage {
+    fn sender(&self) -> Principal;
+
+    fn signature(&self) -> &Signature;
+
+    /// the canonical message covered by the signature
+    fn message(&self) -> Message;
+}
+
+/// `SignedMessage` for request types defined in other crates
+pub struct Signed<'a> {
+    pub sender: Principal,
+    pub signature: &'a Signature,
+    pub message: Message,
+}
+
+impl SignedMessage for Signed<'_> {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        self.signature
+    }
+
+    fn message(&self) -> Message {
+        self.message.clone()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidSignature;
+
+impl InvalidSignature {
+    pub const STATUS: u16 = 401;
+
+    pub fn into_response(self) -> Result<Response> {
+        ApiError::new("InvalidSignature", "invalid signature").into_response(Self::STATUS)
+    }
+}
+
+pub fn verify(req: &impl SignedMessage) -> StdResult<(), InvalidSignature> {
+    req.signature()
+        .clone()
+        .verify_identity(req.sender(), req.message())
+        .map_err(|_| InvalidSignature)
+}
+
+/// parses the JSON body and verifies its signature
+pub async fn parse_verified<T: SignedMessage + DeserializeOwned>(
+    req: &mut Request,
+) -> Result<StdResult<T, InvalidSignature>> {
+    let body: T = serde_json::from_str(&req.text().await?)?;
+
+    Ok(verify(&body).map(|_| body))
+}
@@ -0,0 +1,437 @@
+use std::{marker::PhantomData, result::Result as StdResult};
+
+use num_bigint::{BigInt, BigUint};
+use serde::Serialize;
+use worker::{Result, State, console_warn};
+
+use crate::storage::{
+    SafeStorage, StorageCell, batch::WriteBatch, cumulative_limit::CumulativeLimit,
+};
+
+/// Storage layout of a balance tracked by `BalanceEngine`
+pub trait Currency {
+    const BALANCE_KEY: &'static str;
+    const CREDITED_KEY: &'static str;
+    const DEDUCTED_KEY: &'static str;
+
+    fn initial_balance() -> BigUint {
+        BigUint::ZERO
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DailyLimits {
+    pub max_credited_per_day: u64,
+    pub max_deducted_per_day: u64,
+}
+
+#[derive(Debug)]
+pub enum BalanceError {
+    CreditLimitReached,
+    DeductLimitReached,
+    /// `expected_balance` did not match, carries the current balance
+    Conflict {
+        new_balance: BigUint,
+    },
+    InsufficientFunds,
+    Storage(worker::Error),
+}
+
+impl From<worker::Error> for BalanceError {
+    fn from(value: worker::Error) -> Self {
+        Self::Storage(value)
+    }
+}
+
+/// A user balance guarded by daily credit/debit limits
+pub struct BalanceEngine<C: Currency> {
+    balance: StorageCell<BigUint>,
+    credited: CumulativeLimit,
+    deducted: CumulativeLimit,
+    _currency: PhantomData<C>,
+}
+
+impl<C: Currency> BalanceEngine<C> {
+    pub fn new(limits: DailyLimits) -> Self {
+        Self {
+            balance: StorageCell::new(C::BALANCE_KEY, C::initial_balance),
+            credited: CumulativeLimit::daily(C::CREDITED_KEY, limits.max_credited_per_day),
+            deducted: CumulativeLimit::daily(C::DEDUCTED_KEY, limits.max_deducted_per_day),
+            _currency: PhantomData,
+        }
+    }
+
+    /// the configured limits, see `apply_with_limits` for per call overrides
+    pub fn limits(&self) -> DailyLimits {
+        DailyLimits {
+            max_credited_per_day: self.credited.max(),
+            max_deducted_per_day: self.deducted.max(),
+        }
+    }
+
+    /// drops cached state, see `StorageCell::invalidate`
+    pub fn invalidate(&mut self) {
+        self.balance.invalidate();
+        self.credited.invalidate();
+        self.deducted.invalidate();
+    }
+
+    pub async fn balance(&mut self, storage: &SafeStorage) -> Result<BigUint> {
+        Ok(self.balance.read(storage).await?.clone())
+    }
+
+    /// overwrites the balance, bypassing the daily limits
+    pub async fn set(&mut self, storage: &mut SafeStorage, balance: BigUint) -> Result<()> {
+        self.balance.set(storage, balance).await
+    }
+
+    /// mutates the balance directly, bypassing the daily limits
+    /// meant for internal flows like game payouts
+    pub async fn update(
+        &mut self,
+        storage: &mut SafeStorage,
+        updater: impl FnOnce(&mut BigUint),
+    ) -> Result<()> {
+        self.balance.update(storage, updater).await
+    }
+
+    /// same as `update`, staging the write in `batch`
+    pub async fn update_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        updater: impl FnOnce(&mut BigUint),
+    ) -> Result<()> {
+        self.balance.update_in(storage, batch, updater).await
+    }
+
+    pub async fn apply(
+        &mut self,
+        storage: &mut SafeStorage,
+        expected_balance: Option<&BigUint>,
+        delta: &BigInt,
+        reserved: &BigUint,
+    ) -> StdResult<BigUint, BalanceError> {
+        let limits = self.limits();
+        self.apply_with_limits(storage, expected_balance, delta, reserved, limits)
+            .await
+    }
+
+    /// applies `delta` after consuming the matching daily limit
+    /// if `expected_balance` is set, the update fails on mismatch
+    /// debits can not dip into the `reserved` part of the balance
+    pub async fn apply_with_limits(
+        &mut self,
+        storage: &mut SafeStorage,
+        expected_balance: Option<&BigUint>,
+        delta: &BigInt,
+        reserved: &BigUint,
+        limits: DailyLimits,
+    ) -> StdResult<BigUint, BalanceError> {
+        let mut batch = storage.batch();
+        let new_bal = self
+            .apply_with_limits_in(
+                storage,
+                &mut batch,
+                expected_balance,
+                delta,
+                reserved,
+                limits,
+            )
+            .await?;
+        self.commit(storage, batch).await?;
+
+        Ok(new_bal)
+    }
+
+    /// same as `apply_with_limits`, staging the limit and balance writes in `batch`
+    /// nothing is staged on failure, `invalidate` if the batch fails to commit
+    pub async fn apply_with_limits_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        expected_balance: Option<&BigUint>,
+        delta: &BigInt,
+        reserved: &BigUint,
+        limits: DailyLimits,
+    ) -> StdResult<BigUint, BalanceError> {
+        let amount = delta.magnitude().clone();
+        let is_credit = *delta >= BigInt::ZERO;
+        let mut balance = self.balance.read(storage).await?.clone();
+        if let Some(expected_balance) = expected_balance {
+            if *expected_balance != balance {
+                return Err(BalanceError::Conflict {
+                    new_balance: balance,
+                });
+            }
+        }
+        if is_credit {
+            balance += &amount;
+            self.credited
+                .try_consume_with_max_in(storage, batch, amount, limits.max_credited_per_day)
+                .await
+                .map_err(|_| BalanceError::CreditLimitReached)?;
+        } else {
+            if amount.clone() + reserved > balance {
+                return Err(BalanceError::InsufficientFunds);
+            }
+            balance -= &amount;
+            self.deducted
+                .try_consume_with_max_in(storage, batch, amount, limits.max_deducted_per_day)
+                .await
+                .map_err(|_| BalanceError::DeductLimitReached)?;
+        }
+        self.balance.set_in(batch, balance.clone())?;
+
+        Ok(balance)
+    }
+
+    pub async fn revert(
+        &mut self,
+        storage: &mut SafeStorage,
+        delta: &BigInt,
+    ) -> StdResult<BigUint, BalanceError> {
+        let limits = self.limits();
+        self.revert_with_limits(storage, delta, limits).await
+    }
+
+    /// compensates a `delta` previously applied with `apply`
+    /// the consumed daily limit is given back
+    pub async fn revert_with_limits(
+        &mut self,
+        storage: &mut SafeStorage,
+        delta: &BigInt,
+        limits: DailyLimits,
+    ) -> StdResult<BigUint, BalanceError> {
+        let mut batch = storage.batch();
+        let new_bal = self
+            .revert_with_limits_in(storage, &mut batch, delta, limits)
+            .await?;
+        self.commit(storage, batch).await?;
+
+        Ok(new_bal)
+    }
+
+    /// same as `revert_with_limits`, staging the limit and balance writes in `batch`
+    /// nothing is staged on failure, `invalidate` if the batch fails to commit
+    pub async fn revert_with_limits_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        delta: &BigInt,
+        limits: DailyLimits,
+    ) -> StdResult<BigUint, BalanceError> {
+        let amount = delta.magnitude().clone();
+        let was_credit = *delta >= BigInt::ZERO;
+        let mut balance = self.balance.read(storage).await?.clone();
+        if was_credit {
+            if amount > balance {
+                return Err(BalanceError::InsufficientFunds);
+            }
+            balance -= &amount;
+            self.credited
+                .rollback_with_max_in(storage, batch, amount, limits.max_credited_per_day)
+                .await?;
+        } else {
+            balance += &amount;
+            self.deducted
+                .rollback_with_max_in(storage, batch, amount, limits.max_deducted_per_day)
+                .await?;
+        }
+        self.balance.set_in(batch, balance.clone())?;
+
+        Ok(balance)
+    }
+
+    async fn commit(
+        &mut self,
+        storage: &mut SafeStorage,
+        batch: WriteBatch,
+    ) -> StdResult<(), BalanceError> {
+        batch.commit(storage).await.map_err(|e| {
+            self.invalidate();
+            BalanceError::Storage(e)
+        })
+    }
+
+    /// (credited, deducted) in the current daily windows
+    pub async fn consumed_today(
+        &mut self,
+        storage: &SafeStorage,
+        limits: DailyLimits,
+    ) -> Result<(BigUint, BigUint)> {
+        let credited = self
+            .credited
+            .consumed_with_max(storage, limits.max_credited_per_day)
+            .await?;
+        let deducted = self
+            .deducted
+            .consumed_with_max(storage, limits.max_deducted_per_day)
+            .await?;
+
+        Ok((credited, deducted))
+    }
+
+    /// credit and debit allowance left today, with the reset time of each window
+    pub async fn remaining_today(
+        &mut self,
+        storage: &SafeStorage,
+        limits: DailyLimits,
+    ) -> Result<((BigUint, u64), (BigUint, u64))> {
+        let credit = self
+            .credited
+            .remaining_with_max(storage, limits.max_credited_per_day)
+            .await?;
+        let debit = self
+            .deducted
+            .remaining_with_max(storage, limits.max_deducted_per_day)
+            .await?;
+
+        Ok((credit, debit))
+    }
+
+    /// carry the current daily windows over to new limits
+    pub async fn rebase_limits(
+        &mut self,
+        storage: &mut SafeStorage,
+        old: DailyLimits,
+        new: DailyLimits,
+    ) -> Result<()> {
+        self.credited
+            .rebase(storage, old.max_credited_per_day, new.max_credited_per_day)
+            .await?;
+        self.deducted
+            .rebase(storage, old.max_deducted_per_day, new.max_deducted_per_day)
+            .await
+    }
+}
+
+/// sends `msg` to every websocket attached to the durable object
+pub fn broadcast_to_websockets<T: Serialize>(state: &State, msg: &T) {
+    for ws in state.get_websockets() {
+        if let Err(e) = ws.send(msg) {
+            console_warn!("failed to broadcast balance update: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{advance_clock, block_on, memory_storage};
+
+    struct TestCoin;
+
+    impl Currency for TestCoin {
+        const BALANCE_KEY: &'static str = "balance";
+        const CREDITED_KEY: &'static str = "credited";
+        const DEDUCTED_KEY: &'static str = "deducted";
+    }
+
+    const LIMITS: DailyLimits = DailyLimits {
+        max_credited_per_day: 100,
+        max_deducted_per_day: 50,
+    };
+
+    #[test]
+    fn credit_limit_is_enforced_and_resets_daily() {
+        let mut storage = memory_storage();
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+
+        let bal =
+            block_on(engine.apply(&mut storage, None, &BigInt::from(80), &BigUint::ZERO)).unwrap();
+        assert_eq!(bal, BigUint::from(80u32));
+        let res = block_on(engine.apply(&mut storage, None, &BigInt::from(30), &BigUint::ZERO));
+        assert!(matches!(res, Err(BalanceError::CreditLimitReached)));
+
+        advance_clock(24 * 3600 * 1000);
+        let bal =
+            block_on(engine.apply(&mut storage, None, &BigInt::from(30), &BigUint::ZERO)).unwrap();
+        assert_eq!(bal, BigUint::from(110u32));
+    }
+
+    #[test]
+    fn failed_debit_gives_back_the_limit() {
+        let mut storage = memory_storage();
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+        block_on(engine.apply(&mut storage, None, &BigInt::from(20), &BigUint::ZERO)).unwrap();
+
+        let res = block_on(engine.apply(
+            &mut storage,
+            None,
+            &BigInt::from(-15),
+            &BigUint::from(10u32),
+        ));
+        assert!(matches!(res, Err(BalanceError::InsufficientFunds)));
+
+        let (_, deducted) = block_on(engine.consumed_today(&storage, LIMITS)).unwrap();
+        assert_eq!(deducted, BigUint::ZERO);
+    }
+
+    #[test]
+    fn expected_balance_mismatch_conflicts() {
+        let mut storage = memory_storage();
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+        block_on(engine.apply(&mut storage, None, &BigInt::from(20), &BigUint::ZERO)).unwrap();
+
+        let res = block_on(engine.apply(
+            &mut storage,
+            Some(&BigUint::from(5u32)),
+            &BigInt::from(-5),
+            &BigUint::ZERO,
+        ));
+        let Err(BalanceError::Conflict { new_balance }) = res else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(new_balance, BigUint::from(20u32));
+    }
+
+    #[test]
+    fn staged_updates_land_only_on_commit() {
+        let mut storage = memory_storage();
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+        block_on(engine.apply(&mut storage, None, &BigInt::from(20), &BigUint::ZERO)).unwrap();
+
+        let mut batch = storage.batch();
+        let staged = block_on(engine.apply_with_limits_in(
+            &storage,
+            &mut batch,
+            None,
+            &BigInt::from(-5),
+            &BigUint::ZERO,
+            LIMITS,
+        ))
+        .unwrap();
+        assert_eq!(staged, BigUint::from(15u32));
+        drop(batch);
+        engine.invalidate();
+        assert_eq!(
+            block_on(engine.balance(&storage)).unwrap(),
+            BigUint::from(20u32)
+        );
+        let (_, deducted) = block_on(engine.consumed_today(&storage, LIMITS)).unwrap();
+        assert_eq!(deducted, BigUint::ZERO);
+
+        let mut batch = storage.batch();
+        block_on(engine.revert_with_limits_in(&storage, &mut batch, &BigInt::from(20), LIMITS))
+            .unwrap();
+        block_on(batch.commit(&mut storage)).unwrap();
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+        assert_eq!(block_on(engine.balance(&storage)).unwrap(), BigUint::ZERO);
+        let (credited, _) = block_on(engine.consumed_today(&storage, LIMITS)).unwrap();
+        assert_eq!(credited, BigUint::ZERO);
+    }
+
+    #[test]
+    fn state_survives_a_fresh_engine() {
+        let mut storage = memory_storage();
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+        block_on(engine.apply(&mut storage, None, &BigInt::from(20), &BigUint::ZERO)).unwrap();
+
+        let mut engine = BalanceEngine::<TestCoin>::new(LIMITS);
+        assert_eq!(
+            block_on(engine.balance(&storage)).unwrap(),
+            BigUint::from(20u32)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::BTreeMap, fmt::Debug};
+
+use futures::future::try_join_all;
+use serde::{Serialize, de::DeserializeOwned};
+use worker::{
+    Result,
+    js_sys::{Object, Reflect},
+    wasm_bindgen::JsValue,
+};
+
+use super::{Backend, MAX_KEYS_PER_CALL, SafeStorage, StorageCell, bytes_to_js, ser_bytes};
+
+/// puts and deletes applied together by `commit`
+///
+/// all writes are issued without awaiting in between,
+/// so the durable object's write coalescing commits them atomically
+#[derive(Default)]
+pub struct WriteBatch {
+    // None marks a delete, the last write to a key wins
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl WriteBatch {
+    pub fn put(&mut self, key: impl AsRef<str>, v: &impl Serialize) -> Result<&mut Self> {
+        self.writes
+            .insert(key.as_ref().to_string(), Some(ser_bytes(v)?));
+        Ok(self)
+    }
+
+    /// puts an already encoded value, e.g. one read with `SafeStorage::list_bytes`
+    pub(crate) fn put_bytes(&mut self, key: impl AsRef<str>, v: Vec<u8>) -> &mut Self {
+        self.writes.insert(key.as_ref().to_string(), Some(v));
+        self
+    }
+
+    pub fn delete(&mut self, key: impl AsRef<str>) -> &mut Self {
+        self.writes.insert(key.as_ref().to_string(), None);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub async fn commit(self, storage: &mut SafeStorage) -> Result<()> {
+        let storage = match &storage.0 {
+            Backend::Durable(storage) => storage,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => {
+                for (key, v) in self.writes {
+                    match v {
+                        Some(v) => storage.put(&key, v),
+                        None => _ = storage.delete(&key),
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        let mut puts = Vec::new();
+        let mut deletes = Vec::new();
+        for (key, v) in self.writes {
+            match v {
+                Some(v) => puts.push((key, bytes_to_js(v)?)),
+                None => deletes.push(key),
+            }
+        }
+
+        let mut put_objs = Vec::new();
+        for chunk in puts.chunks(MAX_KEYS_PER_CALL) {
+            let obj = Object::new();
+            for (key, v) in chunk {
+                Reflect::set(&obj, &JsValue::from_str(key), v)?;
+            }
+            put_objs.push(obj);
+        }
+
+        let put_futs = put_objs
+            .into_iter()
+            .map(|obj| storage.put_multiple_raw(obj));
+        let delete_futs = deletes
+            .chunks(MAX_KEYS_PER_CALL)
+            .map(|chunk| storage.delete_multiple(chunk.to_vec()));
+        let (puts, deletes) = futures::join!(try_join_all(put_futs), try_join_all(delete_futs));
+        puts?;
+        deletes?;
+
+        Ok(())
+    }
+}
+
+impl SafeStorage {
+    /// an empty `WriteBatch`, see `WriteBatch::commit`
+    pub fn batch(&self) -> WriteBatch {
+        WriteBatch::default()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone + Debug> StorageCell<T> {
+    /// stages `v` in `batch`
+    /// the cached value changes right away, `invalidate` if the batch fails to commit
+    pub fn set_in(&mut self, batch: &mut WriteBatch, v: T) -> Result<()> {
+        batch.put(&self.key, &v)?;
+        self.hot_cache = Some(v);
+        Ok(())
+    }
+
+    /// same as `update`, staging the write in `batch`
+    /// the cached value changes right away, `invalidate` if the batch fails to commit
+    pub async fn update_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        updater: impl FnOnce(&mut T),
+    ) -> Result<()> {
+        let mut v = self.read(storage).await?.clone();
+        updater(&mut v);
+        self.set_in(batch, v)
+    }
+}
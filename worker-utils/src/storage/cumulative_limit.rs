@@ -0,0 +1,291 @@
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use worker::Result;
+
+use crate::{
+    storage::{SafeStorage, StorageCell, batch::WriteBatch},
+    time::now_millis,
+};
+
+/// length of a limit window, a window starts on the first use after the previous one expired
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitWindow {
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl LimitWindow {
+    pub const fn as_millis(self) -> u64 {
+        const HOUR_MS: u64 = 3600 * 1000;
+        match self {
+            Self::Hourly => HOUR_MS,
+            Self::Daily => 24 * HOUR_MS,
+            Self::Weekly => 7 * 24 * HOUR_MS,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+}
+
+/// the default value is an already expired window,
+/// it is reset to the maximum on first use
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct CumulativeInner {
+    amount: BigUint,
+    last_reset_epoch: u64,
+}
+
+impl CumulativeInner {
+    fn with_max(max: u64) -> Self {
+        Self {
+            amount: BigUint::from(max),
+            last_reset_epoch: now_millis(),
+        }
+    }
+
+    fn expired(&self, window: LimitWindow) -> bool {
+        now_millis().saturating_sub(window.as_millis()) >= self.last_reset_epoch
+    }
+
+    fn consume(&mut self, window: LimitWindow, amount: BigUint, max: u64) -> Result<()> {
+        if self.expired(window) {
+            *self = Self::with_max(max);
+        }
+        if self.amount < amount {
+            return Err(worker::Error::RustError(format!(
+                "{} limit reached",
+                window.as_str()
+            )));
+        }
+        self.amount -= amount;
+
+        Ok(())
+    }
+
+    fn give_back(&mut self, amount: BigUint, max: u64) {
+        self.amount = (self.amount.clone() + amount).min(max.into());
+    }
+}
+
+/// caps the total amount consumed per `LimitWindow`
+pub struct CumulativeLimit {
+    cell: StorageCell<CumulativeInner>,
+    window: LimitWindow,
+    max: u64,
+}
+
+impl CumulativeLimit {
+    pub fn new(key: impl AsRef<str>, window: LimitWindow, max: u64) -> Self {
+        Self {
+            cell: StorageCell::new(key, CumulativeInner::default),
+            window,
+            max,
+        }
+    }
+
+    pub fn hourly(key: impl AsRef<str>, max: u64) -> Self {
+        Self::new(key, LimitWindow::Hourly, max)
+    }
+
+    pub fn daily(key: impl AsRef<str>, max: u64) -> Self {
+        Self::new(key, LimitWindow::Daily, max)
+    }
+
+    pub fn weekly(key: impl AsRef<str>, max: u64) -> Self {
+        Self::new(key, LimitWindow::Weekly, max)
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn window(&self) -> LimitWindow {
+        self.window
+    }
+
+    /// takes effect from the next window, use `rebase` to apply it to the current one
+    pub fn set_max(&mut self, max: u64) {
+        self.max = max;
+    }
+
+    pub fn invalidate(&mut self) {
+        self.cell.invalidate();
+    }
+
+    pub async fn try_consume(&mut self, storage: &mut SafeStorage, amount: BigUint) -> Result<()> {
+        self.try_consume_with_max(storage, amount, self.max).await
+    }
+
+    /// same as `try_consume`, with `max` overriding the configured maximum
+    pub async fn try_consume_with_max(
+        &mut self,
+        storage: &mut SafeStorage,
+        amount: BigUint,
+        max: u64,
+    ) -> Result<()> {
+        let window = self.window;
+        let mut err = None::<worker::Error>;
+        self.cell
+            .update(storage, |inner| {
+                err = inner.consume(window, amount, max).err()
+            })
+            .await?;
+
+        if let Some(e) = err {
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// same as `try_consume_with_max`, staging the write in `batch`
+    /// nothing is staged if the limit is reached, `invalidate` if the batch fails to commit
+    pub async fn try_consume_with_max_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        amount: BigUint,
+        max: u64,
+    ) -> Result<()> {
+        let mut inner = self.cell.read(storage).await?.clone();
+        inner.consume(self.window, amount, max)?;
+        self.cell.set_in(batch, inner)
+    }
+
+    pub async fn rollback(&mut self, storage: &mut SafeStorage, amount: BigUint) -> Result<()> {
+        self.rollback_with_max(storage, amount, self.max).await
+    }
+
+    /// same as `rollback`, with `max` overriding the configured maximum
+    pub async fn rollback_with_max(
+        &mut self,
+        storage: &mut SafeStorage,
+        amount: BigUint,
+        max: u64,
+    ) -> Result<()> {
+        self.cell
+            .update(storage, |inner| inner.give_back(amount, max))
+            .await
+    }
+
+    /// same as `rollback_with_max`, staging the write in `batch`
+    pub async fn rollback_with_max_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        amount: BigUint,
+        max: u64,
+    ) -> Result<()> {
+        self.cell
+            .update_in(storage, batch, |inner| inner.give_back(amount, max))
+            .await
+    }
+
+    /// amount consumed in the current window, given the window's maximum
+    pub async fn consumed_with_max(&mut self, storage: &SafeStorage, max: u64) -> Result<BigUint> {
+        let window = self.window;
+        let inner = self.cell.read(storage).await?;
+        if inner.expired(window) {
+            return Ok(BigUint::ZERO);
+        }
+
+        Ok(BigUint::from(max) - inner.amount.clone().min(max.into()))
+    }
+
+    /// allowance left in the current window and when the window resets (unix millis)
+    /// without an active window the full maximum is left, for a window starting now
+    pub async fn remaining(&mut self, storage: &SafeStorage) -> Result<(BigUint, u64)> {
+        self.remaining_with_max(storage, self.max).await
+    }
+
+    /// same as `remaining`, with `max` overriding the configured maximum
+    pub async fn remaining_with_max(
+        &mut self,
+        storage: &SafeStorage,
+        max: u64,
+    ) -> Result<(BigUint, u64)> {
+        let window = self.window;
+        let inner = self.cell.read(storage).await?;
+        if inner.expired(window) {
+            return Ok((BigUint::from(max), now_millis() + window.as_millis()));
+        }
+
+        Ok((
+            inner.amount.clone().min(max.into()),
+            inner.last_reset_epoch + window.as_millis(),
+        ))
+    }
+
+    /// carry the current window over to a new maximum
+    /// whatever was consumed so far stays consumed
+    pub async fn rebase(
+        &mut self,
+        storage: &mut SafeStorage,
+        old_max: u64,
+        new_max: u64,
+    ) -> Result<()> {
+        let window = self.window;
+        self.cell
+            .update(storage, |inner| {
+                // an expired window is reset with the new max on next use
+                if inner.expired(window) {
+                    return;
+                }
+                let consumed = BigUint::from(old_max) - inner.amount.clone().min(old_max.into());
+                inner.amount = BigUint::from(new_max) - consumed.min(new_max.into());
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{advance_clock, block_on, freeze_clock, memory_storage};
+
+    #[test]
+    fn hourly_window_resets_after_an_hour() {
+        let mut storage = memory_storage();
+        let mut limit = CumulativeLimit::hourly("limit", 10);
+
+        block_on(limit.try_consume(&mut storage, BigUint::from(10u32))).unwrap();
+        assert!(block_on(limit.try_consume(&mut storage, BigUint::from(1u32))).is_err());
+
+        advance_clock(LimitWindow::Hourly.as_millis());
+        block_on(limit.try_consume(&mut storage, BigUint::from(10u32))).unwrap();
+    }
+
+    #[test]
+    fn remaining_tracks_consumption() {
+        freeze_clock();
+        let mut storage = memory_storage();
+        let mut limit = CumulativeLimit::daily("limit", 10);
+
+        let (remaining, _) = block_on(limit.remaining(&storage)).unwrap();
+        assert_eq!(remaining, BigUint::from(10u32));
+
+        block_on(limit.try_consume(&mut storage, BigUint::from(4u32))).unwrap();
+        let (remaining, reset_at) = block_on(limit.remaining(&storage)).unwrap();
+        assert_eq!(remaining, BigUint::from(6u32));
+        assert_eq!(reset_at, now_millis() + LimitWindow::Daily.as_millis());
+    }
+
+    #[test]
+    fn weekly_window_outlives_a_day() {
+        let mut storage = memory_storage();
+        let mut limit = CumulativeLimit::weekly("limit", 10);
+
+        block_on(limit.try_consume(&mut storage, BigUint::from(10u32))).unwrap();
+        advance_clock(LimitWindow::Daily.as_millis());
+        assert!(block_on(limit.try_consume(&mut storage, BigUint::from(1u32))).is_err());
+
+        advance_clock(LimitWindow::Weekly.as_millis());
+        block_on(limit.try_consume(&mut storage, BigUint::from(1u32))).unwrap();
+    }
+}
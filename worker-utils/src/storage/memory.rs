@@ -0,0 +1,70 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use serde::Deserialize;
+use worker::{ListOptions, Result};
+
+/// fields of `ListOptions`, which has no getters
+#[derive(Deserialize, Default)]
+struct ListParams {
+    start: Option<String>,
+    end: Option<String>,
+    prefix: Option<String>,
+    reverse: Option<bool>,
+    limit: Option<usize>,
+}
+
+/// In-memory durable object storage for native tests
+///
+/// clones share the same data, like `Storage` handles of a single DO
+#[derive(Clone, Default)]
+pub struct MemoryStorage(Rc<RefCell<BTreeMap<String, Vec<u8>>>>);
+
+impl MemoryStorage {
+    pub fn keys(&self) -> Vec<String> {
+        self.0.borrow().keys().cloned().collect()
+    }
+
+    pub(super) fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.0.borrow().get(key).cloned()
+    }
+
+    pub(super) fn put(&self, key: &str, v: Vec<u8>) {
+        self.0.borrow_mut().insert(key.to_string(), v);
+    }
+
+    pub(super) fn delete(&self, key: &str) -> bool {
+        self.0.borrow_mut().remove(key).is_some()
+    }
+
+    pub(super) fn delete_all(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    pub(super) fn list(&self, list_options: &ListOptions<'_>) -> Result<Vec<(String, Vec<u8>)>> {
+        let params: ListParams = serde_json::from_value(serde_json::to_value(list_options)?)?;
+        let data = self.0.borrow();
+        let matching = data.iter().filter(|(k, _)| {
+            params.start.as_ref().is_none_or(|start| *k >= start)
+                && params.end.as_ref().is_none_or(|end| *k < end)
+                && params
+                    .prefix
+                    .as_ref()
+                    .is_none_or(|prefix| k.starts_with(prefix))
+        });
+        let limit = params.limit.unwrap_or(usize::MAX);
+        let entries = if params.reverse.unwrap_or_default() {
+            matching
+                .rev()
+                .take(limit)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        } else {
+            matching
+                .take(limit)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        };
+
+        Ok(entries)
+    }
+}
@@ -1,16 +1,38 @@
-pub mod daily_cumulative_limit;
+pub mod balance;
+pub mod batch;
+pub mod cumulative_limit;
+#[cfg(any(test, feature = "testing"))]
+pub mod memory;
+pub mod rate_limit;
+pub mod versioned;
 
-use std::{fmt::Debug, ops::Deref, result::Result as StdResult};
+use std::{collections::HashMap, fmt::Debug, ops::Deref, result::Result as StdResult};
 
 use serde::{Serialize, de::DeserializeOwned};
 use serde_bytes::ByteBuf;
-use worker::{ListOptions, Result, Storage, console_error};
+use worker::{ListOptions, Result, Storage, console_error, wasm_bindgen::JsValue};
 
-pub struct SafeStorage(Storage);
+/// max keys per storage call
+pub const MAX_KEYS_PER_CALL: usize = 128;
+
+enum Backend {
+    Durable(Storage),
+    #[cfg(any(test, feature = "testing"))]
+    Memory(memory::MemoryStorage),
+}
+
+pub struct SafeStorage(Backend);
 
 impl From<Storage> for SafeStorage {
     fn from(value: Storage) -> Self {
-        Self(value)
+        Self(Backend::Durable(value))
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl From<memory::MemoryStorage> for SafeStorage {
+    fn from(value: memory::MemoryStorage) -> Self {
+        Self(Backend::Memory(value))
     }
 }
 
@@ -25,13 +47,25 @@ fn deser_bbuf<T: DeserializeOwned>(key: &str, buf: ByteBuf) -> Result<T> {
     }
 }
 
+fn ser_bytes(v: &impl Serialize) -> Result<Vec<u8>> {
+    rmp_serde::to_vec(v).map_err(|e| worker::Error::RustError(e.to_string()))
+}
+
+fn bytes_to_js(v_ser: Vec<u8>) -> Result<JsValue> {
+    let v_raw = ByteBuf::from(v_ser);
+
+    Ok(serde_wasm_bindgen::to_value(&v_raw)?)
+}
+
 impl SafeStorage {
     pub async fn put(&mut self, key: impl AsRef<str>, v: &impl Serialize) -> worker::Result<()> {
-        let v_ser = rmp_serde::to_vec(&v).map_err(|e| worker::Error::RustError(e.to_string()))?;
-        let v_raw = ByteBuf::from(v_ser);
-        let v_js = serde_wasm_bindgen::to_value(&v_raw)?;
+        let v_ser = ser_bytes(v)?;
 
-        self.0.put_raw(key.as_ref(), v_js).await?;
+        match &self.0 {
+            Backend::Durable(storage) => storage.put_raw(key.as_ref(), bytes_to_js(v_ser)?).await?,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => storage.put(key.as_ref(), v_ser),
+        }
 
         Ok(())
     }
@@ -41,7 +75,17 @@ impl SafeStorage {
         key: impl AsRef<str>,
     ) -> worker::Result<Option<T>> {
         let key = key.as_ref();
-        let res = self.0.get::<ByteBuf>(key).await;
+        let storage = match &self.0 {
+            Backend::Durable(storage) => storage,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => {
+                return storage
+                    .get(key)
+                    .map(|v| deser_bbuf(key, ByteBuf::from(v)))
+                    .transpose();
+            }
+        };
+        let res = storage.get::<ByteBuf>(key).await;
         let v_js = match res {
             Ok(Some(v)) => v,
             Ok(None) => return Ok(None),
@@ -57,6 +101,22 @@ impl SafeStorage {
         deser_bbuf(key, v_js)
     }
 
+    /// the stored encoding of `key`, without decoding it
+    pub(crate) async fn get_bytes(&self, key: impl AsRef<str>) -> Result<Option<Vec<u8>>> {
+        let key = key.as_ref();
+        match &self.0 {
+            Backend::Durable(storage) => match storage.get::<ByteBuf>(key).await {
+                Ok(v) => Ok(v.map(ByteBuf::into_vec)),
+                Err(worker::Error::JsError(err)) if err.contains("No such value in storage") => {
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            },
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => Ok(storage.get(key)),
+        }
+    }
+
     pub async fn list_with_prefix<T: DeserializeOwned>(
         &self,
         prefix: impl AsRef<str>,
@@ -69,31 +129,177 @@ impl SafeStorage {
         &self,
         list_options: ListOptions<'_>,
     ) -> impl Iterator<Item = worker::Result<(String, T)>> + use<T> {
-        let v_idx = self
-            .0
-            .list_with_options(list_options)
-            .await
-            .unwrap_or_default();
+        let raw_entries: Vec<(String, ByteBuf)> = match &self.0 {
+            Backend::Durable(storage) => storage
+                .list_with_options(list_options)
+                .await
+                .unwrap_or_default()
+                .entries()
+                .into_iter()
+                .map(|entry| {
+                    let raw_entry = entry.expect("invalid prefixed value stored?!");
+                    serde_wasm_bindgen::from_value(raw_entry)
+                        .expect("invalid prefixed value stored?!")
+                })
+                .collect(),
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => storage
+                .list(&list_options)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| (k, ByteBuf::from(v)))
+                .collect(),
+        };
 
-        v_idx.entries().into_iter().map(|entry| {
-            let raw_entry = entry.expect("invalid prefixed value stored?!");
-            let (key, v_raw): (String, ByteBuf) =
-                serde_wasm_bindgen::from_value(raw_entry).expect("invalid prefixed value stored?!");
+        raw_entries.into_iter().map(|(key, v_raw)| {
             let v: T = deser_bbuf(&key, v_raw)?;
             Ok((key, v))
         })
     }
 
+    /// the stored encodings of the listed keys, without decoding them
+    pub(crate) async fn list_bytes(
+        &self,
+        list_options: ListOptions<'_>,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        match &self.0 {
+            Backend::Durable(storage) => storage
+                .list_with_options(list_options)
+                .await?
+                .entries()
+                .into_iter()
+                .map(|entry| {
+                    let (key, v): (String, ByteBuf) = serde_wasm_bindgen::from_value(entry?)?;
+                    Ok((key, v.into_vec()))
+                })
+                .collect(),
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => storage.list(&list_options),
+        }
+    }
+
     pub async fn delete(&mut self, key: impl AsRef<str>) -> Result<bool> {
-        self.0.delete(key.as_ref()).await
+        match &self.0 {
+            Backend::Durable(storage) => storage.delete(key.as_ref()).await,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => Ok(storage.delete(key.as_ref())),
+        }
+    }
+
+    /// values of the stored `keys`, missing keys are left out
+    pub async fn get_multiple<T: DeserializeOwned>(
+        &self,
+        keys: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<HashMap<String, T>> {
+        let keys: Vec<String> = keys.into_iter().map(|k| k.as_ref().to_string()).collect();
+        let mut values = HashMap::with_capacity(keys.len());
+        let storage = match &self.0 {
+            Backend::Durable(storage) => storage,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => {
+                for key in keys {
+                    if let Some(v) = storage.get(&key) {
+                        let v = deser_bbuf(&key, ByteBuf::from(v))?;
+                        values.insert(key, v);
+                    }
+                }
+                return Ok(values);
+            }
+        };
+        for chunk in keys.chunks(MAX_KEYS_PER_CALL) {
+            let raw = storage.get_multiple(chunk.to_vec()).await?;
+            for entry in raw.entries() {
+                let (key, v_raw): (String, ByteBuf) = serde_wasm_bindgen::from_value(entry?)?;
+                let v = deser_bbuf(&key, v_raw)?;
+                values.insert(key, v);
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// same as `put` for every entry, written as a single `WriteBatch`
+    pub async fn put_multiple<'a, V: Serialize + 'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = (impl AsRef<str>, &'a V)>,
+    ) -> Result<()> {
+        let mut batch = self.batch();
+        for (key, v) in entries {
+            batch.put(key, v)?;
+        }
+
+        batch.commit(self).await
     }
 
     pub async fn delete_multiple(&mut self, keys: Vec<impl Deref<Target = str>>) -> Result<usize> {
-        self.0.delete_multiple(keys).await
+        let storage = match &self.0 {
+            Backend::Durable(storage) => storage,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => {
+                return Ok(keys.iter().filter(|k| storage.delete(k)).count());
+            }
+        };
+        let mut deleted = 0;
+        let mut keys = keys.into_iter().peekable();
+        while keys.peek().is_some() {
+            let chunk: Vec<_> = keys.by_ref().take(MAX_KEYS_PER_CALL).collect();
+            deleted += storage.delete_multiple(chunk).await?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// deletes every key starting with `prefix`, returns the number of deleted keys
+    pub async fn delete_prefix(&mut self, prefix: impl AsRef<str>) -> Result<usize> {
+        const LIST_PAGE_SIZE: usize = 1024;
+
+        let storage = match &self.0 {
+            Backend::Durable(storage) => storage,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => {
+                let keys: Vec<String> = storage
+                    .list(&ListOptions::new().prefix(prefix.as_ref()))?
+                    .into_iter()
+                    .map(|(k, _)| k)
+                    .collect();
+                return self.delete_multiple(keys).await;
+            }
+        };
+        let mut deleted = 0;
+        loop {
+            let page = storage
+                .list_with_options(
+                    ListOptions::new()
+                        .prefix(prefix.as_ref())
+                        .limit(LIST_PAGE_SIZE),
+                )
+                .await?;
+            let keys = page
+                .keys()
+                .into_iter()
+                .map(|key| {
+                    key?.as_string()
+                        .ok_or_else(|| worker::Error::RustError("non string storage key".into()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let listed = keys.len();
+            deleted += self.delete_multiple(keys).await?;
+
+            if listed < LIST_PAGE_SIZE {
+                return Ok(deleted);
+            }
+        }
     }
 
     pub async fn delete_all(&mut self) -> Result<()> {
-        self.0.delete_all().await
+        match &self.0 {
+            Backend::Durable(storage) => storage.delete_all().await,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::Memory(storage) => {
+                storage.delete_all();
+                Ok(())
+            }
+        }
     }
 }
 
@@ -163,6 +369,12 @@ impl<T: Serialize + DeserializeOwned + Clone + Debug> StorageCell<T> {
         Ok(mutated_val.clone())
     }
 
+    /// drops the in-memory copy, the next access reloads from storage
+    /// needed after the underlying key is modified outside of this cell
+    pub fn invalidate(&mut self) {
+        self.hot_cache = None;
+    }
+
     pub async fn read(&mut self, storage: &SafeStorage) -> Result<&T> {
         if self.hot_cache.is_some() {
             return Ok(self.hot_cache.as_ref().unwrap());
@@ -0,0 +1,116 @@
+use std::result::Result as StdResult;
+
+use serde::{Deserialize, Serialize};
+use worker::{Response, Result};
+
+use crate::{
+    api_error::error_resp,
+    storage::{SafeStorage, StorageCell},
+    time::now_millis,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct RateLimitInner {
+    window_start: u64,
+    count: u32,
+}
+
+/// Fixed window request limiter backed by durable object storage
+pub struct RateLimit {
+    cell: StorageCell<RateLimitInner>,
+    max_requests: u32,
+    window_ms: u64,
+}
+
+impl RateLimit {
+    pub fn new(key: impl AsRef<str>, max_requests: u32, window_ms: u64) -> Self {
+        Self {
+            cell: StorageCell::new(key, RateLimitInner::default),
+            max_requests,
+            window_ms,
+        }
+    }
+
+    pub fn max_requests(&self) -> u32 {
+        self.max_requests
+    }
+
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    pub fn invalidate(&mut self) {
+        self.cell.invalidate();
+    }
+
+    /// returns Ok(Err(retry_after_ms)) if the limit is exhausted
+    pub async fn try_acquire(&mut self, storage: &mut SafeStorage) -> Result<StdResult<(), u64>> {
+        self.try_acquire_many(storage, 1).await
+    }
+
+    /// acquires `count` requests at once, or none of them if fewer are left in the window
+    pub async fn try_acquire_many(
+        &mut self,
+        storage: &mut SafeStorage,
+        count: u32,
+    ) -> Result<StdResult<(), u64>> {
+        let now = now_millis();
+        let (max_requests, window_ms) = (self.max_requests, self.window_ms);
+        let res = self
+            .cell
+            .try_get_update(storage, |inner| {
+                if now - window_ms >= inner.window_start {
+                    *inner = RateLimitInner {
+                        window_start: now,
+                        count: 0,
+                    };
+                }
+                if inner.count.saturating_add(count) > max_requests {
+                    return Err(inner.window_start + window_ms - now);
+                }
+                inner.count += count;
+                Ok(())
+            })
+            .await;
+
+        match res {
+            Ok(_) => Ok(Ok(())),
+            Err(Ok(retry_after_ms)) => Ok(Err(retry_after_ms)),
+            Err(Err(e)) => Err(e),
+        }
+    }
+}
+
+/// 429 response with a `Retry-After` header (in seconds)
+pub fn rate_limited_response(retry_after_ms: u64) -> Result<Response> {
+    let retry_after_secs = retry_after_ms.div_ceil(1000).max(1);
+    let res = error_resp("rate limited", 429)?;
+    res.headers()
+        .set("Retry-After", &retry_after_secs.to_string())?;
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, memory_storage};
+
+    #[test]
+    fn batches_are_acquired_whole_or_not_at_all() {
+        let mut storage = memory_storage();
+        let mut limit = RateLimit::new("limit", 5, 60_000);
+
+        assert!(
+            block_on(limit.try_acquire_many(&mut storage, 4))
+                .unwrap()
+                .is_ok()
+        );
+        assert!(
+            block_on(limit.try_acquire_many(&mut storage, 2))
+                .unwrap()
+                .is_err()
+        );
+        assert!(block_on(limit.try_acquire(&mut storage)).unwrap().is_ok());
+    }
+}
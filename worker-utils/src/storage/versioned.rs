@@ -0,0 +1,261 @@
+use std::{collections::BTreeMap, fmt::Debug};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use worker::Result;
+
+use super::{SafeStorage, ser_bytes};
+
+/// re-encodes a value of version `n` as version `n + 1`
+type Upcast = Box<dyn Fn(&[u8]) -> Result<Vec<u8>>>;
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    rmp_serde::from_slice(bytes).map_err(|e| worker::Error::RustError(e.to_string()))
+}
+
+/// what is actually stored under the key
+/// the payload is kept encoded so the version can be read before its shape is known
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    #[serde(with = "serde_bytes")]
+    payload: Vec<u8>,
+}
+
+/// `StorageCell` that stores a version alongside the value
+///
+/// values written by an older version are upcast one version at a time on read,
+/// the upcast value is written back at the current version on the next `set` / `update`
+pub struct VersionedStorageCell<T: Serialize + DeserializeOwned + Clone + Debug> {
+    key: String,
+    version: u32,
+    upcasts: BTreeMap<u32, Upcast>,
+    /// key and version of an unversioned value this cell replaces
+    legacy: Option<(String, u32)>,
+    /// set when the cached value still lives under the legacy key
+    legacy_pending: bool,
+    hot_cache: Option<T>,
+    initial_value: fn() -> T,
+}
+
+impl<T: Serialize + DeserializeOwned + Clone + Debug> VersionedStorageCell<T> {
+    pub fn new(key: impl AsRef<str>, version: u32, initial_value: fn() -> T) -> Self {
+        Self {
+            key: key.as_ref().to_string(),
+            version,
+            upcasts: BTreeMap::new(),
+            legacy: None,
+            legacy_pending: false,
+            hot_cache: None,
+            initial_value,
+        }
+    }
+
+    /// registers the upcast from `from_version` to `from_version + 1`
+    pub fn with_upcast<Old: DeserializeOwned, New: Serialize>(
+        mut self,
+        from_version: u32,
+        upcast: fn(Old) -> New,
+    ) -> Self {
+        self.upcasts.insert(
+            from_version,
+            Box::new(move |bytes| ser_bytes(&upcast(decode(bytes)?))),
+        );
+        self
+    }
+
+    /// reads a plain `StorageCell` value from `key` when nothing is stored under this cell's key,
+    /// treating it as `version`. the legacy key is deleted once the value is written back
+    pub fn with_legacy_key(mut self, key: impl AsRef<str>, version: u32) -> Self {
+        self.legacy = Some((key.as_ref().to_string(), version));
+        self
+    }
+
+    fn upcast(&self, mut payload: Vec<u8>, mut version: u32) -> Result<T> {
+        while version < self.version {
+            let upcast = self.upcasts.get(&version).ok_or_else(|| {
+                worker::Error::RustError(format!(
+                    "no upcast for {} from version {version}",
+                    self.key
+                ))
+            })?;
+            payload = upcast(&payload)?;
+            version += 1;
+        }
+
+        decode(&payload)
+    }
+
+    async fn load(&mut self, storage: &SafeStorage) -> Result<T> {
+        if let Some(envelope) = storage.get::<Envelope>(&self.key).await? {
+            if envelope.version > self.version {
+                return Err(worker::Error::RustError(format!(
+                    "{} is at version {}, newer than {}",
+                    self.key, envelope.version, self.version
+                )));
+            }
+            return self.upcast(envelope.payload, envelope.version);
+        }
+
+        if let Some((legacy_key, legacy_version)) = &self.legacy {
+            if let Some(payload) = storage.get_bytes(legacy_key).await? {
+                let value = self.upcast(payload, *legacy_version)?;
+                self.legacy_pending = true;
+                return Ok(value);
+            }
+        }
+
+        Ok((self.initial_value)())
+    }
+
+    async fn store(&mut self, storage: &mut SafeStorage, v: &T) -> Result<()> {
+        let envelope = Envelope {
+            version: self.version,
+            payload: ser_bytes(v)?,
+        };
+        if !self.legacy_pending {
+            return storage.put(&self.key, &envelope).await;
+        }
+
+        let (legacy_key, _) = self.legacy.as_ref().unwrap();
+        let mut batch = storage.batch();
+        batch.put(&self.key, &envelope)?.delete(legacy_key);
+        batch.commit(storage).await?;
+        self.legacy_pending = false;
+
+        Ok(())
+    }
+
+    pub async fn read(&mut self, storage: &SafeStorage) -> Result<&T> {
+        if self.hot_cache.is_none() {
+            let v = self.load(storage).await?;
+            self.hot_cache = Some(v);
+        }
+
+        Ok(self.hot_cache.as_ref().unwrap())
+    }
+
+    pub async fn set(&mut self, storage: &mut SafeStorage, v: T) -> Result<()> {
+        self.store(storage, &v).await?;
+        self.hot_cache = Some(v);
+
+        Ok(())
+    }
+
+    pub async fn update(
+        &mut self,
+        storage: &mut SafeStorage,
+        updater: impl FnOnce(&mut T),
+    ) -> Result<()> {
+        let mut v = self.read(storage).await?.clone();
+        updater(&mut v);
+
+        self.set(storage, v).await
+    }
+
+    /// drops the in-memory copy, the next access reloads from storage
+    pub fn invalidate(&mut self) {
+        self.hot_cache = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::StorageCell,
+        testing::{block_on, memory_storage},
+    };
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct ProfileV0 {
+        username: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct ProfileV1 {
+        username: String,
+        score: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Profile {
+        name: String,
+        score: u64,
+    }
+
+    fn profile_cell() -> VersionedStorageCell<Profile> {
+        VersionedStorageCell::new("profile", 2, || Profile {
+            name: String::new(),
+            score: 0,
+        })
+        .with_upcast(0, |v: ProfileV0| ProfileV1 {
+            username: v.username,
+            score: 0,
+        })
+        .with_upcast(1, |v: ProfileV1| Profile {
+            name: v.username,
+            score: v.score,
+        })
+    }
+
+    #[test]
+    fn upcasts_legacy_values_and_drops_the_old_key() {
+        let mut storage = memory_storage();
+        let mut legacy = StorageCell::new("profile_v0", || ProfileV0 {
+            username: String::new(),
+        });
+        block_on(legacy.set(
+            &mut storage,
+            ProfileV0 {
+                username: "ada".into(),
+            },
+        ))
+        .unwrap();
+
+        let mut cell = profile_cell().with_legacy_key("profile_v0", 0);
+        let expected = Profile {
+            name: "ada".into(),
+            score: 0,
+        };
+        assert_eq!(block_on(cell.read(&storage)).unwrap(), &expected);
+
+        block_on(cell.update(&mut storage, |p| p.score += 1)).unwrap();
+        assert!(block_on(storage.get_bytes("profile_v0")).unwrap().is_none());
+
+        let mut reloaded = profile_cell();
+        assert_eq!(block_on(reloaded.read(&storage)).unwrap().score, 1);
+    }
+
+    #[test]
+    fn upcasts_older_versions() {
+        let mut storage = memory_storage();
+        let mut old = VersionedStorageCell::new("profile", 1, || ProfileV1 {
+            username: "ada".into(),
+            score: 3,
+        });
+        let v = block_on(old.read(&storage)).unwrap().clone();
+        block_on(old.set(&mut storage, v)).unwrap();
+
+        let mut cell = profile_cell();
+        let profile = block_on(cell.read(&storage)).unwrap();
+        assert_eq!(profile.name, "ada");
+        assert_eq!(profile.score, 3);
+    }
+
+    #[test]
+    fn missing_upcast_is_an_error() {
+        let mut storage = memory_storage();
+        let mut old = VersionedStorageCell::new("profile", 1, || ProfileV1 {
+            username: "ada".into(),
+            score: 0,
+        });
+        let v = block_on(old.read(&storage)).unwrap().clone();
+        block_on(old.set(&mut storage, v)).unwrap();
+
+        let mut cell = VersionedStorageCell::new("profile", 2, || Profile {
+            name: String::new(),
+            score: 0,
+        });
+        assert!(block_on(cell.read(&storage)).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+/// unix time in millis
+///
+/// native builds read the system clock, or the time pinned by `testing::freeze_clock`,
+/// shifted by `testing::advance_clock`
+pub fn now_millis() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        worker::Date::now().as_millis()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let now = native::FROZEN_AT_MS
+            .get()
+            .unwrap_or_else(native::system_millis);
+        now + native::CLOCK_OFFSET_MS.get()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod native {
+    use std::cell::Cell;
+
+    thread_local! {
+        pub(crate) static CLOCK_OFFSET_MS: Cell<u64> = const { Cell::new(0) };
+        pub(crate) static FROZEN_AT_MS: Cell<Option<u64>> = const { Cell::new(None) };
+    }
+
+    pub(crate) fn system_millis() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,216 @@
+//! trace ids carried across workers, durable objects, queues and outbound requests
+//!
+//! the entry worker calls `with_trace` before routing, everything downstream reads the
+//! id back from `TRACE_HEADER` and forwards it with `DoClient::with_trace`,
+//! `RequestInitBuilder::trace` or a `trace_id` field on queued events
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use worker::{Headers, Request, Response, Result};
+
+pub const TRACE_HEADER: &str = "x-trace-id";
+
+/// longer or non alphanumeric ids from clients are replaced, they end up in logs
+const MAX_TRACE_ID_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct TraceId(String);
+
+impl TraceId {
+    /// 128 random bits, hex encoded
+    pub fn new() -> Self {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).expect("no randomness available");
+
+        Self(bytes.iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        let valid = !id.is_empty()
+            && id.len() <= MAX_TRACE_ID_LEN
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+
+        valid.then(|| Self(id.to_string()))
+    }
+
+    pub fn from_headers(headers: &Headers) -> Option<Self> {
+        headers
+            .get(TRACE_HEADER)
+            .ok()
+            .flatten()
+            .and_then(|id| Self::parse(&id))
+    }
+
+    /// the request's trace id, or a fresh one if it has none
+    pub fn from_request(req: &Request) -> Self {
+        Self::from_headers(req.headers()).unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// echoes the id back so clients can quote it in bug reports
+    pub fn tag(&self, mut res: Response) -> Result<Response> {
+        res.headers_mut().set(TRACE_HEADER, &self.0)?;
+        Ok(res)
+    }
+}
+
+impl Default for TraceId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// makes sure `req` carries a valid trace id, so handlers can read it with `TraceId::from_request`
+pub fn with_trace(req: Request) -> Result<(Request, TraceId)> {
+    if let Some(trace) = TraceId::from_headers(req.headers()) {
+        return Ok((req, trace));
+    }
+
+    // headers of incoming requests are immutable
+    let mut req = req.clone_mut()?;
+    let trace = TraceId::new();
+    req.headers_mut()?.set(TRACE_HEADER, trace.as_str())?;
+
+    Ok((req, trace))
+}
+
+/// a queue message with the trace of the request that sent it
+///
+/// messages sent before tracing was added deserialize with `trace_id: None`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Traced<T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<TraceId>,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T> Traced<T> {
+    pub fn new(body: T, trace: &TraceId) -> Self {
+        Self {
+            trace_id: Some(trace.clone()),
+            body,
+        }
+    }
+
+    /// the message's trace, or a fresh one for untraced messages
+    pub fn trace(&self) -> TraceId {
+        self.trace_id.clone().unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "axum")]
+pub use extract::propagate_trace;
+
+#[cfg(feature = "axum")]
+mod extract {
+    use std::convert::Infallible;
+
+    use axum::{
+        async_trait,
+        extract::{FromRequestParts, Request},
+        http::{HeaderValue, request::Parts},
+        middleware::Next,
+        response::Response,
+    };
+
+    use super::{TRACE_HEADER, TraceId};
+
+    /// middleware that gives every request a trace id and echoes it in the response
+    pub async fn propagate_trace(mut req: Request, next: Next) -> Response {
+        let trace = req
+            .headers()
+            .get(TRACE_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .and_then(TraceId::parse)
+            .unwrap_or_default();
+        let header = HeaderValue::from_str(trace.as_str()).expect("trace ids are valid headers");
+        req.headers_mut().insert(TRACE_HEADER, header.clone());
+        req.extensions_mut().insert(trace);
+
+        let mut res = next.run(req).await;
+        res.headers_mut().insert(TRACE_HEADER, header);
+        res
+    }
+
+    /// the id set by `propagate_trace`, or a fresh one on routes without it
+    #[async_trait]
+    impl<S: Send + Sync> FromRequestParts<S> for TraceId {
+        type Rejection = Infallible;
+
+        async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+            Ok(parts
+                .extensions
+                .get::<TraceId>()
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+}
+
+/// `console_log!` prefixed with the trace id
+#[macro_export]
+macro_rules! trace_log {
+    ($trace:expr, $($arg:tt)+) => {
+        ::worker::console_log!("[trace {}] {}", $trace, format_args!($($arg)+))
+    };
+}
+
+/// `console_error!` prefixed with the trace id
+#[macro_export]
+macro_rules! trace_error {
+    ($trace:expr, $($arg:tt)+) => {
+        ::worker::console_error!("[trace {}] {}", $trace, format_args!($($arg)+))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_only_safe_ids() {
+        let trace = TraceId::new();
+        assert_eq!(trace.as_str().len(), 32);
+        assert_eq!(TraceId::parse(trace.as_str()), Some(trace));
+
+        assert!(TraceId::parse("upload-7f3a_1").is_some());
+        assert!(TraceId::parse("").is_none());
+        assert!(TraceId::parse("a b").is_none());
+        assert!(TraceId::parse("x\n[trace forged]").is_none());
+        assert!(TraceId::parse(&"a".repeat(MAX_TRACE_ID_LEN + 1)).is_none());
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Message {
+        Upload(String),
+        Sync { id: u64 },
+    }
+
+    #[test]
+    fn traced_messages_read_untraced_ones() {
+        let trace = TraceId::parse("abc").unwrap();
+        let json = serde_json::to_value(Traced::new(Message::Sync { id: 1 }, &trace)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "trace_id": "abc", "Sync": { "id": 1 } })
+        );
+
+        let old: Traced<Message> = serde_json::from_str(r#"{ "Upload": "uid" }"#).unwrap();
+        assert_eq!(old.trace_id, None);
+        assert_eq!(old.body, Message::Upload("uid".into()));
+    }
+}
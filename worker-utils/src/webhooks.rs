@@ -0,0 +1,151 @@
+//! domain events pushed to partners, delivered by the `yral-webhooks` worker
+//!
+//! producers send a `WebhookEvent` to `WEBHOOK_EVENTS_QUEUE`, yral-webhooks fans it out to
+//! every partner endpoint subscribed to its kind, signs each delivery and retries it with backoff
+//!
+//! partners receive the event as JSON with `WEBHOOK_SIGNATURE_HEADER` set to
+//! `time=<millis>,sig1=<hex hmac-sha256 of "{time}.{body}">` under their endpoint's secret
+
+use serde::{Deserialize, Serialize};
+
+use crate::time::now_millis;
+
+pub const WEBHOOK_EVENTS_QUEUE: &str = "WEBHOOK_EVENTS";
+
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "Yral-Webhook-Signature";
+/// the same on every delivery of an event, partners dedupe on it
+pub const WEBHOOK_EVENT_ID_HEADER: &str = "Yral-Webhook-Id";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    PostPublished,
+    WithdrawalCompleted,
+    ReferralCredited,
+}
+
+impl WebhookEventKind {
+    pub const ALL: [Self; 3] = [
+        Self::PostPublished,
+        Self::WithdrawalCompleted,
+        Self::ReferralCredited,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PostPublished => "post_published",
+            Self::WithdrawalCompleted => "withdrawal_completed",
+            Self::ReferralCredited => "referral_credited",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEventData {
+    PostPublished {
+        user_principal: String,
+        post_id: String,
+    },
+    /// `amount` of `token` left the user's wallet
+    WithdrawalCompleted {
+        user_principal: String,
+        /// in the token's smallest unit, as a decimal string
+        amount: String,
+        token: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        withdrawal_id: Option<String>,
+    },
+    /// `user_principal` was rewarded for referring `referee_principal`
+    ReferralCredited {
+        user_principal: String,
+        referee_principal: String,
+        /// sats
+        amount: u64,
+    },
+}
+
+impl WebhookEventData {
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            Self::PostPublished { .. } => WebhookEventKind::PostPublished,
+            Self::WithdrawalCompleted { .. } => WebhookEventKind::WithdrawalCompleted,
+            Self::ReferralCredited { .. } => WebhookEventKind::ReferralCredited,
+        }
+    }
+}
+
+/// the body partners receive
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WebhookEvent {
+    /// 128 random bits, hex encoded
+    pub id: String,
+    /// unix millis
+    pub created_at: u64,
+    #[serde(flatten)]
+    pub data: WebhookEventData,
+}
+
+impl WebhookEvent {
+    pub fn new(data: WebhookEventData) -> Self {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).expect("no randomness available");
+
+        Self {
+            id: bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            created_at: now_millis(),
+            data,
+        }
+    }
+
+    pub fn kind(&self) -> WebhookEventKind {
+        self.data.kind()
+    }
+
+    /// sends the event to `WEBHOOK_EVENTS_QUEUE`
+    #[cfg(feature = "queue")]
+    pub async fn send(&self, env: &worker::Env) -> worker::Result<()> {
+        env.queue(WEBHOOK_EVENTS_QUEUE)?.send(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn events_are_flat_and_tagged() {
+        let event = WebhookEvent {
+            id: "abc".into(),
+            created_at: 1_700_000_000_000,
+            data: WebhookEventData::ReferralCredited {
+                user_principal: "2vxsx-fae".into(),
+                referee_principal: "aaaaa-aa".into(),
+                amount: 10,
+            },
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "id": "abc",
+                "created_at": 1_700_000_000_000u64,
+                "event": "referral_credited",
+                "user_principal": "2vxsx-fae",
+                "referee_principal": "aaaaa-aa",
+                "amount": 10,
+            })
+        );
+        assert_eq!(value["event"], event.kind().as_str());
+        assert_eq!(
+            serde_json::from_value::<WebhookEvent>(value).unwrap(),
+            event
+        );
+    }
+}
@@ -2,6 +2,19 @@ use candid::{CandidType, Decode, Encode, Principal};
 use ic_agent::Agent;
 use serde::{Deserialize, Serialize};
 use worker::*;
+use worker_utils::{
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+};
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "icpump_token_cleanup",
+    &[
+        Dependency::Var("FIREBASE_PROJECT_ID"),
+        Dependency::Secret("FIREBASE_API_KEY"),
+        Dependency::Secret("WORKER_AUTH_TOKEN"),
+    ],
+);
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TokenListItem {
@@ -192,7 +205,25 @@ async fn delete_invalid_tokens(env: &Env, invalid_tokens: &[TokenListItem]) -> R
 
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    // the cleanup deletes tokens on a GET, it's checked as the write it is
+    let path = req.path();
+    let method = if path == "/" {
+        Method::Post
+    } else {
+        req.method()
+    };
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "icpump_token_cleanup", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
     Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .get_async("/", |req: Request, ctx: RouteContext<()>| {
             async move {
                 // Getting env from context instead of cloning
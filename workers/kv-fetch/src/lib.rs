@@ -4,14 +4,35 @@ use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use serde::Deserialize;
 use serde_json::json;
 use worker::*;
+use worker_utils::{
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+};
 
 mod utils;
 
+static HEALTH: HealthCheck = HealthCheck::new(
+    "kv-fetch",
+    &[Dependency::Kv("kvfetch"), Dependency::Secret("PUBLIC_KEY")],
+);
+
 #[event(fetch)]
 async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let (method, path) = (req.method(), req.path());
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "kv-fetch", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
     let router = Router::new();
 
     router
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .post_async("/:key", |mut req, ctx| async move {
             if let Err((msg, status)) = verify_jwt_token(&req, &ctx) {
                 return Response::error(msg, status);
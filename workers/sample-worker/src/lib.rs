@@ -1,10 +1,23 @@
 use serde::{Deserialize, Serialize};
 use worker::*;
+use worker_utils::{
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+};
 
 mod utils;
 
+static HEALTH: HealthCheck = HealthCheck::new("sample-worker", &[Dependency::Kv("assets")]);
+
 #[event(fetch)]
 async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let (method, path) = (req.method(), req.path());
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "sample-worker", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
     let router = Router::new();
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -14,6 +27,11 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
 
     router
         .get("/", |_, _| Response::ok("Hello from Workers!"))
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .post_async("/:country", |mut req, ctx| async move {
             let country = ctx.param("country").unwrap();
             let city = match req.json::<Country>().await {
@@ -0,0 +1,249 @@
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+use worker_utils::{
+    analytics::AnalyticsEvent,
+    backup::{self, Backup},
+    pagination::KeyCursorPager,
+    storage::{SafeStorage, StorageCell},
+    time::now_millis,
+};
+
+pub const USER_ACTIVITY: &str = "USER_ACTIVITY";
+
+const ACTIVITY_PREFIX: &str = "activity-";
+
+/// older items are pruned as new ones arrive
+const FEED_RETENTION_MS: u64 = 90 * 24 * 60 * 60 * 1000;
+
+pub const MAX_PAGE_SIZE: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Activity {
+    Vote {
+        post_id: String,
+        won: bool,
+        vote_amount: u64,
+        /// sats won or lost
+        amount: u64,
+    },
+    VideoPublished {
+        post_id: String,
+    },
+    VideoUploadFailed {
+        video_uid: String,
+    },
+    Claim {
+        /// DOLR, as a decimal string
+        amount: String,
+    },
+    Referral {
+        referee_principal: String,
+        /// sats rewarded
+        amount: u64,
+    },
+    Signup {
+        referrer_principal: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActivityItem {
+    /// unix millis
+    pub at: u64,
+    #[serde(flatten)]
+    pub activity: Activity,
+}
+
+impl From<AnalyticsEvent> for ActivityItem {
+    fn from(event: AnalyticsEvent) -> Self {
+        let at = event.at();
+        let activity = match event {
+            AnalyticsEvent::Vote {
+                post_id,
+                won,
+                vote_amount,
+                amount,
+                ..
+            } => Activity::Vote {
+                post_id,
+                won,
+                vote_amount,
+                amount,
+            },
+            AnalyticsEvent::VideoPublished { post_id, .. } => Activity::VideoPublished { post_id },
+            AnalyticsEvent::VideoUploadFailed { video_uid, .. } => {
+                Activity::VideoUploadFailed { video_uid }
+            }
+            AnalyticsEvent::Claim { amount, .. } => Activity::Claim { amount },
+            AnalyticsEvent::Referral {
+                referee_principal,
+                amount,
+                ..
+            } => Activity::Referral {
+                referee_principal,
+                amount,
+            },
+            AnalyticsEvent::Signup {
+                referrer_principal, ..
+            } => Activity::Signup { referrer_principal },
+        };
+
+        Self { at, activity }
+    }
+}
+
+impl ActivityItem {
+    /// sorts by time, the rest keeps a redelivered event on the same key
+    fn key(&self) -> String {
+        let id = match &self.activity {
+            Activity::Vote { post_id, .. } => format!("vote-{post_id}"),
+            Activity::VideoPublished { post_id } => format!("video_published-{post_id}"),
+            Activity::VideoUploadFailed { video_uid } => {
+                format!("video_upload_failed-{video_uid}")
+            }
+            Activity::Claim { amount } => format!("claim-{amount}"),
+            Activity::Referral {
+                referee_principal, ..
+            } => format!("referral-{referee_principal}"),
+            // once per user
+            Activity::Signup { .. } => "signup".into(),
+        };
+
+        format!("{ACTIVITY_PREFIX}{:020}-{id}", self.at)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeedQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeedPage {
+    pub items: Vec<ActivityItem>,
+    pub next: Option<String>,
+}
+
+/// a user's activity feed, newest first
+#[durable_object]
+pub struct UserActivity {
+    state: State,
+    env: Env,
+    /// unix millis, items before this are already pruned
+    pruned_until: RefCell<StorageCell<u64>>,
+}
+
+// SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+// because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserActivity {
+    fn storage(&self) -> SafeStorage {
+        self.state.storage().into()
+    }
+
+    async fn record(&self, events: Vec<AnalyticsEvent>) -> Result<()> {
+        let mut storage = self.storage();
+        let items: Vec<_> = events.into_iter().map(ActivityItem::from).collect();
+        storage
+            .put_multiple(items.iter().map(|item| (item.key(), item)))
+            .await?;
+
+        self.prune(&mut storage).await
+    }
+
+    async fn prune(&self, storage: &mut SafeStorage) -> Result<()> {
+        let cutoff = now_millis().saturating_sub(FEED_RETENTION_MS);
+        let mut pruned_until = self.pruned_until.borrow_mut();
+        // at most once a day
+        if *pruned_until.read(storage).await? + 24 * 60 * 60 * 1000 > cutoff {
+            return Ok(());
+        }
+
+        let end = format!("{ACTIVITY_PREFIX}{cutoff:020}");
+        let expired: Vec<String> = storage
+            .list_with_options::<ActivityItem>(ListOptions::new().prefix(ACTIVITY_PREFIX).end(&end))
+            .await
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_>>()?;
+        storage.delete_multiple(expired).await?;
+
+        pruned_until.set(storage, cutoff).await
+    }
+
+    async fn page(&self, query: FeedQuery) -> Result<FeedPage> {
+        let storage = self.storage();
+        let page = KeyCursorPager::new(ACTIVITY_PREFIX)
+            .with_max_page_size(MAX_PAGE_SIZE)
+            .reversed()
+            .page(
+                &storage,
+                query.limit.unwrap_or(MAX_PAGE_SIZE),
+                query.cursor.as_deref(),
+                |_, item: ActivityItem| item,
+            )
+            .await?;
+
+        Ok(FeedPage {
+            items: page.items,
+            next: page.next,
+        })
+    }
+}
+
+impl Backup for UserActivity {
+    const NAMESPACE: &'static str = USER_ACTIVITY;
+
+    fn backup_state(&self) -> &State {
+        &self.state
+    }
+
+    fn backup_env(&self) -> &Env {
+        &self.env
+    }
+
+    fn invalidate_cached_state(&self) {
+        self.pruned_until.borrow_mut().invalidate();
+    }
+}
+
+// SAFETY: See comment on first impl block for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl DurableObject for UserActivity {
+    fn new(state: State, env: Env) -> Self {
+        console_error_panic_hook::set_once();
+
+        Self {
+            state,
+            env,
+            pruned_until: RefCell::new(StorageCell::new("pruned_until", || 0)),
+        }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        if let Some(res) = backup::handle(self, &mut req).await {
+            return res;
+        }
+        let env = self.env.clone();
+        let router = Router::with_data(self);
+
+        router
+            .post_async("/record", async |mut req, ctx| {
+                let events: Vec<AnalyticsEvent> = req.json().await?;
+                ctx.data.record(events).await?;
+
+                Response::ok("done")
+            })
+            .post_async("/feed", async |mut req, ctx| {
+                let query: FeedQuery = req.json().await?;
+                let page = ctx.data.page(query).await?;
+
+                Response::from_json(&page)
+            })
+            .run(req, env)
+            .await
+    }
+}
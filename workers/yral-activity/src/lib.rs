@@ -0,0 +1,119 @@
+mod feed;
+mod jwt;
+
+use std::collections::HashMap;
+
+use feed::{FeedQuery, USER_ACTIVITY};
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
+use serde_json::Value;
+use worker::*;
+use worker_utils::{
+    analytics::AnalyticsEvent,
+    api_error::error_resp,
+    cors::cors_for_env,
+    do_client::DoClient,
+    health::{Dependency, HealthCheck},
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    principals,
+};
+
+static HEALTH: HealthCheck =
+    HealthCheck::new("yral-activity", &[Dependency::DurableObject(USER_ACTIVITY)]);
+
+/// `GET /feed/:user_principal?limit=&cursor=`, newest first
+async fn user_feed(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+    let user_principal = principals!(ctx, "user_principal");
+    let Ok(query) = req.query::<FeedQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+
+    DoClient::new(&ctx.env)
+        .post(USER_ACTIVITY, &user_principal.to_text(), "feed", &query)
+        .await
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-activity", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/feed/:user_principal", user_feed)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// hands each user's events to their feed in one call, a failed user is retried alone
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    let metrics = Metrics::new(&env, "yral-activity");
+    let mut by_user = HashMap::<String, Vec<(Message<Value>, AnalyticsEvent)>>::new();
+    for message in batch.messages()? {
+        match serde_json::from_value::<AnalyticsEvent>(message.body().clone()) {
+            Ok(event) => by_user
+                .entry(event.user_principal().to_string())
+                .or_default()
+                .push((message, event)),
+            Err(e) => {
+                console_error!("dropping malformed activity event {}: {e}", message.id());
+                metrics.counter("activity_events", &["malformed"]);
+                message.ack();
+            }
+        }
+    }
+
+    let client = DoClient::new(&env);
+    for (user, entries) in by_user {
+        let events: Vec<_> = entries.iter().map(|(_, event)| event).collect();
+        let res = client.post(USER_ACTIVITY, &user, "record", &events).await;
+        match res {
+            Ok(res) if res.status_code() == 200 => {
+                for (message, event) in &entries {
+                    metrics.counter("activity_events", &[event.kind()]);
+                    message.ack();
+                }
+            }
+            Ok(res) => {
+                console_error!(
+                    "failed to record activity of {user}: status {}",
+                    res.status_code()
+                );
+                entries.iter().for_each(|(message, _)| message.retry());
+            }
+            Err(e) => {
+                console_error!("failed to record activity of {user}: {e}");
+                entries.iter().for_each(|(message, _)| message.retry());
+            }
+        }
+    }
+
+    Ok(())
+}
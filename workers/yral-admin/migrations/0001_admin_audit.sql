@@ -0,0 +1,36 @@
+-- every admin operation, written before it runs and again with its outcome
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- shared by the rows of one operation
+    op_id TEXT NOT NULL,
+    -- JWT subject of the admin
+    actor TEXT NOT NULL,
+    -- json encoded roles of the admin's token
+    roles TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    -- json encoded request
+    params TEXT NOT NULL,
+    -- requested, succeeded or failed
+    phase TEXT NOT NULL,
+    status_code INTEGER,
+    error TEXT,
+    trace_id TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS admin_audit_log_actor ON admin_audit_log (actor, id);
+CREATE INDEX IF NOT EXISTS admin_audit_log_target ON admin_audit_log (target, id);
+
+-- the log is immutable, corrections are new rows
+CREATE TRIGGER IF NOT EXISTS admin_audit_log_no_update
+BEFORE UPDATE ON admin_audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'admin_audit_log is append only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS admin_audit_log_no_delete
+BEFORE DELETE ON admin_audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'admin_audit_log is append only');
+END;
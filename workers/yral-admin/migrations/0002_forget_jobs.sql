@@ -0,0 +1,28 @@
+-- account deletions, one job per request and one row per step it fans out to
+CREATE TABLE IF NOT EXISTS forget_jobs (
+    id TEXT PRIMARY KEY,
+    user_principal TEXT NOT NULL,
+    user_canister TEXT,
+    -- JWT subject of the admin who requested it
+    requested_by TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    -- json encoded request, read by the queue consumer
+    params TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    -- set once every step succeeded or was skipped
+    completed_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS forget_jobs_user ON forget_jobs (user_principal, created_at);
+
+CREATE TABLE IF NOT EXISTS forget_steps (
+    job_id TEXT NOT NULL,
+    step TEXT NOT NULL,
+    -- pending, succeeded, failed or skipped
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- what was erased, or the last error
+    detail TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (job_id, step)
+);
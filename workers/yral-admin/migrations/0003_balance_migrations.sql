@@ -0,0 +1,36 @@
+-- bulk balance conversions between economies, one row per run
+CREATE TABLE IF NOT EXISTS balance_migrations (
+    id TEXT PRIMARY KEY,
+    -- economy debited, cents
+    source TEXT NOT NULL,
+    -- economy credited, sats or yral
+    destination TEXT NOT NULL,
+    -- destination base units per source base unit
+    rate_numerator INTEGER NOT NULL,
+    rate_denominator INTEGER NOT NULL,
+    -- 1 if balances are only read and converted
+    dry_run INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    -- JWT subject of the admin who requested it
+    requested_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+-- one row per user of a run, read back by the queue consumer and the reconciliation report
+CREATE TABLE IF NOT EXISTS balance_conversions (
+    migration_id TEXT NOT NULL,
+    user_principal TEXT NOT NULL,
+    user_canister TEXT NOT NULL,
+    -- pending, planned, debited, credited, skipped or failed
+    status TEXT NOT NULL,
+    -- decimal strings in base units, set once read or debited and once credited
+    source_amount TEXT,
+    destination_amount TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- the last error
+    detail TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (migration_id, user_principal)
+);
+
+CREATE INDEX IF NOT EXISTS balance_conversions_status ON balance_conversions (migration_id, status);
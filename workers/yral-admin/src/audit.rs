@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::{wasm_bindgen::JsValue, D1Database, Result};
+use worker_utils::jwt::AdminClaims;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// written before the operation runs, nothing runs without it
+    Requested,
+    Succeeded,
+    Failed,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Requested => "requested",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// one admin operation, recorded once per phase
+pub struct AuditRecord<'a> {
+    pub op_id: &'a str,
+    pub admin: &'a AdminClaims,
+    pub action: &'static str,
+    pub target: &'a str,
+    pub params: &'a Value,
+    pub trace_id: &'a str,
+}
+
+fn opt_str(v: Option<&str>) -> JsValue {
+    v.map(JsValue::from).unwrap_or(JsValue::NULL)
+}
+
+impl AuditRecord<'_> {
+    /// rows are only ever inserted, the table rejects updates and deletes
+    pub async fn append(
+        &self,
+        db: &D1Database,
+        phase: Phase,
+        status_code: Option<u16>,
+        error: Option<&str>,
+        now: u64,
+    ) -> Result<()> {
+        db.prepare(
+            "INSERT INTO admin_audit_log \
+            (op_id, actor, roles, action, target, params, phase, status_code, error, trace_id, created_at) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )
+        .bind(&[
+            self.op_id.into(),
+            self.admin.sub.as_str().into(),
+            serde_json::to_string(&self.admin.roles)?.into(),
+            self.action.into(),
+            self.target.into(),
+            self.params.to_string().into(),
+            phase.as_str().into(),
+            status_code
+                .map(|c| JsValue::from_f64(c as f64))
+                .unwrap_or(JsValue::NULL),
+            opt_str(error),
+            self.trace_id.into(),
+            JsValue::from_f64(now as f64),
+        ])?
+        .run()
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub target: Option<String>,
+    /// only rows older than this id, for paging
+    pub before: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditRow {
+    pub id: u64,
+    pub op_id: String,
+    pub actor: String,
+    pub roles: String,
+    pub action: String,
+    pub target: String,
+    pub params: String,
+    pub phase: Phase,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub trace_id: Option<String>,
+    /// unix millis
+    pub created_at: u64,
+}
+
+/// newest first
+pub async fn query_audit_log(
+    db: &D1Database,
+    query: &AuditQuery,
+    limit: u32,
+) -> Result<Vec<AuditRow>> {
+    db.prepare(
+        "SELECT * FROM admin_audit_log \
+        WHERE (?1 IS NULL OR actor = ?1) AND (?2 IS NULL OR target = ?2) AND (?3 IS NULL OR id < ?3) \
+        ORDER BY id DESC LIMIT ?4",
+    )
+    .bind(&[
+        opt_str(query.actor.as_deref()),
+        opt_str(query.target.as_deref()),
+        query
+            .before
+            .map(|id| JsValue::from_f64(id as f64))
+            .unwrap_or(JsValue::NULL),
+        JsValue::from_f64(limit as f64),
+    ])?
+    .all()
+    .await?
+    .results()
+}
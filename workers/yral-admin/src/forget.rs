@@ -0,0 +1,581 @@
+//! account deletion, fanned out as one queue message per step so each step is retried on its own
+
+use std::{future::Future, time::Duration};
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::{backup, trace::TraceId, RequestInitBuilder};
+
+use crate::ops::Target;
+
+pub const FORGET_USER_QUEUE: &str = "FORGET_USER";
+
+/// same prefix as yral-coin's own forget route, archives of both end up side by side
+const GDPR_ARCHIVE_PREFIX: &str = "yral-coin";
+
+const STREAM_API_BASE: &str = "https://api.cloudflare.com/client/v4/accounts";
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// yral-upload-video's video -> post mappings and their per creator index
+const VIDEO_POSTS_KV: &str = "VIDEO_POSTS";
+/// same prefix as yral-upload-video's `CREATOR_PREFIX`
+const CREATOR_PREFIX: &str = "creator-";
+
+/// posts a single message of a paged step handles, the rest go out as follow ups
+const POSTS_PER_MESSAGE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// `UserHonGameState`
+    HotOrNot,
+    /// `UserEphemeralState`, settled before it's erased
+    PumpNDump,
+    /// `UserYralCoinState`, archived before it's erased
+    Coin,
+    /// videos of every post in the user's creator index
+    StreamVideos,
+    /// post service mappings of the user's canister posts
+    RedisMappings,
+}
+
+impl Step {
+    pub const ALL: [Step; 5] = [
+        Self::HotOrNot,
+        Self::PumpNDump,
+        Self::Coin,
+        Self::StreamVideos,
+        Self::RedisMappings,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::HotOrNot => "hot_or_not",
+            Self::PumpNDump => "pump_n_dump",
+            Self::Coin => "coin",
+            Self::StreamVideos => "stream_videos",
+            Self::RedisMappings => "redis_mappings",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Succeeded,
+    /// the last attempt failed, the queue retries it until it's dead lettered
+    Failed,
+    /// nothing to erase for the user
+    Skipped,
+}
+
+impl StepStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// body of `POST /forget_user/:user_principal`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ForgetUserRequest {
+    pub reason: String,
+    /// pump-n-dump state and post mappings are keyed by it, both are skipped without it
+    #[serde(default)]
+    pub user_canister: Option<Principal>,
+}
+
+/// same shape as yral-upload-video's `VideoPost`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VideoPost {
+    pub video_uid: String,
+    pub canister_id: Principal,
+    pub post_id: String,
+}
+
+/// what a job erases, stored as the job's params so every step works on the same posts
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ForgetParams {
+    #[serde(flatten)]
+    pub req: ForgetUserRequest,
+    /// the user's posts as listed when the job was created
+    #[serde(default)]
+    pub posts: Vec<VideoPost>,
+}
+
+impl ForgetParams {
+    /// posts whose post service mapping is keyed by the user's canister
+    fn canister_posts(&self) -> Vec<&VideoPost> {
+        self.posts
+            .iter()
+            .filter(|post| Some(post.canister_id) == self.req.user_canister)
+            .collect()
+    }
+
+    fn skips(&self, step: Step) -> bool {
+        match step {
+            Step::HotOrNot | Step::Coin => false,
+            Step::PumpNDump => self.req.user_canister.is_none(),
+            Step::StreamVideos => self.posts.is_empty(),
+            Step::RedisMappings => self.canister_posts().is_empty(),
+        }
+    }
+}
+
+/// one step of a job, the job's params are read back from d1
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForgetStepMessage {
+    pub job_id: String,
+    pub step: Step,
+    /// posts handled by earlier messages of a paged step
+    #[serde(default)]
+    pub offset: usize,
+}
+
+fn creator_prefix(user_principal: &str) -> String {
+    format!("{CREATOR_PREFIX}{user_principal}/")
+}
+
+/// lists yral-upload-video's creator index, each key carries its post as metadata
+pub async fn user_posts(env: &Env, user_principal: &str) -> Result<Vec<VideoPost>> {
+    let kv = env.kv(VIDEO_POSTS_KV)?;
+    let prefix = creator_prefix(user_principal);
+    let mut posts = vec![];
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(prefix.clone());
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor);
+        }
+        let page = list
+            .execute()
+            .await
+            .map_err(|e| Error::RustError(e.to_string()))?;
+        for key in page.keys {
+            match key.metadata.map(serde_json::from_value::<VideoPost>) {
+                Some(Ok(post)) => posts.push(post),
+                _ => console_warn!("skipping creator index entry {} without a post", key.name),
+            }
+        }
+        cursor = page.cursor.filter(|_| !page.list_complete);
+        if cursor.is_none() {
+            return Ok(posts);
+        }
+    }
+}
+
+fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+fn opt_str(v: Option<&str>) -> JsValue {
+    v.map(JsValue::from).unwrap_or(JsValue::NULL)
+}
+
+/// records the job and its steps, then enqueues every step that isn't skipped
+pub async fn create_job(
+    env: &Env,
+    db: &D1Database,
+    job_id: &str,
+    user_principal: &str,
+    requested_by: &str,
+    params: &ForgetParams,
+    now: u64,
+) -> Result<()> {
+    let req = &params.req;
+    let user_canister = req.user_canister.map(|c| c.to_text());
+    let mut statements = vec![db
+        .prepare(
+            "INSERT INTO forget_jobs \
+            (id, user_principal, user_canister, requested_by, reason, params, created_at) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )
+        .bind(&[
+            job_id.into(),
+            user_principal.into(),
+            opt_str(user_canister.as_deref()),
+            requested_by.into(),
+            req.reason.as_str().into(),
+            serde_json::to_string(params)?.into(),
+            num(now),
+        ])?];
+    let mut pending = vec![];
+    for step in Step::ALL {
+        let status = if params.skips(step) {
+            StepStatus::Skipped
+        } else {
+            pending.push(step);
+            StepStatus::Pending
+        };
+        statements.push(
+            db.prepare(
+                "INSERT INTO forget_steps (job_id, step, status, updated_at) \
+                VALUES (?1, ?2, ?3, ?4)",
+            )
+            .bind(&[
+                job_id.into(),
+                step.as_str().into(),
+                status.as_str().into(),
+                num(now),
+            ])?,
+        );
+    }
+    db.batch(statements).await?;
+
+    let queue = env.queue(FORGET_USER_QUEUE)?;
+    for step in pending {
+        queue
+            .send(&ForgetStepMessage {
+                job_id: job_id.to_string(),
+                step,
+                offset: 0,
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct JobRow {
+    id: String,
+    user_principal: String,
+    user_canister: Option<String>,
+    requested_by: String,
+    reason: String,
+    params: String,
+    created_at: u64,
+    completed_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StepRow {
+    pub step: Step,
+    pub status: StepStatus,
+    pub attempts: u32,
+    pub detail: Option<String>,
+    /// unix millis
+    pub updated_at: u64,
+}
+
+/// completion report of `GET /forget_jobs/:job_id`
+#[derive(Serialize, Clone, Debug)]
+pub struct ForgetReport {
+    pub id: String,
+    pub user_principal: String,
+    pub user_canister: Option<String>,
+    pub requested_by: String,
+    pub reason: String,
+    pub created_at: u64,
+    /// set once every step succeeded or was skipped
+    pub completed_at: Option<u64>,
+    pub steps: Vec<StepRow>,
+}
+
+async fn job(db: &D1Database, job_id: &str) -> Result<Option<JobRow>> {
+    db.prepare("SELECT * FROM forget_jobs WHERE id = ?1")
+        .bind(&[job_id.into()])?
+        .first(None)
+        .await
+}
+
+pub async fn job_report(db: &D1Database, job_id: &str) -> Result<Option<ForgetReport>> {
+    let Some(job) = job(db, job_id).await? else {
+        return Ok(None);
+    };
+    let mut steps: Vec<StepRow> = db
+        .prepare(
+            "SELECT step, status, attempts, detail, updated_at FROM forget_steps WHERE job_id = ?1",
+        )
+        .bind(&[job_id.into()])?
+        .all()
+        .await?
+        .results()?;
+    steps.sort_by_key(|s| Step::ALL.iter().position(|step| *step == s.step));
+
+    Ok(Some(ForgetReport {
+        id: job.id,
+        user_principal: job.user_principal,
+        user_canister: job.user_canister,
+        requested_by: job.requested_by,
+        reason: job.reason,
+        created_at: job.created_at,
+        completed_at: job.completed_at,
+        steps,
+    }))
+}
+
+async fn record_step(
+    db: &D1Database,
+    job_id: &str,
+    step: Step,
+    status: StepStatus,
+    detail: &str,
+    now: u64,
+) -> Result<()> {
+    let statements = vec![
+        db.prepare(
+            "UPDATE forget_steps SET status = ?3, attempts = attempts + 1, detail = ?4, \
+            updated_at = ?5 WHERE job_id = ?1 AND step = ?2",
+        )
+        .bind(&[
+            job_id.into(),
+            step.as_str().into(),
+            status.as_str().into(),
+            detail.into(),
+            num(now),
+        ])?,
+        db.prepare(
+            "UPDATE forget_jobs SET completed_at = ?2 WHERE id = ?1 AND completed_at IS NULL \
+            AND NOT EXISTS (SELECT 1 FROM forget_steps WHERE job_id = ?1 \
+            AND status NOT IN ('succeeded', 'skipped'))",
+        )
+        .bind(&[job_id.into(), num(now)])?,
+    ];
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+async fn expect_ok(mut res: Response, what: &str) -> Result<()> {
+    if (200..300).contains(&res.status_code()) {
+        return Ok(());
+    }
+    let body = res.text().await.unwrap_or_default();
+    Err(Error::RustError(format!(
+        "{what} responded with {}: {body}",
+        res.status_code()
+    )))
+}
+
+/// drops the object from yral-backup's registry and deletes its snapshots, erased state
+/// mustn't come back with a restore
+async fn forget_backups(env: &Env, target: Target, key: &str) -> Result<()> {
+    let namespace = target.namespace();
+    let object_id = env
+        .durable_object(namespace)?
+        .id_from_name(key)?
+        .to_string();
+    let registry_key = backup::registry_key(namespace, &object_id);
+    env.kv(backup::BACKUP_REGISTRY)?
+        .delete(&registry_key)
+        .await
+        .map_err(|e| Error::RustError(e.to_string()))?;
+
+    let bucket = env.bucket("BACKUPS")?;
+    let prefix = format!("{registry_key}/");
+    loop {
+        // deleted keys drop out of the listing, the first page is always the next one
+        let page = bucket.list().prefix(prefix.clone()).execute().await?;
+        for object in page.objects() {
+            bucket.delete(object.key()).await?;
+        }
+        if !page.truncated() {
+            return Ok(());
+        }
+    }
+}
+
+async fn forget_object(env: &Env, trace: &TraceId, target: Target, key: &str) -> Result<String> {
+    let res = target
+        .client(env, trace)
+        .post(target.namespace(), key, "forget", &())
+        .await?;
+    expect_ok(res, target.as_str()).await?;
+    forget_backups(env, target, key).await?;
+
+    Ok(format!("erased {} state", target.as_str()))
+}
+
+/// mirrors yral-coin's own forget route, the export is archived before anything is erased
+async fn forget_coin(env: &Env, trace: &TraceId, user_principal: &str, now: u64) -> Result<String> {
+    let target = Target::Coin;
+    let client = target.client(env, trace);
+    let mut export_res = client
+        .get(target.namespace(), user_principal, "export")
+        .await?;
+    let export = export_res.text().await?;
+    if export_res.status_code() != 200 {
+        return Err(Error::RustError(format!(
+            "coin export responded with {}: {export}",
+            export_res.status_code()
+        )));
+    }
+
+    let archive_key = format!("{GDPR_ARCHIVE_PREFIX}/{user_principal}/{now}.json");
+    env.bucket("GDPR_ARCHIVE")?
+        .put(&archive_key, export.into_bytes())
+        .execute()
+        .await?;
+
+    let res = client
+        .post(target.namespace(), user_principal, "forget", &())
+        .await?;
+    expect_ok(res, target.as_str()).await?;
+    forget_backups(env, target, user_principal).await?;
+    env.kv("COIN_HOLDERS")?
+        .delete(user_principal)
+        .await
+        .map_err(|e| Error::RustError(e.to_string()))?;
+
+    Ok(format!("archived to {archive_key}"))
+}
+
+/// already deleted videos count as deleted, so retries pick up where they failed
+///
+/// the video's post mapping and index entry go with it
+async fn delete_stream_videos(env: &Env, user_principal: &str, posts: &[&VideoPost]) -> Result<()> {
+    let account_id = env.secret("CLOUDFLARE_STREAM_ACCOUNT_ID")?.to_string();
+    let api_token = env.secret("CLOUDFLARE_STREAM_API_TOKEN")?.to_string();
+    let auth = format!("Bearer {api_token}");
+    let kv = env.kv(VIDEO_POSTS_KV)?;
+    let prefix = creator_prefix(user_principal);
+
+    for post in posts {
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Delete)
+            .header("Authorization", &auth)?
+            .timeout(UPSTREAM_TIMEOUT);
+        let res = init
+            .fetch(&format!(
+                "{STREAM_API_BASE}/{account_id}/stream/{}",
+                post.video_uid
+            ))
+            .await?;
+        if res.status_code() != 404 {
+            expect_ok(res, "cloudflare stream").await?;
+        }
+        for key in [
+            post.video_uid.clone(),
+            format!("{prefix}{}:{}", post.canister_id, post.post_id),
+        ] {
+            kv.delete(&key)
+                .await
+                .map_err(|e| Error::RustError(e.to_string()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// keys are written by yral-upload-video as `{user_canister}:{post_id}`
+async fn delete_redis_mappings(env: &Env, user_canister: &str, posts: &[&VideoPost]) -> Result<()> {
+    let base_url = env
+        .secret("SERVICE_CANISTER_POST_MAPPING_REDIS_REST_ENDPOINT")?
+        .to_string();
+    let auth_token = env
+        .secret("SERVICE_CANISTER_POST_MAPPING_REDIS_REST_TOKEN")?
+        .to_string();
+    let auth = format!("Bearer {auth_token}");
+
+    for post in posts {
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Post)
+            .header("Authorization", &auth)?
+            .timeout(UPSTREAM_TIMEOUT);
+        let res = init
+            .fetch(&format!(
+                "{}/del/{user_canister}:{}",
+                base_url.trim_end_matches('/'),
+                post.post_id
+            ))
+            .await?;
+        expect_ok(res, "post mapping redis").await?;
+    }
+
+    Ok(())
+}
+
+/// runs one page of a step over the job's posts, the next page is sent as its own message
+///
+/// returns the step's status and detail once the page is done
+async fn run_paged(
+    env: &Env,
+    msg: &ForgetStepMessage,
+    posts: &[&VideoPost],
+    what: &str,
+    page: impl Future<Output = Result<()>>,
+) -> Result<(StepStatus, String)> {
+    page.await?;
+    let done = (msg.offset + POSTS_PER_MESSAGE).min(posts.len());
+    if done == posts.len() {
+        return Ok((StepStatus::Succeeded, format!("deleted {done} {what}")));
+    }
+
+    env.queue(FORGET_USER_QUEUE)?
+        .send(&ForgetStepMessage {
+            job_id: msg.job_id.clone(),
+            step: msg.step,
+            offset: done,
+        })
+        .await?;
+    Ok((
+        StepStatus::Pending,
+        format!("deleted {done} of {} {what}", posts.len()),
+    ))
+}
+
+fn page<'a>(posts: &'a [&'a VideoPost], offset: usize) -> &'a [&'a VideoPost] {
+    let start = offset.min(posts.len());
+    &posts[start..(start + POSTS_PER_MESSAGE).min(posts.len())]
+}
+
+/// runs one step and records its outcome, the job completes with its last step
+///
+/// errors are recorded on the step before they're returned, the caller retries the message
+pub async fn run_step(
+    env: &Env,
+    db: &D1Database,
+    trace: &TraceId,
+    msg: &ForgetStepMessage,
+    now: u64,
+) -> Result<()> {
+    let Some(job) = job(db, &msg.job_id).await? else {
+        console_warn!(
+            "dropping step {} of unknown forget job {}",
+            msg.step.as_str(),
+            msg.job_id
+        );
+        return Ok(());
+    };
+    let params: ForgetParams = serde_json::from_str(&job.params)?;
+    let user_canister = job.user_canister.as_deref().unwrap_or_default();
+    let posts: Vec<_> = params.posts.iter().collect();
+    let canister_posts = params.canister_posts();
+
+    let succeeded = |detail| (StepStatus::Succeeded, detail);
+    let res = match msg.step {
+        Step::HotOrNot => forget_object(env, trace, Target::HotOrNot, &job.user_principal)
+            .await
+            .map(succeeded),
+        Step::PumpNDump => forget_object(env, trace, Target::PumpNDump, user_canister)
+            .await
+            .map(succeeded),
+        Step::Coin => forget_coin(env, trace, &job.user_principal, now)
+            .await
+            .map(succeeded),
+        Step::StreamVideos => {
+            let videos = page(&posts, msg.offset);
+            let deleted = delete_stream_videos(env, &job.user_principal, videos);
+            run_paged(env, msg, &posts, "videos", deleted).await
+        }
+        Step::RedisMappings => {
+            let mappings = page(&canister_posts, msg.offset);
+            let deleted = delete_redis_mappings(env, user_canister, mappings);
+            run_paged(env, msg, &canister_posts, "post mappings", deleted).await
+        }
+    };
+    let (status, detail) = match &res {
+        Ok((status, detail)) => (*status, detail.clone()),
+        Err(e) => (StepStatus::Failed, e.to_string()),
+    };
+    record_step(db, &msg.job_id, msg.step, status, &detail, now).await?;
+
+    res.map(|_| ())
+}
@@ -0,0 +1,42 @@
+use std::result::Result as StdResult;
+
+use serde_json::json;
+use worker::Request;
+use worker_utils::{
+    api_error::ApiError,
+    jwt::{authorize, AdminClaims, JwtKeys, Role},
+};
+
+pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
+-----END PUBLIC KEY-----";
+
+/// second admin's token, for operations under dual control
+pub const COSIGNER_HEADER: &str = "X-Cosigner-Authorization";
+
+/// verifies the co-signer token in `COSIGNER_HEADER`, it must belong to another finance admin and
+/// approve exactly `operation`, so it can't be replayed for a different one
+pub async fn cosign(
+    keys: &JwtKeys<'_>,
+    req: &Request,
+    admin: &AdminClaims,
+    operation: &str,
+) -> StdResult<AdminClaims, (ApiError, u16)> {
+    let cosigner = authorize(keys, req, COSIGNER_HEADER, Role::Finance).await?;
+    if cosigner.sub == admin.sub {
+        return Err((
+            ApiError::new("Forbidden", "co-signer must be a different admin"),
+            403,
+        ));
+    }
+    if cosigner.approves.as_deref() != Some(operation) {
+        let err = ApiError::new(
+            "Forbidden",
+            "co-signer token doesn't approve this operation",
+        )
+        .with_details(json!({ "approves": operation }));
+        return Err((err, 403));
+    }
+
+    Ok(cosigner)
+}
@@ -0,0 +1,603 @@
+mod audit;
+mod forget;
+mod jwt;
+mod migrate;
+mod ops;
+
+use std::future::Future;
+
+use audit::{query_audit_log, AuditQuery, AuditRecord, Phase};
+use forget::{
+    create_job, job_report, run_step, user_posts, ForgetParams, ForgetStepMessage,
+    ForgetUserRequest, FORGET_USER_QUEUE,
+};
+use jwt::{cosign, JWT_PUBKEY};
+use migrate::{
+    add_users, create_migration, migration, migration_report, resume, run_conversion,
+    ConversionMessage, MigrationRequest, MigrationUsersRequest, BALANCE_MIGRATION_QUEUE,
+    MAX_MIGRATION_USERS,
+};
+use ops::{
+    adjust_balance, force_settle, inspect, invalid_message, migration_status, needs_cosigner,
+    requeue, AdjustRequest, RequeueRequest, Target, MAX_REQUEUE_MESSAGES, REQUEUE_QUEUES,
+};
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{
+    api_error::{error_resp, ApiError},
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::{authorize, AdminClaims, JwtKeys, Role},
+    metrics::Metrics,
+    principals,
+    time::now_millis,
+    trace::{with_trace, TraceId},
+};
+
+const ADMIN_AUDIT_DB: &str = "ADMIN_AUDIT_DB";
+
+const FORGET_USER_QUEUE_NAME: &str = "yral-forget-user";
+const BALANCE_MIGRATION_QUEUE_NAME: &str = "yral-balance-migration";
+
+const DEFAULT_AUDIT_LIMIT: u32 = 100;
+const MAX_AUDIT_LIMIT: u32 = 500;
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-admin",
+    &[
+        Dependency::D1(ADMIN_AUDIT_DB),
+        Dependency::DurableObject("USER_HON_GAME_STATE"),
+        Dependency::DurableObject("USER_EPHEMERAL_STATE"),
+        Dependency::DurableObject("USER_YRAL_COIN_STATE"),
+        Dependency::Queue(FORGET_USER_QUEUE),
+        Dependency::Queue(BALANCE_MIGRATION_QUEUE),
+        Dependency::Bucket("GDPR_ARCHIVE"),
+        Dependency::Kv("COIN_HOLDERS"),
+        Dependency::Secret("CLOUDFLARE_STREAM_API_TOKEN"),
+        Dependency::Secret("SERVICE_CANISTER_POST_MAPPING_REDIS_REST_TOKEN"),
+    ],
+);
+
+macro_rules! admin {
+    ($ctx:expr, $req:expr, $role:expr) => {
+        match authorize(
+            &JwtKeys::from_env(&$ctx.env, JWT_PUBKEY),
+            &$req,
+            "Authorization",
+            $role,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        }
+    };
+}
+
+/// runs `op` between two audit rows, nothing runs unless the first one is written
+async fn audited<F>(
+    env: &Env,
+    trace: &TraceId,
+    admin: &AdminClaims,
+    action: &'static str,
+    target: &str,
+    params: Value,
+    op: F,
+) -> Result<Response>
+where
+    F: Future<Output = Result<Response>>,
+{
+    let db = env.d1(ADMIN_AUDIT_DB)?;
+    let op_id = uuid::Uuid::new_v4().to_string();
+    let record = AuditRecord {
+        op_id: &op_id,
+        admin,
+        action,
+        target,
+        params: &params,
+        trace_id: trace.as_str(),
+    };
+    record
+        .append(&db, Phase::Requested, None, None, now_millis())
+        .await?;
+
+    let res = op.await;
+    let (phase, status, error) = match &res {
+        Ok(res) if res.status_code() < 400 => (Phase::Succeeded, Some(res.status_code()), None),
+        Ok(res) => (Phase::Failed, Some(res.status_code()), None),
+        Err(e) => (Phase::Failed, None, Some(e.to_string())),
+    };
+    if let Err(e) = record
+        .append(&db, phase, status, error.as_deref(), now_millis())
+        .await
+    {
+        console_error!("failed to audit the outcome of {action} {op_id}: {e}");
+    }
+    Metrics::new(env, "yral-admin").counter("admin_ops", &[action, phase.as_str()]);
+
+    let mut res = res?;
+    res.headers_mut().set("X-Admin-Op-Id", &op_id)?;
+    Ok(res)
+}
+
+async fn inspect_state(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Viewer);
+    let Some(target) = ctx
+        .param("target")
+        .map(String::as_str)
+        .and_then(Target::from_param)
+    else {
+        return ApiError::new(
+            "UnknownTarget",
+            "target must be hot-or-not, pump-n-dump or coin",
+        )
+        .into_response(400);
+    };
+    // pump-n-dump is keyed by the user's canister, the others by the user's principal
+    let key = principals!(ctx, "key").to_text();
+    let trace = TraceId::from_request(&req);
+
+    let target_name = format!("{}/{key}", target.as_str());
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "inspect",
+        &target_name,
+        json!({}),
+        async { Response::from_json(&inspect(&ctx.env, &trace, target, &key).await?) },
+    )
+    .await
+}
+
+async fn requeue_messages(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let Some(queue) = ctx.param("queue").and_then(|q| {
+        REQUEUE_QUEUES
+            .iter()
+            .copied()
+            .find(|allowed| *allowed == q.as_str())
+    }) else {
+        return ApiError::new("UnknownQueue", "queue can't be requeued on")
+            .with_details(json!({ "allowed": REQUEUE_QUEUES }))
+            .into_response(400);
+    };
+    let body: RequeueRequest = json_body!(req);
+    let trace = TraceId::from_request(&req);
+    if body.messages.is_empty() || body.messages.len() > MAX_REQUEUE_MESSAGES {
+        return error_resp(
+            format!("between 1 and {MAX_REQUEUE_MESSAGES} messages can be requeued at once"),
+            400,
+        );
+    }
+    let invalid: Vec<_> = body
+        .messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| invalid_message(queue, m).map(|e| json!({ "index": i, "error": e })))
+        .collect();
+    if !invalid.is_empty() {
+        return ApiError::new(
+            "InvalidMessages",
+            format!("messages don't match the {queue} queue"),
+        )
+        .with_details(json!({ "invalid": invalid }))
+        .into_response(400);
+    }
+
+    let params = json!({ "count": body.messages.len() });
+    audited(&ctx.env, &trace, &admin, "requeue", queue, params, async {
+        let requeued = requeue(&ctx.env, queue, &body.messages).await?;
+        Response::from_json(&json!({ "requeued": requeued }))
+    })
+    .await
+}
+
+async fn settle_user(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let user_canister = principals!(ctx, "user_canister").to_text();
+    let trace = TraceId::from_request(&req);
+
+    let target = format!("pump-n-dump/{user_canister}");
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "force_settle",
+        &target,
+        json!({}),
+        async { force_settle(&ctx.env, &trace, &user_canister).await },
+    )
+    .await
+}
+
+/// adjustments above the dual control threshold need a second finance admin in `COSIGNER_HEADER`
+/// whose token approves `AdjustRequest::approval`
+async fn adjust_user_balance(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Finance);
+    let user_principal = principals!(ctx, "user_principal").to_text();
+    let body: AdjustRequest = json_body!(req);
+    if body.reason.trim().is_empty() {
+        return error_resp("reason is required", 400);
+    }
+    let Some(delta) = body.delta() else {
+        return error_resp("delta must be an integer", 400);
+    };
+
+    let mut approvers = vec![admin.sub.clone()];
+    if needs_cosigner(&ctx.env, &delta) {
+        let cosigner = match cosign(
+            &JwtKeys::from_env(&ctx.env, JWT_PUBKEY),
+            &req,
+            &admin,
+            &body.approval(&user_principal, &delta),
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        };
+        approvers.push(cosigner.sub);
+    }
+    let trace = TraceId::from_request(&req);
+
+    let target = format!("coin/{user_principal}");
+    let params = json!({
+        "adjustment_id": body.adjustment_id,
+        "delta": body.delta,
+        "reason": body.reason,
+        "approvers": approvers,
+    });
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "adjust_balance",
+        &target,
+        params,
+        async { adjust_balance(&ctx.env, &trace, &user_principal, &body, &approvers).await },
+    )
+    .await
+}
+
+async fn user_migration_status(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let user_principal = principals!(ctx, "user_principal").to_text();
+    let trace = TraceId::from_request(&req);
+
+    migration_status(&ctx.env, &trace, &user_principal).await
+}
+
+/// erases the user everywhere, each step runs from the forget queue and is tracked in d1
+async fn forget_user(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Privacy);
+    let user_principal = principals!(ctx, "user_principal").to_text();
+    let body: ForgetUserRequest = json_body!(req);
+    if body.reason.trim().is_empty() {
+        return error_resp("reason is required", 400);
+    }
+    let trace = TraceId::from_request(&req);
+    // listed here rather than taken from the caller, only the user's own posts are erased
+    let params = ForgetParams {
+        posts: user_posts(&ctx.env, &user_principal).await?,
+        req: body,
+    };
+
+    let job_id = uuid::Uuid::new_v4().to_string();
+    let target = format!("user/{user_principal}");
+    let audit_params = json!({
+        "job_id": job_id,
+        "reason": params.req.reason,
+        "user_canister": params.req.user_canister.map(|c| c.to_text()),
+        "posts": params.posts.len(),
+    });
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "forget_user",
+        &target,
+        audit_params,
+        async {
+            let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+            create_job(
+                &ctx.env,
+                &db,
+                &job_id,
+                &user_principal,
+                &admin.sub,
+                &params,
+                now_millis(),
+            )
+            .await?;
+            let report = job_report(&db, &job_id).await?;
+            Ok(Response::from_json(&report)?.with_status(202))
+        },
+    )
+    .await
+}
+
+async fn forget_job(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let Some(job_id) = ctx.param("job_id") else {
+        return error_resp("job_id is required", 400);
+    };
+
+    let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+    match job_report(&db, job_id).await? {
+        Some(report) => Response::from_json(&report),
+        None => error_resp("forget job not found", 404),
+    }
+}
+
+/// live migrations move every listed balance, they need a second finance admin in
+/// `COSIGNER_HEADER` whose token approves `MigrationRequest::approval`
+async fn start_migration(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Finance);
+    let body: MigrationRequest = json_body!(req);
+    if let Some(msg) = body.invalid() {
+        return error_resp(msg, 400);
+    }
+    let migration_id = body
+        .migration_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let mut approvers = vec![admin.sub.clone()];
+    if !body.dry_run {
+        let cosigner = match cosign(
+            &JwtKeys::from_env(&ctx.env, JWT_PUBKEY),
+            &req,
+            &admin,
+            &body.approval(&migration_id),
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        };
+        approvers.push(cosigner.sub);
+    }
+    let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+    if migration(&db, &migration_id).await?.is_some() {
+        return error_resp("migration already exists", 409);
+    }
+    let trace = TraceId::from_request(&req);
+
+    let target = format!("migration/{migration_id}");
+    let params = json!({
+        "source": body.source,
+        "destination": body.destination,
+        "rate": body.rate,
+        "dry_run": body.dry_run,
+        "reason": body.reason,
+        "approvers": approvers,
+    });
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "create_migration",
+        &target,
+        params,
+        async {
+            let migration =
+                create_migration(&db, &migration_id, &admin.sub, &body, now_millis()).await?;
+            Ok(Response::from_json(&migration)?.with_status(201))
+        },
+    )
+    .await
+}
+
+/// adds users to a migration in chunks, each is converted from the migration queue
+async fn add_migration_users(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Finance);
+    let Some(migration_id) = ctx.param("migration_id").cloned() else {
+        return error_resp("migration_id is required", 400);
+    };
+    let body: MigrationUsersRequest = json_body!(req);
+    if body.users.is_empty() || body.users.len() > MAX_MIGRATION_USERS {
+        return error_resp(
+            format!("between 1 and {MAX_MIGRATION_USERS} users can be added at once"),
+            400,
+        );
+    }
+    let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+    if migration(&db, &migration_id).await?.is_none() {
+        return error_resp("migration not found", 404);
+    }
+    let trace = TraceId::from_request(&req);
+
+    let target = format!("migration/{migration_id}");
+    let params = json!({ "users": body.users.len() });
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "add_migration_users",
+        &target,
+        params,
+        async {
+            let added = add_users(&ctx.env, &db, &migration_id, &body.users, now_millis()).await?;
+            Ok(Response::from_json(&added)?.with_status(202))
+        },
+    )
+    .await
+}
+
+/// enqueues every unfinished user again, once their messages were dead lettered
+async fn resume_migration(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Finance);
+    let Some(migration_id) = ctx.param("migration_id").cloned() else {
+        return error_resp("migration_id is required", 400);
+    };
+    let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+    if migration(&db, &migration_id).await?.is_none() {
+        return error_resp("migration not found", 404);
+    }
+    let trace = TraceId::from_request(&req);
+
+    let target = format!("migration/{migration_id}");
+    audited(
+        &ctx.env,
+        &trace,
+        &admin,
+        "resume_migration",
+        &target,
+        json!({}),
+        async {
+            let resumed = resume(&ctx.env, &db, &migration_id).await?;
+            Ok(Response::from_json(&json!({ "resumed": resumed }))?.with_status(202))
+        },
+    )
+    .await
+}
+
+async fn migration_reconciliation(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let Some(migration_id) = ctx.param("migration_id") else {
+        return error_resp("migration_id is required", 400);
+    };
+
+    let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+    match migration_report(&db, migration_id).await? {
+        Some(report) => Response::from_json(&report),
+        None => error_resp("migration not found", 404),
+    }
+}
+
+async fn audit_log(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let Ok(query) = req.query::<AuditQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .min(MAX_AUDIT_LIMIT);
+
+    let db = ctx.env.d1(ADMIN_AUDIT_DB)?;
+    Response::from_json(&query_audit_log(&db, &query, limit).await?)
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let (req, trace) = with_trace(req)?;
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+    // every route is an admin route, the maintenance switch never blocks them
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/state/:target/:key", inspect_state)
+        .post_async("/requeue/:queue", requeue_messages)
+        .post_async("/settle/:user_canister", settle_user)
+        .post_async("/adjust/:user_principal", adjust_user_balance)
+        .get_async("/migration_status/:user_principal", user_migration_status)
+        .get_async("/audit", audit_log)
+        .post_async("/forget_user/:user_principal", forget_user)
+        .get_async("/forget_jobs/:job_id", forget_job)
+        .post_async("/migrations", start_migration)
+        .get_async("/migrations/:migration_id", migration_reconciliation)
+        .post_async("/migrations/:migration_id/users", add_migration_users)
+        .post_async("/migrations/:migration_id/resume", resume_migration)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), trace.tag(res)?)
+}
+
+/// runs forget steps one by one, a failed step is retried without holding back the others
+async fn run_forget_batch(batch: MessageBatch<Value>, env: &Env) -> Result<()> {
+    let db = env.d1(ADMIN_AUDIT_DB)?;
+    let metrics = Metrics::new(env, "yral-admin");
+    for message in batch.messages()? {
+        let msg = match serde_json::from_value::<ForgetStepMessage>(message.body().clone()) {
+            Ok(msg) => msg,
+            Err(e) => {
+                console_error!("dropping malformed forget step {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+        let trace = TraceId::new();
+        match run_step(env, &db, &trace, &msg, now_millis()).await {
+            Ok(()) => {
+                metrics.counter("forget_steps", &[msg.step.as_str(), "succeeded"]);
+                message.ack();
+            }
+            Err(e) => {
+                console_error!(
+                    "forget step {} of job {} failed: {e}",
+                    msg.step.as_str(),
+                    msg.job_id
+                );
+                metrics.counter("forget_steps", &[msg.step.as_str(), "failed"]);
+                message.retry();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// converts users one by one, a failed user is retried from where they got to
+async fn run_migration_batch(batch: MessageBatch<Value>, env: &Env) -> Result<()> {
+    let db = env.d1(ADMIN_AUDIT_DB)?;
+    let metrics = Metrics::new(env, "yral-admin");
+    for message in batch.messages()? {
+        let msg = match serde_json::from_value::<ConversionMessage>(message.body().clone()) {
+            Ok(msg) => msg,
+            Err(e) => {
+                console_error!(
+                    "dropping malformed balance conversion {}: {e}",
+                    message.id()
+                );
+                message.ack();
+                continue;
+            }
+        };
+        let trace = TraceId::new();
+        match run_conversion(env, &db, &trace, &msg, now_millis()).await {
+            Ok(status) => {
+                metrics.counter("balance_conversions", &[status.as_str()]);
+                message.ack();
+            }
+            Err(e) => {
+                console_error!(
+                    "converting {} in migration {} failed: {e}",
+                    msg.user_principal,
+                    msg.migration_id
+                );
+                metrics.counter("balance_conversions", &["failed"]);
+                message.retry();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    match batch.queue().as_str() {
+        FORGET_USER_QUEUE_NAME => run_forget_batch(batch, &env).await,
+        BALANCE_MIGRATION_QUEUE_NAME => run_migration_batch(batch, &env).await,
+        queue => {
+            console_error!("unexpected queue {queue}");
+            batch.retry_all();
+            Ok(())
+        }
+    }
+}
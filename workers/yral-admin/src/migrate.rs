@@ -0,0 +1,843 @@
+//! bulk balance migrations between economies, one queue message per user so each is retried on
+//! its own
+//!
+//! a user is debited before they're credited, the conversion row remembers how far they got
+
+use std::collections::BTreeMap;
+
+use candid::{Nat, Principal};
+use futures::{stream, StreamExt, TryStreamExt};
+use hon_worker_common::SatsBalanceUpdateRequest;
+use num_bigint::{BigInt, BigUint};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::{environment::profile_config, trace::TraceId};
+use yral_metadata_client::MetadataClient;
+
+use crate::ops::Target;
+
+pub const BALANCE_MIGRATION_QUEUE: &str = "BALANCE_MIGRATION";
+
+pub const MAX_MIGRATION_USERS: usize = 500;
+
+/// cloudflare's limit on messages per `send_batch`
+const SEND_BATCH_SIZE: usize = 100;
+const REPORT_PAGE_SIZE: u32 = 1000;
+/// users listed by the report, the counts cover everyone
+const MAX_REPORTED_USERS: usize = 100;
+/// concurrent metadata lookups while users are added
+const CANISTER_LOOKUPS: usize = 16;
+
+/// economies balances are moved out of
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// legacy gDOLLR cents in yral-pump-n-dump, retired per user once migrated
+    Cents,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Cents => "cents",
+        }
+    }
+}
+
+/// economies balances are moved into
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Destination {
+    /// SATS in yral-hot-or-not
+    Sats,
+    /// YRAL in yral-coin
+    Yral,
+}
+
+impl Destination {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sats => "sats",
+            Self::Yral => "yral",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionStatus {
+    Pending,
+    /// dry run, `destination_amount` is what the user would get
+    Planned,
+    /// debited, not credited yet
+    Debited,
+    Credited,
+    /// nothing to move
+    Skipped,
+    /// the last attempt failed before the debit, the queue retries it until it's dead lettered
+    Failed,
+}
+
+impl ConversionStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Planned => "planned",
+            Self::Debited => "debited",
+            Self::Credited => "credited",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn is_final(self) -> bool {
+        matches!(self, Self::Planned | Self::Credited | Self::Skipped)
+    }
+}
+
+/// destination base units per source base unit, e.g. 10 SATS per cent is `10 / 1_000_000`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Rate {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Rate {
+    /// rounds down, the remainder is reported as rounding
+    pub fn convert(self, amount: &BigUint) -> BigUint {
+        amount * self.numerator / self.denominator
+    }
+}
+
+/// body of `POST /migrations`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MigrationRequest {
+    /// picked by the caller so the co-signer can approve it up front, required unless it's a dry
+    /// run
+    #[serde(default)]
+    pub migration_id: Option<String>,
+    pub source: Source,
+    pub destination: Destination,
+    pub rate: Rate,
+    /// balances are read and converted, nothing is moved
+    pub dry_run: bool,
+    pub reason: String,
+}
+
+impl MigrationRequest {
+    pub fn invalid(&self) -> Option<&'static str> {
+        if self.reason.trim().is_empty() {
+            return Some("reason is required");
+        }
+        if self.rate.numerator == 0 || self.rate.denominator == 0 {
+            return Some("rate must be positive");
+        }
+        match &self.migration_id {
+            Some(id) if uuid::Uuid::parse_str(id).is_err() => {
+                return Some("migration_id must be a uuid")
+            }
+            None if !self.dry_run => return Some("migration_id is required for a live migration"),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// what the co-signer's token must approve, `migration:{id}:{hex sha256}` of
+    /// `{source}|{destination}|{numerator}/{denominator}|{reason}`
+    pub fn approval(&self, migration_id: &str) -> String {
+        let payload = format!(
+            "{}|{}|{}/{}|{}",
+            self.source.as_str(),
+            self.destination.as_str(),
+            self.rate.numerator,
+            self.rate.denominator,
+            self.reason
+        );
+        let hash = hex::encode(Sha256::digest(payload.as_bytes()));
+
+        format!("migration:{migration_id}:{hash}")
+    }
+}
+
+/// the canister cents are keyed by is looked up from the principal, never taken from the caller
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MigrationUser {
+    pub user_principal: Principal,
+}
+
+/// body of `POST /migrations/:migration_id/users`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MigrationUsersRequest {
+    pub users: Vec<MigrationUser>,
+}
+
+/// one user of a migration
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConversionMessage {
+    pub migration_id: String,
+    pub user_principal: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MigrationRow {
+    pub id: String,
+    pub source: Source,
+    pub destination: Destination,
+    pub rate_numerator: u64,
+    pub rate_denominator: u64,
+    /// 1 for a dry run
+    pub dry_run: u32,
+    pub reason: String,
+    pub requested_by: String,
+    /// unix millis
+    pub created_at: u64,
+}
+
+impl MigrationRow {
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run != 0
+    }
+
+    fn rate(&self) -> Rate {
+        Rate {
+            numerator: self.rate_numerator,
+            denominator: self.rate_denominator,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ConversionRow {
+    user_canister: String,
+    status: ConversionStatus,
+    source_amount: Option<String>,
+}
+
+fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+fn opt_str(v: Option<&str>) -> JsValue {
+    v.map(JsValue::from).unwrap_or(JsValue::NULL)
+}
+
+fn parse_amount(amount: &str) -> Result<BigUint> {
+    amount
+        .parse()
+        .map_err(|e| Error::RustError(format!("invalid amount {amount}: {e}")))
+}
+
+pub async fn create_migration(
+    db: &D1Database,
+    migration_id: &str,
+    requested_by: &str,
+    req: &MigrationRequest,
+    now: u64,
+) -> Result<MigrationRow> {
+    db.prepare(
+        "INSERT INTO balance_migrations \
+        (id, source, destination, rate_numerator, rate_denominator, dry_run, reason, requested_by, created_at) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) RETURNING *",
+    )
+    .bind(&[
+        migration_id.into(),
+        req.source.as_str().into(),
+        req.destination.as_str().into(),
+        num(req.rate.numerator),
+        num(req.rate.denominator),
+        num(req.dry_run as u64),
+        req.reason.as_str().into(),
+        requested_by.into(),
+        num(now),
+    ])?
+    .first(None)
+    .await?
+    .ok_or_else(|| Error::RustError("migration wasn't created".into()))
+}
+
+pub async fn migration(db: &D1Database, migration_id: &str) -> Result<Option<MigrationRow>> {
+    db.prepare("SELECT * FROM balance_migrations WHERE id = ?1")
+        .bind(&[migration_id.into()])?
+        .first(None)
+        .await
+}
+
+async fn enqueue(env: &Env, migration_id: &str, users: Vec<String>) -> Result<()> {
+    let queue = env.queue(BALANCE_MIGRATION_QUEUE)?;
+    for chunk in users.chunks(SEND_BATCH_SIZE) {
+        queue
+            .send_batch(chunk.iter().map(|user_principal| ConversionMessage {
+                migration_id: migration_id.to_string(),
+                user_principal: user_principal.clone(),
+            }))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// users added to a migration, `unresolved` have no canister in yral-metadata and are left out
+#[derive(Serialize, Clone, Debug)]
+pub struct AddedUsers {
+    pub added: usize,
+    pub unresolved: Vec<Principal>,
+}
+
+async fn user_canisters(
+    env: &Env,
+    users: &[MigrationUser],
+) -> Result<Vec<(Principal, Option<Principal>)>> {
+    let metadata: MetadataClient<false> = MetadataClient::with_base_url(
+        profile_config(env)
+            .metadata_server_url
+            .parse()
+            .map_err(|e| Error::RustError(format!("invalid metadata url: {e}")))?,
+    );
+    let metadata = &metadata;
+
+    stream::iter(users.iter().map(|user| user.user_principal))
+        .map(|user_principal| async move {
+            let meta = metadata
+                .get_user_metadata_v2(user_principal.to_text())
+                .await
+                .map_err(|e| Error::RustError(format!("metadata of {user_principal}: {e}")))?;
+            Ok::<_, Error>((user_principal, meta.map(|m| m.user_canister_id)))
+        })
+        .buffered(CANISTER_LOOKUPS)
+        .try_collect()
+        .await
+}
+
+/// records the users and enqueues them, users already in the migration are left alone
+pub async fn add_users(
+    env: &Env,
+    db: &D1Database,
+    migration_id: &str,
+    users: &[MigrationUser],
+    now: u64,
+) -> Result<AddedUsers> {
+    let mut unresolved = vec![];
+    let mut resolved = vec![];
+    for (user_principal, user_canister) in user_canisters(env, users).await? {
+        match user_canister {
+            Some(user_canister) => resolved.push((user_principal, user_canister)),
+            None => unresolved.push(user_principal),
+        }
+    }
+    if resolved.is_empty() {
+        return Ok(AddedUsers {
+            added: 0,
+            unresolved,
+        });
+    }
+
+    let statements = resolved
+        .iter()
+        .map(|(user_principal, user_canister)| {
+            db.prepare(
+                "INSERT INTO balance_conversions \
+                (migration_id, user_principal, user_canister, status, updated_at) \
+                VALUES (?1, ?2, ?3, 'pending', ?4) \
+                ON CONFLICT DO NOTHING RETURNING user_principal",
+            )
+            .bind(&[
+                migration_id.into(),
+                user_principal.to_text().into(),
+                user_canister.to_text().into(),
+                num(now),
+            ])
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut added = vec![];
+    for res in db.batch(statements).await? {
+        #[derive(Deserialize)]
+        struct Added {
+            user_principal: String,
+        }
+        added.extend(
+            res.results::<Added>()?
+                .into_iter()
+                .map(|a| a.user_principal),
+        );
+    }
+    let count = added.len();
+    enqueue(env, migration_id, added).await?;
+
+    Ok(AddedUsers {
+        added: count,
+        unresolved,
+    })
+}
+
+/// enqueues every user that isn't done, for users whose messages were dead lettered
+pub async fn resume(env: &Env, db: &D1Database, migration_id: &str) -> Result<usize> {
+    #[derive(Deserialize)]
+    struct Unfinished {
+        seq: u64,
+        user_principal: String,
+    }
+
+    let mut after = 0;
+    let mut count = 0;
+    loop {
+        let page: Vec<Unfinished> = db
+            .prepare(
+                "SELECT rowid AS seq, user_principal FROM balance_conversions \
+                WHERE migration_id = ?1 AND status IN ('pending', 'debited', 'failed') \
+                AND rowid > ?2 ORDER BY rowid LIMIT ?3",
+            )
+            .bind(&[
+                migration_id.into(),
+                num(after),
+                num(REPORT_PAGE_SIZE as u64),
+            ])?
+            .all()
+            .await?
+            .results()?;
+        let Some(last) = page.last() else {
+            return Ok(count);
+        };
+        after = last.seq;
+        count += page.len();
+        enqueue(
+            env,
+            migration_id,
+            page.into_iter().map(|u| u.user_principal).collect(),
+        )
+        .await?;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record(
+    db: &D1Database,
+    msg: &ConversionMessage,
+    status: ConversionStatus,
+    source_amount: Option<&BigUint>,
+    destination_amount: Option<&BigUint>,
+    detail: Option<&str>,
+    now: u64,
+) -> Result<()> {
+    let source_amount = source_amount.map(|a| a.to_string());
+    let destination_amount = destination_amount.map(|a| a.to_string());
+    db.prepare(
+        "UPDATE balance_conversions SET status = ?3, \
+        source_amount = COALESCE(?4, source_amount), \
+        destination_amount = COALESCE(?5, destination_amount), \
+        attempts = attempts + 1, detail = ?6, updated_at = ?7 \
+        WHERE migration_id = ?1 AND user_principal = ?2",
+    )
+    .bind(&[
+        msg.migration_id.as_str().into(),
+        msg.user_principal.as_str().into(),
+        status.as_str().into(),
+        opt_str(source_amount.as_deref()),
+        opt_str(destination_amount.as_deref()),
+        opt_str(detail),
+        num(now),
+    ])?
+    .run()
+    .await?;
+
+    Ok(())
+}
+
+async fn expect_ok(mut res: Response, what: &str) -> Result<Response> {
+    if (200..300).contains(&res.status_code()) {
+        return Ok(res);
+    }
+    let body = res.text().await.unwrap_or_default();
+    Err(Error::RustError(format!(
+        "{what} responded with {}: {body}",
+        res.status_code()
+    )))
+}
+
+#[derive(Deserialize)]
+struct CentsBalance {
+    balance: Nat,
+}
+
+async fn read_cents(env: &Env, trace: &TraceId, user_canister: &str) -> Result<BigUint> {
+    let target = Target::PumpNDump;
+    let res = target
+        .client(env, trace)
+        .get(
+            target.namespace(),
+            user_canister,
+            &format!("balance_v2/{user_canister}"),
+        )
+        .await?;
+    let balance: CentsBalance = expect_ok(res, "pump-n-dump balance").await?.json().await?;
+
+    Ok(balance.balance.0)
+}
+
+/// pump-n-dump's `MigrateOutReq`
+#[derive(Serialize)]
+struct MigrateOutReq<'a> {
+    user_canister: &'a str,
+    migration_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct MigratedOut {
+    amount: Nat,
+}
+
+/// debits the whole balance, pump-n-dump remembers the migration id so a retry debits once
+async fn debit_cents(
+    env: &Env,
+    trace: &TraceId,
+    user_canister: &str,
+    migration_id: &str,
+) -> Result<BigUint> {
+    let target = Target::PumpNDump;
+    let res = target
+        .client(env, trace)
+        .post(
+            target.namespace(),
+            user_canister,
+            "migrate_out",
+            &MigrateOutReq {
+                user_canister,
+                migration_id,
+            },
+        )
+        .await?;
+    let migrated: MigratedOut = expect_ok(res, "pump-n-dump migrate_out")
+        .await?
+        .json()
+        .await?;
+
+    Ok(migrated.amount.0)
+}
+
+/// same shape as hot-or-not's `IdempotentUpdate<SatsBalanceUpdateRequest>`
+#[derive(Serialize)]
+struct SatsUpdate {
+    #[serde(flatten)]
+    request: SatsBalanceUpdateRequest,
+    idempotency_key: String,
+}
+
+/// hot-or-not replays the idempotency key for a day, a retried credit is never applied twice
+/// as long as the migration's queue retries land within it
+async fn credit_sats(
+    env: &Env,
+    trace: &TraceId,
+    migration: &MigrationRow,
+    user_principal: &str,
+    amount: &BigUint,
+) -> Result<()> {
+    let target = Target::HotOrNot;
+    let res = target
+        .client(env, trace)
+        .post(
+            target.namespace(),
+            user_principal,
+            "update_balance",
+            &SatsUpdate {
+                request: SatsBalanceUpdateRequest {
+                    delta: BigInt::from(amount.clone()),
+                    is_airdropped: false,
+                },
+                idempotency_key: format!("migration-{}", migration.id),
+            },
+        )
+        .await?;
+    expect_ok(res, "hot-or-not update_balance").await?;
+
+    Ok(())
+}
+
+/// same shape as yral-coin's `YralBalanceUpdateRequest`
+#[derive(Serialize)]
+struct CoinUpdate {
+    previous_balance: BigUint,
+    delta: String,
+    reason: &'static str,
+    description: String,
+    metadata: BTreeMap<&'static str, String>,
+    idempotency_key: String,
+}
+
+#[derive(Deserialize)]
+struct CoinBalance {
+    balance: String,
+}
+
+/// yral-coin remembers the idempotency key, a retried credit is never applied twice
+async fn credit_yral(
+    env: &Env,
+    trace: &TraceId,
+    migration: &MigrationRow,
+    user_principal: &str,
+    amount: &BigUint,
+) -> Result<()> {
+    let target = Target::Coin;
+    let client = target.client(env, trace);
+    let res = client
+        .get(target.namespace(), user_principal, "balance")
+        .await?;
+    let balance: CoinBalance = expect_ok(res, "coin balance").await?.json().await?;
+
+    let res = client
+        .post(
+            target.namespace(),
+            user_principal,
+            "update_balance",
+            &CoinUpdate {
+                previous_balance: parse_amount(&balance.balance)?,
+                delta: amount.to_string(),
+                reason: "Migration",
+                description: migration.reason.clone(),
+                metadata: BTreeMap::from([
+                    ("migration_id", migration.id.clone()),
+                    ("source", migration.source.as_str().to_string()),
+                ]),
+                idempotency_key: format!("migration-{}", migration.id),
+            },
+        )
+        .await?;
+    expect_ok(res, "coin update_balance").await?;
+
+    Ok(())
+}
+
+async fn convert_user(
+    env: &Env,
+    db: &D1Database,
+    trace: &TraceId,
+    migration: &MigrationRow,
+    conversion: &ConversionRow,
+    msg: &ConversionMessage,
+    now: u64,
+) -> Result<ConversionStatus> {
+    let rate = migration.rate();
+    let user_canister = conversion.user_canister.as_str();
+    if migration.is_dry_run() {
+        let balance = read_cents(env, trace, user_canister).await?;
+        let status = if balance == BigUint::ZERO {
+            ConversionStatus::Skipped
+        } else {
+            ConversionStatus::Planned
+        };
+        let converted = rate.convert(&balance);
+        record(db, msg, status, Some(&balance), Some(&converted), None, now).await?;
+        return Ok(status);
+    }
+
+    let debited = match (conversion.status, &conversion.source_amount) {
+        (ConversionStatus::Debited, Some(amount)) => parse_amount(amount)?,
+        _ => {
+            let debited = debit_cents(env, trace, user_canister, &migration.id).await?;
+            if debited == BigUint::ZERO {
+                let status = ConversionStatus::Skipped;
+                record(db, msg, status, Some(&debited), None, None, now).await?;
+                return Ok(status);
+            }
+            let status = ConversionStatus::Debited;
+            record(db, msg, status, Some(&debited), None, None, now).await?;
+            debited
+        }
+    };
+
+    let converted = rate.convert(&debited);
+    let res = match migration.destination {
+        Destination::Sats => {
+            credit_sats(env, trace, migration, &msg.user_principal, &converted).await
+        }
+        Destination::Yral => {
+            credit_yral(env, trace, migration, &msg.user_principal, &converted).await
+        }
+    };
+    match res {
+        Ok(()) => {
+            let status = ConversionStatus::Credited;
+            record(db, msg, status, None, Some(&converted), None, now).await?;
+            Ok(status)
+        }
+        Err(e) => {
+            // stays debited, the retry only credits
+            let detail = e.to_string();
+            let status = ConversionStatus::Debited;
+            record(db, msg, status, None, None, Some(&detail), now).await?;
+            Err(e)
+        }
+    }
+}
+
+/// converts one user and records how far they got, a finished user is left alone
+///
+/// errors are recorded on the conversion before they're returned, the caller retries the message
+pub async fn run_conversion(
+    env: &Env,
+    db: &D1Database,
+    trace: &TraceId,
+    msg: &ConversionMessage,
+    now: u64,
+) -> Result<ConversionStatus> {
+    let Some(migration) = migration(db, &msg.migration_id).await? else {
+        console_warn!(
+            "dropping {} of unknown migration {}",
+            msg.user_principal,
+            msg.migration_id
+        );
+        return Ok(ConversionStatus::Skipped);
+    };
+    let conversion: Option<ConversionRow> = db
+        .prepare(
+            "SELECT user_canister, status, source_amount FROM balance_conversions \
+            WHERE migration_id = ?1 AND user_principal = ?2",
+        )
+        .bind(&[
+            msg.migration_id.as_str().into(),
+            msg.user_principal.as_str().into(),
+        ])?
+        .first(None)
+        .await?;
+    let Some(conversion) = conversion else {
+        return Ok(ConversionStatus::Skipped);
+    };
+    if conversion.status.is_final() {
+        return Ok(conversion.status);
+    }
+
+    let res = convert_user(env, db, trace, &migration, &conversion, msg, now).await;
+    if let Err(e) = &res {
+        // a failure after the debit is already recorded as debited
+        let detail = e.to_string();
+        db.prepare(
+            "UPDATE balance_conversions SET status = 'failed', attempts = attempts + 1, \
+            detail = ?3, updated_at = ?4 \
+            WHERE migration_id = ?1 AND user_principal = ?2 AND status IN ('pending', 'failed')",
+        )
+        .bind(&[
+            msg.migration_id.as_str().into(),
+            msg.user_principal.as_str().into(),
+            detail.into(),
+            num(now),
+        ])?
+        .run()
+        .await?;
+    }
+
+    res
+}
+
+/// reconciliation report of `GET /migrations/:migration_id`
+#[derive(Serialize, Clone, Debug)]
+pub struct MigrationReport {
+    #[serde(flatten)]
+    pub migration: MigrationRow,
+    /// users by status
+    pub counts: BTreeMap<&'static str, u64>,
+    /// source units read on a dry run, debited otherwise
+    pub source_total: String,
+    /// destination units planned on a dry run, credited otherwise
+    pub destination_total: String,
+    /// `source_total` converted at once, the difference to `destination_total` is rounding
+    pub expected_destination_total: String,
+    /// users whose recorded amounts don't match the rate, first `MAX_REPORTED_USERS` of them
+    pub mismatched: Vec<String>,
+    /// users debited but not credited, first `MAX_REPORTED_USERS` of them
+    pub debited_only: Vec<String>,
+    /// every user is done and every amount matches the rate
+    pub reconciled: bool,
+}
+
+pub async fn migration_report(
+    db: &D1Database,
+    migration_id: &str,
+) -> Result<Option<MigrationReport>> {
+    #[derive(Deserialize)]
+    struct ReportRow {
+        seq: u64,
+        user_principal: String,
+        status: ConversionStatus,
+        source_amount: Option<String>,
+        destination_amount: Option<String>,
+    }
+
+    let Some(migration) = migration(db, migration_id).await? else {
+        return Ok(None);
+    };
+    let rate = migration.rate();
+    let mut counts = BTreeMap::new();
+    let mut source_total = BigUint::ZERO;
+    let mut destination_total = BigUint::ZERO;
+    let mut mismatches = 0;
+    let mut mismatched = vec![];
+    let mut debited_only = vec![];
+
+    let mut after = 0;
+    loop {
+        let page: Vec<ReportRow> = db
+            .prepare(
+                "SELECT rowid AS seq, user_principal, status, source_amount, destination_amount \
+                FROM balance_conversions WHERE migration_id = ?1 AND rowid > ?2 \
+                ORDER BY rowid LIMIT ?3",
+            )
+            .bind(&[
+                migration_id.into(),
+                num(after),
+                num(REPORT_PAGE_SIZE as u64),
+            ])?
+            .all()
+            .await?
+            .results()?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.seq;
+
+        for row in page {
+            *counts.entry(row.status.as_str()).or_insert(0) += 1;
+            let source = row.source_amount.as_deref().map(parse_amount).transpose()?;
+            let destination = row
+                .destination_amount
+                .as_deref()
+                .map(parse_amount)
+                .transpose()?;
+            match row.status {
+                ConversionStatus::Planned | ConversionStatus::Credited => {
+                    let source = source.unwrap_or_default();
+                    let destination = destination.unwrap_or_default();
+                    if rate.convert(&source) != destination {
+                        mismatches += 1;
+                        if mismatched.len() < MAX_REPORTED_USERS {
+                            mismatched.push(row.user_principal);
+                        }
+                    }
+                    source_total += source;
+                    destination_total += destination;
+                }
+                ConversionStatus::Debited => {
+                    source_total += source.unwrap_or_default();
+                    if debited_only.len() < MAX_REPORTED_USERS {
+                        debited_only.push(row.user_principal);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let unfinished: u64 = ["pending", "debited", "failed"]
+        .iter()
+        .filter_map(|status| counts.get(status))
+        .sum();
+    Ok(Some(MigrationReport {
+        expected_destination_total: rate.convert(&source_total).to_string(),
+        source_total: source_total.to_string(),
+        destination_total: destination_total.to_string(),
+        reconciled: unfinished == 0 && mismatches == 0,
+        migration,
+        counts,
+        mismatched,
+        debited_only,
+    }))
+}
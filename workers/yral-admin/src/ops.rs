@@ -0,0 +1,325 @@
+use std::str::FromStr;
+
+use candid::Principal;
+use num_bigint::{BigInt, BigUint};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use worker::*;
+use worker_utils::{
+    analytics::AnalyticsEvent, do_client::DoClient, notification::NotificationJob, trace::TraceId,
+};
+
+/// yral-coin's objects learn who they belong to from this header, see its consts.rs
+const COIN_OWNER_HEADER: &str = "X-Yral-User-Principal";
+
+/// queues dead lettered messages can be put back on, all bound in wrangler.toml
+pub const REQUEUE_QUEUES: &[&str] = &[
+    "NOTIFICATIONS",
+    "ANALYTICS_EVENTS",
+    "COIN_LEDGER",
+    "UPLOAD_VIDEO",
+];
+
+pub const MAX_REQUEUE_MESSAGES: usize = 100;
+
+/// same default as yral-coin's own admin route
+pub const DEFAULT_DUAL_CONTROL_THRESHOLD_YRAL: u64 = 10_000;
+
+/// per user durable objects owned by other workers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// `UserHonGameState`, keyed by user principal
+    HotOrNot,
+    /// `UserEphemeralState`, keyed by user canister
+    PumpNDump,
+    /// `UserYralCoinState`, keyed by user principal
+    Coin,
+}
+
+impl Target {
+    pub fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "hot-or-not" => Some(Self::HotOrNot),
+            "pump-n-dump" => Some(Self::PumpNDump),
+            "coin" => Some(Self::Coin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::HotOrNot => "hot-or-not",
+            Self::PumpNDump => "pump-n-dump",
+            Self::Coin => "coin",
+        }
+    }
+
+    pub fn namespace(self) -> &'static str {
+        match self {
+            Self::HotOrNot => "USER_HON_GAME_STATE",
+            Self::PumpNDump => "USER_EPHEMERAL_STATE",
+            Self::Coin => "USER_YRAL_COIN_STATE",
+        }
+    }
+
+    /// read only routes of the object, dumped by `inspect`
+    fn state_paths(self, key: &str) -> Vec<String> {
+        let paths: &[&str] = match self {
+            Self::HotOrNot => &[
+                "v2/balance",
+                "treasury_status",
+                "last_airdrop_claimed_at",
+                "migration_status",
+            ],
+            Self::PumpNDump => &[
+                "balance_v2/{key}",
+                "earnings/{key}",
+                "uncommitted_games/{key}",
+                "fraud_events",
+            ],
+            Self::Coin => &["snapshot", "limits", "admin_audit"],
+        };
+
+        paths.iter().map(|p| p.replace("{key}", key)).collect()
+    }
+
+    pub fn client<'a>(self, env: &'a Env, trace: &'a TraceId) -> DoClient<'a> {
+        let client = DoClient::new(env).with_trace(trace);
+        match self {
+            Self::Coin => client.with_name_header(COIN_OWNER_HEADER),
+            Self::HotOrNot | Self::PumpNDump => client,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StateDump {
+    pub target: &'static str,
+    pub key: String,
+    /// body of each read only route, by path
+    pub state: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct SchemaStatus {
+    schema_version: u32,
+    latest: u32,
+}
+
+/// reads every read only route of the object, a failing route is kept as `{ "status", "error" }`
+///
+/// hot-or-not upgrades its state on any other route, an outdated one only reports its schema
+pub async fn inspect(env: &Env, trace: &TraceId, target: Target, key: &str) -> Result<StateDump> {
+    let client = target.client(env, trace);
+    let mut state = Map::new();
+    if target == Target::HotOrNot {
+        let mut res = client
+            .get(target.namespace(), key, "migration_status")
+            .await?;
+        let status: SchemaStatus = res.json().await?;
+        if status.schema_version < status.latest {
+            state.insert(
+                "migration_status".into(),
+                serde_json::json!({
+                    "schema_version": status.schema_version,
+                    "latest": status.latest,
+                }),
+            );
+            return Ok(StateDump {
+                target: target.as_str(),
+                key: key.to_string(),
+                state,
+            });
+        }
+    }
+    for path in target.state_paths(key) {
+        let mut res = client.get(target.namespace(), key, &path).await?;
+        let status = res.status_code();
+        let body = res.text().await?;
+        let value = match serde_json::from_str(&body) {
+            Ok(value) if (200..300).contains(&status) => value,
+            _ if (200..300).contains(&status) => Value::String(body),
+            _ => serde_json::json!({ "status": status, "error": body }),
+        };
+        state.insert(path, value);
+    }
+
+    Ok(StateDump {
+        target: target.as_str(),
+        key: key.to_string(),
+        state,
+    })
+}
+
+/// pump-n-dump settles on an alarm, this settles right away
+pub async fn force_settle(env: &Env, trace: &TraceId, user_canister: &str) -> Result<Response> {
+    let target = Target::PumpNDump;
+    target
+        .client(env, trace)
+        .post(target.namespace(), user_canister, "settle", &())
+        .await
+}
+
+pub async fn migration_status(
+    env: &Env,
+    trace: &TraceId,
+    user_principal: &str,
+) -> Result<Response> {
+    let target = Target::HotOrNot;
+    target
+        .client(env, trace)
+        .get(target.namespace(), user_principal, "migration_status")
+        .await
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RequeueRequest {
+    /// message bodies as found in the dead letter queue
+    pub messages: Vec<Value>,
+}
+
+/// the parts of yral-coin's `GlobalLedgerMessage` its consumer can't write without
+#[derive(Deserialize)]
+struct CoinLedgerMessage {
+    user_principal: Principal,
+    seq: u64,
+    entry: CoinLedgerEntry,
+}
+
+/// amounts of yral-coin's `LedgerEntry`, as decimal strings
+#[derive(Deserialize)]
+struct CoinLedgerEntry {
+    delta: String,
+    resulting_balance: String,
+}
+
+impl CoinLedgerMessage {
+    fn check(&self) -> std::result::Result<(), String> {
+        let entry = &self.entry;
+        if BigInt::from_str(&entry.delta).is_err()
+            || BigUint::from_str(&entry.resulting_balance).is_err()
+        {
+            return Err(format!(
+                "ledger entry {} of {} has an invalid amount",
+                self.seq, self.user_principal
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// variants of yral-upload-video's `UploadVideoQueueMessage`
+const UPLOAD_VIDEO_MESSAGES: &[&str] = &[
+    "UploadVideo",
+    "UploadVideoStorj",
+    "MarkVideoAsDownloadable",
+    "PushPostToPostServiceCanister",
+    "UploadToStorj",
+    "ModerateVideo",
+];
+
+/// why `message` can't be consumed from `queue`, so it isn't put back only to be dead lettered
+/// again
+pub fn invalid_message(queue: &str, message: &Value) -> Option<String> {
+    let parsed = match queue {
+        "NOTIFICATIONS" => serde_json::from_value::<NotificationJob>(message.clone()).map(drop),
+        "ANALYTICS_EVENTS" => serde_json::from_value::<AnalyticsEvent>(message.clone()).map(drop),
+        "COIN_LEDGER" => {
+            return match serde_json::from_value::<CoinLedgerMessage>(message.clone()) {
+                Ok(message) => message.check().err(),
+                Err(e) => Some(e.to_string()),
+            };
+        }
+        "UPLOAD_VIDEO" => {
+            // a traced enum, `{ "trace_id"?, "<Variant>": ... }`
+            let variants: Vec<_> = message
+                .as_object()
+                .map(|m| m.keys().filter(|k| *k != "trace_id").collect())
+                .unwrap_or_default();
+            return match variants.as_slice() {
+                [variant] if UPLOAD_VIDEO_MESSAGES.contains(&variant.as_str()) => None,
+                _ => Some(format!(
+                    "expected one of {}",
+                    UPLOAD_VIDEO_MESSAGES.join(", ")
+                )),
+            };
+        }
+        _ => return Some("unknown queue".into()),
+    };
+
+    parsed.err().map(|e| e.to_string())
+}
+
+pub async fn requeue(env: &Env, queue: &str, messages: &[Value]) -> Result<usize> {
+    let queue = env.queue(queue)?;
+    for message in messages {
+        queue.send(message).await?;
+    }
+
+    Ok(messages.len())
+}
+
+/// body of `POST /adjust/:user_principal`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AdjustRequest {
+    /// unique per correction, retries with the same id are applied once
+    pub adjustment_id: String,
+    /// signed YRAL amount, as a decimal string
+    pub delta: String,
+    pub reason: String,
+}
+
+impl AdjustRequest {
+    pub fn delta(&self) -> Option<BigInt> {
+        BigInt::from_str(&self.delta).ok()
+    }
+
+    /// what the co-signer's token must approve, `adjustment:{user_principal}:{adjustment_id}:{delta}`
+    pub fn approval(&self, user_principal: &str, delta: &BigInt) -> String {
+        format!("adjustment:{user_principal}:{}:{delta}", self.adjustment_id)
+    }
+}
+
+/// yral-coin's `AdminAdjustReq`
+#[derive(Serialize)]
+struct CoinAdjustReq<'a> {
+    adjustment_id: &'a str,
+    delta: &'a str,
+    reason: &'a str,
+    approvers: &'a [String],
+}
+
+pub fn needs_cosigner(env: &Env, delta: &BigInt) -> bool {
+    let threshold = env
+        .var("ADMIN_DUAL_CONTROL_THRESHOLD_YRAL")
+        .ok()
+        .and_then(|v| v.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_DUAL_CONTROL_THRESHOLD_YRAL);
+
+    delta.magnitude() > &BigUint::from(threshold)
+}
+
+pub async fn adjust_balance(
+    env: &Env,
+    trace: &TraceId,
+    user_principal: &str,
+    req: &AdjustRequest,
+    approvers: &[String],
+) -> Result<Response> {
+    let target = Target::Coin;
+    target
+        .client(env, trace)
+        .post(
+            target.namespace(),
+            user_principal,
+            "admin_adjust",
+            &CoinAdjustReq {
+                adjustment_id: &req.adjustment_id,
+                delta: &req.delta,
+                reason: &req.reason,
+                approvers,
+            },
+        )
+        .await
+}
@@ -0,0 +1,31 @@
+-- counters per hour, `hour` is the unix millis the hour starts at
+CREATE TABLE IF NOT EXISTS hourly_metrics (
+    hour INTEGER NOT NULL,
+    metric TEXT NOT NULL,
+    value INTEGER NOT NULL,
+    PRIMARY KEY (hour, metric)
+);
+
+-- users seen each hour, counted into `active_users` by the hourly rollup
+CREATE TABLE IF NOT EXISTS hourly_active_users (
+    hour INTEGER NOT NULL,
+    user_principal TEXT NOT NULL,
+    PRIMARY KEY (hour, user_principal)
+);
+
+-- recomputed from the hourly tables, `day` is the unix millis the UTC day starts at
+CREATE TABLE IF NOT EXISTS daily_metrics (
+    day INTEGER NOT NULL,
+    metric TEXT NOT NULL,
+    value INTEGER NOT NULL,
+    PRIMARY KEY (day, metric)
+);
+
+-- queue message ids already counted, redeliveries are skipped
+CREATE TABLE IF NOT EXISTS processed_events (
+    id TEXT PRIMARY KEY,
+    processed_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS processed_events_processed_at
+    ON processed_events (processed_at);
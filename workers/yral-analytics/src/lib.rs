@@ -0,0 +1,164 @@
+mod jwt;
+mod rollups;
+
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
+use rollups::{query_rollups, record_events, roll_up, Granularity, RollupQuery};
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{
+    analytics::AnalyticsEvent,
+    api_error::{error_resp, ApiError},
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    time::now_millis,
+};
+
+const ANALYTICS_DB: &str = "ANALYTICS_DB";
+
+/// consumed by yral-activity, which builds each user's feed from the same events
+const ACTIVITY_EVENTS_QUEUE: &str = "ACTIVITY_EVENTS";
+/// consumed by yral-rewards, which grants campaign rewards on the same events
+const REWARDS_EVENTS_QUEUE: &str = "REWARDS_EVENTS";
+
+/// every event is forwarded to each of these
+const FORWARD_QUEUES: [&str; 2] = [ACTIVITY_EVENTS_QUEUE, REWARDS_EVENTS_QUEUE];
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-analytics",
+    &[
+        Dependency::D1(ANALYTICS_DB),
+        Dependency::Queue(ACTIVITY_EVENTS_QUEUE),
+        Dependency::Queue(REWARDS_EVENTS_QUEUE),
+    ],
+);
+
+/// `GET /rollups/:granularity?metric=&from=&to=`, granularity is `hour` or `day`
+///
+/// hourly `active_users` only appear once the scheduled rollup has run for that hour
+async fn get_rollups(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+    let Some(granularity) = ctx
+        .param("granularity")
+        .map(String::as_str)
+        .and_then(Granularity::from_param)
+    else {
+        return ApiError::new("UnknownGranularity", "granularity must be hour or day")
+            .into_response(400);
+    };
+    let Ok(query) = req.query::<RollupQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+
+    let db = ctx.env.d1(ANALYTICS_DB)?;
+    let points = query_rollups(&db, granularity, &query).await?;
+
+    Response::from_json(&json!({
+        "granularity": granularity,
+        "points": points,
+    }))
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-analytics", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/rollups/:granularity", get_rollups)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// activity feeds key items by the event itself and rewards dedupe their grants,
+/// so forwarding a retried batch again is harmless
+async fn forward_events(env: &Env, events: &[(String, AnalyticsEvent)]) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    for queue in FORWARD_QUEUES {
+        env.queue(queue)?
+            .send_batch(events.iter().map(|(_, event)| event.clone()))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// counts the batch in one transaction, so it's retried as a whole and redeliveries are skipped
+///
+/// the events are then forwarded to yral-activity and yral-rewards, a failed forward retries the batch too
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    let db = env.d1(ANALYTICS_DB)?;
+    let metrics = Metrics::new(&env, "yral-analytics");
+
+    let mut events = vec![];
+    for message in batch.messages()? {
+        match serde_json::from_value::<AnalyticsEvent>(message.body().clone()) {
+            Ok(event) => events.push((message.id(), event)),
+            Err(e) => {
+                console_error!("dropping malformed analytics event {}: {e}", message.id());
+                metrics.counter("analytics_events", &["malformed"]);
+            }
+        }
+    }
+
+    if let Err(e) = record_events(&db, &events, now_millis()).await {
+        console_error!("failed to record {} analytics events: {e}", events.len());
+        batch.retry_all();
+        return Ok(());
+    }
+    if let Err(e) = forward_events(&env, &events).await {
+        console_error!("failed to forward {} analytics events: {e}", events.len());
+        batch.retry_all();
+        return Ok(());
+    }
+    for (_, event) in &events {
+        metrics.counter("analytics_events", &[event.kind()]);
+    }
+    batch.ack_all();
+
+    Ok(())
+}
+
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    let res = match env.d1(ANALYTICS_DB) {
+        Ok(db) => roll_up(&db, now_millis()).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        console_error!("analytics rollup failed: {e}");
+    }
+}
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, D1Database, D1PreparedStatement, Result};
+use worker_utils::analytics::AnalyticsEvent;
+
+pub const HOUR_MS: u64 = 60 * 60 * 1000;
+pub const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// how far back the scheduled rollup recomputes, covers events delivered late by an outbox
+const ROLLUP_LOOKBACK_MS: u64 = 2 * DAY_MS;
+
+const HOURLY_RETENTION_MS: u64 = 30 * DAY_MS;
+const ACTIVE_USERS_RETENTION_MS: u64 = 7 * DAY_MS;
+/// longer than a message can stay on the queue
+const PROCESSED_RETENTION_MS: u64 = 7 * DAY_MS;
+
+pub const MAX_POINTS: u32 = 5_000;
+
+/// counters an event adds to the hour it happened in
+pub fn increments(event: &AnalyticsEvent) -> Vec<(&'static str, u64)> {
+    match event {
+        AnalyticsEvent::Vote {
+            vote_amount,
+            won,
+            amount,
+            ..
+        } => vec![
+            ("votes", 1),
+            ("sats_wagered", *vote_amount),
+            (if *won { "sats_won" } else { "sats_lost" }, *amount),
+        ],
+        AnalyticsEvent::VideoPublished { .. } => vec![("videos_published", 1)],
+        AnalyticsEvent::Claim { amount, .. } => vec![
+            ("claims", 1),
+            ("dolr_claimed", amount.parse().unwrap_or(u64::MAX)),
+        ],
+        AnalyticsEvent::Referral { amount, .. } => {
+            vec![("referrals", 1), ("sats_referral_rewards", *amount)]
+        }
+        AnalyticsEvent::VideoUploadFailed { .. } => vec![("video_upload_failures", 1)],
+        AnalyticsEvent::Signup { .. } => vec![("signups", 1)],
+    }
+}
+
+fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+/// statements counting one queue message, skipped entirely if `id` was already counted
+fn event_statements(
+    db: &D1Database,
+    id: &str,
+    event: &AnalyticsEvent,
+    now: u64,
+) -> Result<Vec<D1PreparedStatement>> {
+    let hour = event.at() - event.at() % HOUR_MS;
+    let mut statements = vec![];
+    for (metric, value) in increments(event) {
+        statements.push(
+            db.prepare(
+                "INSERT INTO hourly_metrics (hour, metric, value) \
+                SELECT ?1, ?2, ?3 WHERE NOT EXISTS (SELECT 1 FROM processed_events WHERE id = ?4) \
+                ON CONFLICT (hour, metric) DO UPDATE SET value = value + excluded.value",
+            )
+            .bind(&[num(hour), metric.into(), num(value), id.into()])?,
+        );
+    }
+    statements.push(
+        db.prepare(
+            "INSERT OR IGNORE INTO hourly_active_users (hour, user_principal) VALUES (?1, ?2)",
+        )
+        .bind(&[num(hour), event.user_principal().into()])?,
+    );
+    statements.push(
+        db.prepare("INSERT OR IGNORE INTO processed_events (id, processed_at) VALUES (?1, ?2)")
+            .bind(&[id.into(), num(now)])?,
+    );
+
+    Ok(statements)
+}
+
+/// counts a batch of `(message id, event)` in one transaction
+pub async fn record_events(
+    db: &D1Database,
+    events: &[(String, AnalyticsEvent)],
+    now: u64,
+) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut statements = vec![];
+    for (id, event) in events {
+        statements.extend(event_statements(db, id, event, now)?);
+    }
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+/// recomputes active users and the daily rollups of the last `ROLLUP_LOOKBACK_MS`, then prunes
+pub async fn roll_up(db: &D1Database, now: u64) -> Result<()> {
+    let since = now.saturating_sub(ROLLUP_LOOKBACK_MS);
+    let since_hour = since - since % HOUR_MS;
+    let since_day = since - since % DAY_MS;
+
+    let statements = vec![
+        db.prepare(
+            "INSERT OR REPLACE INTO hourly_metrics (hour, metric, value) \
+            SELECT hour, 'active_users', COUNT(*) FROM hourly_active_users \
+            WHERE hour >= ?1 GROUP BY hour",
+        )
+        .bind(&[num(since_hour)])?,
+        db.prepare(
+            "INSERT OR REPLACE INTO daily_metrics (day, metric, value) \
+            SELECT hour - hour % ?2, metric, SUM(value) FROM hourly_metrics \
+            WHERE hour >= ?1 AND metric != 'active_users' GROUP BY 1, metric",
+        )
+        .bind(&[num(since_day), num(DAY_MS)])?,
+        db.prepare(
+            "INSERT OR REPLACE INTO daily_metrics (day, metric, value) \
+            SELECT hour - hour % ?2, 'active_users', COUNT(DISTINCT user_principal) \
+            FROM hourly_active_users WHERE hour >= ?1 GROUP BY 1",
+        )
+        .bind(&[num(since_day), num(DAY_MS)])?,
+        db.prepare("DELETE FROM hourly_metrics WHERE hour < ?1")
+            .bind(&[num(now.saturating_sub(HOURLY_RETENTION_MS))])?,
+        db.prepare("DELETE FROM hourly_active_users WHERE hour < ?1")
+            .bind(&[num(now.saturating_sub(ACTIVE_USERS_RETENTION_MS))])?,
+        db.prepare("DELETE FROM processed_events WHERE processed_at < ?1")
+            .bind(&[num(now.saturating_sub(PROCESSED_RETENTION_MS))])?,
+    ];
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    pub fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            _ => None,
+        }
+    }
+
+    fn table(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Hour => ("hourly_metrics", "hour"),
+            Self::Day => ("daily_metrics", "day"),
+        }
+    }
+}
+
+/// query of `GET /rollups/:granularity`, `from` and `to` are unix millis
+#[derive(Deserialize, Default)]
+pub struct RollupQuery {
+    pub metric: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RollupPoint {
+    /// unix millis the hour or day starts at
+    pub period_start: u64,
+    pub metric: String,
+    pub value: u64,
+}
+
+/// oldest first, at most `MAX_POINTS`
+pub async fn query_rollups(
+    db: &D1Database,
+    granularity: Granularity,
+    query: &RollupQuery,
+) -> Result<Vec<RollupPoint>> {
+    let (table, column) = granularity.table();
+    let mut filters = Vec::<String>::new();
+    let mut binds = Vec::<JsValue>::new();
+    if let Some(metric) = query.metric.as_deref() {
+        filters.push("metric = ?".into());
+        binds.push(metric.into());
+    }
+    if let Some(from) = query.from {
+        filters.push(format!("{column} >= ?"));
+        binds.push(num(from));
+    }
+    if let Some(to) = query.to {
+        filters.push(format!("{column} < ?"));
+        binds.push(num(to));
+    }
+    let where_clause = if filters.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", filters.join(" AND "))
+    };
+    binds.push(num(MAX_POINTS as u64));
+
+    db.prepare(format!(
+        "SELECT {column} AS period_start, metric, value FROM {table} {where_clause} \
+        ORDER BY {column}, metric LIMIT ?"
+    ))
+    .bind(&binds)?
+    .all()
+    .await?
+    .results()
+}
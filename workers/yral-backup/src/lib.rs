@@ -0,0 +1,227 @@
+mod jwt;
+mod snapshots;
+
+use jwt::JWT_PUBKEY;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use snapshots::{
+    backup_object, enqueue_page, list_snapshots, object_id, restore, start_run, BackupJob,
+    RestoreTarget, BACKUPS_BUCKET, BACKUP_JOBS_QUEUE, NAMESPACES,
+};
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    backup::BACKUP_REGISTRY,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::{authorize, JwtKeys, Role},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    time::now_millis,
+};
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-backup",
+    &[
+        Dependency::Kv(BACKUP_REGISTRY),
+        Dependency::Bucket(BACKUPS_BUCKET),
+        Dependency::Queue(BACKUP_JOBS_QUEUE),
+        Dependency::DurableObject(NAMESPACES[0]),
+        Dependency::DurableObject(NAMESPACES[1]),
+        Dependency::DurableObject(NAMESPACES[2]),
+        Dependency::DurableObject(NAMESPACES[3]),
+    ],
+);
+
+macro_rules! admin {
+    ($ctx:expr, $req:expr, $role:expr) => {
+        match authorize(
+            &JwtKeys::from_env(&$ctx.env, JWT_PUBKEY),
+            &$req,
+            "Authorization",
+            $role,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        }
+    };
+}
+
+/// the namespace and object named in the route, e.g. `USER_HON_GAME_STATE` and a user principal
+fn target(ctx: &RouteContext<()>) -> std::result::Result<(String, String), &'static str> {
+    let Some(namespace) = ctx.param("namespace") else {
+        return Err("namespace is required");
+    };
+    if !NAMESPACES.contains(&namespace.as_str()) {
+        return Err("unknown namespace");
+    }
+    let Some(name) = ctx.param("name") else {
+        return Err("name is required");
+    };
+
+    Ok((namespace.clone(), name.clone()))
+}
+
+/// starts a run outside the schedule, e.g. before a risky migration
+async fn run(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+
+    let run_at = now_millis();
+    start_run(&ctx.env, run_at).await?;
+    console_log!("backup run {run_at} started by {}", admin.sub);
+
+    Ok(Response::from_json(&json!({ "run_at": run_at }))?.with_status(202))
+}
+
+/// `GET /snapshots/:namespace/:name`, oldest first
+async fn snapshots(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let (namespace, name) = match target(&ctx) {
+        Ok(target) => target,
+        Err(msg) => return error_resp(msg, 400),
+    };
+
+    let object_id = object_id(&ctx.env, &namespace, &name)?;
+    let snapshots = list_snapshots(&ctx.env, &namespace, &object_id).await?;
+    Response::from_json(&json!({ "object_id": object_id, "snapshots": snapshots }))
+}
+
+#[derive(Deserialize)]
+struct RestoreRequest {
+    /// `exported_at` of the snapshot, the latest when missing
+    snapshot_at: Option<u64>,
+    /// restore into a new object instead, for recovery drills
+    #[serde(default)]
+    unique: bool,
+}
+
+/// the object answers 409 if it already holds state, erase it first through yral-admin
+async fn restore_object(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let (namespace, name) = match target(&ctx) {
+        Ok(target) => target,
+        Err(msg) => return error_resp(msg, 400),
+    };
+    let body: RestoreRequest = json_body!(req);
+
+    let object_id = object_id(&ctx.env, &namespace, &name)?;
+    let snapshot_at = match body.snapshot_at {
+        Some(snapshot_at) => snapshot_at,
+        None => match list_snapshots(&ctx.env, &namespace, &object_id)
+            .await?
+            .last()
+        {
+            Some(latest) => latest.exported_at,
+            None => return error_resp("no snapshots", 404),
+        },
+    };
+    let restore_target = if body.unique {
+        RestoreTarget::Unique
+    } else {
+        RestoreTarget::Original
+    };
+
+    let Some(res) = restore(
+        &ctx.env,
+        &namespace,
+        &object_id,
+        snapshot_at,
+        restore_target,
+    )
+    .await?
+    else {
+        return error_resp("snapshot not found", 404);
+    };
+    console_log!(
+        "{} restored {namespace}/{object_id} from {snapshot_at}, status {}",
+        admin.sub,
+        res.status_code()
+    );
+
+    Ok(res)
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let (method, path) = (req.method(), req.path());
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-backup", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
+    Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .post_async("/runs", run)
+        .get_async("/snapshots/:namespace/:name", snapshots)
+        .post_async("/snapshots/:namespace/:name/restore", restore_object)
+        .run(req, env)
+        .await
+}
+
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    if let Err(e) = start_run(&env, now_millis()).await {
+        console_error!("failed to start backup run: {e}");
+    }
+}
+
+async fn run_job(env: &Env, job: BackupJob, metrics: &Metrics) -> Result<()> {
+    match job {
+        BackupJob::Page { run_at, cursor } => {
+            let enqueued = enqueue_page(env, run_at, cursor).await?;
+            metrics.count("backup_objects_enqueued", &[], enqueued as u64);
+            Ok(())
+        }
+        BackupJob::Object {
+            run_at,
+            namespace,
+            object_id,
+        } => {
+            let res = backup_object(env, run_at, &namespace, &object_id).await;
+            let outcome = match &res {
+                Ok(outcome) => outcome.as_str(),
+                Err(e) => {
+                    console_error!("failed to back up {namespace}/{object_id}: {e}");
+                    "failed"
+                }
+            };
+            metrics.counter("backup_snapshots", &[namespace.as_str(), outcome]);
+            res.map(|_| ())
+        }
+    }
+}
+
+/// jobs run one by one, a failed job is retried alone and lands in the DLQ once retries run out
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    let metrics = Metrics::new(&env, "yral-backup");
+    for message in batch.messages()? {
+        let job = match serde_json::from_value::<BackupJob>(message.body().clone()) {
+            Ok(job) => job,
+            Err(e) => {
+                console_error!("dropping malformed backup job {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+        match run_job(&env, job, &metrics).await {
+            Ok(()) => message.ack(),
+            Err(_) => message.retry(),
+        }
+    }
+
+    Ok(())
+}
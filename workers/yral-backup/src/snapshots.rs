@@ -0,0 +1,251 @@
+//! snapshots in R2, keyed `{namespace}/{object_id}/{exported_at}.msgpack`
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+use worker_utils::backup::{registry_key, Snapshot, BACKUP_REGISTRY, EXPORT_PATH, RESTORE_PATH};
+
+pub const BACKUPS_BUCKET: &str = "BACKUPS";
+pub const BACKUP_JOBS_QUEUE: &str = "BACKUP_JOBS";
+
+/// namespaces objects register from, every one is bound in wrangler.toml
+pub const NAMESPACES: [&str; 4] = [
+    "USER_HON_GAME_STATE",
+    "USER_EPHEMERAL_STATE",
+    "USER_YRAL_COIN_STATE",
+    "USER_ACTIVITY",
+];
+
+/// durable objects ignore the host, only the path is routed
+const DO_BASE_URL: &str = "http://fake_url.com";
+
+/// registry keys listed per page
+const REGISTRY_PAGE_SIZE: u64 = 1000;
+/// cloudflare's limit on messages per `send_batch`
+const SEND_BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackupJob {
+    /// enqueues the objects of a page of the registry, then the next page
+    Page {
+        /// unix millis the run started, shared by all its snapshots
+        run_at: u64,
+        cursor: Option<String>,
+    },
+    Object {
+        run_at: u64,
+        namespace: String,
+        object_id: String,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SnapshotInfo {
+    pub key: String,
+    /// unix millis
+    pub exported_at: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Written,
+    /// nothing stored, e.g. the object was erased since it registered
+    Empty,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Written => "written",
+            Self::Empty => "empty",
+        }
+    }
+}
+
+fn snapshot_key(namespace: &str, object_id: &str, exported_at: u64) -> String {
+    format!(
+        "{}/{exported_at:020}.msgpack",
+        registry_key(namespace, object_id)
+    )
+}
+
+fn exported_at(key: &str) -> Option<u64> {
+    key.rsplit('/')
+        .next()?
+        .strip_suffix(".msgpack")?
+        .parse()
+        .ok()
+}
+
+fn stub(env: &Env, namespace: &str, object_id: &str) -> Result<Stub> {
+    env.durable_object(namespace)?
+        .id_from_string(object_id)?
+        .get_stub()
+}
+
+/// hex id of the object named `name`, e.g. a user principal
+pub fn object_id(env: &Env, namespace: &str, name: &str) -> Result<String> {
+    Ok(env
+        .durable_object(namespace)?
+        .id_from_name(name)?
+        .to_string())
+}
+
+/// starts a run, the queue walks the registry from there
+pub async fn start_run(env: &Env, run_at: u64) -> Result<()> {
+    env.queue(BACKUP_JOBS_QUEUE)?
+        .send(&BackupJob::Page {
+            run_at,
+            cursor: None,
+        })
+        .await
+}
+
+/// a redelivered page enqueues its objects again, their snapshots overwrite the same keys
+pub async fn enqueue_page(env: &Env, run_at: u64, cursor: Option<String>) -> Result<usize> {
+    let mut list = env.kv(BACKUP_REGISTRY)?.list().limit(REGISTRY_PAGE_SIZE);
+    if let Some(cursor) = cursor {
+        list = list.cursor(cursor);
+    }
+    let page = list
+        .execute()
+        .await
+        .map_err(|e| Error::RustError(e.to_string()))?;
+
+    let jobs: Vec<_> = page
+        .keys
+        .iter()
+        .filter_map(|key| {
+            let (namespace, object_id) = key.name.split_once('/')?;
+            Some(BackupJob::Object {
+                run_at,
+                namespace: namespace.to_string(),
+                object_id: object_id.to_string(),
+            })
+        })
+        .collect();
+    let queue = env.queue(BACKUP_JOBS_QUEUE)?;
+    for chunk in jobs.chunks(SEND_BATCH_SIZE) {
+        queue.send_batch(chunk.iter().cloned()).await?;
+    }
+    if !page.list_complete {
+        if let Some(cursor) = page.cursor {
+            queue
+                .send(&BackupJob::Page {
+                    run_at,
+                    cursor: Some(cursor),
+                })
+                .await?;
+        }
+    }
+
+    Ok(jobs.len())
+}
+
+/// exports the object and writes its snapshot, empty objects are skipped
+pub async fn backup_object(
+    env: &Env,
+    run_at: u64,
+    namespace: &str,
+    object_id: &str,
+) -> Result<Outcome> {
+    let mut res = stub(env, namespace, object_id)?
+        .fetch_with_str(&format!("{DO_BASE_URL}{EXPORT_PATH}"))
+        .await?;
+    if res.status_code() != 200 {
+        return Err(Error::RustError(format!(
+            "export responded with {}: {}",
+            res.status_code(),
+            res.text().await.unwrap_or_default()
+        )));
+    }
+    let bytes = res.bytes().await?;
+    if Snapshot::from_bytes(&bytes)?.is_empty() {
+        return Ok(Outcome::Empty);
+    }
+
+    env.bucket(BACKUPS_BUCKET)?
+        .put(snapshot_key(namespace, object_id, run_at), bytes)
+        .execute()
+        .await?;
+
+    Ok(Outcome::Written)
+}
+
+/// the object's snapshots, oldest first
+pub async fn list_snapshots(
+    env: &Env,
+    namespace: &str,
+    object_id: &str,
+) -> Result<Vec<SnapshotInfo>> {
+    let bucket = env.bucket(BACKUPS_BUCKET)?;
+    let prefix = format!("{}/", registry_key(namespace, object_id));
+    let mut snapshots = vec![];
+    let mut cursor = None::<String>;
+    loop {
+        let mut list = bucket.list().prefix(prefix.clone());
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+        snapshots.extend(page.objects().into_iter().filter_map(|object| {
+            let key = object.key();
+            Some(SnapshotInfo {
+                exported_at: exported_at(&key)?,
+                size: object.size(),
+                key,
+            })
+        }));
+
+        if !page.truncated() {
+            break;
+        }
+        cursor = page.cursor();
+        if cursor.is_none() {
+            break;
+        }
+    }
+    snapshots.sort_by_key(|s| s.exported_at);
+
+    Ok(snapshots)
+}
+
+/// where a restore writes, the object must be empty either way
+#[derive(Clone, Copy, Debug)]
+pub enum RestoreTarget {
+    /// the object the snapshot was taken from, after its state was lost
+    Original,
+    /// a new object nothing else addresses, for recovery drills
+    Unique,
+}
+
+/// replays the snapshot into the target object, its response is returned as is
+pub async fn restore(
+    env: &Env,
+    namespace: &str,
+    object_id: &str,
+    exported_at: u64,
+    target: RestoreTarget,
+) -> Result<Option<Response>> {
+    let key = snapshot_key(namespace, object_id, exported_at);
+    let Some(object) = env.bucket(BACKUPS_BUCKET)?.get(&key).execute().await? else {
+        return Ok(None);
+    };
+    let Some(body) = object.body() else {
+        return Ok(None);
+    };
+    let bytes = body.bytes().await?;
+
+    let ns = env.durable_object(namespace)?;
+    let id = match target {
+        RestoreTarget::Original => ns.id_from_string(object_id)?,
+        RestoreTarget::Unique => ns.unique_id()?,
+    };
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_body(Some(js_sys::Uint8Array::from(bytes.as_slice()).into()));
+    let req = Request::new_with_init(&format!("{DO_BASE_URL}{RESTORE_PATH}"), &init)?;
+
+    id.get_stub()?.fetch_with_request(req).await.map(Some)
+}
@@ -0,0 +1,20 @@
+-- every coin transaction across all users, streamed from the per-user DO ledgers
+CREATE TABLE IF NOT EXISTS coin_transactions (
+    -- random per ledger entry, makes queue redeliveries idempotent
+    -- "<user principal>:<DO ledger sequence>" on rows streamed before it was random
+    id TEXT PRIMARY KEY,
+    user_principal TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    delta TEXT NOT NULL,
+    resulting_balance TEXT NOT NULL,
+    reason_code TEXT,
+    reason TEXT,
+    idempotency_key TEXT,
+    metadata TEXT NOT NULL DEFAULT '{}',
+    timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS coin_transactions_principal_timestamp
+    ON coin_transactions (user_principal, timestamp);
+CREATE INDEX IF NOT EXISTS coin_transactions_timestamp
+    ON coin_transactions (timestamp);
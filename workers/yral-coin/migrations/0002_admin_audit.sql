@@ -0,0 +1,18 @@
+-- admin balance adjustments, kept outside the per-user DOs so erasing a user keeps the record
+CREATE TABLE IF NOT EXISTS admin_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_principal TEXT NOT NULL,
+    adjustment_id TEXT NOT NULL,
+    -- json array of the approving admins' JWT subjects
+    approvers TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    delta TEXT NOT NULL,
+    balance_before TEXT NOT NULL,
+    balance_after TEXT NOT NULL,
+    -- set if applying the adjustment failed
+    error TEXT,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS admin_audit_principal_timestamp
+    ON admin_audit (user_principal, timestamp);
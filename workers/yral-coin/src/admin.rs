@@ -0,0 +1,191 @@
+use candid::Principal;
+use num_bigint::BigInt;
+use serde::Deserialize;
+use std::result::Result as StdResult;
+use worker::{wasm_bindgen::JsValue, *};
+
+use crate::{
+    coin::UserYralCoinState,
+    error::WorkerError,
+    types::{AdminAdjustReq, AdminAuditEntry, BalanceUpdateReason, LedgerReason},
+};
+
+/// entries written to DO storage before the log moved to `ADMIN_AUDIT_DB`, read but never written
+pub(crate) const ADMIN_AUDIT_PREFIX: &str = "admin-audit-";
+pub(crate) const ADMIN_ADJUSTMENT_PREFIX: &str = "admin-adjustment-";
+/// outside the user's DO, so `forget` doesn't erase the audit trail
+const ADMIN_AUDIT_DB: &str = "COIN_LEDGER_DB";
+
+fn to_internal(e: worker::Error) -> (u16, WorkerError) {
+    (500, WorkerError::Internal(e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct AdminAuditRow {
+    adjustment_id: String,
+    approvers: String,
+    reason: String,
+    delta: String,
+    balance_before: String,
+    balance_after: String,
+    timestamp: u64,
+    error: Option<String>,
+}
+
+impl TryFrom<AdminAuditRow> for AdminAuditEntry {
+    type Error = worker::Error;
+
+    fn try_from(row: AdminAuditRow) -> Result<Self> {
+        let invalid =
+            |e: &dyn std::fmt::Display| Error::RustError(format!("invalid audit row: {e}"));
+        Ok(Self {
+            adjustment_id: row.adjustment_id,
+            approvers: serde_json::from_str(&row.approvers)?,
+            reason: row.reason,
+            delta: row.delta.parse().map_err(|e| invalid(&e))?,
+            balance_before: row.balance_before.parse().map_err(|e| invalid(&e))?,
+            balance_after: row.balance_after.parse().map_err(|e| invalid(&e))?,
+            timestamp: row.timestamp,
+            error: row.error,
+        })
+    }
+}
+
+/// every adjustment of the user, oldest first
+pub async fn query_admin_audit(
+    env: &Env,
+    user_principal: Principal,
+) -> Result<Vec<AdminAuditEntry>> {
+    env.d1(ADMIN_AUDIT_DB)?
+        .prepare("SELECT * FROM admin_audit WHERE user_principal = ?1 ORDER BY timestamp, id")
+        .bind(&[user_principal.to_text().into()])?
+        .all()
+        .await?
+        .results::<AdminAuditRow>()?
+        .into_iter()
+        .map(AdminAuditEntry::try_from)
+        .collect()
+}
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    async fn append_admin_audit(&self, entry: &AdminAuditEntry) -> Result<()> {
+        let storage = self.storage();
+        let Some(owner) = ({ *self.owner.borrow_mut().read(&storage).await? }) else {
+            return Err(Error::RustError("DO owner unknown, can't audit".into()));
+        };
+
+        // entries are never updated, a failure is recorded as a new entry
+        self.env
+            .d1(ADMIN_AUDIT_DB)?
+            .prepare(
+                "INSERT INTO admin_audit \
+                (user_principal, adjustment_id, approvers, reason, delta, balance_before, balance_after, error, timestamp) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )
+            .bind(&[
+                owner.to_text().into(),
+                entry.adjustment_id.as_str().into(),
+                serde_json::to_string(&entry.approvers)?.into(),
+                entry.reason.as_str().into(),
+                entry.delta.to_string().into(),
+                entry.balance_before.to_string().into(),
+                entry.balance_after.to_string().into(),
+                entry
+                    .error
+                    .as_deref()
+                    .map(JsValue::from)
+                    .unwrap_or(JsValue::NULL),
+                JsValue::from_f64(entry.timestamp as f64),
+            ])?
+            .run()
+            .await?;
+
+        Ok(())
+    }
+
+    /// applies an admin correction, the audit entry is written before the balance changes
+    pub async fn admin_adjust(
+        &self,
+        req: AdminAdjustReq,
+    ) -> StdResult<AdminAuditEntry, (u16, WorkerError)> {
+        let mut storage = self.storage();
+        let marker_key = format!("{ADMIN_ADJUSTMENT_PREFIX}{}", req.adjustment_id);
+        if storage
+            .get::<u64>(&marker_key)
+            .await
+            .map_err(to_internal)?
+            .is_some()
+            || self.is_forgotten(&marker_key).await.map_err(to_internal)?
+        {
+            return Err((409, WorkerError::DuplicateAdminAdjustment));
+        }
+
+        let balance_before = self.balance_info().await.map_err(to_internal)?.balance;
+        let balance_after = (BigInt::from(balance_before.clone()) + &req.delta)
+            .to_biguint()
+            .ok_or((400, WorkerError::InsufficientFunds))?;
+
+        let mut entry = AdminAuditEntry {
+            adjustment_id: req.adjustment_id.clone(),
+            approvers: req.approvers.clone(),
+            reason: req.reason.clone(),
+            delta: req.delta.clone(),
+            balance_before: balance_before.clone(),
+            balance_after,
+            timestamp: Date::now().as_millis(),
+            error: None,
+        };
+        self.append_admin_audit(&entry).await.map_err(to_internal)?;
+        storage
+            .put(&marker_key, &entry.timestamp)
+            .await
+            .map_err(to_internal)?;
+
+        let mut reason = LedgerReason::new(BalanceUpdateReason::AdminAdjust, req.reason);
+        reason
+            .metadata
+            .insert("approvers".into(), req.approvers.join(","));
+        let res = self
+            .apply_delta(
+                Some(balance_before),
+                req.delta,
+                reason,
+                Some(req.adjustment_id),
+            )
+            .await;
+        if let Err((code, e)) = res {
+            entry.error = Some(e.to_string());
+            entry.timestamp = Date::now().as_millis();
+            self.append_admin_audit(&entry).await.map_err(to_internal)?;
+            // allow retrying under the same id
+            storage.delete(&marker_key).await.map_err(to_internal)?;
+
+            return Err((code, e));
+        }
+
+        Ok(entry)
+    }
+
+    /// entries audited in DO storage before the move to d1
+    pub async fn legacy_admin_audit(&self) -> Result<Vec<AdminAuditEntry>> {
+        self.storage()
+            .list_with_prefix(ADMIN_AUDIT_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect()
+    }
+
+    /// the user's adjustments, oldest first
+    pub async fn admin_audit_log(&self) -> Result<Vec<AdminAuditEntry>> {
+        let mut log = self.legacy_admin_audit().await?;
+        let storage = self.storage();
+        if let Some(owner) = ({ *self.owner.borrow_mut().read(&storage).await? }) {
+            log.extend(query_admin_audit(&self.env, owner).await?);
+        }
+        log.sort_by_key(|entry| entry.timestamp);
+
+        Ok(log)
+    }
+}
@@ -0,0 +1,151 @@
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{CASHBACK_CAMPAIGN_PREFIX, CASHBACK_HISTORY_LIMIT},
+    error::WorkerError,
+    types::{
+        BalanceUpdateReason, CashbackCampaign, CashbackRecord, ConversionStatus, LedgerReason,
+        SpendWithCashbackReq, SpendWithCashbackRes,
+    },
+};
+
+pub(crate) const CASHBACK_PREFIX: &str = "cashback-";
+
+fn to_internal(e: worker::Error) -> (u16, WorkerError) {
+    (500, WorkerError::Internal(e.to_string()))
+}
+
+impl UserYralCoinState {
+    async fn cashback_campaign(
+        &self,
+        campaign_id: &str,
+    ) -> StdResult<CashbackCampaign, (u16, WorkerError)> {
+        let campaign = self
+            .env
+            .kv("YRAL_COIN_CONFIG")
+            .map_err(to_internal)?
+            .get(&format!("{CASHBACK_CAMPAIGN_PREFIX}{campaign_id}"))
+            .json::<CashbackCampaign>()
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?
+            .ok_or((404, WorkerError::CampaignNotFound))?;
+
+        if !campaign.is_live(Date::now().as_millis()) {
+            return Err((400, WorkerError::CampaignInactive));
+        }
+
+        Ok(campaign)
+    }
+
+    async fn save_cashback(&self, record: &CashbackRecord) -> StdResult<(), (u16, WorkerError)> {
+        self.storage()
+            .put(format!("{CASHBACK_PREFIX}{}", record.id), record)
+            .await
+            .map_err(to_internal)
+    }
+
+    /// spends SATS held in the hot or not state and credits YRAL cashback
+    ///
+    /// same saga as `convert_sats`, the spend is recorded as pending,
+    /// SATS are deducted, then the cashback is credited
+    /// a failed cashback credit refunds the deducted SATS
+    pub async fn spend_with_cashback(
+        &self,
+        req: SpendWithCashbackReq,
+    ) -> StdResult<SpendWithCashbackRes, (u16, WorkerError)> {
+        let key = format!("{CASHBACK_PREFIX}{}", req.nonce);
+        let existing = self
+            .storage()
+            .get::<CashbackRecord>(&key)
+            .await
+            .map_err(to_internal)?;
+        if existing.is_some() || self.is_forgotten(&key).await.map_err(to_internal)? {
+            return Err((409, WorkerError::DuplicateCashbackSpend));
+        }
+
+        let campaign = self.cashback_campaign(&req.campaign_id).await?;
+        if req.sats_amount < BigUint::from(campaign.min_spend_sats) {
+            return Err((400, WorkerError::CashbackSpendTooSmall));
+        }
+        let cashback = campaign.cashback_for(&req.sats_amount);
+        if cashback == BigUint::ZERO {
+            return Err((400, WorkerError::CashbackSpendTooSmall));
+        }
+
+        let mut record = CashbackRecord {
+            id: req.nonce.clone(),
+            campaign_id: campaign.id.clone(),
+            sats_spent: req.sats_amount.clone(),
+            yral_cashback: cashback.clone(),
+            status: ConversionStatus::Pending,
+            created_at: Date::now().as_millis(),
+        };
+        self.save_cashback(&record).await?;
+
+        let user_principal = req.user_principal.to_text();
+        let sats_delta = BigInt::from(req.sats_amount);
+        if let Err(e) = self
+            .update_sats_balance(&user_principal, -sats_delta.clone())
+            .await
+        {
+            record.status = ConversionStatus::Aborted;
+            self.save_cashback(&record).await?;
+            return Err(e);
+        }
+
+        let mut reason = LedgerReason::new(BalanceUpdateReason::Campaign, "sats spend cashback");
+        reason
+            .metadata
+            .insert("campaign_id".into(), campaign.id.clone());
+        let credit_res = self
+            .apply_delta(
+                None,
+                BigInt::from(cashback.clone()),
+                reason,
+                Some(req.nonce),
+            )
+            .await;
+        let new_balance = match credit_res {
+            Ok(new_balance) => new_balance,
+            Err(e) => {
+                record.status = match self.update_sats_balance(&user_principal, sats_delta).await {
+                    Ok(()) => ConversionStatus::Refunded,
+                    Err((_, refund_err)) => {
+                        console_error!(
+                            "failed to refund sats for cashback spend {}: {refund_err}",
+                            record.id
+                        );
+                        ConversionStatus::RefundFailed
+                    }
+                };
+                self.save_cashback(&record).await?;
+                return Err(e);
+            }
+        };
+
+        record.status = ConversionStatus::Committed;
+        self.save_cashback(&record).await?;
+
+        Ok(SpendWithCashbackRes {
+            sats_spent: record.sats_spent,
+            yral_cashback: cashback,
+            new_balance,
+        })
+    }
+
+    pub async fn cashbacks(&self) -> Result<Vec<CashbackRecord>> {
+        let mut records = self
+            .storage()
+            .list_with_prefix(CASHBACK_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect::<Result<Vec<CashbackRecord>>>()?;
+        records.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        records.truncate(CASHBACK_HISTORY_LIMIT);
+
+        Ok(records)
+    }
+}
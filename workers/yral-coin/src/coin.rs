@@ -1,28 +1,140 @@
+use candid::Principal;
 use num_bigint::{BigInt, BigUint};
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::result::Result as StdResult;
 use worker::*;
 use worker_utils::{
-    err_to_resp,
-    storage::{daily_cumulative_limit::DailyCumulativeLimit, SafeStorage, StorageCell},
+    api_error::{error_resp, ApiError},
+    backup::{self, Backup},
+    metrics::Metrics,
+    notification::{Notification, NotificationJob},
+    storage::{
+        balance::{broadcast_to_websockets, BalanceEngine, BalanceError, Currency, DailyLimits},
+        batch::WriteBatch,
+        cumulative_limit::CumulativeLimit,
+        rate_limit::RateLimit,
+        SafeStorage, StorageCell,
+    },
 };
 
 use crate::{
     consts::{
-        MAX_CREDITED_PER_DAY_PER_USER_YRAL, MAX_DEDUCTED_PER_DAY_PER_USER_YRAL,
-        YRAL_CREDITED_STORAGE_KEY, YRAL_DEDUCTED_STORAGE_KEY,
+        DAILY_LIMITS_KEY, DEFAULT_CREDIT_NOTIFICATION_THRESHOLD_YRAL,
+        DEFAULT_MAX_CREDITED_PER_DAY_PER_USER_YRAL, DEFAULT_MAX_DEDUCTED_PER_DAY_PER_USER_YRAL,
+        DEFAULT_MAX_WITHDRAWAL_PER_DAY_PER_USER_YRAL, DEFAULT_UPDATE_BALANCE_TARGET_MAX_PER_WINDOW,
+        IDEMPOTENCY_KEY_TTL_MS, OWNER_HEADER, UPDATE_BALANCE_RATE_LIMIT_WINDOW_MS,
+        YRAL_CREDITED_STORAGE_KEY, YRAL_DEDUCTED_STORAGE_KEY, YRAL_TREASURY_STORAGE_KEY,
     },
+    edge_cache::{not_modified, purge_balance},
     error::WorkerError,
-    types::{YralBalanceInfo, YralBalanceUpdateRequest},
+    ledger::CoinLedger,
+    rate_limiter::{limit_from_env, update_rate_limited_response},
+    treasury::YralTreasuryImpl,
+    types::{
+        AdminAdjustReq, BalanceBreakdown, CaptureReq, ConvertReq, HoldReq, LedgerEntry,
+        LedgerReason, LimitOverrides, LimitsStatus, MemoizedUpdate, MinBalancePolicy, PromoGrant,
+        RateLimitScope, RedeemReq, ReleaseReq, SpendWithCashbackReq, TransactionsReq, TransferReq,
+        UpdateOutcome, UserLimits, WebhookSubscription, WithdrawReq, WithdrawalStatus,
+        YralBalanceInfo, YralBalanceUpdateRequest,
+    },
 };
 
+pub struct Yral;
+
+impl Currency for Yral {
+    const BALANCE_KEY: &'static str = "yral_balance_v0";
+    const CREDITED_KEY: &'static str = YRAL_CREDITED_STORAGE_KEY;
+    const DEDUCTED_KEY: &'static str = YRAL_DEDUCTED_STORAGE_KEY;
+}
+
+pub(crate) fn balance_err(e: BalanceError) -> (u16, WorkerError) {
+    match e {
+        BalanceError::CreditLimitReached => (400, WorkerError::YralCreditLimitReached),
+        BalanceError::DeductLimitReached => (400, WorkerError::YralDeductLimitReached),
+        BalanceError::Conflict { new_balance } => {
+            (409, WorkerError::BalanceTransactionConflict { new_balance })
+        }
+        BalanceError::InsufficientFunds => (400, WorkerError::InsufficientFunds),
+        BalanceError::Storage(e) => (500, WorkerError::Internal(e.to_string())),
+    }
+}
+
+/// the `BalanceBreakdown` and metrics label of a ledger entry
+fn reason_label(entry: &LedgerEntry) -> String {
+    entry
+        .reason_code
+        .as_ref()
+        .map(|code| format!("{code:?}"))
+        .unwrap_or_else(|| "Other".into())
+}
+
+/// a notification for credits above `CREDIT_NOTIFICATION_THRESHOLD_YRAL` or campaign rewards
+fn credit_notification(env: &Env, req: &YralBalanceUpdateRequest) -> Option<Notification> {
+    if req.delta <= BigInt::ZERO {
+        return None;
+    }
+    let threshold = env
+        .var("CREDIT_NOTIFICATION_THRESHOLD_YRAL")
+        .ok()
+        .and_then(|v| v.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_CREDIT_NOTIFICATION_THRESHOLD_YRAL);
+    let amount = req.delta.to_string();
+    if req.campaign {
+        Some(Notification::CampaignReward { amount })
+    } else if req.delta >= BigInt::from(threshold) {
+        Some(Notification::CoinsCredited { amount })
+    } else {
+        None
+    }
+}
+
+/// the part of the balance a debit can't touch
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Reserve {
+    /// held coins can only be spent through a capture,
+    /// other spends draw from the promotional and then the airdropped coins
+    Held,
+    /// only earned coins may leave the platform
+    Withdrawable,
+}
+
 #[durable_object]
 pub struct UserYralCoinState {
-    state: State,
+    pub(crate) state: State,
     pub(crate) env: Env,
-    yral_balance: RefCell<StorageCell<BigUint>>,
-    yral_credited: RefCell<DailyCumulativeLimit<{ MAX_CREDITED_PER_DAY_PER_USER_YRAL }>>,
-    yral_deducted: RefCell<DailyCumulativeLimit<{ MAX_DEDUCTED_PER_DAY_PER_USER_YRAL }>>,
+    pub(crate) yral: RefCell<BalanceEngine<Yral>>,
+    airdropped: RefCell<StorageCell<BigUint>>,
+    /// coins reserved by pending holds, not spendable until released
+    pub(crate) held: RefCell<StorageCell<BigUint>>,
+    /// promotional credits, part of the balance until they expire
+    pub(crate) promo_grants: RefCell<StorageCell<Vec<PromoGrant>>>,
+    /// limits before per-user overrides, loaded once per DO instance
+    base_limits: RefCell<Option<UserLimits>>,
+    /// per reason credit/debit totals, see `BalanceBreakdown`
+    pub(crate) reason_totals: RefCell<StorageCell<BalanceBreakdown>>,
+    pub(crate) ledger: RefCell<CoinLedger>,
+    update_rate_limit: RefCell<RateLimit>,
+    /// incremented on every balance change, used as the ETag for balance reads
+    balance_version: RefCell<StorageCell<u64>>,
+    pub(crate) limit_overrides: RefCell<StorageCell<LimitOverrides>>,
+    /// the user this DO belongs to, learned from `OWNER_HEADER`
+    pub(crate) owner: RefCell<StorageCell<Option<Principal>>>,
+    /// (fetched_at, subscriptions), see `cached_webhooks`
+    pub(crate) webhooks: RefCell<Option<(u64, Vec<WebhookSubscription>)>>,
+    /// `None` if the treasury couldn't be set up, withdrawals fail until it can
+    pub(crate) treasury: Option<YralTreasuryImpl>,
+    /// daily cap on withdrawals to the ledger
+    pub(crate) treasury_amount: RefCell<CumulativeLimit>,
+    /// nonces of withdrawals being processed by this instance, see `withdraw`
+    pub(crate) withdrawals_in_flight: RefCell<HashSet<String>>,
+    /// nonces of transfers being processed by this instance, see `transfer`
+    pub(crate) transfers_in_flight: RefCell<HashSet<String>>,
+    /// loaded once per DO instance, see `min_balance_policy`
+    pub(crate) min_balance_policy: RefCell<Option<MinBalancePolicy>>,
+    /// unix millis of the last owner initiated request, see `touch_activity`
+    pub(crate) last_activity: RefCell<StorageCell<u64>>,
+    metrics: Metrics,
 }
 
 impl UserYralCoinState {
@@ -35,140 +147,1019 @@ impl UserYralCoinState {
     // The RefCell interior mutability pattern is required due to Worker 0.7.4 API changes
     // that mandate `&self` instead of `&mut self` for DurableObject trait methods.
     #[allow(clippy::await_holding_refcell_ref)]
-    async fn broadcast_balance_inner(&self) -> Result<()> {
+    pub(crate) async fn balance_info(&self) -> Result<YralBalanceInfo> {
         let storage = self.storage();
-        let balance = { self.yral_balance.borrow_mut().read(&storage).await?.clone() };
-        let bal = YralBalanceInfo { balance };
-        for ws in self.state.get_websockets() {
-            let err = ws.send(&bal);
-            if let Err(e) = err {
-                console_warn!("failed to broadcast balance update: {e}");
-            }
+        let balance = { self.yral.borrow_mut().balance(&storage).await? };
+        let airdropped = { self.airdropped.borrow_mut().read(&storage).await?.clone() };
+        let held = { self.held.borrow_mut().read(&storage).await?.clone() };
+        let promotional = {
+            self.promo_grants
+                .borrow_mut()
+                .read(&storage)
+                .await?
+                .iter()
+                .map(|grant| &grant.amount)
+                .sum()
+        };
+
+        Ok(YralBalanceInfo {
+            balance,
+            airdropped,
+            held,
+            promotional,
+        })
+    }
+
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn balance_version(&self) -> Result<u64> {
+        let storage = self.storage();
+        let version = { *self.balance_version.borrow_mut().read(&storage).await? };
+
+        Ok(version)
+    }
+
+    /// bumps the balance version, invalidating cached reads
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn bump_balance_version(&self) -> Result<()> {
+        let mut storage = self.storage();
+        {
+            self.balance_version
+                .borrow_mut()
+                .update(&mut storage, |version| *version += 1)
+                .await?;
         }
+        purge_balance(&self.state.id().to_string()).await;
 
         Ok(())
     }
 
-    async fn broadcast_balance(&self) {
+    async fn broadcast_balance_inner(&self) -> Result<()> {
+        self.bump_balance_version().await?;
+        let bal = self.balance_info().await?;
+        broadcast_to_websockets(&self.state, &bal);
+
+        Ok(())
+    }
+
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn record_owner(&self, owner: &str) -> Result<()> {
+        let mut storage = self.storage();
+        let mut cell = self.owner.borrow_mut();
+        if cell.read(&storage).await?.is_some() {
+            return Ok(());
+        }
+        let Ok(owner) = Principal::from_text(owner) else {
+            console_warn!("ignoring invalid owner header {owner}");
+            return Ok(());
+        };
+
+        cell.set(&mut storage, Some(owner)).await
+    }
+
+    /// drops every in-memory copy of stored state
+    /// required after storage is modified behind the cells' back, e.g. `delete_all`
+    pub(crate) fn invalidate_cached_state(&self) {
+        self.yral.borrow_mut().invalidate();
+        self.airdropped.borrow_mut().invalidate();
+        self.held.borrow_mut().invalidate();
+        self.promo_grants.borrow_mut().invalidate();
+        self.reason_totals.borrow_mut().invalidate();
+        self.ledger.borrow_mut().invalidate();
+        self.update_rate_limit.borrow_mut().invalidate();
+        self.balance_version.borrow_mut().invalidate();
+        self.limit_overrides.borrow_mut().invalidate();
+        self.owner.borrow_mut().invalidate();
+        self.treasury_amount.borrow_mut().invalidate();
+        self.last_activity.borrow_mut().invalidate();
+    }
+
+    pub(crate) async fn broadcast_balance(&self) {
         if let Err(e) = self.broadcast_balance_inner().await {
             console_error!("failed to read balance data: {e}");
         }
     }
 
-    // SAFETY: See comment on broadcast_balance_inner for safety rationale
+    /// daily limits for this user, including admin overrides
+    // SAFETY: See comment on balance_info for safety rationale
     #[allow(clippy::await_holding_refcell_ref)]
-    pub async fn update_balance_for_external_client(
+    pub(crate) async fn limits(&self) -> Result<UserLimits> {
+        let storage = self.storage();
+        let overrides = {
+            self.limit_overrides
+                .borrow_mut()
+                .read(&storage)
+                .await?
+                .clone()
+        };
+
+        let base = self.base_limits().await;
+
+        Ok(UserLimits {
+            max_credited_per_day: overrides
+                .max_credited_per_day
+                .unwrap_or(base.max_credited_per_day),
+            max_deducted_per_day: overrides
+                .max_deducted_per_day
+                .unwrap_or(base.max_deducted_per_day),
+        })
+    }
+
+    /// `limits` along with the allowance left in each window
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub(crate) async fn limits_status(&self) -> Result<LimitsStatus> {
+        let limits = self.limits().await?;
+        let storage = self.storage();
+        let (credit, deduct) = self
+            .yral
+            .borrow_mut()
+            .remaining_today(&storage, limits.into())
+            .await?;
+        let withdrawal = self
+            .treasury_amount
+            .borrow_mut()
+            .remaining(&storage)
+            .await?;
+
+        Ok(LimitsStatus {
+            limits,
+            credit: credit.into(),
+            deduct: deduct.into(),
+            withdrawal: withdrawal.into(),
+        })
+    }
+
+    /// limits from env vars, overridable at runtime through `DAILY_LIMITS_KEY` in KV
+    async fn base_limits(&self) -> UserLimits {
+        if let Some(limits) = *self.base_limits.borrow() {
+            return limits;
+        }
+
+        let mut limits = UserLimits::from(self.yral.borrow().limits());
+        let kv_limits = async {
+            self.env
+                .kv("YRAL_COIN_CONFIG")?
+                .get(DAILY_LIMITS_KEY)
+                .json::<LimitOverrides>()
+                .await
+        };
+        match kv_limits.await {
+            Ok(Some(kv_limits)) => {
+                limits.max_credited_per_day = kv_limits
+                    .max_credited_per_day
+                    .unwrap_or(limits.max_credited_per_day);
+                limits.max_deducted_per_day = kv_limits
+                    .max_deducted_per_day
+                    .unwrap_or(limits.max_deducted_per_day);
+            }
+            Ok(None) => (),
+            // not cached, retried on next use
+            Err(e) => {
+                console_warn!("failed to read daily limits from KV: {e}");
+                return limits;
+            }
+        }
+
+        *self.base_limits.borrow_mut() = Some(limits);
+        limits
+    }
+
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn set_limit_overrides(&self, overrides: LimitOverrides) -> Result<UserLimits> {
+        let old_limits = self.limits().await?;
+        let mut storage = self.storage();
+        {
+            self.limit_overrides
+                .borrow_mut()
+                .set(&mut storage, overrides)
+                .await?;
+        }
+        let new_limits = self.limits().await?;
+
+        {
+            self.yral
+                .borrow_mut()
+                .rebase_limits(&mut storage, old_limits.into(), new_limits.into())
+                .await?;
+        }
+
+        Ok(new_limits)
+    }
+
+    /// stages `entry` and its per reason totals in `batch`, returns the entry's sequence
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn stage_ledger_entry(
         &self,
-        expected_balance: BigUint,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        entry: &LedgerEntry,
+    ) -> Result<u64> {
+        let seq = {
+            self.ledger
+                .borrow_mut()
+                .append_in(storage, batch, entry)
+                .await?
+        };
+        {
+            self.reason_totals
+                .borrow_mut()
+                .update_in(storage, batch, |breakdown| {
+                    let totals = breakdown.entry(reason_label(entry)).or_default();
+                    if entry.delta >= BigInt::ZERO {
+                        totals.credited += entry.delta.to_biguint().unwrap();
+                    } else {
+                        totals.deducted += entry.delta.magnitude();
+                    }
+                })
+                .await?;
+        }
+
+        Ok(seq)
+    }
+
+    /// commits `batch` along with `entry`, then hands the entry to its consumers
+    /// on failure nothing in `batch` was written
+    pub(crate) async fn commit_with_ledger_entry(
+        &self,
+        mut batch: WriteBatch,
+        entry: LedgerEntry,
+    ) -> Result<()> {
+        let mut storage = self.storage();
+        let committed = async {
+            let seq = self
+                .stage_ledger_entry(&storage, &mut batch, &entry)
+                .await?;
+            batch.commit(&mut storage).await?;
+            Ok::<_, worker::Error>(seq)
+        };
+        let seq = committed
+            .await
+            .inspect_err(|_| self.invalidate_cached_state())?;
+
+        self.publish_ledger_entry(seq, &entry).await;
+        self.broadcast_balance().await;
+
+        Ok(())
+    }
+
+    /// best effort, the entry is already committed
+    async fn publish_ledger_entry(&self, seq: u64, entry: &LedgerEntry) {
+        if let Err(e) = self.stream_to_global_ledger(seq, entry).await {
+            console_error!("failed to stream ledger entry: {e}");
+        }
+
+        let reason = reason_label(entry);
+        let direction = if entry.delta >= BigInt::ZERO {
+            "credit"
+        } else {
+            "debit"
+        };
+        self.metrics
+            .counter("coin_balance_updates", &[&reason, direction]);
+        self.metrics.histogram(
+            "coin_balance_delta",
+            &[&reason, direction],
+            u64::try_from(entry.delta.magnitude()).map_or(f64::MAX, |d| d as f64),
+        );
+
+        if let Err(e) = self.queue_balance_webhooks(entry).await {
+            console_error!("failed to queue balance webhooks: {e}");
+        }
+    }
+
+    /// applies `delta` to the balance after consuming the daily limits
+    /// if `expected_balance` is set, the update fails on mismatch
+    pub(crate) async fn apply_delta(
+        &self,
+        expected_balance: Option<BigUint>,
         delta: BigInt,
+        reason: LedgerReason,
+        idempotency_key: Option<String>,
     ) -> StdResult<BigUint, (u16, WorkerError)> {
-        let mut storage = self.storage();
-        if delta >= BigInt::ZERO {
-            let result = {
-                self.yral_credited
+        self.apply_delta_in(
+            self.storage().batch(),
+            expected_balance,
+            delta,
+            reason,
+            idempotency_key,
+        )
+        .await
+    }
+
+    /// same as `apply_delta`, committing the writes staged in `batch` with the update
+    /// nothing in `batch` is written if the update fails
+    pub(crate) async fn apply_delta_in(
+        &self,
+        batch: WriteBatch,
+        expected_balance: Option<BigUint>,
+        delta: BigInt,
+        reason: LedgerReason,
+        idempotency_key: Option<String>,
+    ) -> StdResult<BigUint, (u16, WorkerError)> {
+        self.apply_delta_reserving(
+            batch,
+            expected_balance,
+            delta,
+            reason,
+            idempotency_key,
+            Reserve::Held,
+        )
+        .await
+    }
+
+    /// debits coins that may leave the platform
+    pub(crate) async fn debit_withdrawable(
+        &self,
+        amount: BigUint,
+        reason: LedgerReason,
+        idempotency_key: Option<String>,
+    ) -> StdResult<BigUint, (u16, WorkerError)> {
+        self.apply_delta_reserving(
+            self.storage().batch(),
+            None,
+            -BigInt::from(amount),
+            reason,
+            idempotency_key,
+            Reserve::Withdrawable,
+        )
+        .await
+    }
+
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn apply_delta_reserving(
+        &self,
+        mut batch: WriteBatch,
+        expected_balance: Option<BigUint>,
+        delta: BigInt,
+        reason: LedgerReason,
+        idempotency_key: Option<String>,
+        reserve: Reserve,
+    ) -> StdResult<BigUint, (u16, WorkerError)> {
+        let storage = self.storage();
+        let info = self
+            .balance_info()
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        let reserved = match reserve {
+            Reserve::Held => info.held,
+            Reserve::Withdrawable => info.held + info.airdropped + info.promotional,
+        };
+        let limits = self
+            .limits()
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        let staged = async {
+            let new_bal = {
+                self.yral
                     .borrow_mut()
-                    .try_consume(&mut storage, delta.to_biguint().unwrap())
+                    .apply_with_limits_in(
+                        &storage,
+                        &mut batch,
+                        expected_balance.as_ref(),
+                        &delta,
+                        &reserved,
+                        limits.into(),
+                    )
                     .await
+                    .map_err(balance_err)?
             };
-            result.map_err(|_| (400, WorkerError::YralCreditLimitReached))?;
-        } else {
-            let result = {
-                self.yral_deducted
-                    .borrow_mut()
-                    .try_consume(&mut storage, (-delta.clone()).to_biguint().unwrap())
+            if reserve == Reserve::Held && delta < BigInt::ZERO {
+                self.stage_bonus_spend(&storage, &mut batch, delta.magnitude().clone())
                     .await
-            };
-            result.map_err(|_| (400, WorkerError::YralDeductLimitReached))?;
+                    .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+            }
+            Ok::<_, (u16, WorkerError)>(new_bal)
+        };
+        let new_bal = staged
+            .await
+            .inspect_err(|_| self.invalidate_cached_state())?;
+
+        self.commit_with_ledger_entry(
+            batch,
+            LedgerEntry {
+                delta,
+                reason: reason.description,
+                resulting_balance: new_bal.clone(),
+                timestamp: Date::now().as_millis(),
+                idempotency_key,
+                reason_code: reason.code,
+                metadata: reason.metadata,
+            },
+        )
+        .await
+        .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+
+        Ok(new_bal)
+    }
+
+    /// spends draw from the expiring promotional coins first, then from airdropped ones
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn stage_bonus_spend(
+        &self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        amount: BigUint,
+    ) -> Result<()> {
+        let rest = self.stage_promo_spend(storage, batch, amount).await?;
+        if rest == BigUint::ZERO {
+            return Ok(());
         }
 
+        self.airdropped
+            .borrow_mut()
+            .update_in(storage, batch, |airdropped| {
+                *airdropped -= rest.min(airdropped.clone());
+            })
+            .await
+    }
+
+    /// compensates a `delta` previously applied with `apply_delta`
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub(crate) async fn revert_delta(
+        &self,
+        delta: BigInt,
+        reason: LedgerReason,
+    ) -> StdResult<BigUint, (u16, WorkerError)> {
+        let storage = self.storage();
+        let mut batch = storage.batch();
+        let limits = self
+            .limits()
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
         let new_bal = {
-            self.yral_balance
+            self.yral
                 .borrow_mut()
-                .try_get_update(&mut storage, |balance| {
-                    if expected_balance != *balance {
-                        return Err((
-                            409,
-                            WorkerError::BalanceTransactionConflict {
-                                new_balance: balance.clone(),
-                            },
-                        ));
-                    }
-                    let delta = delta.clone();
-                    if delta >= BigInt::ZERO {
-                        let delta = delta.to_biguint().unwrap();
-                        *balance += delta;
-                        return Ok(());
-                    }
-                    let neg_delta = (-delta).to_biguint().unwrap();
-                    if neg_delta > *balance {
-                        return Err((400, WorkerError::InsufficientFunds));
-                    }
-                    *balance -= neg_delta;
-
-                    Ok(())
-                })
+                .revert_with_limits_in(&storage, &mut batch, &delta, limits.into())
                 .await
-                .map_err(|e| match e {
-                    Ok(e) => e,
-                    Err(e) => (500, WorkerError::Internal(e.to_string())),
-                })?
+                .map_err(balance_err)?
         };
 
-        self.broadcast_balance().await;
+        self.commit_with_ledger_entry(
+            batch,
+            LedgerEntry {
+                delta: -delta,
+                reason: reason.description,
+                resulting_balance: new_bal.clone(),
+                timestamp: Date::now().as_millis(),
+                idempotency_key: None,
+                reason_code: reason.code,
+                metadata: reason.metadata,
+            },
+        )
+        .await
+        .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+
+        Ok(new_bal)
+    }
+
+    // SAFETY: See comment on balance_info for safety rationale
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn update_balance_for_external_client(
+        &self,
+        expected_balance: BigUint,
+        delta: BigInt,
+        reason: LedgerReason,
+        idempotency_key: Option<String>,
+        is_airdropped: bool,
+        promo_expires_at: Option<u64>,
+    ) -> StdResult<BigUint, (u16, WorkerError)> {
+        if is_airdropped && delta < BigInt::ZERO {
+            return Err((400, WorkerError::InvalidAirdropDelta));
+        }
+        if let Some(expires_at) = promo_expires_at {
+            if delta <= BigInt::ZERO || expires_at <= Date::now().as_millis() {
+                return Err((400, WorkerError::InvalidPromoCredit));
+            }
+        }
+
+        self.check_min_balance(&delta, reason.code).await?;
+
+        // the bonus buckets are committed with the credit itself
+        let storage = self.storage();
+        let mut batch = storage.batch();
+        let staged = async {
+            if let Some(expires_at) = promo_expires_at {
+                self.stage_promo_grant(&storage, &mut batch, delta.magnitude().clone(), expires_at)
+                    .await?;
+            }
+            if is_airdropped {
+                self.airdropped
+                    .borrow_mut()
+                    .update_in(&storage, &mut batch, |airdropped| {
+                        *airdropped += delta.magnitude();
+                    })
+                    .await?;
+            }
+            Ok::<_, worker::Error>(())
+        };
+        staged.await.map_err(|e| {
+            self.invalidate_cached_state();
+            (500, WorkerError::Internal(e.to_string()))
+        })?;
+
+        let new_bal = self
+            .apply_delta_in(
+                batch,
+                Some(expected_balance),
+                delta,
+                reason,
+                idempotency_key,
+            )
+            .await?;
+        if let Some(expires_at) = promo_expires_at {
+            if let Err(e) = self.schedule_alarm(expires_at).await {
+                console_error!("failed to schedule promotional coin expiry: {e}");
+            }
+        }
 
         Ok(new_bal)
     }
+
+    fn idempotency_storage_key(key: &str) -> String {
+        format!("idempotency-{key}")
+    }
+
+    /// same as `update_balance_for_external_client`, but replays the stored outcome
+    /// if `idempotency_key` was seen in the last 24h
+    pub async fn update_balance_idempotent(
+        &self,
+        req: YralBalanceUpdateRequest,
+    ) -> Result<UpdateOutcome> {
+        let Some(key) = req.idempotency_key.clone() else {
+            return Ok(self.update_balance_inner(req).await);
+        };
+        let storage_key = Self::idempotency_storage_key(&key);
+        let mut storage = self.storage();
+        let now = Date::now().as_millis();
+
+        let memoized = storage.get::<MemoizedUpdate>(&storage_key).await?;
+        if let Some(memoized) = memoized {
+            if now - memoized.created_at < IDEMPOTENCY_KEY_TTL_MS {
+                return Ok(memoized.outcome);
+            }
+        }
+
+        let outcome = self.update_balance_inner(req).await;
+        // internal errors are transient, retries should go through
+        if matches!(outcome, UpdateOutcome::Err { code: 500, .. }) {
+            return Ok(outcome);
+        }
+
+        let memoized = MemoizedUpdate {
+            outcome,
+            created_at: now,
+        };
+        storage.put(&storage_key, &memoized).await?;
+        self.schedule_alarm(now + IDEMPOTENCY_KEY_TTL_MS).await?;
+
+        Ok(memoized.outcome)
+    }
+
+    /// a replayed update returns its memoized outcome before reaching here,
+    /// so the notification goes out once per ledger entry
+    async fn update_balance_inner(&self, req: YralBalanceUpdateRequest) -> UpdateOutcome {
+        let notification = credit_notification(&self.env, &req);
+        match self
+            .update_balance_for_external_client(
+                req.previous_balance,
+                req.delta,
+                LedgerReason {
+                    code: Some(req.reason),
+                    description: req.description,
+                    metadata: req.metadata,
+                },
+                req.idempotency_key,
+                req.is_airdropped,
+                req.promo_expires_at,
+            )
+            .await
+        {
+            Ok(new_bal) => {
+                if let Some(notification) = notification {
+                    self.notify_owner(notification).await;
+                }
+                UpdateOutcome::Ok(new_bal)
+            }
+            Err((code, error)) => UpdateOutcome::Err { code, error },
+        }
+    }
+
+    /// queued in the background, the update doesn't wait on the notifications queue
+    async fn notify_owner(&self, notification: Notification) {
+        let owner = match self.owner.borrow_mut().read(&self.storage()).await {
+            Ok(owner) => *owner,
+            Err(e) => {
+                console_error!("failed to read owner for a credit notification: {e}");
+                return;
+            }
+        };
+        let Some(owner) = owner else {
+            console_warn!("skipping credit notification, owner unknown");
+            return;
+        };
+
+        let job = NotificationJob::new(owner, notification);
+        let env = self.env.clone();
+        self.state.wait_until(async move {
+            if let Err(e) = job.enqueue(&env).await {
+                console_error!("failed to queue credit notification: {e}");
+            }
+        });
+    }
+
+    /// moves the alarm earlier if `at` (unix millis) is before the scheduled one
+    pub(crate) async fn schedule_alarm(&self, at: u64) -> Result<()> {
+        let storage = self.state.storage();
+        let at = at as i64;
+        if let Some(scheduled) = storage.get_alarm().await? {
+            if scheduled <= at {
+                return Ok(());
+            }
+        }
+        let offset = (at - Date::now().as_millis() as i64).max(0);
+
+        storage.set_alarm(offset).await
+    }
+
+    /// drops memoized outcomes older than the TTL
+    async fn cleanup_idempotency_keys(&self) -> Result<()> {
+        let mut storage = self.storage();
+        let now = Date::now().as_millis();
+        let entries = storage
+            .list_with_prefix::<MemoizedUpdate>("idempotency-")
+            .await
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut expired = Vec::new();
+        let mut next_expiry = None::<u64>;
+        for (key, memoized) in entries {
+            let expires_at = memoized.created_at + IDEMPOTENCY_KEY_TTL_MS;
+            if expires_at <= now {
+                expired.push(key);
+            } else {
+                next_expiry = Some(next_expiry.map_or(expires_at, |e| e.min(expires_at)));
+            }
+        }
+        if !expired.is_empty() {
+            storage.delete_multiple(expired).await?;
+        }
+
+        if let Some(next_expiry) = next_expiry {
+            self.schedule_alarm(next_expiry).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Backup for UserYralCoinState {
+    const NAMESPACE: &'static str = crate::USER_YRAL_COIN_STATE;
+
+    fn backup_state(&self) -> &State {
+        &self.state
+    }
+
+    fn backup_env(&self) -> &Env {
+        &self.env
+    }
+
+    fn invalidate_cached_state(&self) {
+        Self::invalidate_cached_state(self)
+    }
 }
 
 impl DurableObject for UserYralCoinState {
     fn new(state: State, env: Env) -> Self {
         console_error_panic_hook::set_once();
 
+        let limit_var = |name: &str| env.var(name).ok()?.to_string().parse::<u64>().ok();
+        let max_credited = limit_var("MAX_CREDITED_PER_DAY_PER_USER_YRAL")
+            .unwrap_or(DEFAULT_MAX_CREDITED_PER_DAY_PER_USER_YRAL);
+        let max_deducted = limit_var("MAX_DEDUCTED_PER_DAY_PER_USER_YRAL")
+            .unwrap_or(DEFAULT_MAX_DEDUCTED_PER_DAY_PER_USER_YRAL);
+        let max_withdrawal = limit_var("MAX_WITHDRAWAL_PER_DAY_PER_USER_YRAL")
+            .unwrap_or(DEFAULT_MAX_WITHDRAWAL_PER_DAY_PER_USER_YRAL);
+        let treasury = YralTreasuryImpl::new(&env)
+            .inspect_err(|e| console_error!("failed to create treasury: {e}"))
+            .ok();
+        let metrics = Metrics::new(&env, "yral-coin");
+        let update_target_max = limit_from_env(
+            &env,
+            "UPDATE_BALANCE_TARGET_MAX_PER_MINUTE",
+            DEFAULT_UPDATE_BALANCE_TARGET_MAX_PER_WINDOW,
+        );
+
         Self {
             state,
             env,
-            yral_balance: RefCell::new(StorageCell::new("yral_balance_v0", || BigUint::ZERO)),
-            yral_credited: RefCell::new(DailyCumulativeLimit::new(YRAL_CREDITED_STORAGE_KEY)),
-            yral_deducted: RefCell::new(DailyCumulativeLimit::new(YRAL_DEDUCTED_STORAGE_KEY)),
+            yral: RefCell::new(BalanceEngine::new(DailyLimits {
+                max_credited_per_day: max_credited,
+                max_deducted_per_day: max_deducted,
+            })),
+            airdropped: RefCell::new(StorageCell::new("yral_airdropped_v0", || BigUint::ZERO)),
+            held: RefCell::new(StorageCell::new("yral_held_v0", || BigUint::ZERO)),
+            promo_grants: RefCell::new(StorageCell::new("yral_promo_grants_v0", Vec::new)),
+            base_limits: RefCell::new(None),
+            reason_totals: RefCell::new(StorageCell::new(
+                "reason_totals_v0",
+                BalanceBreakdown::new,
+            )),
+            ledger: RefCell::new(CoinLedger::default()),
+            balance_version: RefCell::new(StorageCell::new("balance_version_v0", || 0)),
+            update_rate_limit: RefCell::new(RateLimit::new(
+                "update-balance-rate-limit",
+                update_target_max,
+                UPDATE_BALANCE_RATE_LIMIT_WINDOW_MS,
+            )),
+            limit_overrides: RefCell::new(StorageCell::new(
+                "limit_overrides_v0",
+                LimitOverrides::default,
+            )),
+            owner: RefCell::new(StorageCell::new("owner_v0", || None)),
+            webhooks: RefCell::new(None),
+            treasury,
+            treasury_amount: RefCell::new(CumulativeLimit::daily(
+                YRAL_TREASURY_STORAGE_KEY,
+                max_withdrawal,
+            )),
+            withdrawals_in_flight: RefCell::new(HashSet::new()),
+            transfers_in_flight: RefCell::new(HashSet::new()),
+            min_balance_policy: RefCell::new(None),
+            last_activity: RefCell::new(StorageCell::new("last_activity_v0", || 0)),
+            metrics,
         }
     }
 
-    async fn fetch(&self, req: Request) -> Result<Response> {
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        if let Some(res) = backup::handle(self, &mut req).await {
+            return res;
+        }
+        if let Some(owner) = req.headers().get(OWNER_HEADER)? {
+            self.record_owner(&owner).await?;
+            self.touch_activity().await?;
+        }
+
         let env = self.env.clone();
         let router = Router::with_data(self);
         router
-            .get_async("/balance", {
-                // SAFETY: See comment on broadcast_balance_inner for safety rationale
+            .get_async("/balance", async |req, ctx| {
+                let this = ctx.data;
+                let version = this.balance_version().await?;
+                let bal = this.balance_info().await?;
+                let res = Response::from_json(&bal)?;
+                res.headers().set("ETag", &format!("\"{version}\""))?;
+                if let Some(not_modified) = not_modified(&req, &res)? {
+                    return Ok(not_modified);
+                }
+
+                Ok(res)
+            })
+            .post_async("/update_balance", {
+                // SAFETY: See comment on balance_info for safety rationale
+                #[allow(clippy::await_holding_refcell_ref)]
+                async |mut req, ctx| {
+                    let req_data: YralBalanceUpdateRequest =
+                        serde_json::from_str(&req.text().await?)?;
+                    let this = ctx.data;
+
+                    let limited = {
+                        this.update_rate_limit
+                            .borrow_mut()
+                            .try_acquire(&mut this.storage())
+                            .await?
+                    };
+                    if let Err(retry_after_ms) = limited {
+                        return update_rate_limited_response(
+                            RateLimitScope::Target,
+                            &this.update_rate_limit.borrow(),
+                            retry_after_ms,
+                        );
+                    }
+
+                    match this.update_balance_idempotent(req_data).await? {
+                        UpdateOutcome::Ok(new_bal) => Response::ok(new_bal.to_string()),
+                        UpdateOutcome::Err { code, error } => {
+                            ApiError::from(error).into_response(code)
+                        }
+                    }
+                }
+            })
+            .post_async("/transactions", {
+                // SAFETY: See comment on balance_info for safety rationale
+                #[allow(clippy::await_holding_refcell_ref)]
+                async |mut req, ctx| {
+                    let req_data: TransactionsReq = req.json().await?;
+                    let this = ctx.data;
+                    let storage = this.storage();
+                    let res = {
+                        this.ledger
+                            .borrow()
+                            .page(&storage, req_data.page_size, req_data.cursor)
+                            .await?
+                    };
+
+                    Response::from_json(&res)
+                }
+            })
+            .post_async("/transfer", async |mut req, ctx| {
+                let req_data: TransferReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.transfer(req_data).await {
+                    Ok(new_bal) => Response::ok(new_bal.to_string()),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .post_async("/receive_transfer", async |mut req, ctx| {
+                let req_data: TransferReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.receive_transfer(req_data).await {
+                    Ok(new_bal) => Response::ok(new_bal.to_string()),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .post_async("/convert", async |mut req, ctx| {
+                let req_data: ConvertReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.convert_sats(req_data).await {
+                    Ok(res) => Response::from_json(&res),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .post_async("/spend_with_cashback", async |mut req, ctx| {
+                let req_data: SpendWithCashbackReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.spend_with_cashback(req_data).await {
+                    Ok(res) => Response::from_json(&res),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .get_async("/cashbacks", async |_, ctx| {
+                let this = ctx.data;
+                let cashbacks = this.cashbacks().await?;
+
+                Response::from_json(&cashbacks)
+            })
+            .get_async("/conversions", async |_, ctx| {
+                let this = ctx.data;
+                let conversions = this.conversions().await?;
+
+                Response::from_json(&conversions)
+            })
+            .get_async("/balance_breakdown", {
+                // SAFETY: See comment on balance_info for safety rationale
                 #[allow(clippy::await_holding_refcell_ref)]
                 async |_, ctx| {
                     let this = ctx.data;
                     let storage = this.storage();
-                    let balance = { this.yral_balance.borrow_mut().read(&storage).await?.clone() };
-                    Response::from_json(&YralBalanceInfo { balance })
+                    let breakdown = {
+                        this.reason_totals
+                            .borrow_mut()
+                            .read(&storage)
+                            .await?
+                            .clone()
+                    };
+
+                    Response::from_json(&breakdown)
                 }
             })
-            .post_async("/update_balance", async |mut req, ctx| {
-                let req_data: YralBalanceUpdateRequest = serde_json::from_str(&req.text().await?)?;
+            .get_async("/snapshot", async |_, ctx| {
                 let this = ctx.data;
+                let snapshot = this.snapshot().await?;
 
-                match this
-                    .update_balance_for_external_client(req_data.previous_balance, req_data.delta)
-                    .await
-                {
+                Response::from_json(&snapshot)
+            })
+            .post_async("/withdraw", async |mut req, ctx| {
+                let req_data: WithdrawReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.withdraw(req_data).await {
+                    Ok(receipt) if receipt.status == WithdrawalStatus::Pending => {
+                        Ok(Response::from_json(&receipt)?.with_status(202))
+                    }
+                    Ok(receipt) => Response::from_json(&receipt),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .get_async("/withdrawals", async |_, ctx| {
+                let this = ctx.data;
+                let withdrawals = this.withdrawals().await?;
+
+                Response::from_json(&withdrawals)
+            })
+            .get_async("/export", async |_, ctx| {
+                let this = ctx.data;
+                let export = this.export_data().await?;
+
+                Response::from_json(&export)
+            })
+            .post_async("/forget", async |_, ctx| {
+                let this = ctx.data;
+
+                match this.forget().await {
+                    Ok(()) => Response::ok("forgotten"),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .get_async("/limits", async |_, ctx| {
+                let this = ctx.data;
+                let status = this.limits_status().await?;
+
+                Response::from_json(&status)
+            })
+            .post_async("/limits", async |mut req, ctx| {
+                let overrides: LimitOverrides = req.json().await?;
+                let this = ctx.data;
+                let limits = this.set_limit_overrides(overrides).await?;
+
+                Response::from_json(&limits)
+            })
+            .post_async("/hold", async |mut req, ctx| {
+                let req_data: HoldReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.hold(req_data).await {
+                    Ok(hold) => Response::from_json(&hold),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .post_async("/capture", async |mut req, ctx| {
+                let req_data: CaptureReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.capture(req_data).await {
                     Ok(new_bal) => Response::ok(new_bal.to_string()),
-                    Err((code, msg)) => err_to_resp(code, msg),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .post_async("/release", async |mut req, ctx| {
+                let req_data: ReleaseReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.release(&req_data.hold_id).await {
+                    Ok(()) => Response::ok("released"),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .post_async("/redeem", async |mut req, ctx| {
+                let req_data: RedeemReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.redeem(req_data).await {
+                    Ok(receipt) => Response::from_json(&receipt),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .get_async("/redemptions", async |_, ctx| {
+                let this = ctx.data;
+                let receipts = this.redemptions().await?;
+
+                Response::from_json(&receipts)
+            })
+            .post_async("/admin_adjust", async |mut req, ctx| {
+                let req_data: AdminAdjustReq = req.json().await?;
+                let this = ctx.data;
+
+                match this.admin_adjust(req_data).await {
+                    Ok(entry) => Response::from_json(&entry),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
+                }
+            })
+            .get_async("/admin_audit", async |_, ctx| {
+                let this = ctx.data;
+                let log = this.legacy_admin_audit().await?;
+
+                Response::from_json(&log)
+            })
+            .post_async("/claim_daily_bonus", async |_, ctx| {
+                let this = ctx.data;
+
+                match this.claim_daily_bonus().await {
+                    Ok(res) => Response::from_json(&res),
+                    Err((code, msg)) => ApiError::from(msg).into_response(code),
                 }
             })
             .get_async("/ws/balance", |req, ctx| async move {
                 let upgrade = req.headers().get("Upgrade")?;
                 if upgrade.as_deref() != Some("websocket") {
-                    return Response::error("expected websocket", 400);
+                    return error_resp("expected websocket", 400);
                 }
 
                 let pair = WebSocketPair::new()?;
                 let this = ctx.data;
                 this.state.accept_web_socket(&pair.server);
-                this.broadcast_balance().await;
+                pair.server.send(&this.balance_info().await?)?;
 
                 Response::from_websocket(pair.client)
             })
@@ -176,6 +1167,18 @@ impl DurableObject for UserYralCoinState {
             .await
     }
 
+    async fn alarm(&self) -> Result<Response> {
+        self.cleanup_idempotency_keys().await?;
+        self.release_expired_holds().await?;
+        self.expire_promos().await?;
+        self.reconcile_withdrawals().await?;
+        self.reconcile_transfers().await?;
+        // last, it may erase everything
+        self.cleanup_if_inactive().await?;
+
+        Response::ok("done")
+    }
+
     async fn websocket_message(
         &self,
         ws: WebSocket,
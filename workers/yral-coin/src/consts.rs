@@ -1,7 +1,111 @@
+use std::time::Duration;
+
 // 1 million YRAL
 pub const YRAL_CREDITED_STORAGE_KEY: &str = "yral-credited-limit-v0";
 // 100,000 YRAL
 pub const YRAL_DEDUCTED_STORAGE_KEY: &str = "yral-deducted-limit-v0";
 
-pub const MAX_CREDITED_PER_DAY_PER_USER_YRAL: u64 = 1_000_000;
-pub const MAX_DEDUCTED_PER_DAY_PER_USER_YRAL: u64 = 100_000;
+/// fallbacks for the `MAX_*_PER_DAY_PER_USER_YRAL` worker vars
+pub const DEFAULT_MAX_CREDITED_PER_DAY_PER_USER_YRAL: u64 = 1_000_000;
+pub const DEFAULT_MAX_DEDUCTED_PER_DAY_PER_USER_YRAL: u64 = 100_000;
+/// KV key holding `LimitOverrides` applied to every user
+pub const DAILY_LIMITS_KEY: &str = "daily-limits";
+
+/// outcomes of updates with an idempotency key are replayed for this long
+pub const IDEMPOTENCY_KEY_TTL_MS: u64 = 24 * 3600 * 1000;
+
+/// KV key holding the SATS -> YRAL conversion rate
+pub const SATS_TO_YRAL_RATE_KEY: &str = "sats-to-yral-rate";
+pub const CONVERSION_HISTORY_LIMIT: usize = 50;
+/// KV prefix for cashback campaign definitions, suffixed by the campaign id
+pub const CASHBACK_CAMPAIGN_PREFIX: &str = "cashback-campaign-";
+pub const CASHBACK_HISTORY_LIMIT: usize = 50;
+
+pub const MAX_BULK_BALANCE_PRINCIPALS: usize = 100;
+pub const BULK_BALANCE_CONCURRENCY: usize = 10;
+
+/// signed query strings (balance ws, data export) must expire within this window
+pub const MAX_QUERY_SIGNATURE_VALIDITY_MS: u64 = 10 * 60 * 1000;
+
+/// R2 prefix for archival copies taken before a user's coin data is erased
+pub const GDPR_ARCHIVE_PREFIX: &str = "yral-coin";
+
+pub const DAY_MS: u64 = 24 * 3600 * 1000;
+pub const DAILY_BONUS_BASE_YRAL: u64 = 10;
+/// streaks beyond this many days don't increase the bonus further
+pub const DAILY_BONUS_MAX_MULTIPLIER: u64 = 7;
+
+pub const DEFAULT_HOLD_TTL_MS: u64 = 15 * 60 * 1000;
+pub const MAX_HOLD_TTL_MS: u64 = 24 * 3600 * 1000;
+
+/// each holder costs a DO subrequest, a cron run exports at most
+/// `SNAPSHOT_PAGES_PER_RUN` pages and the next run picks up from the cursor
+pub const COIN_HOLDERS_PAGE_SIZE: u64 = 250;
+pub const SNAPSHOT_PAGES_PER_RUN: usize = 2;
+/// KV key (`YRAL_COIN_CONFIG`) holding the progress of the running export
+pub const SNAPSHOT_EXPORT_STATE_KEY: &str = "balance-snapshot-export";
+pub const SNAPSHOT_CONCURRENCY: usize = 20;
+pub const SNAPSHOT_EXPORT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const OFF_CHAIN_EVENTS_URL: &str = "https://offchain.yral.com/api/v2/events";
+
+/// fallback for the `CREDIT_NOTIFICATION_THRESHOLD_YRAL` worker var
+pub const DEFAULT_CREDIT_NOTIFICATION_THRESHOLD_YRAL: u64 = 1000;
+
+/// KV key holding the redemption catalog
+pub const REDEMPTION_CATALOG_KEY: &str = "redemption-catalog";
+pub const REDEMPTION_HISTORY_LIMIT: usize = 50;
+
+/// fallback for the `ADMIN_DUAL_CONTROL_THRESHOLD_YRAL` worker var
+/// adjustments above this need a second admin's sign off
+pub const DEFAULT_ADMIN_DUAL_CONTROL_THRESHOLD_YRAL: u64 = 10_000;
+pub const ADMIN_COSIGNER_HEADER: &str = "X-Cosigner-Authorization";
+
+/// staleness bound for balances cached in colos the DO can't purge
+pub const BALANCE_EDGE_CACHE_TTL_SECS: u64 = 10;
+
+pub const UPDATE_BALANCE_RATE_LIMIT_WINDOW_MS: u64 = 60 * 1000;
+/// fallback for the `UPDATE_BALANCE_CALLER_MAX_PER_MINUTE` worker var
+pub const DEFAULT_UPDATE_BALANCE_CALLER_MAX_PER_WINDOW: u32 = 6000;
+/// fallback for the `UPDATE_BALANCE_TARGET_MAX_PER_MINUTE` worker var
+pub const DEFAULT_UPDATE_BALANCE_TARGET_MAX_PER_WINDOW: u32 = 60;
+
+/// set by this worker on requests to a user's DO so it knows whose balance it tracks
+pub const OWNER_HEADER: &str = "X-Yral-User-Principal";
+
+/// KV (`YRAL_COIN_CONFIG`) prefix for registered balance webhooks
+pub const WEBHOOK_PREFIX: &str = "balance-webhook-";
+/// how long a DO reuses the webhook registry before reloading it from KV
+pub const WEBHOOK_CACHE_TTL_MS: u64 = 60 * 1000;
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Yral-Signature";
+/// subscribers are retried through the queue, don't wait on slow ones
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const YRAL_TREASURY_STORAGE_KEY: &str = "yral-treasury-limit-v0";
+/// fallback for the `MAX_WITHDRAWAL_PER_DAY_PER_USER_YRAL` worker var
+pub const DEFAULT_MAX_WITHDRAWAL_PER_DAY_PER_USER_YRAL: u64 = 10_000;
+/// pending withdrawals are resent this long after their last attempt
+pub const WITHDRAWAL_RECONCILE_DELAY_MS: u64 = 60 * 1000;
+/// the ledger dedupes transfers for 24h after `created_at_time`, with some margin
+pub const WITHDRAWAL_DEDUPE_WINDOW_MS: u64 = 23 * 3600 * 1000;
+/// pending transfers are credited to the recipient again this long after their last attempt
+pub const TRANSFER_RECONCILE_DELAY_MS: u64 = 60 * 1000;
+/// the on-chain token has 8 decimals, off-chain balances are whole coins
+pub const YRAL_E8S_PER_COIN: u64 = 100_000_000;
+
+/// KV key (`YRAL_COIN_CONFIG`) overriding the `MIN_BALANCE_FLOOR_YRAL` policy
+pub const MIN_BALANCE_POLICY_KEY: &str = "min-balance-policy";
+/// fallback for the `MIN_BALANCE_FLOOR_YRAL` worker var, no floor by default
+pub const DEFAULT_MIN_BALANCE_FLOOR_YRAL: u64 = 0;
+
+/// queues consumed by this worker, `batch.queue()` picks the handler
+pub const BALANCE_WEBHOOKS_QUEUE: &str = "yral-coin-balance-webhooks";
+pub const COIN_LEDGER_QUEUE: &str = "yral-coin-ledger";
+pub const DEFAULT_GLOBAL_LEDGER_QUERY_LIMIT: u32 = 100;
+pub const MAX_GLOBAL_LEDGER_QUERY_LIMIT: u32 = 1000;
+
+/// fallback for the `INACTIVE_CLEANUP_AFTER_DAYS` worker var
+pub const DEFAULT_INACTIVE_CLEANUP_AFTER_DAYS: u64 = 180;
+/// granularity of the stored last activity time
+pub const ACTIVITY_RESOLUTION_MS: u64 = 24 * 60 * 60 * 1000;
+/// `COIN_ARCHIVE` prefix for state archived by the inactivity cleanup
+pub const INACTIVE_ARCHIVE_PREFIX: &str = "inactive";
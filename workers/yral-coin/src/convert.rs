@@ -0,0 +1,173 @@
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+use worker_utils::RequestInitBuilder;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{CONVERSION_HISTORY_LIMIT, SATS_TO_YRAL_RATE_KEY},
+    error::WorkerError,
+    types::{ConversionRecord, ConversionStatus, ConvertReq, ConvertRes, SatsToYralRate},
+};
+
+pub(crate) const CONVERSION_PREFIX: &str = "conversion-";
+
+fn to_internal(e: worker::Error) -> (u16, WorkerError) {
+    (500, WorkerError::Internal(e.to_string()))
+}
+
+impl UserYralCoinState {
+    async fn sats_to_yral_rate(&self) -> StdResult<SatsToYralRate, (u16, WorkerError)> {
+        let rate = self
+            .env
+            .kv("YRAL_COIN_CONFIG")
+            .map_err(to_internal)?
+            .get(SATS_TO_YRAL_RATE_KEY)
+            .json::<SatsToYralRate>()
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+
+        match rate {
+            Some(rate) if rate.sats > 0 => Ok(rate),
+            _ => Err((503, WorkerError::ConversionRateUnavailable)),
+        }
+    }
+
+    pub(crate) async fn update_sats_balance(
+        &self,
+        user_principal: &str,
+        delta: BigInt,
+    ) -> StdResult<(), (u16, WorkerError)> {
+        let hon_stub = self
+            .env
+            .durable_object("USER_HON_GAME_STATE")
+            .and_then(|ns| ns.id_from_name(user_principal))
+            .and_then(|id| id.get_stub())
+            .map_err(to_internal)?;
+
+        let req = Request::new_with_init(
+            "http://fake_url.com/update_balance",
+            RequestInitBuilder::default()
+                .method(Method::Post)
+                .json(&hon_worker_common::SatsBalanceUpdateRequest {
+                    delta,
+                    is_airdropped: false,
+                })
+                .map_err(to_internal)?
+                .build(),
+        )
+        .map_err(to_internal)?;
+
+        let mut res = hon_stub
+            .fetch_with_request(req)
+            .await
+            .map_err(to_internal)?;
+        if res.status_code() == 200 {
+            return Ok(());
+        }
+
+        let err = res.text().await.map_err(to_internal)?;
+        Err((400, WorkerError::SatsUpdateFailed(err)))
+    }
+
+    async fn save_conversion(
+        &self,
+        record: &ConversionRecord,
+    ) -> StdResult<(), (u16, WorkerError)> {
+        self.storage()
+            .put(format!("{CONVERSION_PREFIX}{}", record.id), record)
+            .await
+            .map_err(to_internal)
+    }
+
+    /// converts SATS held in the hot or not state to YRAL
+    ///
+    /// prepare: the conversion is recorded as pending
+    /// commit: SATS are deducted, then YRAL is credited
+    /// a failed YRAL credit refunds the deducted SATS
+    pub async fn convert_sats(&self, req: ConvertReq) -> StdResult<ConvertRes, (u16, WorkerError)> {
+        let key = format!("{CONVERSION_PREFIX}{}", req.nonce);
+        let existing = self
+            .storage()
+            .get::<ConversionRecord>(&key)
+            .await
+            .map_err(to_internal)?;
+        if existing.is_some() || self.is_forgotten(&key).await.map_err(to_internal)? {
+            return Err((409, WorkerError::DuplicateConversion));
+        }
+
+        let rate = self.sats_to_yral_rate().await?;
+        let yral_amount = req.sats_amount.clone() * rate.yral / rate.sats;
+        if yral_amount == BigUint::ZERO {
+            return Err((400, WorkerError::ConversionAmountTooSmall));
+        }
+
+        let mut record = ConversionRecord {
+            id: req.nonce.clone(),
+            sats_amount: req.sats_amount.clone(),
+            yral_amount: yral_amount.clone(),
+            rate,
+            status: ConversionStatus::Pending,
+            created_at: Date::now().as_millis(),
+        };
+        self.save_conversion(&record).await?;
+
+        let user_principal = req.user_principal.to_text();
+        let sats_delta = BigInt::from(req.sats_amount);
+        if let Err(e) = self
+            .update_sats_balance(&user_principal, -sats_delta.clone())
+            .await
+        {
+            record.status = ConversionStatus::Aborted;
+            self.save_conversion(&record).await?;
+            return Err(e);
+        }
+
+        let credit_res = self
+            .apply_delta(
+                None,
+                BigInt::from(yral_amount.clone()),
+                "sats conversion".into(),
+                Some(req.nonce),
+            )
+            .await;
+        let new_balance = match credit_res {
+            Ok(new_balance) => new_balance,
+            Err(e) => {
+                record.status = match self.update_sats_balance(&user_principal, sats_delta).await {
+                    Ok(()) => ConversionStatus::Refunded,
+                    Err((_, refund_err)) => {
+                        console_error!(
+                            "failed to refund sats for conversion {}: {refund_err}",
+                            record.id
+                        );
+                        ConversionStatus::RefundFailed
+                    }
+                };
+                self.save_conversion(&record).await?;
+                return Err(e);
+            }
+        };
+
+        record.status = ConversionStatus::Committed;
+        self.save_conversion(&record).await?;
+
+        Ok(ConvertRes {
+            yral_credited: yral_amount,
+            new_balance,
+        })
+    }
+
+    pub async fn conversions(&self) -> Result<Vec<ConversionRecord>> {
+        let mut records = self
+            .storage()
+            .list_with_prefix(CONVERSION_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect::<Result<Vec<ConversionRecord>>>()?;
+        records.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        records.truncate(CONVERSION_HISTORY_LIMIT);
+
+        Ok(records)
+    }
+}
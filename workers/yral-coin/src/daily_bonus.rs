@@ -0,0 +1,66 @@
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{DAILY_BONUS_BASE_YRAL, DAILY_BONUS_MAX_MULTIPLIER, DAY_MS},
+    error::WorkerError,
+    types::{BalanceUpdateReason, DailyBonusRes, DailyBonusState, LedgerReason},
+};
+
+pub(crate) const DAILY_BONUS_KEY: &str = "daily_bonus_v0";
+
+impl UserYralCoinState {
+    /// credits the daily bonus, at most once per UTC day
+    /// consecutive days grow the streak, multiplying the bonus
+    pub async fn claim_daily_bonus(&self) -> StdResult<DailyBonusRes, (u16, WorkerError)> {
+        let to_internal = |e: worker::Error| (500, WorkerError::Internal(e.to_string()));
+        let mut storage = self.storage();
+        let state = storage
+            .get::<DailyBonusState>(DAILY_BONUS_KEY)
+            .await
+            .map_err(to_internal)?
+            .unwrap_or_default();
+
+        let today = Date::now().as_millis() / DAY_MS;
+        let next_claim_at = (today + 1) * DAY_MS;
+        if state.last_claim_day == Some(today) {
+            return Err((409, WorkerError::DailyBonusAlreadyClaimed { next_claim_at }));
+        }
+
+        let streak = if state.last_claim_day == Some(today - 1) {
+            state.streak + 1
+        } else {
+            1
+        };
+        let amount = BigUint::from(DAILY_BONUS_BASE_YRAL * streak.min(DAILY_BONUS_MAX_MULTIPLIER));
+
+        let new_balance = self
+            .apply_delta(
+                None,
+                BigInt::from(amount.clone()),
+                LedgerReason::new(BalanceUpdateReason::Campaign, "daily bonus"),
+                Some(format!("daily-bonus-{today}")),
+            )
+            .await?;
+
+        storage
+            .put(
+                DAILY_BONUS_KEY,
+                &DailyBonusState {
+                    last_claim_day: Some(today),
+                    streak,
+                },
+            )
+            .await
+            .map_err(to_internal)?;
+
+        Ok(DailyBonusRes {
+            amount,
+            streak,
+            next_claim_at,
+            new_balance,
+        })
+    }
+}
@@ -0,0 +1,47 @@
+use worker::*;
+
+use crate::consts::BALANCE_EDGE_CACHE_TTL_SECS;
+
+/// cache keys are per DO id, so the DO can purge its own entry without knowing its principal
+fn balance_cache_key(do_id: &str) -> String {
+    format!("https://yral-coin.cache/balance/{do_id}")
+}
+
+pub async fn cached_balance(do_id: &str) -> Result<Option<Response>> {
+    Cache::default().get(balance_cache_key(do_id), true).await
+}
+
+pub async fn store_balance(do_id: &str, res: &mut Response) -> Result<()> {
+    let mut cached = res.cloned()?;
+    cached.headers_mut().set(
+        "Cache-Control",
+        &format!("max-age={BALANCE_EDGE_CACHE_TTL_SECS}"),
+    )?;
+
+    Cache::default().put(balance_cache_key(do_id), cached).await
+}
+
+/// best effort, only purges the cache in the DO's colo
+/// other colos rely on the short TTL
+pub async fn purge_balance(do_id: &str) {
+    if let Err(e) = Cache::default()
+        .delete(balance_cache_key(do_id), true)
+        .await
+    {
+        console_warn!("failed to purge cached balance: {e}");
+    }
+}
+
+/// 304 if the client already has the current version
+pub fn not_modified(req: &Request, res: &Response) -> Result<Option<Response>> {
+    let etag = res.headers().get("ETag")?;
+    let if_none_match = req.headers().get("If-None-Match")?;
+    match (etag, if_none_match) {
+        (Some(etag), Some(if_none_match)) if etag == if_none_match => {
+            let not_modified = Response::empty()?.with_status(304);
+            not_modified.headers().set("ETag", &etag)?;
+            Ok(Some(not_modified))
+        }
+        _ => Ok(None),
+    }
+}
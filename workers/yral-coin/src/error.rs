@@ -1,6 +1,7 @@
 use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use worker_utils::api_error::ApiError;
 
 #[derive(Serialize, Deserialize, Debug, Error)]
 pub enum WorkerError {
@@ -14,4 +15,67 @@ pub enum WorkerError {
     YralCreditLimitReached,
     #[error("yral deduct limit reached")]
     YralDeductLimitReached,
+    #[error("invalid transfer")]
+    InvalidTransfer,
+    #[error("transfer already processed")]
+    DuplicateTransfer,
+    #[error("airdrop delta must be positive")]
+    InvalidAirdropDelta,
+    #[error("conversion rate unavailable")]
+    ConversionRateUnavailable,
+    #[error("conversion amount too small")]
+    ConversionAmountTooSmall,
+    #[error("conversion already processed")]
+    DuplicateConversion,
+    #[error("failed to update sats balance: {0}")]
+    SatsUpdateFailed(String),
+    #[error("promotional credits must be positive and expire in the future")]
+    InvalidPromoCredit,
+    #[error("admin adjustment already applied")]
+    DuplicateAdminAdjustment,
+    #[error("catalog item not found")]
+    CatalogItemNotFound,
+    #[error("redemption already processed")]
+    DuplicateRedemption,
+    #[error("hold not found")]
+    HoldNotFound,
+    #[error("hold already exists")]
+    DuplicateHold,
+    #[error("invalid hold ttl")]
+    InvalidHoldTtl,
+    #[error("daily bonus already claimed")]
+    DailyBonusAlreadyClaimed { next_claim_at: u64 },
+    #[error("invalid withdrawal")]
+    InvalidWithdrawal,
+    #[error("withdrawal already processed")]
+    DuplicateWithdrawal,
+    #[error("daily withdrawal limit reached")]
+    WithdrawalLimitReached,
+    #[error("treasury out of funds")]
+    TreasuryOutOfFunds,
+    #[error("treasury unavailable")]
+    TreasuryUnavailable,
+    #[error("deduction would take the balance below the minimum of {floor}")]
+    BelowMinimumBalance { floor: BigUint },
+    #[error("campaign not found")]
+    CampaignNotFound,
+    #[error("campaign is not running")]
+    CampaignInactive,
+    #[error("spend too small for cashback")]
+    CashbackSpendTooSmall,
+    #[error("cashback spend already processed")]
+    DuplicateCashbackSpend,
+    #[error("user has pending {0}, retry once they settle")]
+    PendingWork(String),
+}
+
+impl From<WorkerError> for ApiError {
+    fn from(e: WorkerError) -> Self {
+        let ApiError { code, details, .. } = ApiError::from_serialized(&e);
+        ApiError {
+            code,
+            message: e.to_string(),
+            details,
+        }
+    }
 }
@@ -0,0 +1,202 @@
+use num_bigint::BigUint;
+use serde::de::DeserializeOwned;
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    admin::ADMIN_ADJUSTMENT_PREFIX,
+    cashback::CASHBACK_PREFIX,
+    coin::UserYralCoinState,
+    convert::CONVERSION_PREFIX,
+    daily_bonus::DAILY_BONUS_KEY,
+    error::WorkerError,
+    hold::HOLD_PREFIX,
+    redeem::RECEIPT_PREFIX,
+    transfer::{LEGACY_NONCE_PREFIX, RECEIVED_PREFIX, TRANSFER_PREFIX},
+    types::{
+        CashbackRecord, CoinDataExport, ConversionRecord, ConversionStatus, TransferRecord,
+        TransferStatus, WithdrawalReceipt, WithdrawalStatus,
+    },
+    withdraw::WITHDRAWAL_PREFIX,
+};
+
+/// records keyed by a nonce or id that a replayed request would reuse
+/// `forget` keeps a marker for each, so requests signed before it are still rejected
+const REPLAY_PREFIXES: [&str; 8] = [
+    TRANSFER_PREFIX,
+    LEGACY_NONCE_PREFIX,
+    RECEIVED_PREFIX,
+    WITHDRAWAL_PREFIX,
+    CONVERSION_PREFIX,
+    CASHBACK_PREFIX,
+    RECEIPT_PREFIX,
+    ADMIN_ADJUSTMENT_PREFIX,
+];
+/// markers left by `forget`, the rest of the key is the erased record's key
+const FORGOTTEN_PREFIX: &str = "forgotten-";
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    async fn list_all<T: DeserializeOwned>(&self, prefix: &str) -> Result<Vec<T>> {
+        self.storage()
+            .list_with_prefix(prefix)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect()
+    }
+
+    /// all coin data held for this user, for data subject access requests
+    pub async fn export_data(&self) -> Result<CoinDataExport> {
+        let storage = self.storage();
+        let balance = self.balance_info().await?;
+        let limits = self.limits().await?;
+        let limit_overrides = {
+            self.limit_overrides
+                .borrow_mut()
+                .read(&storage)
+                .await?
+                .clone()
+        };
+        let breakdown = {
+            self.reason_totals
+                .borrow_mut()
+                .read(&storage)
+                .await?
+                .clone()
+        };
+        let promo_grants = { self.promo_grants.borrow_mut().read(&storage).await?.clone() };
+        let ledger = { self.ledger.borrow().entries(&storage).await? };
+
+        Ok(CoinDataExport {
+            exported_at: Date::now().as_millis(),
+            balance,
+            limits,
+            limit_overrides,
+            breakdown,
+            daily_bonus: storage.get(DAILY_BONUS_KEY).await?.unwrap_or_default(),
+            promo_grants,
+            holds: self.list_all(HOLD_PREFIX).await?,
+            ledger,
+            conversions: self.list_all(CONVERSION_PREFIX).await?,
+            redemptions: self.list_all(RECEIPT_PREFIX).await?,
+            admin_audit: self.admin_audit_log().await?,
+            withdrawals: self.list_all(WITHDRAWAL_PREFIX).await?,
+            cashbacks: self.list_all(CASHBACK_PREFIX).await?,
+        })
+    }
+
+    /// whether `key` was erased by `forget`, a request reusing it is a replay
+    pub(crate) async fn is_forgotten(&self, key: &str) -> Result<bool> {
+        let marker = self
+            .storage()
+            .get::<bool>(&format!("{FORGOTTEN_PREFIX}{key}"))
+            .await?;
+
+        Ok(marker.is_some())
+    }
+
+    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.storage()
+            .list_with_prefix::<serde_json::Value>(prefix)
+            .await
+            .map(|v| v.map(|v| v.0))
+            .collect()
+    }
+
+    /// what still has to settle before the user can be erased, `None` if nothing
+    pub(crate) async fn pending_work(&self) -> Result<Option<&'static str>> {
+        if !self.withdrawals_in_flight.borrow().is_empty()
+            || !self.transfers_in_flight.borrow().is_empty()
+        {
+            return Ok(Some("requests"));
+        }
+        if self.balance_info().await?.held != BigUint::ZERO {
+            return Ok(Some("holds"));
+        }
+        let transfers: Vec<TransferRecord> = self.list_all(TRANSFER_PREFIX).await?;
+        if transfers
+            .iter()
+            .any(|t| t.status == TransferStatus::Pending)
+        {
+            return Ok(Some("transfers"));
+        }
+        let withdrawals: Vec<WithdrawalReceipt> = self.list_all(WITHDRAWAL_PREFIX).await?;
+        if withdrawals
+            .iter()
+            .any(|w| w.status == WithdrawalStatus::Pending)
+        {
+            return Ok(Some("withdrawals"));
+        }
+        let conversions: Vec<ConversionRecord> = self.list_all(CONVERSION_PREFIX).await?;
+        if conversions
+            .iter()
+            .any(|c| c.status == ConversionStatus::Pending)
+        {
+            return Ok(Some("conversions"));
+        }
+        let cashbacks: Vec<CashbackRecord> = self.list_all(CASHBACK_PREFIX).await?;
+        if cashbacks
+            .iter()
+            .any(|c| c.status == ConversionStatus::Pending)
+        {
+            return Ok(Some("cashback spends"));
+        }
+
+        Ok(None)
+    }
+
+    /// erases everything stored for this user
+    /// callers are expected to archive `export_data` first
+    ///
+    /// refused while anything is pending, its records are needed to settle it
+    /// markers of used nonces and the ledger sequence are kept, see `REPLAY_PREFIXES`
+    pub async fn forget(&self) -> StdResult<(), (u16, WorkerError)> {
+        let to_internal = |e: worker::Error| (500, WorkerError::Internal(e.to_string()));
+        if let Some(pending) = self.pending_work().await.map_err(to_internal)? {
+            return Err((409, WorkerError::PendingWork(pending.into())));
+        }
+
+        let mut storage = self.storage();
+        let mut tombstone = self
+            .list_keys(FORGOTTEN_PREFIX)
+            .await
+            .map_err(to_internal)?;
+        for prefix in REPLAY_PREFIXES {
+            let keys = self.list_keys(prefix).await.map_err(to_internal)?;
+            tombstone.extend(keys.into_iter().map(|k| format!("{FORGOTTEN_PREFIX}{k}")));
+        }
+        let next_seq = {
+            self.ledger
+                .borrow_mut()
+                .next_seq(&storage)
+                .await
+                .map_err(to_internal)?
+        };
+
+        storage.delete_all().await.map_err(to_internal)?;
+        self.state
+            .storage()
+            .delete_alarm()
+            .await
+            .map_err(to_internal)?;
+        self.invalidate_cached_state();
+        storage
+            .put_multiple(tombstone.iter().map(|k| (k.as_str(), &true)))
+            .await
+            .map_err(to_internal)?;
+        {
+            self.ledger
+                .borrow_mut()
+                .resume_at(&mut storage, next_seq)
+                .await
+                .map_err(to_internal)?;
+        }
+        console_log!("erased coin data for {}", self.state.id());
+
+        // also purges the edge cached balance
+        self.broadcast_balance().await;
+
+        Ok(())
+    }
+}
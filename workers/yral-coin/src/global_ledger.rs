@@ -0,0 +1,154 @@
+use worker::{wasm_bindgen::JsValue, *};
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{DEFAULT_GLOBAL_LEDGER_QUERY_LIMIT, MAX_GLOBAL_LEDGER_QUERY_LIMIT},
+    types::{
+        GlobalLedgerCursor, GlobalLedgerMessage, GlobalLedgerQuery, GlobalLedgerQueryRes,
+        GlobalLedgerRow, LedgerEntry,
+    },
+};
+
+fn reason_code_str(entry: &LedgerEntry) -> Option<String> {
+    entry.reason_code.map(|code| format!("{code:?}"))
+}
+
+fn opt_str(v: Option<String>) -> JsValue {
+    v.map(JsValue::from).unwrap_or(JsValue::NULL)
+}
+
+fn insert_statement(db: &D1Database, msg: &GlobalLedgerMessage) -> Result<D1PreparedStatement> {
+    let entry = &msg.entry;
+    let metadata = serde_json::to_string(&entry.metadata)?;
+    let id = msg
+        .id
+        .clone()
+        .unwrap_or_else(|| format!("{}:{}", msg.user_principal.to_text(), msg.seq));
+
+    db.prepare(
+        "INSERT OR IGNORE INTO coin_transactions \
+        (id, user_principal, seq, delta, resulting_balance, reason_code, reason, idempotency_key, metadata, timestamp) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )
+    .bind(&[
+        id.into(),
+        msg.user_principal.to_text().into(),
+        JsValue::from_f64(msg.seq as f64),
+        entry.delta.to_string().into(),
+        entry.resulting_balance.to_string().into(),
+        opt_str(reason_code_str(entry)),
+        opt_str(entry.reason.clone()),
+        opt_str(entry.idempotency_key.clone()),
+        metadata.into(),
+        JsValue::from_f64(entry.timestamp as f64),
+    ])
+}
+
+/// writes a batch of streamed ledger entries to `COIN_LEDGER_DB`
+/// the batch is retried as a whole, redeliveries are ignored by the primary key
+/// the key is the message's random id, a user's seq starts over if their DO is recreated
+pub async fn record_ledger_entries(env: &Env, batch: &[GlobalLedgerMessage]) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let db = env.d1("COIN_LEDGER_DB")?;
+    let statements = batch
+        .iter()
+        .map(|msg| insert_statement(&db, msg))
+        .collect::<Result<Vec<_>>>()?;
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+/// newest transactions first, across every user unless filtered
+pub async fn query_ledger(env: &Env, query: GlobalLedgerQuery) -> Result<GlobalLedgerQueryRes> {
+    let db = env.d1("COIN_LEDGER_DB")?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_GLOBAL_LEDGER_QUERY_LIMIT)
+        .clamp(1, MAX_GLOBAL_LEDGER_QUERY_LIMIT);
+
+    let mut filters = Vec::<&str>::new();
+    let mut binds = Vec::<JsValue>::new();
+    if let Some(user_principal) = query.user_principal {
+        filters.push("user_principal = ?");
+        binds.push(user_principal.to_text().into());
+    }
+    if let Some(reason_code) = query.reason_code {
+        filters.push("reason_code = ?");
+        binds.push(format!("{reason_code:?}").into());
+    }
+    if let Some(from) = query.from {
+        filters.push("timestamp >= ?");
+        binds.push(JsValue::from_f64(from as f64));
+    }
+    if let Some(to) = query.to {
+        filters.push("timestamp < ?");
+        binds.push(JsValue::from_f64(to as f64));
+    }
+    if let Some(cursor) = query.cursor.as_ref() {
+        filters.push("(timestamp < ? OR (timestamp = ? AND id < ?))");
+        binds.push(JsValue::from_f64(cursor.timestamp as f64));
+        binds.push(JsValue::from_f64(cursor.timestamp as f64));
+        binds.push(cursor.id.as_str().into());
+    }
+    let where_clause = if filters.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", filters.join(" AND "))
+    };
+    // one extra row to know if there's a next page
+    binds.push(JsValue::from_f64((limit + 1) as f64));
+
+    let mut transactions = db
+        .prepare(format!(
+            "SELECT * FROM coin_transactions {where_clause} ORDER BY timestamp DESC, id DESC LIMIT ?"
+        ))
+        .bind(&binds)?
+        .all()
+        .await?
+        .results::<GlobalLedgerRow>()?;
+
+    let next = if transactions.len() > limit as usize {
+        transactions.pop();
+        transactions.last().map(|row| GlobalLedgerCursor {
+            timestamp: row.timestamp,
+            id: row.id.clone(),
+        })
+    } else {
+        None
+    };
+
+    Ok(GlobalLedgerQueryRes { transactions, next })
+}
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    /// streams a persisted ledger entry to the global ledger through `COIN_LEDGER` queue
+    pub(crate) async fn stream_to_global_ledger(
+        &self,
+        seq: u64,
+        entry: &LedgerEntry,
+    ) -> Result<()> {
+        let storage = self.storage();
+        let Some(user_principal) = ({ *self.owner.borrow_mut().read(&storage).await? }) else {
+            console_warn!("skipping global ledger, DO owner unknown");
+            return Ok(());
+        };
+
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut id).expect("no randomness available");
+
+        self.env
+            .queue("COIN_LEDGER")?
+            .send(&GlobalLedgerMessage {
+                id: Some(hex::encode(id)),
+                user_principal,
+                seq,
+                entry: entry.clone(),
+            })
+            .await
+    }
+}
@@ -0,0 +1,171 @@
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{DEFAULT_HOLD_TTL_MS, MAX_HOLD_TTL_MS},
+    error::WorkerError,
+    types::{BalanceUpdateReason, CaptureReq, Hold, HoldReq, LedgerReason},
+};
+
+pub(crate) const HOLD_PREFIX: &str = "hold-";
+
+fn hold_key(hold_id: &str) -> String {
+    format!("{HOLD_PREFIX}{hold_id}")
+}
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    /// reserves `amount` so it can't be spent elsewhere until captured or released
+    /// held coins never exceed the balance
+    pub async fn hold(&self, req: HoldReq) -> StdResult<Hold, (u16, WorkerError)> {
+        let to_internal = |e: worker::Error| (500, WorkerError::Internal(e.to_string()));
+        let ttl_ms = req.ttl_ms.unwrap_or(DEFAULT_HOLD_TTL_MS);
+        if ttl_ms == 0 || ttl_ms > MAX_HOLD_TTL_MS {
+            return Err((400, WorkerError::InvalidHoldTtl));
+        }
+
+        let mut storage = self.storage();
+        let key = hold_key(&req.hold_id);
+        if storage
+            .get::<Hold>(&key)
+            .await
+            .map_err(to_internal)?
+            .is_some()
+        {
+            return Err((409, WorkerError::DuplicateHold));
+        }
+
+        let balance = self.balance_info().await.map_err(to_internal)?.balance;
+        let res = {
+            self.held
+                .borrow_mut()
+                .try_get_update(&mut storage, |held| {
+                    if req.amount.clone() + &*held > balance {
+                        return Err((400, WorkerError::InsufficientFunds));
+                    }
+                    *held += &req.amount;
+                    Ok(())
+                })
+                .await
+        };
+        res.map_err(|e| match e {
+            Ok(e) => e,
+            Err(e) => to_internal(e),
+        })?;
+
+        let hold = Hold {
+            hold_id: req.hold_id,
+            amount: req.amount,
+            expires_at: Date::now().as_millis() + ttl_ms,
+        };
+        storage.put(&key, &hold).await.map_err(to_internal)?;
+        self.schedule_alarm(hold.expires_at)
+            .await
+            .map_err(to_internal)?;
+
+        self.broadcast_balance().await;
+
+        Ok(hold)
+    }
+
+    /// removes the hold and returns its reserved amount to the spendable balance
+    async fn remove_hold(&self, hold_id: &str) -> StdResult<Hold, (u16, WorkerError)> {
+        let to_internal = |e: worker::Error| (500, WorkerError::Internal(e.to_string()));
+        let mut storage = self.storage();
+        let key = hold_key(hold_id);
+        let hold = storage
+            .get::<Hold>(&key)
+            .await
+            .map_err(to_internal)?
+            .ok_or((404, WorkerError::HoldNotFound))?;
+
+        let res = {
+            self.held
+                .borrow_mut()
+                .update(&mut storage, |held| {
+                    // saturate, held must never underflow
+                    *held -= hold.amount.clone().min(held.clone());
+                })
+                .await
+        };
+        res.map_err(to_internal)?;
+        storage.delete(&key).await.map_err(to_internal)?;
+
+        Ok(hold)
+    }
+
+    /// debits the held amount
+    pub async fn capture(&self, req: CaptureReq) -> StdResult<BigUint, (u16, WorkerError)> {
+        let hold = self.remove_hold(&req.hold_id).await?;
+        let res = self
+            .apply_delta(
+                None,
+                -BigInt::from(hold.amount.clone()),
+                LedgerReason::new(
+                    BalanceUpdateReason::Purchase,
+                    req.reason.unwrap_or_else(|| "hold capture".into()),
+                ),
+                Some(format!("hold-{}", hold.hold_id)),
+            )
+            .await;
+        let e = match res {
+            Ok(new_bal) => return Ok(new_bal),
+            Err(e) => e,
+        };
+
+        {
+            // restore the hold so the caller can retry or release it
+            let to_internal = |e: worker::Error| (500, WorkerError::Internal(e.to_string()));
+            let mut storage = self.storage();
+            let restore = {
+                self.held
+                    .borrow_mut()
+                    .update(&mut storage, |held| *held += &hold.amount)
+                    .await
+            };
+            restore.map_err(to_internal)?;
+            storage
+                .put(hold_key(&hold.hold_id), &hold)
+                .await
+                .map_err(to_internal)?;
+        }
+
+        Err(e)
+    }
+
+    pub async fn release(&self, hold_id: &str) -> StdResult<(), (u16, WorkerError)> {
+        self.remove_hold(hold_id).await?;
+        self.broadcast_balance().await;
+
+        Ok(())
+    }
+
+    pub(crate) async fn release_expired_holds(&self) -> Result<()> {
+        let now = Date::now().as_millis();
+        let holds = self
+            .storage()
+            .list_with_prefix::<Hold>(HOLD_PREFIX)
+            .await
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut next_expiry = None::<u64>;
+        for (_, hold) in holds {
+            if hold.expires_at > now {
+                next_expiry = Some(next_expiry.map_or(hold.expires_at, |e| e.min(hold.expires_at)));
+                continue;
+            }
+            if let Err((_, e)) = self.release(&hold.hold_id).await {
+                console_error!("failed to release expired hold {}: {e}", hold.hold_id);
+            }
+        }
+
+        if let Some(next_expiry) = next_expiry {
+            self.schedule_alarm(next_expiry).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use num_bigint::BigUint;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{
+        ACTIVITY_RESOLUTION_MS, DEFAULT_INACTIVE_CLEANUP_AFTER_DAYS, INACTIVE_ARCHIVE_PREFIX,
+    },
+    snapshot::unregister_coin_holder,
+};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    fn inactive_cleanup_after_ms(&self) -> u64 {
+        let days = self
+            .env
+            .var("INACTIVE_CLEANUP_AFTER_DAYS")
+            .ok()
+            .and_then(|v| v.to_string().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_INACTIVE_CLEANUP_AFTER_DAYS);
+
+        days * DAY_MS
+    }
+
+    /// records user activity and pushes out the inactivity check
+    /// only written once per `ACTIVITY_RESOLUTION_MS` to keep requests cheap
+    pub(crate) async fn touch_activity(&self) -> Result<()> {
+        let mut storage = self.storage();
+        let now = Date::now().as_millis();
+        {
+            let mut last_activity = self.last_activity.borrow_mut();
+            if now - *last_activity.read(&storage).await? < ACTIVITY_RESOLUTION_MS {
+                return Ok(());
+            }
+            last_activity.set(&mut storage, now).await?;
+        }
+
+        self.schedule_alarm(now + self.inactive_cleanup_after_ms())
+            .await
+    }
+
+    /// archives and erases the state of users inactive with an empty balance
+    /// everything is recreated from defaults if they come back
+    pub(crate) async fn cleanup_if_inactive(&self) -> Result<()> {
+        let storage = self.storage();
+        let last_activity = { *self.last_activity.borrow_mut().read(&storage).await? };
+        // state from before activity tracking, start the clock now
+        if last_activity == 0 {
+            return self.touch_activity().await;
+        }
+
+        let inactive_at = last_activity + self.inactive_cleanup_after_ms();
+        if Date::now().as_millis() < inactive_at {
+            return self.schedule_alarm(inactive_at).await;
+        }
+
+        let balance = self.balance_info().await?;
+        if balance.balance != BigUint::ZERO || balance.held != BigUint::ZERO {
+            return Ok(());
+        }
+        // the alarm that settles it runs the check again
+        if self.pending_work().await?.is_some() {
+            return Ok(());
+        }
+
+        let owner = { *self.owner.borrow_mut().read(&storage).await? };
+        let export = self.export_data().await?;
+        let archive_key = format!(
+            "{INACTIVE_ARCHIVE_PREFIX}/{}/{}.json",
+            owner
+                .map(|owner| owner.to_text())
+                .unwrap_or_else(|| self.state.id().to_string()),
+            Date::now().as_millis()
+        );
+        self.env
+            .bucket("COIN_ARCHIVE")?
+            .put(&archive_key, serde_json::to_vec(&export)?)
+            .execute()
+            .await?;
+
+        self.forget()
+            .await
+            .map_err(|(_, e)| Error::RustError(e.to_string()))?;
+        if let Some(owner) = owner {
+            unregister_coin_holder(&self.env, owner).await?;
+        }
+        console_log!("archived inactive coin state to {archive_key}");
+
+        Ok(())
+    }
+}
@@ -1,5 +1,37 @@
+use worker_utils::jwt::{JwtPolicy, EXP_ROLLOUT_GRACE_UNTIL};
+
 pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
 MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
 -----END PUBLIC KEY-----";
 
 pub const JWT_AUD: &str = "yral-coin-worker";
+/// backend services are still rolling out expiring tokens, see `EXP_ROLLOUT_GRACE_UNTIL`
+pub const JWT_POLICY: JwtPolicy =
+    JwtPolicy::expiring(60).with_missing_exp_grace(EXP_ROLLOUT_GRACE_UNTIL);
+
+pub const ADMIN_JWT_AUD: &str = "yral-coin-admin";
+/// admin tokens are short lived, they must also be freshly issued
+pub const ADMIN_JWT_POLICY: JwtPolicy = JwtPolicy::expiring(60).with_max_age(60 * 60);
+
+/// claims of an admin token, `sub` identifies the admin
+#[derive(serde::Deserialize)]
+pub struct AdminClaims {
+    pub sub: String,
+    /// the one correction a co-signer token approves, see `AdminAdjustRequest::approval`
+    #[serde(default)]
+    pub approves: Option<String>,
+}
+
+/// claims of a backend service token
+#[derive(serde::Deserialize)]
+pub struct CallerClaims {
+    #[serde(default)]
+    pub sub: Option<String>,
+}
+
+impl CallerClaims {
+    /// tokens without a subject share a single budget
+    pub fn subject(&self) -> String {
+        self.sub.clone().unwrap_or_else(|| "anonymous".into())
+    }
+}
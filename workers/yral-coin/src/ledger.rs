@@ -0,0 +1,88 @@
+use worker::Result;
+use worker_utils::{
+    pagination::KeyCursorPager,
+    storage::{batch::WriteBatch, SafeStorage, StorageCell},
+};
+
+use crate::types::{LedgerEntry, TransactionsRes};
+
+const LEDGER_PREFIX: &str = "ledger-";
+
+/// append only log of balance mutations
+pub struct CoinLedger {
+    next_seq: StorageCell<u64>,
+}
+
+impl Default for CoinLedger {
+    fn default() -> Self {
+        Self {
+            next_seq: StorageCell::new("ledger_next_seq", || 0),
+        }
+    }
+}
+
+impl CoinLedger {
+    fn entry_key(seq: u64) -> String {
+        // zero padded so keys sort by sequence
+        format!("{LEDGER_PREFIX}{seq:020}")
+    }
+
+    /// stages the entry in `batch`, returns its sequence
+    pub async fn append_in(
+        &mut self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        entry: &LedgerEntry,
+    ) -> Result<u64> {
+        let seq = *self.next_seq.read(storage).await?;
+        batch.put(Self::entry_key(seq), entry)?;
+        self.next_seq.set_in(batch, seq + 1)?;
+
+        Ok(seq)
+    }
+
+    /// every entry, oldest first
+    pub async fn entries(&self, storage: &SafeStorage) -> Result<Vec<LedgerEntry>> {
+        storage
+            .list_with_prefix(LEDGER_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect()
+    }
+
+    pub async fn next_seq(&mut self, storage: &SafeStorage) -> Result<u64> {
+        Ok(*self.next_seq.read(storage).await?)
+    }
+
+    /// continues the sequence at `seq` after the entries were erased, so it never repeats
+    pub async fn resume_at(&mut self, storage: &mut SafeStorage, seq: u64) -> Result<()> {
+        self.next_seq.set(storage, seq).await
+    }
+
+    pub fn invalidate(&mut self) {
+        self.next_seq.invalidate();
+    }
+
+    /// newest entries first
+    pub async fn page(
+        &self,
+        storage: &SafeStorage,
+        page_size: usize,
+        cursor: Option<String>,
+    ) -> Result<TransactionsRes> {
+        let page = KeyCursorPager::new(LEDGER_PREFIX)
+            .reversed()
+            .page(
+                storage,
+                page_size,
+                cursor.as_deref(),
+                |_, entry: LedgerEntry| entry,
+            )
+            .await?;
+
+        Ok(TransactionsRes {
+            transactions: page.items,
+            next: page.next,
+        })
+    }
+}
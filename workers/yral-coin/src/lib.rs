@@ -1,100 +1,1040 @@
+mod admin;
+mod cashback;
 mod coin;
 mod consts;
+mod convert;
+mod daily_bonus;
+mod edge_cache;
 mod error;
+mod gdpr;
+mod global_ledger;
+mod hold;
+mod inactivity;
 mod jwt;
+mod ledger;
+mod policy;
+mod promo;
+mod rate_limiter;
+mod redeem;
+mod snapshot;
+mod transfer;
+mod treasury;
 mod types;
+mod webhook;
+mod withdraw;
 
 use candid::Principal;
+use futures::{stream, StreamExt};
+use num_bigint::{BigInt, BigUint};
+use std::{
+    collections::{HashMap, HashSet},
+    result::Result as StdResult,
+};
 use worker::*;
-use worker_utils::{jwt::verify_jwt_from_header, parse_principal, RequestInitBuilder};
+use worker_utils::{
+    api_error::error_resp,
+    cors::cors_for_env,
+    do_client::DoClient,
+    error_report::ErrorReporter,
+    flags::Flag,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    notification::NOTIFICATIONS_QUEUE,
+    principals, require_flag,
+    secrets::SecretSet,
+    signed_req::{self, InvalidSignature, Signed},
+    webhooks::{WebhookEvent, WebhookEventData, WEBHOOK_EVENTS_QUEUE},
+    RequestInitBuilder,
+};
+use yral_identity::{msg_builder::Message, Signature};
 
 use crate::{
-    jwt::{JWT_AUD, JWT_PUBKEY},
-    types::YralBalanceUpdateRequest,
+    admin::query_admin_audit,
+    consts::{
+        ADMIN_COSIGNER_HEADER, BALANCE_WEBHOOKS_QUEUE, BULK_BALANCE_CONCURRENCY, COIN_LEDGER_QUEUE,
+        DAY_MS, DEFAULT_ADMIN_DUAL_CONTROL_THRESHOLD_YRAL, GDPR_ARCHIVE_PREFIX,
+        MAX_BULK_BALANCE_PRINCIPALS, MAX_QUERY_SIGNATURE_VALIDITY_MS, OWNER_HEADER,
+    },
+    edge_cache::{cached_balance, not_modified, store_balance},
+    global_ledger::{query_ledger, record_ledger_entries},
+    jwt::{
+        AdminClaims, CallerClaims, ADMIN_JWT_AUD, ADMIN_JWT_POLICY, JWT_AUD, JWT_POLICY, JWT_PUBKEY,
+    },
+    redeem::load_catalog,
+    snapshot::{export_balance_snapshots, register_coin_holder, unregister_coin_holder},
+    types::{
+        balance_ws_msg, data_export_msg, redeem_msg, AdminAdjustReq, AdminAdjustRequest,
+        AdminAuditEntry, BalanceUpdateReason, BulkBalanceReq, BulkBalanceRes, ConvertReq,
+        DailyBonusClaimRequest, ForgetRes, GlobalLedgerMessage, GlobalLedgerQuery, LimitOverrides,
+        RedeemReq, SignedQuery, SpendWithCashbackReq, TransactionsReq, TransferReq,
+        WebhookDelivery, WebhookSubscription, WithdrawReq, YralBalanceInfo,
+        YralBalanceUpdateRequest, YralConvertRequest, YralRedeemRequest,
+        YralSignedBalanceUpdateRequest, YralSpendWithCashbackRequest, YralTransferRequest,
+        YralWithdrawRequest,
+    },
+    webhook::{delete_webhook, deliver_webhook, list_webhooks, register_webhook},
 };
 
-fn cors_policy() -> Cors {
-    Cors::new()
-        .with_origins(["*"])
-        .with_methods([Method::Head, Method::Get, Method::Post, Method::Options])
-        .with_allowed_headers(vec!["*"])
-        .with_max_age(86400)
+const USER_YRAL_COIN_STATE: &str = "USER_YRAL_COIN_STATE";
+
+/// the per user state learns who it belongs to from `OWNER_HEADER`
+fn coin_state(env: &Env) -> DoClient<'_> {
+    DoClient::new(env).with_name_header(OWNER_HEADER)
 }
 
-fn get_yral_state_stub<T>(ctx: &RouteContext<T>, user_principal: Principal) -> Result<Stub> {
-    let state_ns = ctx.durable_object("USER_YRAL_COIN_STATE")?;
-    let state_obj = state_ns.id_from_name(&user_principal.to_text())?;
-    let state_stub = state_obj.get_stub()?;
+/// balance reads are public unless `REQUIRE_READ_JWT` is set
+/// `READ_JWT_AUDIENCES` is a comma separated allowlist, defaulting to this worker's audience
+async fn verify_read_jwt(req: &Request, env: &Env) -> StdResult<(), (String, u16)> {
+    let required = env
+        .var("REQUIRE_READ_JWT")
+        .map(|v| v.to_string() == "true")
+        .unwrap_or_default();
+    if !required {
+        return Ok(());
+    }
 
-    Ok(state_stub)
+    let audiences = env
+        .var("READ_JWT_AUDIENCES")
+        .map(|v| {
+            v.to_string()
+                .split(',')
+                .map(|aud| aud.trim().to_string())
+                .filter(|aud| !aud.is_empty())
+                .collect::<HashSet<_>>()
+        })
+        .ok()
+        .filter(|auds| !auds.is_empty())
+        .unwrap_or_else(|| HashSet::from([JWT_AUD.to_string()]));
+
+    JwtKeys::from_env(env, JWT_PUBKEY)
+        .verify_from_header_with_audiences(audiences, JWT_POLICY, req)
+        .await
 }
 
-async fn user_yral_balance(ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-    let game_stub = get_yral_state_stub(&ctx, user_principal)?;
+async fn user_yral_balance(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
 
-    let res = game_stub
-        .fetch_with_str("http://fake_url.com/balance")
-        .await?;
+    let user_principal = principals!(ctx, "user_principal");
+    let do_id = ctx
+        .durable_object(USER_YRAL_COIN_STATE)?
+        .id_from_name(&user_principal.to_text())?
+        .to_string();
+
+    let res = match cached_balance(&do_id).await? {
+        Some(res) => res,
+        None => {
+            let mut res = coin_state(&ctx.env)
+                .get(USER_YRAL_COIN_STATE, &user_principal.to_text(), "balance")
+                .await?;
+            if res.status_code() == 200 {
+                store_balance(&do_id, &mut res).await?;
+            }
+            res
+        }
+    };
+
+    if let Some(not_modified) = not_modified(&req, &res)? {
+        return Ok(not_modified);
+    }
 
     Ok(res)
 }
 
+async fn bulk_yral_balances(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let req_data: BulkBalanceReq = json_body!(req);
+    if req_data.principals.len() > MAX_BULK_BALANCE_PRINCIPALS {
+        return error_resp(
+            format!("at most {MAX_BULK_BALANCE_PRINCIPALS} principals allowed"),
+            400,
+        );
+    }
+
+    let ctx = &ctx;
+    let results = stream::iter(req_data.principals)
+        .map(|user_principal| async move {
+            let res = async {
+                let mut res = coin_state(&ctx.env)
+                    .get(USER_YRAL_COIN_STATE, &user_principal.to_text(), "balance")
+                    .await?;
+                res.json::<YralBalanceInfo>().await
+            }
+            .await;
+            (user_principal, res)
+        })
+        .buffer_unordered(BULK_BALANCE_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut balances = HashMap::new();
+    let mut failed = vec![];
+    for (user_principal, res) in results {
+        match res {
+            Ok(bal) => {
+                balances.insert(user_principal.to_text(), bal);
+            }
+            Err(e) => {
+                console_warn!("failed to fetch balance for {user_principal}: {e}");
+                failed.push(user_principal);
+            }
+        }
+    }
+
+    Response::from_json(&BulkBalanceRes { balances, failed })
+}
+
+/// consumes one request from the caller's `/update_balance` budget
+/// returns the 429 response if it is exhausted
+async fn acquire_caller_limit(ctx: &RouteContext<()>, caller: &str) -> Result<Option<Response>> {
+    let res = DoClient::new(&ctx.env)
+        .fetch(
+            "CALLER_RATE_LIMITER",
+            caller,
+            "acquire",
+            RequestInitBuilder::default().method(Method::Post),
+        )
+        .await?;
+    if res.status_code() == 200 {
+        return Ok(None);
+    }
+
+    Ok(Some(res))
+}
+
+/// self-service spends signed by the user, the alternative to a backend JWT
+fn verify_signed_update(
+    user_principal: Principal,
+    req: &YralSignedBalanceUpdateRequest,
+) -> StdResult<(), (String, u16)> {
+    if req.sender != user_principal {
+        return Err(("sender mismatch".into(), 403));
+    }
+    if req.delta >= BigInt::ZERO || req.reason == BalanceUpdateReason::AdminAdjust {
+        return Err(("signed updates can only spend".into(), 403));
+    }
+    let now = Date::now().as_millis();
+    if req.expires_at < now || req.expires_at > now + MAX_QUERY_SIGNATURE_VALIDITY_MS {
+        return Err(("signature expired".into(), 401));
+    }
+
+    if signed_req::verify(req).is_err() {
+        return Err(("invalid signature".into(), InvalidSignature::STATUS));
+    }
+
+    Ok(())
+}
+
+async fn update_yral_balance_signed(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let signed_req: YralSignedBalanceUpdateRequest = json_body!(req);
+    if let Err((msg, status)) = verify_signed_update(user_principal, &signed_req) {
+        return error_resp(msg, status);
+    }
+    if let Some(limited) = acquire_caller_limit(&ctx, &user_principal.to_text()).await? {
+        return Ok(limited);
+    }
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "update_balance",
+            &YralBalanceUpdateRequest::from(signed_req),
+        )
+        .await
+}
+
+/// backends authenticate with a JWT, requests without one must be signed by the user
 async fn update_yral_balance(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if req.headers().get("Authorization")?.is_none() {
+        return update_yral_balance_signed(req, ctx).await;
+    }
+
+    let claims: CallerClaims = match JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .claims_from_header(
+            HashSet::from([JWT_AUD.to_string()]),
+            JWT_POLICY,
+            &req,
+            "Authorization",
+        )
+        .await
+    {
+        Ok(claims) => claims,
+        Err((msg, code)) => return error_resp(msg, code),
+    };
+    if let Some(limited) = acquire_caller_limit(&ctx, &claims.subject()).await? {
+        return Ok(limited);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    let req_data: YralBalanceUpdateRequest = json_body!(req);
+    register_coin_holder(&ctx.env, user_principal).await;
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "update_balance",
+            &req_data,
+        )
+        .await
+}
+
+/// forwards a backend (JWT authenticated) request body as is to the user's DO
+async fn forward_backend_req(
+    mut req: Request,
+    ctx: RouteContext<()>,
+    do_path: &str,
+) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    };
+
+    let user_principal = principals!(ctx, "user_principal");
+    let body: serde_json::Value = json_body!(req);
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            do_path,
+            &body,
+        )
+        .await
+}
+
+async fn user_transactions(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    let req_data: TransactionsReq = json_body!(req);
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "transactions",
+            &req_data,
+        )
+        .await
+}
+
+async fn transfer_yral(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let req_data: YralTransferRequest = json_body!(req);
+    if req_data.sender != user_principal {
+        return error_resp("sender mismatch", 403);
+    }
+    if let Err(e) = signed_req::verify(&req_data) {
+        return e.into_response();
+    }
+    register_coin_holder(&ctx.env, req_data.recipient).await;
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "transfer",
+            &TransferReq::from(req_data),
+        )
+        .await
+}
+
+/// ramps the sats to YRAL bridge, see worker-utils/src/flags.rs
+static SATS_BRIDGE: Flag = Flag::new("coin_sats_bridge").default_on();
+
+async fn convert_sats_to_yral(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+    require_flag!(ctx.env, SATS_BRIDGE, user_principal);
+
+    let req_data: YralConvertRequest = json_body!(req);
+    if req_data.sender != user_principal {
+        return error_resp("sender mismatch", 403);
+    }
+    if let Err(e) = signed_req::verify(&req_data) {
+        return e.into_response();
+    }
+    register_coin_holder(&ctx.env, user_principal).await;
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "convert",
+            &ConvertReq::from(req_data),
+        )
+        .await
+}
+
+async fn user_conversions(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    coin_state(&ctx.env)
+        .get(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "conversions",
+        )
+        .await
+}
+
+/// deducts SATS and credits a campaign defined share back as YRAL
+async fn spend_with_cashback(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let req_data: YralSpendWithCashbackRequest = json_body!(req);
+    if req_data.sender != user_principal {
+        return error_resp("sender mismatch", 403);
+    }
+    if let Err(e) = signed_req::verify(&req_data) {
+        return e.into_response();
+    }
+    register_coin_holder(&ctx.env, user_principal).await;
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "spend_with_cashback",
+            &SpendWithCashbackReq::from(req_data),
+        )
+        .await
+}
+
+async fn user_cashbacks(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    coin_state(&ctx.env)
+        .get(USER_YRAL_COIN_STATE, &user_principal.to_text(), "cashbacks")
+        .await
+}
+
+fn verify_signed_query(
+    user_principal: Principal,
+    query: &SignedQuery,
+    msg: fn(u64) -> Message,
+) -> StdResult<(), (String, u16)> {
+    let now = Date::now().as_millis();
+    if query.expires_at < now || query.expires_at > now + MAX_QUERY_SIGNATURE_VALIDITY_MS {
+        return Err(("signature expired".into(), 401));
+    }
+    let Ok(signature) = serde_json::from_str::<Signature>(&query.signature) else {
+        return Err(("invalid signature".into(), 400));
+    };
+
+    let signed = Signed {
+        sender: user_principal,
+        signature: &signature,
+        message: msg(query.expires_at),
+    };
+    if signed_req::verify(&signed).is_err() {
+        return Err(("invalid signature".into(), InvalidSignature::STATUS));
+    }
+
+    Ok(())
+}
+
+async fn claim_daily_bonus(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let req_data: DailyBonusClaimRequest = json_body!(req);
+    if req_data.sender != user_principal {
+        return error_resp("sender mismatch", 403);
+    }
+    if req_data.day != Date::now().as_millis() / DAY_MS {
+        return error_resp("claim is not for today", 400);
+    }
+    if let Err(e) = signed_req::verify(&req_data) {
+        return e.into_response();
+    }
+    register_coin_holder(&ctx.env, user_principal).await;
+
+    coin_state(&ctx.env)
+        .fetch(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "claim_daily_bonus",
+            RequestInitBuilder::default().method(Method::Post),
+        )
+        .await
+}
+
+async fn redemption_catalog(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let catalog = load_catalog(&ctx.env).await?;
+
+    Response::from_json(&catalog)
+}
+
+async fn redeem_item(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let req_data: YralRedeemRequest = json_body!(req);
+    if req_data.sender != user_principal {
+        return error_resp("sender mismatch", 403);
+    }
+    if let Err(e) = signed_req::verify(&req_data) {
+        return e.into_response();
+    }
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "redeem",
+            &RedeemReq::from(req_data),
+        )
+        .await
+}
+
+async fn withdraw_yral(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let req_data: YralWithdrawRequest = json_body!(req);
+    if req_data.sender != user_principal {
+        return error_resp("sender mismatch", 403);
+    }
+    if let Err(e) = signed_req::verify(&req_data) {
+        return e.into_response();
+    }
+
+    let event = WebhookEvent::new(WebhookEventData::WithdrawalCompleted {
+        user_principal: user_principal.to_text(),
+        amount: req_data.amount.to_string(),
+        token: "YRAL".into(),
+        withdrawal_id: Some(req_data.nonce.clone()),
+    });
+    let res = coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "withdraw",
+            &WithdrawReq::from(req_data),
+        )
+        .await?;
+    // 202 means the transfer is still pending
+    if res.status_code() == 200 {
+        if let Err(e) = event.send(&ctx.env).await {
+            console_error!("failed to send withdrawal webhook event: {e}");
+        }
+    }
+
+    Ok(res)
+}
+
+async fn user_withdrawals(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    coin_state(&ctx.env)
+        .get(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "withdrawals",
+        )
+        .await
+}
+
+async fn user_redemptions(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    coin_state(&ctx.env)
+        .get(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "redemptions",
+        )
+        .await
+}
+
+async fn user_balance_breakdown(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    coin_state(&ctx.env)
+        .get(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "balance_breakdown",
+        )
+        .await
+}
+
+async fn user_limits(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = verify_read_jwt(&req, &ctx.env).await {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    coin_state(&ctx.env)
+        .get(USER_YRAL_COIN_STATE, &user_principal.to_text(), "limits")
+        .await
+}
+
+async fn set_user_limits(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    let req_data: LimitOverrides = json_body!(req);
+
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "limits",
+            &req_data,
+        )
+        .await
+}
+
+async fn admin_claims(
+    req: &Request,
+    env: &Env,
+    header: &str,
+) -> StdResult<AdminClaims, (String, u16)> {
+    JwtKeys::from_env(env, JWT_PUBKEY)
+        .claims_from_header(
+            HashSet::from([ADMIN_JWT_AUD.to_string()]),
+            ADMIN_JWT_POLICY,
+            req,
+            header,
+        )
+        .await
+}
+
+/// corrections above `ADMIN_DUAL_CONTROL_THRESHOLD_YRAL` need a second admin token
+/// from a different subject in `ADMIN_COSIGNER_HEADER`, approving exactly this correction
+async fn admin_adjust(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let proposer = match admin_claims(&req, &ctx.env, "Authorization").await {
+        Ok(claims) => claims.sub,
+        Err((msg, code)) => return error_resp(msg, code),
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
-    let game_stub = get_yral_state_stub(&ctx, user_principal)?;
+    let user_principal = principals!(ctx, "user_principal");
+    let req_data: AdminAdjustRequest = json_body!(req);
+    if req_data.reason.trim().is_empty() {
+        return error_resp("reason is required", 400);
+    }
+
+    let threshold = ctx
+        .env
+        .var("ADMIN_DUAL_CONTROL_THRESHOLD_YRAL")
+        .ok()
+        .and_then(|v| v.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_ADMIN_DUAL_CONTROL_THRESHOLD_YRAL);
+    let mut approvers = vec![proposer];
+    if req_data.delta.magnitude() > &BigUint::from(threshold) {
+        let cosigner = match admin_claims(&req, &ctx.env, ADMIN_COSIGNER_HEADER).await {
+            Ok(claims) => claims,
+            Err((msg, code)) => return error_resp(msg, code),
+        };
+        if approvers.contains(&cosigner.sub) {
+            return error_resp("co-signer must be a different admin", 403);
+        }
+        if cosigner.approves != Some(req_data.approval(&user_principal.to_text())) {
+            return error_resp("co-signer token doesn't approve this adjustment", 403);
+        }
+        approvers.push(cosigner.sub);
+    }
 
-    let req_data: YralBalanceUpdateRequest = serde_json::from_str(&req.text().await?)?;
+    let adjust_req = AdminAdjustReq {
+        adjustment_id: req_data.adjustment_id,
+        delta: req_data.delta,
+        reason: req_data.reason,
+        approvers,
+    };
+    coin_state(&ctx.env)
+        .post(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "admin_adjust",
+            &adjust_req,
+        )
+        .await
+}
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/update_balance",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
+async fn admin_audit_log(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
 
-    game_stub.fetch_with_request(req).await
+    let user_principal = principals!(ctx, "user_principal");
+    let mut legacy_res = coin_state(&ctx.env)
+        .get(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "admin_audit",
+        )
+        .await?;
+    if legacy_res.status_code() != 200 {
+        return Ok(legacy_res);
+    }
+    let mut log: Vec<AdminAuditEntry> = legacy_res.json().await?;
+    log.extend(query_admin_audit(&ctx.env, user_principal).await?);
+    log.sort_by_key(|entry| entry.timestamp);
+
+    Response::from_json(&log)
 }
 
-async fn estabilish_balance_ws(ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-    let game_stub = get_yral_state_stub(&ctx, user_principal)?;
+/// cross user transaction search for finance reconciliation and fraud investigation
+async fn query_global_ledger(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
 
-    let headers = Headers::new();
-    headers.set("Upgrade", "websocket")?;
-    let new_req = Request::new_with_init(
-        "http://fake_url.com/ws/balance",
-        RequestInitBuilder::default()
-            .method(Method::Get)
-            .replace_headers(headers)
-            .build(),
-    )?;
+    let query: GlobalLedgerQuery = json_body!(req);
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            return error_resp("`from` must be before `to`", 400);
+        }
+    }
 
-    game_stub.fetch_with_request(new_req).await
+    Response::from_json(&query_ledger(&ctx.env, query).await?)
 }
 
+async fn export_user_data(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let Ok(query) = req.query::<SignedQuery>() else {
+        return error_resp("missing signature", 401);
+    };
+    if let Err((msg, status)) = verify_signed_query(user_principal, &query, data_export_msg) {
+        return error_resp(msg, status);
+    }
+
+    coin_state(&ctx.env)
+        .get(USER_YRAL_COIN_STATE, &user_principal.to_text(), "export")
+        .await
+}
+
+/// archives the user's coin data to `GDPR_ARCHIVE` before erasing it
+async fn forget_user(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    let user_principal = principals!(ctx, "user_principal");
+    let coin_state = coin_state(&ctx.env);
+
+    let mut export_res = coin_state
+        .get(USER_YRAL_COIN_STATE, &user_principal.to_text(), "export")
+        .await?;
+    if export_res.status_code() != 200 {
+        return Ok(export_res);
+    }
+    let export = export_res.text().await?;
+
+    let archive_key = format!(
+        "{GDPR_ARCHIVE_PREFIX}/{}/{}.json",
+        user_principal.to_text(),
+        Date::now().as_millis()
+    );
+    ctx.env
+        .bucket("GDPR_ARCHIVE")?
+        .put(&archive_key, export.into_bytes())
+        .execute()
+        .await?;
+
+    let forget_res = coin_state
+        .fetch(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "forget",
+            RequestInitBuilder::default().method(Method::Post),
+        )
+        .await?;
+    if forget_res.status_code() != 200 {
+        return Ok(forget_res);
+    }
+
+    if let Err(e) = unregister_coin_holder(&ctx.env, user_principal).await {
+        console_warn!("failed to unregister coin holder {user_principal}: {e}");
+    }
+
+    Response::from_json(&ForgetRes { archive_key })
+}
+
+async fn add_balance_webhook(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    let webhook: WebhookSubscription = json_body!(req);
+    if webhook.id.is_empty() || !webhook.url.starts_with("https://") {
+        return error_resp("invalid webhook", 400);
+    }
+    register_webhook(&ctx.env, &webhook).await?;
+
+    Response::from_json(&webhook)
+}
+
+async fn balance_webhooks(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    Response::from_json(&list_webhooks(&ctx.env).await?)
+}
+
+async fn remove_balance_webhook(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(ADMIN_JWT_AUD.into(), ADMIN_JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    let Some(webhook_id) = ctx.param("webhook_id") else {
+        return error_resp("missing webhook id", 400);
+    };
+    delete_webhook(&ctx.env, webhook_id).await?;
+
+    Response::ok("deleted")
+}
+
+async fn estabilish_balance_ws(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    let Ok(query) = req.query::<SignedQuery>() else {
+        return error_resp("missing signature", 401);
+    };
+    if let Err((msg, status)) = verify_signed_query(user_principal, &query, balance_ws_msg) {
+        return error_resp(msg, status);
+    }
+
+    coin_state(&ctx.env)
+        .fetch(
+            USER_YRAL_COIN_STATE,
+            &user_principal.to_text(),
+            "ws/balance",
+            RequestInitBuilder::default()
+                .method(Method::Get)
+                .header("Upgrade", "websocket")?,
+        )
+        .await
+}
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-coin",
+    &[
+        Dependency::DurableObject(USER_YRAL_COIN_STATE),
+        Dependency::DurableObject("CALLER_RATE_LIMITER"),
+        Dependency::DurableObject("USER_HON_GAME_STATE"),
+        Dependency::Kv("YRAL_COIN_CONFIG"),
+        Dependency::Kv("COIN_HOLDERS"),
+        Dependency::Bucket("GDPR_ARCHIVE"),
+        Dependency::Bucket("COIN_ARCHIVE"),
+        Dependency::Queue("REDEMPTION_FULFILLMENT"),
+        Dependency::Queue("BALANCE_WEBHOOKS"),
+        Dependency::Queue("COIN_LEDGER"),
+        Dependency::Queue(NOTIFICATIONS_QUEUE),
+        Dependency::Queue(WEBHOOK_EVENTS_QUEUE),
+        Dependency::D1("COIN_LEDGER_DB"),
+        Dependency::Secret("BACKEND_ADMIN_KEY"),
+        Dependency::Secret("WEBHOOK_SIGNING_SECRET"),
+        Dependency::Secret("OFF_CHAIN_GRPC_AUTH_TOKEN"),
+    ],
+);
+
+const FETCH_SECRETS: &[&str] = &["BACKEND_ADMIN_KEY"];
+const QUEUE_SECRETS: &[&str] = &["WEBHOOK_SIGNING_SECRET"];
+const SCHEDULED_SECRETS: &[&str] = &["OFF_CHAIN_GRPC_AUTH_TOKEN"];
+
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    console_error_panic_hook::set_once();
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let reporter = ErrorReporter::install(&env, "yral-coin").with_request(&req);
+
+    if let Err(e) = SecretSet::validate(&env, FETCH_SECRETS) {
+        return e.into_response();
+    }
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    // account deletion is run by admins
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-coin", method.as_ref(), &path, &["/forget/"]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
 
     let router = Router::new();
 
     let res = router
-        .get_async("/balance/:user_principal", |_req, ctx| {
-            user_yral_balance(ctx)
-        })
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/balance/:user_principal", user_yral_balance)
+        .post_async("/balances", bulk_yral_balances)
         .post_async("/update_balance/:user_principal", update_yral_balance)
-        .get_async("/ws/balance/:user_principal", |_req, ctx| {
-            estabilish_balance_ws(ctx)
+        .post_async("/transactions/:user_principal", user_transactions)
+        .post_async("/transfer/:user_principal", transfer_yral)
+        .post_async("/hold/:user_principal", |req, ctx| {
+            forward_backend_req(req, ctx, "/hold")
         })
+        .post_async("/capture/:user_principal", |req, ctx| {
+            forward_backend_req(req, ctx, "/capture")
+        })
+        .post_async("/release/:user_principal", |req, ctx| {
+            forward_backend_req(req, ctx, "/release")
+        })
+        .post_async("/convert/:user_principal", convert_sats_to_yral)
+        .get_async("/conversions/:user_principal", user_conversions)
+        .post_async("/spend_with_cashback/:user_principal", spend_with_cashback)
+        .get_async("/cashbacks/:user_principal", user_cashbacks)
+        .get_async("/balance_breakdown/:user_principal", user_balance_breakdown)
+        .get_async("/catalog", redemption_catalog)
+        .post_async("/redeem/:user_principal", redeem_item)
+        .get_async("/redemptions/:user_principal", user_redemptions)
+        .post_async("/withdraw/:user_principal", withdraw_yral)
+        .get_async("/withdrawals/:user_principal", user_withdrawals)
+        .get_async("/limits/:user_principal", user_limits)
+        .post_async("/claim_daily_bonus/:user_principal", claim_daily_bonus)
+        .post_async("/admin/limits/:user_principal", set_user_limits)
+        .post_async("/admin/adjust/:user_principal", admin_adjust)
+        .get_async("/admin/audit/:user_principal", admin_audit_log)
+        .post_async("/admin/webhooks", add_balance_webhook)
+        .get_async("/admin/webhooks", balance_webhooks)
+        .delete_async("/admin/webhooks/:webhook_id", remove_balance_webhook)
+        .post_async("/admin/ledger/query", query_global_ledger)
+        .get_async("/ws/balance/:user_principal", estabilish_balance_ws)
+        .get_async("/export/:user_principal", export_user_data)
+        .post_async("/forget/:user_principal", forget_user)
         .options("/*catchall", |_, _| Response::empty())
         .run(req, env)
-        .await?;
+        .await;
+    let res = reporter.check(&ctx, res)?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    if let Err(e) = SecretSet::validate(&env, SCHEDULED_SECRETS) {
+        console_error!("skipping balance snapshot export, {e}");
+        return;
+    }
+
+    if let Err(e) = export_balance_snapshots(&env).await {
+        console_error!("balance snapshot export failed: {e}");
+    }
+}
+
+/// delivers balance webhooks, failed deliveries are retried by the queue
+async fn deliver_balance_webhooks(batch: MessageBatch<serde_json::Value>, env: &Env) -> Result<()> {
+    for message in batch.messages()? {
+        let delivery: WebhookDelivery = match serde_json::from_value(message.body().clone()) {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                console_error!("dropping malformed webhook delivery: {e}");
+                message.ack();
+                continue;
+            }
+        };
+        match deliver_webhook(env, &delivery).await {
+            Ok(()) => message.ack(),
+            Err(e) => {
+                console_warn!("balance webhook delivery failed: {e}");
+                message.retry();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// streams DO ledger entries into `COIN_LEDGER_DB`
+async fn record_global_ledger(batch: MessageBatch<serde_json::Value>, env: &Env) -> Result<()> {
+    let entries = batch
+        .messages()?
+        .into_iter()
+        .filter_map(|message| {
+            serde_json::from_value::<GlobalLedgerMessage>(message.body().clone())
+                .inspect_err(|e| console_error!("dropping malformed ledger message: {e}"))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+
+    match record_ledger_entries(env, &entries).await {
+        Ok(()) => batch.ack_all(),
+        Err(e) => {
+            console_error!("failed to record global ledger batch: {e}");
+            batch.retry_all();
+        }
+    }
+
+    Ok(())
+}
+
+#[event(queue)]
+async fn queue(batch: MessageBatch<serde_json::Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    if let Err(e) = SecretSet::validate(&env, QUEUE_SECRETS) {
+        console_error!("{e}");
+        batch.retry_all();
+        return Ok(());
+    }
 
-    res.with_cors(&cors_policy())
+    match batch.queue().as_str() {
+        COIN_LEDGER_QUEUE => record_global_ledger(batch, &env).await,
+        BALANCE_WEBHOOKS_QUEUE => deliver_balance_webhooks(batch, &env).await,
+        queue => {
+            console_error!("unexpected queue {queue}");
+            batch.retry_all();
+            Ok(())
+        }
+    }
 }
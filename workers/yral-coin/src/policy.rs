@@ -0,0 +1,78 @@
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{DEFAULT_MIN_BALANCE_FLOOR_YRAL, MIN_BALANCE_POLICY_KEY},
+    error::WorkerError,
+    types::{BalanceUpdateReason, MinBalancePolicy},
+};
+
+impl UserYralCoinState {
+    /// floor from the `MIN_BALANCE_FLOOR_YRAL` var, replaced by `MIN_BALANCE_POLICY_KEY` in KV if set
+    /// loaded once per DO instance
+    async fn min_balance_policy(&self) -> MinBalancePolicy {
+        if let Some(policy) = self.min_balance_policy.borrow().as_ref() {
+            return policy.clone();
+        }
+
+        let floor = self
+            .env
+            .var("MIN_BALANCE_FLOOR_YRAL")
+            .ok()
+            .and_then(|v| v.to_string().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MIN_BALANCE_FLOOR_YRAL);
+        let kv_policy = async {
+            self.env
+                .kv("YRAL_COIN_CONFIG")?
+                .get(MIN_BALANCE_POLICY_KEY)
+                .json::<MinBalancePolicy>()
+                .await
+        };
+        let policy = match kv_policy.await {
+            Ok(Some(policy)) => policy,
+            Ok(None) => MinBalancePolicy::new(BigUint::from(floor)),
+            // not cached, retried on next use
+            Err(e) => {
+                console_warn!("failed to read min balance policy from KV: {e}");
+                return MinBalancePolicy::new(BigUint::from(floor));
+            }
+        };
+
+        *self.min_balance_policy.borrow_mut() = Some(policy.clone());
+        policy
+    }
+
+    /// rejects deductions that would leave less than the policy floor
+    /// on top of held coins, plain overdrafts are left to `apply_delta`
+    pub(crate) async fn check_min_balance(
+        &self,
+        delta: &BigInt,
+        reason: Option<BalanceUpdateReason>,
+    ) -> StdResult<(), (u16, WorkerError)> {
+        if *delta >= BigInt::ZERO {
+            return Ok(());
+        }
+        let policy = self.min_balance_policy().await;
+        if !policy.applies_to(reason) {
+            return Ok(());
+        }
+
+        let info = self
+            .balance_info()
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        let required = delta.magnitude() + &info.held;
+        if info.balance >= required && info.balance < required + &policy.floor {
+            return Err((
+                400,
+                WorkerError::BelowMinimumBalance {
+                    floor: policy.floor,
+                },
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use num_bigint::{BigInt, BigUint};
+use worker::*;
+use worker_utils::storage::{batch::WriteBatch, SafeStorage};
+
+use crate::{
+    coin::UserYralCoinState,
+    types::{LedgerEntry, PromoGrant},
+};
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    /// stages a promotional grant of coins credited in the same batch
+    /// the caller schedules the alarm for `expires_at` once the batch commits
+    pub(crate) async fn stage_promo_grant(
+        &self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        amount: BigUint,
+        expires_at: u64,
+    ) -> Result<()> {
+        self.promo_grants
+            .borrow_mut()
+            .update_in(storage, batch, |grants| {
+                grants.push(PromoGrant { amount, expires_at });
+                grants.sort_by_key(|grant| grant.expires_at);
+            })
+            .await
+    }
+
+    /// stages drawing `amount` from the grants expiring soonest, returns what they didn't cover
+    pub(crate) async fn stage_promo_spend(
+        &self,
+        storage: &SafeStorage,
+        batch: &mut WriteBatch,
+        mut amount: BigUint,
+    ) -> Result<BigUint> {
+        self.promo_grants
+            .borrow_mut()
+            .update_in(storage, batch, |grants| {
+                for grant in grants.iter_mut() {
+                    let used = amount.clone().min(grant.amount.clone());
+                    grant.amount -= &used;
+                    amount -= used;
+                    if amount == BigUint::ZERO {
+                        break;
+                    }
+                }
+                grants.retain(|grant| grant.amount > BigUint::ZERO);
+            })
+            .await?;
+
+        Ok(amount)
+    }
+
+    /// removes expired promotional coins from the balance
+    pub(crate) async fn expire_promos(&self) -> Result<()> {
+        let storage = self.storage();
+        let mut batch = storage.batch();
+        let now = Date::now().as_millis();
+        let mut expired = BigUint::ZERO;
+        let mut next_expiry = None;
+        let mut removed = BigUint::ZERO;
+        let mut new_bal = BigUint::ZERO;
+        let staged = async {
+            {
+                self.promo_grants
+                    .borrow_mut()
+                    .update_in(&storage, &mut batch, |grants| {
+                        for grant in grants.iter().filter(|grant| grant.expires_at <= now) {
+                            expired += &grant.amount;
+                        }
+                        grants.retain(|grant| grant.expires_at > now);
+                        next_expiry = grants.first().map(|grant| grant.expires_at);
+                    })
+                    .await?;
+            }
+            if expired == BigUint::ZERO {
+                return Ok(());
+            }
+
+            // held coins stay until captured or released, so only the rest can expire
+            let held = { self.held.borrow_mut().read(&storage).await?.clone() };
+            // expiry is not a user spend, so the daily deduct limit is left untouched
+            self.yral
+                .borrow_mut()
+                .update_in(&storage, &mut batch, |balance| {
+                    let spendable = if *balance > held {
+                        balance.clone() - &held
+                    } else {
+                        BigUint::ZERO
+                    };
+                    removed = expired.clone().min(spendable);
+                    *balance -= &removed;
+                    new_bal = balance.clone();
+                })
+                .await
+        };
+        staged
+            .await
+            .inspect_err(|_| self.invalidate_cached_state())?;
+        if let Some(next_expiry) = next_expiry {
+            self.schedule_alarm(next_expiry).await?;
+        }
+        if expired == BigUint::ZERO {
+            return Ok(());
+        }
+
+        self.commit_with_ledger_entry(
+            batch,
+            LedgerEntry {
+                delta: -BigInt::from(removed.clone()),
+                reason: Some("promotional coins expired".into()),
+                resulting_balance: new_bal,
+                timestamp: now,
+                idempotency_key: None,
+                reason_code: None,
+                metadata: Default::default(),
+            },
+        )
+        .await?;
+        console_log!("expired {removed} promotional YRAL");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use std::cell::RefCell;
+
+use worker::*;
+use worker_utils::{
+    api_error::ApiError,
+    storage::{rate_limit::RateLimit, SafeStorage},
+};
+
+use crate::{
+    consts::{DEFAULT_UPDATE_BALANCE_CALLER_MAX_PER_WINDOW, UPDATE_BALANCE_RATE_LIMIT_WINDOW_MS},
+    types::{RateLimitScope, UpdateRateLimited},
+};
+
+/// 429 with the exhausted limit in the error details and a `Retry-After` header (in seconds)
+pub fn update_rate_limited_response(
+    scope: RateLimitScope,
+    limit: &RateLimit,
+    retry_after_ms: u64,
+) -> Result<Response> {
+    let res = ApiError::from_status(429, "rate limited")
+        .with_details(UpdateRateLimited {
+            scope,
+            max_requests: limit.max_requests(),
+            window_ms: limit.window_ms(),
+            retry_after_ms,
+        })
+        .into_response(429)?;
+    res.headers().set(
+        "Retry-After",
+        &retry_after_ms.div_ceil(1000).max(1).to_string(),
+    )?;
+
+    Ok(res)
+}
+
+pub fn limit_from_env(env: &Env, var: &str, default: u32) -> u32 {
+    env.var(var)
+        .ok()
+        .and_then(|v| v.to_string().parse().ok())
+        .unwrap_or(default)
+}
+
+/// Per caller (JWT subject) limiter for balance updates
+/// one instance per subject
+#[durable_object]
+pub struct CallerRateLimiter {
+    state: State,
+    env: Env,
+    limit: RefCell<RateLimit>,
+}
+
+impl DurableObject for CallerRateLimiter {
+    fn new(state: State, env: Env) -> Self {
+        console_error_panic_hook::set_once();
+
+        let max_requests = limit_from_env(
+            &env,
+            "UPDATE_BALANCE_CALLER_MAX_PER_MINUTE",
+            DEFAULT_UPDATE_BALANCE_CALLER_MAX_PER_WINDOW,
+        );
+        Self {
+            state,
+            env,
+            limit: RefCell::new(RateLimit::new(
+                "caller-rate-limit",
+                max_requests,
+                UPDATE_BALANCE_RATE_LIMIT_WINDOW_MS,
+            )),
+        }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let env = self.env.clone();
+        let router = Router::with_data(self);
+
+        router
+            .post_async("/acquire", {
+                // SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+                // because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+                #[allow(clippy::await_holding_refcell_ref)]
+                async |_, ctx| {
+                    let this = ctx.data;
+                    let mut storage: SafeStorage = this.state.storage().into();
+                    let mut limit = this.limit.borrow_mut();
+                    if let Err(retry_after_ms) = limit.try_acquire(&mut storage).await? {
+                        return update_rate_limited_response(
+                            RateLimitScope::Caller,
+                            &limit,
+                            retry_after_ms,
+                        );
+                    }
+
+                    Response::ok("ok")
+                }
+            })
+            .run(req, env)
+            .await
+    }
+}
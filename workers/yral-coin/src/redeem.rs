@@ -0,0 +1,114 @@
+use num_bigint::BigInt;
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{REDEMPTION_CATALOG_KEY, REDEMPTION_HISTORY_LIMIT},
+    error::WorkerError,
+    types::{BalanceUpdateReason, CatalogItem, LedgerReason, RedeemReq, RedemptionReceipt},
+};
+
+fn to_internal(e: worker::Error) -> (u16, WorkerError) {
+    (500, WorkerError::Internal(e.to_string()))
+}
+
+pub(crate) const RECEIPT_PREFIX: &str = "redemption-";
+
+fn receipt_key(receipt_id: &str) -> String {
+    format!("{RECEIPT_PREFIX}{receipt_id}")
+}
+
+/// redeemable items, stored as a JSON array under `REDEMPTION_CATALOG_KEY`
+pub async fn load_catalog(env: &Env) -> Result<Vec<CatalogItem>> {
+    let catalog = env
+        .kv("YRAL_COIN_CONFIG")?
+        .get(REDEMPTION_CATALOG_KEY)
+        .json::<Vec<CatalogItem>>()
+        .await?;
+
+    Ok(catalog.unwrap_or_default())
+}
+
+impl UserYralCoinState {
+    /// deducts the item's price, records a receipt and queues it for fulfillment
+    pub async fn redeem(&self, req: RedeemReq) -> StdResult<RedemptionReceipt, (u16, WorkerError)> {
+        let mut storage = self.storage();
+        let key = receipt_key(&req.nonce);
+        if storage
+            .get::<RedemptionReceipt>(&key)
+            .await
+            .map_err(to_internal)?
+            .is_some()
+            || self.is_forgotten(&key).await.map_err(to_internal)?
+        {
+            return Err((409, WorkerError::DuplicateRedemption));
+        }
+
+        let item = load_catalog(&self.env)
+            .await
+            .map_err(to_internal)?
+            .into_iter()
+            .find(|item| item.item_id == req.item_id && item.available)
+            .ok_or((404, WorkerError::CatalogItemNotFound))?;
+
+        let delta = -BigInt::from(item.price.clone());
+        let new_balance = self
+            .apply_delta(
+                None,
+                delta.clone(),
+                LedgerReason::new(
+                    BalanceUpdateReason::Purchase,
+                    format!("redeemed {}", item.item_id),
+                ),
+                Some(req.nonce.clone()),
+            )
+            .await?;
+
+        let receipt = RedemptionReceipt {
+            receipt_id: req.nonce,
+            user_principal: req.user_principal,
+            item_id: item.item_id,
+            price: item.price,
+            new_balance,
+            created_at: Date::now().as_millis(),
+        };
+        let res = async {
+            storage.put(&key, &receipt).await?;
+            self.env
+                .queue("REDEMPTION_FULFILLMENT")?
+                .send(&receipt)
+                .await
+        }
+        .await;
+        let Err(e) = res else {
+            return Ok(receipt);
+        };
+
+        // nothing will be fulfilled, give the coins back
+        console_error!("failed to queue redemption {}: {e}", receipt.receipt_id);
+        if let Err(e) = storage.delete(&key).await {
+            console_error!("failed to delete redemption receipt: {e}");
+        }
+        self.revert_delta(
+            delta,
+            format!("redemption {} failed", receipt.receipt_id).into(),
+        )
+        .await?;
+
+        Err(to_internal(e))
+    }
+
+    pub async fn redemptions(&self) -> Result<Vec<RedemptionReceipt>> {
+        let mut receipts = self
+            .storage()
+            .list_with_prefix(RECEIPT_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect::<Result<Vec<RedemptionReceipt>>>()?;
+        receipts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        receipts.truncate(REDEMPTION_HISTORY_LIMIT);
+
+        Ok(receipts)
+    }
+}
@@ -0,0 +1,201 @@
+use candid::Principal;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::*;
+use worker_utils::RequestInitBuilder;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{
+        COIN_HOLDERS_PAGE_SIZE, DAY_MS, OFF_CHAIN_EVENTS_URL, SNAPSHOT_CONCURRENCY,
+        SNAPSHOT_EXPORT_STATE_KEY, SNAPSHOT_EXPORT_TIMEOUT, SNAPSHOT_PAGES_PER_RUN,
+    },
+    types::BalanceSnapshot,
+};
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    pub async fn snapshot(&self) -> Result<BalanceSnapshot> {
+        let storage = self.storage();
+        let balance = self.balance_info().await?.balance;
+        let limits = self.limits().await?;
+        let (credited_today, deducted_today) = {
+            self.yral
+                .borrow_mut()
+                .consumed_today(&storage, limits.into())
+                .await?
+        };
+
+        Ok(BalanceSnapshot {
+            balance,
+            credited_today,
+            deducted_today,
+        })
+    }
+}
+
+/// adds the principal to the KV registry walked by the nightly export
+pub async fn register_coin_holder(env: &Env, user_principal: Principal) {
+    let res = async {
+        let kv = env.kv("COIN_HOLDERS")?;
+        let key = user_principal.to_text();
+        if kv.get(&key).text().await?.is_some() {
+            return Ok(());
+        }
+        kv.put(&key, "")?.execute().await?;
+        Ok::<_, worker::Error>(())
+    };
+    if let Err(e) = res.await {
+        console_warn!("failed to register coin holder {user_principal}: {e}");
+    }
+}
+
+/// removes the principal from the registry, e.g. once their data is erased
+pub async fn unregister_coin_holder(env: &Env, user_principal: Principal) -> Result<()> {
+    env.kv("COIN_HOLDERS")?
+        .delete(&user_principal.to_text())
+        .await
+        .map_err(|e| Error::RustError(e.to_string()))
+}
+
+async fn fetch_snapshot(env: &Env, user_principal: Principal) -> Result<BalanceSnapshot> {
+    let stub = env
+        .durable_object("USER_YRAL_COIN_STATE")?
+        .id_from_name(&user_principal.to_text())?
+        .get_stub()?;
+    let mut res = stub.fetch_with_str("http://fake_url.com/snapshot").await?;
+    if res.status_code() != 200 {
+        return Err(Error::RustError(res.text().await?));
+    }
+
+    res.json().await
+}
+
+async fn send_snapshot_event(
+    env: &Env,
+    snapshot_at: u64,
+    snapshots: Vec<serde_json::Value>,
+) -> Result<()> {
+    let auth_token = env.secret("OFF_CHAIN_GRPC_AUTH_TOKEN")?.to_string();
+    let params = json!({
+        "snapshot_at": snapshot_at,
+        "snapshots": snapshots,
+    })
+    .to_string();
+
+    let mut res = RequestInitBuilder::default()
+        .method(Method::Post)
+        .header("Authorization", &format!("Bearer {auth_token}"))?
+        .json(&json!({
+            "event": "yral_coin_balance_snapshot",
+            "params": params,
+        }))?
+        .timeout(SNAPSHOT_EXPORT_TIMEOUT)
+        .fetch(OFF_CHAIN_EVENTS_URL)
+        .await?;
+    if res.status_code() >= 300 {
+        return Err(Error::RustError(format!(
+            "snapshot export failed: {} {}",
+            res.status_code(),
+            res.text().await?
+        )));
+    }
+
+    Ok(())
+}
+
+/// progress of the current export, kept in `YRAL_COIN_CONFIG` between cron runs
+#[derive(Serialize, Deserialize)]
+struct SnapshotExport {
+    snapshot_at: u64,
+    cursor: Option<String>,
+    complete: bool,
+}
+
+/// ships balance snapshots of registered coin holders to the warehouse
+/// a new export starts once a day, each cron run continues it for a few pages
+/// from the saved cursor so a run stays within the CPU and subrequest limits
+pub async fn export_balance_snapshots(env: &Env) -> Result<()> {
+    let config = env.kv("YRAL_COIN_CONFIG")?;
+    let holders = env.kv("COIN_HOLDERS")?;
+    let now = Date::now().as_millis();
+
+    let mut export = match config
+        .get(SNAPSHOT_EXPORT_STATE_KEY)
+        .json::<SnapshotExport>()
+        .await?
+    {
+        Some(export) if !export.complete => export,
+        Some(export) if export.snapshot_at / DAY_MS == now / DAY_MS => return Ok(()),
+        _ => SnapshotExport {
+            snapshot_at: now,
+            cursor: None,
+            complete: false,
+        },
+    };
+    let mut exported = 0usize;
+    let mut failed = 0usize;
+
+    for _ in 0..SNAPSHOT_PAGES_PER_RUN {
+        let mut list = holders.list().limit(COIN_HOLDERS_PAGE_SIZE);
+        if let Some(cursor) = export.cursor.clone() {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+
+        let principals = page
+            .keys
+            .iter()
+            .filter_map(|key| Principal::from_text(&key.name).ok());
+        let snapshots: Vec<_> = stream::iter(principals)
+            .map(|principal| async move {
+                let res = fetch_snapshot(env, principal).await;
+                (principal, res)
+            })
+            .buffer_unordered(SNAPSHOT_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut batch = Vec::with_capacity(snapshots.len());
+        for (principal, res) in snapshots {
+            match res {
+                Ok(snapshot) => batch.push(json!({
+                    "user_principal": principal,
+                    "balance": snapshot.balance.to_string(),
+                    "credited_today": snapshot.credited_today.to_string(),
+                    "deducted_today": snapshot.deducted_today.to_string(),
+                })),
+                Err(e) => {
+                    console_error!("failed to snapshot {principal}: {e}");
+                    failed += 1;
+                }
+            }
+        }
+        exported += batch.len();
+        if !batch.is_empty() {
+            send_snapshot_event(env, export.snapshot_at, batch).await?;
+        }
+
+        export.cursor = page.cursor.filter(|_| !page.list_complete);
+        export.complete = export.cursor.is_none();
+        config
+            .put(SNAPSHOT_EXPORT_STATE_KEY, &export)?
+            .execute()
+            .await?;
+        if export.complete {
+            break;
+        }
+    }
+
+    console_log!(
+        "exported {exported} coin balance snapshots, {failed} failed, export {}",
+        if export.complete {
+            "complete"
+        } else {
+            "continues next run"
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+use worker_utils::{api_error::ApiError, RequestInitBuilder};
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{OWNER_HEADER, TRANSFER_RECONCILE_DELAY_MS},
+    error::WorkerError,
+    types::{TransferRecord, TransferReq, TransferStatus},
+};
+
+pub(crate) const TRANSFER_PREFIX: &str = "transfer-record-";
+/// nonces of transfers made before they had records, kept so they can't be replayed
+pub(crate) const LEGACY_NONCE_PREFIX: &str = "transfer-nonce-";
+/// recipient side, remembers credits already applied so a resent one is a no-op
+pub(crate) const RECEIVED_PREFIX: &str = "received-transfer-";
+
+fn to_internal(e: worker::Error) -> (u16, WorkerError) {
+    (500, WorkerError::Internal(e.to_string()))
+}
+
+fn transfer_key(nonce: &str) -> String {
+    format!("{TRANSFER_PREFIX}{nonce}")
+}
+
+fn received_key(req: &TransferReq) -> String {
+    format!("{RECEIVED_PREFIX}{}-{}", req.sender, req.nonce)
+}
+
+/// the recipient refused the credit, e.g. a limit was hit
+/// anything else may have been applied and is only retried
+fn is_definite(code: u16) -> bool {
+    (400..500).contains(&code)
+}
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    async fn credit_recipient(&self, req: &TransferReq) -> StdResult<(), (u16, WorkerError)> {
+        let recipient_stub = self
+            .env
+            .durable_object("USER_YRAL_COIN_STATE")
+            .and_then(|ns| ns.id_from_name(&req.recipient.to_text()))
+            .and_then(|id| id.get_stub())
+            .map_err(to_internal)?;
+
+        let credit_req = Request::new_with_init(
+            "http://fake_url.com/receive_transfer",
+            RequestInitBuilder::default()
+                .method(Method::Post)
+                .header(OWNER_HEADER, &req.recipient.to_text())
+                .map_err(to_internal)?
+                .json(req)
+                .map_err(to_internal)?
+                .build(),
+        )
+        .map_err(to_internal)?;
+
+        let mut res = recipient_stub
+            .fetch_with_request(credit_req)
+            .await
+            .map_err(to_internal)?;
+        if res.status_code() == 200 {
+            return Ok(());
+        }
+
+        let code = res.status_code();
+        let err = match res.json::<ApiError>().await {
+            Ok(api_err) => api_err
+                .to_enum()
+                .unwrap_or(WorkerError::Internal(api_err.message)),
+            Err(e) => WorkerError::Internal(e.to_string()),
+        };
+        Err((code, err))
+    }
+
+    async fn has_transfer(&self, nonce: &str) -> StdResult<bool, (u16, WorkerError)> {
+        let storage = self.storage();
+        let key = transfer_key(nonce);
+        let recorded = storage
+            .get::<TransferRecord>(&key)
+            .await
+            .map_err(to_internal)?
+            .is_some();
+        if recorded || self.is_forgotten(&key).await.map_err(to_internal)? {
+            return Ok(true);
+        }
+        let legacy_key = format!("{LEGACY_NONCE_PREFIX}{nonce}");
+        let legacy = storage
+            .get::<bool>(&legacy_key)
+            .await
+            .map_err(to_internal)?
+            .is_some();
+
+        Ok(legacy || self.is_forgotten(&legacy_key).await.map_err(to_internal)?)
+    }
+
+    /// debits the sender, crediting the recipient's state
+    /// the debit is reverted only if the recipient refused the credit, a credit with an unknown
+    /// outcome stays pending and is resent by the alarm
+    pub async fn transfer(&self, req: TransferReq) -> StdResult<BigUint, (u16, WorkerError)> {
+        if req.amount == BigUint::ZERO || req.sender == req.recipient {
+            return Err((400, WorkerError::InvalidTransfer));
+        }
+
+        // other requests run while this one awaits the recipient, the nonce is
+        // claimed here so they can't debit the same transfer twice
+        if !self
+            .transfers_in_flight
+            .borrow_mut()
+            .insert(req.nonce.clone())
+        {
+            return Err((409, WorkerError::DuplicateTransfer));
+        }
+        let nonce = req.nonce.clone();
+        let res = self.transfer_inner(req).await;
+        self.transfers_in_flight.borrow_mut().remove(&nonce);
+
+        res
+    }
+
+    async fn transfer_inner(&self, req: TransferReq) -> StdResult<BigUint, (u16, WorkerError)> {
+        if self.has_transfer(&req.nonce).await? {
+            return Err((409, WorkerError::DuplicateTransfer));
+        }
+
+        let mut record = TransferRecord {
+            req,
+            created_at: Date::now().as_millis(),
+            status: TransferStatus::Pending,
+        };
+        // recorded with the debit and before the credit, so a crash or an unknown outcome
+        // is reconciled, a failed debit writes neither and the nonce can be used again
+        let mut batch = self.storage().batch();
+        batch
+            .put(transfer_key(&record.req.nonce), &record)
+            .map_err(to_internal)?;
+        let new_bal = self
+            .apply_delta_in(
+                batch,
+                None,
+                -BigInt::from(record.req.amount.clone()),
+                format!("transfer to {}", record.req.recipient).into(),
+                Some(record.req.nonce.clone()),
+            )
+            .await?;
+        if let Err(e) = self
+            .schedule_alarm(record.created_at + TRANSFER_RECONCILE_DELAY_MS)
+            .await
+        {
+            console_error!("failed to schedule transfer reconciliation: {e}");
+        }
+
+        let credit = self.credit_recipient(&record.req).await;
+        self.settle_transfer(&mut record, credit).await?;
+
+        Ok(new_bal)
+    }
+
+    /// records the credit's outcome, the sender is only refunded once the recipient refused it
+    async fn settle_transfer(
+        &self,
+        record: &mut TransferRecord,
+        credit: StdResult<(), (u16, WorkerError)>,
+    ) -> StdResult<(), (u16, WorkerError)> {
+        let res = match credit {
+            Ok(()) => {
+                record.status = TransferStatus::Completed;
+                Ok(())
+            }
+            Err((code, e)) if !is_definite(code) => {
+                console_warn!(
+                    "yral transfer {} outcome unknown, reconciling later: {e}",
+                    record.req.nonce
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                self.revert_delta(
+                    -BigInt::from(record.req.amount.clone()),
+                    format!("transfer to {} reverted", record.req.recipient).into(),
+                )
+                .await?;
+                record.status = TransferStatus::Failed;
+                Err(e)
+            }
+        };
+
+        let key = transfer_key(&record.req.nonce);
+        if let Err(e) = self.storage().put(&key, &*record).await {
+            console_error!("failed to store transfer record: {e}");
+        }
+
+        res
+    }
+
+    /// credits the recipients of `Pending` transfers again, the recipient dedupes on the nonce
+    pub(crate) async fn reconcile_transfers(&self) -> Result<()> {
+        let now = Date::now().as_millis();
+        let pending = self
+            .storage()
+            .list_with_prefix::<TransferRecord>(TRANSFER_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|record| record.status == TransferStatus::Pending);
+
+        let mut next_attempt = None::<u64>;
+        for mut record in pending {
+            if self
+                .transfers_in_flight
+                .borrow()
+                .contains(&record.req.nonce)
+                || record.created_at + TRANSFER_RECONCILE_DELAY_MS > now
+            {
+                next_attempt = Some(now + TRANSFER_RECONCILE_DELAY_MS);
+                continue;
+            }
+
+            let credit = self.credit_recipient(&record.req).await;
+            if let Err((_, e)) = self.settle_transfer(&mut record, credit).await {
+                console_error!("transfer {} refused by recipient: {e}", record.req.nonce);
+            }
+            if record.status == TransferStatus::Pending {
+                next_attempt = Some(now + TRANSFER_RECONCILE_DELAY_MS);
+            }
+        }
+
+        if let Some(next_attempt) = next_attempt {
+            self.schedule_alarm(next_attempt).await?;
+        }
+
+        Ok(())
+    }
+
+    /// credits a transfer once, a resent credit returns the current balance
+    pub async fn receive_transfer(
+        &self,
+        req: TransferReq,
+    ) -> StdResult<BigUint, (u16, WorkerError)> {
+        let key = received_key(&req);
+        let storage = self.storage();
+        if storage
+            .get::<bool>(&key)
+            .await
+            .map_err(to_internal)?
+            .is_some()
+            || self.is_forgotten(&key).await.map_err(to_internal)?
+        {
+            let info = self.balance_info().await.map_err(to_internal)?;
+            return Ok(info.balance);
+        }
+
+        // marked received in the same commit as the credit, a failed credit leaves no mark
+        let mut batch = storage.batch();
+        batch.put(&key, &true).map_err(to_internal)?;
+        self.apply_delta_in(
+            batch,
+            None,
+            BigInt::from(req.amount.clone()),
+            format!("transfer from {}", req.sender).into(),
+            Some(req.nonce.clone()),
+        )
+        .await
+    }
+}
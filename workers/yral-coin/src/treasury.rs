@@ -0,0 +1,133 @@
+use candid::{Nat, Principal};
+use enum_dispatch::enum_dispatch;
+use worker::Env;
+use worker_utils::{
+    environment::{profile_config, TreasuryKind},
+    icp::agent_wrapper::{identity_from_pem, AgentConfig, AgentWrapper},
+};
+use yral_canisters_client::sns_ledger::{
+    Account, SnsLedger, TransferArg, TransferError, TransferResult,
+};
+
+use crate::error::WorkerError;
+
+pub(crate) enum TreasuryError {
+    /// the ledger refused the transfer, nothing moved
+    Rejected((u16, WorkerError)),
+    /// the call failed in flight, the transfer may have landed
+    /// resend it with the same arguments, the ledger dedupes it
+    Ambiguous(String),
+}
+
+/// pays out on-chain YRAL (DOLR) from the treasury account
+#[enum_dispatch]
+pub(crate) trait YralTreasury {
+    /// `amount` is in the ledger's base units (e8s)
+    /// `created_at_time` (unix nanos) lets the ledger dedupe resends of the same transfer
+    async fn transfer_yral(
+        &self,
+        to: Principal,
+        amount: Nat,
+        memo_text: Option<String>,
+        created_at_time: u64,
+    ) -> Result<(), TreasuryError>;
+}
+
+pub struct NoOpYralTreasury;
+
+impl YralTreasury for NoOpYralTreasury {
+    async fn transfer_yral(
+        &self,
+        _to: Principal,
+        _amount: Nat,
+        _memo_text: Option<String>,
+        _created_at_time: u64,
+    ) -> Result<(), TreasuryError> {
+        Ok(())
+    }
+}
+
+pub struct AdminYralTreasury {
+    agent: AgentWrapper,
+    ledger: Principal,
+}
+
+impl AdminYralTreasury {
+    pub fn new(env: &Env) -> Result<Self, worker::Error> {
+        let admin_pem = env.secret("BACKEND_ADMIN_KEY")?.to_string();
+        let id = identity_from_pem(&admin_pem)?;
+        let ledger = Principal::from_text(env.var("YRAL_LEDGER_CANISTER_ID")?.to_string())
+            .map_err(|e| worker::Error::RustError(e.to_string()))?;
+
+        Ok(Self {
+            agent: AgentWrapper::with_config(id, AgentConfig::from_env(env)),
+            ledger,
+        })
+    }
+}
+
+impl YralTreasury for AdminYralTreasury {
+    async fn transfer_yral(
+        &self,
+        to: Principal,
+        amount: Nat,
+        memo_text: Option<String>,
+        created_at_time: u64,
+    ) -> Result<(), TreasuryError> {
+        let ledger = SnsLedger(self.ledger, self.agent.get().await);
+
+        let memo = memo_text.unwrap_or_else(|| "Memo not specified".to_string());
+
+        let res = self
+            .agent
+            .guarded(
+                self.ledger,
+                ledger.icrc_1_transfer(TransferArg {
+                    to: Account {
+                        owner: to,
+                        subaccount: None,
+                    },
+                    fee: None,
+                    memo: Some(Vec::from(memo).into()),
+                    from_subaccount: None,
+                    created_at_time: Some(created_at_time),
+                    amount,
+                }),
+            )
+            .await
+            .map_err(|e| TreasuryError::Ambiguous(e.to_string()))?;
+        match res {
+            TransferResult::Ok(_) => Ok(()),
+            // an earlier attempt of this transfer went through
+            TransferResult::Err(TransferError::Duplicate { .. }) => Ok(()),
+            // past the ledger's dedupe window, only the ledger history can tell
+            TransferResult::Err(TransferError::TooOld) => Err(TreasuryError::Ambiguous(
+                "transfer too old to dedupe".into(),
+            )),
+            TransferResult::Err(TransferError::InsufficientFunds { .. }) => Err(
+                TreasuryError::Rejected((500, WorkerError::TreasuryOutOfFunds)),
+            ),
+            TransferResult::Err(e) => Err(TreasuryError::Rejected((
+                500,
+                WorkerError::Internal(format!("{e:?}")),
+            ))),
+        }
+    }
+}
+
+#[enum_dispatch(YralTreasury)]
+pub enum YralTreasuryImpl {
+    Mock(NoOpYralTreasury),
+    Real(AdminYralTreasury),
+}
+
+impl YralTreasuryImpl {
+    pub fn new(env: &Env) -> Result<Self, worker::Error> {
+        let this = match profile_config(env).treasury {
+            TreasuryKind::Admin => Self::Real(AdminYralTreasury::new(env)?),
+            TreasuryKind::NoOp => Self::Mock(NoOpYralTreasury),
+        };
+
+        Ok(this)
+    }
+}
@@ -1,18 +1,1060 @@
+use std::collections::{BTreeMap, HashMap};
+
+use candid::{Nat, Principal};
 use num_bigint::{BigInt, BigUint};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
+use worker_utils::{signed_req::SignedMessage, storage::balance::DailyLimits};
+use yral_identity::{msg_builder::Message, Signature};
+
+use crate::error::WorkerError;
 
 #[serde_as]
 #[derive(Serialize, Deserialize)]
 pub struct YralBalanceInfo {
     #[serde_as(as = "DisplayFromStr")]
     pub balance: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub airdropped: BigUint,
+    /// part of `balance` reserved by pending holds
+    #[serde_as(as = "DisplayFromStr")]
+    pub held: BigUint,
+    /// part of `balance` that expires, see `PromoGrant`
+    #[serde_as(as = "DisplayFromStr")]
+    pub promotional: BigUint,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BulkBalanceReq {
+    pub principals: Vec<Principal>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BulkBalanceRes {
+    /// keyed by principal text
+    pub balances: HashMap<String, YralBalanceInfo>,
+    pub failed: Vec<Principal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceUpdateReason {
+    GamePayout,
+    Campaign,
+    Purchase,
+    Refund,
+    AdminAdjust,
+    Withdrawal,
+    /// converted from another economy by yral-admin
+    Migration,
+}
+
+/// why a balance changed, recorded with the ledger entry
+#[derive(Clone, Debug, Default)]
+pub struct LedgerReason {
+    pub code: Option<BalanceUpdateReason>,
+    pub description: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl LedgerReason {
+    pub fn new(code: BalanceUpdateReason, description: impl Into<String>) -> Self {
+        Self {
+            code: Some(code),
+            description: Some(description.into()),
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<&str> for LedgerReason {
+    fn from(description: &str) -> Self {
+        String::from(description).into()
+    }
+}
+
+impl From<String> for LedgerReason {
+    fn from(description: String) -> Self {
+        Self {
+            description: Some(description),
+            ..Default::default()
+        }
+    }
 }
 
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReasonTotals {
+    #[serde_as(as = "DisplayFromStr")]
+    pub credited: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub deducted: BigUint,
+}
+
+/// keyed by `BalanceUpdateReason`, updates without a reason code are under `Other`
+pub type BalanceBreakdown = BTreeMap<String, ReasonTotals>;
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct YralBalanceUpdateRequest {
     pub previous_balance: BigUint,
     #[serde_as(as = "DisplayFromStr")]
     pub delta: BigInt,
+    pub reason: BalanceUpdateReason,
+    /// free form description stored in the ledger
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    #[serde(default)]
+    pub is_airdropped: bool,
+    /// credits the delta as promotional coins expiring at this unix timestamp (millis)
+    #[serde(default)]
+    pub promo_expires_at: Option<u64>,
+    /// always notify the user about this credit
+    #[serde(default)]
+    pub campaign: bool,
+}
+
+/// stored result of an update carrying an idempotency key
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub enum UpdateOutcome {
+    Ok(#[serde_as(as = "DisplayFromStr")] BigUint),
+    Err { code: u16, error: WorkerError },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MemoizedUpdate {
+    pub outcome: UpdateOutcome,
+    pub created_at: u64,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerEntry {
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    /// free form description
+    pub reason: Option<String>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub resulting_balance: BigUint,
+    pub timestamp: u64,
+    pub idempotency_key: Option<String>,
+    #[serde(default)]
+    pub reason_code: Option<BalanceUpdateReason>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionsReq {
+    pub page_size: usize,
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionsRes {
+    pub transactions: Vec<LedgerEntry>,
+    pub next: Option<String>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YralTransferRequest {
+    pub sender: Principal,
+    pub recipient: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    /// unique per transfer, replays are rejected
+    pub nonce: String,
+    pub signature: Signature,
+}
+
+pub fn transfer_msg(recipient: Principal, amount: BigUint, nonce: String) -> Message {
+    Message::default()
+        .method_name("yral_coin_transfer".into())
+        .args((recipient, Nat::from(amount), nonce))
+        .expect("transfer request should serialize")
+}
+
+impl SignedMessage for YralTransferRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        transfer_msg(self.recipient, self.amount.clone(), self.nonce.clone())
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransferReq {
+    pub sender: Principal,
+    pub recipient: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    pub nonce: String,
+}
+
+impl From<YralTransferRequest> for TransferReq {
+    fn from(value: YralTransferRequest) -> Self {
+        Self {
+            sender: value.sender,
+            recipient: value.recipient,
+            amount: value.amount,
+            nonce: value.nonce,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferStatus {
+    /// the sender is debited, the recipient's credit is retried by the alarm until it's
+    /// known, see `reconcile_transfers`
+    Pending,
+    Completed,
+    /// the recipient refused the credit and the sender was given the coins back
+    Failed,
+}
+
+/// the sender's record of a transfer, keyed by its nonce
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransferRecord {
+    pub req: TransferReq,
+    /// unix millis
+    pub created_at: u64,
+    pub status: TransferStatus,
+}
+
+/// `sats` SATS convert to `yral` YRAL
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SatsToYralRate {
+    pub sats: u64,
+    pub yral: u64,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YralConvertRequest {
+    pub sender: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_amount: BigUint,
+    /// unique per conversion, replays are rejected
+    pub nonce: String,
+    pub signature: Signature,
+}
+
+pub fn convert_msg(sats_amount: BigUint, nonce: String) -> Message {
+    Message::default()
+        .method_name("yral_coin_convert_sats".into())
+        .args((Nat::from(sats_amount), nonce))
+        .expect("convert request should serialize")
+}
+
+impl SignedMessage for YralConvertRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        convert_msg(self.sats_amount.clone(), self.nonce.clone())
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConvertReq {
+    pub user_principal: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_amount: BigUint,
+    pub nonce: String,
+}
+
+impl From<YralConvertRequest> for ConvertReq {
+    fn from(value: YralConvertRequest) -> Self {
+        Self {
+            user_principal: value.sender,
+            sats_amount: value.sats_amount,
+            nonce: value.nonce,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConvertRes {
+    #[serde_as(as = "DisplayFromStr")]
+    pub yral_credited: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub new_balance: BigUint,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConversionStatus {
+    Pending,
+    Committed,
+    Aborted,
+    Refunded,
+    RefundFailed,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConversionRecord {
+    pub id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_amount: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub yral_amount: BigUint,
+    pub rate: SatsToYralRate,
+    pub status: ConversionStatus,
+    pub created_at: u64,
+}
+
+/// signature over a method specific message, passed as query params for GET requests
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedQuery {
+    pub signature: String,
+    pub expires_at: u64,
+}
+
+pub fn balance_ws_msg(expires_at: u64) -> Message {
+    Message::default()
+        .method_name("yral_coin_balance_ws".into())
+        .args((expires_at,))
+        .expect("balance ws request should serialize")
+}
+
+/// per user replacements for the default daily limits
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LimitOverrides {
+    pub max_credited_per_day: Option<u64>,
+    pub max_deducted_per_day: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct UserLimits {
+    pub max_credited_per_day: u64,
+    pub max_deducted_per_day: u64,
+}
+
+/// headroom left in a limit window
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Allowance {
+    #[serde_as(as = "DisplayFromStr")]
+    pub remaining: BigUint,
+    /// unix millis
+    pub reset_at: u64,
+}
+
+impl From<(BigUint, u64)> for Allowance {
+    fn from((remaining, reset_at): (BigUint, u64)) -> Self {
+        Self {
+            remaining,
+            reset_at,
+        }
+    }
+}
+
+/// the user's limits with what is left of each
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LimitsStatus {
+    #[serde(flatten)]
+    pub limits: UserLimits,
+    pub credit: Allowance,
+    pub deduct: Allowance,
+    pub withdrawal: Allowance,
+}
+
+impl From<DailyLimits> for UserLimits {
+    fn from(value: DailyLimits) -> Self {
+        Self {
+            max_credited_per_day: value.max_credited_per_day,
+            max_deducted_per_day: value.max_deducted_per_day,
+        }
+    }
+}
+
+impl From<UserLimits> for DailyLimits {
+    fn from(value: UserLimits) -> Self {
+        Self {
+            max_credited_per_day: value.max_credited_per_day,
+            max_deducted_per_day: value.max_deducted_per_day,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct DailyBonusState {
+    /// days since unix epoch (UTC)
+    pub last_claim_day: Option<u64>,
+    pub streak: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DailyBonusClaimRequest {
+    pub sender: Principal,
+    /// the UTC day claimed, in days since unix epoch
+    /// signed, so a signature can't be replayed on a later day
+    pub day: u64,
+    pub signature: Signature,
+}
+
+pub fn daily_bonus_msg(day: u64) -> Message {
+    Message::default()
+        .method_name("yral_coin_claim_daily_bonus".into())
+        .args((day,))
+        .expect("daily bonus request should serialize")
+}
+
+impl SignedMessage for DailyBonusClaimRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        daily_bonus_msg(self.day)
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DailyBonusRes {
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    pub streak: u64,
+    /// unix timestamp in millis
+    pub next_claim_at: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub new_balance: BigUint,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HoldReq {
+    /// chosen by the caller, used to capture or release the hold
+    pub hold_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    /// defaults to `DEFAULT_HOLD_TTL_MS`
+    #[serde(default)]
+    pub ttl_ms: Option<u64>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hold {
+    pub hold_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    /// unix timestamp in millis, the hold is released automatically after this
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CaptureReq {
+    pub hold_id: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReleaseReq {
+    pub hold_id: String,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PromoGrant {
+    /// remaining, unspent amount
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    /// unix timestamp in millis
+    pub expires_at: u64,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BalanceSnapshot {
+    #[serde_as(as = "DisplayFromStr")]
+    pub balance: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub credited_today: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub deducted_today: BigUint,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CatalogItem {
+    pub item_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub price: BigUint,
+    #[serde(default = "default_true")]
+    pub available: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YralRedeemRequest {
+    pub sender: Principal,
+    pub item_id: String,
+    /// unique per redemption, replays are rejected
+    pub nonce: String,
+    pub signature: Signature,
+}
+
+pub fn redeem_msg(item_id: String, nonce: String) -> Message {
+    Message::default()
+        .method_name("yral_coin_redeem".into())
+        .args((item_id, nonce))
+        .expect("redeem request should serialize")
+}
+
+impl SignedMessage for YralRedeemRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        redeem_msg(self.item_id.clone(), self.nonce.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RedeemReq {
+    pub user_principal: Principal,
+    pub item_id: String,
+    pub nonce: String,
+}
+
+impl From<YralRedeemRequest> for RedeemReq {
+    fn from(value: YralRedeemRequest) -> Self {
+        Self {
+            user_principal: value.sender,
+            item_id: value.item_id,
+            nonce: value.nonce,
+        }
+    }
+}
+
+/// also the fulfillment event sent to the `REDEMPTION_FULFILLMENT` queue
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RedemptionReceipt {
+    pub receipt_id: String,
+    pub user_principal: Principal,
+    pub item_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub price: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub new_balance: BigUint,
+    pub created_at: u64,
+}
+
+/// body of `POST /admin/adjust/:user_principal`
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AdminAdjustRequest {
+    /// unique per correction
+    pub adjustment_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    pub reason: String,
+}
+
+impl AdminAdjustRequest {
+    /// what the co-signer's token must approve, `adjustment:{user_principal}:{adjustment_id}:{delta}`
+    pub fn approval(&self, user_principal: &str) -> String {
+        format!(
+            "adjustment:{user_principal}:{}:{}",
+            self.adjustment_id, self.delta
+        )
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AdminAdjustReq {
+    pub adjustment_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    pub reason: String,
+    /// JWT subjects of the admins that signed off
+    pub approvers: Vec<String>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AdminAuditEntry {
+    pub adjustment_id: String,
+    pub approvers: Vec<String>,
+    pub reason: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    #[serde_as(as = "DisplayFromStr")]
+    pub balance_before: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub balance_after: BigUint,
+    pub timestamp: u64,
+    /// set if applying the adjustment failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum RateLimitScope {
+    /// the calling service, identified by its JWT subject
+    Caller,
+    /// the user whose balance is being updated
+    Target,
+}
+
+/// body of a 429 from `/update_balance`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpdateRateLimited {
+    pub scope: RateLimitScope,
+    pub max_requests: u32,
+    pub window_ms: u64,
+    pub retry_after_ms: u64,
+}
+
+pub fn data_export_msg(expires_at: u64) -> Message {
+    Message::default()
+        .method_name("yral_coin_data_export".into())
+        .args((expires_at,))
+        .expect("data export request should serialize")
+}
+
+/// everything the coin worker stores about a user
+#[derive(Serialize, Deserialize)]
+pub struct CoinDataExport {
+    pub exported_at: u64,
+    pub balance: YralBalanceInfo,
+    pub limits: UserLimits,
+    pub limit_overrides: LimitOverrides,
+    pub breakdown: BalanceBreakdown,
+    pub daily_bonus: DailyBonusState,
+    pub promo_grants: Vec<PromoGrant>,
+    pub holds: Vec<Hold>,
+    pub ledger: Vec<LedgerEntry>,
+    pub conversions: Vec<ConversionRecord>,
+    pub redemptions: Vec<RedemptionReceipt>,
+    pub admin_audit: Vec<AdminAuditEntry>,
+    pub withdrawals: Vec<WithdrawalReceipt>,
+    #[serde(default)]
+    pub cashbacks: Vec<CashbackRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForgetRes {
+    pub archive_key: String,
+}
+
+/// a downstream service notified of balance changes, see `webhook`
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    /// changes with a smaller magnitude are not delivered
+    #[serde_as(as = "DisplayFromStr")]
+    pub min_delta: BigUint,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BalanceChangeEvent {
+    pub user_principal: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    #[serde_as(as = "DisplayFromStr")]
+    pub new_balance: BigUint,
+    pub reason_code: Option<BalanceUpdateReason>,
+    pub timestamp: u64,
+}
+
+/// message on the `BALANCE_WEBHOOKS` queue, one per (subscription, event)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    pub webhook_id: String,
+    pub url: String,
+    pub event: BalanceChangeEvent,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YralWithdrawRequest {
+    pub sender: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    /// unique per withdrawal, replays are rejected
+    pub nonce: String,
+    pub signature: Signature,
+}
+
+pub fn withdraw_msg(amount: BigUint, nonce: String) -> Message {
+    Message::default()
+        .method_name("yral_coin_withdraw".into())
+        .args((Nat::from(amount), nonce))
+        .expect("withdraw request should serialize")
+}
+
+impl SignedMessage for YralWithdrawRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        withdraw_msg(self.amount.clone(), self.nonce.clone())
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WithdrawReq {
+    pub recipient: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    pub nonce: String,
+}
+
+impl From<YralWithdrawRequest> for WithdrawReq {
+    fn from(value: YralWithdrawRequest) -> Self {
+        Self {
+            recipient: value.sender,
+            amount: value.amount,
+            nonce: value.nonce,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WithdrawalStatus {
+    /// recorded before the ledger transfer, resolved by the alarm if the
+    /// transfer's outcome isn't known, see `reconcile_withdrawals`
+    Pending,
+    #[default]
+    Completed,
+    /// the ledger refused the transfer and the coins were given back
+    Failed,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WithdrawalReceipt {
+    pub withdrawal_id: String,
+    pub recipient: Principal,
+    /// whole coins, see `YRAL_E8S_PER_COIN` for the on-chain amount
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub new_balance: BigUint,
+    /// also the transfer's `created_at_time`, so resends are deduped by the ledger
+    pub created_at: u64,
+    #[serde(default)]
+    pub status: WithdrawalStatus,
+}
+
+/// a balance update authorized by the user's own signature instead of a backend JWT
+/// only debits are allowed, see `signed_update_msg`
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YralSignedBalanceUpdateRequest {
+    pub sender: Principal,
+    #[serde_as(as = "DisplayFromStr")]
+    pub previous_balance: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    pub reason: BalanceUpdateReason,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// unique per update, used as the idempotency key
+    pub nonce: String,
+    /// unix millis, bounds how long the signature can be replayed
+    pub expires_at: u64,
+    pub signature: Signature,
+}
+
+pub fn signed_update_msg(req: &YralSignedBalanceUpdateRequest) -> Message {
+    Message::default()
+        .method_name("yral_coin_update_balance".into())
+        .args((
+            Nat::from(req.previous_balance.clone()),
+            req.delta.to_string(),
+            format!("{:?}", req.reason),
+            req.description.clone(),
+            req.nonce.clone(),
+            req.expires_at,
+        ))
+        .expect("signed balance update should serialize")
+}
+
+impl SignedMessage for YralSignedBalanceUpdateRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        signed_update_msg(self)
+    }
+}
+
+impl From<YralSignedBalanceUpdateRequest> for YralBalanceUpdateRequest {
+    fn from(value: YralSignedBalanceUpdateRequest) -> Self {
+        Self {
+            previous_balance: value.previous_balance,
+            delta: value.delta,
+            reason: value.reason,
+            description: value.description,
+            metadata: BTreeMap::from([("authorized_by".into(), "user_signature".into())]),
+            // namespaced so user nonces can't collide with backend keys
+            idempotency_key: Some(format!("signed-{}", value.nonce)),
+            is_airdropped: false,
+            promo_expires_at: None,
+            campaign: false,
+        }
+    }
+}
+
+fn default_floor_exempt_reasons() -> Vec<BalanceUpdateReason> {
+    vec![BalanceUpdateReason::AdminAdjust]
+}
+
+/// deductions can't take the balance below `floor`, unless their reason is exempt
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MinBalancePolicy {
+    #[serde_as(as = "DisplayFromStr")]
+    pub floor: BigUint,
+    #[serde(default = "default_floor_exempt_reasons")]
+    pub exempt_reasons: Vec<BalanceUpdateReason>,
+}
+
+impl MinBalancePolicy {
+    pub fn new(floor: BigUint) -> Self {
+        Self {
+            floor,
+            exempt_reasons: default_floor_exempt_reasons(),
+        }
+    }
+
+    pub fn applies_to(&self, reason: Option<BalanceUpdateReason>) -> bool {
+        self.floor > BigUint::ZERO
+            && reason.is_none_or(|reason| !self.exempt_reasons.contains(&reason))
+    }
+}
+
+/// a DO ledger entry streamed to the global ledger, see `global_ledger`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GlobalLedgerMessage {
+    /// 128 random bits, hex encoded, picked once per entry so redeliveries are ignored
+    /// missing on messages queued before it was added
+    #[serde(default)]
+    pub id: Option<String>,
+    pub user_principal: Principal,
+    /// sequence in the user's DO ledger
+    pub seq: u64,
+    pub entry: LedgerEntry,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GlobalLedgerCursor {
+    pub timestamp: u64,
+    pub id: String,
+}
+
+/// filters for the global ledger, all optional
+/// `from` is inclusive, `to` is exclusive (ms since epoch)
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GlobalLedgerQuery {
+    #[serde(default)]
+    pub user_principal: Option<Principal>,
+    #[serde(default)]
+    pub reason_code: Option<BalanceUpdateReason>,
+    #[serde(default)]
+    pub from: Option<u64>,
+    #[serde(default)]
+    pub to: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub cursor: Option<GlobalLedgerCursor>,
+}
+
+/// a row of the `coin_transactions` table
+/// amounts are kept as strings, same as the rest of the API
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GlobalLedgerRow {
+    pub id: String,
+    pub user_principal: String,
+    pub seq: u64,
+    pub delta: String,
+    pub resulting_balance: String,
+    pub reason_code: Option<String>,
+    pub reason: Option<String>,
+    pub idempotency_key: Option<String>,
+    /// JSON encoded `LedgerEntry::metadata`
+    pub metadata: String,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GlobalLedgerQueryRes {
+    pub transactions: Vec<GlobalLedgerRow>,
+    pub next: Option<GlobalLedgerCursor>,
+}
+
+/// KV (`YRAL_COIN_CONFIG`) definition of a SATS spend cashback campaign
+/// cashback is `cashback_bps` of the spent SATS, paid out 1:1 in YRAL
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CashbackCampaign {
+    pub id: String,
+    /// basis points, 100 = 1%
+    pub cashback_bps: u32,
+    #[serde(default)]
+    pub min_spend_sats: u64,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub max_cashback_yral: Option<BigUint>,
+    #[serde(default)]
+    pub starts_at: Option<u64>,
+    #[serde(default)]
+    pub ends_at: Option<u64>,
+}
+
+impl CashbackCampaign {
+    pub fn is_live(&self, now: u64) -> bool {
+        self.starts_at.is_none_or(|starts_at| starts_at <= now)
+            && self.ends_at.is_none_or(|ends_at| now < ends_at)
+    }
+
+    pub fn cashback_for(&self, sats_amount: &BigUint) -> BigUint {
+        let cashback = sats_amount * self.cashback_bps / 10_000u32;
+        match self.max_cashback_yral.as_ref() {
+            Some(max) if &cashback > max => max.clone(),
+            _ => cashback,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YralSpendWithCashbackRequest {
+    pub sender: Principal,
+    pub campaign_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_amount: BigUint,
+    /// unique per spend, replays are rejected
+    pub nonce: String,
+    pub signature: Signature,
+}
+
+pub fn spend_with_cashback_msg(
+    campaign_id: String,
+    sats_amount: BigUint,
+    nonce: String,
+) -> Message {
+    Message::default()
+        .method_name("yral_coin_spend_with_cashback".into())
+        .args((campaign_id, Nat::from(sats_amount), nonce))
+        .expect("spend with cashback request should serialize")
+}
+
+impl SignedMessage for YralSpendWithCashbackRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> Message {
+        spend_with_cashback_msg(
+            self.campaign_id.clone(),
+            self.sats_amount.clone(),
+            self.nonce.clone(),
+        )
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpendWithCashbackReq {
+    pub user_principal: Principal,
+    pub campaign_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_amount: BigUint,
+    pub nonce: String,
+}
+
+impl From<YralSpendWithCashbackRequest> for SpendWithCashbackReq {
+    fn from(value: YralSpendWithCashbackRequest) -> Self {
+        Self {
+            user_principal: value.sender,
+            campaign_id: value.campaign_id,
+            sats_amount: value.sats_amount,
+            nonce: value.nonce,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpendWithCashbackRes {
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_spent: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub yral_cashback: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub new_balance: BigUint,
+}
+
+/// saga state of a cashback spend, see `spend_with_cashback`
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CashbackRecord {
+    pub id: String,
+    pub campaign_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub sats_spent: BigUint,
+    #[serde_as(as = "DisplayFromStr")]
+    pub yral_cashback: BigUint,
+    pub status: ConversionStatus,
+    pub created_at: u64,
 }
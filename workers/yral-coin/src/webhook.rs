@@ -0,0 +1,138 @@
+use hmac::{Hmac, Mac};
+use num_bigint::BigInt;
+use sha2::Sha256;
+use worker::*;
+use worker_utils::RequestInitBuilder;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{WEBHOOK_CACHE_TTL_MS, WEBHOOK_PREFIX, WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMEOUT},
+    types::{BalanceChangeEvent, LedgerEntry, WebhookDelivery, WebhookSubscription},
+};
+
+fn webhook_key(id: &str) -> String {
+    format!("{WEBHOOK_PREFIX}{id}")
+}
+
+pub async fn list_webhooks(env: &Env) -> Result<Vec<WebhookSubscription>> {
+    let kv = env.kv("YRAL_COIN_CONFIG")?;
+    let page = kv.list().prefix(WEBHOOK_PREFIX.into()).execute().await?;
+
+    let mut webhooks = Vec::with_capacity(page.keys.len());
+    for key in page.keys {
+        if let Some(webhook) = kv.get(&key.name).json().await? {
+            webhooks.push(webhook);
+        }
+    }
+
+    Ok(webhooks)
+}
+
+pub async fn register_webhook(env: &Env, webhook: &WebhookSubscription) -> Result<()> {
+    env.kv("YRAL_COIN_CONFIG")?
+        .put(&webhook_key(&webhook.id), serde_json::to_string(webhook)?)?
+        .execute()
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_webhook(env: &Env, id: &str) -> Result<()> {
+    env.kv("YRAL_COIN_CONFIG")?
+        .delete(&webhook_key(id))
+        .await
+        .map_err(|e| Error::RustError(e.to_string()))
+}
+
+/// `time=<millis>,sig1=<hex hmac-sha256 of "{time}.{body}">`
+fn signature_header(secret: &str, time: u64, body: &str) -> Result<String> {
+    type HmacSha256 = Hmac<Sha256>;
+
+    let mut hmac = HmacSha256::new_from_slice(secret.as_bytes())
+        .map_err(|e| Error::RustError(e.to_string()))?;
+    hmac.update(format!("{time}.{body}").as_bytes());
+    let digest = hex::encode(hmac.finalize().into_bytes());
+
+    Ok(format!("time={time},sig1={digest}"))
+}
+
+/// POSTs the event to the subscriber, non 2xx responses are errors
+pub async fn deliver_webhook(env: &Env, delivery: &WebhookDelivery) -> Result<()> {
+    let secret = env.secret("WEBHOOK_SIGNING_SECRET")?.to_string();
+    let body = serde_json::to_string(&delivery.event)?;
+    let signature = signature_header(&secret, Date::now().as_millis(), &body)?;
+
+    // `json` serializes the event exactly as signed above
+    let res = RequestInitBuilder::default()
+        .method(Method::Post)
+        .header(WEBHOOK_SIGNATURE_HEADER, &signature)?
+        .json(&delivery.event)?
+        .timeout(WEBHOOK_TIMEOUT)
+        .fetch(&delivery.url)
+        .await?;
+    if !(200..300).contains(&res.status_code()) {
+        return Err(Error::RustError(format!(
+            "webhook {} responded with {}",
+            delivery.webhook_id,
+            res.status_code()
+        )));
+    }
+
+    Ok(())
+}
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    async fn cached_webhooks(&self) -> Result<Vec<WebhookSubscription>> {
+        let now = Date::now().as_millis();
+        if let Some((fetched_at, webhooks)) = self.webhooks.borrow().as_ref() {
+            if now - fetched_at < WEBHOOK_CACHE_TTL_MS {
+                return Ok(webhooks.clone());
+            }
+        }
+
+        let webhooks = list_webhooks(&self.env).await?;
+        *self.webhooks.borrow_mut() = Some((now, webhooks.clone()));
+
+        Ok(webhooks)
+    }
+
+    /// queues a delivery to every subscription whose threshold `entry` crosses
+    pub(crate) async fn queue_balance_webhooks(&self, entry: &LedgerEntry) -> Result<()> {
+        let storage = self.storage();
+        let Some(user_principal) = ({ *self.owner.borrow_mut().read(&storage).await? }) else {
+            console_warn!("skipping balance webhooks, DO owner unknown");
+            return Ok(());
+        };
+
+        let magnitude = entry.delta.magnitude();
+        let deliveries: Vec<_> = self
+            .cached_webhooks()
+            .await?
+            .into_iter()
+            .filter(|webhook| entry.delta != BigInt::ZERO && magnitude >= &webhook.min_delta)
+            .map(|webhook| WebhookDelivery {
+                webhook_id: webhook.id,
+                url: webhook.url,
+                event: BalanceChangeEvent {
+                    user_principal,
+                    delta: entry.delta.clone(),
+                    new_balance: entry.resulting_balance.clone(),
+                    reason_code: entry.reason_code,
+                    timestamp: entry.timestamp,
+                },
+            })
+            .collect();
+        if deliveries.is_empty() {
+            return Ok(());
+        }
+
+        let queue = self.env.queue("BALANCE_WEBHOOKS")?;
+        for delivery in deliveries {
+            queue.send(&delivery).await?;
+        }
+
+        Ok(())
+    }
+}
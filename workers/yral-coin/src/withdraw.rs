@@ -0,0 +1,243 @@
+use candid::Nat;
+use num_bigint::{BigInt, BigUint};
+use std::result::Result as StdResult;
+use worker::*;
+
+use crate::{
+    coin::UserYralCoinState,
+    consts::{WITHDRAWAL_DEDUPE_WINDOW_MS, WITHDRAWAL_RECONCILE_DELAY_MS, YRAL_E8S_PER_COIN},
+    error::WorkerError,
+    treasury::{TreasuryError, YralTreasury},
+    types::{BalanceUpdateReason, LedgerReason, WithdrawReq, WithdrawalReceipt, WithdrawalStatus},
+};
+
+pub(crate) const WITHDRAWAL_PREFIX: &str = "withdrawal-";
+
+fn to_internal(e: worker::Error) -> (u16, WorkerError) {
+    (500, WorkerError::Internal(e.to_string()))
+}
+
+fn withdrawal_key(nonce: &str) -> String {
+    format!("{WITHDRAWAL_PREFIX}{nonce}")
+}
+
+// SAFETY: See comment on balance_info for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserYralCoinState {
+    /// moves `amount` off-chain YRAL to the user's principal on the ledger
+    /// the receipt stays `Pending` if the transfer's outcome is unknown, the alarm
+    /// resends it later and the ledger dedupes it on `created_at_time`
+    pub async fn withdraw(
+        &self,
+        req: WithdrawReq,
+    ) -> StdResult<WithdrawalReceipt, (u16, WorkerError)> {
+        // other requests run while this one awaits the ledger, the nonce is
+        // claimed here so they can't pay out the same withdrawal twice
+        if !self
+            .withdrawals_in_flight
+            .borrow_mut()
+            .insert(req.nonce.clone())
+        {
+            return Err((409, WorkerError::DuplicateWithdrawal));
+        }
+        let nonce = req.nonce.clone();
+        let res = self.withdraw_inner(req).await;
+        self.withdrawals_in_flight.borrow_mut().remove(&nonce);
+
+        res
+    }
+
+    async fn withdraw_inner(
+        &self,
+        req: WithdrawReq,
+    ) -> StdResult<WithdrawalReceipt, (u16, WorkerError)> {
+        if req.amount == BigUint::ZERO {
+            return Err((400, WorkerError::InvalidWithdrawal));
+        }
+        if self.treasury.is_none() {
+            return Err((503, WorkerError::TreasuryUnavailable));
+        }
+
+        let mut storage = self.storage();
+        let key = withdrawal_key(&req.nonce);
+        if storage
+            .get::<WithdrawalReceipt>(&key)
+            .await
+            .map_err(to_internal)?
+            .is_some()
+            || self.is_forgotten(&key).await.map_err(to_internal)?
+        {
+            return Err((409, WorkerError::DuplicateWithdrawal));
+        }
+
+        let res = {
+            self.treasury_amount
+                .borrow_mut()
+                .try_consume(&mut storage, req.amount.clone())
+                .await
+        };
+        res.map_err(|_| (400, WorkerError::WithdrawalLimitReached))?;
+
+        let debit = self
+            .debit_withdrawable(
+                req.amount.clone(),
+                LedgerReason::new(BalanceUpdateReason::Withdrawal, "withdrawal to ledger"),
+                Some(key.clone()),
+            )
+            .await;
+        let new_balance = match debit {
+            Ok(new_balance) => new_balance,
+            Err(e) => {
+                self.rollback_treasury_limit(&req.amount).await;
+                return Err(e);
+            }
+        };
+
+        let mut receipt = WithdrawalReceipt {
+            withdrawal_id: req.nonce,
+            recipient: req.recipient,
+            amount: req.amount,
+            new_balance,
+            created_at: Date::now().as_millis(),
+            status: WithdrawalStatus::Pending,
+        };
+        // recorded before the transfer, so a crash or an unknown outcome is reconciled
+        storage.put(&key, &receipt).await.map_err(to_internal)?;
+        self.schedule_alarm(receipt.created_at + WITHDRAWAL_RECONCILE_DELAY_MS)
+            .await
+            .map_err(to_internal)?;
+
+        let transfer = self.send_withdrawal(&receipt).await;
+        self.settle_withdrawal(&mut receipt, transfer).await?;
+
+        Ok(receipt)
+    }
+
+    async fn send_withdrawal(&self, receipt: &WithdrawalReceipt) -> StdResult<(), TreasuryError> {
+        let Some(treasury) = &self.treasury else {
+            return Err(TreasuryError::Ambiguous("treasury unavailable".into()));
+        };
+
+        treasury
+            .transfer_yral(
+                receipt.recipient,
+                Nat::from(receipt.amount.clone() * YRAL_E8S_PER_COIN),
+                Some(format!("yral withdrawal {}", receipt.withdrawal_id)),
+                receipt.created_at * 1_000_000,
+            )
+            .await
+    }
+
+    /// records the transfer's outcome on the receipt
+    /// coins are only given back once the ledger has refused the transfer
+    async fn settle_withdrawal(
+        &self,
+        receipt: &mut WithdrawalReceipt,
+        transfer: StdResult<(), TreasuryError>,
+    ) -> StdResult<(), (u16, WorkerError)> {
+        let res = match transfer {
+            Ok(()) => {
+                receipt.status = WithdrawalStatus::Completed;
+                Ok(())
+            }
+            Err(TreasuryError::Ambiguous(e)) => {
+                console_warn!(
+                    "yral withdrawal {} outcome unknown, reconciling later: {e}",
+                    receipt.withdrawal_id
+                );
+                return Ok(());
+            }
+            Err(TreasuryError::Rejected(e)) => {
+                console_error!("yral withdrawal {} failed: {}", receipt.withdrawal_id, e.1);
+                self.rollback_treasury_limit(&receipt.amount).await;
+                self.revert_delta(
+                    -BigInt::from(receipt.amount.clone()),
+                    LedgerReason::new(
+                        BalanceUpdateReason::Refund,
+                        format!("withdrawal {} failed", receipt.withdrawal_id),
+                    ),
+                )
+                .await?;
+                receipt.status = WithdrawalStatus::Failed;
+                Err(e)
+            }
+        };
+
+        let key = withdrawal_key(&receipt.withdrawal_id);
+        if let Err(e) = self.storage().put(&key, &*receipt).await {
+            console_error!("failed to store withdrawal receipt: {e}");
+        }
+
+        res
+    }
+
+    /// resends transfers of `Pending` receipts left by a crash or an unknown outcome
+    /// past the ledger's dedupe window a resend could pay twice, those are left for review
+    pub(crate) async fn reconcile_withdrawals(&self) -> Result<()> {
+        let now = Date::now().as_millis();
+        let pending = self
+            .withdrawals()
+            .await?
+            .into_iter()
+            .filter(|receipt| receipt.status == WithdrawalStatus::Pending);
+
+        let mut next_attempt = None::<u64>;
+        for mut receipt in pending {
+            if self
+                .withdrawals_in_flight
+                .borrow()
+                .contains(&receipt.withdrawal_id)
+                || receipt.created_at + WITHDRAWAL_RECONCILE_DELAY_MS > now
+            {
+                next_attempt = Some(now + WITHDRAWAL_RECONCILE_DELAY_MS);
+                continue;
+            }
+            if receipt.created_at + WITHDRAWAL_DEDUPE_WINDOW_MS <= now {
+                console_error!(
+                    "yral withdrawal {} is past the dedupe window, needs manual reconciliation",
+                    receipt.withdrawal_id
+                );
+                continue;
+            }
+
+            let transfer = self.send_withdrawal(&receipt).await;
+            if let Err((_, e)) = self.settle_withdrawal(&mut receipt, transfer).await {
+                console_error!("failed to settle withdrawal {}: {e}", receipt.withdrawal_id);
+            }
+            if receipt.status == WithdrawalStatus::Pending {
+                next_attempt = Some(now + WITHDRAWAL_RECONCILE_DELAY_MS);
+            }
+        }
+
+        if let Some(next_attempt) = next_attempt {
+            self.schedule_alarm(next_attempt).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn rollback_treasury_limit(&self, amount: &BigUint) {
+        let mut storage = self.storage();
+        let res = {
+            self.treasury_amount
+                .borrow_mut()
+                .rollback(&mut storage, amount.clone())
+                .await
+        };
+        if let Err(e) = res {
+            console_error!("failed to rollback treasury limit: {e}");
+        }
+    }
+
+    pub async fn withdrawals(&self) -> Result<Vec<WithdrawalReceipt>> {
+        let mut receipts = self
+            .storage()
+            .list_with_prefix(WITHDRAWAL_PREFIX)
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect::<Result<Vec<WithdrawalReceipt>>>()?;
+        receipts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(receipts)
+    }
+}
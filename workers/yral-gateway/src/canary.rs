@@ -0,0 +1,125 @@
+//! canary and shadow deployments of an upstream, both driven by feature flags
+//!
+//! a canary serves a slice of real traffic, picked per caller by its flag's rollout or forced
+//! with `CANARY_HEADER` while the flag is enabled. a shadow gets a copy of every mutating request
+//! while its flag is on, it runs against its own staging objects and its responses are only
+//! compared to the upstream's, never returned
+//!
+//! a shadow is deployed with its own KV namespaces, queues and a backend identity without admin
+//! rights, see .github/workflows/deploy-canary-worker.yml, so its copies can't reach production
+
+use worker::*;
+use worker_utils::{flags::Flag, metrics::Metrics};
+
+use crate::{
+    upstream_request,
+    upstreams::{Caller, Upstream},
+};
+
+/// `1` sends the request to the canary while its flag is enabled, `0` keeps it off the canary
+pub const CANARY_HEADER: &str = "X-Yral-Canary";
+/// set on shadowed copies, so they can be told apart in the shadow's logs
+pub const SHADOW_HEADER: &str = "X-Yral-Shadow";
+
+/// another deployment of an upstream, reached through its own service binding
+pub struct Deployment {
+    /// service binding in wrangler.toml
+    pub service: &'static str,
+    /// on for the callers whose traffic reaches the deployment
+    pub flag: Flag,
+}
+
+impl Deployment {
+    /// callers without a principal only reach it once the flag is fully rolled out
+    async fn on_for(&self, env: &Env, caller: &Caller) -> bool {
+        match caller.principal() {
+            Some(principal) => self.flag.enabled_for(env, principal).await,
+            None => self.flag.enabled(env).await,
+        }
+    }
+}
+
+/// service binding the request goes to, the canary's if it's picked
+pub async fn pick_service(
+    env: &Env,
+    upstream: &Upstream,
+    req: &Request,
+    caller: &Caller,
+) -> &'static str {
+    let Some(canary) = &upstream.canary else {
+        return upstream.service;
+    };
+    let forced = req.headers().get(CANARY_HEADER).ok().flatten();
+    let on = match forced.as_deref() {
+        Some("0") => false,
+        // disabling the flag stops forced requests too
+        Some("1") => canary.flag.config(env).await.is_some_and(|c| c.enabled),
+        _ => canary.on_for(env, caller).await,
+    };
+
+    if on {
+        canary.service
+    } else {
+        upstream.service
+    }
+}
+
+/// a copy of `req` for the upstream's shadow, `None` unless it's a mutating request to shadow
+///
+/// taken before the request is forwarded, the body is teed between both
+pub async fn shadow_copy(
+    env: &Env,
+    upstream: &Upstream,
+    req: &Request,
+    caller: &Caller,
+) -> Result<Option<Request>> {
+    let Some(shadow) = &upstream.shadow else {
+        return Ok(None);
+    };
+    if matches!(req.method(), Method::Get | Method::Head | Method::Options)
+        || !shadow.on_for(env, caller).await
+    {
+        return Ok(None);
+    }
+
+    req.clone().map(Some)
+}
+
+/// sends the copy once the upstream responded, only whether the statuses match is recorded
+pub fn send_shadow(
+    ctx: &Context,
+    env: &Env,
+    upstream: &'static Upstream,
+    copy: Request,
+    upstream_status: u16,
+) {
+    let Some(shadow) = &upstream.shadow else {
+        return;
+    };
+    let env = env.clone();
+    ctx.wait_until(async move {
+        let res = async {
+            let mut req = upstream_request(&copy, upstream, shadow.service)?;
+            req.headers_mut()?.set(SHADOW_HEADER, "1")?;
+            env.service(shadow.service)?.fetch_request(req).await
+        }
+        .await;
+        let outcome = match res {
+            Ok(res) if res.status_code() == upstream_status => "match",
+            Ok(res) => {
+                console_warn!(
+                    "shadow of {} responded {} where the upstream responded {upstream_status}",
+                    upstream.prefix,
+                    res.status_code()
+                );
+                "mismatch"
+            }
+            Err(e) => {
+                console_warn!("shadow of {} failed: {e}", upstream.prefix);
+                "failed"
+            }
+        };
+        Metrics::new(&env, "yral-gateway")
+            .counter("gateway_shadow_requests", &[upstream.prefix, outcome]);
+    });
+}
@@ -0,0 +1,158 @@
+mod canary;
+mod rate_limiter;
+mod upstreams;
+
+use canary::{pick_service, send_shadow, shadow_copy};
+use rate_limiter::CLIENT_RATE_LIMITER;
+use upstreams::{upstream, Upstream, UPSTREAMS};
+use worker::*;
+use worker_utils::{
+    api_error::{error_resp, ApiError},
+    cors::cors_for_env,
+    do_client::DoClient,
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    time::now_millis,
+    trace::with_trace,
+    RequestInitBuilder,
+};
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-gateway",
+    &[
+        Dependency::DurableObject(CLIENT_RATE_LIMITER),
+        Dependency::Service("YRAL_HOT_OR_NOT"),
+        Dependency::Service("YRAL_PUMP_N_DUMP"),
+        Dependency::Service("YRAL_COIN"),
+        Dependency::Service("YRAL_WALLET"),
+        Dependency::Service("YRAL_NOTIFICATIONS"),
+        Dependency::Service("YRAL_UPLOAD_VIDEO"),
+        Dependency::Service("YRAL_ACTIVITY"),
+    ],
+);
+
+/// `Some(429)` if the client used up its requests for this window
+async fn acquire_client_limit(env: &Env, key: &str) -> Result<Option<Response>> {
+    let res = DoClient::new(env)
+        .fetch(
+            CLIENT_RATE_LIMITER,
+            key,
+            "acquire",
+            RequestInitBuilder::default().method(Method::Post),
+        )
+        .await?;
+    if res.status_code() == 200 {
+        return Ok(None);
+    }
+
+    Ok(Some(res))
+}
+
+/// the same request for `service` with the upstream's prefix stripped, body and headers untouched
+fn upstream_request(req: &Request, upstream: &Upstream, service: &str) -> Result<Request> {
+    let url = req.url()?;
+    let path = url
+        .path()
+        .strip_prefix(&format!("/{}", upstream.prefix))
+        .unwrap_or("/");
+    let mut target = format!("https://{service}{path}");
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+
+    let mut init = RequestInit::new();
+    init.with_method(req.method())
+        .with_headers(req.headers().clone())
+        .with_body(req.inner().body().map(Into::into));
+
+    Request::new_with_init(&target, &init)
+}
+
+/// `/:upstream/*rest`, authenticated and rate limited once, then forwarded as is
+async fn proxy(req: Request, ctx: RouteContext<&Context>) -> Result<Response> {
+    let Some(upstream) = ctx.param("upstream").map(String::as_str).and_then(upstream) else {
+        let prefixes: Vec<_> = UPSTREAMS.iter().map(|u| u.prefix).collect();
+        return ApiError::new("UnknownUpstream", "no upstream serves this path")
+            .with_details(serde_json::json!({ "upstreams": prefixes }))
+            .into_response(404);
+    };
+    let caller = match upstream.authenticate(&req, &ctx.env).await {
+        Ok(caller) => caller,
+        Err((msg, code)) => return error_resp(msg, code),
+    };
+    let metrics = Metrics::new(&ctx.env, "yral-gateway");
+
+    if let Some(key) = caller.limiter_key() {
+        if let Some(res) = acquire_client_limit(&ctx.env, &key).await? {
+            metrics.counter("gateway_rate_limited", &[upstream.prefix, caller.kind()]);
+            return Ok(res);
+        }
+    }
+
+    let service = pick_service(&ctx.env, upstream, &req, &caller).await;
+    let deployment = if service == upstream.service {
+        "stable"
+    } else {
+        "canary"
+    };
+    let shadow = shadow_copy(&ctx.env, upstream, &req, &caller).await?;
+
+    let started_at = now_millis();
+    let res = ctx
+        .env
+        .service(service)?
+        .fetch_request(upstream_request(&req, upstream, service)?)
+        .await?;
+    let status = res.status_code().to_string();
+    metrics.counter("gateway_requests", &[upstream.prefix, &status, deployment]);
+    metrics.histogram(
+        "gateway_upstream_latency_ms",
+        &[upstream.prefix, deployment],
+        now_millis().saturating_sub(started_at) as f64,
+    );
+    if let Some(copy) = shadow {
+        send_shadow(ctx.data, &ctx.env, upstream, copy, res.status_code());
+    }
+
+    Ok(res)
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let (req, trace) = with_trace(req)?;
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-gateway", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::with_data(&ctx)
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/:upstream/*rest", proxy)
+        .post_async("/:upstream/*rest", proxy)
+        .put_async("/:upstream/*rest", proxy)
+        .patch_async("/:upstream/*rest", proxy)
+        .delete_async("/:upstream/*rest", proxy)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    // websocket upgrades are handed back untouched
+    if res.status_code() == 101 {
+        return Ok(res);
+    }
+    cors.apply(&path, origin.as_deref(), trace.tag(res)?)
+}
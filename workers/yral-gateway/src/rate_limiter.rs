@@ -0,0 +1,80 @@
+use std::cell::RefCell;
+
+use serde_json::json;
+use worker::*;
+use worker_utils::{
+    api_error::ApiError,
+    storage::{rate_limit::RateLimit, SafeStorage},
+};
+
+pub const CLIENT_RATE_LIMITER: &str = "CLIENT_RATE_LIMITER";
+
+const DEFAULT_MAX_REQUESTS_PER_MINUTE: u32 = 600;
+const WINDOW_MS: u64 = 60 * 1000;
+
+/// 429 with the exhausted limit in the error details and a `Retry-After` header (in seconds)
+fn rate_limited_response(limit: &RateLimit, retry_after_ms: u64) -> Result<Response> {
+    let res = ApiError::from_status(429, "rate limited")
+        .with_details(json!({
+            "max_requests": limit.max_requests(),
+            "window_ms": limit.window_ms(),
+            "retry_after_ms": retry_after_ms,
+        }))
+        .into_response(429)?;
+    res.headers().set(
+        "Retry-After",
+        &retry_after_ms.div_ceil(1000).max(1).to_string(),
+    )?;
+
+    Ok(res)
+}
+
+/// global limit of one client across every upstream
+/// one instance per JWT subject or IP
+#[durable_object]
+pub struct ClientRateLimiter {
+    state: State,
+    env: Env,
+    limit: RefCell<RateLimit>,
+}
+
+impl DurableObject for ClientRateLimiter {
+    fn new(state: State, env: Env) -> Self {
+        console_error_panic_hook::set_once();
+
+        let max_requests = env
+            .var("GATEWAY_MAX_REQUESTS_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.to_string().parse().ok())
+            .unwrap_or(DEFAULT_MAX_REQUESTS_PER_MINUTE);
+        Self {
+            state,
+            env,
+            limit: RefCell::new(RateLimit::new("client-rate-limit", max_requests, WINDOW_MS)),
+        }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let env = self.env.clone();
+        let router = Router::with_data(self);
+
+        router
+            .post_async("/acquire", {
+                // SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+                // because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+                #[allow(clippy::await_holding_refcell_ref)]
+                async |_, ctx| {
+                    let this = ctx.data;
+                    let mut storage: SafeStorage = this.state.storage().into();
+                    let mut limit = this.limit.borrow_mut();
+                    if let Err(retry_after_ms) = limit.try_acquire(&mut storage).await? {
+                        return rate_limited_response(&limit, retry_after_ms);
+                    }
+
+                    Response::ok("ok")
+                }
+            })
+            .run(req, env)
+            .await
+    }
+}
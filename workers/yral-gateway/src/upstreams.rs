@@ -0,0 +1,186 @@
+use candid::Principal;
+use serde::Deserialize;
+use worker::{Env, Request};
+use worker_utils::{
+    flags::Flag,
+    jwt::{JwtKeys, JwtPolicy, EXP_ROLLOUT_GRACE_UNTIL},
+};
+
+use crate::canary::Deployment;
+
+const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
+-----END PUBLIC KEY-----";
+
+/// pump-n-dump's tokens are signed with a key of their own
+const PUMP_N_DUMP_JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAV+DJfztWOovpmCUcZ5Fram2BLOt2B4LIlzw2vogIqK4=
+-----END PUBLIC KEY-----";
+
+/// a worker behind the gateway, reached at `/{prefix}/...` through a service binding
+pub struct Upstream {
+    /// first path segment, stripped before the request is forwarded
+    pub prefix: &'static str,
+    /// service binding in wrangler.toml
+    pub service: &'static str,
+    /// audiences the upstream issues tokens for, empty if it takes no JWTs
+    pub audiences: &'static [&'static str],
+    /// key the upstream signs with, only a fallback once `JWKS_URL` is set, see `JwtKeys`
+    pub public_key: &'static str,
+    /// the most lenient policy among the upstream's routes, the upstream applies its own on top
+    pub policy: JwtPolicy,
+    /// serves a slice of the upstream's traffic, see src/canary.rs
+    pub canary: Option<Deployment>,
+    /// gets a copy of the upstream's mutating requests, see src/canary.rs
+    pub shadow: Option<Deployment>,
+}
+
+pub const UPSTREAMS: &[Upstream] = &[
+    Upstream {
+        prefix: "hot-or-not",
+        service: "YRAL_HOT_OR_NOT",
+        audiences: &["hot-or-not-worker"],
+        public_key: JWT_PUBKEY,
+        policy: JwtPolicy::expiring(60).with_missing_exp_grace(EXP_ROLLOUT_GRACE_UNTIL),
+        canary: Some(Deployment {
+            service: "YRAL_HOT_OR_NOT_CANARY",
+            flag: Flag::new("gateway_canary_hot_or_not"),
+        }),
+        shadow: Some(Deployment {
+            service: "YRAL_HOT_OR_NOT_SHADOW",
+            flag: Flag::new("gateway_shadow_hot_or_not"),
+        }),
+    },
+    Upstream {
+        prefix: "pump-n-dump",
+        service: "YRAL_PUMP_N_DUMP",
+        audiences: &["pump-n-dump-worker"],
+        public_key: PUMP_N_DUMP_JWT_PUBKEY,
+        policy: JwtPolicy::expiring(5 * 60).with_missing_exp_grace(EXP_ROLLOUT_GRACE_UNTIL),
+        canary: Some(Deployment {
+            service: "YRAL_PUMP_N_DUMP_CANARY",
+            flag: Flag::new("gateway_canary_pump_n_dump"),
+        }),
+        shadow: Some(Deployment {
+            service: "YRAL_PUMP_N_DUMP_SHADOW",
+            flag: Flag::new("gateway_shadow_pump_n_dump"),
+        }),
+    },
+    Upstream {
+        prefix: "coin",
+        service: "YRAL_COIN",
+        audiences: &["yral-coin-worker", "yral-coin-admin"],
+        public_key: JWT_PUBKEY,
+        policy: JwtPolicy::expiring(60).with_missing_exp_grace(EXP_ROLLOUT_GRACE_UNTIL),
+        canary: None,
+        shadow: None,
+    },
+    Upstream {
+        prefix: "wallet",
+        service: "YRAL_WALLET",
+        audiences: &[],
+        public_key: JWT_PUBKEY,
+        policy: JwtPolicy::expiring(60),
+        canary: None,
+        shadow: None,
+    },
+    Upstream {
+        prefix: "notifications",
+        service: "YRAL_NOTIFICATIONS",
+        audiences: &["yral-notifications-worker"],
+        public_key: JWT_PUBKEY,
+        policy: JwtPolicy::expiring(60),
+        canary: None,
+        shadow: None,
+    },
+    Upstream {
+        prefix: "upload",
+        service: "YRAL_UPLOAD_VIDEO",
+        audiences: &[],
+        public_key: JWT_PUBKEY,
+        policy: JwtPolicy::expiring(60),
+        canary: None,
+        shadow: None,
+    },
+    Upstream {
+        prefix: "activity",
+        service: "YRAL_ACTIVITY",
+        audiences: &["yral-activity-worker"],
+        public_key: JWT_PUBKEY,
+        policy: JwtPolicy::expiring(60),
+        canary: None,
+        shadow: None,
+    },
+];
+
+pub fn upstream(prefix: &str) -> Option<&'static Upstream> {
+    UPSTREAMS.iter().find(|u| u.prefix == prefix)
+}
+
+#[derive(Deserialize)]
+struct GatewayClaims {
+    sub: Option<String>,
+}
+
+/// how the caller authenticated, decides which rate limit bucket it draws from
+pub enum Caller {
+    /// a verified token, keyed by its subject
+    Token(String),
+    /// a verified token without a subject, backend services share these so they aren't limited
+    Service,
+    /// no token, keyed by the client's IP
+    Anonymous(String),
+}
+
+impl Caller {
+    /// `None` if the caller isn't rate limited
+    pub fn limiter_key(&self) -> Option<String> {
+        match self {
+            Self::Token(sub) => Some(format!("token:{sub}")),
+            Self::Service => None,
+            Self::Anonymous(ip) => Some(format!("ip:{ip}")),
+        }
+    }
+
+    /// the token's subject if it's a principal, canary rollouts are bucketed by it
+    pub fn principal(&self) -> Option<Principal> {
+        match self {
+            Self::Token(sub) => Principal::from_text(sub).ok(),
+            Self::Service | Self::Anonymous(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Token(_) => "token",
+            Self::Service => "service",
+            Self::Anonymous(_) => "anonymous",
+        }
+    }
+}
+
+impl Upstream {
+    /// verifies the bearer token once for the upstream's audiences
+    ///
+    /// requests without a token pass through, routes that need one are rejected by the upstream
+    /// and signed user requests never carry one
+    ///
+    /// cloudflare sets `CF-Connecting-IP` on every request from the internet, anonymous requests
+    /// without it are rejected rather than sharing one bucket
+    pub async fn authenticate(&self, req: &Request, env: &Env) -> Result<Caller, (String, u16)> {
+        let has_token = req.headers().get("Authorization").ok().flatten().is_some();
+        if !has_token || self.audiences.is_empty() {
+            let Some(ip) = req.headers().get("CF-Connecting-IP").ok().flatten() else {
+                return Err(("client ip is unknown".into(), 400));
+            };
+            return Ok(Caller::Anonymous(ip));
+        }
+
+        let audiences = self.audiences.iter().map(|a| a.to_string()).collect();
+        let claims: GatewayClaims = JwtKeys::from_env(env, self.public_key)
+            .claims_from_header(audiences, self.policy, req, "Authorization")
+            .await?;
+
+        Ok(claims.sub.map_or(Caller::Service, Caller::Token))
+    }
+}
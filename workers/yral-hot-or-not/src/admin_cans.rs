@@ -1,10 +1,12 @@
+use std::sync::Arc;
+
 use candid::Principal;
 use ic_agent::identity::Secp256k1Identity;
 use k256::SecretKey;
 use worker::{Env, Result};
 use worker_utils::{
     environment::{env_kind, RunEnv},
-    icp::agent_wrapper::AgentWrapper,
+    icp::agent_wrapper::{identity_from_pem, AgentConfig, AgentWrapper},
 };
 use yral_canisters_client::individual_user_template::IndividualUserTemplate;
 
@@ -22,13 +24,12 @@ impl AdminCans {
                 let id = Secp256k1Identity::from_private_key(
                     SecretKey::from_bytes(&ADMIN_LOCAL_SECP_SK.into()).unwrap(),
                 );
-                AgentWrapper::new(id)
+                AgentWrapper::with_config(Arc::new(id), AgentConfig::from_env(env))
             }
             RunEnv::Remote => {
                 let admin_pem = env.secret("BACKEND_ADMIN_KEY")?.to_string();
-                let id = Secp256k1Identity::from_pem(admin_pem.as_bytes())
-                    .map_err(|e| worker::Error::RustError(e.to_string()))?;
-                AgentWrapper::new(id)
+                let id = identity_from_pem(&admin_pem)?;
+                AgentWrapper::with_config(id, AgentConfig::from_env(env))
             }
             RunEnv::Mock => panic!("trying to use ic-agent in mock env"),
         };
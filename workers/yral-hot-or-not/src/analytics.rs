@@ -0,0 +1,36 @@
+use candid::Principal;
+use hon_worker_common::GameResult;
+use worker_utils::{analytics::AnalyticsEvent, outbox::Outbox, retry::RetryPolicy, trace::TraceId};
+
+/// delivery attempts before an event is dead lettered, backing off up to an hour
+const ANALYTICS_DELIVERY_POLICY: RetryPolicy = RetryPolicy::new(12).with_delays(5_000, 3_600_000);
+
+pub fn vote_event(
+    user_principal: Principal,
+    post_id: String,
+    creator_principal: Option<Principal>,
+    vote_amount: u128,
+    game_result: &GameResult,
+    at: u64,
+    trace_id: TraceId,
+) -> AnalyticsEvent {
+    let (won, amount) = match game_result {
+        GameResult::Win { win_amt } => (true, win_amt),
+        GameResult::Loss { lose_amt } => (false, lose_amt),
+    };
+
+    AnalyticsEvent::Vote {
+        user_principal: user_principal.to_text(),
+        post_id,
+        creator_principal: creator_principal.map(|p| p.to_text()),
+        vote_amount: vote_amount as u64,
+        won,
+        amount: u64::try_from(amount).unwrap_or(u64::MAX),
+        at,
+        trace_id: Some(trace_id),
+    }
+}
+
+pub fn analytics_outbox() -> Outbox<AnalyticsEvent> {
+    Outbox::new("analytics", ANALYTICS_DELIVERY_POLICY)
+}
@@ -14,8 +14,9 @@ impl UserStateBackendImpl for AdminCans {
     ) -> Result<bool> {
         let user = self.individual_user(user_canister).await;
 
-        let res = user
-            .get_profile_details_v_2()
+        let res = self
+            .agent
+            .guarded(user_canister, user.get_profile_details_v_2())
             .await
             .map_err(|e| worker::Error::RustError(e.to_string()))?;
         if res.principal_id != user_principal {
@@ -24,8 +25,9 @@ impl UserStateBackendImpl for AdminCans {
             ));
         }
 
-        let res = user
-            .get_session_type()
+        let res = self
+            .agent
+            .guarded(user_canister, user.get_session_type())
             .await
             .map_err(|e| worker::Error::RustError(e.to_string()))?;
 
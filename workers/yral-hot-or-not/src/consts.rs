@@ -1,7 +1,3 @@
-use candid::Principal;
-// mxzaz-hqaaa-aaaar-qaada-cai
-#[allow(unused)]
-pub const CKBTC_LEDGER: Principal = Principal::from_slice(&[0, 0, 0, 0, 2, 48, 0, 6, 1, 1]);
 // 500 Satoshis
 
 pub const CKBTC_TREASURY_STORAGE_KEY: &str = "ckbtc-treasury-limit-v5";
@@ -19,5 +15,14 @@ pub const ADMIN_LOCAL_SECP_SK: [u8; 32] = [
 
 pub const SCHEMA_VERSION: u32 = 2;
 
+/// outcomes of balance updates with an idempotency key are replayed for this long
+pub const IDEMPOTENCY_KEY_TTL_MS: u64 = 24 * 3600 * 1000;
+
 // ckBTC transfer limits
 pub const MAX_CKBTC_TRANSFER_SATS: u128 = 20000;
+
+/// set by this worker on vote requests to a user's DO so it knows who voted
+pub const VOTER_HEADER: &str = "X-Yral-User-Principal";
+
+/// a failed analytics drain is retried by the alarm after this long
+pub const ANALYTICS_RETRY_DELAY_MS: u64 = 60 * 1000;
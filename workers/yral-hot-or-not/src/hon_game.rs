@@ -3,7 +3,7 @@ use std::collections::HashMap;
 
 use candid::Principal;
 use global_constants::{
-    CREATOR_COMMISSION_PERCENT, MAX_BET_AMOUNT_SATS, MAX_CREDITED_PER_DAY_PER_USER_SATS,
+    CREATOR_COMMISSION_PERCENT, MAX_CREDITED_PER_DAY_PER_USER_SATS,
     MAX_DEDUCTED_PER_DAY_PER_USER_SATS, MAX_WITHDRAWAL_PER_DAY_SATS, NEW_USER_SIGNUP_REWARD_SATS,
     REFERRAL_REWARD_SATS,
 };
@@ -19,31 +19,102 @@ use num_bigint::{BigInt, BigUint};
 use std::result::Result as StdResult;
 use worker::*;
 use worker_utils::{
+    analytics::{AnalyticsEvent, ANALYTICS_EVENTS_QUEUE},
+    api_error::error_resp,
+    backup::{self, Backup},
+    do_client::DoClient,
     err_to_resp,
-    storage::{daily_cumulative_limit::DailyCumulativeLimit, SafeStorage, StorageCell},
-    RequestInitBuilder,
+    metrics::Metrics,
+    outbox::Outbox,
+    pagination::KeyCursorPager,
+    storage::{
+        balance::{broadcast_to_websockets, BalanceEngine, BalanceError, Currency, DailyLimits},
+        cumulative_limit::CumulativeLimit,
+        SafeStorage, StorageCell,
+    },
+    trace::TraceId,
+    trace_error,
 };
 
 use crate::{
+    analytics::{analytics_outbox, vote_event},
     consts::{
-        CKBTC_TREASURY_STORAGE_KEY, MAX_CKBTC_TRANSFER_SATS, SATS_CREDITED_STORAGE_KEY,
-        SATS_DEDUCTED_STORAGE_KEY, SCHEMA_VERSION,
+        ANALYTICS_RETRY_DELAY_MS, CKBTC_TREASURY_STORAGE_KEY, IDEMPOTENCY_KEY_TTL_MS,
+        MAX_CKBTC_TRANSFER_SATS, SATS_CREDITED_STORAGE_KEY, SATS_DEDUCTED_STORAGE_KEY,
+        SCHEMA_VERSION, VOTER_HEADER,
     },
-    get_hon_game_stub_env,
+    ledger::{SatsLedger, SatsLedgerEntry, SatsLedgerReason, TransactionsReq},
     referral::ReferralStore,
     treasury::{CkBtcTreasury, CkBtcTreasuryImpl},
-    CkBtcTransferRequest, CkBtcTransferResponse,
+    vote::{capped_bet, stage_vote},
+    CkBtcTransferRequest, CkBtcTransferResponse, IdempotentUpdate, MemoizedSatsUpdate,
+    SatsUpdateOutcome, TreasuryStatus, USER_HON_GAME_STATE,
 };
 
+pub struct Sats;
+
+impl Currency for Sats {
+    const BALANCE_KEY: &'static str = "sats_balance_v3";
+    const CREDITED_KEY: &'static str = SATS_CREDITED_STORAGE_KEY;
+    const DEDUCTED_KEY: &'static str = SATS_DEDUCTED_STORAGE_KEY;
+
+    fn initial_balance() -> BigUint {
+        BigUint::from(NEW_USER_SIGNUP_REWARD_SATS)
+    }
+}
+
+fn balance_err(e: BalanceError) -> (u16, WorkerError) {
+    match e {
+        BalanceError::CreditLimitReached => (400, WorkerError::SatsCreditLimitReached),
+        BalanceError::DeductLimitReached => (400, WorkerError::SatsDeductLimitReached),
+        BalanceError::Conflict { new_balance } => {
+            (409, WorkerError::BalanceTransactionConflict { new_balance })
+        }
+        BalanceError::InsufficientFunds => (400, WorkerError::InsufficientFunds),
+        BalanceError::Storage(e) => (500, WorkerError::Internal(e.to_string())),
+    }
+}
+
+fn vote_ledger_entry(
+    game_result: &GameResult,
+    resulting_balance: BigUint,
+    creator_principal: Option<Principal>,
+    post_id: &str,
+) -> SatsLedgerEntry {
+    let delta = match game_result {
+        GameResult::Win { win_amt } => BigInt::from(win_amt.clone()),
+        GameResult::Loss { lose_amt } => -BigInt::from(lose_amt.clone()),
+    };
+
+    SatsLedgerEntry::new(SatsLedgerReason::Vote, delta, resulting_balance)
+        .with_counterparty(creator_principal)
+        .with_post_id(Some(post_id.to_string()))
+}
+
+/// the post goes along as a query param so the creator's ledger can point at it
+/// the voter sent by the worker in `VOTER_HEADER`
+fn voter_principal(req: &Request) -> Result<Option<Principal>> {
+    req.headers()
+        .get(VOTER_HEADER)?
+        .map(|p| Principal::from_text(p).map_err(|e| Error::RustError(e.to_string())))
+        .transpose()
+}
+
+fn creator_reward_path(post_id: &str) -> String {
+    let mut url = Url::parse("http://do/creator_reward").expect("valid url");
+    url.query_pairs_mut().append_pair("post_id", post_id);
+
+    format!("creator_reward?{}", url.query().unwrap_or_default())
+}
+
 #[durable_object]
 pub struct UserHonGameState {
     state: State,
     pub(crate) env: Env,
     #[allow(unused)]
     treasury: CkBtcTreasuryImpl,
-    #[allow(unused)]
-    treasury_amount: RefCell<DailyCumulativeLimit<{ MAX_WITHDRAWAL_PER_DAY_SATS }>>,
-    sats_balance: RefCell<StorageCell<BigUint>>,
+    treasury_amount: RefCell<CumulativeLimit>,
+    sats: RefCell<BalanceEngine<Sats>>,
     airdrop_amount: RefCell<StorageCell<BigUint>>,
     // unix timestamp in millis, None if user has never claimed airdrop before
     last_airdrop_claimed_at: RefCell<StorageCell<Option<u64>>>,
@@ -52,9 +123,10 @@ pub struct UserHonGameState {
     // (user_principal, post_id) -> GameInfo
     games_by_user_principal: RefCell<Option<HashMap<(Principal, String), GameInfo>>>,
     referral: RefCell<ReferralStore>,
-    sats_credited: RefCell<DailyCumulativeLimit<{ MAX_CREDITED_PER_DAY_PER_USER_SATS }>>,
-    sats_deducted: RefCell<DailyCumulativeLimit<{ MAX_DEDUCTED_PER_DAY_PER_USER_SATS }>>,
+    ledger: RefCell<SatsLedger>,
     pub(crate) schema_version: RefCell<StorageCell<u32>>,
+    analytics: RefCell<Outbox<AnalyticsEvent>>,
+    metrics: Metrics,
 }
 
 // SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
@@ -67,9 +139,58 @@ impl UserHonGameState {
         self.state.storage().into()
     }
 
+    fn record_vote(&self, version: &str, game_result: &GameResult, vote_amount: u128) {
+        let outcome = match game_result {
+            GameResult::Win { .. } => "win",
+            GameResult::Loss { .. } => "loss",
+        };
+        self.metrics.counter("hon_votes", &[version, outcome]);
+        self.metrics.histogram(
+            "hon_vote_amount_sats",
+            &[version, outcome],
+            vote_amount as f64,
+        );
+    }
+
+    /// moves the alarm earlier if `at` (unix millis) is before the scheduled one
+    async fn schedule_alarm(&self, at: u64) -> Result<()> {
+        let storage = self.state.storage();
+        let at = at as i64;
+        if let Some(scheduled) = storage.get_alarm().await? {
+            if scheduled <= at {
+                return Ok(());
+            }
+        }
+        let offset = (at - Date::now().as_millis() as i64).max(0);
+
+        storage.set_alarm(offset).await
+    }
+
+    /// delivers recorded analytics events to the analytics queue
+    async fn drain_analytics(&self) -> Result<()> {
+        let queue = self.env.queue(ANALYTICS_EVENTS_QUEUE)?;
+        let mut storage = self.storage();
+        let report = self
+            .analytics
+            .borrow_mut()
+            .drain(&mut storage, &queue)
+            .await?;
+        if report.dead > 0 {
+            console_error!(
+                "dead lettered {} analytics events after repeated failures",
+                report.dead
+            );
+        }
+        if let Some(at) = report.next_attempt_at {
+            self.schedule_alarm(at).await?;
+        }
+
+        Ok(())
+    }
+
     async fn broadcast_balance_inner(&self) -> Result<()> {
         let storage = self.storage();
-        let balance = self.sats_balance.borrow_mut().read(&storage).await?.clone();
+        let balance = self.sats.borrow_mut().balance(&storage).await?;
         let airdropped = self
             .airdrop_amount
             .borrow_mut()
@@ -80,12 +201,7 @@ impl UserHonGameState {
             balance,
             airdropped,
         };
-        for ws in self.state.get_websockets() {
-            let err = ws.send(&bal);
-            if let Err(e) = err {
-                console_warn!("failed to broadcast balance update: {e}");
-            }
-        }
+        broadcast_to_websockets(&self.state, &bal);
 
         Ok(())
     }
@@ -96,6 +212,43 @@ impl UserHonGameState {
         }
     }
 
+    /// best effort, a failed append must not fail the balance update it records
+    async fn append_ledger_entry(&self, entry: SatsLedgerEntry) {
+        let mut storage = self.storage();
+        let res = self.ledger.borrow_mut().append(&mut storage, &entry).await;
+        if let Err(e) = res {
+            self.ledger.borrow_mut().invalidate();
+            console_error!("failed to append sats ledger entry: {e}");
+        }
+    }
+
+    /// required after storage is modified behind the cells' back, e.g. `delete_all`
+    fn invalidate_cached_state(&self) {
+        self.treasury_amount.borrow_mut().invalidate();
+        self.sats.borrow_mut().invalidate();
+        self.airdrop_amount.borrow_mut().invalidate();
+        self.last_airdrop_claimed_at.borrow_mut().invalidate();
+        *self.games.borrow_mut() = None;
+        *self.games_by_user_principal.borrow_mut() = None;
+        self.referral.borrow_mut().invalidate();
+        self.ledger.borrow_mut().invalidate();
+        self.schema_version.borrow_mut().invalidate();
+        self.analytics.borrow_mut().invalidate();
+    }
+
+    /// erases everything stored for this user, undelivered analytics events included
+    async fn forget(&self) -> Result<()> {
+        let mut storage = self.storage();
+        storage.delete_all().await?;
+        self.state.storage().delete_alarm().await?;
+        self.invalidate_cached_state();
+        console_log!("erased hot or not data for {}", self.state.id());
+
+        self.broadcast_balance().await;
+
+        Ok(())
+    }
+
     async fn last_airdrop_claimed_at(&self) -> Result<Option<u64>> {
         let storage = self.storage();
         let last_claimed_timestamp = {
@@ -108,34 +261,49 @@ impl UserHonGameState {
         Ok(last_claimed_timestamp)
     }
 
+    fn invalidate_airdrop_state(&self) {
+        self.last_airdrop_claimed_at.borrow_mut().invalidate();
+        self.sats.borrow_mut().invalidate();
+        self.airdrop_amount.borrow_mut().invalidate();
+    }
+
     async fn claim_airdrop(&self, amount: u64) -> Result<StdResult<u64, AirdropClaimError>> {
         let now = Date::now().as_millis();
         let mut storage = self.storage();
-        // TODO: use txns instead of separate update calls
+        let mut batch = storage.batch();
         {
             self.last_airdrop_claimed_at
                 .borrow_mut()
-                .update(&mut storage, |time| {
-                    *time = Some(now);
-                })
-                .await?;
+                .set_in(&mut batch, Some(now))?;
         }
+        let mut new_balance = BigUint::ZERO;
         {
-            self.sats_balance
+            self.sats
                 .borrow_mut()
-                .update(&mut storage, |balance| {
+                .update_in(&storage, &mut batch, |balance| {
                     *balance += amount;
+                    new_balance = balance.clone();
                 })
                 .await?;
         }
         {
             self.airdrop_amount
                 .borrow_mut()
-                .update(&mut storage, |balance| {
+                .update_in(&storage, &mut batch, |balance| {
                     *balance += amount;
                 })
                 .await?;
         }
+        if let Err(e) = batch.commit(&mut storage).await {
+            self.invalidate_airdrop_state();
+            return Err(e);
+        }
+        self.append_ledger_entry(SatsLedgerEntry::new(
+            SatsLedgerReason::Airdrop,
+            amount.into(),
+            new_balance,
+        ))
+        .await;
 
         self.broadcast_balance().await;
 
@@ -171,39 +339,26 @@ impl UserHonGameState {
         page_size: usize,
         cursor: Option<String>,
     ) -> Result<PaginatedGamesRes> {
-        let page_size = page_size.clamp(1, 100);
-        let to_fetch = page_size + 1;
-        let mut list_options = ListOptions::new().prefix("games-").limit(to_fetch);
-        if let Some(cursor) = cursor.as_ref() {
-            list_options = list_options.start(cursor.as_str());
-        }
-
-        let mut games = self
-            .storage()
-            .list_with_options::<GameInfo>(list_options)
-            .await
-            .map(|v| {
-                v.map(|(k, v)| {
-                    let (can_raw, post_raw) =
-                        k.strip_prefix("games-").unwrap().rsplit_once("-").unwrap();
-                    let canister_id = Principal::from_text(can_raw).unwrap();
-                    let post_id = post_raw.parse::<u64>().unwrap();
+        let page = KeyCursorPager::new("games-")
+            .page(
+                &self.storage(),
+                page_size,
+                cursor.as_deref(),
+                |k, game_info: GameInfo| {
+                    let (can_raw, post_raw) = k.rsplit_once("-").unwrap();
                     GameRes {
-                        post_canister: canister_id,
-                        post_id,
-                        game_info: v,
+                        post_canister: Principal::from_text(can_raw).unwrap(),
+                        post_id: post_raw.parse::<u64>().unwrap(),
+                        game_info,
                     }
-                })
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let next = if games.len() > page_size {
-            let info = games.pop().unwrap();
-            Some(format!("games-{}-{}", info.post_canister, info.post_id))
-        } else {
-            None
-        };
+                },
+            )
+            .await?;
 
-        Ok(PaginatedGamesRes { games, next })
+        Ok(PaginatedGamesRes {
+            games: page.items,
+            next: page.next,
+        })
     }
 
     // async fn redeem_sats_for_ckbtc(
@@ -214,7 +369,7 @@ impl UserHonGameState {
     //     let mut storage = self.storage();
 
     //     let mut insufficient_funds = false;
-    //     self.sats_balance.borrow_mut()
+    //     self.sats.borrow_mut()
     //         .update(&mut storage, |balance| {
     //             if *balance < amount {
     //                 insufficient_funds = true;
@@ -242,7 +397,7 @@ impl UserHonGameState {
     //         })
     //         .is_err()
     //     {
-    //         self.sats_balance.borrow_mut()
+    //         self.sats.borrow_mut()
     //             .update(&mut storage, |balance| {
     //                 *balance += amount.clone();
     //             })
@@ -270,7 +425,7 @@ impl UserHonGameState {
     //                     WorkerError::Internal("failed to rollback treasury".into()),
     //                 )
     //             })?;
-    //         self.sats_balance.borrow_mut()
+    //         self.sats.borrow_mut()
     //             .update(&mut storage, |balance| {
     //                 *balance += amount.clone();
     //             })
@@ -304,12 +459,18 @@ impl UserHonGameState {
             .cloned())
     }
 
-    async fn add_creator_reward(&self, reward: u128) -> StdResult<(), (u16, WorkerError)> {
+    async fn add_creator_reward(
+        &self,
+        reward: u128,
+        post_id: Option<String>,
+    ) -> StdResult<(), (u16, WorkerError)> {
         let mut storage = self.storage();
-        self.sats_balance
+        let mut new_balance = BigUint::ZERO;
+        self.sats
             .borrow_mut()
             .update(&mut storage, |bal| {
                 *bal += reward;
+                new_balance = bal.clone();
             })
             .await
             .map_err(|_| {
@@ -318,6 +479,11 @@ impl UserHonGameState {
                     WorkerError::Internal("failed to update balance".into()),
                 )
             })?;
+        self.append_ledger_entry(
+            SatsLedgerEntry::new(SatsLedgerReason::CreatorReward, reward.into(), new_balance)
+                .with_post_id(post_id),
+        )
+        .await;
 
         self.broadcast_balance().await;
 
@@ -326,12 +492,14 @@ impl UserHonGameState {
 
     async fn vote_on_post(
         &self,
+        user_principal: Principal,
         post_canister: Principal,
         post_id: String,
         mut vote_amount: u128,
         direction: HotOrNot,
         sentiment: HotOrNot,
         creator_principal: Option<Principal>,
+        trace: &TraceId,
     ) -> StdResult<VoteRes, (u16, WorkerError)> {
         let game_info = self
             .game_info(post_canister, post_id.clone())
@@ -341,71 +509,80 @@ impl UserHonGameState {
             return Err((400, WorkerError::AlreadyVotedOnPost));
         }
 
-        vote_amount = vote_amount.min(MAX_BET_AMOUNT_SATS as u128);
+        vote_amount = capped_bet(vote_amount);
 
         let mut storage = self.storage();
-        let mut res = None::<(GameResult, u128)>;
-        self.sats_balance
-            .borrow_mut()
-            .update(&mut storage, |balance| {
-                let creator_reward_rounded =
-                    ((vote_amount as f64) * (CREATOR_COMMISSION_PERCENT as f64) / 100.0).ceil()
-                        as u128;
-                let vote_amount = BigUint::from(vote_amount);
-                if *balance < vote_amount {
-                    return;
-                }
-                let game_res = if sentiment == direction {
-                    let mut win_amt = (vote_amount.clone() * 8u32) / 10u32;
-                    if win_amt == BigUint::from(0u32) {
-                        win_amt = BigUint::from(1u32);
-                    }
-                    *balance += win_amt.clone();
-                    GameResult::Win { win_amt }
-                } else {
-                    *balance -= vote_amount.clone();
-                    GameResult::Loss {
-                        lose_amt: vote_amount.clone(),
-                    }
-                };
-                res = Some((game_res, creator_reward_rounded))
-            })
-            .await
-            .map_err(|_| {
-                (
-                    500,
-                    WorkerError::Internal("failed to update balance".into()),
-                )
-            })?;
+        let mut batch = storage.batch();
+        let (game_result, updated_balance) = stage_vote(
+            &mut self.sats.borrow_mut(),
+            &storage,
+            &mut batch,
+            vote_amount,
+            direction,
+            sentiment,
+        )
+        .await?;
+        let creator_reward =
+            ((vote_amount as f64) * (CREATOR_COMMISSION_PERCENT as f64) / 100.0).ceil() as u128;
 
-        let Some((game_result, creator_reward)) = res else {
-            return Err((400, WorkerError::InsufficientFunds));
+        let game_info = GameInfo::Vote {
+            vote_amount: BigUint::from(vote_amount),
+            game_result: game_result.clone(),
         };
+        batch
+            .put(format!("games-{post_canister}-{post_id}"), &game_info)
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        let event = vote_event(
+            user_principal,
+            post_id.clone(),
+            creator_principal,
+            vote_amount,
+            &game_result,
+            Date::now().as_millis(),
+            trace.clone(),
+        );
+        self.analytics
+            .borrow_mut()
+            .record_in(&storage, &mut batch, event)
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        if let Err(e) = batch.commit(&mut storage).await {
+            self.sats.borrow_mut().invalidate();
+            self.analytics.borrow_mut().invalidate();
+            return Err((
+                500,
+                WorkerError::Internal(format!("failed to store vote: {e}")),
+            ));
+        }
+        self.record_vote("v1", &game_result, vote_amount);
+        self.append_ledger_entry(vote_ledger_entry(
+            &game_result,
+            updated_balance,
+            creator_principal,
+            &post_id,
+        ))
+        .await;
+        if let Err(e) = self.schedule_alarm(Date::now().as_millis()).await {
+            trace_error!(trace, "failed to schedule analytics delivery: {e}");
+        }
 
         self.broadcast_balance().await;
 
         if let Some(creator_principal) = creator_principal {
-            let game_stub = get_hon_game_stub_env(&self.env, creator_principal)
-                .map_err(|_| (500, WorkerError::Internal("failed to get game stub".into())))?;
-            let req = Request::new_with_init(
-                "http://fake_url.com/creator_reward",
-                RequestInitBuilder::default()
-                    .method(Method::Post)
-                    .json(&creator_reward)
-                    .unwrap()
-                    .build(),
-            )
-            .expect("creator reward should build?!");
-            let res = game_stub.fetch_with_request(req).await;
+            let res = DoClient::new(&self.env)
+                .with_trace(trace)
+                .post(
+                    USER_HON_GAME_STATE,
+                    &creator_principal.to_text(),
+                    &creator_reward_path(&post_id),
+                    &creator_reward,
+                )
+                .await;
             if let Err(e) = res {
-                eprintln!("failed to reward creator {e}");
+                trace_error!(trace, "failed to reward creator {e}");
             }
         }
 
-        let game_info = GameInfo::Vote {
-            vote_amount: BigUint::from(vote_amount),
-            game_result: game_result.clone(),
-        };
         self.ensure_games_loaded()
             .await
             .map_err(|_| (500, WorkerError::Internal("failed to get games".into())))?;
@@ -414,27 +591,20 @@ impl UserHonGameState {
             .as_mut()
             .unwrap()
             .insert((post_canister, post_id.to_string()), game_info.clone());
-        self.storage()
-            .put(&format!("games-{post_canister}-{post_id}"), &game_info)
-            .await
-            .map_err(|_| {
-                (
-                    500,
-                    WorkerError::Internal("failed to store game info".into()),
-                )
-            })?;
 
         Ok(VoteRes { game_result })
     }
 
     async fn vote_on_post_v2(
         &self,
+        user_principal: Principal,
         post_canister: Principal,
         post_id: String,
         mut vote_amount: u128,
         direction: HotOrNot,
         sentiment: HotOrNot,
         creator_principal: Option<Principal>,
+        trace: &TraceId,
     ) -> StdResult<VoteResV2, (u16, WorkerError)> {
         let game_info = self
             .game_info(post_canister, post_id.clone())
@@ -444,69 +614,79 @@ impl UserHonGameState {
             return Err((400, WorkerError::AlreadyVotedOnPost));
         }
 
-        vote_amount = vote_amount.min(MAX_BET_AMOUNT_SATS as u128);
+        vote_amount = capped_bet(vote_amount);
 
         let mut storage = self.storage();
-        let mut res = None::<(GameResult, u128, BigUint)>;
-        self.sats_balance
-            .borrow_mut()
-            .update(&mut storage, |balance| {
-                let creator_reward = vote_amount / 10;
-                let vote_amount = BigUint::from(vote_amount);
-                if *balance < vote_amount {
-                    return;
-                }
-                let game_res = if sentiment == direction {
-                    let mut win_amt = (vote_amount.clone() * 8u32) / 10u32;
-                    if win_amt == BigUint::from(0u32) {
-                        win_amt = BigUint::from(1u32);
-                    }
-                    *balance += win_amt.clone();
-                    GameResult::Win { win_amt }
-                } else {
-                    *balance -= vote_amount.clone();
-                    GameResult::Loss {
-                        lose_amt: vote_amount.clone(),
-                    }
-                };
-                res = Some((game_res, creator_reward, balance.clone()))
-            })
-            .await
-            .map_err(|_| {
-                (
-                    500,
-                    WorkerError::Internal("failed to update balance".into()),
-                )
-            })?;
+        let mut batch = storage.batch();
+        let (game_result, updated_balance) = stage_vote(
+            &mut self.sats.borrow_mut(),
+            &storage,
+            &mut batch,
+            vote_amount,
+            direction,
+            sentiment,
+        )
+        .await?;
+        let creator_reward = vote_amount / 10;
 
-        let Some((game_result, creator_reward, updated_balance)) = res else {
-            return Err((400, WorkerError::InsufficientFunds));
+        let game_info = GameInfo::Vote {
+            vote_amount: BigUint::from(vote_amount),
+            game_result: game_result.clone(),
         };
+        batch
+            .put(format!("games-{post_canister}-{}", &post_id), &game_info)
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        let event = vote_event(
+            user_principal,
+            post_id.clone(),
+            creator_principal,
+            vote_amount,
+            &game_result,
+            Date::now().as_millis(),
+            trace.clone(),
+        );
+        self.analytics
+            .borrow_mut()
+            .record_in(&storage, &mut batch, event)
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        if let Err(e) = batch.commit(&mut storage).await {
+            self.sats.borrow_mut().invalidate();
+            self.analytics.borrow_mut().invalidate();
+            return Err((
+                500,
+                WorkerError::Internal(format!("failed to store vote: {e}")),
+            ));
+        }
+        self.record_vote("v2", &game_result, vote_amount);
+        self.append_ledger_entry(vote_ledger_entry(
+            &game_result,
+            updated_balance.clone(),
+            creator_principal,
+            &post_id,
+        ))
+        .await;
+        if let Err(e) = self.schedule_alarm(Date::now().as_millis()).await {
+            trace_error!(trace, "failed to schedule analytics delivery: {e}");
+        }
 
         self.broadcast_balance().await;
 
         if let Some(creator_principal) = creator_principal {
-            let game_stub = get_hon_game_stub_env(&self.env, creator_principal)
-                .map_err(|_| (500, WorkerError::Internal("failed to get game stub".into())))?;
-            let req = Request::new_with_init(
-                "http://fake_url.com/creator_reward",
-                RequestInitBuilder::default()
-                    .method(Method::Post)
-                    .json(&creator_reward)
-                    .unwrap()
-                    .build(),
-            )
-            .expect("creator reward should build?!");
-            let res = game_stub.fetch_with_request(req).await;
+            let res = DoClient::new(&self.env)
+                .with_trace(trace)
+                .post(
+                    USER_HON_GAME_STATE,
+                    &creator_principal.to_text(),
+                    &creator_reward_path(&post_id),
+                    &creator_reward,
+                )
+                .await;
             if let Err(e) = res {
-                eprintln!("failed to reward creator {e}");
+                trace_error!(trace, "failed to reward creator {e}");
             }
         }
 
-        let game_info = GameInfo::Vote {
-            vote_amount: BigUint::from(vote_amount),
-            game_result: game_result.clone(),
-        };
         self.ensure_games_loaded()
             .await
             .map_err(|_| (500, WorkerError::Internal("failed to get games".into())))?;
@@ -515,15 +695,6 @@ impl UserHonGameState {
             .as_mut()
             .unwrap()
             .insert((post_canister, post_id.to_string()), game_info.clone());
-        self.storage()
-            .put(&format!("games-{post_canister}-{}", &post_id), &game_info)
-            .await
-            .map_err(|_| {
-                (
-                    500,
-                    WorkerError::Internal("failed to store game info".into()),
-                )
-            })?;
 
         // Convert GameResult to GameResultV2 by adding updated_balance
         let game_result_v2 = match game_result {
@@ -572,13 +743,20 @@ impl UserHonGameState {
             .await
             .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
 
-        self.sats_balance
+        let mut new_balance = BigUint::ZERO;
+        self.sats
             .borrow_mut()
             .update(&mut storage, |balance| {
                 *balance += BigUint::from(amount);
+                new_balance = balance.clone();
             })
             .await
             .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        self.append_ledger_entry(
+            SatsLedgerEntry::new(SatsLedgerReason::ReferralSignup, amount.into(), new_balance)
+                .with_counterparty(Some(referrer)),
+        )
+        .await;
         self.broadcast_balance().await;
 
         Ok(())
@@ -614,13 +792,20 @@ impl UserHonGameState {
             .await
             .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
 
-        self.sats_balance
+        let mut new_balance = BigUint::ZERO;
+        self.sats
             .borrow_mut()
             .update(&mut storage, |balance| {
                 *balance += BigUint::from(amount);
+                new_balance = balance.clone();
             })
             .await
             .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        self.append_ledger_entry(
+            SatsLedgerEntry::new(SatsLedgerReason::ReferralReward, amount.into(), new_balance)
+                .with_counterparty(Some(referee)),
+        )
+        .await;
         self.broadcast_balance().await;
 
         Ok(())
@@ -693,51 +878,24 @@ impl UserHonGameState {
         delta: BigInt,
         is_airdropped: bool,
     ) -> StdResult<BigUint, (u16, WorkerError)> {
-        if delta >= BigInt::ZERO {
-            self.sats_credited
-                .borrow_mut()
-                .try_consume(&mut self.storage(), delta.to_biguint().unwrap())
-                .await
-                .map_err(|_| (400, WorkerError::SatsCreditLimitReached))?;
-        } else {
-            self.sats_deducted
-                .borrow_mut()
-                .try_consume(&mut self.storage(), (-delta.clone()).to_biguint().unwrap())
-                .await
-                .map_err(|_| (400, WorkerError::SatsDeductLimitReached))?;
-        }
-
         let new_bal = self
-            .sats_balance
+            .sats
             .borrow_mut()
-            .try_get_update(&mut self.storage(), |balance| {
-                if expected_balance.map(|b| b != *balance).unwrap_or_default() {
-                    return Err((
-                        409,
-                        WorkerError::BalanceTransactionConflict {
-                            new_balance: balance.clone(),
-                        },
-                    ));
-                }
-                let delta = delta.clone();
-                if delta >= BigInt::ZERO {
-                    let delta = delta.to_biguint().unwrap();
-                    *balance += delta;
-                    return Ok(());
-                }
-                let neg_delta = (-delta).to_biguint().unwrap();
-                if neg_delta > *balance {
-                    return Err((400, WorkerError::InsufficientFunds));
-                }
-                *balance -= neg_delta;
-
-                Ok(())
-            })
+            .apply(
+                &mut self.storage(),
+                expected_balance.as_ref(),
+                &delta,
+                &BigUint::ZERO,
+            )
             .await
-            .map_err(|e| match e {
-                Ok(e) => e,
-                Err(e) => (500, WorkerError::Internal(e.to_string())),
-            })?;
+            .map_err(balance_err)?;
+        let reason = if is_airdropped {
+            SatsLedgerReason::Airdrop
+        } else {
+            SatsLedgerReason::ExternalUpdate
+        };
+        self.append_ledger_entry(SatsLedgerEntry::new(reason, delta.clone(), new_bal.clone()))
+            .await;
 
         if !is_airdropped {
             self.broadcast_balance().await;
@@ -760,6 +918,98 @@ impl UserHonGameState {
         Ok(new_bal)
     }
 
+    fn idempotency_storage_key(key: &str) -> String {
+        format!("idempotency-{key}")
+    }
+
+    /// same as `update_balance_for_external_client`, but replays the stored outcome
+    /// if `idempotency_key` was seen in the last 24h
+    async fn update_balance_idempotent(
+        &self,
+        expected_balance: Option<BigUint>,
+        delta: BigInt,
+        is_airdropped: bool,
+        idempotency_key: Option<String>,
+    ) -> Result<SatsUpdateOutcome> {
+        let Some(key) = idempotency_key else {
+            return Ok(self
+                .update_balance_inner(expected_balance, delta, is_airdropped)
+                .await);
+        };
+        let storage_key = Self::idempotency_storage_key(&key);
+        let mut storage = self.storage();
+        let now = Date::now().as_millis();
+
+        let memoized = storage.get::<MemoizedSatsUpdate>(&storage_key).await?;
+        if let Some(memoized) = memoized {
+            if now - memoized.created_at < IDEMPOTENCY_KEY_TTL_MS {
+                return Ok(memoized.outcome);
+            }
+        }
+
+        let outcome = self
+            .update_balance_inner(expected_balance, delta, is_airdropped)
+            .await;
+        // internal errors are transient, retries should go through
+        if matches!(outcome, SatsUpdateOutcome::Err { code: 500, .. }) {
+            return Ok(outcome);
+        }
+
+        let memoized = MemoizedSatsUpdate {
+            outcome,
+            created_at: now,
+        };
+        storage.put(&storage_key, &memoized).await?;
+        self.schedule_alarm(now + IDEMPOTENCY_KEY_TTL_MS).await?;
+
+        Ok(memoized.outcome)
+    }
+
+    async fn update_balance_inner(
+        &self,
+        expected_balance: Option<BigUint>,
+        delta: BigInt,
+        is_airdropped: bool,
+    ) -> SatsUpdateOutcome {
+        match self
+            .update_balance_for_external_client(expected_balance, delta, is_airdropped)
+            .await
+        {
+            Ok(new_bal) => SatsUpdateOutcome::Ok(new_bal),
+            Err((code, error)) => SatsUpdateOutcome::Err { code, error },
+        }
+    }
+
+    /// drops memoized outcomes older than the TTL
+    async fn cleanup_idempotency_keys(&self) -> Result<()> {
+        let mut storage = self.storage();
+        let now = Date::now().as_millis();
+        let entries = storage
+            .list_with_prefix::<MemoizedSatsUpdate>("idempotency-")
+            .await
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut expired = Vec::new();
+        let mut next_expiry = None::<u64>;
+        for (key, memoized) in entries {
+            let expires_at = memoized.created_at + IDEMPOTENCY_KEY_TTL_MS;
+            if expires_at <= now {
+                expired.push(key);
+            } else {
+                next_expiry = Some(next_expiry.map_or(expires_at, |e| e.min(expires_at)));
+            }
+        }
+        if !expired.is_empty() {
+            storage.delete_multiple(expired).await?;
+        }
+
+        if let Some(next_expiry) = next_expiry {
+            self.schedule_alarm(next_expiry).await?;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn ensure_games_by_user_principal_loaded(&self) -> Result<()> {
         if self.games_by_user_principal.borrow().is_some() {
             return Ok(());
@@ -792,47 +1042,26 @@ impl UserHonGameState {
         page_size: usize,
         cursor: Option<String>,
     ) -> Result<PaginatedGamesResV3> {
-        let page_size = page_size.clamp(1, 100);
-        let to_fetch = page_size + 1;
-        let mut list_options = ListOptions::new()
-            .prefix("games_by_user_principal-")
-            .limit(to_fetch);
-        if let Some(cursor) = cursor.as_ref() {
-            list_options = list_options.start(cursor.as_str());
-        }
-
-        let mut games = self
-            .storage()
-            .list_with_options::<GameInfo>(list_options)
-            .await
-            .map(|v| {
-                v.map(|(k, v)| {
-                    let (user_raw, post_raw) = k
-                        .strip_prefix("games_by_user_principal-")
-                        .unwrap()
-                        .rsplit_once("-")
-                        .unwrap();
-                    let publisher_principal = Principal::from_text(user_raw).unwrap();
-                    let post_id = post_raw.parse::<u64>().unwrap();
+        let page = KeyCursorPager::new("games_by_user_principal-")
+            .page(
+                &self.storage(),
+                page_size,
+                cursor.as_deref(),
+                |k, game_info: GameInfo| {
+                    let (user_raw, post_raw) = k.rsplit_once("-").unwrap();
                     GameResV3 {
-                        publisher_principal,
-                        post_id,
-                        game_info: v,
+                        publisher_principal: Principal::from_text(user_raw).unwrap(),
+                        post_id: post_raw.parse::<u64>().unwrap(),
+                        game_info,
                     }
-                })
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let next = if games.len() > page_size {
-            let info = games.pop().unwrap();
-            Some(format!(
-                "games_by_user_principal-{}-{}",
-                info.publisher_principal, info.post_id
-            ))
-        } else {
-            None
-        };
+                },
+            )
+            .await?;
 
-        Ok(PaginatedGamesResV3 { games, next })
+        Ok(PaginatedGamesResV3 {
+            games: page.items,
+            next: page.next,
+        })
     }
 
     async fn paginated_games_with_cursor_v4(
@@ -840,47 +1069,26 @@ impl UserHonGameState {
         page_size: usize,
         cursor: Option<String>,
     ) -> Result<PaginatedGamesResV4> {
-        let page_size = page_size.clamp(1, 100);
-        let to_fetch = page_size + 1;
-        let mut list_options = ListOptions::new()
-            .prefix("games_by_user_principal-")
-            .limit(to_fetch);
-        if let Some(cursor) = cursor.as_ref() {
-            list_options = list_options.start(cursor.as_str());
-        }
-
-        let mut games = self
-            .storage()
-            .list_with_options::<GameInfo>(list_options)
-            .await
-            .map(|v| {
-                v.map(|(k, v)| {
-                    let (user_raw, post_raw) = k
-                        .strip_prefix("games_by_user_principal-")
-                        .unwrap()
-                        .rsplit_once("-")
-                        .unwrap();
-                    let publisher_principal = Principal::from_text(user_raw).unwrap();
-                    let post_id = post_raw.to_string();
+        let page = KeyCursorPager::new("games_by_user_principal-")
+            .page(
+                &self.storage(),
+                page_size,
+                cursor.as_deref(),
+                |k, game_info: GameInfo| {
+                    let (user_raw, post_raw) = k.rsplit_once("-").unwrap();
                     GameResV4 {
-                        publisher_principal,
-                        post_id,
-                        game_info: v,
+                        publisher_principal: Principal::from_text(user_raw).unwrap(),
+                        post_id: post_raw.to_string(),
+                        game_info,
                     }
-                })
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let next = if games.len() > page_size {
-            let info = games.pop().unwrap();
-            Some(format!(
-                "games_by_user_principal-{}-{}",
-                info.publisher_principal, info.post_id
-            ))
-        } else {
-            None
-        };
+                },
+            )
+            .await?;
 
-        Ok(PaginatedGamesResV4 { games, next })
+        Ok(PaginatedGamesResV4 {
+            games: page.items,
+            next: page.next,
+        })
     }
 
     async fn game_info_v3(
@@ -908,6 +1116,7 @@ impl UserHonGameState {
             .len())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn vote_on_post_v3(
         &self,
         user_principal: Principal,
@@ -916,6 +1125,7 @@ impl UserHonGameState {
         direction: HotOrNot,
         sentiment: HotOrNot,
         creator_principal: Option<Principal>,
+        trace: &TraceId,
     ) -> StdResult<VoteResV2, (u16, WorkerError)> {
         let game_info = self
             .game_info_v3(user_principal, post_id.clone())
@@ -925,67 +1135,80 @@ impl UserHonGameState {
             return Err((400, WorkerError::AlreadyVotedOnPost));
         }
 
-        vote_amount = vote_amount.min(MAX_BET_AMOUNT_SATS as u128);
+        vote_amount = capped_bet(vote_amount);
 
         let mut storage = self.storage();
-        let mut res = None::<(GameResult, u128, BigUint)>;
-        self.sats_balance
-            .borrow_mut()
-            .update(&mut storage, |balance| {
-                let creator_reward = vote_amount / 10;
-                let vote_amount = BigUint::from(vote_amount);
-                if *balance < vote_amount {
-                    return;
-                }
-                let game_res = if sentiment == direction {
-                    let mut win_amt = (vote_amount.clone() * 8u32) / 10u32;
-                    if win_amt == BigUint::from(0u32) {
-                        win_amt = BigUint::from(1u32);
-                    }
-                    *balance += win_amt.clone();
-                    GameResult::Win { win_amt }
-                } else {
-                    *balance -= vote_amount.clone();
-                    GameResult::Loss {
-                        lose_amt: vote_amount.clone(),
-                    }
-                };
-                res = Some((game_res, creator_reward, balance.clone()))
-            })
-            .await
-            .map_err(|_| {
-                (
-                    500,
-                    WorkerError::Internal("failed to update balance".into()),
-                )
-            })?;
+        let mut batch = storage.batch();
+        let (game_result, updated_balance) = stage_vote(
+            &mut self.sats.borrow_mut(),
+            &storage,
+            &mut batch,
+            vote_amount,
+            direction,
+            sentiment,
+        )
+        .await?;
+        let creator_reward = vote_amount / 10;
 
-        let Some((game_result, creator_reward, updated_balance)) = res else {
-            return Err((400, WorkerError::InsufficientFunds));
+        let game_info = GameInfo::Vote {
+            vote_amount: BigUint::from(vote_amount),
+            game_result: game_result.clone(),
         };
+        batch
+            .put(
+                format!("games_by_user_principal-{user_principal}-{post_id}"),
+                &game_info,
+            )
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        let event = vote_event(
+            user_principal,
+            post_id.clone(),
+            creator_principal,
+            vote_amount,
+            &game_result,
+            Date::now().as_millis(),
+            trace.clone(),
+        );
+        self.analytics
+            .borrow_mut()
+            .record_in(&storage, &mut batch, event)
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+        if let Err(e) = batch.commit(&mut storage).await {
+            self.sats.borrow_mut().invalidate();
+            self.analytics.borrow_mut().invalidate();
+            return Err((
+                500,
+                WorkerError::Internal(format!("failed to store vote: {e}")),
+            ));
+        }
+        self.record_vote("v3", &game_result, vote_amount);
+        self.append_ledger_entry(vote_ledger_entry(
+            &game_result,
+            updated_balance.clone(),
+            creator_principal,
+            &post_id,
+        ))
+        .await;
+        if let Err(e) = self.schedule_alarm(Date::now().as_millis()).await {
+            trace_error!(trace, "failed to schedule analytics delivery: {e}");
+        }
 
         if let Some(creator_principal) = creator_principal {
-            let game_stub = get_hon_game_stub_env(&self.env, creator_principal)
-                .map_err(|_| (500, WorkerError::Internal("failed to get game stub".into())))?;
-            let req = Request::new_with_init(
-                "http://fake_url.com/creator_reward",
-                RequestInitBuilder::default()
-                    .method(Method::Post)
-                    .json(&creator_reward)
-                    .unwrap()
-                    .build(),
-            )
-            .expect("creator reward should build?!");
-            let res = game_stub.fetch_with_request(req).await;
+            let res = DoClient::new(&self.env)
+                .with_trace(trace)
+                .post(
+                    USER_HON_GAME_STATE,
+                    &creator_principal.to_text(),
+                    &creator_reward_path(&post_id),
+                    &creator_reward,
+                )
+                .await;
             if let Err(e) = res {
-                eprintln!("failed to reward creator {e}");
+                trace_error!(trace, "failed to reward creator {e}");
             }
         }
 
-        let game_info = GameInfo::Vote {
-            vote_amount: BigUint::from(vote_amount),
-            game_result: game_result.clone(),
-        };
         self.ensure_games_by_user_principal_loaded()
             .await
             .map_err(|_| (500, WorkerError::Internal("failed to get games".into())))?;
@@ -994,18 +1217,6 @@ impl UserHonGameState {
             .as_mut()
             .unwrap()
             .insert((user_principal, post_id.clone()), game_info.clone());
-        self.storage()
-            .put(
-                &format!("games_by_user_principal-{user_principal}-{post_id}"),
-                &game_info,
-            )
-            .await
-            .map_err(|_| {
-                (
-                    500,
-                    WorkerError::Internal("failed to store game info".into()),
-                )
-            })?;
 
         // Convert GameResult to GameResultV2 by adding updated_balance
         let game_result_v2 = match game_result {
@@ -1080,6 +1291,22 @@ impl UserHonGameState {
     }
 }
 
+impl Backup for UserHonGameState {
+    const NAMESPACE: &'static str = USER_HON_GAME_STATE;
+
+    fn backup_state(&self) -> &State {
+        &self.state
+    }
+
+    fn backup_env(&self) -> &Env {
+        &self.env
+    }
+
+    fn invalidate_cached_state(&self) {
+        Self::invalidate_cached_state(self)
+    }
+}
+
 // SAFETY: See comment on first impl block for safety rationale
 #[allow(clippy::await_holding_refcell_ref)]
 impl DurableObject for UserHonGameState {
@@ -1087,14 +1314,19 @@ impl DurableObject for UserHonGameState {
         console_error_panic_hook::set_once();
 
         let treasury = CkBtcTreasuryImpl::new(&env).expect("failed to create treasury");
+        let metrics = Metrics::new(&env, "yral-hot-or-not");
 
         Self {
             state,
             env,
             treasury,
-            treasury_amount: RefCell::new(DailyCumulativeLimit::new(CKBTC_TREASURY_STORAGE_KEY)),
-            sats_balance: RefCell::new(StorageCell::new("sats_balance_v3", || {
-                BigUint::from(NEW_USER_SIGNUP_REWARD_SATS)
+            treasury_amount: RefCell::new(CumulativeLimit::daily(
+                CKBTC_TREASURY_STORAGE_KEY,
+                MAX_WITHDRAWAL_PER_DAY_SATS,
+            )),
+            sats: RefCell::new(BalanceEngine::new(DailyLimits {
+                max_credited_per_day: MAX_CREDITED_PER_DAY_PER_USER_SATS,
+                max_deducted_per_day: MAX_DEDUCTED_PER_DAY_PER_USER_SATS,
             })),
             airdrop_amount: RefCell::new(StorageCell::new("airdrop_amount_v2", || {
                 BigUint::from(NEW_USER_SIGNUP_REWARD_SATS)
@@ -1106,19 +1338,36 @@ impl DurableObject for UserHonGameState {
             games: RefCell::new(None),
             games_by_user_principal: RefCell::new(None),
             referral: RefCell::new(ReferralStore::default()),
-            sats_credited: RefCell::new(DailyCumulativeLimit::new(SATS_CREDITED_STORAGE_KEY)),
-            sats_deducted: RefCell::new(DailyCumulativeLimit::new(SATS_DEDUCTED_STORAGE_KEY)),
+            ledger: RefCell::new(SatsLedger::default()),
             schema_version: RefCell::new(StorageCell::new("schema_version", || SCHEMA_VERSION)),
+            analytics: RefCell::new(analytics_outbox()),
+            metrics,
         }
     }
 
-    async fn fetch(&self, req: Request) -> Result<Response> {
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
         let mut storage = self.storage();
         let schema_version = *self.schema_version.borrow_mut().read(&storage).await?;
+        // read only, used by yral-admin to check progress without upgrading the state
+        if req.path() == "/migration_status" {
+            return Response::from_json(&serde_json::json!({
+                "schema_version": schema_version,
+                "latest": SCHEMA_VERSION,
+            }));
+        }
+        // erased before any migration, yral-admin's account deletion must not depend on it
+        if req.method() == Method::Post && req.path() == "/forget" {
+            self.forget().await?;
+            return Response::ok("done");
+        }
+        // snapshots are raw storage, migrations run on them once they're restored
+        if let Some(res) = backup::handle(self, &mut req).await {
+            return res;
+        }
         if schema_version == 0 {
             if let Err(e) = self.migrate_games_to_user_principal_key().await {
                 console_error!("migration failed: {e}");
-                return Response::error(e.to_string(), 500);
+                return error_resp(e.to_string(), 500);
             }
         }
 
@@ -1127,10 +1376,18 @@ impl DurableObject for UserHonGameState {
                 .borrow_mut()
                 .set(&mut storage, SCHEMA_VERSION)
                 .await?;
-            self.sats_balance
+            let previous = self.sats.borrow_mut().balance(&storage).await?;
+            let reset_to = BigUint::from(300u32);
+            self.sats
                 .borrow_mut()
-                .set(&mut storage, 300u32.into())
+                .set(&mut storage, reset_to.clone())
                 .await?;
+            self.append_ledger_entry(SatsLedgerEntry::new(
+                SatsLedgerReason::SchemaReset,
+                BigInt::from(reset_to.clone()) - BigInt::from(previous),
+                reset_to,
+            ))
+            .await;
             self.airdrop_amount
                 .borrow_mut()
                 .set(&mut storage, 300u32.into())
@@ -1141,16 +1398,22 @@ impl DurableObject for UserHonGameState {
         let router = Router::with_data(self);
         router
             .post_async("/vote", async |mut req, ctx| {
+                let Some(voter) = voter_principal(&req)? else {
+                    return Response::error("missing voter principal", 400);
+                };
+                let trace = TraceId::from_request(&req);
                 let req_data: VoteRequestWithSentiment = serde_json::from_str(&req.text().await?)?;
                 let this = ctx.data;
                 match this
                     .vote_on_post(
+                        voter,
                         req_data.request.post_canister,
                         req_data.request.post_id.to_string(),
                         req_data.request.vote_amount,
                         req_data.request.direction,
                         req_data.sentiment,
                         req_data.post_creator,
+                        &trace,
                     )
                     .await
                 {
@@ -1159,16 +1422,22 @@ impl DurableObject for UserHonGameState {
                 }
             })
             .post_async("/vote_v2", async |mut req, ctx| {
+                let Some(voter) = voter_principal(&req)? else {
+                    return Response::error("missing voter principal", 400);
+                };
+                let trace = TraceId::from_request(&req);
                 let req_data: VoteRequestWithSentiment = serde_json::from_str(&req.text().await?)?;
                 let this = ctx.data;
                 match this
                     .vote_on_post_v2(
+                        voter,
                         req_data.request.post_canister,
                         req_data.request.post_id.to_string(),
                         req_data.request.vote_amount,
                         req_data.request.direction,
                         req_data.sentiment,
                         req_data.post_creator,
+                        &trace,
                     )
                     .await
                 {
@@ -1185,7 +1454,7 @@ impl DurableObject for UserHonGameState {
             .get_async("/balance", async |_, ctx| {
                 let this = ctx.data;
                 let storage = this.storage();
-                let balance = this.sats_balance.borrow_mut().read(&storage).await?.clone();
+                let balance = this.sats.borrow_mut().balance(&storage).await?;
                 let airdropped = this
                     .airdrop_amount
                     .borrow_mut()
@@ -1200,7 +1469,7 @@ impl DurableObject for UserHonGameState {
             .get_async("/v2/balance", async |_, ctx| {
                 let this = ctx.data;
                 let storage = this.storage();
-                let balance = this.sats_balance.borrow_mut().read(&storage).await?.clone();
+                let balance = this.sats.borrow_mut().balance(&storage).await?;
                 let airdropped = this
                     .airdrop_amount
                     .borrow_mut()
@@ -1212,6 +1481,20 @@ impl DurableObject for UserHonGameState {
                     airdropped,
                 })
             })
+            .get_async("/treasury_status", async |_, ctx| {
+                let this = ctx.data;
+                let storage = this.storage();
+                let (remaining, reset_at) = this
+                    .treasury_amount
+                    .borrow_mut()
+                    .remaining(&storage)
+                    .await?;
+
+                Response::from_json(&TreasuryStatus {
+                    remaining,
+                    reset_at,
+                })
+            })
             .post_async("/game_info", async |mut req, ctx| {
                 let req_data: GameInfoReq = req.json().await?;
 
@@ -1254,8 +1537,13 @@ impl DurableObject for UserHonGameState {
             })
             .post_async("/creator_reward", async |mut req, ctx| {
                 let amount: u128 = serde_json::from_str(&req.text().await?)?;
+                let post_id = req
+                    .url()?
+                    .query_pairs()
+                    .find(|(k, _)| k == "post_id")
+                    .map(|(_, v)| v.into_owned());
                 let this = ctx.data;
-                let res = this.add_creator_reward(amount).await;
+                let res = this.add_creator_reward(amount, post_id).await;
                 if let Err(e) = res {
                     return err_to_resp(e.0, e.1);
                 }
@@ -1299,37 +1587,52 @@ impl DurableObject for UserHonGameState {
                 }
                 Response::from_json(&res.unwrap())
             })
+            .post_async("/transactions", async |mut req, ctx| {
+                let req_data: TransactionsReq = req.json().await?;
+                let this = ctx.data;
+                let storage = this.storage();
+                let res = this
+                    .ledger
+                    .borrow()
+                    .page(&storage, req_data.page_size, req_data.cursor)
+                    .await?;
+
+                Response::from_json(&res)
+            })
             .post_async("/update_balance", async |mut req, ctx| {
-                let req_data: SatsBalanceUpdateRequest = serde_json::from_str(&req.text().await?)?;
+                let req_data: IdempotentUpdate<SatsBalanceUpdateRequest> =
+                    serde_json::from_str(&req.text().await?)?;
                 let this = ctx.data;
 
                 match this
-                    .update_balance_for_external_client(
+                    .update_balance_idempotent(
                         None,
-                        req_data.delta,
-                        req_data.is_airdropped,
+                        req_data.request.delta,
+                        req_data.request.is_airdropped,
+                        req_data.idempotency_key,
                     )
-                    .await
+                    .await?
                 {
-                    Ok(_) => Response::ok("done"),
-                    Err((code, msg)) => err_to_resp(code, msg),
+                    SatsUpdateOutcome::Ok(_) => Response::ok("done"),
+                    SatsUpdateOutcome::Err { code, error } => err_to_resp(code, error),
                 }
             })
             .post_async("/v2/update_balance", async |mut req, ctx| {
-                let req_data: SatsBalanceUpdateRequestV2 =
+                let req_data: IdempotentUpdate<SatsBalanceUpdateRequestV2> =
                     serde_json::from_str(&req.text().await?)?;
                 let this = ctx.data;
 
                 match this
-                    .update_balance_for_external_client(
-                        Some(req_data.previous_balance),
-                        req_data.delta,
-                        req_data.is_airdropped,
+                    .update_balance_idempotent(
+                        Some(req_data.request.previous_balance),
+                        req_data.request.delta,
+                        req_data.request.is_airdropped,
+                        req_data.idempotency_key,
                     )
-                    .await
+                    .await?
                 {
-                    Ok(new_bal) => Response::ok(new_bal.to_string()),
-                    Err((code, msg)) => err_to_resp(code, msg),
+                    SatsUpdateOutcome::Ok(new_bal) => Response::ok(new_bal.to_string()),
+                    SatsUpdateOutcome::Err { code, error } => err_to_resp(code, error),
                 }
             })
             .post_async("/v2/transfer_ckbtc", async |mut req, ctx| {
@@ -1345,7 +1648,7 @@ impl DurableObject for UserHonGameState {
                 let this = ctx.data;
                 match this.migrate_games_to_user_principal_key().await {
                     Ok(_) => Response::ok("done"),
-                    Err(e) => Response::error(e.to_string(), 500),
+                    Err(e) => error_resp(e.to_string(), 500),
                 }
             })
             .post_async("/v3/game_info", async |mut req, ctx| {
@@ -1378,6 +1681,7 @@ impl DurableObject for UserHonGameState {
             .post_async("/v3/vote", async |mut req, ctx| {
                 let req_data: VoteRequestWithSentimentV3 =
                     serde_json::from_str(&req.text().await?)?;
+                let trace = TraceId::from_request(&req);
                 let this = ctx.data;
                 match this
                     .vote_on_post_v3(
@@ -1387,6 +1691,7 @@ impl DurableObject for UserHonGameState {
                         req_data.request.direction,
                         req_data.sentiment,
                         req_data.post_creator,
+                        &trace,
                     )
                     .await
                 {
@@ -1397,6 +1702,7 @@ impl DurableObject for UserHonGameState {
             .post_async("/v4/vote", async |mut req, ctx| {
                 let req_data: VoteRequestWithSentimentV4 =
                     serde_json::from_str(&req.text().await?)?;
+                let trace = TraceId::from_request(&req);
                 let this = ctx.data;
                 match this
                     .vote_on_post_v3(
@@ -1406,6 +1712,7 @@ impl DurableObject for UserHonGameState {
                         req_data.request.direction,
                         req_data.sentiment,
                         req_data.post_creator,
+                        &trace,
                     )
                     .await
                 {
@@ -1441,7 +1748,7 @@ impl DurableObject for UserHonGameState {
             .get_async("/ws/balance", |req, ctx| async move {
                 let upgrade = req.headers().get("Upgrade")?;
                 if upgrade.as_deref() != Some("websocket") {
-                    return Response::error("expected websocket", 400);
+                    return error_resp("expected websocket", 400);
                 }
 
                 let pair = WebSocketPair::new()?;
@@ -1455,6 +1762,22 @@ impl DurableObject for UserHonGameState {
             .await
     }
 
+    async fn alarm(&self) -> Result<Response> {
+        // independent tasks, one failing must not hold up the others
+        if let Err(e) = self.drain_analytics().await {
+            console_error!("failed to drain analytics: {e}");
+            let retry_at = Date::now().as_millis() + ANALYTICS_RETRY_DELAY_MS;
+            if let Err(e) = self.schedule_alarm(retry_at).await {
+                console_error!("failed to schedule analytics retry: {e}");
+            }
+        }
+        if let Err(e) = self.cleanup_idempotency_keys().await {
+            console_error!("failed to clean up idempotency keys: {e}");
+        }
+
+        Response::ok("done")
+    }
+
     async fn websocket_message(
         &self,
         ws: WebSocket,
@@ -1,5 +1,11 @@
+use worker_utils::jwt::{JwtPolicy, EXP_ROLLOUT_GRACE_UNTIL};
+
 pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
 MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
 -----END PUBLIC KEY-----";
 
 pub const JWT_AUD: &str = "hot-or-not-worker";
+
+/// backend services are still rolling out expiring tokens, see `EXP_ROLLOUT_GRACE_UNTIL`
+pub const JWT_POLICY: JwtPolicy =
+    JwtPolicy::expiring(60).with_missing_exp_grace(EXP_ROLLOUT_GRACE_UNTIL);
@@ -0,0 +1,133 @@
+use candid::Principal;
+use num_bigint::{BigInt, BigUint};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use worker::{Date, Result};
+use worker_utils::{
+    pagination::KeyCursorPager,
+    storage::{SafeStorage, StorageCell},
+};
+
+const LEDGER_PREFIX: &str = "sats-ledger-";
+
+pub const MAX_TRANSACTIONS_PAGE_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SatsLedgerReason {
+    Vote,
+    Airdrop,
+    ReferralSignup,
+    ReferralReward,
+    CreatorReward,
+    ExternalUpdate,
+    /// the balance reset of the last schema upgrade
+    SchemaReset,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SatsLedgerEntry {
+    /// unix millis
+    pub timestamp: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delta: BigInt,
+    #[serde_as(as = "DisplayFromStr")]
+    pub resulting_balance: BigUint,
+    pub reason: SatsLedgerReason,
+    /// the other side, e.g. the post's creator for votes or the referee for referrals
+    pub counterparty: Option<Principal>,
+    pub post_id: Option<String>,
+}
+
+impl SatsLedgerEntry {
+    pub fn new(reason: SatsLedgerReason, delta: BigInt, resulting_balance: BigUint) -> Self {
+        Self {
+            timestamp: Date::now().as_millis(),
+            delta,
+            resulting_balance,
+            reason,
+            counterparty: None,
+            post_id: None,
+        }
+    }
+
+    pub fn with_counterparty(mut self, counterparty: Option<Principal>) -> Self {
+        self.counterparty = counterparty;
+        self
+    }
+
+    pub fn with_post_id(mut self, post_id: Option<String>) -> Self {
+        self.post_id = post_id;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionsReq {
+    pub page_size: usize,
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionsRes {
+    pub transactions: Vec<SatsLedgerEntry>,
+    pub next: Option<String>,
+}
+
+/// append only log of sats balance mutations
+pub struct SatsLedger {
+    next_seq: StorageCell<u64>,
+}
+
+impl Default for SatsLedger {
+    fn default() -> Self {
+        Self {
+            next_seq: StorageCell::new("sats_ledger_next_seq", || 0),
+        }
+    }
+}
+
+impl SatsLedger {
+    fn entry_key(seq: u64) -> String {
+        // zero padded so keys sort by sequence
+        format!("{LEDGER_PREFIX}{seq:020}")
+    }
+
+    pub async fn append(
+        &mut self,
+        storage: &mut SafeStorage,
+        entry: &SatsLedgerEntry,
+    ) -> Result<()> {
+        let seq = *self.next_seq.read(storage).await?;
+        storage.put(Self::entry_key(seq), entry).await?;
+        self.next_seq.set(storage, seq + 1).await
+    }
+
+    pub fn invalidate(&mut self) {
+        self.next_seq.invalidate();
+    }
+
+    /// newest entries first
+    pub async fn page(
+        &self,
+        storage: &SafeStorage,
+        page_size: usize,
+        cursor: Option<String>,
+    ) -> Result<TransactionsRes> {
+        let page = KeyCursorPager::new(LEDGER_PREFIX)
+            .reversed()
+            .page(
+                storage,
+                page_size.clamp(1, MAX_TRANSACTIONS_PAGE_SIZE),
+                cursor.as_deref(),
+                |_, entry: SatsLedgerEntry| entry,
+            )
+            .await?;
+
+        Ok(TransactionsRes {
+            transactions: page.items,
+            next: page.next,
+        })
+    }
+}
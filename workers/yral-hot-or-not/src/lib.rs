@@ -1,15 +1,18 @@
 mod admin_cans;
+mod analytics;
 mod backend_impl;
 mod consts;
 mod hon_game;
 mod jwt;
+mod ledger;
 mod migrate;
-mod notification;
 mod referral;
 mod treasury;
+mod vote;
 
 use backend_impl::{StateBackend, UserStateBackendImpl};
 use candid::Principal;
+use consts::VOTER_HEADER;
 use hon_worker_common::{
     hon_game_vote_msg, hon_game_vote_msg_v3, hon_game_vote_msg_v4, hon_referral_msg,
     AirdropClaimError, GameInfoReq, GameInfoReqV3, GameInfoReqV4, HoNGameVoteReq, HoNGameVoteReqV3,
@@ -17,12 +20,33 @@ use hon_worker_common::{
     SatsBalanceUpdateRequest, SatsBalanceUpdateRequestV2, VerifiableClaimRequest,
     VoteRequestWithSentiment, VoteRequestWithSentimentV3, VoteRequestWithSentimentV4, WorkerError,
 };
-use jwt::{JWT_AUD, JWT_PUBKEY};
-use notification::{NotificationClient, NotificationType};
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
+use ledger::TransactionsReq;
 use serde_json::json;
 use std::result::Result as StdResult;
 use worker::*;
-use worker_utils::{err_to_resp, jwt::verify_jwt_from_header, parse_principal, RequestInitBuilder};
+use worker_utils::{
+    analytics::{AnalyticsEvent, ANALYTICS_EVENTS_QUEUE},
+    api_error::error_resp,
+    cors::cors_for_env,
+    do_client::DoClient,
+    err_to_resp,
+    error_report::ErrorReporter,
+    flags::Flag,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    notification::{Notification, NotificationJob, NOTIFICATIONS_QUEUE},
+    principals, require_flag, require_low_risk,
+    risk::{RiskSignal, DEVICE_ID_HEADER, RISK_SERVICE, RISK_SIGNALS_QUEUE},
+    secrets::SecretSet,
+    signed_req::{self, InvalidSignature, Signed},
+    time::now_millis,
+    trace::{with_trace, TraceId},
+    webhooks::{WebhookEvent, WebhookEventData, WEBHOOK_EVENTS_QUEUE},
+    RequestInitBuilder,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -47,6 +71,41 @@ pub struct CkBtcTransferError {
     pub message: String,
 }
 
+/// headroom left under the daily ckBTC withdrawal cap
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TreasuryStatus {
+    #[serde_as(as = "serde_with::DisplayFromStr")]
+    pub remaining: num_bigint::BigUint,
+    /// unix millis
+    pub reset_at: u64,
+}
+
+/// a balance update request with an optional idempotency key
+/// retries carrying the same key replay the first outcome instead of applying the delta again
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdempotentUpdate<T> {
+    #[serde(flatten)]
+    pub request: T,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+/// stored result of an update carrying an idempotency key
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize)]
+pub enum SatsUpdateOutcome {
+    Ok(#[serde_as(as = "serde_with::DisplayFromStr")] num_bigint::BigUint),
+    Err { code: u16, error: WorkerError },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MemoizedSatsUpdate {
+    pub outcome: SatsUpdateOutcome,
+    /// unix millis
+    pub created_at: u64,
+}
+
 // User games count types
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserGamesCountResponse {
@@ -54,370 +113,379 @@ pub struct UserGamesCountResponse {
     pub user_principal: String,
 }
 
-fn cors_policy() -> Cors {
-    Cors::new()
-        .with_origins(["*"])
-        .with_methods([Method::Head, Method::Get, Method::Post, Method::Options])
-        .with_allowed_headers(vec!["*"])
-        .with_max_age(86400)
-}
-
 fn verify_hon_game_req(
     sender: Principal,
     req: &HoNGameVoteReq,
 ) -> StdResult<(), (u16, WorkerError)> {
-    let msg = hon_game_vote_msg(req.request.clone());
-
-    req.signature
-        .clone()
-        .verify_identity(sender, msg)
-        .map_err(|_| (401, WorkerError::InvalidSignature))?;
-
-    Ok(())
+    signed_req::verify(&Signed {
+        sender,
+        signature: &req.signature,
+        message: hon_game_vote_msg(req.request.clone()),
+    })
+    .map_err(|_| (InvalidSignature::STATUS, WorkerError::InvalidSignature))
 }
 
 fn verify_hon_game_req_v3(
     sender: Principal,
     req: &HoNGameVoteReqV3,
 ) -> StdResult<(), (u16, WorkerError)> {
-    let msg = hon_game_vote_msg_v3(req.request.clone());
-
-    req.signature
-        .clone()
-        .verify_identity(sender, msg)
-        .map_err(|_| (401, WorkerError::InvalidSignature))?;
-
-    Ok(())
+    signed_req::verify(&Signed {
+        sender,
+        signature: &req.signature,
+        message: hon_game_vote_msg_v3(req.request.clone()),
+    })
+    .map_err(|_| (InvalidSignature::STATUS, WorkerError::InvalidSignature))
 }
 
 fn verify_hon_game_req_v4(
     sender: Principal,
     req: &HoNGameVoteReqV4,
 ) -> StdResult<(), (u16, WorkerError)> {
-    let msg = hon_game_vote_msg_v4(req.request.clone());
-
-    req.signature
-        .clone()
-        .verify_identity(sender, msg)
-        .map_err(|_| (401, WorkerError::InvalidSignature))?;
-
-    Ok(())
+    signed_req::verify(&Signed {
+        sender,
+        signature: &req.signature,
+        message: hon_game_vote_msg_v4(req.request.clone()),
+    })
+    .map_err(|_| (InvalidSignature::STATUS, WorkerError::InvalidSignature))
 }
 
 fn verify_airdrop_claim_req(
     req: &VerifiableClaimRequest,
 ) -> StdResult<(), (u16, AirdropClaimError)> {
-    let msg = hon_worker_common::verifiable_claim_request_message(req.request.clone());
-
-    req.signature
-        .clone()
-        .verify_identity(req.sender, msg)
-        .map_err(|_| (401, AirdropClaimError::InvalidSignature))?;
-
-    Ok(())
+    signed_req::verify(&Signed {
+        sender: req.sender,
+        signature: &req.signature,
+        message: hon_worker_common::verifiable_claim_request_message(req.request.clone()),
+    })
+    .map_err(|_| {
+        (
+            InvalidSignature::STATUS,
+            AirdropClaimError::InvalidSignature,
+        )
+    })
 }
 
 fn verify_hon_referral_req(req: &ReferralReqWithSignature) -> StdResult<(), (u16, WorkerError)> {
-    let msg = hon_referral_msg(req.request.clone());
-
-    req.signature
-        .clone()
-        .verify_identity(req.request.referee, msg)
-        .map_err(|_| (401, WorkerError::InvalidSignature))?;
-
-    Ok(())
-}
-
-fn get_hon_game_stub<T>(ctx: &RouteContext<T>, user_principal: Principal) -> Result<Stub> {
-    let game_ns = ctx.durable_object("USER_HON_GAME_STATE")?;
-    let game_state_obj = game_ns.id_from_name(&user_principal.to_text())?;
-    let game_stub = game_state_obj.get_stub()?;
-
-    Ok(game_stub)
+    signed_req::verify(&Signed {
+        sender: req.request.referee,
+        signature: &req.signature,
+        message: hon_referral_msg(req.request.clone()),
+    })
+    .map_err(|_| (InvalidSignature::STATUS, WorkerError::InvalidSignature))
+}
+
+const USER_HON_GAME_STATE: &str = "USER_HON_GAME_STATE";
+
+/// sends `signals` to yral-risk, best effort
+async fn report_risk(env: &Env, signals: &[RiskSignal]) {
+    for signal in signals {
+        if let Err(e) = signal.send(env).await {
+            console_warn!("failed to send {} risk signal: {e}", signal.kind());
+        }
+    }
 }
 
-fn get_hon_game_stub_env(env: &Env, user_principal: Principal) -> Result<Stub> {
-    let game_ns = env.durable_object("USER_HON_GAME_STATE")?;
-    let game_state_obj = game_ns.id_from_name(&user_principal.to_text())?;
-    let game_stub = game_state_obj.get_stub()?;
-
-    Ok(game_stub)
+/// vote velocity, and the device if the client sent one
+async fn report_vote_risk(
+    env: &Env,
+    res: &Response,
+    user_principal: Principal,
+    device_id: Option<String>,
+) {
+    if res.status_code() >= 300 {
+        return;
+    }
+    let user_principal = user_principal.to_text();
+    let at = now_millis();
+    let mut signals = vec![RiskSignal::Vote {
+        user_principal: user_principal.clone(),
+        at,
+    }];
+    if let Some(device_id) = device_id {
+        signals.push(RiskSignal::Device {
+            user_principal,
+            device_id,
+            at,
+        });
+    }
+    report_risk(env, &signals).await;
 }
 
 async fn place_hot_or_not_vote(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
+    let trace = TraceId::from_request(&req);
+    let device_id = req.headers().get(DEVICE_ID_HEADER)?;
 
-    let req: HoNGameVoteReq = serde_json::from_str(&req.text().await?)?;
+    let req: HoNGameVoteReq = json_body!(req);
     if let Err((code, err)) = verify_hon_game_req(user_principal, &req) {
         return err_to_resp(code, err);
     };
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
     let req = VoteRequestWithSentiment {
         request: req.request,
         sentiment: req.fetched_sentiment,
         post_creator: req.post_creator,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/vote",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .with_trace(&trace)
+        .with_name_header(VOTER_HEADER)
+        .post(USER_HON_GAME_STATE, &user_principal.to_text(), "vote", &req)
+        .await?;
+    report_vote_risk(&ctx.env, &res, user_principal, device_id).await;
 
     Ok(res)
 }
 
 async fn place_hot_or_not_vote_v2(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
+    let trace = TraceId::from_request(&req);
+    let device_id = req.headers().get(DEVICE_ID_HEADER)?;
 
-    let req: HoNGameVoteReq = serde_json::from_str(&req.text().await?)?;
+    let req: HoNGameVoteReq = json_body!(req);
     if let Err((code, err)) = verify_hon_game_req(user_principal, &req) {
         return err_to_resp(code, err);
     };
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
     let req = VoteRequestWithSentiment {
         request: req.request,
         sentiment: req.fetched_sentiment,
         post_creator: req.post_creator,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/vote_v2",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .with_trace(&trace)
+        .with_name_header(VOTER_HEADER)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "vote_v2",
+            &req,
+        )
+        .await?;
+    report_vote_risk(&ctx.env, &res, user_principal, device_id).await;
 
     Ok(res)
 }
 
+/// kill switches for the vote endpoints, see worker-utils/src/flags.rs
+static V3_VOTE: Flag = Flag::new("hon_v3_vote").default_on();
+static V4_VOTE: Flag = Flag::new("hon_v4_vote").default_on();
+
 async fn place_hot_or_not_vote_v3(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
+    require_flag!(ctx.env, V3_VOTE, user_principal);
+    let trace = TraceId::from_request(&req);
+    let device_id = req.headers().get(DEVICE_ID_HEADER)?;
 
-    let req: HoNGameVoteReqV3 = serde_json::from_str(&req.text().await?)?;
+    let req: HoNGameVoteReqV3 = json_body!(req);
     if let Err((code, err)) = verify_hon_game_req_v3(user_principal, &req) {
         return err_to_resp(code, err);
     };
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
     let req = VoteRequestWithSentimentV3 {
         request: req.request,
         sentiment: req.fetched_sentiment,
         post_creator: req.post_creator,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/v3/vote",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .with_trace(&trace)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v3/vote",
+            &req,
+        )
+        .await?;
+    report_vote_risk(&ctx.env, &res, user_principal, device_id).await;
 
     Ok(res)
 }
 
 async fn place_hot_or_not_vote_v4(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
+    require_flag!(ctx.env, V4_VOTE, user_principal);
+    let trace = TraceId::from_request(&req);
+    let device_id = req.headers().get(DEVICE_ID_HEADER)?;
 
-    let req: HoNGameVoteReqV4 = serde_json::from_str(&req.text().await?)?;
+    let req: HoNGameVoteReqV4 = json_body!(req);
     if let Err((code, err)) = verify_hon_game_req_v4(user_principal, &req) {
         return err_to_resp(code, err);
     };
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
     let req = VoteRequestWithSentimentV4 {
         request: req.request,
         sentiment: req.fetched_sentiment,
         post_creator: req.post_creator,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/v4/vote",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .with_trace(&trace)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v4/vote",
+            &req,
+        )
+        .await?;
+    report_vote_risk(&ctx.env, &res, user_principal, device_id).await;
 
     Ok(res)
 }
 
 async fn user_sats_balance(ctx: RouteContext<()>, use_v2: bool) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let user_principal = principals!(ctx, "user_principal");
 
     let endpoint = if use_v2 { "v2/balance" } else { "balance" };
 
-    let res = game_stub
-        .fetch_with_str(&format!("http://fake_url.com/{endpoint}"))
+    let res = DoClient::new(&ctx.env)
+        .get(USER_HON_GAME_STATE, &user_principal.to_text(), endpoint)
         .await?;
 
     Ok(res)
 }
 
 async fn last_airdrop_claimed_at(ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let user_principal = principals!(ctx, "user_principal");
 
-    let res = game_stub
-        .fetch_with_str("http://fake_url.com/last_airdrop_claimed_at")
+    let res = DoClient::new(&ctx.env)
+        .get(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "last_airdrop_claimed_at",
+        )
         .await?;
 
     Ok(res)
 }
 
 async fn game_info(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
-    let req_data: GameInfoReq = req.json().await?;
+    let user_principal = principals!(ctx, "user_principal");
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/game_info",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
+    let req_data: GameInfoReq = json_body!(req);
 
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "game_info",
+            &req_data,
+        )
+        .await?;
 
     Ok(res)
 }
 
 async fn paginated_games(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let req_data: PaginatedGamesReq = json_body!(req);
 
-    let req_data: PaginatedGamesReq = req.json().await?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/games",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "games",
+            &req_data,
+        )
+        .await?;
 
     Ok(res)
 }
 
 async fn game_info_v3(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let req_data: GameInfoReqV3 = json_body!(req);
 
-    let req_data: GameInfoReqV3 = req.json().await?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/v3/game_info",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v3/game_info",
+            &req_data,
+        )
+        .await?;
 
     Ok(res)
 }
 
 async fn game_info_v4(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let req_data: GameInfoReqV4 = json_body!(req);
 
-    let req_data: GameInfoReqV4 = req.json().await?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/v4/game_info",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v4/game_info",
+            &req_data,
+        )
+        .await?;
 
     Ok(res)
 }
 
 async fn paginated_games_v3(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let req_data: PaginatedGamesReq = json_body!(req);
 
-    let req_data: PaginatedGamesReq = req.json().await?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/v3/games",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v3/games",
+            &req_data,
+        )
+        .await?;
 
     Ok(res)
 }
 
 async fn paginated_games_v4(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let user_principal = principals!(ctx, "user_principal");
 
-    let req_data: PaginatedGamesReq = req.json().await?;
+    let req_data: PaginatedGamesReq = json_body!(req);
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/v4/games",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v4/games",
+            &req_data,
+        )
+        .await?;
 
     Ok(res)
 }
@@ -434,36 +502,36 @@ async fn paginated_games_v4(mut req: Request, ctx: RouteContext<()>) -> Result<R
 // }
 
 async fn claim_airdrop(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
-    let req: VerifiableClaimRequest = serde_json::from_str(&req.text().await?)?;
+    let req: VerifiableClaimRequest = json_body!(req);
     if let Err(e) = verify_airdrop_claim_req(&req) {
         return err_to_resp(e.0, e.1);
     }
 
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/claim_airdrop",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req.amount)?
-            .build(),
-    )?;
-
-    let res = game_stub.fetch_with_request(req).await?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "claim_airdrop",
+            &req.amount,
+        )
+        .await?;
 
     Ok(res)
 }
 
 // async fn withdraw_sats(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-//     if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-//         return Response::error(msg, code);
+//     if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), JWT_POLICY, &req) {
+//         return error_resp(msg, code);
 //     };
-//     let req: HoNGameWithdrawReq = serde_json::from_str(&req.text().await?)?;
+//     let req: HoNGameWithdrawReq = json_body!(req);
 //     if let Err(e) = verify_hon_withdraw_req(&req) {
 //         return err_to_resp(e.0, e.1);
 //     }
@@ -484,11 +552,14 @@ async fn claim_airdrop(mut req: Request, ctx: RouteContext<()>) -> Result<Respon
 // }
 
 async fn referral_reward(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let req_with_sig: ReferralReqWithSignature = serde_json::from_str(&req.text().await?)?;
+    let req_with_sig: ReferralReqWithSignature = json_body!(req);
     if let Err((code, err)) = verify_hon_referral_req(&req_with_sig) {
         return err_to_resp(code, err);
     }
@@ -506,17 +577,14 @@ async fn referral_reward(mut req: Request, ctx: RouteContext<()>) -> Result<Resp
         );
     }
 
-    let referee_game_stub = get_hon_game_stub(&ctx, req.referee)?;
-    let add_referee_signup_reward_req = Request::new_with_init(
-        "http://fake_url.com/add_referee_signup_reward_v2",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
-
-    let mut add_referee_signup_reward_res = referee_game_stub
-        .fetch_with_request(add_referee_signup_reward_req)
+    let hon_game = DoClient::new(&ctx.env);
+    let mut add_referee_signup_reward_res = hon_game
+        .post(
+            USER_HON_GAME_STATE,
+            &req.referee.to_text(),
+            "add_referee_signup_reward_v2",
+            &req,
+        )
         .await?;
     if add_referee_signup_reward_res.status_code() != 200 {
         return err_to_resp(
@@ -525,17 +593,13 @@ async fn referral_reward(mut req: Request, ctx: RouteContext<()>) -> Result<Resp
         );
     }
 
-    let referrer_game_stub = get_hon_game_stub(&ctx, req.referrer)?;
-    let add_referrer_reward_req = Request::new_with_init(
-        "http://fake_url.com/add_referrer_reward_v2",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
-
-    let mut add_referrer_reward_res = referrer_game_stub
-        .fetch_with_request(add_referrer_reward_req)
+    let mut add_referrer_reward_res = hon_game
+        .post(
+            USER_HON_GAME_STATE,
+            &req.referrer.to_text(),
+            "add_referrer_reward_v2",
+            &req,
+        )
         .await?;
     if add_referrer_reward_res.status_code() != 200 {
         return err_to_resp(
@@ -544,20 +608,51 @@ async fn referral_reward(mut req: Request, ctx: RouteContext<()>) -> Result<Resp
         );
     }
 
-    let notif_client = NotificationClient::new(
-        ctx.env
-            .secret("YRAL_METADATA_USER_NOTIFICATION_API_KEY")?
-            .to_string(),
+    let job = NotificationJob::new(
+        req.referrer,
+        Notification::ReferralReward {
+            referee_principal: req.referee,
+            amount: req.amount,
+        },
     );
-    notif_client
-        .send_notification(
-            NotificationType::ReferrerReferralReward {
-                referee_principal: req.referee,
-                amount: req.amount,
-            },
-            Some(req.referrer),
-        )
-        .await;
+    if let Err(e) = job.enqueue(&ctx.env).await {
+        console_error!("failed to queue referral reward notification: {e}");
+    }
+    let event = WebhookEvent::new(WebhookEventData::ReferralCredited {
+        user_principal: req.referrer.to_text(),
+        referee_principal: req.referee.to_text(),
+        amount: req.amount,
+    });
+    if let Err(e) = event.send(&ctx.env).await {
+        console_error!("failed to send referral webhook event: {e}");
+    }
+    let at = now_millis();
+    let events = [
+        AnalyticsEvent::Signup {
+            user_principal: req.referee.to_text(),
+            referrer_principal: Some(req.referrer.to_text()),
+            at,
+            trace_id: None,
+        },
+        AnalyticsEvent::Referral {
+            user_principal: req.referrer.to_text(),
+            referee_principal: req.referee.to_text(),
+            amount: req.amount,
+            at,
+            trace_id: None,
+        },
+    ];
+    for event in events {
+        if let Err(e) = event.send(&ctx.env).await {
+            console_error!("failed to send {} analytics event: {e}", event.kind());
+        }
+    }
+    let signal = RiskSignal::Referral {
+        user_principal: req.referrer.to_text(),
+        referee: req.referee.to_text(),
+        at,
+    };
+    report_risk(&ctx.env, &[signal]).await;
 
     // send sample success response
     let res = Response::from_json(&json!({
@@ -569,106 +664,123 @@ async fn referral_reward(mut req: Request, ctx: RouteContext<()>) -> Result<Resp
 }
 
 async fn referral_paginated_history(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let req: PaginatedReferralsReq = json_body!(req);
 
-    let req: PaginatedReferralsReq = serde_json::from_str(&req.text().await?)?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "referral_history",
+            &req,
+        )
+        .await?;
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/referral_history",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req)?
-            .build(),
-    )?;
+    Ok(res)
+}
 
-    let res = game_stub.fetch_with_request(req).await?;
+async fn sats_transactions(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
 
-    Ok(res)
+    let req: TransactionsReq = json_body!(req);
+
+    DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "transactions",
+            &req,
+        )
+        .await
 }
 
 async fn update_sats_balance(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
+    let user_principal = principals!(ctx, "user_principal");
 
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
+    let req_data: IdempotentUpdate<SatsBalanceUpdateRequest> = json_body!(req);
 
-    let req_data: SatsBalanceUpdateRequest = serde_json::from_str(&req.text().await?)?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/update_balance",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    game_stub.fetch_with_request(req).await
+    DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "update_balance",
+            &req_data,
+        )
+        .await
 }
 
 async fn update_sats_balance_v2(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
-    let user_principal = parse_principal!(ctx, "user_principal");
-    let game_stub = get_hon_game_stub_env(&ctx.env, user_principal)?;
+    let user_principal = principals!(ctx, "user_principal");
+    let req_data: IdempotentUpdate<SatsBalanceUpdateRequestV2> = json_body!(req);
 
-    let req_data: SatsBalanceUpdateRequestV2 = serde_json::from_str(&req.text().await?)?;
-
-    let req = Request::new_with_init(
-        "http://fake_url.com/v2/update_balance",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
-
-    game_stub.fetch_with_request(req).await
+    DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v2/update_balance",
+            &req_data,
+        )
+        .await
 }
 
 async fn migrate_games(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     }
-    let user_principal = parse_principal!(ctx, "user_principal");
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-    let req = Request::new_with_init(
-        "http://fake_url.com/migrate",
-        RequestInitBuilder::default().method(Method::Post).build(),
-    )?;
-    game_stub.fetch_with_request(req).await
+    let user_principal = principals!(ctx, "user_principal");
+    DoClient::new(&ctx.env)
+        .fetch(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "migrate",
+            RequestInitBuilder::default().method(Method::Post),
+        )
+        .await
 }
 
 async fn estabilish_balance_ws(ctx: RouteContext<()>) -> Result<Response> {
-    let user_principal = parse_principal!(ctx, "user_principal");
-    let game_stub = get_hon_game_stub(&ctx, user_principal)?;
-
-    let headers = Headers::new();
-    headers.set("Upgrade", "websocket")?;
-    let new_req = Request::new_with_init(
-        "http://fake_url.com/ws/balance",
-        RequestInitBuilder::default()
-            .method(Method::Get)
-            .replace_headers(headers)
-            .build(),
-    )?;
-
-    game_stub.fetch_with_request(new_req).await
+    let user_principal = principals!(ctx, "user_principal");
+    DoClient::new(&ctx.env)
+        .fetch(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "ws/balance",
+            RequestInitBuilder::default()
+                .method(Method::Get)
+                .header("Upgrade", "websocket")?,
+        )
+        .await
 }
 
 async fn transfer_ckbtc_reward(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // JWT verification
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     };
 
     // Parse request body
-    let req_data: CkBtcTransferRequest = serde_json::from_str(&req.text().await?)?;
+    let req_data: CkBtcTransferRequest = json_body!(req);
 
     // Determine which durable object to use based on recipient_principal
     let user_principal = if let Some(recipient_principal_str) =
@@ -680,47 +792,124 @@ async fn transfer_ckbtc_reward(mut req: Request, ctx: RouteContext<()>) -> Resul
     } else {
         // If no recipient provided, this endpoint needs a way to determine the user
         // For now, return an error requiring recipient_principal
-        return Response::error("recipient_principal is required in the request body", 400);
+        return error_resp("recipient_principal is required in the request body", 400);
     };
 
-    // Get durable object stub for the user
-    let game_stub = get_hon_game_stub_env(&ctx.env, user_principal)?;
+    require_low_risk!(ctx.env, user_principal);
 
     // Forward to durable object
-    let req = Request::new_with_init(
-        "http://fake_url.com/v2/transfer_ckbtc",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&req_data)?
-            .build(),
-    )?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "v2/transfer_ckbtc",
+            &req_data,
+        )
+        .await?;
+    if res.status_code() < 300 {
+        let signal = RiskSignal::Withdrawal {
+            user_principal: user_principal.to_text(),
+            economy: "sats".into(),
+            amount: req_data.amount.to_string(),
+            at: now_millis(),
+        };
+        report_risk(&ctx.env, &[signal]).await;
+
+        let job = NotificationJob::new(
+            user_principal,
+            Notification::WithdrawalCompleted {
+                amount: req_data.amount.to_string(),
+                token: "SATS".into(),
+            },
+        );
+        if let Err(e) = job.enqueue(&ctx.env).await {
+            console_error!("failed to queue withdrawal notification: {e}");
+        }
+
+        let event = WebhookEvent::new(WebhookEventData::WithdrawalCompleted {
+            user_principal: user_principal.to_text(),
+            amount: req_data.amount.to_string(),
+            token: "SATS".into(),
+            withdrawal_id: None,
+        });
+        if let Err(e) = event.send(&ctx.env).await {
+            console_error!("failed to send withdrawal webhook event: {e}");
+        }
+    }
+
+    Ok(res)
+}
 
-    game_stub.fetch_with_request(req).await
+async fn treasury_status(ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+
+    DoClient::new(&ctx.env)
+        .get(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            "treasury_status",
+        )
+        .await
 }
 
 async fn user_games_count(ctx: RouteContext<()>) -> Result<Response> {
     // Parse user principal
-    let user_principal = parse_principal!(ctx, "user_principal");
-
-    // Get durable object stub
-    let game_stub = get_hon_game_stub_env(&ctx.env, user_principal)?;
+    let user_principal = principals!(ctx, "user_principal");
 
     // Forward to durable object with principal in URL
-    let req = Request::new_with_init(
-        &format!("http://fake_url.com/games/count/{}", user_principal),
-        RequestInitBuilder::default().method(Method::Get).build(),
-    )?;
-
-    game_stub.fetch_with_request(req).await
+    DoClient::new(&ctx.env)
+        .get(
+            USER_HON_GAME_STATE,
+            &user_principal.to_text(),
+            &format!("games/count/{user_principal}"),
+        )
+        .await
 }
 
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-hot-or-not",
+    &[
+        Dependency::DurableObject(USER_HON_GAME_STATE),
+        Dependency::Queue(ANALYTICS_EVENTS_QUEUE),
+        Dependency::Queue(NOTIFICATIONS_QUEUE),
+        Dependency::Queue(RISK_SIGNALS_QUEUE),
+        Dependency::Queue(WEBHOOK_EVENTS_QUEUE),
+        Dependency::Service(RISK_SERVICE),
+        Dependency::Secret("BACKEND_ADMIN_KEY"),
+    ],
+);
+
+const REQUIRED_SECRETS: &[&str] = &["BACKEND_ADMIN_KEY"];
+
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    console_error_panic_hook::set_once();
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let reporter = ErrorReporter::install(&env, "yral-hot-or-not");
+
+    if let Err(e) = SecretSet::validate(&env, REQUIRED_SECRETS) {
+        return e.into_response();
+    }
+
+    let (req, trace) = with_trace(req)?;
+    let reporter = reporter.with_request(&req);
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-hot-or-not", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
 
     let router = Router::new();
 
     let res = router
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .get_async("/balance/:user_principal", |_req, ctx| {
             user_sats_balance(ctx, false)
         })
@@ -754,6 +943,9 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         .get_async("/games/count/:user_principal", |_req, ctx| {
             user_games_count(ctx)
         })
+        .get_async("/treasury_status/:user_principal", |_req, ctx| {
+            treasury_status(ctx)
+        })
         .post_async("/claim_airdrop/:user_principal", |req, ctx| {
             claim_airdrop(req, ctx)
         })
@@ -767,6 +959,7 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
             "/referral_history/:user_principal",
             referral_paginated_history,
         )
+        .post_async("/transactions/:user_principal", sats_transactions)
         .post_async("/update_balance/:user_principal", update_sats_balance)
         .post_async("/v2/update_balance/:user_principal", update_sats_balance_v2)
         .post_async("/v2/transfer_ckbtc", transfer_ckbtc_reward)
@@ -776,7 +969,8 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         })
         .options("/*catchall", |_, _| Response::empty())
         .run(req, env)
-        .await?;
+        .await;
+    let res = reporter.check(&ctx, res)?;
 
-    res.with_cors(&cors_policy())
+    cors.apply(&path, origin.as_deref(), trace.tag(res)?)
 }
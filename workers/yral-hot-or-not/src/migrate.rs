@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use worker::*;
+use worker_utils::environment::profile_config;
 use yral_metadata_client::MetadataClient;
 
 use crate::hon_game::UserHonGameState;
@@ -20,7 +21,9 @@ impl UserHonGameState {
         let games = self.games.borrow().as_ref().unwrap().clone();
         let canister_ids: Vec<_> = games.keys().map(|(canister_id, _)| *canister_id).collect();
 
-        let metadata_client: MetadataClient<false> = MetadataClient::default();
+        let metadata_url = profile_config(&self.env).metadata_server_url;
+        let metadata_client: MetadataClient<false> =
+            MetadataClient::with_base_url(metadata_url.parse().unwrap());
         let principals = metadata_client
             .get_canister_to_principal_bulk(canister_ids)
             .await
@@ -36,14 +39,16 @@ impl UserHonGameState {
             }
         }
 
-        for ((principal_id, post_id), game_info) in games_by_user_principal {
-            storage
-                .put(
-                    &format!("games_by_user_principal-{principal_id}-{post_id}"),
-                    &game_info,
-                )
-                .await?;
-        }
+        storage
+            .put_multiple(games_by_user_principal.iter().map(
+                |((principal_id, post_id), game_info)| {
+                    (
+                        format!("games_by_user_principal-{principal_id}-{post_id}"),
+                        game_info,
+                    )
+                },
+            ))
+            .await?;
 
         self.schema_version
             .borrow_mut()
@@ -23,6 +23,10 @@ impl ReferralStore {
         Ok(self.0.as_mut().unwrap())
     }
 
+    pub fn invalidate(&mut self) {
+        self.0 = None;
+    }
+
     pub async fn referral_history(
         &mut self,
         storage: &mut SafeStorage,
@@ -1,18 +1,15 @@
 use candid::{Nat, Principal};
 use enum_dispatch::enum_dispatch;
 use hon_worker_common::WorkerError;
-use ic_agent::identity::Secp256k1Identity;
 use worker::{console_log, Env};
 use worker_utils::{
-    environment::{env_kind, RunEnv},
-    icp::agent_wrapper::AgentWrapper,
+    environment::{profile_config, TreasuryKind},
+    icp::agent_wrapper::{identity_from_pem, AgentConfig, AgentWrapper},
 };
 use yral_canisters_client::sns_ledger::{
     Account, SnsLedger, TransferArg, TransferError, TransferResult,
 };
 
-use crate::consts::CKBTC_LEDGER;
-
 #[allow(unused)]
 #[enum_dispatch]
 pub(crate) trait CkBtcTreasury {
@@ -38,16 +35,20 @@ impl CkBtcTreasury for NoOpCkBtcTreasury {
 }
 
 #[allow(unused)]
-pub struct AdminCkBtcTreasury(AgentWrapper);
+pub struct AdminCkBtcTreasury {
+    agent: AgentWrapper,
+    ledger: Principal,
+}
 
 impl AdminCkBtcTreasury {
     pub fn new(env: &Env) -> Result<Self, worker::Error> {
         let admin_pem = env.secret("BACKEND_ADMIN_KEY")?.to_string();
-        let id = Secp256k1Identity::from_pem(admin_pem.as_bytes())
-            .map_err(|e| worker::Error::RustError(e.to_string()))?;
-        let agent = AgentWrapper::new(id);
+        let id = identity_from_pem(&admin_pem)?;
 
-        Ok(Self(agent))
+        Ok(Self {
+            agent: AgentWrapper::with_config(id, AgentConfig::from_env(env)),
+            ledger: profile_config(env).ckbtc_ledger,
+        })
     }
 }
 
@@ -58,23 +59,27 @@ impl CkBtcTreasury for AdminCkBtcTreasury {
         amount: Nat,
         memo_text: Option<String>,
     ) -> Result<(), (u16, WorkerError)> {
-        console_log!("ledger: {}; to: {}", CKBTC_LEDGER.to_text(), to.to_text());
-        let ledger = SnsLedger(CKBTC_LEDGER, self.0.get().await);
+        console_log!("ledger: {}; to: {}", self.ledger.to_text(), to.to_text());
+        let ledger = SnsLedger(self.ledger, self.agent.get().await);
 
         let memo = memo_text.unwrap_or_else(|| "Memo not specified".to_string());
 
-        let res = ledger
-            .icrc_1_transfer(TransferArg {
-                to: Account {
-                    owner: to,
-                    subaccount: None,
-                },
-                fee: None,
-                memo: Some(Vec::from(memo).into()),
-                from_subaccount: None,
-                created_at_time: None,
-                amount: amount.clone(),
-            })
+        let res = self
+            .agent
+            .guarded(
+                self.ledger,
+                ledger.icrc_1_transfer(TransferArg {
+                    to: Account {
+                        owner: to,
+                        subaccount: None,
+                    },
+                    fee: None,
+                    memo: Some(Vec::from(memo).into()),
+                    from_subaccount: None,
+                    created_at_time: None,
+                    amount: amount.clone(),
+                }),
+            )
             .await
             .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
         match res {
@@ -99,9 +104,9 @@ pub enum CkBtcTreasuryImpl {
 
 impl CkBtcTreasuryImpl {
     pub fn new(env: &Env) -> Result<Self, worker::Error> {
-        let this = match env_kind() {
-            RunEnv::Remote => Self::Real(AdminCkBtcTreasury::new(env)?),
-            _ => Self::Mock(NoOpCkBtcTreasury),
+        let this = match profile_config(env).treasury {
+            TreasuryKind::Admin => Self::Real(AdminCkBtcTreasury::new(env)?),
+            TreasuryKind::NoOp => Self::Mock(NoOpCkBtcTreasury),
         };
 
         Ok(this)
@@ -0,0 +1,176 @@
+//! the part of a vote that settles against the voter's balance, shared by every vote route
+
+use std::result::Result as StdResult;
+
+use global_constants::MAX_BET_AMOUNT_SATS;
+use hon_worker_common::{GameResult, HotOrNot, WorkerError};
+use num_bigint::BigUint;
+use worker_utils::storage::{balance::BalanceEngine, batch::WriteBatch, SafeStorage};
+
+use crate::hon_game::Sats;
+
+/// bets above the cap are played as the cap
+pub(crate) fn capped_bet(vote_amount: u128) -> u128 {
+    vote_amount.min(MAX_BET_AMOUNT_SATS as u128)
+}
+
+/// plays `vote_amount` against `balance`, `None` if the balance can't cover it
+/// a right call wins 80% of the bet, at least 1 sat, a wrong one loses the bet
+pub(crate) fn play(
+    balance: &mut BigUint,
+    vote_amount: u128,
+    direction: HotOrNot,
+    sentiment: HotOrNot,
+) -> Option<GameResult> {
+    let vote_amount = BigUint::from(vote_amount);
+    if *balance < vote_amount {
+        return None;
+    }
+    if sentiment != direction {
+        *balance -= vote_amount.clone();
+        return Some(GameResult::Loss {
+            lose_amt: vote_amount,
+        });
+    }
+
+    let mut win_amt = (vote_amount * 8u32) / 10u32;
+    if win_amt == BigUint::ZERO {
+        win_amt = BigUint::from(1u32);
+    }
+    *balance += win_amt.clone();
+
+    Some(GameResult::Win { win_amt })
+}
+
+/// stages the vote's balance update in `batch`, returning the result and the new balance
+pub(crate) async fn stage_vote(
+    sats: &mut BalanceEngine<Sats>,
+    storage: &SafeStorage,
+    batch: &mut WriteBatch,
+    vote_amount: u128,
+    direction: HotOrNot,
+    sentiment: HotOrNot,
+) -> StdResult<(GameResult, BigUint), (u16, WorkerError)> {
+    let mut res = None;
+    sats.update_in(storage, batch, |balance| {
+        res = play(balance, vote_amount, direction, sentiment)
+            .map(|game_res| (game_res, balance.clone()))
+    })
+    .await
+    .map_err(|_| {
+        (
+            500,
+            WorkerError::Internal("failed to update balance".into()),
+        )
+    })?;
+
+    res.ok_or((400, WorkerError::InsufficientFunds))
+}
+
+#[cfg(test)]
+mod tests {
+    use global_constants::{
+        MAX_CREDITED_PER_DAY_PER_USER_SATS, MAX_DEDUCTED_PER_DAY_PER_USER_SATS,
+    };
+    use serde::{Deserialize, Serialize};
+    use worker_utils::{
+        storage::balance::DailyLimits,
+        testing::{block_on, DoHarness},
+    };
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Vote {
+        amount: u64,
+        direction: HotOrNot,
+        sentiment: HotOrNot,
+    }
+
+    fn sats() -> BalanceEngine<Sats> {
+        BalanceEngine::new(DailyLimits {
+            max_credited_per_day: MAX_CREDITED_PER_DAY_PER_USER_SATS,
+            max_deducted_per_day: MAX_DEDUCTED_PER_DAY_PER_USER_SATS,
+        })
+    }
+
+    /// what a vote route does with the balance, committed like the route commits it
+    async fn vote(
+        storage: &mut SafeStorage,
+        vote: Vote,
+    ) -> StdResult<(GameResult, BigUint), (u16, WorkerError)> {
+        let mut batch = storage.batch();
+        let res = stage_vote(
+            &mut sats(),
+            storage,
+            &mut batch,
+            capped_bet(vote.amount as u128),
+            vote.direction,
+            vote.sentiment,
+        )
+        .await?;
+        batch
+            .commit(storage)
+            .await
+            .map_err(|e| (500, WorkerError::Internal(e.to_string())))?;
+
+        Ok(res)
+    }
+
+    #[test]
+    fn right_call_wins_most_of_the_bet() {
+        let mut balance = BigUint::from(100u32);
+        let res = play(&mut balance, 10, HotOrNot::Hot, HotOrNot::Hot);
+        assert!(matches!(res, Some(GameResult::Win { win_amt }) if win_amt == BigUint::from(8u32)));
+        assert_eq!(balance, BigUint::from(108u32));
+
+        let res = play(&mut balance, 1, HotOrNot::Not, HotOrNot::Not);
+        assert!(matches!(res, Some(GameResult::Win { win_amt }) if win_amt == BigUint::from(1u32)));
+    }
+
+    #[test]
+    fn wrong_call_loses_the_bet() {
+        let mut balance = BigUint::from(100u32);
+        let res = play(&mut balance, 10, HotOrNot::Hot, HotOrNot::Not);
+        assert!(
+            matches!(res, Some(GameResult::Loss { lose_amt }) if lose_amt == BigUint::from(10u32))
+        );
+        assert_eq!(balance, BigUint::from(90u32));
+    }
+
+    #[test]
+    fn votes_settle_against_the_stored_balance() {
+        let mut harness = DoHarness::default();
+        block_on(sats().set(&mut harness.storage, BigUint::from(100u32))).unwrap();
+        let losing = Vote {
+            amount: 60,
+            direction: HotOrNot::Hot,
+            sentiment: HotOrNot::Not,
+        };
+
+        let (_, balance) = harness.call(&losing, vote).unwrap();
+        assert_eq!(balance, BigUint::from(40u32));
+
+        let res = harness.call(&losing, vote);
+        assert!(matches!(res, Err((400, WorkerError::InsufficientFunds))));
+        let stored = block_on(sats().balance(&harness.storage)).unwrap();
+        assert_eq!(stored, BigUint::from(40u32));
+    }
+
+    #[test]
+    fn bets_are_capped() {
+        let mut harness = DoHarness::default();
+        let balance = BigUint::from(MAX_BET_AMOUNT_SATS) * 2u32;
+        block_on(sats().set(&mut harness.storage, balance)).unwrap();
+        let huge = Vote {
+            amount: u64::MAX,
+            direction: HotOrNot::Hot,
+            sentiment: HotOrNot::Not,
+        };
+
+        let (game_res, _) = harness.call(&huge, vote).unwrap();
+        assert!(
+            matches!(game_res, GameResult::Loss { lose_amt } if lose_amt == BigUint::from(MAX_BET_AMOUNT_SATS))
+        );
+    }
+}
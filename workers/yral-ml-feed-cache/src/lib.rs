@@ -1,5 +1,14 @@
 use serde::{Deserialize, Serialize};
 use worker::*;
+use worker_utils::{
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+};
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-ml-feed-cache",
+    &[Dependency::Kv("yral-ml-feed-cache")],
+);
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Country {
@@ -16,10 +25,22 @@ pub struct CustomMlFeedCacheItem {
 
 #[event(fetch)]
 async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let (method, path) = (req.method(), req.path());
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-ml-feed-cache", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
     let router = Router::new();
 
     router
         .get("/", |_, _| Response::ok("Hello from Workers cache!"))
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .post_async("/feed-cache/:canister_id", |mut req, ctx| async move {
             let canister_id = ctx.param("canister_id").unwrap();
             let new_items: Vec<CustomMlFeedCacheItem> = req.json().await.unwrap_or_default();
@@ -0,0 +1,45 @@
+-- one case per flagged post, every flag on the post is folded into it
+CREATE TABLE IF NOT EXISTS moderation_cases (
+    -- `{user_canister}:{post_id}`
+    id TEXT PRIMARY KEY,
+    user_canister TEXT NOT NULL,
+    post_id INTEGER NOT NULL,
+    video_uid TEXT,
+    creator_principal TEXT,
+    -- pending, approved or rejected
+    status TEXT NOT NULL,
+    -- highest score of the NSFW gate, null if it never flagged the post
+    nsfw_score REAL,
+    reports INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    -- JWT subject of the reviewer
+    reviewed_by TEXT,
+    review_note TEXT,
+    reviewed_at INTEGER
+);
+
+-- review queue, oldest first
+CREATE INDEX IF NOT EXISTS moderation_cases_status ON moderation_cases (status);
+
+-- user reports, one per reporter and post
+CREATE TABLE IF NOT EXISTS moderation_reports (
+    case_id TEXT NOT NULL,
+    reporter_principal TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (case_id, reporter_principal)
+);
+
+-- what a rejection fans out to, one row per action
+CREATE TABLE IF NOT EXISTS moderation_actions (
+    case_id TEXT NOT NULL,
+    action TEXT NOT NULL,
+    -- pending, succeeded, failed or skipped
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- what was done, or the last error
+    detail TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (case_id, action)
+);
@@ -0,0 +1,282 @@
+//! what a rejection takes down, one queue message per action so each action is retried on its own
+
+use std::time::Duration;
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::*;
+use worker_utils::{
+    notification::{Notification, NotificationJob},
+    RequestInitBuilder,
+};
+
+use crate::cases::{case, num, CaseRow};
+
+pub const MODERATION_ACTIONS_QUEUE: &str = "MODERATION_ACTIONS";
+
+pub const UPLOAD_VIDEO_SERVICE: &str = "YRAL_UPLOAD_VIDEO";
+
+const STREAM_API_BASE: &str = "https://api.cloudflare.com/client/v4/accounts";
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// marks the post banned on its canister, through yral-upload-video's `/ban_post`
+    BanPost,
+    DeleteStreamAsset,
+    NotifyCreator,
+}
+
+impl Action {
+    pub const ALL: [Action; 3] = [Self::BanPost, Self::DeleteStreamAsset, Self::NotifyCreator];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::BanPost => "ban_post",
+            Self::DeleteStreamAsset => "delete_stream_asset",
+            Self::NotifyCreator => "notify_creator",
+        }
+    }
+
+    /// nothing to act on with what the flags provided
+    pub fn skips(self, case: &CaseRow) -> bool {
+        match self {
+            Self::BanPost => false,
+            Self::DeleteStreamAsset => case.video_uid.is_none(),
+            Self::NotifyCreator => case.creator_principal.is_none(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionStatus {
+    Pending,
+    Succeeded,
+    /// the last attempt failed, the queue retries it until it's dead lettered
+    Failed,
+    Skipped,
+}
+
+impl ActionStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionRow {
+    pub action: Action,
+    pub status: ActionStatus,
+    pub attempts: u32,
+    pub detail: Option<String>,
+    /// unix millis
+    pub updated_at: u64,
+}
+
+/// one action of a rejected case, the case is read back from d1
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionMessage {
+    pub case_id: String,
+    pub action: Action,
+}
+
+/// enqueues every action of `case` that isn't skipped
+pub async fn enqueue_actions(env: &Env, case: &CaseRow) -> Result<()> {
+    let queue = env.queue(MODERATION_ACTIONS_QUEUE)?;
+    for action in Action::ALL {
+        if action.skips(case) {
+            continue;
+        }
+        queue
+            .send(&ActionMessage {
+                case_id: case.id.clone(),
+                action,
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn expect_ok(mut res: Response, what: &str) -> Result<()> {
+    if (200..300).contains(&res.status_code()) {
+        return Ok(());
+    }
+    let body = res.text().await.unwrap_or_default();
+    Err(Error::RustError(format!(
+        "{what} responded with {}: {body}",
+        res.status_code()
+    )))
+}
+
+/// what a post points at, as recorded on its canister
+#[derive(Deserialize, Clone, Debug)]
+pub struct PostDetails {
+    pub video_uid: String,
+    pub creator_principal: Principal,
+}
+
+#[derive(Deserialize)]
+struct PostDetailsResponse {
+    success: bool,
+    data: Option<PostDetails>,
+}
+
+/// looks the post up through yral-upload-video's `/post_details`, `None` if it doesn't exist
+pub async fn post_details(
+    env: &Env,
+    user_canister: Principal,
+    post_id: u64,
+) -> Result<Option<PostDetails>> {
+    let auth_token = env.secret("OFF_CHAIN_GRPC_AUTH_TOKEN")?.to_string();
+
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Post)
+        .header("Authorization", &format!("Bearer {auth_token}"))?
+        .json(&json!({
+            "canister_id": user_canister,
+            "post_id": post_id,
+        }))?;
+    let req = init.request(&format!("https://{UPLOAD_VIDEO_SERVICE}/post_details"))?;
+    let mut res = env
+        .service(UPLOAD_VIDEO_SERVICE)?
+        .fetch_request(req)
+        .await?;
+    // 4xx are failed lookups of posts that don't exist
+    if res.status_code() >= 500 {
+        expect_ok(res, "yral-upload-video").await?;
+        return Ok(None);
+    }
+    let body: PostDetailsResponse = res.json().await?;
+    if !body.success {
+        return Ok(None);
+    }
+
+    Ok(body.data)
+}
+
+async fn ban_post(env: &Env, case: &CaseRow) -> Result<String> {
+    let auth_token = env.secret("OFF_CHAIN_GRPC_AUTH_TOKEN")?.to_string();
+    // posts only users reported are banned as such, anything the NSFW gate caught as explicit
+    let reason = if case.nsfw_score.is_some() {
+        "explicit"
+    } else {
+        "user_reports"
+    };
+
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Post)
+        .header("Authorization", &format!("Bearer {auth_token}"))?
+        .json(&json!({
+            "canister_id": case.user_canister,
+            "post_id": case.post_id,
+            "reason": reason,
+        }))?;
+    let req = init.request(&format!("https://{UPLOAD_VIDEO_SERVICE}/ban_post"))?;
+    let res = env
+        .service(UPLOAD_VIDEO_SERVICE)?
+        .fetch_request(req)
+        .await?;
+    expect_ok(res, "yral-upload-video").await?;
+
+    Ok(format!("banned for {reason}"))
+}
+
+/// an already deleted video counts as deleted
+async fn delete_stream_asset(env: &Env, video_uid: &str) -> Result<String> {
+    let account_id = env.secret("CLOUDFLARE_STREAM_ACCOUNT_ID")?.to_string();
+    let api_token = env.secret("CLOUDFLARE_STREAM_API_TOKEN")?.to_string();
+
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Delete)
+        .header("Authorization", &format!("Bearer {api_token}"))?
+        .timeout(UPSTREAM_TIMEOUT);
+    let res = init
+        .fetch(&format!(
+            "{STREAM_API_BASE}/{account_id}/stream/{video_uid}"
+        ))
+        .await?;
+    if res.status_code() != 404 {
+        expect_ok(res, "cloudflare stream").await?;
+    }
+
+    Ok(format!("deleted {video_uid}"))
+}
+
+async fn notify_creator(env: &Env, case: &CaseRow, creator_principal: &str) -> Result<String> {
+    let user_principal = Principal::from_text(creator_principal)
+        .map_err(|e| Error::RustError(format!("invalid creator principal: {e}")))?;
+    NotificationJob::new(
+        user_principal,
+        Notification::PostRemoved {
+            post_id: case.post_id.to_string(),
+        },
+    )
+    .enqueue(env)
+    .await?;
+
+    Ok("notification queued".into())
+}
+
+async fn record_action(
+    db: &D1Database,
+    case_id: &str,
+    action: Action,
+    status: ActionStatus,
+    detail: &str,
+    now: u64,
+) -> Result<()> {
+    db.prepare(
+        "UPDATE moderation_actions SET status = ?3, attempts = attempts + 1, detail = ?4, \
+        updated_at = ?5 WHERE case_id = ?1 AND action = ?2",
+    )
+    .bind(&[
+        case_id.into(),
+        action.as_str().into(),
+        status.as_str().into(),
+        detail.into(),
+        num(now),
+    ])?
+    .run()
+    .await?;
+
+    Ok(())
+}
+
+/// runs one action and records its outcome
+///
+/// errors are recorded on the action before they're returned, the caller retries the message
+pub async fn run_action(env: &Env, db: &D1Database, msg: &ActionMessage, now: u64) -> Result<()> {
+    let Some(case) = case(db, &msg.case_id).await? else {
+        console_warn!(
+            "dropping action {} of unknown case {}",
+            msg.action.as_str(),
+            msg.case_id
+        );
+        return Ok(());
+    };
+
+    let res = match (msg.action, &case.video_uid, &case.creator_principal) {
+        (Action::BanPost, _, _) => ban_post(env, &case).await,
+        (Action::DeleteStreamAsset, Some(video_uid), _) => {
+            delete_stream_asset(env, video_uid).await
+        }
+        (Action::NotifyCreator, _, Some(creator)) => notify_creator(env, &case, creator).await,
+        _ => return Ok(()),
+    };
+    let (status, detail) = match &res {
+        Ok(detail) => (ActionStatus::Succeeded, detail.clone()),
+        Err(e) => (ActionStatus::Failed, e.to_string()),
+    };
+    record_action(db, &case.id, msg.action, status, &detail, now).await?;
+
+    res.map(|_| ())
+}
@@ -0,0 +1,258 @@
+//! review cases, one per flagged post
+
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::moderation::{FlagSource, ModerationFlag};
+
+use crate::actions::{Action, ActionRow, ActionStatus};
+
+pub const MODERATION_DB: &str = "MODERATION_DB";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseStatus {
+    Pending,
+    /// the post stays up
+    Approved,
+    /// the post is taken down, see `Action`
+    Rejected,
+}
+
+impl CaseStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaseRow {
+    /// insertion order, the cursor of `GET /cases`
+    pub seq: u64,
+    pub id: String,
+    pub user_canister: String,
+    pub post_id: u64,
+    pub video_uid: Option<String>,
+    pub creator_principal: Option<String>,
+    pub status: CaseStatus,
+    /// highest score of the NSFW gate, `None` if only users flagged the post
+    pub nsfw_score: Option<f64>,
+    pub reports: u32,
+    /// unix millis
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub reviewed_by: Option<String>,
+    pub review_note: Option<String>,
+    pub reviewed_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReportRow {
+    pub reporter_principal: String,
+    pub reason: String,
+    pub created_at: u64,
+}
+
+/// response of `GET /cases/:case_id`
+#[derive(Serialize, Clone, Debug)]
+pub struct CaseDetail {
+    #[serde(flatten)]
+    pub case: CaseRow,
+    pub reports: Vec<ReportRow>,
+    pub actions: Vec<ActionRow>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CasesQuery {
+    pub status: Option<CaseStatus>,
+    /// `seq` of the last case of the previous page
+    pub after: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+pub(crate) fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+fn opt_str(v: Option<&str>) -> JsValue {
+    v.map(JsValue::from).unwrap_or(JsValue::NULL)
+}
+
+/// folds every flag into its post's case, redelivered flags change nothing
+///
+/// reviewed cases keep their status, a new flag doesn't reopen them
+/// video and creator are looked up server side for every flag, the latest one wins
+pub async fn record_flags(db: &D1Database, flags: &[ModerationFlag], now: u64) -> Result<()> {
+    let mut statements = vec![];
+    for flag in flags {
+        let case_id = flag.case_id();
+        let nsfw_score = match &flag.source {
+            FlagSource::Nsfw { score } => JsValue::from_f64(*score),
+            FlagSource::UserReport { .. } => JsValue::NULL,
+        };
+        statements.push(
+            db.prepare(
+                "INSERT INTO moderation_cases \
+                (id, user_canister, post_id, video_uid, creator_principal, status, nsfw_score, \
+                created_at, updated_at) \
+                VALUES (?1, ?2, ?3, ?4, ?5, 'pending', ?6, ?7, ?7) \
+                ON CONFLICT (id) DO UPDATE SET \
+                video_uid = COALESCE(excluded.video_uid, video_uid), \
+                creator_principal = COALESCE(excluded.creator_principal, creator_principal), \
+                nsfw_score = MAX(COALESCE(nsfw_score, excluded.nsfw_score), \
+                COALESCE(excluded.nsfw_score, nsfw_score)), \
+                updated_at = excluded.updated_at",
+            )
+            .bind(&[
+                case_id.as_str().into(),
+                flag.user_canister.as_str().into(),
+                num(flag.post_id),
+                opt_str(flag.video_uid.as_deref()),
+                opt_str(flag.creator_principal.as_deref()),
+                nsfw_score,
+                num(now),
+            ])?,
+        );
+
+        let FlagSource::UserReport {
+            reporter_principal,
+            reason,
+        } = &flag.source
+        else {
+            continue;
+        };
+        statements.push(
+            db.prepare(
+                "INSERT OR IGNORE INTO moderation_reports \
+                (case_id, reporter_principal, reason, created_at) VALUES (?1, ?2, ?3, ?4)",
+            )
+            .bind(&[
+                case_id.as_str().into(),
+                reporter_principal.as_str().into(),
+                reason.as_str().into(),
+                num(flag.at),
+            ])?,
+        );
+        statements.push(
+            db.prepare(
+                "UPDATE moderation_cases SET reports = \
+                (SELECT COUNT(*) FROM moderation_reports WHERE case_id = ?1) WHERE id = ?1",
+            )
+            .bind(&[case_id.as_str().into()])?,
+        );
+    }
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+/// oldest first, so the review queue drains in the order posts were flagged
+pub async fn list_cases(
+    db: &D1Database,
+    status: CaseStatus,
+    after: u64,
+    limit: u32,
+) -> Result<Vec<CaseRow>> {
+    db.prepare(
+        "SELECT rowid AS seq, * FROM moderation_cases WHERE status = ?1 AND rowid > ?2 \
+        ORDER BY rowid LIMIT ?3",
+    )
+    .bind(&[status.as_str().into(), num(after), limit.into()])?
+    .all()
+    .await?
+    .results()
+}
+
+pub async fn case(db: &D1Database, case_id: &str) -> Result<Option<CaseRow>> {
+    db.prepare("SELECT rowid AS seq, * FROM moderation_cases WHERE id = ?1")
+        .bind(&[case_id.into()])?
+        .first(None)
+        .await
+}
+
+pub async fn case_detail(db: &D1Database, case_id: &str) -> Result<Option<CaseDetail>> {
+    let Some(case) = case(db, case_id).await? else {
+        return Ok(None);
+    };
+    let reports = db
+        .prepare(
+            "SELECT reporter_principal, reason, created_at FROM moderation_reports \
+            WHERE case_id = ?1 ORDER BY created_at",
+        )
+        .bind(&[case_id.into()])?
+        .all()
+        .await?
+        .results()?;
+    let mut actions: Vec<ActionRow> = db
+        .prepare(
+            "SELECT action, status, attempts, detail, updated_at FROM moderation_actions \
+            WHERE case_id = ?1",
+        )
+        .bind(&[case_id.into()])?
+        .all()
+        .await?
+        .results()?;
+    actions.sort_by_key(|a| Action::ALL.iter().position(|action| *action == a.action));
+
+    Ok(Some(CaseDetail {
+        case,
+        reports,
+        actions,
+    }))
+}
+
+/// settles a pending case, `false` if it was already reviewed
+///
+/// a rejection records its actions in the same batch, the caller enqueues the pending ones
+pub async fn review(
+    db: &D1Database,
+    case: &CaseRow,
+    status: CaseStatus,
+    reviewer: &str,
+    note: Option<&str>,
+    now: u64,
+) -> Result<bool> {
+    let mut statements = vec![db
+        .prepare(
+            "UPDATE moderation_cases SET status = ?2, reviewed_by = ?3, review_note = ?4, \
+            reviewed_at = ?5, updated_at = ?5 WHERE id = ?1 AND status = 'pending' RETURNING id",
+        )
+        .bind(&[
+            case.id.as_str().into(),
+            status.as_str().into(),
+            reviewer.into(),
+            opt_str(note),
+            num(now),
+        ])?];
+    if status == CaseStatus::Rejected {
+        for action in Action::ALL {
+            let action_status = if action.skips(case) {
+                ActionStatus::Skipped
+            } else {
+                ActionStatus::Pending
+            };
+            statements.push(
+                db.prepare(
+                    "INSERT OR IGNORE INTO moderation_actions (case_id, action, status, updated_at) \
+                    SELECT id, ?2, ?3, ?4 FROM moderation_cases WHERE id = ?1 AND status = 'rejected'",
+                )
+                .bind(&[
+                    case.id.as_str().into(),
+                    action.as_str().into(),
+                    action_status.as_str().into(),
+                    num(now),
+                ])?,
+            );
+        }
+    }
+    let results = db.batch(statements).await?;
+    let updated = match results.first() {
+        Some(res) => !res.results::<serde_json::Value>()?.is_empty(),
+        None => false,
+    };
+
+    Ok(updated)
+}
@@ -0,0 +1,336 @@
+mod actions;
+mod cases;
+mod jwt;
+
+use actions::{enqueue_actions, post_details, run_action, ActionMessage, MODERATION_ACTIONS_QUEUE};
+use candid::Principal;
+use cases::{
+    case, case_detail, list_cases, record_flags, review, CaseStatus, CasesQuery, MODERATION_DB,
+};
+use jwt::JWT_PUBKEY;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{
+    api_error::{error_resp, ApiError},
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::{authorize, JwtKeys, Role},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    moderation::{FlagSource, ModerationFlag, MODERATION_FLAGS_QUEUE},
+    signed_req::{self, InvalidSignature, SignedMessage},
+    time::now_millis,
+};
+use yral_identity::{msg_builder::Message as IdentityMessage, Signature};
+
+/// queue names, as reported by `MessageBatch::queue`
+const FLAGS_QUEUE_NAME: &str = "yral-moderation-flags";
+const ACTIONS_QUEUE_NAME: &str = "yral-moderation-actions";
+
+const MAX_REASON_LEN: usize = 500;
+
+const DEFAULT_CASES_LIMIT: u32 = 50;
+const MAX_CASES_LIMIT: u32 = 200;
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-moderation",
+    &[
+        Dependency::D1(MODERATION_DB),
+        Dependency::Queue(MODERATION_FLAGS_QUEUE),
+        Dependency::Queue(MODERATION_ACTIONS_QUEUE),
+        Dependency::Service(actions::UPLOAD_VIDEO_SERVICE),
+        Dependency::Secret("OFF_CHAIN_GRPC_AUTH_TOKEN"),
+        Dependency::Secret("CLOUDFLARE_STREAM_API_TOKEN"),
+    ],
+);
+
+macro_rules! reviewer {
+    ($ctx:expr, $req:expr) => {
+        match authorize(
+            &JwtKeys::from_env(&$ctx.env, JWT_PUBKEY),
+            &$req,
+            "Authorization",
+            Role::Moderator,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        }
+    };
+}
+
+/// body of `POST /report`, signed by the reporting user
+/// the post's video and creator are looked up, not taken from the reporter
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReportRequest {
+    pub sender: Principal,
+    pub user_canister: Principal,
+    pub post_id: u64,
+    pub reason: String,
+    pub signature: Signature,
+}
+
+fn report_msg(user_canister: Principal, post_id: u64, reason: String) -> IdentityMessage {
+    IdentityMessage::default()
+        .method_name("yral_moderation_report".into())
+        .args((user_canister, post_id, reason))
+        .expect("report request should serialize")
+}
+
+impl SignedMessage for ReportRequest {
+    fn sender(&self) -> Principal {
+        self.sender
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn message(&self) -> IdentityMessage {
+        report_msg(self.user_canister, self.post_id, self.reason.clone())
+    }
+}
+
+/// body of `POST /cases/:case_id/approve` and `/reject`
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ReviewRequest {
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// flags the post for review, repeated reports by the same user count once
+async fn report_post(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let body: ReportRequest = json_body!(req);
+    if let Err(e) = signed_req::verify(&body) {
+        return e.into_response();
+    }
+    let reason = body.reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_LEN {
+        return error_resp(
+            format!("reason must be between 1 and {MAX_REASON_LEN} characters"),
+            400,
+        );
+    }
+
+    let Some(post) = post_details(&ctx.env, body.user_canister, body.post_id).await? else {
+        return error_resp("post not found", 404);
+    };
+
+    let flag = ModerationFlag {
+        user_canister: body.user_canister.to_text(),
+        post_id: body.post_id,
+        video_uid: Some(post.video_uid),
+        creator_principal: Some(post.creator_principal.to_text()),
+        source: FlagSource::UserReport {
+            reporter_principal: body.sender.to_text(),
+            reason: reason.to_string(),
+        },
+        at: now_millis(),
+    };
+    flag.send(&ctx.env).await?;
+
+    Ok(Response::from_json(&json!({ "case_id": flag.case_id() }))?.with_status(202))
+}
+
+/// `GET /cases?status=&after=&limit=`, pending cases by default
+async fn cases(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    reviewer!(ctx, req);
+    let Ok(query) = req.query::<CasesQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+    let status = query.status.unwrap_or(CaseStatus::Pending);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_CASES_LIMIT)
+        .min(MAX_CASES_LIMIT);
+
+    let db = ctx.env.d1(MODERATION_DB)?;
+    let cases = list_cases(&db, status, query.after.unwrap_or(0), limit).await?;
+    // a short page is the last one
+    let next = if cases.len() == limit as usize {
+        cases.last().map(|c| c.seq)
+    } else {
+        None
+    };
+
+    Response::from_json(&json!({ "cases": cases, "next": next }))
+}
+
+async fn get_case(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    reviewer!(ctx, req);
+    let Some(case_id) = ctx.param("case_id") else {
+        return error_resp("case_id is required", 400);
+    };
+
+    let db = ctx.env.d1(MODERATION_DB)?;
+    match case_detail(&db, case_id).await? {
+        Some(detail) => Response::from_json(&detail),
+        None => error_resp("case not found", 404),
+    }
+}
+
+/// settles a pending case, a rejection takes the post down
+async fn review_case(
+    mut req: Request,
+    ctx: RouteContext<()>,
+    status: CaseStatus,
+) -> Result<Response> {
+    let reviewer = reviewer!(ctx, req);
+    let Some(case_id) = ctx.param("case_id").cloned() else {
+        return error_resp("case_id is required", 400);
+    };
+    let body: ReviewRequest = json_body!(req);
+
+    let db = ctx.env.d1(MODERATION_DB)?;
+    let Some(case) = case(&db, &case_id).await? else {
+        return error_resp("case not found", 404);
+    };
+    let note = body
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if !review(&db, &case, status, &reviewer.sub, note, now_millis()).await? {
+        return ApiError::new("AlreadyReviewed", "case was already reviewed")
+            .with_details(json!({ "status": case.status }))
+            .into_response(409);
+    }
+    if status == CaseStatus::Rejected {
+        enqueue_actions(&ctx.env, &case).await?;
+    }
+    Metrics::new(&ctx.env, "yral-moderation").counter("moderation_reviews", &[status.as_str()]);
+
+    match case_detail(&db, &case_id).await? {
+        Some(detail) => Response::from_json(&detail),
+        None => error_resp("case not found", 404),
+    }
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    // cases are listed and decided by moderators
+    if let Some(notice) = MaintenanceNotice::check(
+        &env,
+        "yral-moderation",
+        method.as_ref(),
+        &path,
+        &["/cases/"],
+    )
+    .await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .post_async("/report", report_post)
+        .get_async("/cases", cases)
+        .get_async("/cases/:case_id", get_case)
+        .post_async("/cases/:case_id/approve", |req, ctx| {
+            review_case(req, ctx, CaseStatus::Approved)
+        })
+        .post_async("/cases/:case_id/reject", |req, ctx| {
+            review_case(req, ctx, CaseStatus::Rejected)
+        })
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// folds the whole batch of flags into cases at once, they're idempotent so a failure retries all
+async fn record_flag_batch(batch: MessageBatch<Value>, env: &Env) -> Result<()> {
+    let metrics = Metrics::new(env, "yral-moderation");
+    let flags = batch
+        .messages()?
+        .into_iter()
+        .filter_map(|message| {
+            serde_json::from_value::<ModerationFlag>(message.body().clone())
+                .inspect_err(|e| console_error!("dropping malformed moderation flag: {e}"))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    if flags.is_empty() {
+        batch.ack_all();
+        return Ok(());
+    }
+
+    let db = env.d1(MODERATION_DB)?;
+    match record_flags(&db, &flags, now_millis()).await {
+        Ok(()) => {
+            for flag in &flags {
+                metrics.counter("moderation_flags", &[flag.source.kind()]);
+            }
+            batch.ack_all();
+        }
+        Err(e) => {
+            console_error!("failed to record {} moderation flags: {e}", flags.len());
+            batch.retry_all();
+        }
+    }
+
+    Ok(())
+}
+
+/// runs actions one by one, a failed action is retried without holding back the others
+async fn run_action_batch(batch: MessageBatch<Value>, env: &Env) -> Result<()> {
+    let db = env.d1(MODERATION_DB)?;
+    let metrics = Metrics::new(env, "yral-moderation");
+    for message in batch.messages()? {
+        let msg = match serde_json::from_value::<ActionMessage>(message.body().clone()) {
+            Ok(msg) => msg,
+            Err(e) => {
+                console_error!("dropping malformed moderation action {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+        match run_action(env, &db, &msg, now_millis()).await {
+            Ok(()) => {
+                metrics.counter("moderation_actions", &[msg.action.as_str(), "succeeded"]);
+                message.ack();
+            }
+            Err(e) => {
+                console_error!(
+                    "moderation action {} of case {} failed: {e}",
+                    msg.action.as_str(),
+                    msg.case_id
+                );
+                metrics.counter("moderation_actions", &[msg.action.as_str(), "failed"]);
+                message.retry();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    match batch.queue().as_str() {
+        FLAGS_QUEUE_NAME => record_flag_batch(batch, &env).await,
+        ACTIONS_QUEUE_NAME => run_action_batch(batch, &env).await,
+        queue => {
+            console_error!("unexpected queue {queue}");
+            batch.retry_all();
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,24 @@
+-- per user notification preferences, users without a row get every notification
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user_principal TEXT PRIMARY KEY,
+    -- json encoded `NotificationPrefs`
+    prefs TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- outcome of every notification job, keyed by queue message id
+CREATE TABLE IF NOT EXISTS notification_deliveries (
+    id TEXT PRIMARY KEY,
+    user_principal TEXT NOT NULL,
+    notification_type TEXT NOT NULL,
+    -- delivered, suppressed or failed
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    error TEXT,
+    trace_id TEXT,
+    created_at INTEGER NOT NULL,
+    processed_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS notification_deliveries_principal_processed_at
+    ON notification_deliveries (user_principal, processed_at);
@@ -0,0 +1,204 @@
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::{wasm_bindgen::JsValue, D1Database, D1PreparedStatement, Result};
+use worker_utils::{
+    notification::{Locale, NotificationJob},
+    trace::TRACE_HEADER,
+};
+
+/// a user's pending jobs, merged into a single push
+pub struct Digest {
+    pub title: String,
+    pub body: String,
+    pub deeplink: Option<&'static str>,
+}
+
+impl Digest {
+    /// `jobs` must not be empty
+    pub fn of(jobs: &[&NotificationJob], locale: Locale) -> Self {
+        if let [job] = jobs {
+            return Self {
+                title: job.notification.title_in(locale),
+                body: job.notification.body_in(locale),
+                deeplink: job.notification.deeplink(),
+            };
+        }
+
+        let deeplink = jobs[0].notification.deeplink();
+        let same_deeplink = jobs.iter().all(|j| j.notification.deeplink() == deeplink);
+        Self {
+            title: locale.digest_title(jobs.len()),
+            body: jobs
+                .iter()
+                .map(|j| j.notification.body_in(locale))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            deeplink: deeplink.filter(|_| same_deeplink),
+        }
+    }
+}
+
+/// pushes through yral-metadata, which owns the users' device tokens
+pub struct MetadataPush {
+    api_key: String,
+    /// the profile's metadata server, see `worker_utils::environment::ProfileConfig`
+    base_url: &'static str,
+    client: reqwest::Client,
+}
+
+impl MetadataPush {
+    pub fn new(api_key: String, base_url: &'static str) -> Self {
+        Self {
+            api_key,
+            base_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn send(
+        &self,
+        user: Principal,
+        digest: &Digest,
+        trace_id: Option<&str>,
+    ) -> std::result::Result<(), String> {
+        let url = format!("{}/notifications/{}/send", self.base_url, user.to_text());
+        let mut data = json!({
+            "title": digest.title,
+            "body": digest.body,
+        });
+        if let Some(deeplink) = digest.deeplink {
+            data["deeplink"] = deeplink.into();
+        }
+
+        let mut req = self
+            .client
+            .post(&url)
+            .bearer_auth(&self.api_key)
+            .json(&json!({ "data": data }));
+        if let Some(trace_id) = trace_id {
+            req = req.header(TRACE_HEADER, trace_id);
+        }
+        let res = req.send().await.map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            return Err(format!("status {status}: {body}"));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Delivered,
+    /// muted by the user's preferences
+    Suppressed,
+    /// gave up after `MAX_DELIVERY_ATTEMPTS`
+    Failed,
+    /// low priority, held for the user's next digest push
+    Digested,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::Suppressed => "suppressed",
+            Self::Failed => "failed",
+            Self::Digested => "digested",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryOutcome {
+    /// queue message id
+    pub id: String,
+    pub user_principal: String,
+    pub notification_type: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub trace_id: Option<String>,
+    /// unix millis
+    pub created_at: u64,
+    pub processed_at: u64,
+}
+
+impl DeliveryOutcome {
+    pub fn new(
+        id: String,
+        job: &NotificationJob,
+        status: DeliveryStatus,
+        attempts: u32,
+        error: Option<String>,
+        now: u64,
+    ) -> Self {
+        Self {
+            id,
+            user_principal: job.user_principal.to_text(),
+            notification_type: job.notification.kind().to_string(),
+            status,
+            attempts,
+            error,
+            trace_id: job.trace_id.as_ref().map(|t| t.to_string()),
+            created_at: job.created_at,
+            processed_at: now,
+        }
+    }
+}
+
+fn opt_str(v: &Option<String>) -> JsValue {
+    v.as_deref().map(JsValue::from).unwrap_or(JsValue::NULL)
+}
+
+fn insert_statement(db: &D1Database, outcome: &DeliveryOutcome) -> Result<D1PreparedStatement> {
+    db.prepare(
+        "INSERT OR REPLACE INTO notification_deliveries \
+        (id, user_principal, notification_type, status, attempts, error, trace_id, created_at, processed_at) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )
+    .bind(&[
+        outcome.id.as_str().into(),
+        outcome.user_principal.as_str().into(),
+        outcome.notification_type.as_str().into(),
+        outcome.status.as_str().into(),
+        JsValue::from_f64(outcome.attempts as f64),
+        opt_str(&outcome.error),
+        opt_str(&outcome.trace_id),
+        JsValue::from_f64(outcome.created_at as f64),
+        JsValue::from_f64(outcome.processed_at as f64),
+    ])
+}
+
+pub async fn record_outcomes(db: &D1Database, outcomes: &[DeliveryOutcome]) -> Result<()> {
+    if outcomes.is_empty() {
+        return Ok(());
+    }
+    let statements = outcomes
+        .iter()
+        .map(|outcome| insert_statement(db, outcome))
+        .collect::<Result<Vec<_>>>()?;
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+/// newest first
+pub async fn recent_deliveries(
+    db: &D1Database,
+    user: Principal,
+    limit: u32,
+) -> Result<Vec<DeliveryOutcome>> {
+    db.prepare(
+        "SELECT * FROM notification_deliveries WHERE user_principal = ?1 \
+        ORDER BY processed_at DESC LIMIT ?2",
+    )
+    .bind(&[user.to_text().into(), JsValue::from_f64(limit as f64)])?
+    .all()
+    .await?
+    .results()
+}
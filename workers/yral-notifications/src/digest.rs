@@ -0,0 +1,241 @@
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    environment::profile_config,
+    metrics::Metrics,
+    notification::NotificationJob,
+    retry::RetryPolicy,
+    storage::{SafeStorage, StorageCell},
+    time::now_millis,
+};
+
+use crate::{
+    delivery::{record_outcomes, DeliveryOutcome, DeliveryStatus, Digest, MetadataPush},
+    preferences::load_preferences,
+    MAX_DELIVERY_ATTEMPTS, NOTIFICATIONS_DB,
+};
+
+pub const USER_DIGEST: &str = "USER_DIGEST";
+
+const DEFAULT_DIGEST_INTERVAL_MINUTES: u64 = 240;
+
+/// a digest this long is pushed without waiting for the schedule
+const MAX_DIGEST_ENTRIES: usize = 20;
+
+const FLUSH_RETRY_POLICY: RetryPolicy = RetryPolicy::new(MAX_DELIVERY_ATTEMPTS)
+    .with_delays(60_000, 60 * 60 * 1000)
+    .without_jitter();
+
+/// a low priority job waiting for the next digest push
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DigestEntry {
+    /// queue message id, the delivery outcome is recorded under it
+    pub id: String,
+    pub job: NotificationJob,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct DigestState {
+    entries: Vec<DigestEntry>,
+    /// failed pushes of the current entries
+    failed_flushes: u32,
+}
+
+/// low priority notifications of one user, pushed as a single summary on a schedule
+#[durable_object]
+pub struct UserDigest {
+    state: State,
+    env: Env,
+    digest: RefCell<StorageCell<DigestState>>,
+}
+
+// SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+// because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserDigest {
+    fn storage(&self) -> SafeStorage {
+        self.state.storage().into()
+    }
+
+    fn interval_ms(&self) -> u64 {
+        let minutes = self
+            .env
+            .var("NOTIFICATION_DIGEST_INTERVAL_MINUTES")
+            .ok()
+            .and_then(|v| v.to_string().parse().ok())
+            .unwrap_or(DEFAULT_DIGEST_INTERVAL_MINUTES);
+
+        minutes * 60 * 1000
+    }
+
+    /// moves the alarm earlier if `at` (unix millis) is before the scheduled one
+    async fn schedule_flush(&self, at: u64) -> Result<()> {
+        let storage = self.state.storage();
+        let at = at as i64;
+        if let Some(scheduled) = storage.get_alarm().await? {
+            if scheduled <= at {
+                return Ok(());
+            }
+        }
+        let offset = (at - now_millis() as i64).max(0);
+
+        storage.set_alarm(offset).await
+    }
+
+    async fn add(&self, entries: Vec<DigestEntry>) -> Result<()> {
+        let mut storage = self.storage();
+        let mut len = 0;
+        self.digest
+            .borrow_mut()
+            .update(&mut storage, |digest| {
+                for entry in entries {
+                    // redelivered messages keep their id
+                    if digest.entries.iter().all(|e| e.id != entry.id) {
+                        digest.entries.push(entry);
+                    }
+                }
+                len = digest.entries.len();
+            })
+            .await?;
+
+        let now = now_millis();
+        if len >= MAX_DIGEST_ENTRIES {
+            return self.schedule_flush(now).await;
+        }
+        self.schedule_flush(now + self.interval_ms()).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let mut storage = self.storage();
+        let digest = self.digest.borrow_mut().read(&storage).await?.clone();
+        let Some(user) = digest.entries.first().map(|e| e.job.user_principal) else {
+            return Ok(());
+        };
+        let now = now_millis();
+        let db = self.env.d1(NOTIFICATIONS_DB)?;
+        let metrics = Metrics::new(&self.env, "yral-notifications");
+
+        let prefs = load_preferences(&db, user).await?;
+        if let Some(quiet_ms) = prefs.quiet_hours.and_then(|q| q.remaining_ms(now)) {
+            return self.schedule_flush(now + quiet_ms).await;
+        }
+
+        // preferences may have changed since the entries were added
+        let (pending, muted): (Vec<_>, Vec<_>) = digest
+            .entries
+            .iter()
+            .partition(|e| prefs.allows(e.job.notification.category()));
+        let attempts = digest.failed_flushes + 1;
+        let outcome = |entry: &DigestEntry, status, error| {
+            DeliveryOutcome::new(entry.id.clone(), &entry.job, status, attempts, error, now)
+        };
+        let mut outcomes: Vec<_> = muted
+            .iter()
+            .map(|e| outcome(e, DeliveryStatus::Suppressed, None))
+            .collect();
+
+        let res = if pending.is_empty() {
+            Ok(())
+        } else {
+            let push = MetadataPush::new(
+                self.env
+                    .secret("YRAL_METADATA_USER_NOTIFICATION_API_KEY")?
+                    .to_string(),
+                profile_config(&self.env).metadata_server_url,
+            );
+            let jobs: Vec<_> = pending.iter().map(|e| &e.job).collect();
+            let trace_id = jobs.iter().find_map(|j| j.trace_id.as_ref());
+            metrics.histogram(
+                "notification_digest_size",
+                &["scheduled"],
+                jobs.len() as f64,
+            );
+
+            push.send(
+                user,
+                &Digest::of(&jobs, prefs.locale),
+                trace_id.map(|t| t.as_str()),
+            )
+            .await
+        };
+        let (status, error) = match res {
+            Ok(()) => (DeliveryStatus::Delivered, None),
+            Err(e) if attempts < MAX_DELIVERY_ATTEMPTS => {
+                console_warn!("digest push to {user} failed: {e}");
+                self.digest
+                    .borrow_mut()
+                    .update(&mut storage, |digest| digest.failed_flushes = attempts)
+                    .await?;
+                let retry_at = now + FLUSH_RETRY_POLICY.delay_ms(attempts, 0.0);
+                return self.schedule_flush(retry_at).await;
+            }
+            Err(e) => {
+                console_error!("giving up on the digest of {user}: {e}");
+                (DeliveryStatus::Failed, Some(e))
+            }
+        };
+        outcomes.extend(pending.iter().map(|e| outcome(e, status, error.clone())));
+        metrics.count("notifications", &[status.as_str()], pending.len() as u64);
+        metrics.count(
+            "notifications",
+            &[DeliveryStatus::Suppressed.as_str()],
+            muted.len() as u64,
+        );
+
+        // entries added while the push was in flight wait for the next digest
+        let flushed = digest.entries.len();
+        let mut remaining = 0;
+        self.digest
+            .borrow_mut()
+            .update(&mut storage, |digest| {
+                digest.entries.drain(..flushed.min(digest.entries.len()));
+                digest.failed_flushes = 0;
+                remaining = digest.entries.len();
+            })
+            .await?;
+        if remaining > 0 {
+            self.schedule_flush(now + self.interval_ms()).await?;
+        }
+
+        // outcomes are best effort, the digest is already settled
+        if let Err(e) = record_outcomes(&db, &outcomes).await {
+            console_error!("failed to record {} digest outcomes: {e}", outcomes.len());
+        }
+
+        Ok(())
+    }
+}
+
+// SAFETY: See comment on first impl block for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl DurableObject for UserDigest {
+    fn new(state: State, env: Env) -> Self {
+        console_error_panic_hook::set_once();
+
+        Self {
+            state,
+            env,
+            digest: RefCell::new(StorageCell::new("digest", DigestState::default)),
+        }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        if req.method() != Method::Post || req.path() != "/add" {
+            return error_resp("not found", 404);
+        }
+
+        let entries: Vec<DigestEntry> = req.json().await?;
+        self.add(entries).await?;
+
+        Response::ok("done")
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        self.flush().await?;
+
+        Response::ok("done")
+    }
+}
@@ -0,0 +1,332 @@
+mod delivery;
+mod digest;
+mod jwt;
+mod preferences;
+
+use std::collections::HashMap;
+
+use candid::Principal;
+use delivery::{
+    recent_deliveries, record_outcomes, DeliveryOutcome, DeliveryStatus, Digest, MetadataPush,
+};
+use digest::{DigestEntry, USER_DIGEST};
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
+use preferences::{load_preferences, save_preferences, NotificationPrefs};
+use worker::*;
+use worker_utils::{
+    api_error::{error_resp, ApiError},
+    cors::cors_for_env,
+    do_client::DoClient,
+    environment::profile_config,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    notification::{NotificationJob, NotificationPriority},
+    principals,
+    secrets::SecretSet,
+    time::now_millis,
+};
+
+const NOTIFICATIONS_DB: &str = "NOTIFICATIONS_DB";
+
+/// matches `max_retries` in wrangler.toml, the last attempt records the failure
+const MAX_DELIVERY_ATTEMPTS: u32 = 6;
+
+/// queues can't delay a retry by more than 12 hours
+const MAX_RETRY_DELAY_SECS: u64 = 12 * 60 * 60;
+
+const RECENT_DELIVERIES_LIMIT: u32 = 50;
+
+const REQUIRED_SECRETS: &[&str] = &["YRAL_METADATA_USER_NOTIFICATION_API_KEY"];
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-notifications",
+    &[
+        Dependency::D1(NOTIFICATIONS_DB),
+        Dependency::DurableObject(USER_DIGEST),
+        Dependency::Secret("YRAL_METADATA_USER_NOTIFICATION_API_KEY"),
+    ],
+);
+
+async fn get_preferences(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+    let user_principal = principals!(ctx, "user_principal");
+
+    let db = ctx.env.d1(NOTIFICATIONS_DB)?;
+    let prefs = load_preferences(&db, user_principal).await?;
+
+    Response::from_json(&prefs)
+}
+
+async fn set_preferences(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+    let user_principal = principals!(ctx, "user_principal");
+    let prefs: NotificationPrefs = json_body!(req);
+    if prefs.quiet_hours.is_some_and(|q| !q.is_valid()) {
+        return ApiError::new("InvalidQuietHours", "quiet hours are out of range")
+            .into_response(400);
+    }
+
+    let db = ctx.env.d1(NOTIFICATIONS_DB)?;
+    save_preferences(&db, user_principal, &prefs, now_millis()).await?;
+
+    Response::from_json(&prefs)
+}
+
+async fn get_deliveries(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+    let user_principal = principals!(ctx, "user_principal");
+
+    let db = ctx.env.d1(NOTIFICATIONS_DB)?;
+    let deliveries = recent_deliveries(&db, user_principal, RECENT_DELIVERIES_LIMIT).await?;
+
+    Response::from_json(&deliveries)
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    if let Err(e) = SecretSet::validate(&env, REQUIRED_SECRETS) {
+        return e.into_response();
+    }
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-notifications", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/preferences/:user_principal", get_preferences)
+        .post_async("/preferences/:user_principal", set_preferences)
+        .get_async("/deliveries/:user_principal", get_deliveries)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// hands low priority jobs to the user's digest, acked once it holds them
+async fn hold_for_digest(
+    env: &Env,
+    metrics: &Metrics,
+    user: Principal,
+    messages: Vec<Message<NotificationJob>>,
+    outcomes: &mut Vec<DeliveryOutcome>,
+) {
+    let now = now_millis();
+    let entries: Vec<_> = messages
+        .iter()
+        .map(|m| DigestEntry {
+            id: m.id(),
+            job: m.body().clone(),
+        })
+        .collect();
+    let res = DoClient::new(env)
+        .post(USER_DIGEST, &user.to_text(), "add", &entries)
+        .await;
+    match res {
+        Ok(res) if res.status_code() == 200 => {
+            for message in messages {
+                outcomes.push(DeliveryOutcome::new(
+                    message.id(),
+                    message.body(),
+                    DeliveryStatus::Digested,
+                    message.attempts(),
+                    None,
+                    now,
+                ));
+                metrics.counter("notifications", &["digested"]);
+                message.ack();
+            }
+        }
+        Ok(res) => {
+            console_error!(
+                "failed to add to the digest of {user}: status {}",
+                res.status_code()
+            );
+            messages.iter().for_each(|m| m.retry());
+        }
+        Err(e) => {
+            console_error!("failed to add to the digest of {user}: {e}");
+            messages.iter().for_each(|m| m.retry());
+        }
+    }
+}
+
+/// delivers one user's high priority jobs from a batch as a single push
+async fn deliver_to_user(
+    env: &Env,
+    db: &D1Database,
+    push: &MetadataPush,
+    metrics: &Metrics,
+    user: Principal,
+    messages: Vec<Message<NotificationJob>>,
+    outcomes: &mut Vec<DeliveryOutcome>,
+) {
+    let now = now_millis();
+    let prefs = match load_preferences(db, user).await {
+        Ok(prefs) => prefs,
+        Err(e) => {
+            console_error!("failed to load preferences of {user}: {e}");
+            messages.iter().for_each(|m| m.retry());
+            return;
+        }
+    };
+
+    let (pending, muted): (Vec<_>, Vec<_>) = messages
+        .into_iter()
+        .partition(|m| prefs.allows(m.body().notification.category()));
+    for message in muted {
+        outcomes.push(DeliveryOutcome::new(
+            message.id(),
+            message.body(),
+            DeliveryStatus::Suppressed,
+            message.attempts(),
+            None,
+            now,
+        ));
+        metrics.counter("notifications", &["suppressed"]);
+        message.ack();
+    }
+
+    let (pending, low): (Vec<_>, Vec<_>) = pending
+        .into_iter()
+        .partition(|m| m.body().notification.priority() == NotificationPriority::High);
+    if !low.is_empty() {
+        hold_for_digest(env, metrics, user, low, outcomes).await;
+    }
+    if pending.is_empty() {
+        return;
+    }
+
+    if let Some(quiet_ms) = prefs.quiet_hours.and_then(|q| q.remaining_ms(now)) {
+        let delay_seconds = quiet_ms.div_ceil(1000).min(MAX_RETRY_DELAY_SECS) as u32;
+        let options = QueueRetryOptionsBuilder::new()
+            .with_delay_seconds(delay_seconds)
+            .build();
+        for message in &pending {
+            message.retry_with_options(&options);
+        }
+        metrics.counter("notifications", &["deferred"]);
+        return;
+    }
+
+    let jobs: Vec<_> = pending.iter().map(|m| m.body()).collect();
+    let trace_id = jobs.iter().find_map(|j| j.trace_id.as_ref());
+    let res = push
+        .send(
+            user,
+            &Digest::of(&jobs, prefs.locale),
+            trace_id.map(|t| t.as_str()),
+        )
+        .await;
+    metrics.histogram("notification_digest_size", &[], jobs.len() as f64);
+
+    for message in pending {
+        let attempts = message.attempts();
+        match &res {
+            Ok(()) => {
+                outcomes.push(DeliveryOutcome::new(
+                    message.id(),
+                    message.body(),
+                    DeliveryStatus::Delivered,
+                    attempts,
+                    None,
+                    now,
+                ));
+                metrics.counter("notifications", &["delivered"]);
+                message.ack();
+            }
+            Err(e) if attempts >= MAX_DELIVERY_ATTEMPTS => {
+                console_error!("giving up on notification {} for {user}: {e}", message.id());
+                outcomes.push(DeliveryOutcome::new(
+                    message.id(),
+                    message.body(),
+                    DeliveryStatus::Failed,
+                    attempts,
+                    Some(e.clone()),
+                    now,
+                ));
+                metrics.counter("notifications", &["failed"]);
+                message.ack();
+            }
+            Err(e) => {
+                console_warn!("notification delivery to {user} failed: {e}");
+                metrics.counter("notifications", &["retried"]);
+                message.retry();
+            }
+        }
+    }
+}
+
+#[event(queue)]
+async fn queue(batch: MessageBatch<NotificationJob>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    if let Err(e) = SecretSet::validate(&env, REQUIRED_SECRETS) {
+        console_error!("{e}");
+        batch.retry_all();
+        return Ok(());
+    }
+
+    let db = env.d1(NOTIFICATIONS_DB)?;
+    let push = MetadataPush::new(
+        env.secret("YRAL_METADATA_USER_NOTIFICATION_API_KEY")?
+            .to_string(),
+        profile_config(&env).metadata_server_url,
+    );
+    let metrics = Metrics::new(&env, "yral-notifications");
+
+    let mut by_user = HashMap::<Principal, Vec<Message<NotificationJob>>>::new();
+    for message in batch.messages()? {
+        by_user
+            .entry(message.body().user_principal)
+            .or_default()
+            .push(message);
+    }
+
+    let mut outcomes = vec![];
+    for (user, messages) in by_user {
+        deliver_to_user(&env, &db, &push, &metrics, user, messages, &mut outcomes).await;
+    }
+
+    // outcomes are best effort, the jobs themselves are already settled
+    if let Err(e) = record_outcomes(&db, &outcomes).await {
+        console_error!(
+            "failed to record {} notification outcomes: {e}",
+            outcomes.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, D1Database, Result};
+use worker_utils::notification::{Locale, NotificationCategory};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// quiet hours in the user's local time, `start == end` means never quiet
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuietHours {
+    /// minutes after local midnight
+    pub start_minute: u16,
+    pub end_minute: u16,
+    /// offset of the user's timezone from UTC
+    pub utc_offset_minutes: i16,
+}
+
+impl QuietHours {
+    pub fn is_valid(&self) -> bool {
+        let day = MINUTES_PER_DAY as u16;
+        self.start_minute < day
+            && self.end_minute < day
+            && (self.utc_offset_minutes as i64).abs() <= 14 * 60
+    }
+
+    /// millis until quiet hours end, `None` if `now` (unix millis) is outside them
+    pub fn remaining_ms(&self, now: u64) -> Option<u64> {
+        let now_minutes = (now / 60_000) as i64;
+        let local = (now_minutes + self.utc_offset_minutes as i64).rem_euclid(MINUTES_PER_DAY);
+        let (start, end) = (self.start_minute as i64, self.end_minute as i64);
+
+        // windows like 22:00-07:00 wrap around midnight
+        let quiet = if start <= end {
+            (start..end).contains(&local)
+        } else {
+            local >= start || local < end
+        };
+        if !quiet {
+            return None;
+        }
+
+        let minutes_left = (end - local).rem_euclid(MINUTES_PER_DAY);
+        let into_minute = now % 60_000;
+        Some((minutes_left as u64 * 60_000).saturating_sub(into_minute))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NotificationPrefs {
+    #[serde(default)]
+    pub muted: HashSet<NotificationCategory>,
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    /// language pushes are rendered in, english if unset
+    #[serde(default)]
+    pub locale: Locale,
+}
+
+impl NotificationPrefs {
+    pub fn allows(&self, category: NotificationCategory) -> bool {
+        !self.muted.contains(&category)
+    }
+}
+
+#[derive(Deserialize)]
+struct PrefsRow {
+    prefs: String,
+}
+
+pub async fn load_preferences(db: &D1Database, user: Principal) -> Result<NotificationPrefs> {
+    let row = db
+        .prepare("SELECT prefs FROM notification_preferences WHERE user_principal = ?1")
+        .bind(&[user.to_text().into()])?
+        .first::<PrefsRow>(None)
+        .await?;
+
+    match row {
+        Some(row) => Ok(serde_json::from_str(&row.prefs)?),
+        None => Ok(NotificationPrefs::default()),
+    }
+}
+
+pub async fn save_preferences(
+    db: &D1Database,
+    user: Principal,
+    prefs: &NotificationPrefs,
+    now: u64,
+) -> Result<()> {
+    db.prepare(
+        "INSERT INTO notification_preferences (user_principal, prefs, updated_at) \
+        VALUES (?1, ?2, ?3) \
+        ON CONFLICT (user_principal) DO UPDATE SET prefs = excluded.prefs, updated_at = excluded.updated_at",
+    )
+    .bind(&[
+        user.to_text().into(),
+        serde_json::to_string(prefs)?.into(),
+        JsValue::from_f64(now as f64),
+    ])?
+    .run()
+    .await?;
+
+    Ok(())
+}
@@ -17,6 +17,10 @@ use utils::create_agent;
 use wasm_bindgen::JsValue;
 use worker::Router;
 use worker::*;
+use worker_utils::{
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+};
 
 mod individual_user_canister;
 mod platform_ochestrator;
@@ -38,10 +42,31 @@ struct RequestMigration {
 //      /restore => RequestData { canister_id }
 //      /bulk-restore => None
 
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-onchain-backend",
+    &[
+        Dependency::DurableObject("CANISTER_DATA"),
+        Dependency::Secret("CF_WORKER_ACCESS_OFF_CHAIN_AGENT_KEY"),
+        Dependency::Secret("RECLAIM_CANISTER_PEM"),
+    ],
+);
+
 #[event(fetch)]
 pub async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let (method, path) = (req.method(), req.path());
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-onchain-backend", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
     let router = Router::new();
     router
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .put_async("/individual-user/backup", individual_user_backup_handler)
         .put_async("/individual-user/restore", individual_user_restore_handler)
         .put_async(
@@ -0,0 +1,4 @@
+/// the dashboard is opened with yral-admin tokens, see `worker_utils::jwt::authorize`
+pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
+-----END PUBLIC KEY-----";
@@ -0,0 +1,86 @@
+mod jwt;
+mod sources;
+mod stream;
+
+use jwt::JWT_PUBKEY;
+use sources::{snapshot, ACCOUNT_ID_SECRET, ANALYTICS_TOKEN_SECRET, TREASURY_CONTROLLER};
+use stream::ops_stream;
+use worker::*;
+use worker_utils::{
+    api_error::ApiError,
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    jwt::{authorize, JwtKeys, Role},
+    maintenance::MaintenanceNotice,
+};
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-ops",
+    &[
+        Dependency::DurableObject(TREASURY_CONTROLLER),
+        Dependency::Secret(ACCOUNT_ID_SECRET),
+        Dependency::Secret(ANALYTICS_TOKEN_SECRET),
+    ],
+);
+
+macro_rules! admin {
+    ($ctx:expr, $req:expr) => {
+        match authorize(
+            &JwtKeys::from_env(&$ctx.env, JWT_PUBKEY),
+            &$req,
+            "Authorization",
+            Role::Viewer,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        }
+    };
+}
+
+/// the same numbers as the stream, once
+async fn get_snapshot(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req);
+
+    Response::from_json(&snapshot(&ctx.env).await?)
+}
+
+async fn stream(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req);
+    if req.headers().get("Upgrade")?.as_deref() != Some("websocket") {
+        return ApiError::new("ExpectedWebsocket", "expected websocket").into_response(400);
+    }
+
+    ops_stream(ctx.env)
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-ops", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/snapshot", get_snapshot)
+        .get_async("/stream", stream)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
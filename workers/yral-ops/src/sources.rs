@@ -0,0 +1,335 @@
+//! live numbers for the ops dashboard, each source is collected on its own
+//!
+//! queue backlogs come from the Cloudflare GraphQL analytics API, failure rates from the
+//! `yral_worker_metrics` analytics engine dataset and the treasury from yral-pump-n-dump's
+//! `TreasuryController`, a failing source is reported in `errors` without hiding the others
+
+use std::time::Duration;
+
+use futures::future::join4;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{do_client::DoClient, time::now_millis, RequestInitBuilder};
+
+const CF_API_BASE: &str = "https://api.cloudflare.com/client/v4";
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const ACCOUNT_ID_SECRET: &str = "CLOUDFLARE_ACCOUNT_ID";
+pub const ANALYTICS_TOKEN_SECRET: &str = "CLOUDFLARE_ANALYTICS_TOKEN";
+pub const TREASURY_CONTROLLER: &str = "TREASURY_CONTROLLER";
+/// the single controller instance, see `TREASURY_CONTROLLER_NAME` in yral-pump-n-dump
+const TREASURY_CONTROLLER_NAME: &str = "global";
+
+const METRICS_DATASET: &str = "yral_worker_metrics";
+/// backlog samples are recorded every minute or so, the latest one in this window is used
+const BACKLOG_LOOKBACK_MS: u64 = 10 * 60 * 1000;
+/// failure rates are over this trailing window
+const RATE_WINDOW_MINUTES: u32 = 15;
+
+/// queues with a dead letter queue named `{queue}-dlq`
+pub const WATCHED_QUEUES: &[&str] = &[
+    "upload-video",
+    "yral-activity-events",
+    "yral-analytics-events",
+    "yral-backup-jobs",
+    "yral-balance-migration",
+    "yral-coin-balance-webhooks",
+    "yral-coin-ledger",
+    "yral-forget-user",
+    "yral-moderation-actions",
+    "yral-moderation-flags",
+    "yral-notifications",
+    "yral-rewards-events",
+    "yral-risk-signals",
+    "yral-search-index",
+    "yral-webhook-deliveries",
+    "yral-webhook-events",
+];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QueueDepth {
+    pub queue: &'static str,
+    /// messages waiting, `None` if the queue had no backlog samples
+    pub backlog: Option<u64>,
+    pub dlq_backlog: Option<u64>,
+}
+
+/// counts of a counter's outcomes over the trailing window
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct FailureRate {
+    pub total: u64,
+    pub failed: u64,
+    /// `failed / total`, 0 without any events
+    pub rate: f64,
+}
+
+impl FailureRate {
+    fn new(total: u64, failed: u64) -> Self {
+        let rate = if total == 0 {
+            0.0
+        } else {
+            failed as f64 / total as f64
+        };
+        Self {
+            total,
+            failed,
+            rate,
+        }
+    }
+}
+
+/// see `TreasuryStatus` in yral-pump-n-dump
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TreasuryStatus {
+    /// DOLR e8s left in the current window
+    pub remaining: u64,
+    pub max: u64,
+    /// unix millis
+    pub resets_at: u64,
+}
+
+/// what the dashboard shows, sent on connect and every poll
+#[derive(Serialize, Clone, Debug)]
+pub struct OpsSnapshot {
+    /// unix millis
+    pub at: u64,
+    pub queues: Vec<QueueDepth>,
+    pub settlements: Option<FailureRate>,
+    pub notifications: Option<FailureRate>,
+    pub treasury: Option<TreasuryStatus>,
+    /// sources that couldn't be read
+    pub errors: Vec<String>,
+}
+
+struct CfApi {
+    account_id: String,
+    token: String,
+}
+
+impl CfApi {
+    fn new(env: &Env) -> Result<Self> {
+        Ok(Self {
+            account_id: env.secret(ACCOUNT_ID_SECRET)?.to_string(),
+            token: env.secret(ANALYTICS_TOKEN_SECRET)?.to_string(),
+        })
+    }
+
+    async fn call(&self, init: &mut RequestInitBuilder, url: &str) -> Result<Value> {
+        init.header("Authorization", &format!("Bearer {}", self.token))?
+            .timeout(UPSTREAM_TIMEOUT);
+        let mut res = init.fetch(url).await?;
+        if res.status_code() != 200 {
+            return Err(Error::RustError(format!(
+                "{url} returned {}",
+                res.status_code()
+            )));
+        }
+
+        res.json().await
+    }
+
+    /// queue ids by name, the analytics API only knows ids
+    async fn queue_ids(&self) -> Result<Vec<(String, String)>> {
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Get).query(&[("per_page", "1000")]);
+        let res = self
+            .call(
+                &mut init,
+                &format!("{CF_API_BASE}/accounts/{}/queues", self.account_id),
+            )
+            .await?;
+
+        Ok(res["result"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|q| {
+                Some((
+                    q["queue_name"].as_str()?.to_string(),
+                    q["queue_id"].as_str()?.to_string(),
+                ))
+            })
+            .collect())
+    }
+
+    /// the latest backlog sample of every queue in `ids`
+    async fn backlogs(&self, ids: &[&str]) -> Result<Vec<(String, u64)>> {
+        let since_ms = (now_millis() - BACKLOG_LOOKBACK_MS) as f64;
+        let since = String::from(js_sys::Date::new(&since_ms.into()).to_iso_string());
+        let query = "query Backlogs($account: string!, $ids: [string!], $since: Time!) { \
+            viewer { accounts(filter: { accountTag: $account }) { \
+            queueBacklogAdaptiveGroups(limit: 1000, \
+            filter: { queueId_in: $ids, datetimeMinute_geq: $since }, \
+            orderBy: [datetimeMinute_DESC]) { \
+            dimensions { queueId datetimeMinute } avg { messages } } } } }";
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Post).json(&json!({
+            "query": query,
+            "variables": { "account": self.account_id, "ids": ids, "since": since },
+        }))?;
+        let res = self
+            .call(&mut init, &format!("{CF_API_BASE}/graphql"))
+            .await?;
+        if let Some(e) = res["errors"].as_array().and_then(|e| e.first()) {
+            return Err(Error::RustError(format!("queue analytics failed: {e}")));
+        }
+
+        // samples are newest first, the first one of each queue wins
+        let mut latest: Vec<(String, u64)> = Vec::new();
+        let groups = &res["data"]["viewer"]["accounts"][0]["queueBacklogAdaptiveGroups"];
+        for group in groups.as_array().into_iter().flatten() {
+            let Some(id) = group["dimensions"]["queueId"].as_str() else {
+                continue;
+            };
+            if latest.iter().any(|(seen, _)| seen == id) {
+                continue;
+            }
+            let messages = group["avg"]["messages"].as_f64().unwrap_or_default();
+            latest.push((id.to_string(), messages.round() as u64));
+        }
+
+        Ok(latest)
+    }
+
+    /// sums of `metric`'s first label over the trailing window
+    async fn outcomes(&self, metric: &str, environment: &str) -> Result<Vec<(String, u64)>> {
+        // both values come from our own consts and vars, never from requests
+        let sql = format!(
+            "SELECT blob4 AS outcome, SUM(_sample_interval * double1) AS total \
+            FROM {METRICS_DATASET} WHERE index1 = '{metric}' AND blob2 = '{environment}' \
+            AND timestamp > NOW() - INTERVAL '{RATE_WINDOW_MINUTES}' MINUTE \
+            GROUP BY outcome FORMAT JSON"
+        );
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Post).body(sql, "text/plain")?;
+        let res = self
+            .call(
+                &mut init,
+                &format!(
+                    "{CF_API_BASE}/accounts/{}/analytics_engine/sql",
+                    self.account_id
+                ),
+            )
+            .await?;
+
+        Ok(res["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|row| {
+                let outcome = row["outcome"].as_str()?.to_string();
+                // numbers may come back as strings
+                let total = row["total"]
+                    .as_f64()
+                    .or_else(|| row["total"].as_str()?.parse().ok())?;
+                Some((outcome, total.round() as u64))
+            })
+            .collect())
+    }
+}
+
+async fn queues(api: &CfApi) -> Result<Vec<QueueDepth>> {
+    let names = api.queue_ids().await?;
+    let id_of = |name: &str| {
+        names
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| id.as_str())
+    };
+    let dlqs: Vec<String> = WATCHED_QUEUES.iter().map(|q| format!("{q}-dlq")).collect();
+    let ids: Vec<&str> = WATCHED_QUEUES
+        .iter()
+        .copied()
+        .chain(dlqs.iter().map(String::as_str))
+        .filter_map(id_of)
+        .collect();
+    let backlogs = api.backlogs(&ids).await?;
+    let backlog_of = |name: &str| {
+        let id = id_of(name)?;
+        backlogs.iter().find(|(b, _)| b == id).map(|(_, n)| *n)
+    };
+
+    Ok(WATCHED_QUEUES
+        .iter()
+        .zip(&dlqs)
+        .map(|(queue, dlq)| QueueDepth {
+            queue,
+            backlog: backlog_of(queue),
+            dlq_backlog: backlog_of(dlq),
+        })
+        .collect())
+}
+
+/// `failed` out of `succeeded + failed`, other outcomes (e.g. retries) aren't counted
+async fn failure_rate(
+    api: &CfApi,
+    metric: &str,
+    environment: &str,
+    succeeded: &str,
+    failed: &str,
+) -> Result<FailureRate> {
+    let outcomes = api.outcomes(metric, environment).await?;
+    let count = |outcome: &str| {
+        outcomes
+            .iter()
+            .filter(|(o, _)| o == outcome)
+            .map(|(_, n)| n)
+            .sum::<u64>()
+    };
+    let failed = count(failed);
+
+    Ok(FailureRate::new(count(succeeded) + failed, failed))
+}
+
+async fn treasury(env: &Env) -> Result<TreasuryStatus> {
+    let mut res = DoClient::new(env)
+        .get(TREASURY_CONTROLLER, TREASURY_CONTROLLER_NAME, "status")
+        .await?;
+    if res.status_code() != 200 {
+        return Err(Error::RustError(format!(
+            "treasury controller returned {}",
+            res.status_code()
+        )));
+    }
+
+    res.json().await
+}
+
+fn reading<T>(errors: &mut Vec<String>, source: &str, res: Result<T>) -> Option<T> {
+    match res {
+        Ok(v) => Some(v),
+        Err(e) => {
+            console_error!("failed to read {source}: {e}");
+            errors.push(format!("{source}: {e}"));
+            None
+        }
+    }
+}
+
+/// fails only without the Cloudflare API secrets
+pub async fn snapshot(env: &Env) -> Result<OpsSnapshot> {
+    let api = CfApi::new(env)?;
+    let environment = env
+        .var("ENVIRONMENT")
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "production".into());
+
+    let (queues, settlements, notifications, treasury) = join4(
+        queues(&api),
+        failure_rate(&api, "settlements", &environment, "settled", "failed"),
+        failure_rate(&api, "notifications", &environment, "delivered", "failed"),
+        treasury(env),
+    )
+    .await;
+
+    let mut errors = vec![];
+    Ok(OpsSnapshot {
+        at: now_millis(),
+        queues: reading(&mut errors, "queues", queues).unwrap_or_default(),
+        settlements: reading(&mut errors, "settlements", settlements),
+        notifications: reading(&mut errors, "notifications", notifications),
+        treasury: reading(&mut errors, "treasury", treasury),
+        errors,
+    })
+}
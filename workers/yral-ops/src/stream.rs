@@ -0,0 +1,89 @@
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use futures::StreamExt;
+use serde::Serialize;
+use wasm_bindgen_futures::spawn_local;
+use worker::*;
+
+use crate::sources::{snapshot, OpsSnapshot};
+
+/// every viewer polls on its own, keep it well under the Cloudflare API rate limits
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// messages sent to the dashboard
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OpsEvent<'a> {
+    Snapshot {
+        snapshot: &'a OpsSnapshot,
+    },
+    /// nothing could be collected, the stream stays open and keeps trying
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Clone)]
+struct Conn {
+    env: Env,
+    client: WebSocket,
+    closed: Rc<Cell<bool>>,
+}
+
+impl Conn {
+    fn send(&self, event: &OpsEvent) {
+        if self.closed.get() {
+            return;
+        }
+        if let Err(e) = self.client.send(event) {
+            console_error!("failed to send ops event: {e}");
+        }
+    }
+
+    async fn send_snapshot(&self) {
+        match snapshot(&self.env).await {
+            Ok(snapshot) => self.send(&OpsEvent::Snapshot {
+                snapshot: &snapshot,
+            }),
+            Err(e) => self.send(&OpsEvent::Error {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+async fn poll(conn: Conn) {
+    while !conn.closed.get() {
+        conn.send_snapshot().await;
+        Delay::from(POLL_INTERVAL).await;
+    }
+}
+
+/// stops polling once the dashboard goes away
+async fn watch_client(conn: Conn) {
+    if let Ok(mut events) = conn.client.events() {
+        while let Some(event) = events.next().await {
+            if matches!(event, Ok(WebsocketEvent::Close(_)) | Err(_)) {
+                break;
+            }
+        }
+    }
+
+    conn.closed.set(true);
+}
+
+/// a snapshot right away, then one every `POLL_INTERVAL`
+pub fn ops_stream(env: Env) -> Result<Response> {
+    let pair = WebSocketPair::new()?;
+    pair.server.accept()?;
+    let conn = Conn {
+        env,
+        client: pair.server,
+        closed: Rc::new(Cell::new(false)),
+    };
+
+    spawn_local(watch_client(conn.clone()));
+    spawn_local(poll(conn));
+
+    Response::from_websocket(pair.client)
+}
@@ -1,48 +1,53 @@
+use std::sync::Arc;
+
 use candid::Principal;
 use ic_agent::identity::Secp256k1Identity;
 use k256::SecretKey;
 use worker::{Env, Result};
 use worker_utils::{
-    environment::{env_kind, RunEnv},
-    icp::agent_wrapper::AgentWrapper,
+    environment::{env_kind, profile_config, RunEnv},
+    icp::agent_wrapper::{identity_from_pem, AgentConfig, AgentWrapper},
 };
 use yral_canisters_client::{
     individual_user_template::IndividualUserTemplate, sns_ledger::SnsLedger,
 };
 use yral_metadata_client::MetadataClient;
 
-use crate::consts::{ADMIN_LOCAL_SECP_SK, DOLR_LEDGER, LOCAL_METADATA_API_BASE};
+use crate::consts::ADMIN_LOCAL_SECP_SK;
 
 #[derive(Clone)]
 pub struct AdminCans {
     pub agent: AgentWrapper,
     metadata: MetadataClient<false>,
+    pub dolr_ledger: Principal,
 }
 
 impl AdminCans {
     pub fn new(env: &Env) -> Result<Self> {
         let agent;
-        let metadata;
+        let profile = profile_config(env);
+        let metadata = MetadataClient::with_base_url(profile.metadata_server_url.parse().unwrap());
 
         match env_kind() {
             RunEnv::Local => {
                 let id = Secp256k1Identity::from_private_key(
                     SecretKey::from_bytes(&ADMIN_LOCAL_SECP_SK.into()).unwrap(),
                 );
-                agent = AgentWrapper::new(id);
-                metadata = MetadataClient::with_base_url(LOCAL_METADATA_API_BASE.parse().unwrap());
+                agent = AgentWrapper::with_config(Arc::new(id), AgentConfig::from_env(env));
             }
             RunEnv::Remote => {
                 let admin_pem = env.secret("BACKEND_ADMIN_KEY")?.to_string();
-                let id = Secp256k1Identity::from_pem(admin_pem.as_bytes())
-                    .map_err(|e| worker::Error::RustError(e.to_string()))?;
-                agent = AgentWrapper::new(id);
-                metadata = MetadataClient::default();
+                let id = identity_from_pem(&admin_pem)?;
+                agent = AgentWrapper::with_config(id, AgentConfig::from_env(env));
             }
             RunEnv::Mock => panic!("trying to use ic-agent in mock env"),
         };
 
-        Ok(Self { agent, metadata })
+        Ok(Self {
+            agent,
+            metadata,
+            dolr_ledger: profile.dolr_ledger,
+        })
     }
 
     pub async fn user_principal_to_user_canister(
@@ -62,8 +67,9 @@ impl AdminCans {
         // as the worker makes multiple calls on behalf of the user
         // we need to ensure the user really owns this canister
         let user = self.individual_user(user_canister).await;
-        let profile = user
-            .get_profile_details_v_2()
+        let profile = self
+            .agent
+            .guarded(user_canister, user.get_profile_details_v_2())
             .await
             .map_err(|e| worker::Error::RustError(e.to_string()))?;
         if profile.principal_id != user_principal {
@@ -78,6 +84,6 @@ impl AdminCans {
     }
 
     pub async fn dolr_ledger(&self) -> SnsLedger<'_> {
-        SnsLedger(DOLR_LEDGER, self.agent.get().await)
+        SnsLedger(self.dolr_ledger, self.agent.get().await)
     }
 }
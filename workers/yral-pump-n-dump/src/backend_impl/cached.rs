@@ -0,0 +1,88 @@
+use candid::Principal;
+use serde::{de::DeserializeOwned, Serialize};
+use worker::{console_warn, kv::KvStore, Result};
+
+use crate::consts::{WS_BACKEND_CACHE_NEGATIVE_TTL_SECS, WS_BACKEND_CACHE_TTL_SECS};
+
+use super::WsBackendImpl;
+
+/// KV read-through cache for `WsBackendImpl`
+/// misses (no canister / invalid token) are cached with a shorter TTL
+#[derive(Clone)]
+pub struct KvCachedWsBackend<T: WsBackendImpl> {
+    inner: T,
+    kv: KvStore,
+}
+
+impl<T: WsBackendImpl> KvCachedWsBackend<T> {
+    pub fn new(inner: T, kv: KvStore) -> Self {
+        Self { inner, kv }
+    }
+
+    async fn cached<V: Serialize + DeserializeOwned>(&self, key: &str) -> Option<V> {
+        // cache failures must never fail the request
+        match self.kv.get(key).json::<V>().await {
+            Ok(v) => v,
+            Err(e) => {
+                console_warn!("ws backend cache read failed for {key}: {e}");
+                None
+            }
+        }
+    }
+
+    async fn store<V: Serialize>(&self, key: &str, v: &V, hit: bool) {
+        let ttl = if hit {
+            WS_BACKEND_CACHE_TTL_SECS
+        } else {
+            WS_BACKEND_CACHE_NEGATIVE_TTL_SECS
+        };
+        let res = async {
+            self.kv.put(key, v)?.expiration_ttl(ttl).execute().await?;
+            Ok::<_, worker::Error>(())
+        }
+        .await;
+        if let Err(e) = res {
+            console_warn!("ws backend cache write failed for {key}: {e}");
+        }
+    }
+}
+
+impl<T: WsBackendImpl> WsBackendImpl for KvCachedWsBackend<T> {
+    async fn user_principal_to_user_canister(
+        &self,
+        user_principal: Principal,
+    ) -> Result<Option<Principal>> {
+        let key = format!("user-canister-{user_principal}");
+        if let Some(user_canister) = self.cached::<Option<Principal>>(&key).await {
+            return Ok(user_canister);
+        }
+
+        let user_canister = self
+            .inner
+            .user_principal_to_user_canister(user_principal)
+            .await?;
+        self.store(&key, &user_canister, user_canister.is_some())
+            .await;
+
+        Ok(user_canister)
+    }
+
+    async fn validate_token(
+        &self,
+        token_root: Principal,
+        token_creator_canister: Principal,
+    ) -> Result<bool> {
+        let key = format!("valid-token-{token_creator_canister}-{token_root}");
+        if let Some(valid) = self.cached::<bool>(&key).await {
+            return Ok(valid);
+        }
+
+        let valid = self
+            .inner
+            .validate_token(token_root, token_creator_canister)
+            .await?;
+        self.store(&key, &valid, valid).await;
+
+        Ok(valid)
+    }
+}
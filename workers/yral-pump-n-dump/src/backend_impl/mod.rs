@@ -1,6 +1,8 @@
+mod cached;
 mod mock;
 mod real;
 
+use cached::KvCachedWsBackend;
 use candid::{Nat, Principal};
 use enum_dispatch::enum_dispatch;
 use mock::{MockWsBackend, NoOpGameBackend, NoOpUserState};
@@ -99,7 +101,7 @@ impl StateBackend {
 #[derive(Clone)]
 #[enum_dispatch(WsBackendImpl)]
 pub enum WsBackend {
-    Real(AdminCans),
+    Real(KvCachedWsBackend<AdminCans>),
     Mock(MockWsBackend),
 }
 
@@ -108,7 +110,9 @@ impl WsBackend {
         if env_kind() == RunEnv::Mock {
             Ok(WsBackend::Mock(MockWsBackend))
         } else {
-            AdminCans::new(env).map(Self::Real)
+            let admin = AdminCans::new(env)?;
+            let kv = env.kv("WS_BACKEND_CACHE")?;
+            Ok(Self::Real(KvCachedWsBackend::new(admin, kv)))
         }
     }
 }
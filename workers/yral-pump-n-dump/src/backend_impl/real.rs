@@ -30,8 +30,12 @@ impl GameBackendImpl for AdminCans {
         amount: Nat,
     ) -> Result<()> {
         let user = self.individual_user(user_canister).await;
-        let res = user
-            .add_dollr_to_liquidity_pool(token_root, amount)
+        let res = self
+            .agent
+            .guarded(
+                user_canister,
+                user.add_dollr_to_liquidity_pool(token_root, amount),
+            )
             .await
             .map_err(to_worker_error)?;
 
@@ -42,12 +46,16 @@ impl GameBackendImpl for AdminCans {
 impl UserStateBackendImpl for AdminCans {
     async fn game_balance(&self, user_canister: Principal) -> Result<BalanceInfo> {
         let user = self.individual_user(user_canister).await;
-        user.pd_balance_info().await.map_err(to_worker_error)
+        self.agent
+            .guarded(user_canister, user.pd_balance_info())
+            .await
+            .map_err(to_worker_error)
     }
 
     async fn game_balance_v2(&self, user_canister: Principal) -> Result<BalanceInfo> {
         let user = self.individual_user(user_canister).await;
-        user.cents_token_balance_info()
+        self.agent
+            .guarded(user_canister, user.cents_token_balance_info())
             .await
             .map_err(to_worker_error)
     }
@@ -58,8 +66,9 @@ impl UserStateBackendImpl for AdminCans {
         completed_games: Vec<PumpNDumpStateDiff>,
     ) -> Result<()> {
         let user = self.individual_user(user_canister).await;
-        let res = user
-            .reconcile_user_state(completed_games)
+        let res = self
+            .agent
+            .guarded(user_canister, user.reconcile_user_state(completed_games))
             .await
             .map_err(to_worker_error)?;
 
@@ -68,7 +77,11 @@ impl UserStateBackendImpl for AdminCans {
 
     async fn redeem_gdollr(&self, user_canister: Principal, amount: Nat) -> Result<()> {
         let user = self.individual_user(user_canister).await;
-        let res = user.redeem_gdollr(amount).await.map_err(to_worker_error)?;
+        let res = self
+            .agent
+            .guarded(user_canister, user.redeem_gdollr(amount))
+            .await
+            .map_err(to_worker_error)?;
 
         from_can_res(res)
     }
@@ -76,13 +89,19 @@ impl UserStateBackendImpl for AdminCans {
     async fn game_count(&self, user_canister: Principal) -> Result<u64> {
         let user = self.individual_user(user_canister).await;
 
-        user.played_game_count().await.map_err(to_worker_error)
+        self.agent
+            .guarded(user_canister, user.played_game_count())
+            .await
+            .map_err(to_worker_error)
     }
 
     async fn net_earnings(&self, user_canister: Principal) -> Result<Nat> {
         let user = self.individual_user(user_canister).await;
 
-        user.net_earnings().await.map_err(to_worker_error)
+        self.agent
+            .guarded(user_canister, user.net_earnings())
+            .await
+            .map_err(to_worker_error)
     }
 
     async fn canister_controller(&self, user_canister: Principal) -> Result<Principal> {
@@ -95,11 +114,14 @@ impl UserStateBackendImpl for AdminCans {
     async fn dolr_balance(&self, user_index: Principal) -> Result<Nat> {
         let ledger = self.dolr_ledger().await;
 
-        ledger
-            .icrc_1_balance_of(Account {
-                owner: user_index,
-                subaccount: None,
-            })
+        self.agent
+            .guarded(
+                self.dolr_ledger,
+                ledger.icrc_1_balance_of(Account {
+                    owner: user_index,
+                    subaccount: None,
+                }),
+            )
             .await
             .map_err(to_worker_error)
     }
@@ -107,18 +129,22 @@ impl UserStateBackendImpl for AdminCans {
     async fn dolr_transfer(&self, to: Principal, amount: Nat) -> Result<()> {
         let ledger = self.dolr_ledger().await;
 
-        let res = ledger
-            .icrc_1_transfer(TransferArg {
-                from_subaccount: None,
-                to: Account {
-                    owner: to,
-                    subaccount: None,
-                },
-                amount,
-                fee: None,
-                memo: None,
-                created_at_time: None,
-            })
+        let res = self
+            .agent
+            .guarded(
+                self.dolr_ledger,
+                ledger.icrc_1_transfer(TransferArg {
+                    from_subaccount: None,
+                    to: Account {
+                        owner: to,
+                        subaccount: None,
+                    },
+                    amount,
+                    fee: None,
+                    memo: None,
+                    created_at_time: None,
+                }),
+            )
             .await
             .map_err(to_worker_error)?;
 
@@ -144,8 +170,9 @@ impl WsBackendImpl for AdminCans {
         token_creator: Principal,
     ) -> Result<bool> {
         let user = self.individual_user(token_creator).await;
-        let tokens = user
-            .deployed_cdao_canisters()
+        let tokens = self
+            .agent
+            .guarded(token_creator, user.deployed_cdao_canisters())
             .await
             .map_err(to_worker_error)?;
 
@@ -1,5 +1,3 @@
-use candid::Principal;
-
 pub const GDOLLR_TO_DOLLR: u64 = 100;
 pub const DOLLR_TO_E8S: u64 = 1e8 as u64;
 pub const GDOLLR_TO_E8S: u64 = DOLLR_TO_E8S / GDOLLR_TO_DOLLR;
@@ -10,10 +8,24 @@ pub const ADMIN_LOCAL_SECP_SK: [u8; 32] = [
     9, 64, 7, 55, 201, 208, 139, 219, 167, 201, 176, 6, 31, 109, 44, 248, 27, 241, 239, 56, 98,
     100, 158, 36, 79, 233, 172, 151, 228, 187, 8, 224,
 ];
-pub const LOCAL_METADATA_API_BASE: &str = "http://localhost:8001";
-// [0, 0, 0, 0, 2, 0, 0, 43, 1, 1]
-pub const DOLR_LEDGER: Principal = Principal::from_slice(&[0, 0, 0, 0, 2, 0, 0, 43, 1, 1]);
 // 100 DOLLR
 pub const MAXIMUM_DOLR_TREASURY_PER_DAY_PER_USER: u64 = 100 * 1e8 as u64;
 // 400 DOLLR
 pub const USER_INDEX_FUND_AMOUNT: u64 = 400 * 1e8 as u64;
+// 10,000 DOLLR
+pub const MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY: u64 = 10_000 * 1e8 as u64;
+// 500 DOLLR
+pub const MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY_PER_USER: u64 = 500 * 1e8 as u64;
+pub const TREASURY_REFILL_AUDIT_LOG_LIMIT: usize = 100;
+/// canister mappings and token roots never change once set
+pub const WS_BACKEND_CACHE_TTL_SECS: u64 = 24 * 3600;
+/// KV minimum TTL, so newly created users & tokens are picked up quickly
+pub const WS_BACKEND_CACHE_NEGATIVE_TTL_SECS: u64 = 60;
+/// 5 claims per minute per user
+pub const CLAIM_RATE_LIMIT_MAX_REQUESTS: u32 = 5;
+pub const CLAIM_RATE_LIMIT_WINDOW_MS: u64 = 60 * 1000;
+pub const FRAUD_BET_CADENCE_WINDOW_MS: u64 = 60 * 1000;
+pub const FRAUD_DEFAULT_MAX_BETS_PER_WINDOW: u64 = 60;
+pub const FRAUD_DEFAULT_MAX_WIN_RATE: f64 = 0.9;
+pub const FRAUD_DEFAULT_MIN_GAMES_FOR_WIN_RATE: u64 = 20;
+pub const RECENT_ROUND_SUMMARIES_LIMIT: usize = 20;
@@ -8,7 +8,7 @@ use std::{
 
 use crate::{
     backend_impl::{GameBackend, GameBackendImpl},
-    consts::{GDOLLR_TO_E8S, TIDE_SHIFT_DELTA},
+    consts::{GDOLLR_TO_E8S, RECENT_ROUND_SUMMARIES_LIMIT, TIDE_SHIFT_DELTA},
     user_reconciler::{AddRewardReq, DecrementReq, StateDiff},
     utils::{metrics, CfMetricTx},
 };
@@ -22,7 +22,7 @@ use pump_n_dump_common::{
 use serde::{Deserialize, Serialize};
 use wasm_bindgen_futures::spawn_local;
 use worker::*;
-use worker_utils::{storage::SafeStorage, RequestInitBuilder};
+use worker_utils::{api_error::error_resp, storage::SafeStorage, RequestInitBuilder};
 use yral_metrics::metrics::tides_turned::TidesTurned;
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -32,6 +32,18 @@ pub struct TotalBetsInfo {
     pub round: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoundSummary {
+    pub round: u64,
+    pub direction: GameDirection,
+    pub pumps: u64,
+    pub dumps: u64,
+    pub reward_pool: Nat,
+    pub winning_bet_count: u64,
+    pub participants: u64,
+    pub ended_at: u64,
+}
+
 #[durable_object]
 pub struct GameState {
     state: State,
@@ -287,6 +299,15 @@ impl GameState {
         Ok(new_round)
     }
 
+    /// most recent round first
+    async fn recent_rounds(&self) -> Result<Vec<RoundSummary>> {
+        Ok(self
+            .storage()
+            .get("recent-rounds")
+            .await?
+            .unwrap_or_default())
+    }
+
     fn user_state_stub(&self, user: Principal) -> Result<Stub> {
         let user_state = self.env.durable_object("USER_EPHEMERAL_STATE")?;
         let user_state_obj = user_state.id_from_name(&user.to_string())?;
@@ -334,6 +355,7 @@ impl GameState {
         let rewards = RewardIter::new(pumps, dumps, game_creator, token_root, bets.clone());
 
         let winning_pool = pumps + dumps;
+        let mut recent_rounds = self.recent_rounds().await?;
         // cleanup
         let mut storage = self.storage();
         storage.delete_all().await?;
@@ -344,6 +366,23 @@ impl GameState {
         storage.put("total-pumps", &total_pumps).await?;
         storage.put("current-round", &round).await?;
 
+        let summary = RoundSummary {
+            round: round - 1,
+            direction: rewards.outcome,
+            pumps,
+            dumps,
+            reward_pool: rewards.reward_pool.clone(),
+            winning_bet_count: rewards.bet_cnt,
+            participants: bets.len() as u64,
+            ended_at: Date::now().as_millis(),
+        };
+        recent_rounds.insert(0, summary.clone());
+        recent_rounds.truncate(RECENT_ROUND_SUMMARIES_LIMIT);
+        storage.put("recent-rounds", &recent_rounds).await?;
+        if let Err(e) = self.broadcast_round_summary(summary) {
+            console_warn!("failed to broadcast round summary: {e}");
+        }
+
         let game_res = GameResult {
             direction: rewards.outcome,
             reward_pool: rewards.reward_pool.clone(),
@@ -571,7 +610,7 @@ impl DurableObject for GameState {
             .get_async("/bets/:user_canister", |_req, ctx| async move {
                 let user_canister_raw = ctx.param("user_canister").unwrap();
                 let Ok(user_canister) = Principal::from_text(user_canister_raw) else {
-                    return Response::error("Invalid user_canister", 400);
+                    return error_resp("Invalid user_canister", 400);
                 };
 
                 let this = ctx.data;
@@ -595,7 +634,7 @@ impl DurableObject for GameState {
                 |req, ctx| async move {
                     let upgrade = req.headers().get("Upgrade")?;
                     if upgrade.as_deref() != Some("websocket") {
-                        return Response::error("expected websocket", 400);
+                        return error_resp("expected websocket", 400);
                     }
                     let game_canister =
                         Principal::from_text(ctx.param("game_canister").unwrap()).unwrap();
@@ -636,6 +675,12 @@ impl DurableObject for GameState {
 
                 Response::from_json(&res)
             })
+            .get_async("/recent_rounds", |_req, ctx| async move {
+                let this = ctx.data;
+                let recent_rounds = this.recent_rounds().await?;
+
+                Response::from_json(&recent_rounds)
+            })
             .run(req, env)
             .await
     }
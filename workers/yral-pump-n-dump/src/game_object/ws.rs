@@ -9,7 +9,13 @@ use worker::{Result, WebSocket, WebSocketIncomingMessage};
 
 use crate::game_object::GameObjReq;
 
-use super::GameState;
+use super::{GameState, RoundSummary};
+
+/// sent alongside `WsResponse`s, which can't carry the summary
+#[derive(Serialize, Deserialize, Clone)]
+pub enum GameStateEvent {
+    RoundSummary(RoundSummary),
+}
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
 struct WsState {
@@ -71,6 +77,15 @@ impl GameState {
         Ok(())
     }
 
+    pub fn broadcast_round_summary(&self, summary: RoundSummary) -> Result<()> {
+        let event = GameStateEvent::RoundSummary(summary);
+        for ws in self.state.get_websockets() {
+            ws.send(&event)?;
+        }
+
+        Ok(())
+    }
+
     pub async fn handle_ws_message(
         &self,
         ws: &WebSocket,
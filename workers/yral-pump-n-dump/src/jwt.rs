@@ -1,4 +1,10 @@
+use worker_utils::jwt::{JwtPolicy, EXP_ROLLOUT_GRACE_UNTIL};
+
 pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
 MCowBQYDK2VwAyEAV+DJfztWOovpmCUcZ5Fram2BLOt2B4LIlzw2vogIqK4=
 -----END PUBLIC KEY-----";
 pub const JWT_AUD: &str = "pump-n-dump-worker";
+
+/// backend services are still rolling out expiring tokens, see `EXP_ROLLOUT_GRACE_UNTIL`
+pub const JWT_POLICY: JwtPolicy =
+    JwtPolicy::expiring(5 * 60).with_missing_exp_grace(EXP_ROLLOUT_GRACE_UNTIL);
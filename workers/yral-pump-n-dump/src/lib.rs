@@ -3,12 +3,13 @@ mod backend_impl;
 mod consts;
 mod game_object;
 mod jwt;
+mod treasury_controller;
 mod user_reconciler;
 mod utils;
 
 use backend_impl::{WsBackend, WsBackendImpl};
-use candid::Principal;
-use jwt::{JWT_AUD, JWT_PUBKEY};
+use candid::{Nat, Principal};
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
 use pump_n_dump_common::{
     rest::{claim_msg, ClaimReq},
     ws::identify_message,
@@ -16,9 +17,29 @@ use pump_n_dump_common::{
 use serde::{Deserialize, Serialize};
 use std::result::Result as StdResult;
 use user_reconciler::{ClaimGdollrReq, HotOrNotBetRequest};
-use utils::{game_state_stub, user_state_stub};
+use utils::{
+    game_state_name, GAME_STATE, TREASURY_CONTROLLER, TREASURY_CONTROLLER_NAME,
+    USER_EPHEMERAL_STATE,
+};
 use worker::*;
-use worker_utils::{jwt::verify_jwt_from_header, parse_principal, RequestInitBuilder};
+use worker_utils::{
+    analytics::{AnalyticsEvent, ANALYTICS_EVENTS_QUEUE},
+    api_error::error_resp,
+    cors::cors_for_env,
+    do_client::DoClient,
+    error_report::ErrorReporter,
+    flags::Flag,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    principals, require_flag, require_low_risk,
+    risk::{RiskSignal, RISK_SERVICE, RISK_SIGNALS_QUEUE},
+    secrets::SecretSet,
+    signed_req::{self, InvalidSignature, Signed},
+    time::now_millis,
+    RequestInitBuilder,
+};
 use yral_canisters_common::utils::vote::{verifiable_hon_bet_message, VerifiableHonBetReq};
 use yral_identity::Signature;
 
@@ -28,163 +49,217 @@ pub struct GameWsQuery {
     signature: String,
 }
 
-fn verify_claim_req(req: &ClaimReq) -> StdResult<(), (String, u16)> {
-    let msg = claim_msg(req.amount.clone());
-
-    let verify_res = req.signature.clone().verify_identity(req.sender, msg);
-    if verify_res.is_err() {
-        return Err(("invalid signature".into(), 401));
-    }
-
-    Ok(())
+fn verify_claim_req(req: &ClaimReq) -> StdResult<(), InvalidSignature> {
+    signed_req::verify(&Signed {
+        sender: req.sender,
+        signature: &req.signature,
+        message: claim_msg(req.amount.clone()),
+    })
 }
 
-// TODO write an abstraction around verification
-fn verify_hot_or_not_bet_req(req: &VerifiableHonBetReq) -> StdResult<(), (String, u16)> {
-    let msg = verifiable_hon_bet_message(req.args);
-
-    let verify_res = req.signature.clone().verify_identity(req.sender, msg);
-    if verify_res.is_err() {
-        return Err(("invalid signature".into(), 401));
-    }
-
-    Ok(())
+fn verify_hot_or_not_bet_req(req: &VerifiableHonBetReq) -> StdResult<(), InvalidSignature> {
+    signed_req::verify(&Signed {
+        sender: req.sender,
+        signature: &req.signature,
+        message: verifiable_hon_bet_message(req.args),
+    })
 }
 
 async fn place_hot_or_not_bet(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let req: VerifiableHonBetReq = serde_json::from_str(&req.text().await?)?;
-    if let Err((msg, status)) = verify_hot_or_not_bet_req(&req) {
-        return Response::error(msg, status);
+    let req: VerifiableHonBetReq = json_body!(req);
+    if let Err(e) = verify_hot_or_not_bet_req(&req) {
+        return e.into_response();
     }
     let backend = WsBackend::new(&ctx.env)?;
 
     let Some(user_canister) = backend.user_principal_to_user_canister(req.sender).await? else {
-        return Response::error("user not found", 404);
+        return error_resp("user not found", 404);
     };
 
-    let user_state = user_state_stub(&ctx, user_canister)?;
-
     let body = HotOrNotBetRequest {
         user_canister,
         args: req.args,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/place_hot_or_not_bet",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&body)?
-            .build(),
-    )?;
+    DoClient::new(&ctx.env)
+        .post(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            "place_hot_or_not_bet",
+            &body,
+        )
+        .await
+}
 
-    user_state.fetch_with_request(req).await
+/// best effort, a failed send only loses the event from the rollups and the risk score
+async fn record_claim(env: &Env, res: &Response, user_principal: Principal, amount: &Nat) {
+    if res.status_code() >= 300 {
+        return;
+    }
+    let at = now_millis();
+    let event = AnalyticsEvent::Claim {
+        user_principal: user_principal.to_text(),
+        amount: amount.0.to_string(),
+        at,
+        trace_id: None,
+    };
+    if let Err(e) = event.send(env).await {
+        console_error!("failed to send claim analytics event: {e}");
+    }
+    let signal = RiskSignal::Withdrawal {
+        user_principal: user_principal.to_text(),
+        economy: "dolr".into(),
+        amount: amount.0.to_string(),
+        at,
+    };
+    if let Err(e) = signal.send(env).await {
+        console_warn!("failed to send withdrawal risk signal: {e}");
+    }
 }
 
+/// kill switch for payouts, guards both claim routes so v1 can't be used
+/// to go around it, see worker-utils/src/flags.rs
+static CLAIM_V2: Flag = Flag::new("pnd_claim_v2").default_on();
+
 async fn claim_gdollr(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let req: ClaimReq = serde_json::from_str(&req.text().await?)?;
-    if let Err((msg, status)) = verify_claim_req(&req) {
-        return Response::error(msg, status);
+    let req: ClaimReq = json_body!(req);
+    if let Err(e) = verify_claim_req(&req) {
+        return e.into_response();
     }
+    require_low_risk!(ctx.env, req.sender);
+    require_flag!(ctx.env, CLAIM_V2, req.sender);
     let backend = WsBackend::new(&ctx.env)?;
 
     let Some(user_canister) = backend.user_principal_to_user_canister(req.sender).await? else {
-        return Response::error("user not found", 404);
+        return error_resp("user not found", 404);
     };
-    let bal_stub = user_state_stub(&ctx, user_canister)?;
-
     let body = ClaimGdollrReq {
         user_canister,
         amount: req.amount,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/claim_gdollr",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&body)?
-            .build(),
-    )?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            "claim_gdollr",
+            &body,
+        )
+        .await?;
+    record_claim(&ctx.env, &res, req.sender, &body.amount).await;
 
-    bal_stub.fetch_with_request(req).await
+    Ok(res)
 }
 
 async fn claim_gdolr_v2(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     }
 
-    let req: ClaimReq = serde_json::from_str(&req.text().await?)?;
-    if let Err((msg, status)) = verify_claim_req(&req) {
-        return Response::error(msg, status);
+    let req: ClaimReq = json_body!(req);
+    if let Err(e) = verify_claim_req(&req) {
+        return e.into_response();
     }
+    require_low_risk!(ctx.env, req.sender);
+    require_flag!(ctx.env, CLAIM_V2, req.sender);
     let backend = WsBackend::new(&ctx.env)?;
 
     let Some(user_canister) = backend.user_principal_to_user_canister(req.sender).await? else {
-        return Response::error("user not found", 404);
+        return error_resp("user not found", 404);
     };
-    let bal_stub = user_state_stub(&ctx, user_canister)?;
-
     let body = ClaimGdollrReq {
         user_canister,
         amount: req.amount,
     };
 
-    let req = Request::new_with_init(
-        "http://fake_url.com/claim_gdollr_v2",
-        RequestInitBuilder::default()
-            .method(Method::Post)
-            .json(&body)?
-            .build(),
-    )?;
+    let res = DoClient::new(&ctx.env)
+        .post(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            "claim_gdollr_v2",
+            &body,
+        )
+        .await?;
+    record_claim(&ctx.env, &res, req.sender, &body.amount).await;
 
-    bal_stub.fetch_with_request(req).await
+    Ok(res)
 }
 
 async fn user_balance(ctx: RouteContext<()>) -> Result<Response> {
-    let user_canister = parse_principal!(ctx, "user_canister");
+    let user_canister = principals!(ctx, "user_canister");
 
-    let bal_stub = user_state_stub(&ctx, user_canister)?;
-
-    let res = bal_stub
-        .fetch_with_str(&format!("http://fake_url.com/balance/{user_canister}"))
+    let res = DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            &format!("balance/{user_canister}"),
+        )
         .await?;
 
     Ok(res)
 }
 
 async fn user_balance_v2(ctx: RouteContext<()>) -> Result<Response> {
-    let user_canister = parse_principal!(ctx, "user_canister");
-
-    let bal_stub = user_state_stub(&ctx, user_canister)?;
+    let user_canister = principals!(ctx, "user_canister");
 
-    let res = bal_stub
-        .fetch_with_str(&format!("http://fake_url.com/balance_v2/{user_canister}"))
+    let res = DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            &format!("balance_v2/{user_canister}"),
+        )
         .await?;
 
     Ok(res)
 }
 
 async fn user_game_count(ctx: RouteContext<()>) -> Result<Response> {
-    let user_canister = parse_principal!(ctx, "user_canister");
-
-    let state_stub = user_state_stub(&ctx, user_canister)?;
+    let user_canister = principals!(ctx, "user_canister");
 
-    let res = state_stub
-        .fetch_with_str(&format!("http://fake_url.com/game_count/{user_canister}"))
+    let res = DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            &format!("game_count/{user_canister}"),
+        )
         .await?;
 
     Ok(res)
 }
 
-async fn user_bets_for_game(ctx: RouteContext<()>) -> Result<Response> {
-    let game_canister = parse_principal!(ctx, "game_canister");
-    let token_root = parse_principal!(ctx, "token_root");
-    let user_canister = parse_principal!(ctx, "user_canister");
+/// the user's object next to their canister, keyed by principal for yral-reconciler
+async fn user_reconciliation(ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+    let backend = WsBackend::new(&ctx.env)?;
+    let Some(user_canister) = backend
+        .user_principal_to_user_canister(user_principal)
+        .await?
+    else {
+        return error_resp("user not found", 404);
+    };
 
-    let game_stub = game_state_stub(&ctx, game_canister, token_root)?;
+    DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            &format!("reconciliation/{user_canister}"),
+        )
+        .await
+}
 
-    game_stub
-        .fetch_with_str(&format!("http://fake_url.com/bets/{user_canister}"))
+async fn user_bets_for_game(ctx: RouteContext<()>) -> Result<Response> {
+    let (game_canister, token_root, user_canister) =
+        principals!(ctx, "game_canister", "token_root", "user_canister");
+
+    DoClient::new(&ctx.env)
+        .get(
+            GAME_STATE,
+            &game_state_name(game_canister, token_root),
+            &format!("bets/{user_canister}"),
+        )
         .await
 }
 
@@ -192,130 +267,228 @@ fn verify_identify_req(
     game_canister: Principal,
     token_root: Principal,
     sender: Principal,
-    signature: Signature,
-) -> StdResult<(), String> {
-    let msg = identify_message(game_canister, token_root);
-
-    let verify_res = signature.clone().verify_identity(sender, msg);
-    if verify_res.is_err() {
-        return Err("invalid signature".into());
-    }
-
-    Ok(())
+    signature: &Signature,
+) -> StdResult<(), InvalidSignature> {
+    signed_req::verify(&Signed {
+        sender,
+        signature,
+        message: identify_message(game_canister, token_root),
+    })
 }
 
 async fn estabilish_game_ws(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let game_canister = parse_principal!(ctx, "game_canister");
-    let token_root = parse_principal!(ctx, "token_root");
+    let (game_canister, token_root) = principals!(ctx, "game_canister", "token_root");
 
     let raw_query: GameWsQuery = req.query()?;
     let Ok(sender) = Principal::from_text(&raw_query.sender) else {
-        return Response::error("invalid sender", 400);
+        return error_resp("invalid sender", 400);
     };
     let Ok(signature) = serde_json::from_str::<Signature>(&raw_query.signature) else {
-        return Response::error("invalid signature", 400);
+        return error_resp("invalid signature", 400);
     };
 
-    if let Err(e) = verify_identify_req(game_canister, token_root, sender, signature) {
-        return Response::error(e, 403);
+    if verify_identify_req(game_canister, token_root, sender, &signature).is_err() {
+        return error_resp("invalid signature", 403);
     }
 
     let ws_backend = WsBackend::new(&ctx.env)?;
 
     let Some(user_canister) = ws_backend.user_principal_to_user_canister(sender).await? else {
-        return Response::error("invalid user_canister", 400);
+        return error_resp("invalid user_canister", 400);
     };
 
     let token_valid = ws_backend.validate_token(token_root, game_canister).await?;
     if !token_valid {
-        return Response::error("invalid token", 400);
+        return error_resp("invalid token", 400);
     }
-    let game_stub = game_state_stub(&ctx, game_canister, token_root)?;
-
-    let mut url = Url::parse(&format!(
-        "http://fakeurl.com/ws/{game_canister}/{token_root}/{user_canister}"
-    ))?;
-
-    url.set_query(Some(&format!("sender={}", raw_query.sender)));
-    url.set_query(Some(&format!("signature={}", raw_query.signature)));
-    let headers = Headers::new();
-    headers.set("Upgrade", "websocket")?;
-    let new_req = Request::new_with_init(
-        dbg!(url.as_str()),
-        RequestInitBuilder::default()
-            .method(Method::Get)
-            .replace_headers(headers)
-            .build(),
-    )?;
-
-    game_stub.fetch_with_request(new_req).await.inspect(|res| {
-        console_log!("fetch with req: {}", res.status_code());
-    })
+    DoClient::new(&ctx.env)
+        .fetch(
+            GAME_STATE,
+            &game_state_name(game_canister, token_root),
+            &format!("ws/{game_canister}/{token_root}/{user_canister}"),
+            RequestInitBuilder::default()
+                .method(Method::Get)
+                .header("Upgrade", "websocket")?
+                .query(&[
+                    ("sender", &raw_query.sender),
+                    ("signature", &raw_query.signature),
+                ]),
+        )
+        .await
+        .inspect(|res| {
+            console_log!("fetch with req: {}", res.status_code());
+        })
 }
 
 async fn player_count(ctx: RouteContext<()>) -> Result<Response> {
-    let game_canister = parse_principal!(ctx, "game_canister");
-    let token_root = parse_principal!(ctx, "token_root");
+    let (game_canister, token_root) = principals!(ctx, "game_canister", "token_root");
+
+    DoClient::new(&ctx.env)
+        .get(
+            GAME_STATE,
+            &game_state_name(game_canister, token_root),
+            "player_count",
+        )
+        .await
+}
 
-    let game_stub = game_state_stub(&ctx, game_canister, token_root)?;
+async fn recent_rounds(ctx: RouteContext<()>) -> Result<Response> {
+    let (game_canister, token_root) = principals!(ctx, "game_canister", "token_root");
 
-    game_stub
-        .fetch_with_str("http://fake_url.com/player_count")
+    DoClient::new(&ctx.env)
+        .get(
+            GAME_STATE,
+            &game_state_name(game_canister, token_root),
+            "recent_rounds",
+        )
         .await
 }
 
 async fn net_earnings(ctx: RouteContext<()>) -> Result<Response> {
-    let user_canister = parse_principal!(ctx, "user_canister");
-
-    let state_stub = user_state_stub(&ctx, user_canister)?;
+    let user_canister = principals!(ctx, "user_canister");
 
-    state_stub
-        .fetch_with_str(&format!("http://fake_url.com/earnings/{user_canister}"))
+    DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            &format!("earnings/{user_canister}"),
+        )
         .await
 }
 
 async fn uncommitted_games(ctx: RouteContext<()>) -> Result<Response> {
-    let user_canister = parse_principal!(ctx, "user_canister");
+    let user_canister = principals!(ctx, "user_canister");
 
-    let state_stub = user_state_stub(&ctx, user_canister)?;
-
-    state_stub
-        .fetch_with_str(&format!(
-            "http://fake_url.com/uncommitted_games/{user_canister}"
-        ))
+    DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            &format!("uncommitted_games/{user_canister}"),
+        )
         .await
 }
 
 async fn total_bets_info(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Err((msg, code)) = verify_jwt_from_header(JWT_PUBKEY, JWT_AUD.into(), &req) {
-        return Response::error(msg, code);
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
     }
 
-    let game_canister = parse_principal!(ctx, "game_canister");
-    let token_root = parse_principal!(ctx, "token_root");
+    let (game_canister, token_root) = principals!(ctx, "game_canister", "token_root");
 
-    let game_stub = game_state_stub(&ctx, game_canister, token_root)?;
+    DoClient::new(&ctx.env)
+        .get(
+            GAME_STATE,
+            &game_state_name(game_canister, token_root),
+            "total_bets_info",
+        )
+        .await
+}
 
-    game_stub
-        .fetch_with_str("http://fake_url.com/total_bets_info")
+async fn fraud_events(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    let user_canister = principals!(ctx, "user_canister");
+
+    DoClient::new(&ctx.env)
+        .get(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            "fraud_events",
+        )
         .await
 }
 
-fn cors_policy() -> Cors {
-    Cors::new()
-        .with_origins(["*"])
-        .with_methods([Method::Head, Method::Get, Method::Post, Method::Options])
-        .with_allowed_headers(vec!["*"])
-        .with_max_age(86400)
+async fn clear_fraud_review(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    let user_canister = principals!(ctx, "user_canister");
+
+    DoClient::new(&ctx.env)
+        .fetch(
+            USER_EPHEMERAL_STATE,
+            &user_canister.to_text(),
+            "clear_fraud_review",
+            RequestInitBuilder::default().method(Method::Post),
+        )
+        .await
+}
+
+async fn treasury_refill_log(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+
+    DoClient::new(&ctx.env)
+        .get(TREASURY_CONTROLLER, TREASURY_CONTROLLER_NAME, "audit_log")
+        .await
 }
 
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-pump-n-dump",
+    &[
+        Dependency::DurableObject(GAME_STATE),
+        Dependency::DurableObject(USER_EPHEMERAL_STATE),
+        Dependency::DurableObject(TREASURY_CONTROLLER),
+        Dependency::Kv("WS_BACKEND_CACHE"),
+        Dependency::Queue(ANALYTICS_EVENTS_QUEUE),
+        Dependency::Queue(RISK_SIGNALS_QUEUE),
+        Dependency::Service(RISK_SERVICE),
+        Dependency::Secret("BACKEND_ADMIN_KEY"),
+    ],
+);
+
+const REQUIRED_SECRETS: &[&str] = &["BACKEND_ADMIN_KEY"];
+
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    console_error_panic_hook::set_once();
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let reporter = ErrorReporter::install(&env, "yral-pump-n-dump").with_request(&req);
+
+    if let Err(e) = SecretSet::validate(&env, REQUIRED_SECRETS) {
+        return e.into_response();
+    }
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    // fraud reviews are cleared by admins
+    if let Some(notice) = MaintenanceNotice::check(
+        &env,
+        "yral-pump-n-dump",
+        method.as_ref(),
+        &path,
+        &["/clear_fraud_review/"],
+    )
+    .await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
 
     let router = Router::new();
 
     let res = router
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
         .post_async("/claim_gdollr", claim_gdollr)
         .post_async("/claim_gdolr_v2", claim_gdolr_v2)
         .post_async("/place_hot_or_not_bet", place_hot_or_not_bet)
@@ -336,7 +509,13 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         .get_async("/player_count/:game_canister/:token_root", |_req, ctx| {
             player_count(ctx)
         })
+        .get_async("/recent_rounds/:game_canister/:token_root", |_req, ctx| {
+            recent_rounds(ctx)
+        })
         .get_async("/earnings/:user_canister", |_req, ctx| net_earnings(ctx))
+        .get_async("/reconciliation/:user_principal", |_req, ctx| {
+            user_reconciliation(ctx)
+        })
         .get_async("/uncommitted_games/:user_canister", |_req, ctx| {
             uncommitted_games(ctx)
         })
@@ -344,9 +523,13 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
             "/total_bets_info/:game_canister/:token_root",
             total_bets_info,
         )
+        .get_async("/treasury_refill_log", treasury_refill_log)
+        .get_async("/fraud_events/:user_canister", fraud_events)
+        .post_async("/clear_fraud_review/:user_canister", clear_fraud_review)
         .options("/*catchall", |_, _| Response::empty())
         .run(req, env)
-        .await?;
+        .await;
+    let res = reporter.check(&ctx, res)?;
 
-    res.with_cors(&cors_policy())
+    cors.apply(&path, origin.as_deref(), res)
 }
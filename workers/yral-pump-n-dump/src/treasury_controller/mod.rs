@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+
+use candid::{Nat, Principal};
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    storage::{cumulative_limit::CumulativeLimit, SafeStorage},
+};
+
+use crate::consts::{
+    MAXIMUM_DOLR_TREASURY_PER_DAY_PER_USER, MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY,
+    MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY_PER_USER, TREASURY_REFILL_AUDIT_LOG_LIMIT,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreasuryRefillReq {
+    pub user_canister: Principal,
+    pub amount: Nat,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreasuryRefillRes {
+    pub granted: Nat,
+}
+
+/// the platform wide allowance, read by yral-ops
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreasuryStatus {
+    /// DOLR e8s left in the current window
+    pub remaining: u64,
+    pub max: u64,
+    /// unix millis
+    pub resets_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreasuryRefillAuditEntry {
+    pub user_canister: Principal,
+    pub requested: Nat,
+    pub granted: bool,
+    pub reason: Option<String>,
+    pub timestamp: u64,
+}
+
+/// Global controller for the per-user DOLR treasury
+/// a single instance holds the platform wide refill allowance
+#[durable_object]
+pub struct TreasuryController {
+    state: State,
+    env: Env,
+    refill_limit: RefCell<CumulativeLimit>,
+}
+
+// SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+// because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+// The RefCell interior mutability pattern is required due to Worker 0.7.4 API changes
+// that mandate `&self` instead of `&mut self` for DurableObject trait methods.
+#[allow(clippy::await_holding_refcell_ref)]
+impl TreasuryController {
+    fn storage(&self) -> SafeStorage {
+        self.state.storage().into()
+    }
+
+    async fn audit(&self, entry: TreasuryRefillAuditEntry) -> Result<()> {
+        // keys sort by time, the nonce keeps same millisecond refills for one user apart
+        let key = format!(
+            "refill-audit-{:020}-{}-{}",
+            entry.timestamp,
+            entry.user_canister,
+            Uuid::new_v4().simple()
+        );
+        self.storage().put(key, &entry).await
+    }
+
+    fn user_refill_limit(user_canister: Principal) -> CumulativeLimit {
+        CumulativeLimit::daily(
+            format!("user-refill-limit-{user_canister}"),
+            MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY_PER_USER,
+        )
+    }
+
+    async fn request_refill(&self, req: TreasuryRefillReq) -> Result<Response> {
+        let mut entry = TreasuryRefillAuditEntry {
+            user_canister: req.user_canister,
+            requested: req.amount.clone(),
+            granted: false,
+            reason: None,
+            timestamp: Date::now().as_millis(),
+        };
+
+        if req.amount.0 > BigUint::from(MAXIMUM_DOLR_TREASURY_PER_DAY_PER_USER) {
+            entry.reason = Some("refill amount too large".into());
+            self.audit(entry).await?;
+            return error_resp("refill amount too large", 400);
+        }
+
+        // the user's own window comes first, nothing is staged unless both limits allow it
+        let mut storage = self.storage();
+        let mut batch = storage.batch();
+        let mut user_limit = Self::user_refill_limit(req.user_canister);
+        let res = user_limit
+            .try_consume_with_max_in(
+                &storage,
+                &mut batch,
+                req.amount.0.clone(),
+                MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY_PER_USER,
+            )
+            .await;
+        if let Err(e) = res {
+            entry.reason = Some(e.to_string());
+            self.audit(entry).await?;
+            return error_resp("user treasury refill limit reached", 429);
+        }
+
+        let mut refill_limit = self.refill_limit.borrow_mut();
+        let max = refill_limit.max();
+        let res = refill_limit
+            .try_consume_with_max_in(&storage, &mut batch, req.amount.0.clone(), max)
+            .await;
+        if let Err(e) = res {
+            drop(refill_limit);
+            entry.reason = Some(e.to_string());
+            self.audit(entry).await?;
+            return error_resp("global treasury limit reached", 429);
+        }
+        if let Err(e) = batch.commit(&mut storage).await {
+            refill_limit.invalidate();
+            return Err(e);
+        }
+        drop(refill_limit);
+
+        entry.granted = true;
+        self.audit(entry).await?;
+
+        Response::from_json(&TreasuryRefillRes {
+            granted: req.amount,
+        })
+    }
+
+    async fn status(&self) -> Result<TreasuryStatus> {
+        let mut limit = self.refill_limit.borrow_mut();
+        let (remaining, resets_at) = limit.remaining(&self.storage()).await?;
+        let max = limit.max();
+
+        Ok(TreasuryStatus {
+            remaining: u64::try_from(remaining).unwrap_or(max),
+            max,
+            resets_at,
+        })
+    }
+
+    async fn audit_log(&self) -> Result<Vec<TreasuryRefillAuditEntry>> {
+        self.storage()
+            .list_with_options(
+                ListOptions::new()
+                    .prefix("refill-audit-")
+                    .reverse(true)
+                    .limit(TREASURY_REFILL_AUDIT_LOG_LIMIT),
+            )
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect()
+    }
+}
+
+impl DurableObject for TreasuryController {
+    fn new(state: State, env: Env) -> Self {
+        console_error_panic_hook::set_once();
+
+        Self {
+            state,
+            env,
+            refill_limit: RefCell::new(CumulativeLimit::daily(
+                "global-refill-limit",
+                MAXIMUM_DOLR_TREASURY_REFILL_PER_DAY,
+            )),
+        }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let env = self.env.clone();
+        let router = Router::with_data(self);
+
+        router
+            .post_async("/request_refill", |mut req, ctx| async move {
+                let this = ctx.data;
+                let refill_req: TreasuryRefillReq = req.json().await?;
+
+                this.request_refill(refill_req).await
+            })
+            .get_async("/audit_log", |_req, ctx| async move {
+                let this = ctx.data;
+                let log = this.audit_log().await?;
+
+                Response::from_json(&log)
+            })
+            .get_async("/status", |_req, ctx| async move {
+                let this = ctx.data;
+                let status = this.status().await?;
+
+                Response::from_json(&status)
+            })
+            .run(req, env)
+            .await
+    }
+}
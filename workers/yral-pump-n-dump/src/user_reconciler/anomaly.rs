@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use worker::{console_error, Date, Env, Result};
+use worker_utils::storage::{SafeStorage, StorageCell};
+
+use crate::consts::{
+    FRAUD_BET_CADENCE_WINDOW_MS, FRAUD_DEFAULT_MAX_BETS_PER_WINDOW, FRAUD_DEFAULT_MAX_WIN_RATE,
+    FRAUD_DEFAULT_MIN_GAMES_FOR_WIN_RATE,
+};
+
+/// thresholds can be overridden through worker vars
+#[derive(Clone, Copy, Debug)]
+pub struct AnomalyThresholds {
+    pub max_bets_per_window: u64,
+    pub max_win_rate: f64,
+    pub min_games_for_win_rate: u64,
+    pub review_required: bool,
+}
+
+impl AnomalyThresholds {
+    pub fn from_env(env: &Env) -> Self {
+        fn var<T: std::str::FromStr>(env: &Env, name: &str) -> Option<T> {
+            env.var(name).ok()?.to_string().parse().ok()
+        }
+
+        Self {
+            max_bets_per_window: var(env, "FRAUD_MAX_BETS_PER_MINUTE")
+                .unwrap_or(FRAUD_DEFAULT_MAX_BETS_PER_WINDOW),
+            max_win_rate: var(env, "FRAUD_MAX_WIN_RATE").unwrap_or(FRAUD_DEFAULT_MAX_WIN_RATE),
+            min_games_for_win_rate: var(env, "FRAUD_MIN_GAMES_FOR_WIN_RATE")
+                .unwrap_or(FRAUD_DEFAULT_MIN_GAMES_FOR_WIN_RATE),
+            review_required: var(env, "FRAUD_REVIEW_REQUIRED").unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum FraudReason {
+    BetCadence { bets: u64, window_ms: u64 },
+    WinRate { wins: u64, games: u64 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FraudEvent {
+    pub reason: FraudReason,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BetActivity {
+    window_start: u64,
+    bets_in_window: u64,
+    games: u64,
+    wins: u64,
+    pub flagged: bool,
+}
+
+pub struct AnomalyDetector {
+    activity: StorageCell<BetActivity>,
+    thresholds: AnomalyThresholds,
+}
+
+impl AnomalyDetector {
+    pub fn new(thresholds: AnomalyThresholds) -> Self {
+        Self {
+            activity: StorageCell::new("bet-activity", BetActivity::default),
+            thresholds,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.activity.invalidate();
+    }
+
+    async fn flag(&mut self, storage: &mut SafeStorage, reason: FraudReason) -> Result<()> {
+        let event = FraudEvent {
+            reason,
+            timestamp: Date::now().as_millis(),
+        };
+        console_error!("fraud event: {event:?}");
+        storage
+            .put(format!("fraud-event-{:020}", event.timestamp), &event)
+            .await?;
+
+        self.activity
+            .update(storage, |activity| activity.flagged = true)
+            .await
+    }
+
+    pub async fn record_bet(&mut self, storage: &mut SafeStorage) -> Result<()> {
+        let now = Date::now().as_millis();
+        let mut bets = 0;
+        self.activity
+            .update(storage, |activity| {
+                if now - FRAUD_BET_CADENCE_WINDOW_MS >= activity.window_start {
+                    activity.window_start = now;
+                    activity.bets_in_window = 0;
+                }
+                activity.bets_in_window += 1;
+                bets = activity.bets_in_window;
+            })
+            .await?;
+
+        // only flag once per window
+        if bets != self.thresholds.max_bets_per_window + 1 {
+            return Ok(());
+        }
+
+        self.flag(
+            storage,
+            FraudReason::BetCadence {
+                bets,
+                window_ms: FRAUD_BET_CADENCE_WINDOW_MS,
+            },
+        )
+        .await
+    }
+
+    pub async fn record_game(&mut self, storage: &mut SafeStorage, won: bool) -> Result<()> {
+        let mut stats = (0, 0, false);
+        self.activity
+            .update(storage, |activity| {
+                activity.games += 1;
+                activity.wins += won as u64;
+                stats = (activity.games, activity.wins, activity.flagged);
+            })
+            .await?;
+
+        let (games, wins, flagged) = stats;
+        if flagged || games < self.thresholds.min_games_for_win_rate {
+            return Ok(());
+        }
+        if (wins as f64 / games as f64) <= self.thresholds.max_win_rate {
+            return Ok(());
+        }
+
+        self.flag(storage, FraudReason::WinRate { wins, games })
+            .await
+    }
+
+    /// whether claims must wait for a manual review
+    pub async fn review_pending(&mut self, storage: &SafeStorage) -> Result<bool> {
+        if !self.thresholds.review_required {
+            return Ok(false);
+        }
+
+        Ok(self.activity.read(storage).await?.flagged)
+    }
+
+    pub async fn clear_review(&mut self, storage: &mut SafeStorage) -> Result<()> {
+        self.activity
+            .update(storage, |activity| {
+                activity.flagged = false;
+                activity.games = 0;
+                activity.wins = 0;
+            })
+            .await
+    }
+
+    pub async fn events(&self, storage: &SafeStorage) -> Result<Vec<FraudEvent>> {
+        storage
+            .list_with_prefix("fraud-event-")
+            .await
+            .map(|v| v.map(|v| v.1))
+            .collect()
+    }
+}
@@ -0,0 +1,119 @@
+//! how the off chain balance delta moves, the part of the object's routes that only touches storage
+//!
+//! effective balance = on chain balance + delta
+
+use num_bigint::{BigInt, ToBigInt};
+use worker_utils::storage::StorageCell;
+
+use super::StateDiff;
+use crate::consts::GDOLLR_TO_E8S;
+
+pub(super) fn balance_delta() -> StorageCell<BigInt> {
+    StorageCell::new("off_chain_balance_delta", || BigInt::from(0))
+}
+
+/// a bet holds one gdollr until its game settles
+pub(super) fn bet_delta() -> BigInt {
+    -BigInt::from(GDOLLR_TO_E8S)
+}
+
+/// a completed game or creator reward is paid into the balance right away
+pub(super) fn reward_delta(state_diff: &StateDiff) -> BigInt {
+    BigInt::from(state_diff.reward())
+}
+
+/// once settled the canister holds the bets and pays the rewards, so both leave the delta
+pub(super) fn settled_delta(state_diffs: &[StateDiff]) -> BigInt {
+    let mut delta = BigInt::from(0u32);
+    for diff in state_diffs {
+        match diff {
+            StateDiff::CompletedGame(info) => {
+                delta += BigInt::from(info.pumps + info.dumps) * GDOLLR_TO_E8S;
+                delta -= info.reward.clone().0.to_bigint().unwrap();
+            }
+            StateDiff::CreatorReward(rew) => {
+                delta -= rew.clone().0.to_bigint().unwrap();
+            }
+        }
+    }
+
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{Nat, Principal};
+    use pump_n_dump_common::{rest::CompletedGameInfo, GameDirection};
+    use worker_utils::{
+        storage::SafeStorage,
+        testing::{block_on, DoHarness},
+    };
+
+    use super::*;
+
+    fn gdollrs(n: u64) -> Nat {
+        Nat::from(n * GDOLLR_TO_E8S)
+    }
+
+    /// `/decrement`, one bet
+    async fn bet(storage: &mut SafeStorage, _: ()) -> worker::Result<BigInt> {
+        let mut delta = balance_delta();
+        delta.update(storage, |delta| *delta += bet_delta()).await?;
+        delta.read(storage).await.cloned()
+    }
+
+    /// `/add_reward`
+    async fn add_reward(storage: &mut SafeStorage, diff: StateDiff) -> worker::Result<BigInt> {
+        let mut delta = balance_delta();
+        delta
+            .update(storage, |delta| *delta += reward_delta(&diff))
+            .await?;
+        delta.read(storage).await.cloned()
+    }
+
+    /// `/settle`, with the canister accepting every diff
+    async fn settle(storage: &mut SafeStorage, diffs: Vec<StateDiff>) -> worker::Result<BigInt> {
+        let mut delta = balance_delta();
+        delta
+            .update(storage, |delta| *delta += settled_delta(&diffs))
+            .await?;
+        delta.read(storage).await.cloned()
+    }
+
+    #[test]
+    fn settlement_hands_the_whole_delta_to_the_canister() {
+        let mut harness = DoHarness::default();
+        for _ in 0..3 {
+            harness.call(&(), bet).unwrap();
+        }
+        let game = StateDiff::CompletedGame(CompletedGameInfo {
+            pumps: 2,
+            dumps: 1,
+            reward: gdollrs(5),
+            token_root: Principal::anonymous(),
+            outcome: GameDirection::Pump,
+        });
+        let creator = StateDiff::CreatorReward(gdollrs(1));
+
+        harness.call(&game, add_reward).unwrap();
+        let before = harness.call(&creator, add_reward).unwrap();
+        assert_eq!(before, BigInt::from(3 * GDOLLR_TO_E8S));
+
+        let after = harness.call(&vec![game, creator], settle).unwrap();
+        assert_eq!(after, BigInt::from(0));
+    }
+
+    #[test]
+    fn unsettled_bets_stay_held() {
+        let mut harness = DoHarness::default();
+        harness.call(&(), bet).unwrap();
+        harness.call(&(), bet).unwrap();
+
+        let after = harness.call(&Vec::<StateDiff>::new(), settle).unwrap();
+        assert_eq!(after, BigInt::from(2) * bet_delta());
+        let stored = block_on(balance_delta().read(&harness.storage))
+            .unwrap()
+            .clone();
+        assert_eq!(stored, after);
+    }
+}
@@ -0,0 +1,84 @@
+//! moving a user's cents to another economy, driven by yral-admin's balance migrations
+
+use candid::{Nat, Principal};
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use super::UserEphemeralState;
+
+/// set once any balance was migrated out, bets and claims are refused from then on
+const BALANCE_MIGRATED_KEY: &str = "balance_migrated";
+const MIGRATED_OUT_PREFIX: &str = "migrated-out-";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MigrateOutReq {
+    pub user_canister: Principal,
+    /// a retried request with the same id debits once
+    pub migration_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MigratedOut {
+    pub migration_id: String,
+    /// effective balance at the time, debited in full
+    pub amount: Nat,
+    /// unix millis
+    pub at: u64,
+}
+
+// SAFETY: See comment on the impl block in mod.rs for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl UserEphemeralState {
+    pub(super) async fn balance_migrated(&self) -> Result<bool> {
+        Ok(self
+            .storage()
+            .get::<bool>(BALANCE_MIGRATED_KEY)
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// debits the whole effective balance, the caller credits it elsewhere
+    ///
+    /// the debit stays in the off chain delta, the canister balance is retired with the economy
+    pub(super) async fn migrate_out(
+        &self,
+        user_canister: Principal,
+        migration_id: String,
+    ) -> Result<MigratedOut> {
+        let mut storage = self.storage();
+        let marker_key = format!("{MIGRATED_OUT_PREFIX}{migration_id}");
+        if let Some(migrated) = storage.get::<MigratedOut>(&marker_key).await? {
+            return Ok(migrated);
+        }
+
+        // completed games are part of the balance once they're settled
+        self.ensure_state_diffs_loaded().await?;
+        if !self.state_diffs.borrow().as_ref().unwrap().is_empty() {
+            self.settle_balance(user_canister).await?;
+        }
+        let amount = self.effective_balance_info_v2(user_canister).await?.balance;
+
+        let migrated = MigratedOut {
+            migration_id,
+            amount: amount.clone(),
+            at: Date::now().as_millis(),
+        };
+        let mut batch = storage.batch();
+        batch
+            .put(&marker_key, &migrated)?
+            .put(BALANCE_MIGRATED_KEY, &true)?;
+        self.off_chain_balance_delta
+            .borrow_mut()
+            .update_in(&storage, &mut batch, |delta| {
+                *delta -= BigInt::from(amount.0)
+            })
+            .await?;
+        if let Err(e) = batch.commit(&mut storage).await {
+            self.off_chain_balance_delta.borrow_mut().invalidate();
+            return Err(e);
+        }
+
+        Ok(migrated)
+    }
+}
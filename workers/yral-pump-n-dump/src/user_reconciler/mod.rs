@@ -1,16 +1,29 @@
+mod anomaly;
+mod delta;
+mod migrate;
 mod treasury;
 
 use std::{cell::RefCell, collections::HashSet};
 
+use anomaly::{AnomalyDetector, AnomalyThresholds};
 use candid::{Nat, Principal};
-use num_bigint::{BigInt, BigUint, ToBigInt};
+use delta::{balance_delta, bet_delta, reward_delta, settled_delta};
+use migrate::MigrateOutReq;
+use num_bigint::{BigInt, BigUint};
 use pump_n_dump_common::rest::{BalanceInfoResponse, CompletedGameInfo, UncommittedGameInfo};
 use serde::{Deserialize, Serialize};
 use treasury::DolrTreasury;
 use worker::*;
 use worker_utils::{
-    parse_principal,
-    storage::{SafeStorage, StorageCell},
+    api_error::error_resp,
+    backup::{self, Backup},
+    metrics::Metrics,
+    principals,
+    storage::{
+        rate_limit::{rate_limited_response, RateLimit},
+        SafeStorage, StorageCell,
+    },
+    RequestInitBuilder,
 };
 use yral_canisters_client::individual_user_template::{BalanceInfo, PumpNDumpStateDiff};
 use yral_canisters_common::utils::vote::HonBetArg;
@@ -18,8 +31,12 @@ use yral_metrics::metrics::cents_withdrawal::CentsWithdrawal;
 
 use crate::{
     backend_impl::{StateBackend, UserStateBackendImpl},
-    consts::{GDOLLR_TO_E8S, USER_INDEX_FUND_AMOUNT, USER_STATE_RECONCILE_TIME_MS},
-    utils::{metrics, CfMetricTx},
+    consts::{
+        CLAIM_RATE_LIMIT_MAX_REQUESTS, CLAIM_RATE_LIMIT_WINDOW_MS, GDOLLR_TO_E8S,
+        USER_INDEX_FUND_AMOUNT, USER_STATE_RECONCILE_TIME_MS,
+    },
+    treasury_controller::{TreasuryRefillReq, TreasuryRefillRes},
+    utils::{metrics, treasury_controller_stub, CfMetricTx, USER_EPHEMERAL_STATE},
 };
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -70,6 +87,21 @@ impl StateDiff {
     }
 }
 
+/// the object's view next to the canister's, compared by yral-reconciler
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReconciliationReport {
+    pub user_canister: Principal,
+    /// amounts are decimal strings, the delta is signed
+    pub on_chain_balance: String,
+    pub off_chain_balance_delta: String,
+    pub effective_balance: String,
+    /// completed games and rewards not yet settled to the canister
+    pub unsettled_diffs: usize,
+    pub pending_games: usize,
+    /// unix millis of the next settlement, `None` if none is scheduled
+    pub settle_at: Option<i64>,
+}
+
 #[durable_object]
 pub struct UserEphemeralState {
     state: State,
@@ -83,6 +115,8 @@ pub struct UserEphemeralState {
     pending_games: RefCell<Option<HashSet<Principal>>>,
     backend: StateBackend,
     dolr_treasury: RefCell<DolrTreasury>,
+    claim_rate_limit: RefCell<RateLimit>,
+    anomaly_detector: RefCell<AnomalyDetector>,
     metrics: CfMetricTx,
 }
 
@@ -282,7 +316,7 @@ impl UserEphemeralState {
         let mut storage = self.storage();
         self.off_chain_balance_delta
             .borrow_mut()
-            .update(&mut storage, |delta| *delta -= GDOLLR_TO_E8S)
+            .update(&mut storage, |delta| *delta += bet_delta())
             .await?;
 
         self.ensure_pending_games_loaded().await?;
@@ -303,6 +337,11 @@ impl UserEphemeralState {
             )
             .await?;
 
+        self.anomaly_detector
+            .borrow_mut()
+            .record_bet(&mut storage)
+            .await?;
+
         Ok(())
     }
 
@@ -311,7 +350,7 @@ impl UserEphemeralState {
         let mut storage = self.storage();
         self.off_chain_balance_delta
             .borrow_mut()
-            .update(&mut storage, |delta| *delta += BigInt::from(reward.clone()))
+            .update(&mut storage, |delta| *delta += reward_delta(&state_diff))
             .await?;
 
         self.ensure_off_chain_earning_delta_loaded().await?;
@@ -341,6 +380,11 @@ impl UserEphemeralState {
             storage
                 .delete(&format!("pending-game-{}", ginfo.token_root))
                 .await?;
+
+            self.anomaly_detector
+                .borrow_mut()
+                .record_game(&mut storage, ginfo.reward > 0u32)
+                .await?;
         }
 
         storage
@@ -373,59 +417,48 @@ impl UserEphemeralState {
             .as_ref()
             .unwrap()
             .clone();
-        *self.off_chain_earning_delta.borrow_mut() = Some(0u32.into());
-        storage.delete("off_chain_earning_delta").await?;
-
         self.ensure_state_diffs_loaded().await?;
         let state_diffs = std::mem::take(self.state_diffs.borrow_mut().as_mut().unwrap());
-        storage
-            .delete_multiple(
-                (0..state_diffs.len())
-                    .map(|i| format!("state-diff-{i}"))
-                    .collect(),
-            )
-            .await?;
+        *self.off_chain_earning_delta.borrow_mut() = Some(0u32.into());
 
-        let mut delta_delta = BigInt::from(0u32);
-        let state_diffs_conv = state_diffs
-            .iter()
-            .map(|diff| {
-                match diff {
-                    StateDiff::CompletedGame(info) => {
-                        delta_delta += BigInt::from(info.pumps + info.dumps) * GDOLLR_TO_E8S;
-                        delta_delta -= info.reward.clone().0.to_bigint().unwrap();
-                    }
-                    StateDiff::CreatorReward(rew) => {
-                        delta_delta -= rew.clone().0.to_bigint().unwrap();
-                    }
-                }
-                diff.clone().into()
-            })
-            .collect();
+        // earnings, diffs and the balance delta are settled together
+        let mut batch = storage.batch();
+        batch.delete("off_chain_earning_delta");
+        for i in 0..state_diffs.len() {
+            batch.delete(format!("state-diff-{i}"));
+        }
+
+        let delta_delta = settled_delta(&state_diffs);
+        let state_diffs_conv = state_diffs.iter().cloned().map(Into::into).collect();
 
         self.off_chain_balance_delta
             .borrow_mut()
-            .update(&mut storage, |delta| *delta += delta_delta)
+            .update_in(&storage, &mut batch, |delta| *delta += delta_delta)
             .await?;
+        if let Err(e) = batch.commit(&mut storage).await {
+            self.restore_unsettled(earnings, state_diffs);
+            return Err(e);
+        }
 
         let res = self
             .backend
             .reconcile_user_state(user_canister, state_diffs_conv)
             .await;
+        // settlement failures are watched by yral-ops
+        let outcome = if res.is_ok() { "settled" } else { "failed" };
+        Metrics::new(&self.env, "yral-pump-n-dump").counter("settlements", &[outcome]);
 
         if let Err(e) = res {
+            let mut batch = storage.batch();
             self.off_chain_balance_delta
                 .borrow_mut()
-                .set(&mut storage, to_settle)
-                .await?;
-            *self.state_diffs.borrow_mut() = Some(state_diffs.clone());
-            *self.off_chain_earning_delta.borrow_mut() = Some(earnings.clone());
-
-            storage.put("off_chain_earning_delta", &earnings).await?;
-
-            for (i, state_diff) in state_diffs.into_iter().enumerate() {
-                storage.put(&format!("state-diff-{i}"), &state_diff).await?;
+                .set_in(&mut batch, to_settle)?;
+            batch.put("off_chain_earning_delta", &earnings)?;
+            for (i, state_diff) in state_diffs.iter().enumerate() {
+                batch.put(format!("state-diff-{i}"), state_diff)?;
             }
+            batch.commit(&mut storage).await?;
+            self.restore_unsettled(earnings, state_diffs);
 
             return Err(e);
         }
@@ -433,6 +466,14 @@ impl UserEphemeralState {
         Ok(())
     }
 
+    /// puts back the in-memory state taken by `settle_balance`
+    /// the balance delta is reloaded from storage
+    fn restore_unsettled(&self, earnings: Nat, state_diffs: Vec<StateDiff>) {
+        self.off_chain_balance_delta.borrow_mut().invalidate();
+        *self.state_diffs.borrow_mut() = Some(state_diffs);
+        *self.off_chain_earning_delta.borrow_mut() = Some(earnings);
+    }
+
     async fn check_user_index_balance(
         &self,
         user_canister: Principal,
@@ -451,11 +492,48 @@ impl UserEphemeralState {
         Ok(())
     }
 
+    /// request the shortfall from the treasury controller
+    /// if the user's daily treasury can't cover `amount`
+    async fn ensure_treasury_allowance(&self, user_canister: Principal, amount: Nat) -> Result<()> {
+        let mut storage = self.storage();
+        let available = self.dolr_treasury.borrow_mut().amount(&mut storage).await?;
+        if available >= amount {
+            return Ok(());
+        }
+
+        let controller = treasury_controller_stub(&self.env)?;
+        let req = Request::new_with_init(
+            "http://fake_url.com/request_refill",
+            RequestInitBuilder::default()
+                .method(Method::Post)
+                .json(&TreasuryRefillReq {
+                    user_canister,
+                    amount: amount - available,
+                })?
+                .build(),
+        )?;
+        let mut res = controller.fetch_with_request(req).await?;
+        if res.status_code() != 200 {
+            let err = res.text().await?;
+            return Err(worker::Error::RustError(format!(
+                "treasury refill failed: {err}"
+            )));
+        }
+        let refill: TreasuryRefillRes = res.json().await?;
+
+        self.dolr_treasury
+            .borrow_mut()
+            .refill(&mut storage, refill.granted)
+            .await
+    }
+
     async fn redeem_gdollr(&self, user_canister: Principal, amount: Nat) -> Result<Response> {
         let mut storage = self.storage();
 
         self.check_user_index_balance(user_canister, amount.clone())
             .await?;
+        self.ensure_treasury_allowance(user_canister, amount.clone())
+            .await?;
         self.dolr_treasury
             .borrow_mut()
             .try_consume(&mut storage, amount.clone())
@@ -481,12 +559,39 @@ impl UserEphemeralState {
                     .borrow_mut()
                     .rollback(&mut storage, amount)
                     .await?;
-                Response::error(e.to_string(), 500u16)
+                error_resp(e.to_string(), 500u16)
             }
         }
     }
 
+    /// one attempt from the budget both claim routes share, the 429 response once it's spent
+    async fn acquire_claim_limit(&self) -> Result<Option<Response>> {
+        let limited = self
+            .claim_rate_limit
+            .borrow_mut()
+            .try_acquire(&mut self.storage())
+            .await?;
+        match limited {
+            Ok(()) => Ok(None),
+            Err(retry_after_ms) => rate_limited_response(retry_after_ms).map(Some),
+        }
+    }
+
+    async fn claims_held_for_review(&self) -> Result<bool> {
+        self.anomaly_detector
+            .borrow_mut()
+            .review_pending(&self.storage())
+            .await
+    }
+
     async fn claim_gdollr(&self, user_canister: Principal, amount: Nat) -> Result<Response> {
+        if self.claims_held_for_review().await? {
+            return error_resp("claims held for review", 403);
+        }
+        if self.balance_migrated().await? {
+            return error_resp("balance migrated", 409);
+        }
+
         let on_chain_bal = self.backend.game_balance(user_canister).await?;
         if on_chain_bal.withdrawable >= amount {
             let res = self.redeem_gdollr(user_canister, amount).await;
@@ -495,7 +600,7 @@ impl UserEphemeralState {
 
         let effective_bal = self.effective_balance_info_inner(on_chain_bal).await?;
         if amount > effective_bal.withdrawable {
-            return Response::error("not enough balance", 400);
+            return error_resp("not enough balance", 400);
         }
 
         self.settle_balance(user_canister).await?;
@@ -504,6 +609,13 @@ impl UserEphemeralState {
     }
 
     async fn claim_gdollr_v2(&self, user_canister: Principal, amount: Nat) -> Result<Response> {
+        if self.claims_held_for_review().await? {
+            return error_resp("claims held for review", 403);
+        }
+        if self.balance_migrated().await? {
+            return error_resp("balance migrated", 409);
+        }
+
         let on_chain_bal = self.backend.game_balance_v2(user_canister).await?;
         if on_chain_bal.withdrawable >= amount {
             let res = self.redeem_gdollr(user_canister, amount).await;
@@ -512,7 +624,7 @@ impl UserEphemeralState {
 
         let effective_bal = self.effective_balance_info_inner_v2(on_chain_bal).await?;
         if amount > effective_bal.withdrawable {
-            return Response::error("not enough balance", 400);
+            return error_resp("not enough balance", 400);
         }
 
         self.settle_balance(user_canister).await?;
@@ -530,6 +642,67 @@ impl UserEphemeralState {
         Ok(on_chain_count + off_chain_count as u64)
     }
 
+    async fn reconciliation_report(
+        &self,
+        user_canister: Principal,
+    ) -> Result<ReconciliationReport> {
+        let on_chain_balance = self.backend.game_balance_v2(user_canister).await?.balance;
+        let effective_balance = self
+            .effective_balance_inner(on_chain_balance.clone())
+            .await?;
+        let off_chain_balance_delta = self
+            .off_chain_balance_delta
+            .borrow_mut()
+            .read(&self.storage())
+            .await?
+            .to_string();
+        self.ensure_state_diffs_loaded().await?;
+        self.ensure_pending_games_loaded().await?;
+        let unsettled_diffs = self.state_diffs.borrow().as_ref().unwrap().len();
+        let pending_games = self.pending_games.borrow().as_ref().unwrap().len();
+        let settle_at = self.state.storage().get_alarm().await?;
+
+        Ok(ReconciliationReport {
+            user_canister,
+            on_chain_balance: on_chain_balance.0.to_string(),
+            off_chain_balance_delta,
+            effective_balance: effective_balance.0.to_string(),
+            unsettled_diffs,
+            pending_games,
+            settle_at,
+        })
+    }
+
+    /// required after storage is modified behind the cells' back, e.g. `delete_all`
+    fn invalidate_cached_state(&self) {
+        self.off_chain_balance_delta.borrow_mut().invalidate();
+        *self.off_chain_earning_delta.borrow_mut() = None;
+        *self.user_canister.borrow_mut() = None;
+        *self.state_diffs.borrow_mut() = None;
+        *self.pending_games.borrow_mut() = None;
+        self.dolr_treasury.borrow_mut().invalidate();
+        self.claim_rate_limit.borrow_mut().invalidate();
+        self.anomaly_detector.borrow_mut().invalidate();
+    }
+
+    /// settles what the user is owed on chain, then erases everything stored for them
+    async fn forget(&self) -> Result<()> {
+        if let Some(user_canister) = self.try_get_user_canister().await {
+            self.ensure_state_diffs_loaded().await?;
+            if !self.state_diffs.borrow().as_ref().unwrap().is_empty() {
+                self.settle_balance(user_canister).await?;
+            }
+        }
+
+        let mut storage = self.storage();
+        storage.delete_all().await?;
+        self.state.storage().delete_alarm().await?;
+        self.invalidate_cached_state();
+        console_log!("erased pump n dump data for {}", self.state.id());
+
+        Ok(())
+    }
+
     async fn effective_net_earnings(&self, user_canister: Principal) -> Result<Nat> {
         let on_chain_earnings = self.backend.net_earnings(user_canister).await?;
         self.ensure_off_chain_earning_delta_loaded().await?;
@@ -544,6 +717,24 @@ impl UserEphemeralState {
     }
 }
 
+impl Backup for UserEphemeralState {
+    const NAMESPACE: &'static str = USER_EPHEMERAL_STATE;
+    // restored state diffs are settled like any others
+    const RESTORE_ALARM_MS: Option<i64> = Some(USER_STATE_RECONCILE_TIME_MS);
+
+    fn backup_state(&self) -> &State {
+        &self.state
+    }
+
+    fn backup_env(&self) -> &Env {
+        &self.env
+    }
+
+    fn invalidate_cached_state(&self) {
+        Self::invalidate_cached_state(self)
+    }
+}
+
 // SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
 // because Workers run in a single-threaded JavaScript runtime with no concurrent access.
 // The RefCell interior mutability pattern is required due to Worker 0.7.4 API changes
@@ -554,25 +745,32 @@ impl DurableObject for UserEphemeralState {
         console_error_panic_hook::set_once();
 
         let backend = StateBackend::new(&env).unwrap();
+        let anomaly_thresholds = AnomalyThresholds::from_env(&env);
 
         Self {
             state,
             env,
-            off_chain_balance_delta: RefCell::new(StorageCell::new(
-                "off_chain_balance_delta",
-                || BigInt::from(0),
-            )),
+            off_chain_balance_delta: RefCell::new(balance_delta()),
             off_chain_earning_delta: RefCell::new(None),
             user_canister: RefCell::new(None),
             state_diffs: RefCell::new(None),
             pending_games: RefCell::new(None),
             dolr_treasury: RefCell::new(DolrTreasury::default()),
+            claim_rate_limit: RefCell::new(RateLimit::new(
+                "claim-rate-limit",
+                CLAIM_RATE_LIMIT_MAX_REQUESTS,
+                CLAIM_RATE_LIMIT_WINDOW_MS,
+            )),
+            anomaly_detector: RefCell::new(AnomalyDetector::new(anomaly_thresholds)),
             backend,
             metrics: metrics(),
         }
     }
 
-    async fn fetch(&self, req: Request) -> Result<Response> {
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        if let Some(res) = backup::handle(self, &mut req).await {
+            return res;
+        }
         let env = self.env.clone();
         let router = Router::with_data(self);
 
@@ -580,7 +778,7 @@ impl DurableObject for UserEphemeralState {
             .get_async("/balance/:user_canister", |_req, ctx| async move {
                 let user_canister_raw = ctx.param("user_canister").unwrap();
                 let Ok(user_canister) = Principal::from_text(user_canister_raw) else {
-                    return Response::error("Invalid user_canister", 400);
+                    return error_resp("Invalid user_canister", 400);
                 };
 
                 let this = ctx.data;
@@ -589,7 +787,7 @@ impl DurableObject for UserEphemeralState {
                 Response::from_json(&bal)
             })
             .get_async("/balance_v2/:user_canister", |_req, ctx| async move {
-                let user_canister = parse_principal!(ctx, "user_canister");
+                let user_canister = principals!(ctx, "user_canister");
 
                 let this = ctx.data;
                 this.set_user_canister(user_canister).await?;
@@ -597,7 +795,7 @@ impl DurableObject for UserEphemeralState {
                 Response::from_json(&bal)
             })
             .get_async("/earnings/:user_canister", |_req, ctx| async move {
-                let user_canister = parse_principal!(ctx, "user_canister");
+                let user_canister = principals!(ctx, "user_canister");
 
                 let this = ctx.data;
                 this.set_user_canister(user_canister).await?;
@@ -609,13 +807,16 @@ impl DurableObject for UserEphemeralState {
                 let decr_req: DecrementReq = req.json().await?;
                 this.set_user_canister(decr_req.user_canister).await?;
 
+                if this.balance_migrated().await? {
+                    return error_resp("balance migrated", 409);
+                }
                 let bal = this.effective_balance(decr_req.user_canister).await?;
                 if bal < GDOLLR_TO_E8S {
-                    return Response::error("Not enough balance", 400);
+                    return error_resp("Not enough balance", 400);
                 }
                 let res = this.decrement(decr_req.token_root).await;
                 if let Err(e) = res {
-                    return Response::error(format!("failed to decrement: {e}"), 500);
+                    return error_resp(format!("failed to decrement: {e}"), 500);
                 }
 
                 Response::ok("done")
@@ -634,6 +835,9 @@ impl DurableObject for UserEphemeralState {
                 let claim_req: ClaimGdollrReq = req.json().await?;
 
                 this.set_user_canister(claim_req.user_canister).await?;
+                if let Some(limited) = this.acquire_claim_limit().await? {
+                    return Ok(limited);
+                }
 
                 this.claim_gdollr(claim_req.user_canister, claim_req.amount)
                     .await
@@ -643,14 +847,80 @@ impl DurableObject for UserEphemeralState {
                 let claim_req: ClaimGdollrReq = req.json().await?;
 
                 this.set_user_canister(claim_req.user_canister).await?;
+                if let Some(limited) = this.acquire_claim_limit().await? {
+                    return Ok(limited);
+                }
 
                 this.claim_gdollr_v2(claim_req.user_canister, claim_req.amount)
                     .await
             })
+            .get_async("/fraud_events", |_req, ctx| async move {
+                let this = ctx.data;
+                let events = this
+                    .anomaly_detector
+                    .borrow()
+                    .events(&this.storage())
+                    .await?;
+
+                Response::from_json(&events)
+            })
+            .post_async("/clear_fraud_review", |_req, ctx| async move {
+                let this = ctx.data;
+                this.anomaly_detector
+                    .borrow_mut()
+                    .clear_review(&mut this.storage())
+                    .await?;
+
+                Response::ok("done")
+            })
+            // settles right away instead of waiting for the reconcile alarm, used by yral-admin
+            .post_async("/settle", |_req, ctx| async move {
+                let this = ctx.data;
+                let Some(user_canister) = this.try_get_user_canister().await else {
+                    return error_resp("user canister unknown", 404);
+                };
+                this.ensure_state_diffs_loaded().await?;
+                if this.state_diffs.borrow().as_ref().unwrap().is_empty() {
+                    return Response::ok("not required");
+                }
+                this.settle_balance(user_canister).await?;
+
+                Response::ok("done")
+            })
+            // balance migration, used by yral-admin
+            .post_async("/migrate_out", |mut req, ctx| async move {
+                let this = ctx.data;
+                let migrate_req: MigrateOutReq = req.json().await?;
+
+                this.set_user_canister(migrate_req.user_canister).await?;
+                this.ensure_pending_games_loaded().await?;
+                if !this.pending_games.borrow().as_ref().unwrap().is_empty() {
+                    return error_resp("games in progress", 409);
+                }
+                let migrated = this
+                    .migrate_out(migrate_req.user_canister, migrate_req.migration_id)
+                    .await?;
+
+                Response::from_json(&migrated)
+            })
+            // account deletion, used by yral-admin
+            .post_async("/forget", |_req, ctx| async move {
+                ctx.data.forget().await?;
+
+                Response::ok("done")
+            })
+            .get_async("/reconciliation/:user_canister", |_req, ctx| async move {
+                let user_canister = principals!(ctx, "user_canister");
+
+                let this = ctx.data;
+                this.set_user_canister(user_canister).await?;
+                let report = this.reconciliation_report(user_canister).await?;
+                Response::from_json(&report)
+            })
             .get_async("/game_count/:user_canister", |_req, ctx| async move {
                 let user_canister_raw = ctx.param("user_canister").unwrap();
                 let Ok(user_canister) = Principal::from_text(user_canister_raw) else {
-                    return Response::error("Invalid user_canister", 400);
+                    return error_resp("Invalid user_canister", 400);
                 };
 
                 let this = ctx.data;
@@ -662,7 +932,7 @@ impl DurableObject for UserEphemeralState {
             .get_async(
                 "/uncommitted_games/:user_canister",
                 |_req, ctx| async move {
-                    let user_canister = parse_principal!(ctx, "user_canister");
+                    let user_canister = principals!(ctx, "user_canister");
 
                     let this = ctx.data;
                     this.set_user_canister(user_canister).await?;
@@ -38,6 +38,10 @@ impl DolrTreasury {
         Ok(self.0.as_mut().unwrap())
     }
 
+    pub fn invalidate(&mut self) {
+        self.0 = None;
+    }
+
     async fn treasury(&mut self, storage: &mut SafeStorage) -> Result<&mut DolrTreasuryInner> {
         let treasury = self.get_or_init(storage).await?;
         if Date::now().as_millis() - (24 * 3600 * 1000) >= treasury.last_reset_epoch {
@@ -59,10 +63,25 @@ impl DolrTreasury {
         Ok(())
     }
 
+    /// give back exactly what `try_consume` took, refilled allowance included
+    /// a debit from a window that has since reset is already forgotten
     pub async fn rollback(&mut self, storage: &mut SafeStorage, amount: Nat) -> Result<()> {
+        let treasury = self.get_or_init(storage).await?;
+        if Date::now().as_millis() - (24 * 3600 * 1000) >= treasury.last_reset_epoch {
+            *treasury = DolrTreasuryInner::default();
+        } else {
+            treasury.amount += amount.0;
+        }
+        storage.put("dolr-treasury-limit", treasury).await?;
+
+        Ok(())
+    }
+
+    /// credit extra allowance granted by the treasury controller
+    /// not capped by the per user daily maximum
+    pub async fn refill(&mut self, storage: &mut SafeStorage, amount: Nat) -> Result<()> {
         let treasury = self.treasury(storage).await?;
-        treasury.amount =
-            (treasury.amount.clone() + amount.0).min(MAXIMUM_DOLR_TREASURY_PER_DAY_PER_USER.into());
+        treasury.amount += amount.0;
         storage.put("dolr-treasury-limit", treasury).await?;
 
         Ok(())
@@ -1,41 +1,28 @@
 use candid::Principal;
-use worker::{Result, RouteContext, Stub};
-use worker_utils::environment::{env_kind, RunEnv};
-use yral_metrics::{
-    metric_sender::{
-        js_spawn::JsSpawnMetricTx, mock::MaybeMockLocalMetricEventTx, vectordb::VectorDbMetricTx,
-        LocalMetricTx,
-    },
-    metrics::EventSource,
-};
+use worker::{Env, Result, Stub};
+use worker_utils::metrics::yral::event_tx;
+use yral_metrics::metrics::EventSource;
 
-pub fn game_state_stub<T>(
-    ctx: &RouteContext<T>,
-    game_canister: Principal,
-    token_root: Principal,
-) -> Result<Stub> {
-    let game_ns = ctx.durable_object("GAME_STATE")?;
-    let game_state_obj = game_ns.id_from_name(&format!("{game_canister}-{token_root}"))?;
-    let game_stub = game_state_obj.get_stub()?;
+pub use worker_utils::metrics::yral::CfMetricTx;
 
-    Ok(game_stub)
+pub const GAME_STATE: &str = "GAME_STATE";
+pub const USER_EPHEMERAL_STATE: &str = "USER_EPHEMERAL_STATE";
+pub const TREASURY_CONTROLLER: &str = "TREASURY_CONTROLLER";
+/// there is a single treasury controller
+pub const TREASURY_CONTROLLER_NAME: &str = "global";
+
+/// name of the `GAME_STATE` object of a token
+pub fn game_state_name(game_canister: Principal, token_root: Principal) -> String {
+    format!("{game_canister}-{token_root}")
 }
 
-pub fn user_state_stub<T>(ctx: &RouteContext<T>, user_canister: Principal) -> Result<Stub> {
-    let state_ns = ctx.durable_object("USER_EPHEMERAL_STATE")?;
-    let state_obj = state_ns.id_from_name(&user_canister.to_text())?;
+pub fn treasury_controller_stub(env: &Env) -> Result<Stub> {
+    let controller_ns = env.durable_object(TREASURY_CONTROLLER)?;
+    let controller_obj = controller_ns.id_from_name(TREASURY_CONTROLLER_NAME)?;
 
-    state_obj.get_stub()
+    controller_obj.get_stub()
 }
 
-pub type CfMetricTx = LocalMetricTx<MaybeMockLocalMetricEventTx<JsSpawnMetricTx<VectorDbMetricTx>>>;
-
 pub fn metrics() -> CfMetricTx {
-    let ev_tx = if env_kind() == RunEnv::Remote {
-        MaybeMockLocalMetricEventTx::Real(JsSpawnMetricTx(VectorDbMetricTx::default()))
-    } else {
-        MaybeMockLocalMetricEventTx::default()
-    };
-
-    LocalMetricTx::new(EventSource::PumpNDumpWorker, ev_tx)
+    event_tx(EventSource::PumpNDumpWorker)
 }
@@ -0,0 +1,36 @@
+-- one row per reconciliation run
+CREATE TABLE IF NOT EXISTS reconciliation_runs (
+    id TEXT PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    sampled INTEGER NOT NULL DEFAULT 0,
+    checked INTEGER NOT NULL DEFAULT 0,
+    -- users whose state couldn't be read
+    errors INTEGER NOT NULL DEFAULT 0,
+    discrepancies INTEGER NOT NULL DEFAULT 0,
+    -- sum of the drift of every discrepancy, as a decimal string
+    total_drift TEXT NOT NULL DEFAULT '0',
+    alerted INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS reconciliation_runs_started_at
+    ON reconciliation_runs (started_at);
+
+-- drift between a durable object and the canister it mirrors
+CREATE TABLE IF NOT EXISTS reconciliation_discrepancies (
+    run_id TEXT NOT NULL,
+    target TEXT NOT NULL,
+    user_principal TEXT NOT NULL,
+    user_canister TEXT,
+    -- overdraft or unsettled
+    kind TEXT NOT NULL,
+    -- as a decimal string
+    drift TEXT NOT NULL,
+    -- json encoded report the drift was computed from
+    details TEXT NOT NULL,
+    detected_at INTEGER NOT NULL,
+    PRIMARY KEY (run_id, target, user_principal, kind)
+);
+
+CREATE INDEX IF NOT EXISTS reconciliation_discrepancies_principal
+    ON reconciliation_discrepancies (user_principal, detected_at);
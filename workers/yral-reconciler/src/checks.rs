@@ -0,0 +1,132 @@
+//! compares what durable objects hold against the canisters they mirror
+//!
+//! only pump-n-dump keeps a delta on top of an on-chain balance, hot-or-not's SATS live only
+//! in its durable objects so there is nothing on chain to compare them against
+
+use std::{pin::pin, str::FromStr, time::Duration};
+
+use futures::future::{select, Either};
+use num_bigint::{BigInt, BigUint};
+use serde::{Deserialize, Serialize};
+use worker::*;
+use worker_utils::{trace::TraceId, RequestInitBuilder};
+
+const PUMP_N_DUMP: &str = "YRAL_PUMP_N_DUMP";
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// a settlement this far past due means the object's alarm was lost
+const SETTLE_GRACE_MS: i64 = 60 * 60 * 1000;
+
+/// yral-pump-n-dump's `ReconciliationReport`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PumpNDumpReport {
+    pub user_canister: String,
+    pub on_chain_balance: String,
+    pub off_chain_balance_delta: String,
+    pub effective_balance: String,
+    pub unsettled_diffs: usize,
+    pub pending_games: usize,
+    pub settle_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// the object spent more than the canister holds, the effective balance was clamped to zero
+    Overdraft,
+    /// completed games are waiting to be settled but no settlement is coming
+    Unsettled,
+}
+
+impl DiscrepancyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Overdraft => "overdraft",
+            Self::Unsettled => "unsettled",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Discrepancy {
+    pub target: &'static str,
+    pub user_principal: String,
+    pub user_canister: Option<String>,
+    pub kind: DiscrepancyKind,
+    pub drift: BigUint,
+    pub details: serde_json::Value,
+}
+
+/// `None` if the user never played pump-n-dump
+pub async fn pump_n_dump_report(
+    env: &Env,
+    trace: &TraceId,
+    user_principal: &str,
+) -> Result<Option<PumpNDumpReport>> {
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Get).trace(trace)?;
+    let req = init.request(&format!(
+        "https://{PUMP_N_DUMP}/reconciliation/{user_principal}"
+    ))?;
+
+    let fetch = pin!(env.service(PUMP_N_DUMP)?.fetch_request(req));
+    let delay = pin!(Delay::from(CHECK_TIMEOUT));
+    let mut res = match select(fetch, delay).await {
+        Either::Left((res, _)) => res?,
+        Either::Right(_) => {
+            return Err(Error::RustError(format!(
+                "timed out after {}ms",
+                CHECK_TIMEOUT.as_millis()
+            )))
+        }
+    };
+    match res.status_code() {
+        200 => Ok(Some(res.json().await?)),
+        404 => Ok(None),
+        status => {
+            let body = res.text().await.unwrap_or_default();
+            Err(Error::RustError(format!("status {status}: {body}")))
+        }
+    }
+}
+
+fn parse<T: FromStr>(v: &str, what: &str) -> Result<T> {
+    T::from_str(v).map_err(|_| Error::RustError(format!("invalid {what}: {v}")))
+}
+
+pub fn check_pump_n_dump(
+    user_principal: &str,
+    report: &PumpNDumpReport,
+    now: i64,
+) -> Result<Vec<Discrepancy>> {
+    let on_chain: BigUint = parse(&report.on_chain_balance, "on chain balance")?;
+    let delta: BigInt = parse(&report.off_chain_balance_delta, "off chain delta")?;
+    let details = serde_json::to_value(report)?;
+    let discrepancy = |kind, drift| Discrepancy {
+        target: "pump-n-dump",
+        user_principal: user_principal.to_string(),
+        user_canister: Some(report.user_canister.clone()),
+        kind,
+        drift,
+        details: details.clone(),
+    };
+
+    let mut found = vec![];
+    if delta.magnitude() > &on_chain {
+        found.push(discrepancy(
+            DiscrepancyKind::Overdraft,
+            delta.magnitude() - &on_chain,
+        ));
+    }
+    // games in progress move the delta before anything needs settling
+    let settle_overdue = report.settle_at.is_none_or(|at| at + SETTLE_GRACE_MS < now);
+    if report.unsettled_diffs > 0 && settle_overdue {
+        found.push(discrepancy(
+            DiscrepancyKind::Unsettled,
+            delta.magnitude().clone(),
+        ));
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,10 @@
+use worker_utils::jwt::JwtPolicy;
+
+pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
+-----END PUBLIC KEY-----";
+
+/// issued to ops only
+pub const JWT_AUD: &str = "yral-reconciler";
+
+pub const JWT_POLICY: JwtPolicy = JwtPolicy::expiring(60);
@@ -0,0 +1,267 @@
+mod checks;
+mod jwt;
+mod store;
+
+use std::time::Duration;
+
+use checks::{check_pump_n_dump, pump_n_dump_report, Discrepancy};
+use futures::{stream, StreamExt};
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
+use num_bigint::BigUint;
+use serde_json::json;
+use store::{finish_run, recent_runs, run_discrepancies, sample_users, start_run, RunSummary};
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    jwt::JwtKeys,
+    lock::DistributedLock,
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    time::now_millis,
+    trace::TraceId,
+    RequestInitBuilder,
+};
+
+/// hosted by this worker, only the reconciler takes locks
+pub use worker_utils::lock::LockObject;
+
+const RECONCILER_DB: &str = "RECONCILER_DB";
+const ANALYTICS_DB: &str = "ANALYTICS_DB";
+
+const DEFAULT_SAMPLE_SIZE: u32 = 500;
+const MAX_SAMPLE_SIZE: u32 = 5_000;
+/// users checked at once, each check reads a canister
+const CHECK_CONCURRENCY: usize = 10;
+
+const DEFAULT_ALERT_THRESHOLD: u64 = 100_000_000;
+/// optional, alerts are only logged without it
+const ALERT_WEBHOOK_SECRET: &str = "RECONCILER_ALERT_WEBHOOK_URL";
+const ALERT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const LOCKS: &str = "LOCKS";
+const RUN_LOCK: &str = "reconciliation-run";
+/// longer than a cron invocation may run, so a crashed run frees the lock on its own
+const RUN_LOCK_TTL: Duration = Duration::from_secs(20 * 60);
+
+const DEFAULT_RUNS_LIMIT: u32 = 30;
+const MAX_RUNS_LIMIT: u32 = 365;
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-reconciler",
+    &[
+        Dependency::D1(RECONCILER_DB),
+        Dependency::D1(ANALYTICS_DB),
+        Dependency::DurableObject(LOCKS),
+        Dependency::Service("YRAL_PUMP_N_DUMP"),
+    ],
+);
+
+fn var_or<T: std::str::FromStr>(env: &Env, name: &str, default: T) -> T {
+    env.var(name)
+        .ok()
+        .and_then(|v| v.to_string().parse().ok())
+        .unwrap_or(default)
+}
+
+/// `Ok(None)` if the user has nothing to reconcile
+async fn check_user(
+    env: &Env,
+    trace: &TraceId,
+    user_principal: &str,
+    now: u64,
+) -> Result<Option<Vec<Discrepancy>>> {
+    let Some(report) = pump_n_dump_report(env, trace, user_principal).await? else {
+        return Ok(None);
+    };
+
+    check_pump_n_dump(user_principal, &report, now as i64).map(Some)
+}
+
+async fn send_alert(env: &Env, run: &RunSummary) -> Result<()> {
+    let text = format!(
+        "reconciliation run {} found {} discrepancies, {} in total drift across {} users checked",
+        run.id, run.discrepancies, run.total_drift, run.checked
+    );
+    console_error!("{text}");
+    let Ok(url) = env.secret(ALERT_WEBHOOK_SECRET) else {
+        return Ok(());
+    };
+
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Post)
+        .timeout(ALERT_TIMEOUT)
+        .json(&json!({ "text": text }))?;
+    let res = init.fetch(&url.to_string()).await?;
+    if res.status_code() >= 300 {
+        return Err(Error::RustError(format!(
+            "alert webhook responded with {}",
+            res.status_code()
+        )));
+    }
+
+    Ok(())
+}
+
+/// samples users, checks each of them and records what drifted
+async fn reconcile(env: &Env) -> Result<RunSummary> {
+    let db = env.d1(RECONCILER_DB)?;
+    let metrics = Metrics::new(env, "yral-reconciler");
+    let trace = TraceId::new();
+    let started_at = now_millis();
+    let mut run = RunSummary {
+        id: uuid::Uuid::new_v4().to_string(),
+        started_at,
+        ..Default::default()
+    };
+    start_run(&db, &run.id, started_at).await?;
+
+    let sample_size =
+        var_or(env, "RECONCILER_SAMPLE_SIZE", DEFAULT_SAMPLE_SIZE).min(MAX_SAMPLE_SIZE);
+    let users = sample_users(&env.d1(ANALYTICS_DB)?, started_at, sample_size).await?;
+    run.sampled = users.len() as u32;
+
+    let results: Vec<_> = stream::iter(&users)
+        .map(|user| async { (user, check_user(env, &trace, user, started_at).await) })
+        .buffer_unordered(CHECK_CONCURRENCY)
+        .collect()
+        .await;
+    let mut discrepancies = vec![];
+    for (user, res) in results {
+        match res {
+            Ok(Some(found)) => {
+                run.checked += 1;
+                discrepancies.extend(found);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                console_warn!("failed to reconcile {user}: {e}");
+                run.errors += 1;
+            }
+        }
+    }
+
+    let total_drift: BigUint = discrepancies.iter().map(|d| &d.drift).sum();
+    run.discrepancies = discrepancies.len() as u32;
+    run.total_drift = total_drift.to_string();
+    run.finished_at = Some(now_millis());
+    for d in &discrepancies {
+        metrics.counter("reconciliation_discrepancies", &[d.target, d.kind.as_str()]);
+    }
+    metrics.count("reconciliation_errors", &[], run.errors as u64);
+
+    let threshold = var_or(env, "RECONCILER_ALERT_THRESHOLD", DEFAULT_ALERT_THRESHOLD);
+    if run.discrepancies > 0 && total_drift >= BigUint::from(threshold) {
+        run.alerted = 1;
+        if let Err(e) = send_alert(env, &run).await {
+            console_error!("failed to send reconciliation alert: {e}");
+        }
+    }
+    finish_run(&db, &run, &discrepancies).await?;
+
+    Ok(run)
+}
+
+/// `reconcile` unless another run holds the lock, `Ok(None)` if one does
+async fn reconcile_exclusive(env: &Env) -> Result<Option<RunSummary>> {
+    let owner = uuid::Uuid::new_v4().to_string();
+    DistributedLock::new(env, LOCKS, RUN_LOCK, owner)
+        .run(RUN_LOCK_TTL, || reconcile(env))
+        .await
+}
+
+async fn authorize(req: &Request, env: &Env) -> std::result::Result<(), Result<Response>> {
+    JwtKeys::from_env(env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, req)
+        .await
+        .map_err(|(msg, code)| error_resp(msg, code))
+}
+
+async fn list_runs(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err(res) = authorize(&req, &ctx.env).await {
+        return res;
+    }
+    let limit = req
+        .url()?
+        .query_pairs()
+        .find(|(k, _)| k == "limit")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(DEFAULT_RUNS_LIMIT)
+        .min(MAX_RUNS_LIMIT);
+
+    let db = ctx.env.d1(RECONCILER_DB)?;
+    Response::from_json(&recent_runs(&db, limit).await?)
+}
+
+async fn list_discrepancies(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err(res) = authorize(&req, &ctx.env).await {
+        return res;
+    }
+    let Some(run_id) = ctx.param("run_id") else {
+        return error_resp("run_id is required", 400);
+    };
+
+    let db = ctx.env.d1(RECONCILER_DB)?;
+    Response::from_json(&run_discrepancies(&db, run_id).await?)
+}
+
+/// runs a reconciliation right away instead of waiting for the cron
+async fn trigger_run(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err(res) = authorize(&req, &ctx.env).await {
+        return res;
+    }
+
+    match reconcile_exclusive(&ctx.env).await? {
+        Some(run) => Response::from_json(&run),
+        None => error_resp("a reconciliation run is already in progress", 409),
+    }
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    // runs are started by operators
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-reconciler", method.as_ref(), &path, &["/runs"]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/runs", list_runs)
+        .post_async("/runs", trigger_run)
+        .get_async("/runs/:run_id/discrepancies", list_discrepancies)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    match reconcile_exclusive(&env).await {
+        Ok(None) => console_log!("skipping reconciliation, a run is already in progress"),
+        Ok(Some(run)) => console_log!(
+            "reconciliation run {} checked {} users, {} discrepancies",
+            run.id,
+            run.checked,
+            run.discrepancies
+        ),
+        Err(e) => console_error!("reconciliation run failed: {e}"),
+    }
+}
@@ -0,0 +1,142 @@
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, D1Database, Result};
+
+use crate::checks::Discrepancy;
+
+/// users active in yral-analytics' rollups over this window are sampled
+const SAMPLE_WINDOW_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+#[derive(Deserialize)]
+struct SampledUser {
+    user_principal: String,
+}
+
+/// random users seen in the last `SAMPLE_WINDOW_MS`, read from yral-analytics' database
+pub async fn sample_users(analytics: &D1Database, now: u64, size: u32) -> Result<Vec<String>> {
+    let users = analytics
+        .prepare(
+            "SELECT DISTINCT user_principal FROM hourly_active_users \
+            WHERE hour >= ?1 ORDER BY RANDOM() LIMIT ?2",
+        )
+        .bind(&[num(now.saturating_sub(SAMPLE_WINDOW_MS)), num(size as u64)])?
+        .all()
+        .await?
+        .results::<SampledUser>()?;
+
+    Ok(users.into_iter().map(|u| u.user_principal).collect())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RunSummary {
+    pub id: String,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub sampled: u32,
+    pub checked: u32,
+    pub errors: u32,
+    pub discrepancies: u32,
+    pub total_drift: String,
+    /// 1 if the run raised an alert, d1 has no booleans
+    pub alerted: u32,
+}
+
+pub async fn start_run(db: &D1Database, id: &str, started_at: u64) -> Result<()> {
+    db.prepare("INSERT INTO reconciliation_runs (id, started_at) VALUES (?1, ?2)")
+        .bind(&[id.into(), num(started_at)])?
+        .run()
+        .await?;
+
+    Ok(())
+}
+
+/// writes the run's discrepancies and totals in one transaction
+pub async fn finish_run(
+    db: &D1Database,
+    run: &RunSummary,
+    discrepancies: &[Discrepancy],
+) -> Result<()> {
+    let mut statements = vec![];
+    for d in discrepancies {
+        statements.push(
+            db.prepare(
+                "INSERT OR REPLACE INTO reconciliation_discrepancies \
+                (run_id, target, user_principal, user_canister, kind, drift, details, detected_at) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )
+            .bind(&[
+                run.id.as_str().into(),
+                d.target.into(),
+                d.user_principal.as_str().into(),
+                d.user_canister
+                    .as_deref()
+                    .map(JsValue::from)
+                    .unwrap_or(JsValue::NULL),
+                d.kind.as_str().into(),
+                d.drift.to_string().into(),
+                serde_json::to_string(&d.details)?.into(),
+                num(run.finished_at.unwrap_or(run.started_at)),
+            ])?,
+        );
+    }
+    statements.push(
+        db.prepare(
+            "UPDATE reconciliation_runs SET finished_at = ?2, sampled = ?3, checked = ?4, \
+            errors = ?5, discrepancies = ?6, total_drift = ?7, alerted = ?8 WHERE id = ?1",
+        )
+        .bind(&[
+            run.id.as_str().into(),
+            run.finished_at.map(num).unwrap_or(JsValue::NULL),
+            num(run.sampled as u64),
+            num(run.checked as u64),
+            num(run.errors as u64),
+            num(run.discrepancies as u64),
+            run.total_drift.as_str().into(),
+            num(run.alerted as u64),
+        ])?,
+    );
+    db.batch(statements).await?;
+
+    Ok(())
+}
+
+/// newest first
+pub async fn recent_runs(db: &D1Database, limit: u32) -> Result<Vec<RunSummary>> {
+    db.prepare("SELECT * FROM reconciliation_runs ORDER BY started_at DESC LIMIT ?1")
+        .bind(&[num(limit as u64)])?
+        .all()
+        .await?
+        .results()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiscrepancyRow {
+    pub target: String,
+    pub user_principal: String,
+    pub user_canister: Option<String>,
+    pub kind: String,
+    pub drift: String,
+    /// json encoded report
+    pub details: String,
+    pub detected_at: u64,
+}
+
+/// largest drift first
+pub async fn run_discrepancies(db: &D1Database, run_id: &str) -> Result<Vec<DiscrepancyRow>> {
+    let mut rows: Vec<DiscrepancyRow> = db
+        .prepare("SELECT * FROM reconciliation_discrepancies WHERE run_id = ?1")
+        .bind(&[run_id.into()])?
+        .all()
+        .await?
+        .results()?;
+    // drift is a decimal string, sorting in sql would compare it as text
+    rows.sort_by_cached_key(|row| {
+        std::cmp::Reverse(row.drift.parse::<BigUint>().unwrap_or_default())
+    });
+
+    Ok(rows)
+}
@@ -0,0 +1,58 @@
+-- reward campaigns, managed through PUT /campaigns/:campaign_id
+CREATE TABLE IF NOT EXISTS campaigns (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- signup, referral or streak
+    trigger TEXT NOT NULL,
+    -- sats or yral
+    token TEXT NOT NULL,
+    -- per grant, in the token's smallest unit
+    amount INTEGER NOT NULL,
+    -- consecutive active days, streak campaigns only
+    streak_days INTEGER,
+    -- grants per user
+    per_user_cap INTEGER NOT NULL,
+    -- total amount the campaign may grant, null for no limit
+    budget INTEGER,
+    -- total amount granted so far, kept by reward_grants_issued
+    issued INTEGER NOT NULL DEFAULT 0,
+    starts_at INTEGER NOT NULL,
+    -- null while the campaign runs indefinitely
+    ends_at INTEGER,
+    updated_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- one row per reward, the key dedupes redelivered events
+CREATE TABLE IF NOT EXISTS reward_grants (
+    campaign_id TEXT NOT NULL,
+    -- e.g. `referral-{referee_principal}`
+    dedupe_key TEXT NOT NULL,
+    user_principal TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    -- pending, credited or failed
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- the last error
+    detail TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (campaign_id, dedupe_key)
+);
+
+CREATE INDEX IF NOT EXISTS reward_grants_user ON reward_grants (campaign_id, user_principal);
+
+-- reserved grants count against the budget whether or not they're credited yet
+CREATE TRIGGER IF NOT EXISTS reward_grants_issued AFTER INSERT ON reward_grants
+BEGIN
+    UPDATE campaigns SET issued = issued + NEW.amount WHERE id = NEW.campaign_id;
+END;
+
+-- consecutive days with a vote, upload or claim
+CREATE TABLE IF NOT EXISTS activity_streaks (
+    user_principal TEXT PRIMARY KEY,
+    -- unix days
+    last_day INTEGER NOT NULL,
+    length INTEGER NOT NULL
+);
@@ -0,0 +1,184 @@
+//! campaign definitions, owned by this worker and managed by finance admins
+
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, *};
+
+pub const REWARDS_DB: &str = "REWARDS_DB";
+
+/// the event a campaign rewards
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// the new user
+    Signup,
+    /// the referrer, once per referee
+    Referral,
+    /// every `streak_days` consecutive days with a vote, upload or claim
+    Streak,
+}
+
+impl Trigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Signup => "signup",
+            Self::Referral => "referral",
+            Self::Streak => "streak",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Token {
+    /// credited on yral-hot-or-not
+    Sats,
+    /// credited on yral-coin
+    Yral,
+}
+
+impl Token {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sats => "sats",
+            Self::Yral => "yral",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Campaign {
+    pub id: String,
+    pub name: String,
+    pub trigger: Trigger,
+    pub token: Token,
+    pub amount: u64,
+    pub streak_days: Option<u32>,
+    pub per_user_cap: u32,
+    pub budget: Option<u64>,
+    pub issued: u64,
+    /// unix millis
+    pub starts_at: u64,
+    pub ends_at: Option<u64>,
+    pub updated_by: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// body of `PUT /campaigns/:campaign_id`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignRequest {
+    pub name: String,
+    pub trigger: Trigger,
+    pub token: Token,
+    pub amount: u64,
+    #[serde(default)]
+    pub streak_days: Option<u32>,
+    #[serde(default = "default_per_user_cap")]
+    pub per_user_cap: u32,
+    #[serde(default)]
+    pub budget: Option<u64>,
+    pub starts_at: u64,
+    /// set to now to stop a running campaign
+    #[serde(default)]
+    pub ends_at: Option<u64>,
+}
+
+fn default_per_user_cap() -> u32 {
+    1
+}
+
+impl CampaignRequest {
+    /// what's wrong with the definition, if anything
+    pub fn invalid(&self) -> Option<&'static str> {
+        if self.name.trim().is_empty() {
+            return Some("name is required");
+        }
+        if self.amount == 0 || self.per_user_cap == 0 {
+            return Some("amount and per_user_cap must be positive");
+        }
+        if self
+            .ends_at
+            .is_some_and(|ends_at| ends_at <= self.starts_at)
+        {
+            return Some("ends_at must be after starts_at");
+        }
+        match (self.trigger, self.streak_days) {
+            (Trigger::Streak, Some(days)) if days >= 2 => None,
+            (Trigger::Streak, _) => Some("streak campaigns need streak_days of at least 2"),
+            (_, Some(_)) => Some("streak_days only applies to streak campaigns"),
+            (_, None) => None,
+        }
+    }
+}
+
+pub(crate) fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+fn opt_num(v: Option<u64>) -> JsValue {
+    v.map(num).unwrap_or(JsValue::NULL)
+}
+
+/// creates or redefines the campaign, what it already issued is kept
+pub async fn put_campaign(
+    db: &D1Database,
+    id: &str,
+    req: &CampaignRequest,
+    admin: &str,
+    now: u64,
+) -> Result<Campaign> {
+    db.prepare(
+        "INSERT INTO campaigns \
+        (id, name, trigger, token, amount, streak_days, per_user_cap, budget, starts_at, ends_at, \
+        updated_by, created_at, updated_at) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?12) \
+        ON CONFLICT (id) DO UPDATE SET name = excluded.name, trigger = excluded.trigger, \
+        token = excluded.token, amount = excluded.amount, streak_days = excluded.streak_days, \
+        per_user_cap = excluded.per_user_cap, budget = excluded.budget, \
+        starts_at = excluded.starts_at, ends_at = excluded.ends_at, \
+        updated_by = excluded.updated_by, updated_at = excluded.updated_at \
+        RETURNING *",
+    )
+    .bind(&[
+        id.into(),
+        req.name.trim().into(),
+        req.trigger.as_str().into(),
+        req.token.as_str().into(),
+        num(req.amount),
+        opt_num(req.streak_days.map(u64::from)),
+        req.per_user_cap.into(),
+        opt_num(req.budget),
+        num(req.starts_at),
+        opt_num(req.ends_at),
+        admin.into(),
+        num(now),
+    ])?
+    .first(None)
+    .await?
+    .ok_or_else(|| Error::RustError(format!("campaign {id} wasn't written")))
+}
+
+pub async fn list_campaigns(db: &D1Database) -> Result<Vec<Campaign>> {
+    db.prepare("SELECT * FROM campaigns ORDER BY created_at DESC")
+        .all()
+        .await?
+        .results()
+}
+
+pub async fn campaign(db: &D1Database, id: &str) -> Result<Option<Campaign>> {
+    db.prepare("SELECT * FROM campaigns WHERE id = ?1")
+        .bind(&[id.into()])?
+        .first(None)
+        .await
+}
+
+/// campaigns running at `now`, exhausted ones included, their grants are refused anyway
+pub async fn live_campaigns(db: &D1Database, now: u64) -> Result<Vec<Campaign>> {
+    db.prepare(
+        "SELECT * FROM campaigns WHERE starts_at <= ?1 AND (ends_at IS NULL OR ends_at > ?1)",
+    )
+    .bind(&[num(now)])?
+    .all()
+    .await?
+    .results()
+}
@@ -0,0 +1,355 @@
+//! rewards granted by campaigns, reserved in d1 before they're credited
+
+use std::collections::BTreeMap;
+
+use hon_worker_common::SatsBalanceUpdateRequest;
+use num_bigint::{BigInt, BigUint};
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::do_client::DoClient;
+
+use crate::campaigns::{num, Campaign, Token};
+
+pub const USER_HON_GAME_STATE: &str = "USER_HON_GAME_STATE";
+pub const USER_YRAL_COIN_STATE: &str = "USER_YRAL_COIN_STATE";
+
+/// yral-coin keys its objects by this header
+const COIN_OWNER_HEADER: &str = "X-Yral-User-Principal";
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantStatus {
+    /// reserved, not credited yet
+    Pending,
+    Credited,
+    /// the last credit failed, the event is retried until it's dead lettered
+    Failed,
+}
+
+impl GrantStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Credited => "credited",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GrantRow {
+    /// insertion order, the cursor of `GET /campaigns/:campaign_id/grants`
+    pub seq: u64,
+    pub campaign_id: String,
+    pub dedupe_key: String,
+    pub user_principal: String,
+    pub amount: u64,
+    pub status: GrantStatus,
+    pub attempts: u32,
+    pub detail: Option<String>,
+    /// unix millis
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// what granting a reward came to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrantOutcome {
+    Credited,
+    /// the same event was already rewarded
+    Duplicate,
+    /// the user's cap or the campaign's budget is used up
+    Capped,
+}
+
+impl GrantOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Credited => "credited",
+            Self::Duplicate => "duplicate",
+            Self::Capped => "capped",
+        }
+    }
+}
+
+/// reserves the grant against the user's cap and the campaign's budget in one statement,
+/// `false` if it was already reserved or either is used up
+async fn reserve(
+    db: &D1Database,
+    campaign: &Campaign,
+    user_principal: &str,
+    dedupe_key: &str,
+    now: u64,
+) -> Result<bool> {
+    let reserved: Option<serde_json::Value> = db
+        .prepare(
+            "INSERT INTO reward_grants \
+            (campaign_id, dedupe_key, user_principal, amount, status, created_at, updated_at) \
+            SELECT id, ?2, ?3, amount, 'pending', ?4, ?4 FROM campaigns \
+            WHERE id = ?1 AND (budget IS NULL OR issued + amount <= budget) \
+            AND (SELECT COUNT(*) FROM reward_grants WHERE campaign_id = ?1 \
+            AND user_principal = ?3) < per_user_cap \
+            ON CONFLICT DO NOTHING RETURNING status",
+        )
+        .bind(&[
+            campaign.id.as_str().into(),
+            dedupe_key.into(),
+            user_principal.into(),
+            num(now),
+        ])?
+        .first(None)
+        .await?;
+
+    Ok(reserved.is_some())
+}
+
+async fn existing_status(
+    db: &D1Database,
+    campaign_id: &str,
+    dedupe_key: &str,
+) -> Result<Option<GrantStatus>> {
+    #[derive(Deserialize)]
+    struct Existing {
+        status: GrantStatus,
+    }
+
+    let existing: Option<Existing> = db
+        .prepare("SELECT status FROM reward_grants WHERE campaign_id = ?1 AND dedupe_key = ?2")
+        .bind(&[campaign_id.into(), dedupe_key.into()])?
+        .first(None)
+        .await?;
+
+    Ok(existing.map(|e| e.status))
+}
+
+async fn record(
+    db: &D1Database,
+    campaign_id: &str,
+    dedupe_key: &str,
+    status: GrantStatus,
+    detail: Option<&str>,
+    now: u64,
+) -> Result<()> {
+    db.prepare(
+        "UPDATE reward_grants SET status = ?3, attempts = attempts + 1, detail = ?4, \
+        updated_at = ?5 WHERE campaign_id = ?1 AND dedupe_key = ?2",
+    )
+    .bind(&[
+        campaign_id.into(),
+        dedupe_key.into(),
+        status.as_str().into(),
+        detail.map(JsValue::from).unwrap_or(JsValue::NULL),
+        num(now),
+    ])?
+    .run()
+    .await?;
+
+    Ok(())
+}
+
+async fn expect_ok(mut res: Response, what: &str) -> Result<()> {
+    if (200..300).contains(&res.status_code()) {
+        return Ok(());
+    }
+    let body = res.text().await.unwrap_or_default();
+    Err(Error::RustError(format!(
+        "{what} responded with {}: {body}",
+        res.status_code()
+    )))
+}
+
+/// same shape as hot-or-not's `IdempotentUpdate<SatsBalanceUpdateRequest>`
+#[derive(Serialize)]
+struct SatsUpdate {
+    #[serde(flatten)]
+    request: SatsBalanceUpdateRequest,
+    idempotency_key: String,
+}
+
+/// hot-or-not replays the idempotency key for a day, a retried credit is never applied twice
+async fn credit_sats(
+    env: &Env,
+    campaign: &Campaign,
+    user_principal: &str,
+    dedupe_key: &str,
+) -> Result<()> {
+    let res = DoClient::new(env)
+        .post(
+            USER_HON_GAME_STATE,
+            user_principal,
+            "update_balance",
+            &SatsUpdate {
+                request: SatsBalanceUpdateRequest {
+                    delta: BigInt::from(campaign.amount),
+                    is_airdropped: false,
+                },
+                idempotency_key: format!("reward-{}-{dedupe_key}", campaign.id),
+            },
+        )
+        .await?;
+
+    expect_ok(res, "yral-hot-or-not").await
+}
+
+/// same shape as yral-coin's `YralBalanceUpdateRequest`
+#[derive(Serialize)]
+struct CoinUpdate {
+    previous_balance: BigUint,
+    delta: String,
+    reason: &'static str,
+    description: String,
+    metadata: BTreeMap<&'static str, String>,
+    idempotency_key: String,
+    /// yral-coin notifies the user about campaign credits
+    campaign: bool,
+}
+
+#[derive(Deserialize)]
+struct CoinBalance {
+    balance: String,
+}
+
+/// yral-coin remembers the idempotency key, a retried credit is never applied twice
+async fn credit_yral(
+    env: &Env,
+    campaign: &Campaign,
+    user_principal: &str,
+    dedupe_key: &str,
+) -> Result<()> {
+    let client = DoClient::new(env).with_name_header(COIN_OWNER_HEADER);
+    let mut balance_res = client
+        .get(USER_YRAL_COIN_STATE, user_principal, "balance")
+        .await?;
+    if balance_res.status_code() != 200 {
+        return expect_ok(balance_res, "yral-coin balance").await;
+    }
+    let balance: CoinBalance = balance_res.json().await?;
+    let previous_balance = balance
+        .balance
+        .parse()
+        .map_err(|e| Error::RustError(format!("invalid coin balance: {e}")))?;
+
+    let res = client
+        .post(
+            USER_YRAL_COIN_STATE,
+            user_principal,
+            "update_balance",
+            &CoinUpdate {
+                previous_balance,
+                delta: campaign.amount.to_string(),
+                reason: "Campaign",
+                description: campaign.name.clone(),
+                metadata: BTreeMap::from([("campaign_id", campaign.id.clone())]),
+                idempotency_key: format!("reward-{}-{dedupe_key}", campaign.id),
+                campaign: true,
+            },
+        )
+        .await?;
+
+    expect_ok(res, "yral-coin").await
+}
+
+/// reserves and credits one reward, `dedupe_key` identifies the event within the campaign
+///
+/// errors are recorded on the grant before they're returned, the caller retries the event
+pub async fn grant(
+    env: &Env,
+    db: &D1Database,
+    campaign: &Campaign,
+    user_principal: &str,
+    dedupe_key: &str,
+    now: u64,
+) -> Result<GrantOutcome> {
+    // a pending or failed grant is credited again
+    if !reserve(db, campaign, user_principal, dedupe_key, now).await? {
+        match existing_status(db, &campaign.id, dedupe_key).await? {
+            Some(GrantStatus::Credited) => return Ok(GrantOutcome::Duplicate),
+            Some(_) => {}
+            None => return Ok(GrantOutcome::Capped),
+        }
+    }
+
+    let res = match campaign.token {
+        Token::Sats => credit_sats(env, campaign, user_principal, dedupe_key).await,
+        Token::Yral => credit_yral(env, campaign, user_principal, dedupe_key).await,
+    };
+    match &res {
+        Ok(()) => {
+            record(
+                db,
+                &campaign.id,
+                dedupe_key,
+                GrantStatus::Credited,
+                None,
+                now,
+            )
+            .await?
+        }
+        Err(e) => {
+            let detail = e.to_string();
+            record(
+                db,
+                &campaign.id,
+                dedupe_key,
+                GrantStatus::Failed,
+                Some(&detail),
+                now,
+            )
+            .await?
+        }
+    }
+
+    res.map(|_| GrantOutcome::Credited)
+}
+
+pub async fn list_grants(
+    db: &D1Database,
+    campaign_id: &str,
+    after: u64,
+    limit: u32,
+) -> Result<Vec<GrantRow>> {
+    db.prepare(
+        "SELECT rowid AS seq, * FROM reward_grants WHERE campaign_id = ?1 AND rowid > ?2 \
+        ORDER BY rowid LIMIT ?3",
+    )
+    .bind(&[campaign_id.into(), num(after), limit.into()])?
+    .all()
+    .await?
+    .results()
+}
+
+/// the user's current streak
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Streak {
+    /// unix days
+    pub last_day: u64,
+    pub length: u64,
+}
+
+impl Streak {
+    /// one key per `streak_days` completed in a run of active days
+    pub fn dedupe_key(self, streak_days: u32) -> Option<String> {
+        let completed = self.length / u64::from(streak_days);
+        let start_day = self.last_day + 1 - self.length;
+
+        (completed > 0).then(|| format!("streak-{start_day}-{completed}"))
+    }
+}
+
+/// counts the day of `at` towards the user's streak, late events don't break it
+pub async fn record_active_day(db: &D1Database, user_principal: &str, at: u64) -> Result<Streak> {
+    db.prepare(
+        "INSERT INTO activity_streaks (user_principal, last_day, length) VALUES (?1, ?2, 1) \
+        ON CONFLICT (user_principal) DO UPDATE SET \
+        length = CASE WHEN excluded.last_day = last_day + 1 THEN length + 1 \
+        WHEN excluded.last_day <= last_day THEN length ELSE 1 END, \
+        last_day = MAX(last_day, excluded.last_day) \
+        RETURNING last_day, length",
+    )
+    .bind(&[user_principal.into(), num(at / DAY_MS)])?
+    .first(None)
+    .await?
+    .ok_or_else(|| Error::RustError(format!("streak of {user_principal} wasn't written")))
+}
@@ -0,0 +1,271 @@
+mod campaigns;
+mod grants;
+mod jwt;
+
+use campaigns::{
+    campaign, list_campaigns, live_campaigns, put_campaign, Campaign, CampaignRequest, Trigger,
+    REWARDS_DB,
+};
+use grants::{grant, list_grants, record_active_day, USER_HON_GAME_STATE, USER_YRAL_COIN_STATE};
+use jwt::JWT_PUBKEY;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{
+    analytics::AnalyticsEvent,
+    api_error::error_resp,
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::{authorize, JwtKeys, Role},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    time::now_millis,
+};
+
+const MAX_CAMPAIGN_ID_LEN: usize = 64;
+
+const DEFAULT_GRANTS_LIMIT: u32 = 100;
+const MAX_GRANTS_LIMIT: u32 = 500;
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-rewards",
+    &[
+        Dependency::D1(REWARDS_DB),
+        Dependency::DurableObject(USER_HON_GAME_STATE),
+        Dependency::DurableObject(USER_YRAL_COIN_STATE),
+    ],
+);
+
+macro_rules! admin {
+    ($ctx:expr, $req:expr) => {
+        match authorize(
+            &JwtKeys::from_env(&$ctx.env, JWT_PUBKEY),
+            &$req,
+            "Authorization",
+            Role::Finance,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        }
+    };
+}
+
+#[derive(Deserialize)]
+struct GrantsQuery {
+    /// `seq` of the last grant of the previous page
+    after: Option<u64>,
+    limit: Option<u32>,
+}
+
+async fn campaigns(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req);
+
+    let db = ctx.env.d1(REWARDS_DB)?;
+    Response::from_json(&list_campaigns(&db).await?)
+}
+
+async fn get_campaign(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req);
+    let Some(campaign_id) = ctx.param("campaign_id") else {
+        return error_resp("campaign_id is required", 400);
+    };
+
+    let db = ctx.env.d1(REWARDS_DB)?;
+    match campaign(&db, campaign_id).await? {
+        Some(campaign) => Response::from_json(&campaign),
+        None => error_resp("campaign not found", 404),
+    }
+}
+
+/// creates or redefines a campaign, lowering its budget below what it issued stops its grants
+async fn upsert_campaign(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req);
+    let Some(campaign_id) = ctx.param("campaign_id").cloned() else {
+        return error_resp("campaign_id is required", 400);
+    };
+    let valid_id = campaign_id.len() <= MAX_CAMPAIGN_ID_LEN
+        && campaign_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid_id {
+        return error_resp(
+            format!("campaign_id must be at most {MAX_CAMPAIGN_ID_LEN} letters, digits, - or _"),
+            400,
+        );
+    }
+    let body: CampaignRequest = json_body!(req);
+    if let Some(msg) = body.invalid() {
+        return error_resp(msg, 400);
+    }
+
+    let db = ctx.env.d1(REWARDS_DB)?;
+    let campaign = put_campaign(&db, &campaign_id, &body, &admin.sub, now_millis()).await?;
+    Response::from_json(&campaign)
+}
+
+/// `GET /campaigns/:campaign_id/grants?after=&limit=`, oldest first
+async fn campaign_grants(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req);
+    let Some(campaign_id) = ctx.param("campaign_id") else {
+        return error_resp("campaign_id is required", 400);
+    };
+    let Ok(query) = req.query::<GrantsQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_GRANTS_LIMIT)
+        .min(MAX_GRANTS_LIMIT);
+
+    let db = ctx.env.d1(REWARDS_DB)?;
+    let grants = list_grants(&db, campaign_id, query.after.unwrap_or(0), limit).await?;
+    // a short page is the last one
+    let next = if grants.len() == limit as usize {
+        grants.last().map(|g| g.seq)
+    } else {
+        None
+    };
+
+    Response::from_json(&json!({ "grants": grants, "next": next }))
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    // campaigns are managed by admins
+    if let Some(notice) = MaintenanceNotice::check(
+        &env,
+        "yral-rewards",
+        method.as_ref(),
+        &path,
+        &["/campaigns/"],
+    )
+    .await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/campaigns", campaigns)
+        .get_async("/campaigns/:campaign_id", get_campaign)
+        .put_async("/campaigns/:campaign_id", upsert_campaign)
+        .get_async("/campaigns/:campaign_id/grants", campaign_grants)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// grants every reward `event` triggers, the first failure is returned once all were tried
+async fn reward_event(
+    env: &Env,
+    db: &D1Database,
+    campaigns: &[Campaign],
+    event: &AnalyticsEvent,
+    metrics: &Metrics,
+    now: u64,
+) -> Result<()> {
+    let triggered = |trigger| campaigns.iter().filter(move |c| c.trigger == trigger);
+    let user_principal = event.user_principal();
+
+    let mut grants = vec![];
+    match event {
+        AnalyticsEvent::Signup { .. } => {
+            grants.extend(triggered(Trigger::Signup).map(|c| (c, "signup".to_string())));
+        }
+        AnalyticsEvent::Referral {
+            referee_principal, ..
+        } => {
+            let key = format!("referral-{referee_principal}");
+            grants.extend(triggered(Trigger::Referral).map(|c| (c, key.clone())));
+        }
+        // failures aren't activity a streak should count
+        AnalyticsEvent::VideoUploadFailed { .. } => {}
+        AnalyticsEvent::Vote { .. }
+        | AnalyticsEvent::VideoPublished { .. }
+        | AnalyticsEvent::Claim { .. } => {
+            let streak_campaigns: Vec<_> = triggered(Trigger::Streak).collect();
+            // streaks are only tracked while a campaign rewards them
+            if streak_campaigns.is_empty() {
+                return Ok(());
+            }
+            let streak = record_active_day(db, user_principal, event.at()).await?;
+            grants.extend(streak_campaigns.into_iter().filter_map(|c| {
+                let key = c.streak_days.and_then(|days| streak.dedupe_key(days))?;
+                Some((c, key))
+            }));
+        }
+    }
+
+    let mut res = Ok(());
+    for (campaign, key) in grants {
+        let outcome = match grant(env, db, campaign, user_principal, &key, now).await {
+            Ok(outcome) => outcome.as_str(),
+            Err(e) => {
+                console_error!(
+                    "campaign {} failed to reward {user_principal}: {e}",
+                    campaign.id
+                );
+                res = res.and(Err(e));
+                "failed"
+            }
+        };
+        metrics.counter("reward_grants", &[campaign.trigger.as_str(), outcome]);
+    }
+
+    res
+}
+
+/// rewards events one by one, a failed event is retried alone and its granted rewards are deduped
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    let db = env.d1(REWARDS_DB)?;
+    let metrics = Metrics::new(&env, "yral-rewards");
+    let now = now_millis();
+    let campaigns = match live_campaigns(&db, now).await {
+        Ok(campaigns) => campaigns,
+        Err(e) => {
+            console_error!("failed to load live campaigns: {e}");
+            batch.retry_all();
+            return Ok(());
+        }
+    };
+    if campaigns.is_empty() {
+        batch.ack_all();
+        return Ok(());
+    }
+
+    for message in batch.messages()? {
+        let event = match serde_json::from_value::<AnalyticsEvent>(message.body().clone()) {
+            Ok(event) => event,
+            Err(e) => {
+                console_error!("dropping malformed reward event {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+        match reward_event(&env, &db, &campaigns, &event, &metrics, now).await {
+            Ok(()) => message.ack(),
+            Err(_) => message.retry(),
+        }
+    }
+
+    Ok(())
+}
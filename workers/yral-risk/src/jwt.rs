@@ -0,0 +1,10 @@
+use worker_utils::jwt::JwtPolicy;
+
+pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
+-----END PUBLIC KEY-----";
+
+/// issued to the workers that hold payouts, see `worker_utils::risk::RISK_TOKEN_SECRET`
+pub const JWT_AUD: &str = "yral-risk";
+
+pub const JWT_POLICY: JwtPolicy = JwtPolicy::expiring(60);
@@ -0,0 +1,123 @@
+mod jwt;
+mod principal_risk;
+mod scoring;
+
+use std::collections::HashMap;
+
+use jwt::{JWT_AUD, JWT_POLICY, JWT_PUBKEY};
+use principal_risk::PRINCIPAL_RISK;
+use serde_json::Value;
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    cors::cors_for_env,
+    do_client::DoClient,
+    health::{Dependency, HealthCheck},
+    jwt::JwtKeys,
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    principals,
+    risk::RiskSignal,
+};
+
+static HEALTH: HealthCheck =
+    HealthCheck::new("yral-risk", &[Dependency::DurableObject(PRINCIPAL_RISK)]);
+
+/// `GET /risk/:principal`, consulted by payouts through `worker_utils::risk::assess`
+async fn principal_risk(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Err((msg, code)) = JwtKeys::from_env(&ctx.env, JWT_PUBKEY)
+        .verify_from_header(JWT_AUD.into(), JWT_POLICY, &req)
+        .await
+    {
+        return error_resp(msg, code);
+    }
+    let principal = principals!(ctx, "principal").to_text();
+
+    DoClient::new(&ctx.env)
+        .get(
+            PRINCIPAL_RISK,
+            &principal,
+            &format!("assessment/{principal}"),
+        )
+        .await
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-risk", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/risk/:principal", principal_risk)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// hands each principal's signals to its object in one call, a failed principal is retried alone
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    let metrics = Metrics::new(&env, "yral-risk");
+    let mut by_principal = HashMap::<String, Vec<(Message<Value>, RiskSignal)>>::new();
+    for message in batch.messages()? {
+        match serde_json::from_value::<RiskSignal>(message.body().clone()) {
+            Ok(signal) => by_principal
+                .entry(signal.user_principal().to_string())
+                .or_default()
+                .push((message, signal)),
+            Err(e) => {
+                console_error!("dropping malformed risk signal {}: {e}", message.id());
+                metrics.counter("risk_signals", &["malformed"]);
+                message.ack();
+            }
+        }
+    }
+
+    let client = DoClient::new(&env);
+    for (principal, entries) in by_principal {
+        let signals: Vec<_> = entries.iter().map(|(_, signal)| signal).collect();
+        let res = client
+            .post(PRINCIPAL_RISK, &principal, "signals", &signals)
+            .await;
+        match res {
+            Ok(res) if res.status_code() == 200 => {
+                for (message, signal) in &entries {
+                    metrics.counter("risk_signals", &[signal.kind()]);
+                    message.ack();
+                }
+            }
+            Ok(res) => {
+                console_error!(
+                    "failed to record risk signals of {principal}: status {}",
+                    res.status_code()
+                );
+                entries.iter().for_each(|(message, _)| message.retry());
+            }
+            Err(e) => {
+                console_error!("failed to record risk signals of {principal}: {e}");
+                entries.iter().for_each(|(message, _)| message.retry());
+            }
+        }
+    }
+
+    Ok(())
+}
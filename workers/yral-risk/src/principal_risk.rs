@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+
+use worker::*;
+use worker_utils::{
+    risk::RiskSignal,
+    storage::{SafeStorage, StorageCell},
+    time::now_millis,
+};
+
+use crate::scoring::RiskState;
+
+pub const PRINCIPAL_RISK: &str = "PRINCIPAL_RISK";
+
+const DEFAULT_HOLD_THRESHOLD: u32 = 70;
+
+/// signals and score of one principal
+#[durable_object]
+pub struct PrincipalRisk {
+    state: State,
+    env: Env,
+    risk: RefCell<StorageCell<RiskState>>,
+}
+
+// SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+// because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+// The RefCell interior mutability pattern is required due to Worker 0.7.4 API changes
+// that mandate `&self` instead of `&mut self` for DurableObject trait methods.
+#[allow(clippy::await_holding_refcell_ref)]
+impl PrincipalRisk {
+    fn storage(&self) -> SafeStorage {
+        self.state.storage().into()
+    }
+
+    fn hold_threshold(&self) -> u32 {
+        self.env
+            .var("RISK_HOLD_THRESHOLD")
+            .ok()
+            .and_then(|v| v.to_string().parse().ok())
+            .unwrap_or(DEFAULT_HOLD_THRESHOLD)
+    }
+
+    async fn record(&self, signals: &[RiskSignal]) -> Result<()> {
+        let mut storage = self.storage();
+        self.risk
+            .borrow_mut()
+            .update(&mut storage, |risk| {
+                for signal in signals {
+                    risk.record(signal);
+                }
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+// SAFETY: See comment on first impl block for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl DurableObject for PrincipalRisk {
+    fn new(state: State, env: Env) -> Self {
+        console_error_panic_hook::set_once();
+
+        Self {
+            state,
+            env,
+            risk: RefCell::new(StorageCell::new("risk", RiskState::default)),
+        }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let env = self.env.clone();
+        let router = Router::with_data(self);
+
+        router
+            .post_async("/signals", async |mut req, ctx| {
+                let signals: Vec<RiskSignal> = req.json().await?;
+                ctx.data.record(&signals).await?;
+
+                Response::ok("done")
+            })
+            .get_async("/assessment/:principal", async |_, ctx| {
+                let Some(principal) = ctx.param("principal") else {
+                    return Response::error("principal is required", 400);
+                };
+                let this = ctx.data;
+                let storage = this.storage();
+                let assessment = this.risk.borrow_mut().read(&storage).await?.assess(
+                    principal,
+                    now_millis(),
+                    this.hold_threshold(),
+                );
+
+                Response::from_json(&assessment)
+            })
+            .run(req, env)
+            .await
+    }
+}
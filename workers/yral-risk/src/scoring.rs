@@ -0,0 +1,276 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use worker_utils::risk::{RiskAssessment, RiskSignal};
+
+const VOTE_WINDOW_MS: u64 = 60 * 1000;
+/// a vote burst keeps counting against the principal this long
+const VOTE_PEAK_TTL_MS: u64 = 60 * 60 * 1000;
+const MAX_VOTES_PER_WINDOW: u32 = 30;
+
+const REFERRAL_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+const MAX_REFERRALS_PER_WINDOW: usize = 20;
+
+const WITHDRAWAL_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+const MAX_WITHDRAWALS_PER_WINDOW: usize = 5;
+/// 100k SATS
+const LARGE_SATS_WITHDRAWAL: u128 = 100_000;
+/// 1000 DOLR, in e8s
+const LARGE_DOLR_WITHDRAWAL: u128 = 1_000 * 100_000_000;
+
+const MAX_DEVICES: usize = 5;
+/// devices past this aren't tracked, the principal is already flagged
+const MAX_TRACKED_DEVICES: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Withdrawal {
+    economy: String,
+    amount: u128,
+    at: u64,
+}
+
+/// what `PrincipalRisk` remembers about a principal, older signals are pruned as new ones arrive
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RiskState {
+    /// unix millis
+    vote_window_start: u64,
+    votes_in_window: u32,
+    /// most votes seen in one window, until `VOTE_PEAK_TTL_MS` after `peak_votes_at`
+    peak_votes: u32,
+    peak_votes_at: u64,
+    referrals: Vec<u64>,
+    withdrawals: Vec<Withdrawal>,
+    devices: BTreeSet<String>,
+}
+
+fn large_withdrawal(economy: &str) -> u128 {
+    match economy {
+        "sats" => LARGE_SATS_WITHDRAWAL,
+        "dolr" => LARGE_DOLR_WITHDRAWAL,
+        _ => u128::MAX,
+    }
+}
+
+impl RiskState {
+    pub fn record(&mut self, signal: &RiskSignal) {
+        let at = signal.at();
+        match signal {
+            RiskSignal::Vote { .. } => {
+                if at >= self.vote_window_start + VOTE_WINDOW_MS {
+                    self.vote_window_start = at;
+                    self.votes_in_window = 0;
+                }
+                self.votes_in_window += 1;
+                if self.votes_in_window > self.peak_votes
+                    || self.peak_votes_at + VOTE_PEAK_TTL_MS < at
+                {
+                    self.peak_votes = self.votes_in_window;
+                    self.peak_votes_at = at;
+                }
+            }
+            RiskSignal::Referral { .. } => {
+                self.referrals.retain(|t| t + REFERRAL_WINDOW_MS > at);
+                self.referrals.push(at);
+            }
+            RiskSignal::Withdrawal {
+                economy, amount, ..
+            } => {
+                self.withdrawals
+                    .retain(|w| w.at + WITHDRAWAL_WINDOW_MS > at);
+                self.withdrawals.push(Withdrawal {
+                    economy: economy.clone(),
+                    amount: amount.parse().unwrap_or(u128::MAX),
+                    at,
+                });
+            }
+            RiskSignal::Device { device_id, .. } => {
+                if self.devices.len() < MAX_TRACKED_DEVICES {
+                    self.devices.insert(device_id.clone());
+                }
+            }
+        }
+    }
+
+    /// 0 to 100, each tripped check adds its weight
+    pub fn assess(&self, principal: &str, now: u64, hold_threshold: u32) -> RiskAssessment {
+        let mut checks = vec![];
+        if self.peak_votes > MAX_VOTES_PER_WINDOW && self.peak_votes_at + VOTE_PEAK_TTL_MS > now {
+            checks.push(("vote_velocity", 40));
+        }
+        let referrals = self
+            .referrals
+            .iter()
+            .filter(|t| *t + REFERRAL_WINDOW_MS > now)
+            .count();
+        if referrals > MAX_REFERRALS_PER_WINDOW {
+            checks.push(("referral_burst", 30));
+        }
+        let withdrawals: Vec<_> = self
+            .withdrawals
+            .iter()
+            .filter(|w| w.at + WITHDRAWAL_WINDOW_MS > now)
+            .collect();
+        if withdrawals.len() > MAX_WITHDRAWALS_PER_WINDOW {
+            checks.push(("withdrawal_frequency", 20));
+        }
+        if withdrawals
+            .iter()
+            .any(|w| w.amount >= large_withdrawal(&w.economy))
+        {
+            checks.push(("large_withdrawal", 30));
+        }
+        if self.devices.len() > MAX_DEVICES {
+            checks.push(("many_devices", 20));
+        }
+
+        let score = checks
+            .iter()
+            .map(|(_, weight)| weight)
+            .sum::<u32>()
+            .min(100);
+        RiskAssessment {
+            principal: principal.to_string(),
+            score,
+            reasons: checks
+                .iter()
+                .map(|(reason, _)| reason.to_string())
+                .collect(),
+            hold_payouts: score >= hold_threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRINCIPAL: &str = "2vxsx-fae";
+    const START: u64 = 1_700_000_000_000;
+
+    fn votes(state: &mut RiskState, count: u32, at: u64) {
+        for _ in 0..count {
+            state.record(&RiskSignal::Vote {
+                user_principal: PRINCIPAL.into(),
+                at,
+            });
+        }
+    }
+
+    fn withdraw(state: &mut RiskState, economy: &str, amount: u128, at: u64) {
+        state.record(&RiskSignal::Withdrawal {
+            user_principal: PRINCIPAL.into(),
+            economy: economy.into(),
+            amount: amount.to_string(),
+            at,
+        });
+    }
+
+    #[test]
+    fn quiet_principals_score_zero() {
+        let mut state = RiskState::default();
+        votes(&mut state, MAX_VOTES_PER_WINDOW, START);
+        withdraw(&mut state, "sats", LARGE_SATS_WITHDRAWAL - 1, START);
+
+        let assessment = state.assess(PRINCIPAL, START, 70);
+        assert_eq!(assessment.principal, PRINCIPAL);
+        assert_eq!(assessment.score, 0);
+        assert!(assessment.reasons.is_empty());
+        assert!(!assessment.hold_payouts);
+    }
+
+    #[test]
+    fn vote_bursts_count_until_their_peak_expires() {
+        let mut state = RiskState::default();
+        votes(&mut state, MAX_VOTES_PER_WINDOW + 1, START);
+        // a quiet window afterwards doesn't clear the burst
+        votes(&mut state, 1, START + VOTE_WINDOW_MS);
+
+        let assessment = state.assess(PRINCIPAL, START + VOTE_WINDOW_MS, 70);
+        assert_eq!(assessment.reasons, ["vote_velocity"]);
+        assert_eq!(assessment.score, 40);
+
+        let later = state.assess(PRINCIPAL, START + VOTE_PEAK_TTL_MS, 70);
+        assert_eq!(later.score, 0);
+    }
+
+    #[test]
+    fn only_recent_referrals_count() {
+        let mut state = RiskState::default();
+        for i in 0..=MAX_REFERRALS_PER_WINDOW as u64 {
+            state.record(&RiskSignal::Referral {
+                user_principal: PRINCIPAL.into(),
+                referee: format!("referee-{i}"),
+                at: START + i,
+            });
+        }
+
+        let assessment = state.assess(PRINCIPAL, START + 100, 70);
+        assert_eq!(assessment.reasons, ["referral_burst"]);
+
+        let next_day = state.assess(PRINCIPAL, START + REFERRAL_WINDOW_MS, 70);
+        assert!(next_day.reasons.is_empty());
+    }
+
+    #[test]
+    fn large_withdrawals_are_judged_per_economy() {
+        let mut state = RiskState::default();
+        withdraw(&mut state, "dolr", LARGE_SATS_WITHDRAWAL, START);
+        withdraw(&mut state, "unknown", u128::MAX - 1, START);
+        assert!(state.assess(PRINCIPAL, START, 70).reasons.is_empty());
+
+        withdraw(&mut state, "sats", LARGE_SATS_WITHDRAWAL, START);
+        let assessment = state.assess(PRINCIPAL, START, 70);
+        assert_eq!(assessment.reasons, ["large_withdrawal"]);
+        assert_eq!(assessment.score, 30);
+    }
+
+    #[test]
+    fn frequent_withdrawals_are_flagged() {
+        let mut state = RiskState::default();
+        for i in 0..=MAX_WITHDRAWALS_PER_WINDOW as u64 {
+            withdraw(&mut state, "sats", 1, START + i);
+        }
+
+        let assessment = state.assess(PRINCIPAL, START + 100, 70);
+        assert_eq!(assessment.reasons, ["withdrawal_frequency"]);
+    }
+
+    #[test]
+    fn devices_are_tracked_up_to_a_cap() {
+        let mut state = RiskState::default();
+        for i in 0..MAX_TRACKED_DEVICES + 10 {
+            state.record(&RiskSignal::Device {
+                user_principal: PRINCIPAL.into(),
+                device_id: format!("device-{i}"),
+                at: START,
+            });
+        }
+
+        assert_eq!(state.devices.len(), MAX_TRACKED_DEVICES);
+        assert_eq!(state.assess(PRINCIPAL, START, 70).reasons, ["many_devices"]);
+    }
+
+    #[test]
+    fn scores_are_capped_and_hold_at_the_threshold() {
+        let mut state = RiskState::default();
+        votes(&mut state, MAX_VOTES_PER_WINDOW + 1, START);
+        withdraw(&mut state, "sats", LARGE_SATS_WITHDRAWAL, START);
+
+        let assessment = state.assess(PRINCIPAL, START, 70);
+        assert_eq!(assessment.score, 70);
+        assert!(assessment.hold_payouts);
+        assert!(!state.assess(PRINCIPAL, START, 71).hold_payouts);
+
+        for i in 0..=MAX_WITHDRAWALS_PER_WINDOW as u64 {
+            withdraw(&mut state, "sats", 1, START + i);
+        }
+        for i in 0..=MAX_DEVICES {
+            state.record(&RiskSignal::Device {
+                user_principal: PRINCIPAL.into(),
+                device_id: format!("device-{i}"),
+                at: START,
+            });
+        }
+        assert_eq!(state.assess(PRINCIPAL, START + 100, 70).score, 100);
+    }
+}
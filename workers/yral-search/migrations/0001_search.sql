@@ -0,0 +1,48 @@
+-- posts as last indexed, post ids are only unique within their canister
+CREATE TABLE IF NOT EXISTS posts (
+    canister_id TEXT NOT NULL,
+    post_id TEXT NOT NULL,
+    creator_principal TEXT NOT NULL,
+    video_uid TEXT NOT NULL,
+    description TEXT NOT NULL,
+    -- normalized and space separated, e.g. `beach sunset`
+    hashtags TEXT NOT NULL,
+    is_nsfw INTEGER NOT NULL,
+    -- removed posts keep their row, so late events can't bring them back
+    removed INTEGER NOT NULL DEFAULT 0,
+    published_at INTEGER NOT NULL,
+    -- `at` of the last applied event
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (canister_id, post_id)
+);
+
+CREATE INDEX IF NOT EXISTS posts_creator ON posts (creator_principal, published_at);
+
+-- full text index over posts, kept in sync by the triggers below
+CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(
+    description,
+    hashtags,
+    creator_principal,
+    content = 'posts',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS posts_fts_insert AFTER INSERT ON posts
+BEGIN
+    INSERT INTO posts_fts (rowid, description, hashtags, creator_principal)
+    VALUES (NEW.rowid, NEW.description, NEW.hashtags, NEW.creator_principal);
+END;
+
+CREATE TRIGGER IF NOT EXISTS posts_fts_delete AFTER DELETE ON posts
+BEGIN
+    INSERT INTO posts_fts (posts_fts, rowid, description, hashtags, creator_principal)
+    VALUES ('delete', OLD.rowid, OLD.description, OLD.hashtags, OLD.creator_principal);
+END;
+
+CREATE TRIGGER IF NOT EXISTS posts_fts_update AFTER UPDATE ON posts
+BEGIN
+    INSERT INTO posts_fts (posts_fts, rowid, description, hashtags, creator_principal)
+    VALUES ('delete', OLD.rowid, OLD.description, OLD.hashtags, OLD.creator_principal);
+    INSERT INTO posts_fts (rowid, description, hashtags, creator_principal)
+    VALUES (NEW.rowid, NEW.description, NEW.hashtags, NEW.creator_principal);
+END;
@@ -0,0 +1,152 @@
+//! applies `SearchIndexEvent`s to `posts`, `posts_fts` follows through the triggers
+
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::search::{PostDocument, SearchIndexEvent};
+
+pub const SEARCH_DB: &str = "SEARCH_DB";
+
+const MAX_HASHTAGS: usize = 30;
+const MAX_HASHTAG_LEN: usize = 64;
+const MAX_DESCRIPTION_LEN: usize = 2000;
+
+pub(crate) fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+/// lowercase alphanumerics without the `#`, so every hashtag is a single fts token
+pub fn normalize_hashtag(tag: &str) -> String {
+    tag.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .take(MAX_HASHTAG_LEN)
+        .collect()
+}
+
+/// normalized, deduped and space separated, as stored in `posts.hashtags`
+pub fn normalize_hashtags(tags: &[String]) -> String {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|t| normalize_hashtag(t)) {
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+        if normalized.len() == MAX_HASHTAGS {
+            break;
+        }
+    }
+    normalized.join(" ")
+}
+
+fn description(description: &str) -> String {
+    description.chars().take(MAX_DESCRIPTION_LEN).collect()
+}
+
+pub enum Applied {
+    /// indexed, or ignored because a newer event already was
+    Done,
+    /// an edit of a post that isn't indexed yet, retried until its publish arrives
+    UnknownPost,
+}
+
+async fn publish(db: &D1Database, post: &PostDocument, at: u64) -> Result<()> {
+    db.prepare(
+        "INSERT INTO posts (canister_id, post_id, creator_principal, video_uid, description, \
+        hashtags, is_nsfw, removed, published_at, updated_at) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0, ?8, ?8) \
+        ON CONFLICT (canister_id, post_id) DO UPDATE SET \
+        creator_principal = excluded.creator_principal, video_uid = excluded.video_uid, \
+        description = excluded.description, hashtags = excluded.hashtags, \
+        is_nsfw = excluded.is_nsfw, updated_at = excluded.updated_at \
+        WHERE posts.removed = 0 AND excluded.updated_at >= posts.updated_at",
+    )
+    .bind(&[
+        post.canister_id.as_str().into(),
+        post.post_id.as_str().into(),
+        post.creator_principal.as_str().into(),
+        post.video_uid.as_str().into(),
+        description(&post.description).into(),
+        normalize_hashtags(&post.hashtags).into(),
+        num(post.is_nsfw as u64),
+        num(at),
+    ])?
+    .run()
+    .await?;
+
+    Ok(())
+}
+
+async fn edit(
+    db: &D1Database,
+    canister_id: &str,
+    post_id: &str,
+    desc: &str,
+    hashtags: &[String],
+    at: u64,
+) -> Result<Applied> {
+    let known = db
+        .prepare("SELECT 1 AS known FROM posts WHERE canister_id = ?1 AND post_id = ?2")
+        .bind(&[canister_id.into(), post_id.into()])?
+        .first::<u32>(Some("known"))
+        .await?
+        .is_some();
+    if !known {
+        return Ok(Applied::UnknownPost);
+    }
+
+    db.prepare(
+        "UPDATE posts SET description = ?3, hashtags = ?4, updated_at = ?5 \
+        WHERE canister_id = ?1 AND post_id = ?2 AND removed = 0 AND updated_at <= ?5",
+    )
+    .bind(&[
+        canister_id.into(),
+        post_id.into(),
+        description(desc).into(),
+        normalize_hashtags(hashtags).into(),
+        num(at),
+    ])?
+    .run()
+    .await?;
+
+    Ok(Applied::Done)
+}
+
+/// keeps a tombstone even for posts that were never indexed, so a late publish stays removed
+async fn remove(db: &D1Database, canister_id: &str, post_id: &str, at: u64) -> Result<()> {
+    db.prepare(
+        "INSERT INTO posts (canister_id, post_id, creator_principal, video_uid, description, \
+        hashtags, is_nsfw, removed, published_at, updated_at) \
+        VALUES (?1, ?2, '', '', '', '', 0, 1, ?3, ?3) \
+        ON CONFLICT (canister_id, post_id) DO UPDATE SET removed = 1, \
+        updated_at = max(posts.updated_at, excluded.updated_at)",
+    )
+    .bind(&[canister_id.into(), post_id.into(), num(at)])?
+    .run()
+    .await?;
+
+    Ok(())
+}
+
+pub async fn apply(db: &D1Database, event: &SearchIndexEvent) -> Result<Applied> {
+    match event {
+        SearchIndexEvent::PostPublished { post, at, .. } => {
+            publish(db, post, *at).await?;
+            Ok(Applied::Done)
+        }
+        SearchIndexEvent::PostEdited {
+            canister_id,
+            post_id,
+            description,
+            hashtags,
+            at,
+            ..
+        } => edit(db, canister_id, post_id, description, hashtags, *at).await,
+        SearchIndexEvent::PostRemoved {
+            canister_id,
+            post_id,
+            at,
+            ..
+        } => {
+            remove(db, canister_id, post_id, *at).await?;
+            Ok(Applied::Done)
+        }
+    }
+}
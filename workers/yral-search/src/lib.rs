@@ -0,0 +1,110 @@
+mod index;
+mod query;
+
+use index::{apply, Applied, SEARCH_DB};
+use query::{search, SearchQuery};
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    search::SearchIndexEvent,
+};
+
+/// an edit waits this long for its post's publish to be indexed
+const UNKNOWN_POST_RETRY_DELAY_SECS: u32 = 60;
+
+static HEALTH: HealthCheck = HealthCheck::new("yral-search", &[Dependency::D1(SEARCH_DB)]);
+
+async fn search_posts(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let Ok(query) = req.query::<SearchQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+    if let Some(msg) = query.invalid() {
+        return error_resp(msg, 400);
+    }
+
+    let db = ctx.env.d1(SEARCH_DB)?;
+    match search(&db, &query).await? {
+        Some((posts, next)) => Response::from_json(&json!({ "posts": posts, "next": next })),
+        None => error_resp("one of q, hashtags or creator is required", 400),
+    }
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-search", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/search", search_posts)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), res)
+}
+
+/// applies index events one by one, events of a post carry `at` so order doesn't matter
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    let db = env.d1(SEARCH_DB)?;
+    let metrics = Metrics::new(&env, "yral-search");
+
+    for message in batch.messages()? {
+        let event = match serde_json::from_value::<SearchIndexEvent>(message.body().clone()) {
+            Ok(event) => event,
+            Err(e) => {
+                console_error!("dropping malformed index event {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+
+        let outcome = match apply(&db, &event).await {
+            Ok(Applied::Done) => {
+                message.ack();
+                "indexed"
+            }
+            Ok(Applied::UnknownPost) => {
+                let (canister_id, post_id) = event.post_key();
+                console_warn!("post {canister_id}/{post_id} isn't indexed yet, retrying its edit");
+                message.retry_with_options(
+                    &QueueRetryOptionsBuilder::new()
+                        .with_delay_seconds(UNKNOWN_POST_RETRY_DELAY_SECS)
+                        .build(),
+                );
+                "unknown_post"
+            }
+            Err(e) => {
+                let (canister_id, post_id) = event.post_key();
+                console_error!("failed to index post {canister_id}/{post_id}: {e}");
+                message.retry();
+                "failed"
+            }
+        };
+        metrics.counter("search_index_events", &[event.kind(), outcome]);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+//! `GET /search`, full text over description, hashtags and creator
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::index::{normalize_hashtag, num};
+
+const MAX_QUERY_LEN: usize = 200;
+const MAX_TERMS: usize = 10;
+const MAX_HASHTAG_FILTERS: usize = 5;
+
+pub const DEFAULT_LIMIT: u32 = 20;
+pub const MAX_LIMIT: u32 = 100;
+/// deeper pages are rarely useful and fts offsets get slow
+pub const MAX_OFFSET: u32 = 1000;
+
+#[derive(Deserialize, Default, Debug)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    /// comma separated, posts must carry all of them
+    pub hashtags: Option<String>,
+    pub creator: Option<String>,
+    #[serde(default)]
+    pub nsfw: bool,
+    /// `next` of the previous page
+    pub offset: Option<u32>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct PostRow {
+    canister_id: String,
+    post_id: String,
+    creator_principal: String,
+    video_uid: String,
+    description: String,
+    hashtags: String,
+    is_nsfw: u32,
+    published_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub canister_id: String,
+    pub post_id: String,
+    pub creator_principal: String,
+    pub video_uid: String,
+    pub description: String,
+    pub hashtags: Vec<String>,
+    pub is_nsfw: bool,
+    /// unix millis
+    pub published_at: u64,
+}
+
+impl From<PostRow> for SearchHit {
+    fn from(row: PostRow) -> Self {
+        Self {
+            canister_id: row.canister_id,
+            post_id: row.post_id,
+            creator_principal: row.creator_principal,
+            video_uid: row.video_uid,
+            description: row.description,
+            hashtags: row.hashtags.split_whitespace().map(String::from).collect(),
+            is_nsfw: row.is_nsfw != 0,
+            published_at: row.published_at,
+        }
+    }
+}
+
+/// an fts5 match expression, every term is a quoted prefix so user input can't use fts syntax
+///
+/// `#tag` terms in `q` filter on hashtags like the `hashtags` param does
+pub fn match_expr(q: &str, hashtags: &[String]) -> Option<String> {
+    let mut clauses = Vec::new();
+    let mut tags: Vec<String> = hashtags.to_vec();
+
+    for term in q.split_whitespace().take(MAX_TERMS) {
+        if term.starts_with('#') {
+            let tag = normalize_hashtag(term);
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+            continue;
+        }
+        let term: String = term.chars().filter(|c| c.is_alphanumeric()).collect();
+        if !term.is_empty() {
+            clauses.push(format!("\"{term}\"*"));
+        }
+    }
+    clauses.extend(
+        tags.iter()
+            .take(MAX_HASHTAG_FILTERS)
+            .map(|t| format!("hashtags : \"{t}\"")),
+    );
+
+    (!clauses.is_empty()).then(|| clauses.join(" AND "))
+}
+
+impl SearchQuery {
+    pub fn invalid(&self) -> Option<&'static str> {
+        if self.q.as_ref().is_some_and(|q| q.len() > MAX_QUERY_LEN) {
+            return Some("q is too long");
+        }
+        if self.offset.is_some_and(|o| o > MAX_OFFSET) {
+            return Some("offset is too deep");
+        }
+        None
+    }
+
+    fn hashtags(&self) -> Vec<String> {
+        self.hashtags
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(normalize_hashtag)
+            .filter(|t| !t.is_empty())
+            .fold(Vec::new(), |mut tags, tag| {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+                tags
+            })
+    }
+
+    fn creator(&self) -> Option<&str> {
+        self.creator
+            .as_deref()
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+    }
+}
+
+/// hits of the page and the offset of the next one, `None` if the query has nothing to match on
+pub async fn search(
+    db: &D1Database,
+    query: &SearchQuery,
+) -> Result<Option<(Vec<SearchHit>, Option<u32>)>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    let expr = match_expr(query.q.as_deref().unwrap_or_default(), &query.hashtags());
+    let creator = query.creator();
+
+    let stmt = match (&expr, creator) {
+        (Some(expr), _) => db
+            .prepare(
+                "SELECT p.* FROM posts_fts JOIN posts p ON p.rowid = posts_fts.rowid \
+                WHERE posts_fts MATCH ?1 AND p.removed = 0 AND (?2 = 1 OR p.is_nsfw = 0) \
+                AND (?3 IS NULL OR p.creator_principal = ?3) \
+                ORDER BY posts_fts.rank, p.published_at DESC LIMIT ?4 OFFSET ?5",
+            )
+            .bind(&[
+                expr.as_str().into(),
+                num(query.nsfw as u64),
+                creator.into(),
+                num(limit as u64 + 1),
+                num(offset as u64),
+            ])?,
+        (None, Some(creator)) => db
+            .prepare(
+                "SELECT * FROM posts WHERE creator_principal = ?1 AND removed = 0 \
+                AND (?2 = 1 OR is_nsfw = 0) ORDER BY published_at DESC LIMIT ?3 OFFSET ?4",
+            )
+            .bind(&[
+                creator.into(),
+                num(query.nsfw as u64),
+                num(limit as u64 + 1),
+                num(offset as u64),
+            ])?,
+        (None, None) => return Ok(None),
+    };
+
+    let mut rows: Vec<PostRow> = stmt.all().await?.results()?;
+    let next = if rows.len() > limit as usize {
+        rows.truncate(limit as usize);
+        Some(offset + limit).filter(|&next| next <= MAX_OFFSET)
+    } else {
+        None
+    };
+
+    Ok(Some((
+        rows.into_iter().map(SearchHit::from).collect(),
+        next,
+    )))
+}
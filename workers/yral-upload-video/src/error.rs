@@ -0,0 +1,114 @@
+use std::{error::Error, fmt::Display};
+
+use axum::http::StatusCode;
+use ic_agent::AgentError;
+use thiserror::Error;
+use worker::kv::KvError;
+
+/// failures of `server_impl`, surfaced by `APIResponse` with their own status and code
+///
+/// errors boxed into `Box<dyn Error>` on the way are recovered by downcasting
+#[derive(Debug, Error)]
+pub enum UploadError {
+    /// Stream rejected the call or couldn't be reached
+    #[error("stream api error: {0}")]
+    StreamApi(String),
+    /// a canister rejected the call or couldn't be reached
+    #[error("canister error: {0}")]
+    Canister(String),
+    /// the delegated identity or webhook signature doesn't verify
+    #[error("invalid identity: {0}")]
+    Identity(String),
+    #[error("{0}")]
+    Validation(String),
+    /// the caller may not act on the video or post
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0} not found")]
+    NotFound(String),
+    /// the video isn't in a state that allows the call yet, e.g. still being moderated
+    #[error("{0}")]
+    NotReady(String),
+    /// KV, queues and other bindings
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl UploadError {
+    pub fn stream(e: impl Display) -> Self {
+        Self::StreamApi(e.to_string())
+    }
+
+    pub fn identity(e: impl Display) -> Self {
+        Self::Identity(e.to_string())
+    }
+
+    pub fn validation(e: impl Display) -> Self {
+        Self::Validation(e.to_string())
+    }
+
+    pub fn internal(e: impl Display) -> Self {
+        Self::Internal(e.to_string())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::StreamApi(_) | Self::Canister(_) => StatusCode::BAD_GATEWAY,
+            Self::Identity(_) => StatusCode::UNAUTHORIZED,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::NotReady(_) => StatusCode::CONFLICT,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// sent to clients as `code`
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::StreamApi(_) => "STREAM_API_ERROR",
+            Self::Canister(_) => "CANISTER_ERROR",
+            Self::Identity(_) => "IDENTITY_ERROR",
+            Self::Validation(_) => "VALIDATION_ERROR",
+            Self::Forbidden(_) => "FORBIDDEN",
+            Self::NotFound(_) => "NOT_FOUND",
+            Self::NotReady(_) => "NOT_READY",
+            Self::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    /// whether the same request can succeed later
+    pub const fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::StreamApi(_) | Self::Canister(_) | Self::NotReady(_) | Self::Internal(_)
+        )
+    }
+}
+
+impl From<Box<dyn Error>> for UploadError {
+    fn from(e: Box<dyn Error>) -> Self {
+        match e.downcast::<UploadError>() {
+            Ok(e) => *e,
+            Err(e) => Self::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<AgentError> for UploadError {
+    fn from(e: AgentError) -> Self {
+        Self::Canister(e.to_string())
+    }
+}
+
+impl From<worker::Error> for UploadError {
+    fn from(e: worker::Error) -> Self {
+        Self::Internal(e.to_string())
+    }
+}
+
+impl From<KvError> for UploadError {
+    fn from(e: KvError) -> Self {
+        Self::Internal(e.to_string())
+    }
+}
@@ -1,4 +1,5 @@
 use axum::body::Body;
+use axum::extract::DefaultBodyLimit;
 use axum::http::{HeaderMap, StatusCode};
 use axum::middleware::{self, Next};
 use axum::response::IntoResponse;
@@ -10,40 +11,76 @@ use axum::{
 use candid::Principal;
 use ic_agent::identity::{DelegatedIdentity, Secp256k1Identity};
 use ic_agent::{Agent, Identity};
-use reqwest::header::AUTHORIZATION;
+use reqwest::header::{AUTHORIZATION, RETRY_AFTER};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::result::Result;
+use std::time::Duration;
 use std::{error::Error, sync::Arc};
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_service::Service;
-use utils::cloudflare_stream::CloudflareStream;
+use utils::cloudflare_stream::{CloudflareStream, StreamAccounts};
 use utils::events::{EventService, Warehouse};
 use utils::storj_interface::StorjInterface;
 use utils::types::{
-    DelegatedIdentityWire, DirectUploadResult, Video, DELEGATED_IDENTITY_KEY, POST_DETAILS_KEY,
+    DelegatedIdentityWire, DirectUploadResult, Video, CF_WATERMARK_UID, DELEGATED_IDENTITY_KEY,
+    POST_DETAILS_KEY,
 };
 use utils::user_ic_agent::create_ic_agent_from_meta;
 use worker::Result as WorkerResult;
 use worker::*;
+use worker_utils::analytics::{AnalyticsEvent, ANALYTICS_EVENTS_QUEUE};
+use worker_utils::body::MAX_JSON_BODY_BYTES;
+use worker_utils::cors::{allowed_origins, AllowedOrigins};
+use worker_utils::do_client::DoClient;
+use worker_utils::environment::{env_kind, RunEnv};
+use worker_utils::health::{Dependency, HealthCheck, HealthReport};
+use worker_utils::maintenance::MaintenanceNotice;
+use worker_utils::metrics::Metrics;
+use worker_utils::notification::{Notification, NotificationJob, NOTIFICATIONS_QUEUE};
+use worker_utils::search::{SearchIndexEvent, SEARCH_INDEX_QUEUE};
+use worker_utils::secrets::SecretSet;
+use worker_utils::time::now_millis;
+use worker_utils::trace::{propagate_trace, TraceId, Traced};
+use worker_utils::webhooks::{WebhookEvent, WebhookEventData, WEBHOOK_EVENTS_QUEUE};
+use worker_utils::{trace_error, trace_log};
 use yral_canisters_client::individual_user_template::PostDetailsFromFrontend;
 
-use axum::extract::State;
+use axum::extract::{Path, State};
 
-use crate::server_impl::notify_video_upload_impl::notify_video_upload_impl;
+use crate::error::UploadError;
+use crate::server_impl::ban_post::{
+    ban_post_impl, post_details_impl, BanPostRequest, ModeratedPostDetails, PostDetailsRequest,
+};
+use crate::server_impl::notify_video_upload_impl::{notify_video_upload_impl, WEBHOOK_NONCES_KV};
+use crate::server_impl::playback_token::{
+    playback_token_impl, PlaybackToken, PlaybackTokenRequest,
+};
+use crate::server_impl::set_thumbnail::{set_thumbnail_impl, SetThumbnailRequest};
+use crate::server_impl::sweep_stuck_videos::StuckVideoSweep;
 use crate::server_impl::sync_post_with_post_service_canister::SyncPostToPostServiceRequest;
 use crate::server_impl::upload_video_to_canister::{
     mark_post_as_published_and_emit_events, upload_video,
 };
 use crate::server_impl::{
+    reprocess_video::{reprocess_video_impl, ReprocessVideoRequest, ReprocessVideoResult},
     sync_post_with_post_service_canister::sync_post_with_post_service_canister_impl,
     upload_video_to_canister::mark_video_as_downloadable,
 };
-use crate::utils::notification_client::NotificationClient;
+use crate::utils::event_outbox::EVENT_OUTBOX;
+use crate::utils::failed_uploads::{
+    list_failed_uploads, redrive_failed_upload_impl, DeadLetters, FailedUploadsPage,
+    DEFAULT_FAILED_UPLOADS_LIMIT, FAILED_UPLOADS_KV, UPLOAD_VIDEO_DLQ,
+};
+use crate::utils::moderation::{store_verdict, verdict_of, ModerationService, ModerationVerdict};
 use crate::utils::service_canister_post_mapping_redis_rest_client::RedisRestClient;
-use crate::utils::types::{MarkPostAsPublishedRequest, RequestPostDetails};
+use crate::utils::types::{MarkPostAsPublishedRequest, RequestPostDetails, TusUploadResult};
+use crate::utils::upload_latency::{record_queue_upload, METADATA_SET_AT_KEY};
+use crate::utils::upload_url_limiter::{AcquireUploadUrls, UPLOAD_URL_LIMITER};
+use crate::utils::video_posts::{post_for_video, VideoPost, VIDEO_POSTS_KV};
 
+pub mod error;
 pub mod server_impl;
 pub mod utils;
 
@@ -55,6 +92,15 @@ where
     pub message: Option<String>,
     pub success: bool,
     pub data: Option<T>,
+    /// set for `UploadError`s, see `UploadError::code`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// set for `UploadError`s, whether the same request can succeed later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retryable: Option<bool>,
+    /// of failures, 400 unless set
+    #[serde(skip)]
+    pub status: Option<StatusCode>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -76,6 +122,34 @@ pub enum UploadVideoQueueMessage {
     MarkVideoAsDownloadable(String),
     PushPostToPostServiceCanister(SyncPostToPostServiceRequest),
     UploadToStorj(UploadToStorjRequest),
+    /// scores a ready video for the publish gate, see `utils::moderation`
+    ModerateVideo(String),
+}
+
+impl UploadVideoQueueMessage {
+    /// metric label for the message type
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UploadVideo(_) => "upload_video",
+            Self::UploadVideoStorj { .. } => "upload_video_storj",
+            Self::MarkVideoAsDownloadable(_) => "mark_video_as_downloadable",
+            Self::PushPostToPostServiceCanister(_) => "push_post_to_post_service_canister",
+            Self::UploadToStorj(_) => "upload_to_storj",
+            Self::ModerateVideo(_) => "moderate_video",
+        }
+    }
+
+    /// the video the message is about, if any
+    pub fn video_uid(&self) -> Option<&str> {
+        match self {
+            Self::UploadVideo(video_uid)
+            | Self::MarkVideoAsDownloadable(video_uid)
+            | Self::ModerateVideo(video_uid) => Some(video_uid),
+            Self::UploadVideoStorj { video_uid, .. } => Some(video_uid),
+            Self::UploadToStorj(request) => Some(&request.video_id),
+            Self::PushPostToPostServiceCanister(_) => None,
+        }
+    }
 }
 
 impl<T> IntoResponse for APIResponse<T>
@@ -87,11 +161,14 @@ where
             message: self.message.clone(),
             success: self.success,
             data: self.data,
+            code: self.code,
+            retryable: self.retryable,
+            status: None,
         })
         .into_response();
 
         if !self.success {
-            *response_body.status_mut() = StatusCode::BAD_REQUEST;
+            *response_body.status_mut() = self.status.unwrap_or(StatusCode::BAD_REQUEST);
         }
 
         response_body
@@ -107,7 +184,7 @@ pub struct VideoKvStoreValue {
 
 impl<T, E> From<Result<T, E>> for APIResponse<T>
 where
-    E: Display,
+    E: Into<Box<dyn Error>>,
     T: Clone + Serialize,
 {
     fn from(value: Result<T, E>) -> Self {
@@ -116,29 +193,51 @@ where
                 message: None,
                 success: true,
                 data: Some(data),
+                code: None,
+                retryable: None,
+                status: None,
             },
-            Err(err) => Self {
-                message: Some(format!("{err}")),
-                success: false,
-                data: None,
-            },
+            Err(err) => {
+                let err: Box<dyn Error> = err.into();
+                match err.downcast_ref::<UploadError>() {
+                    Some(e) => Self {
+                        message: Some(e.to_string()),
+                        success: false,
+                        data: None,
+                        code: Some(e.code().to_string()),
+                        retryable: Some(e.is_retryable()),
+                        status: Some(e.status()),
+                    },
+                    None => Self {
+                        message: Some(format!("{err}")),
+                        success: false,
+                        data: None,
+                        code: None,
+                        retryable: None,
+                        status: None,
+                    },
+                }
+            }
         }
     }
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub cloudflare_stream: CloudflareStream,
+    pub streams: StreamAccounts,
     pub events: Warehouse,
     pub webhook_secret_key: String,
     pub event_rest_service: EventService,
     pub upload_video_queue: Queue,
     pub admin_ic_agent: Agent,
-    pub notification_client: NotificationClient,
     pub storj_interface: StorjInterface,
+    /// Stream watermark profile for watermarked renditions
+    pub watermark_uid: String,
+    pub env: Env,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         clouflare_account_id: String,
         cloudflare_api_token: String,
@@ -146,24 +245,70 @@ impl AppState {
         off_chain_auth_token: String,
         upload_video_queue: Queue,
         canisters_admin_key: String,
-        notification_api_key: String,
+        env: Env,
     ) -> Result<Self, Box<dyn Error>> {
-        let cloudflare_stream = CloudflareStream::new(clouflare_account_id, cloudflare_api_token)?;
-        let notification_client = NotificationClient::new(notification_api_key);
+        let streams = stream_accounts(&env, clouflare_account_id, cloudflare_api_token)?;
         let storj_interface = StorjInterface::new("https://storj-interface.yral.com".to_string())?;
         Ok(Self {
-            cloudflare_stream,
+            streams,
             events: Warehouse::with_auth_token(off_chain_auth_token.clone()),
             webhook_secret_key,
-            event_rest_service: EventService::with_auth_token(off_chain_auth_token),
+            event_rest_service: EventService::new(env.clone()),
             upload_video_queue,
             admin_ic_agent: init_canisters_admin_ic_agent(canisters_admin_key)?,
-            notification_client,
             storj_interface,
+            watermark_uid: watermark_uid(&env),
+            env,
         })
     }
 }
 
+/// mock builds use the Stream API served at `MOCK_BACKEND_URL`, see tests/
+fn cloudflare_stream(
+    env: &Env,
+    account_id: String,
+    api_token: String,
+) -> Result<CloudflareStream, Box<dyn Error>> {
+    if env_kind() != RunEnv::Mock {
+        return CloudflareStream::new(account_id, api_token);
+    }
+
+    let base_url = Url::parse(&env.var("MOCK_BACKEND_URL")?.to_string())?.join("stream/")?;
+    CloudflareStream::with_base_url(base_url, api_token)
+}
+
+/// the yral logo profile unless `CF_WATERMARK_UID` points to another one
+fn watermark_uid(env: &Env) -> String {
+    env.var("CF_WATERMARK_UID")
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| CF_WATERMARK_UID.to_string())
+}
+
+/// adds the account set in `CLOUDFLARE_STREAM_SECONDARY_*` while videos are migrated off it
+fn stream_accounts(
+    env: &Env,
+    account_id: String,
+    api_token: String,
+) -> Result<StreamAccounts, Box<dyn Error>> {
+    let primary = cloudflare_stream(env, account_id, api_token)?;
+    if env_kind() == RunEnv::Mock {
+        return Ok(StreamAccounts::new(primary, None));
+    }
+
+    let secondary = match (
+        env.secret("CLOUDFLARE_STREAM_SECONDARY_ACCOUNT_ID"),
+        env.secret("CLOUDFLARE_STREAM_SECONDARY_API_TOKEN"),
+    ) {
+        (Ok(account_id), Ok(api_token)) => Some(CloudflareStream::new(
+            account_id.to_string(),
+            api_token.to_string(),
+        )?),
+        _ => None,
+    };
+
+    Ok(StreamAccounts::new(primary, secondary))
+}
+
 fn init_canisters_admin_ic_agent(identity_str: String) -> Result<Agent, Box<dyn Error>> {
     let identity = Secp256k1Identity::from_pem(identity_str.as_bytes())?;
 
@@ -175,13 +320,22 @@ fn init_canisters_admin_ic_agent(identity_str: String) -> Result<Agent, Box<dyn
     Ok(agent)
 }
 
+fn cors_layer(env: &Env) -> CorsLayer {
+    match allowed_origins(env) {
+        AllowedOrigins::Any => CorsLayer::permissive(),
+        origins => CorsLayer::new()
+            .allow_origin(AllowOrigin::predicate(move |origin, _| {
+                origin.to_str().is_ok_and(|o| origins.allows(o))
+            }))
+            .allow_methods(Any)
+            .allow_headers(Any)
+            .max_age(Duration::from_secs(86400)),
+    }
+}
+
 fn router(env: Env, _ctx: Context) -> Router {
     let upload_queue: Queue = env.queue("UPLOAD_VIDEO").expect("Queue binding invalid");
     let off_chain_auth_token = env.secret("OFF_CHAIN_GRPC_AUTH_TOKEN").unwrap().to_string();
-    let notification_api_key = env
-        .secret("YRAL_METADATA_USER_NOTIFICATION_API_KEY")
-        .unwrap()
-        .to_string();
 
     let off_chain_auth_token_clone = off_chain_auth_token.clone();
 
@@ -198,9 +352,10 @@ fn router(env: Env, _ctx: Context) -> Router {
         off_chain_auth_token.clone(),
         upload_queue,
         env.secret("CANISTERS_ADMIN_KEY").unwrap().to_string(),
-        notification_api_key,
+        env.clone(),
     )
     .unwrap();
+    let app_state = Arc::new(app_state);
 
     Router::new()
         .route(
@@ -211,6 +366,15 @@ fn router(env: Env, _ctx: Context) -> Router {
             "/create_video_url_for_ai_draft",
             post(get_upload_url_for_ai_draft_video),
         )
+        .route("/ban_post", post(ban_post))
+        .route("/post_details", post(post_details))
+        .route("/failed_uploads", post(failed_uploads))
+        .route("/failed_uploads/:id/redrive", post(redrive_failed_upload))
+        .route(
+            "/moderation/:video_uid/override",
+            post(override_moderation_verdict),
+        )
+        .route("/admin/reprocess_video", post(reprocess_video))
         .route_layer(middleware::from_fn(
             move |req: axum::http::Request<Body>, next: Next| {
                 let auth_token = off_chain_auth_token_clone.clone();
@@ -231,16 +395,88 @@ fn router(env: Env, _ctx: Context) -> Router {
         ))
         .route("/mark_post_as_published", post(mark_post_as_published))
         .route("/", get(root))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
         .route("/get_upload_url", get(get_upload_url))
-        .route("/get_upload_url_v2", get(get_upload_url_v2))
+        .route(
+            "/get_upload_url_v2",
+            get(get_upload_url_v2).post(get_upload_url_v2),
+        )
         .route("/get_upload_url_v3", post(get_upload_url_v3))
+        .route("/get_tus_upload_url", post(get_tus_upload_url))
+        .route("/get_upload_urls_batch", post(get_upload_urls_batch))
         .route("/update_metadata", post(update_metadata))
         .route("/update_metadata_v2", post(update_metadata_v2))
+        .route("/set_thumbnail", post(set_thumbnail))
+        .route("/playback_token", post(playback_token))
+        .route("/post_for_video/:video_uid", get(get_post_for_video))
         .route("/notify", post(notify_video_upload))
-        .layer(CorsLayer::permissive())
-        .with_state(Arc::new(app_state))
+        .layer(DefaultBodyLimit::max(MAX_JSON_BODY_BYTES))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            maintenance_guard,
+        ))
+        .layer(middleware::from_fn(propagate_trace))
+        .layer(cors_layer(&env))
+        .with_state(app_state)
 }
 
+/// stream's webhook, the off-chain agent's callbacks and the routes behind its token keep working
+/// through maintenance, on top of worker_utils::maintenance's own exemptions
+const MAINTENANCE_EXEMPT: &[&str] = &[
+    "/notify",
+    "/mark_post_as_published",
+    "/sync_post_to_post_canister",
+    "/ban_post",
+    "/failed_uploads",
+    "/moderation/",
+];
+
+/// rejects mutating requests while the worker is under maintenance, see worker_utils::maintenance
+#[worker::send]
+async fn maintenance_guard(
+    State(app_state): State<Arc<AppState>>,
+    req: axum::http::Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let method = req.method().as_str().to_string();
+    let path = req.uri().path().to_string();
+    match MaintenanceNotice::check(
+        &app_state.env,
+        "yral-upload-video",
+        &method,
+        &path,
+        MAINTENANCE_EXEMPT,
+    )
+    .await
+    {
+        Some(notice) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, notice.retry_after_secs.to_string())],
+            Json(notice.error),
+        )
+            .into_response(),
+        None => next.run(req).await,
+    }
+}
+
+/// unwrapped by `router`
+const FETCH_SECRETS: &[&str] = &[
+    "CLOUDFLARE_STREAM_ACCOUNT_ID",
+    "CLOUDFLARE_STREAM_API_TOKEN",
+    "CLOUDFLARE_STREAM_WEBHOOK_SECRET",
+    "OFF_CHAIN_GRPC_AUTH_TOKEN",
+    "CANISTERS_ADMIN_KEY",
+];
+
+const QUEUE_SECRETS: &[&str] = &[
+    "CLOUDFLARE_STREAM_ACCOUNT_ID",
+    "CLOUDFLARE_STREAM_API_TOKEN",
+    "CANISTERS_ADMIN_KEY",
+    "SERVICE_CANISTER_POST_MAPPING_REDIS_REST_ENDPOINT",
+    "SERVICE_CANISTER_POST_MAPPING_REDIS_REST_TOKEN",
+];
+
 #[event(fetch)]
 async fn fetch(
     req: HttpRequest,
@@ -248,16 +484,28 @@ async fn fetch(
     ctx: Context,
 ) -> WorkerResult<axum::http::Response<axum::body::Body>> {
     console_error_panic_hook::set_once();
+
+    if let Err(e) = SecretSet::validate(&env, FETCH_SECRETS) {
+        console_error!("{e}");
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, Json(e.to_api_error())).into_response());
+    }
     Ok(router(env, ctx).call(req).await?)
 }
 
 #[event(queue)]
 async fn queue(
-    message_batch: MessageBatch<UploadVideoQueueMessage>,
+    message_batch: MessageBatch<Traced<UploadVideoQueueMessage>>,
     env: Env,
     _: Context,
 ) -> Result<(), Box<dyn Error>> {
-    let cloudflare_stream_client = CloudflareStream::new(
+    if let Err(e) = SecretSet::validate(&env, QUEUE_SECRETS) {
+        console_error!("{e}");
+        message_batch.retry_all();
+        return Ok(());
+    }
+
+    let cloudflare_stream_client = stream_accounts(
+        &env,
         env.secret("CLOUDFLARE_STREAM_ACCOUNT_ID")?.to_string(),
         env.secret("CLOUDFLARE_STREAM_API_TOKEN")?.to_string(),
     )?;
@@ -267,8 +515,7 @@ async fn queue(
     let admin_ic_agent =
         init_canisters_admin_ic_agent(env.secret("CANISTERS_ADMIN_KEY")?.to_string())?;
 
-    let events_rest_service =
-        EventService::with_auth_token(env.secret("OFF_CHAIN_GRPC_AUTH_TOKEN")?.to_string());
+    let events_rest_service = EventService::new(env.clone());
 
     let service_canister_post_mapping_redis_rest_endpoint = env
         .secret("SERVICE_CANISTER_POST_MAPPING_REDIS_REST_ENDPOINT")?
@@ -284,9 +531,23 @@ async fn queue(
 
     let storj_interface = StorjInterface::new("https://storj-interface.yral.com".to_string())?;
 
+    let metrics = Metrics::new(&env, "yral-upload-video");
+    let dead_letters = DeadLetters::new(&env)?;
+
     for message in message_batch.messages()? {
+        let kind = message.body().body.kind();
+        metrics.counter("upload_queue_messages", &[kind]);
+        metrics.histogram(
+            "upload_queue_message_attempts",
+            &[kind],
+            message.attempts() as f64,
+        );
+
         process_message(
             message,
+            &env,
+            &metrics,
+            &dead_letters,
             &upload_queue,
             &cloudflare_stream_client,
             &events_rest_service,
@@ -300,6 +561,36 @@ async fn queue(
     Ok(())
 }
 
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    if let Err(e) = SecretSet::validate(&env, QUEUE_SECRETS) {
+        console_error!("{e}");
+        return;
+    }
+
+    if let Err(e) = sweep_stuck_videos(&env).await {
+        console_error!("stuck video sweep failed: {e}");
+    }
+}
+
+async fn sweep_stuck_videos(env: &Env) -> Result<(), Box<dyn Error>> {
+    let streams = stream_accounts(
+        env,
+        env.secret("CLOUDFLARE_STREAM_ACCOUNT_ID")?.to_string(),
+        env.secret("CLOUDFLARE_STREAM_API_TOKEN")?.to_string(),
+    )?;
+    let upload_queue: Queue = env.queue("UPLOAD_VIDEO")?;
+
+    // uploads started before the cut over still settle on the secondary account
+    for cloudflare_stream_client in streams.all() {
+        StuckVideoSweep::new(env, cloudflare_stream_client, &upload_queue)
+            .run()
+            .await?;
+    }
+
+    Ok(())
+}
+
 fn is_video_ready(video_details: &Video) -> Result<(bool, String), Box<dyn Error>> {
     let video_status = video_details
         .status
@@ -324,21 +615,29 @@ fn is_video_ready(video_details: &Video) -> Result<(bool, String), Box<dyn Error
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_message(
-    message: Message<UploadVideoQueueMessage>,
+    message: Message<Traced<UploadVideoQueueMessage>>,
+    env: &Env,
+    metrics: &Metrics,
+    dead_letters: &DeadLetters,
     upload_queue: &Queue,
-    cloudflare_stream_client: &CloudflareStream,
+    cloudflare_stream_client: &StreamAccounts,
     events_rest_service: &EventService,
     admin_ic_agent: &Agent,
     service_canister_post_mapping_client: &RedisRestClient,
     storj_interface: &StorjInterface,
 ) {
-    let message_body = message.body();
+    let trace = message.body().trace();
 
-    match message_body {
+    match &message.body().body {
         UploadVideoQueueMessage::UploadVideo(video_uid) => {
             process_message_for_video_upload(
                 &message,
+                &trace,
+                env,
+                metrics,
+                dead_letters,
                 upload_queue,
                 cloudflare_stream_client,
                 events_rest_service,
@@ -354,6 +653,9 @@ pub async fn process_message(
         } => {
             process_message_for_storj_video_upload(
                 &message,
+                &trace,
+                env,
+                dead_letters,
                 events_rest_service,
                 admin_ic_agent,
                 video_uid.clone(),
@@ -365,6 +667,8 @@ pub async fn process_message(
         UploadVideoQueueMessage::MarkVideoAsDownloadable(video_uid) => {
             process_message_for_marking_video_downloadable(
                 &message,
+                &trace,
+                dead_letters,
                 cloudflare_stream_client,
                 upload_queue,
                 video_uid.clone(),
@@ -374,6 +678,9 @@ pub async fn process_message(
         UploadVideoQueueMessage::PushPostToPostServiceCanister(request_payload) => {
             process_message_for_sync_video_to_post_service_canister(
                 &message,
+                &trace,
+                env,
+                dead_letters,
                 admin_ic_agent,
                 request_payload.clone(),
                 service_canister_post_mapping_client,
@@ -381,17 +688,90 @@ pub async fn process_message(
             .await;
         }
         UploadVideoQueueMessage::UploadToStorj(request) => {
-            process_message_for_storj_upload(&message, storj_interface, request.clone()).await;
+            process_message_for_storj_upload(
+                &message,
+                &trace,
+                dead_letters,
+                storj_interface,
+                request.clone(),
+            )
+            .await;
+        }
+        UploadVideoQueueMessage::ModerateVideo(video_uid) => {
+            process_message_for_moderation(
+                &message,
+                &trace,
+                env,
+                dead_letters,
+                cloudflare_stream_client,
+                video_uid,
+            )
+            .await;
+        }
+    }
+}
+
+async fn process_message_for_moderation(
+    message: &Message<Traced<UploadVideoQueueMessage>>,
+    trace: &TraceId,
+    env: &Env,
+    dead_letters: &DeadLetters,
+    cloudflare_stream_client: &StreamAccounts,
+    video_uid: &str,
+) {
+    let Some(moderation) = ModerationService::from_env(env) else {
+        trace_log!(trace, "moderation is off, not scoring video {}", video_uid);
+        message.ack();
+        return;
+    };
+
+    let result = async {
+        let cloudflare_stream_client = cloudflare_stream_client.for_video(video_uid).await?;
+        let video = cloudflare_stream_client
+            .get_video_details(video_uid)
+            .await?;
+        // an admin's override is kept
+        if verdict_of(&video).is_some_and(|v| v.overridden) {
+            return Ok(());
+        }
+        let verdict = moderation.classify(video_uid).await?;
+        store_verdict(cloudflare_stream_client, &video, video_uid, &verdict).await?;
+        if verdict.is_flagged() {
+            trace_log!(
+                trace,
+                "video {} flagged with nsfw score {}",
+                video_uid,
+                verdict.nsfw_score
+            );
+        }
+
+        Ok::<_, Box<dyn Error>>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => message.ack(),
+        Err(e) => {
+            trace_error!(trace, "Error moderating video {}: {}", video_uid, e);
+            dead_letters
+                .retry(message, &format!("moderation failed: {e}"))
+                .await;
         }
     }
 }
 
 async fn process_message_for_storj_upload(
-    message: &Message<UploadVideoQueueMessage>,
+    message: &Message<Traced<UploadVideoQueueMessage>>,
+    trace: &TraceId,
+    dead_letters: &DeadLetters,
     storj_interface: &StorjInterface,
     request: UploadToStorjRequest,
 ) {
-    console_log!("Processing Storj upload for video {}", request.video_id);
+    trace_log!(
+        trace,
+        "Processing Storj upload for video {}",
+        request.video_id
+    );
 
     let result = storj_interface
         .duplicate_video_from_cf_to_storj(
@@ -404,27 +784,38 @@ async fn process_message_for_storj_upload(
 
     match result {
         Ok(_) => {
-            console_log!("Successfully uploaded video {} to Storj", request.video_id);
+            trace_log!(
+                trace,
+                "Successfully uploaded video {} to Storj",
+                request.video_id
+            );
             message.ack();
         }
         Err(e) => {
-            console_error!(
+            trace_error!(
+                trace,
                 "Error uploading video {} to Storj: {}",
                 request.video_id,
                 e.to_string()
             );
-            message.retry();
+            dead_letters
+                .retry(message, &format!("storj upload failed: {e}"))
+                .await;
         }
     }
 }
 
 async fn process_message_for_sync_video_to_post_service_canister(
-    message: &Message<UploadVideoQueueMessage>,
+    message: &Message<Traced<UploadVideoQueueMessage>>,
+    trace: &TraceId,
+    env: &Env,
+    dead_letters: &DeadLetters,
     admin_ic_agent: &Agent,
     sync_video_request: SyncPostToPostServiceRequest,
     service_canister_post_mapping_client: &RedisRestClient,
 ) {
     match sync_post_with_post_service_canister_impl(
+        env,
         admin_ic_agent,
         sync_video_request,
         service_canister_post_mapping_client,
@@ -433,16 +824,23 @@ async fn process_message_for_sync_video_to_post_service_canister(
     {
         Ok(_) => message.ack(),
         Err(e) => {
-            console_error!("Error syncing post to post service canister: {}", e);
-            message.retry();
+            trace_error!(trace, "Error syncing post to post service canister: {}", e);
+            dead_letters
+                .retry(message, &format!("post service sync failed: {e}"))
+                .await;
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_message_for_video_upload(
-    message: &Message<UploadVideoQueueMessage>,
+    message: &Message<Traced<UploadVideoQueueMessage>>,
+    trace: &TraceId,
+    env: &Env,
+    metrics: &Metrics,
+    dead_letters: &DeadLetters,
     upload_queue: &Queue,
-    cloudflare_stream_client: &CloudflareStream,
+    cloudflare_stream_client: &StreamAccounts,
     events_rest_service: &EventService,
     admin_ic_agent: &Agent,
     video_uid: String,
@@ -450,8 +848,11 @@ pub async fn process_message_for_video_upload(
     let video_details_result = cloudflare_stream_client.get_video_details(&video_uid).await;
 
     if let Err(e) = video_details_result.as_ref() {
-        console_error!("Error {}", e.to_string());
-        message.retry();
+        trace_error!(trace, "Error {}", e.to_string());
+        metrics.counter("video_uploads", &["stream_error"]);
+        dead_letters
+            .retry(message, &format!("stream video details failed: {e}"))
+            .await;
         return;
     }
 
@@ -460,67 +861,127 @@ pub async fn process_message_for_video_upload(
     let is_video_ready = is_video_ready(&video_details);
 
     let Ok(meta) = video_details.meta.as_ref().ok_or("meta not found") else {
-        console_error!("meta not found");
-        message.retry();
+        trace_error!(trace, "meta not found");
+        dead_letters.retry(message, "meta not found").await;
         return;
     };
 
     match is_video_ready {
         Ok((true, _)) => {
             let result = extract_fields_from_video_meta_and_upload_video(
+                env,
                 video_uid.to_string(),
                 meta,
                 events_rest_service,
                 admin_ic_agent,
+                trace,
             )
             .await;
 
             match result {
                 Ok(_post_meta) => {
+                    record_queue_upload(metrics, &video_details, message.attempts());
                     let mark_video_download_message =
                         UploadVideoQueueMessage::MarkVideoAsDownloadable(video_uid.to_string());
-                    if let Err(e) = upload_queue.send(mark_video_download_message).await {
-                        console_log!("Error sending mark video download message: {}", e);
+                    if let Err(e) = upload_queue
+                        .send(Traced::new(mark_video_download_message, trace))
+                        .await
+                    {
+                        trace_log!(trace, "Error sending mark video download message: {}", e);
                     }
+                    metrics.counter("video_uploads", &["uploaded"]);
                     message.ack();
                 }
                 Err(e) => {
-                    console_error!(
+                    trace_error!(
+                        trace,
                         "Error uploading video {} to canister {}",
                         video_uid,
                         e.to_string()
                     );
 
-                    message.retry()
+                    metrics.counter("video_uploads", &["canister_error"]);
+                    dead_letters
+                        .retry(message, &format!("canister upload failed: {e}"))
+                        .await;
                 }
             }
         }
         Ok((false, err)) => {
-            console_error!(
+            trace_error!(
+                trace,
                 "Error processing video {} on cloudflare. Error {}",
                 video_uid,
                 err
             );
 
+            metrics.counter("video_uploads", &["processing_failed"]);
+            notify_upload_error(env, trace, &video_details, &video_uid).await;
             message.ack();
         }
         Err(e) => {
-            console_error!("Error extracting video status. Error {}", e.to_string());
-            message.retry();
+            trace_error!(
+                trace,
+                "Error extracting video status. Error {}",
+                e.to_string()
+            );
+            dead_letters
+                .retry(message, &format!("video status: {e}"))
+                .await;
         }
     };
 }
 
+/// tells the uploader Stream couldn't process their video
+async fn notify_upload_error(env: &Env, trace: &TraceId, video_details: &Video, video_uid: &str) {
+    let Some(user_principal) = video_details.uploader() else {
+        trace_error!(
+            trace,
+            "video {} failed but its uploader is unknown",
+            video_uid
+        );
+        return;
+    };
+    let Some(status) = video_details.status.as_ref() else {
+        return;
+    };
+
+    let job = NotificationJob::new(
+        user_principal,
+        Notification::VideoUploadError {
+            video_uid: video_uid.to_string(),
+            reason: status.user_facing_error().to_string(),
+        },
+    )
+    .with_trace(trace);
+    if let Err(e) = job.enqueue(env).await {
+        trace_error!(
+            trace,
+            "Error queueing video upload error notification: {}",
+            e
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_message_for_storj_video_upload(
-    message: &Message<UploadVideoQueueMessage>,
+    message: &Message<Traced<UploadVideoQueueMessage>>,
+    trace: &TraceId,
+    env: &Env,
+    dead_letters: &DeadLetters,
     events_rest_service: &EventService,
     admin_ic_agent: &Agent,
     video_uid: String,
     delegated_identity_json: String,
     post_details_json: String,
 ) {
-    console_log!("Processing Storj video upload for video {}", video_uid);
-    console_log!(
+    trace_log!(
+        trace,
+        "Processing Storj video upload for video {}",
+        video_uid
+    );
+    trace_log!(
+        trace,
         "Received delegated_identity size: {} bytes, post_details size: {} bytes",
         delegated_identity_json.len(),
         post_details_json.len()
@@ -531,48 +992,64 @@ pub async fn process_message_for_storj_video_upload(
     metadata.insert(DELEGATED_IDENTITY_KEY.to_string(), delegated_identity_json);
     metadata.insert(POST_DETAILS_KEY.to_string(), post_details_json);
 
-    console_log!(
+    trace_log!(
+        trace,
         "Reconstructed metadata keys: {:?}",
         metadata.keys().collect::<Vec<_>>()
     );
 
     // For Storj uploads, metadata is already finalized, just upload to canister
     let result = extract_fields_from_video_meta_and_upload_video(
+        env,
         video_uid.clone(),
         &metadata,
         events_rest_service,
         admin_ic_agent,
+        trace,
     )
     .await;
 
     match result {
         Ok(_) => {
-            console_log!(
+            trace_log!(
+                trace,
                 "Successfully uploaded Storj video {} to canister",
                 video_uid
             );
             message.ack();
         }
         Err(e) => {
-            console_error!(
+            trace_error!(
+                trace,
                 "Error uploading Storj video {} to canister: {}",
                 video_uid,
                 e.to_string()
             );
-            message.retry();
+            dead_letters
+                .retry(message, &format!("canister upload failed: {e}"))
+                .await;
         }
     }
 }
 
 pub async fn process_message_for_marking_video_downloadable(
-    message: &Message<UploadVideoQueueMessage>,
-    cloudflare_stream_client: &CloudflareStream,
+    message: &Message<Traced<UploadVideoQueueMessage>>,
+    trace: &TraceId,
+    dead_letters: &DeadLetters,
+    cloudflare_stream_client: &StreamAccounts,
     upload_queue: &Queue,
     video_uid: String,
 ) {
     if let Err(e) = mark_video_as_downloadable(cloudflare_stream_client, &video_uid).await {
-        console_error!("Error marking video {} as downloadable: {}", video_uid, e);
-        message.retry();
+        trace_error!(
+            trace,
+            "Error marking video {} as downloadable: {}",
+            video_uid,
+            e
+        );
+        dead_letters
+            .retry(message, &format!("marking downloadable failed: {e}"))
+            .await;
         return;
     }
 
@@ -603,38 +1080,49 @@ pub async fn process_message_for_marking_video_downloadable(
                                             storj_upload_request,
                                         );
 
-                                        if let Err(e) = upload_queue.send(storj_message).await {
-                                            console_error!(
+                                        if let Err(e) = upload_queue
+                                            .send(Traced::new(storj_message, trace))
+                                            .await
+                                        {
+                                            trace_error!(
+                                                trace,
                                                 "Error queueing Storj upload for video {}: {}",
                                                 video_uid,
                                                 e.to_string()
                                             );
                                         } else {
-                                            console_log!(
+                                            trace_log!(
+                                                trace,
                                                 "Queued Storj upload for video {}",
                                                 video_uid
                                             );
                                         }
                                     }
-                                    Err(e) => console_error!(
+                                    Err(e) => trace_error!(
+                                        trace,
                                         "Error getting publisher principal: {}",
                                         e.to_string()
                                     ),
                                 },
-                                Err(e) => console_error!(
+                                Err(e) => trace_error!(
+                                    trace,
                                     "Error creating delegated identity: {}",
                                     e.to_string()
                                 ),
                             }
                         }
-                        _ => console_error!("Error parsing metadata for Storj upload"),
+                        _ => trace_error!(trace, "Error parsing metadata for Storj upload"),
                     }
                 } else {
-                    console_error!("Missing delegated identity or post details in metadata");
+                    trace_error!(
+                        trace,
+                        "Missing delegated identity or post details in metadata"
+                    );
                 }
             }
         }
-        Err(e) => console_error!(
+        Err(e) => trace_error!(
+            trace,
             "Error getting video details for Storj upload: {}",
             e.to_string()
         ),
@@ -644,10 +1132,12 @@ pub async fn process_message_for_marking_video_downloadable(
 }
 
 pub async fn extract_fields_from_video_meta_and_upload_video(
+    env: &Env,
     video_uid: String,
     meta: &HashMap<String, String>,
     events: &EventService,
     admin_ic_agent: &Agent,
+    trace: &TraceId,
 ) -> Result<(), Box<dyn Error>> {
     let post_details_from_frontend_string = meta
         .get(POST_DETAILS_KEY)
@@ -660,21 +1150,73 @@ pub async fn extract_fields_from_video_meta_and_upload_video(
 
     let user_agent = create_ic_agent_from_meta(meta)?;
 
-    upload_video(
+    let (canister_id, post_id) = upload_video(
         events,
-        video_uid,
+        video_uid.clone(),
         &user_agent,
         admin_ic_agent,
         post_details_from_frontend.into(),
         country,
+        trace,
     )
-    .await
+    .await?;
+
+    let video_post = VideoPost::new(video_uid, canister_id, post_id, user_agent.get_principal()?);
+    if let Err(e) = video_post.record(env).await {
+        trace_error!(
+            trace,
+            "Error recording post of video {}: {}",
+            video_post.video_uid,
+            e
+        );
+    }
+
+    Ok(())
 }
 
 pub async fn root() -> &'static str {
     "Hello Axum!"
 }
 
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-upload-video",
+    &[
+        Dependency::DurableObject(EVENT_OUTBOX),
+        Dependency::DurableObject(UPLOAD_URL_LIMITER),
+        Dependency::Queue("UPLOAD_VIDEO"),
+        Dependency::Queue(NOTIFICATIONS_QUEUE),
+        Dependency::Queue(ANALYTICS_EVENTS_QUEUE),
+        Dependency::Queue(WEBHOOK_EVENTS_QUEUE),
+        Dependency::Queue(SEARCH_INDEX_QUEUE),
+        Dependency::Queue(UPLOAD_VIDEO_DLQ),
+        Dependency::Kv(FAILED_UPLOADS_KV),
+        Dependency::Kv(WEBHOOK_NONCES_KV),
+        Dependency::Kv(VIDEO_POSTS_KV),
+        Dependency::Secret("CLOUDFLARE_STREAM_ACCOUNT_ID"),
+        Dependency::Secret("CLOUDFLARE_STREAM_API_TOKEN"),
+        Dependency::Secret("CLOUDFLARE_STREAM_WEBHOOK_SECRET"),
+        Dependency::Secret("OFF_CHAIN_GRPC_AUTH_TOKEN"),
+        Dependency::Secret("CANISTERS_ADMIN_KEY"),
+    ],
+);
+
+pub async fn healthz() -> Json<HealthReport> {
+    Json(HEALTH.live())
+}
+
+#[debug_handler]
+#[worker::send]
+pub async fn readyz(State(app_state): State<Arc<AppState>>) -> impl IntoResponse {
+    let report = HEALTH.check(&app_state.env).await;
+    let status = if report.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(report))
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct UpdateMetadataRequest {
     video_uid: String,
@@ -688,28 +1230,215 @@ struct AIVideoUploadUrlRequest {
     pub user_id: Principal,
 }
 
+/// optional body of `get_upload_url_v2`, plain GETs are never watermarked
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+struct GetUploadUrlV2Request {
+    #[serde(default)]
+    pub watermark: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct GetUploadUrlV3Request {
     pub publisher_user_id: String,
 }
 
+/// upload URLs a single batch may ask for
+const MAX_UPLOAD_URLS_PER_BATCH: u32 = 10;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct GetUploadUrlsBatchRequest {
+    pub delegated_identity_wire: DelegatedIdentityWire,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct GetTusUploadUrlRequest {
+    /// size of the file in bytes
+    pub upload_length: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct FailedUploadsRequest {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
 #[debug_handler]
 #[worker::send]
 pub async fn sync_post_with_post_service_canister(
     State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
     Json(payload): Json<SyncPostToPostServiceRequest>,
 ) -> APIResponse<()> {
     let sync_post_message = UploadVideoQueueMessage::PushPostToPostServiceCanister(payload);
 
-    let message_result = app_state.upload_video_queue.send(sync_post_message).await;
+    let message_result = app_state
+        .upload_video_queue
+        .send(Traced::new(sync_post_message, &trace))
+        .await;
 
     message_result.into()
 }
 
+/// dead lettered upload queue messages, see utils::failed_uploads
+#[debug_handler]
+#[worker::send]
+pub async fn failed_uploads(
+    State(app_state): State<Arc<AppState>>,
+    payload: Option<Json<FailedUploadsRequest>>,
+) -> APIResponse<FailedUploadsPage> {
+    let Json(payload) = payload.unwrap_or_default();
+    list_failed_uploads(
+        &app_state.env,
+        payload.cursor,
+        payload.limit.unwrap_or(DEFAULT_FAILED_UPLOADS_LIMIT),
+    )
+    .await
+    .into()
+}
+
+#[debug_handler]
+#[worker::send]
+pub async fn redrive_failed_upload(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Path(id): Path<String>,
+) -> APIResponse<()> {
+    let result = match redrive_failed_upload_impl(
+        &app_state.env,
+        &app_state.upload_video_queue,
+        &id,
+    )
+    .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("no failed upload {id}")),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = &result {
+        trace_error!(trace, "Error re-driving failed upload {}: {}", id, e);
+    } else {
+        trace_log!(trace, "Re-drove failed upload {}", id);
+    }
+
+    result.into()
+}
+
+/// re-queues stages of the upload flow for a video ops needs to rescue
+#[debug_handler]
+#[worker::send]
+pub async fn reprocess_video(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Json(payload): Json<ReprocessVideoRequest>,
+) -> APIResponse<ReprocessVideoResult> {
+    let video_uid = payload.video_uid.clone();
+    let result = reprocess_video_impl(
+        &app_state.env,
+        &app_state.streams,
+        &app_state.upload_video_queue,
+        payload,
+        &trace,
+    )
+    .await;
+    match &result {
+        Ok(res) => trace_log!(trace, "Re-queued video {} for {:?}", video_uid, res.queued),
+        Err(e) => trace_error!(trace, "Error reprocessing video {}: {}", video_uid, e),
+    }
+
+    result.into()
+}
+
+/// lets a video through the publish gate whatever its score
+#[debug_handler]
+#[worker::send]
+pub async fn override_moderation_verdict(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Path(video_uid): Path<String>,
+) -> APIResponse<ModerationVerdict> {
+    let result = async {
+        let cloudflare_stream = app_state.streams.for_video(&video_uid).await?;
+        let video = cloudflare_stream.get_video_details(&video_uid).await?;
+        let verdict = ModerationVerdict::overridden(verdict_of(&video));
+        store_verdict(cloudflare_stream, &video, &video_uid, &verdict).await?;
+
+        Ok::<_, Box<dyn Error>>(verdict)
+    }
+    .await;
+    match &result {
+        Ok(_) => trace_log!(trace, "Overrode moderation verdict of {}", video_uid),
+        Err(e) => trace_error!(
+            trace,
+            "Error overriding moderation verdict of {}: {}",
+            video_uid,
+            e
+        ),
+    }
+
+    result.into()
+}
+
+/// the video and creator of a post reported in yral-moderation
+#[debug_handler]
+#[worker::send]
+pub async fn post_details(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Json(payload): Json<PostDetailsRequest>,
+) -> APIResponse<ModeratedPostDetails> {
+    let result = post_details_impl(&app_state.admin_ic_agent, &payload).await;
+    if let Err(e) = &result {
+        trace_error!(
+            trace,
+            "Error reading post {} on {}: {}",
+            payload.post_id,
+            payload.canister_id,
+            e
+        );
+    }
+
+    result.into()
+}
+
+/// rejected in yral-moderation, the post is hidden on its canister
+#[debug_handler]
+#[worker::send]
+pub async fn ban_post(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Json(payload): Json<BanPostRequest>,
+) -> APIResponse<()> {
+    let result = ban_post_impl(&app_state.admin_ic_agent, &payload).await;
+    match &result {
+        Ok(()) => {
+            let event = SearchIndexEvent::PostRemoved {
+                canister_id: payload.canister_id.to_text(),
+                post_id: payload.post_id.to_string(),
+                at: now_millis(),
+                trace_id: Some(trace.clone()),
+            };
+            if let Err(e) = event.send(&app_state.env).await {
+                trace_error!(trace, "Error removing banned post from search: {}", e);
+            }
+        }
+        Err(e) => trace_error!(
+            trace,
+            "Error banning post {} on {}: {}",
+            payload.post_id,
+            payload.canister_id,
+            e
+        ),
+    }
+
+    result.into()
+}
+
 #[debug_handler]
 #[worker::send]
 pub async fn mark_post_as_published(
     State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
     Json(payload): Json<MarkPostAsPublishedRequest>,
 ) -> APIResponse<()> {
     let user_principal =
@@ -718,23 +1447,43 @@ pub async fn mark_post_as_published(
     let post_id = payload.post_id.clone();
 
     let result = mark_post_as_published_and_emit_events(
+        &app_state.env,
         &app_state.admin_ic_agent,
+        &app_state.streams,
         &app_state.event_rest_service,
         payload,
+        &trace,
     )
     .await;
 
     if let Ok(()) = &result {
-        app_state
-            .notification_client
-            .send_notification(
-                utils::notification_client::NotificationType::VideoPublished {
-                    user_principal,
-                    post_id,
-                },
-                user_principal,
-            )
-            .await;
+        let event = AnalyticsEvent::VideoPublished {
+            user_principal: user_principal.to_text(),
+            post_id: post_id.clone(),
+            at: now_millis(),
+            trace_id: Some(trace.clone()),
+        };
+        if let Err(e) = event.send(&app_state.env).await {
+            trace_error!(
+                trace,
+                "Error sending video published analytics event: {}",
+                e
+            );
+        }
+
+        let event = WebhookEvent::new(WebhookEventData::PostPublished {
+            user_principal: user_principal.to_text(),
+            post_id: post_id.clone(),
+        });
+        if let Err(e) = event.send(&app_state.env).await {
+            trace_error!(trace, "Error sending post published webhook event: {}", e);
+        }
+
+        let job = NotificationJob::new(user_principal, Notification::VideoPublished { post_id })
+            .with_trace(&trace);
+        if let Err(e) = job.enqueue(&app_state.env).await {
+            trace_error!(trace, "Error queueing video published notification: {}", e);
+        }
     }
 
     result.into()
@@ -744,15 +1493,17 @@ pub async fn mark_post_as_published(
 #[worker::send]
 pub async fn update_metadata(
     State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
     Json(payload): Json<UpdateMetadataRequest>,
 ) -> APIResponse<()> {
     let video_uid = payload.video_uid.clone();
-    let result = update_metadata_impl(&app_state.cloudflare_stream, payload).await;
+    let result = update_metadata_impl(&app_state.streams, payload).await;
 
     let api_response: APIResponse<()> = result.into();
 
     if !api_response.success {
-        console_error!(
+        trace_error!(
+            trace,
             "error updating metadata {}",
             &api_response.message.as_ref().unwrap_or(&String::from(""))
         )
@@ -763,11 +1514,12 @@ pub async fn update_metadata(
     // upload video uid
     let queue_send_result = app_state
         .upload_video_queue
-        .send(upload_video_message)
+        .send(Traced::new(upload_video_message, &trace))
         .await;
 
     if let Err(e) = queue_send_result {
-        console_error!(
+        trace_error!(
+            trace,
             "Error sending message to upload queue. Error {}",
             e.to_string()
         );
@@ -780,6 +1532,7 @@ pub async fn update_metadata(
 #[worker::send]
 pub async fn update_metadata_v2(
     State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
     Json(payload): Json<UpdateMetadataRequest>,
 ) -> APIResponse<()> {
     let video_uid = payload.video_uid.clone();
@@ -788,7 +1541,8 @@ pub async fn update_metadata_v2(
     let api_response: APIResponse<()> = result.into();
 
     if !api_response.success {
-        console_error!(
+        trace_error!(
+            trace,
             "error finalizing storj upload {}",
             &api_response.message.as_ref().unwrap_or(&String::from(""))
         )
@@ -801,7 +1555,8 @@ pub async fn update_metadata_v2(
         ))
         .unwrap_or_default();
 
-        console_log!(
+        trace_log!(
+ trace,
             "Queuing Storj video upload with delegated_identity size: {} bytes, post_details size: {} bytes",
             delegated_identity_json.len(),
             post_details_json.len()
@@ -816,11 +1571,12 @@ pub async fn update_metadata_v2(
 
         let queue_send_result = app_state
             .upload_video_queue
-            .send(upload_video_message)
+            .send(Traced::new(upload_video_message, &trace))
             .await;
 
         if let Err(e) = queue_send_result {
-            console_error!(
+            trace_error!(
+                trace,
                 "Error sending message to upload queue. Error {}",
                 e.to_string()
             );
@@ -830,6 +1586,57 @@ pub async fn update_metadata_v2(
     api_response
 }
 
+#[debug_handler]
+#[worker::send]
+pub async fn get_post_for_video(
+    State(app_state): State<Arc<AppState>>,
+    Path(video_uid): Path<String>,
+) -> APIResponse<VideoPost> {
+    match post_for_video(&app_state.env, &video_uid).await {
+        Ok(Some(video_post)) => Ok(video_post),
+        Ok(None) => Err(UploadError::NotFound(format!("post for video {video_uid}"))),
+        Err(e) => Err(UploadError::from(e)),
+    }
+    .into()
+}
+
+#[debug_handler]
+#[worker::send]
+pub async fn playback_token(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Json(payload): Json<PlaybackTokenRequest>,
+) -> APIResponse<PlaybackToken> {
+    let video_uid = payload.video_uid.clone();
+    let result = playback_token_impl(&app_state.streams, payload).await;
+    if let Err(e) = &result {
+        trace_error!(
+            trace,
+            "error minting playback token for {}: {}",
+            video_uid,
+            e
+        );
+    }
+
+    result.into()
+}
+
+#[debug_handler]
+#[worker::send]
+pub async fn set_thumbnail(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Json(payload): Json<SetThumbnailRequest>,
+) -> APIResponse<()> {
+    let video_uid = payload.video_uid.clone();
+    let result = set_thumbnail_impl(&app_state.streams, payload).await;
+    if let Err(e) = &result {
+        trace_error!(trace, "error setting thumbnail of {}: {}", video_uid, e);
+    }
+
+    result.into()
+}
+
 #[debug_handler]
 #[worker::send]
 pub async fn notify_video_upload(
@@ -843,7 +1650,7 @@ pub async fn notify_video_upload(
 
     if let Err(e) = notify_video_upload_impl(
         &app_state.admin_ic_agent,
-        &app_state.notification_client,
+        &app_state.env,
         payload,
         headers,
         webhook_secret_key,
@@ -859,7 +1666,7 @@ pub async fn notify_video_upload(
 }
 
 async fn update_metadata_impl(
-    cloudflare_stream: &CloudflareStream,
+    streams: &StreamAccounts,
     mut req_data: UpdateMetadataRequest,
 ) -> Result<(), Box<dyn Error>> {
     let _delegated_identity =
@@ -876,9 +1683,14 @@ async fn update_metadata_impl(
             req_data.post_details.clone(),
         ))?,
     );
+    req_data
+        .meta
+        .insert(METADATA_SET_AT_KEY.to_string(), now_millis().to_string());
 
     // Update Cloudflare Stream metadata
-    cloudflare_stream
+    streams
+        .for_video(&req_data.video_uid)
+        .await?
         .add_meta_to_video(&req_data.video_uid, req_data.meta.clone())
         .await?;
 
@@ -933,9 +1745,7 @@ async fn update_metadata_impl_v2(
 pub async fn get_upload_url(
     State(app_state): State<Arc<AppState>>,
 ) -> APIResponse<DirectUploadResult> {
-    get_upload_url_impl(&app_state.cloudflare_stream)
-        .await
-        .into()
+    get_upload_url_impl(&app_state.streams.primary).await.into()
 }
 
 #[debug_handler]
@@ -955,6 +1765,9 @@ pub async fn get_upload_url_v3(
     APIResponse {
         success: true,
         message: None,
+        code: None,
+        retryable: None,
+        status: None,
         data: Some(DirectUploadResult {
             scheduled_deletion: None,
             uid: Some(video_id),
@@ -964,6 +1777,76 @@ pub async fn get_upload_url_v3(
     }
 }
 
+/// several direct upload URLs at once, counted against the caller's hourly cap
+#[debug_handler]
+#[worker::send]
+pub async fn get_upload_urls_batch(
+    State(app_state): State<Arc<AppState>>,
+    trace: TraceId,
+    Json(payload): Json<GetUploadUrlsBatchRequest>,
+) -> APIResponse<Vec<DirectUploadResult>> {
+    get_upload_urls_batch_impl(&app_state, payload, &trace)
+        .await
+        .into()
+}
+
+async fn get_upload_urls_batch_impl(
+    app_state: &AppState,
+    payload: GetUploadUrlsBatchRequest,
+    trace: &TraceId,
+) -> Result<Vec<DirectUploadResult>, Box<dyn Error>> {
+    if !(1..=MAX_UPLOAD_URLS_PER_BATCH).contains(&payload.count) {
+        return Err(format!("count must be between 1 and {MAX_UPLOAD_URLS_PER_BATCH}").into());
+    }
+
+    DelegatedIdentity::try_from(payload.delegated_identity_wire.clone())?;
+    let user_principal = Principal::self_authenticating(&payload.delegated_identity_wire.from_key);
+
+    DoClient::new(&app_state.env)
+        .with_trace(trace)
+        .call::<_, ()>(
+            UPLOAD_URL_LIMITER,
+            &user_principal.to_text(),
+            "acquire",
+            &AcquireUploadUrls {
+                count: payload.count,
+            },
+        )
+        .await?
+        .map_err(|(_, e)| e.message)?;
+
+    let mut results = Vec::with_capacity(payload.count as usize);
+    for _ in 0..payload.count {
+        results.push(app_state.streams.primary.get_upload_url().await?);
+    }
+
+    Ok(results)
+}
+
+/// resumable alternative to `get_upload_url` for clients on flaky networks
+#[debug_handler]
+#[worker::send]
+pub async fn get_tus_upload_url(
+    State(app_state): State<Arc<AppState>>,
+    Json(payload): Json<GetTusUploadUrlRequest>,
+) -> APIResponse<TusUploadResult> {
+    get_tus_upload_url_impl(&app_state.streams.primary, payload.upload_length)
+        .await
+        .into()
+}
+
+async fn get_tus_upload_url_impl(
+    cloudflare_stream: &CloudflareStream,
+    upload_length: u64,
+) -> Result<TusUploadResult, Box<dyn Error>> {
+    if upload_length == 0 {
+        return Err("upload_length must be positive".into());
+    }
+
+    let result = cloudflare_stream.create_tus_upload(upload_length).await?;
+    Ok(result)
+}
+
 async fn get_upload_url_impl(
     cloudflare_stream: &CloudflareStream,
 ) -> Result<DirectUploadResult, Box<dyn Error>> {
@@ -975,8 +1858,13 @@ async fn get_upload_url_impl(
 #[worker::send]
 pub async fn get_upload_url_v2(
     State(app_state): State<Arc<AppState>>,
+    payload: Option<Json<GetUploadUrlV2Request>>,
 ) -> APIResponse<DirectUploadResult> {
-    get_upload_url_impl_v2(&app_state.cloudflare_stream)
+    let Json(payload) = payload.unwrap_or_default();
+    let watermark_uid = payload
+        .watermark
+        .then_some(app_state.watermark_uid.as_str());
+    get_upload_url_impl_v2(&app_state.streams.primary, watermark_uid)
         .await
         .into()
 }
@@ -987,24 +1875,31 @@ pub async fn get_upload_url_for_ai_draft_video(
     State(app_state): State<Arc<AppState>>,
     Json(payload): Json<AIVideoUploadUrlRequest>,
 ) -> APIResponse<DirectUploadResult> {
-    get_upload_url_for_ai_draft_video_impl(&app_state.cloudflare_stream, payload.user_id.to_text())
-        .await
-        .into()
+    // AI drafts always get the watermarked renditions
+    get_upload_url_for_ai_draft_video_impl(
+        &app_state.streams.primary,
+        payload.user_id.to_text(),
+        &app_state.watermark_uid,
+    )
+    .await
+    .into()
 }
 
 async fn get_upload_url_for_ai_draft_video_impl(
     cloudflare_stream: &CloudflareStream,
     user_principal: String,
+    watermark_uid: &str,
 ) -> Result<DirectUploadResult, Box<dyn Error>> {
     let result = cloudflare_stream
-        .get_upload_url_for_ai_draft_video(user_principal)
+        .get_upload_url_for_ai_draft_video(user_principal, Some(watermark_uid))
         .await?;
     Ok(result)
 }
 
 async fn get_upload_url_impl_v2(
     cloudflare_stream: &CloudflareStream,
+    watermark_uid: Option<&str>,
 ) -> Result<DirectUploadResult, Box<dyn Error>> {
-    let result = cloudflare_stream.get_upload_url_v2().await?;
+    let result = cloudflare_stream.get_upload_url_v2(watermark_uid).await?;
     Ok(result)
 }
@@ -0,0 +1,61 @@
+use candid::Principal;
+use ic_agent::Agent;
+use serde::{Deserialize, Serialize};
+use yral_canisters_client::individual_user_template::{IndividualUserTemplate, PostStatus};
+
+use crate::error::UploadError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BanReason {
+    Explicit,
+    UserReports,
+}
+
+/// sent by yral-moderation when a reviewer rejects a post
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BanPostRequest {
+    pub canister_id: Principal,
+    pub post_id: u64,
+    pub reason: BanReason,
+}
+
+pub async fn ban_post_impl(agent: &Agent, req: &BanPostRequest) -> Result<(), UploadError> {
+    let status = match req.reason {
+        BanReason::Explicit => PostStatus::BannedForExplicitness,
+        BanReason::UserReports => PostStatus::BannedDueToUserReporting,
+    };
+
+    IndividualUserTemplate(req.canister_id, agent)
+        .update_post_status(req.post_id, status)
+        .await?;
+
+    Ok(())
+}
+
+/// asked by yral-moderation for what a reported post points at, so reporters can't name it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PostDetailsRequest {
+    pub canister_id: Principal,
+    pub post_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModeratedPostDetails {
+    pub video_uid: String,
+    pub creator_principal: Principal,
+}
+
+pub async fn post_details_impl(
+    agent: &Agent,
+    req: &PostDetailsRequest,
+) -> Result<ModeratedPostDetails, UploadError> {
+    let details = IndividualUserTemplate(req.canister_id, agent)
+        .get_individual_post_details_by_id(req.post_id)
+        .await?;
+
+    Ok(ModeratedPostDetails {
+        video_uid: details.video_uid,
+        creator_principal: details.created_by_user_principal_id,
+    })
+}
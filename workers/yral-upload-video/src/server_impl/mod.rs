@@ -1,3 +1,8 @@
+pub mod ban_post;
 pub mod notify_video_upload_impl;
+pub mod playback_token;
+pub mod reprocess_video;
+pub mod set_thumbnail;
+pub mod sweep_stuck_videos;
 pub mod sync_post_with_post_service_canister;
 pub mod upload_video_to_canister;
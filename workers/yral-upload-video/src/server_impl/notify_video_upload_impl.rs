@@ -1,45 +1,71 @@
-use std::error::Error;
-
 use axum::http::HeaderMap;
 use candid::Principal;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
-use worker::console_log;
+use worker::{console_error, console_log, Env};
+use worker_utils::{
+    notification::{Notification, NotificationJob},
+    time::now_millis,
+    trace::{TraceId, Traced},
+};
+use yral_canisters_client::ic::USER_POST_SERVICE_ID;
 
 use crate::{
+    error::UploadError,
     server_impl::upload_video_to_canister::upload_ai_video_to_canister_as_draft,
     utils::{
-        notification_client,
         types::{NotifyRequestPayload, POST_ID, USER_ID},
+        upload_latency::rfc3339_millis,
+        video_posts::VideoPost,
     },
+    UploadVideoQueueMessage,
 };
 
+pub const WEBHOOK_NONCES_KV: &str = "WEBHOOK_NONCES";
+/// max age of a webhook signature in seconds, defaults to `DEFAULT_WEBHOOK_TOLERANCE_SECS`
+const WEBHOOK_TOLERANCE_VAR: &str = "WEBHOOK_TOLERANCE_SECS";
+const DEFAULT_WEBHOOK_TOLERANCE_SECS: u64 = 300;
+/// KV won't expire keys sooner
+const MIN_NONCE_TTL_SECS: u64 = 60;
+
+fn webhook_tolerance_secs(env: &Env) -> u64 {
+    env.var(WEBHOOK_TOLERANCE_VAR)
+        .ok()
+        .and_then(|v| v.to_string().parse().ok())
+        .unwrap_or(DEFAULT_WEBHOOK_TOLERANCE_SECS)
+}
+
+/// checks the HMAC and the signature's age, returns the signed unix time in seconds
 pub fn verify_webhook_signature(
     webhook_secret_key: String,
     webhook_signature: &str,
     req_data: String,
-) -> Result<(), Box<dyn Error>> {
+    now_secs: u64,
+    tolerance_secs: u64,
+) -> Result<u64, UploadError> {
+    let malformed = |reason: &str| UploadError::Validation(reason.to_string());
     let mut time_and_signature = webhook_signature.split(",");
 
     let time = time_and_signature
         .next()
-        .ok_or("time not found in web signature")?
+        .ok_or_else(|| malformed("time not found in web signature"))?
         .split("=")
         .last()
-        .ok_or("invalid time header format")?;
+        .ok_or_else(|| malformed("invalid time header format"))?;
 
     let signature = time_and_signature
         .next()
-        .ok_or("signature not found in web signature")?
+        .ok_or_else(|| malformed("signature not found in web signature"))?
         .split("=")
         .last()
-        .ok_or("invalid signature header format")?;
+        .ok_or_else(|| malformed("invalid signature header format"))?;
 
     let input_str = format!("{time}.{req_data}");
 
     type HmacSha256 = Hmac<Sha256>;
 
-    let mut hmac = HmacSha256::new_from_slice(webhook_secret_key.as_bytes())?;
+    let mut hmac =
+        HmacSha256::new_from_slice(webhook_secret_key.as_bytes()).map_err(UploadError::internal)?;
 
     hmac.update(input_str.as_bytes());
 
@@ -47,39 +73,80 @@ pub fn verify_webhook_signature(
     let result_str = mac_result.into_bytes();
     let digest = hex::encode(result_str);
 
-    if digest.eq(&signature) {
-        Ok(())
-    } else {
-        Err("Invalid webhook signature".into())
+    if !digest.eq(&signature) {
+        return Err(UploadError::Identity("Invalid webhook signature".into()));
+    }
+
+    let signed_at: u64 = time.parse().map_err(UploadError::validation)?;
+    if now_secs.abs_diff(signed_at) > tolerance_secs {
+        return Err(UploadError::Identity("webhook signature expired".into()));
+    }
+
+    Ok(signed_at)
+}
+
+/// records the `(uid, time)` pair of a verified webhook, failing if it was already seen
+///
+/// entries only need to outlive the tolerance, older signatures are rejected anyway
+async fn claim_webhook_nonce(
+    env: &Env,
+    video_uid: &str,
+    signed_at: u64,
+    tolerance_secs: u64,
+) -> Result<(), UploadError> {
+    let kv = env.kv(WEBHOOK_NONCES_KV)?;
+    let key = format!("{video_uid}:{signed_at}");
+    if kv.get(&key).text().await?.is_some() {
+        return Err(UploadError::Identity(format!(
+            "webhook for {video_uid} signed at {signed_at} replayed"
+        )));
     }
+
+    kv.put(&key, signed_at)?
+        .expiration_ttl((tolerance_secs * 2).max(MIN_NONCE_TTL_SECS))
+        .execute()
+        .await?;
+
+    Ok(())
 }
 
 pub async fn notify_video_upload_impl(
     admin_agent: &ic_agent::Agent,
-    notification_client: &notification_client::NotificationClient,
+    env: &Env,
     req_data: String,
     headers: HeaderMap,
     webhook_secret_key: String,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), UploadError> {
     let webhook_signature = headers
         .get("Webhook-Signature")
-        .ok_or("Signature not found")?
-        .to_str()?;
-
-    let notify_req_paylod: NotifyRequestPayload = serde_json::from_str(&req_data)?;
-
-    verify_webhook_signature(webhook_secret_key, webhook_signature, req_data)?;
+        .ok_or_else(|| UploadError::Identity("Signature not found".into()))?
+        .to_str()
+        .map_err(UploadError::identity)?;
+
+    let notify_req_paylod: NotifyRequestPayload =
+        serde_json::from_str(&req_data).map_err(UploadError::validation)?;
+
+    let tolerance_secs = webhook_tolerance_secs(env);
+    let signed_at = verify_webhook_signature(
+        webhook_secret_key,
+        webhook_signature,
+        req_data,
+        now_millis() / 1000,
+        tolerance_secs,
+    )?;
+    claim_webhook_nonce(env, &notify_req_paylod.uid, signed_at, tolerance_secs).await?;
 
     if notify_req_paylod
         .status
         .state
         .is_some_and(|s| s.eq("error"))
     {
-        return Err(notify_req_paylod
-            .status
-            .err_reason_text
-            .unwrap_or("unknown error while processing video".into())
-            .into());
+        return Err(UploadError::StreamApi(
+            notify_req_paylod
+                .status
+                .err_reason_text
+                .unwrap_or("unknown error while processing video".into()),
+        ));
     }
 
     let Some(post_id) = notify_req_paylod.meta.get(POST_ID) else {
@@ -92,28 +159,53 @@ pub async fn notify_video_upload_impl(
         return Ok(());
     };
 
-    let user_principal = Principal::from_text(user_principal_str)?;
+    let user_principal =
+        Principal::from_text(user_principal_str).map_err(UploadError::validation)?;
 
     let video_uid = notify_req_paylod.uid;
     let upload_video_to_draft_result = upload_ai_video_to_canister_as_draft(
         admin_agent,
         user_principal,
         post_id.clone(),
-        video_uid,
+        video_uid.clone(),
     )
     .await;
 
     match upload_video_to_draft_result {
         Ok(_) => {
-            notification_client
-                .send_notification(
-                    notification_client::NotificationType::VideoUploadedToDraft {
-                        user_principal,
-                        post_id: post_id.clone(),
-                    },
-                    user_principal,
-                )
-                .await;
+            let video_post = VideoPost::new(
+                video_uid.clone(),
+                USER_POST_SERVICE_ID,
+                post_id.clone(),
+                user_principal,
+            )
+            .with_url_issued_at(rfc3339_millis(notify_req_paylod.created.as_ref()));
+            if let Err(e) = video_post.record(env).await {
+                console_error!("Error recording post of video {}: {}", video_uid, e);
+            }
+
+            // drafts are published by the user later, the gate needs a verdict by then
+            let message = Traced::new(
+                UploadVideoQueueMessage::ModerateVideo(video_uid.clone()),
+                &TraceId::new(),
+            );
+            let queued = match env.queue("UPLOAD_VIDEO") {
+                Ok(queue) => queue.send(message).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = queued {
+                console_error!("Error queueing moderation of video {}: {}", video_uid, e);
+            }
+
+            let job = NotificationJob::new(
+                user_principal,
+                Notification::VideoUploadedToDraft {
+                    post_id: post_id.clone(),
+                },
+            );
+            if let Err(e) = job.enqueue(env).await {
+                console_error!("Error queueing video uploaded to draft notification: {}", e);
+            }
 
             Ok(())
         }
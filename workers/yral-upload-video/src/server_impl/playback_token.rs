@@ -0,0 +1,59 @@
+use candid::Principal;
+use ic_agent::identity::DelegatedIdentity;
+use serde::{Deserialize, Serialize};
+use worker_utils::time::now_millis;
+
+use crate::{
+    error::UploadError,
+    utils::{cloudflare_stream::StreamAccounts, types::DelegatedIdentityWire},
+};
+
+/// long enough to preview a video, short enough that a leaked token is soon useless
+const PLAYBACK_TOKEN_TTL_SECS: u64 = 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaybackTokenRequest {
+    pub video_uid: String,
+    pub delegated_identity_wire: DelegatedIdentityWire,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaybackToken {
+    /// used in place of the video uid in Stream playback URLs
+    pub token: String,
+    /// unix seconds
+    pub expires_at: u64,
+}
+
+/// only the video's owner can preview it before it's published
+pub async fn playback_token_impl(
+    streams: &StreamAccounts,
+    req: PlaybackTokenRequest,
+) -> Result<PlaybackToken, UploadError> {
+    // rejects identities whose delegation doesn't verify
+    DelegatedIdentity::try_from(req.delegated_identity_wire.clone())
+        .map_err(UploadError::identity)?;
+    let caller = Principal::self_authenticating(&req.delegated_identity_wire.from_key);
+
+    let cloudflare_stream = streams
+        .for_video(&req.video_uid)
+        .await
+        .map_err(UploadError::stream)?;
+    let video = cloudflare_stream
+        .get_video_details(&req.video_uid)
+        .await
+        .map_err(UploadError::stream)?;
+    if video.owner() != Some(caller) {
+        return Err(UploadError::Forbidden(
+            "only the owner can preview this video".into(),
+        ));
+    }
+
+    let expires_at = now_millis() / 1000 + PLAYBACK_TOKEN_TTL_SECS;
+    let token = cloudflare_stream
+        .create_playback_token(&req.video_uid, expires_at)
+        .await
+        .map_err(UploadError::stream)?;
+
+    Ok(PlaybackToken { token, expires_at })
+}
@@ -0,0 +1,89 @@
+//! pushes a video through the upload pipeline again, e.g. when it ended up half-published
+//!
+//! stages are queued as fresh messages, whatever happened to the video's earlier ones
+
+use serde::{Deserialize, Serialize};
+use worker::{Env, Queue};
+use worker_utils::trace::{TraceId, Traced};
+
+use crate::{
+    error::UploadError,
+    utils::{cloudflare_stream::StreamAccounts, video_posts::post_for_video},
+    UploadVideoQueueMessage,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReprocessStage {
+    /// the full flow, creating the post queues the later stages
+    UploadVideo,
+    MarkVideoAsDownloadable,
+    ModerateVideo,
+}
+
+impl ReprocessStage {
+    fn message(self, video_uid: String) -> UploadVideoQueueMessage {
+        match self {
+            Self::UploadVideo => UploadVideoQueueMessage::UploadVideo(video_uid),
+            Self::MarkVideoAsDownloadable => {
+                UploadVideoQueueMessage::MarkVideoAsDownloadable(video_uid)
+            }
+            Self::ModerateVideo => UploadVideoQueueMessage::ModerateVideo(video_uid),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReprocessVideoRequest {
+    pub video_uid: String,
+    /// the full flow if empty
+    #[serde(default)]
+    pub stages: Vec<ReprocessStage>,
+    /// runs `UploadVideo` even if the video already became a post, which creates another one
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReprocessVideoResult {
+    /// kinds of the queued messages, in order
+    pub queued: Vec<String>,
+}
+
+pub async fn reprocess_video_impl(
+    env: &Env,
+    streams: &StreamAccounts,
+    upload_queue: &Queue,
+    req: ReprocessVideoRequest,
+    trace: &TraceId,
+) -> Result<ReprocessVideoResult, UploadError> {
+    let stages = if req.stages.is_empty() {
+        vec![ReprocessStage::UploadVideo]
+    } else {
+        req.stages
+    };
+
+    // an unknown uid would only fail once the message is consumed
+    streams
+        .get_video_details(&req.video_uid)
+        .await
+        .map_err(UploadError::stream)?;
+
+    if stages.contains(&ReprocessStage::UploadVideo) && !req.force {
+        if let Some(post) = post_for_video(env, &req.video_uid).await? {
+            return Err(UploadError::Validation(format!(
+                "video {} is already post {} on {}, pick later stages or force a new post",
+                req.video_uid, post.post_id, post.canister_id
+            )));
+        }
+    }
+
+    let mut queued = Vec::with_capacity(stages.len());
+    for stage in stages {
+        let message = stage.message(req.video_uid.clone());
+        queued.push(message.kind().to_string());
+        upload_queue.send(Traced::new(message, trace)).await?;
+    }
+
+    Ok(ReprocessVideoResult { queued })
+}
@@ -0,0 +1,81 @@
+use candid::Principal;
+use ic_agent::identity::DelegatedIdentity;
+use serde::{Deserialize, Serialize};
+use worker::Url;
+
+use crate::{
+    error::UploadError,
+    utils::{cloudflare_stream::StreamAccounts, types::DelegatedIdentityWire},
+};
+
+/// set in the video's meta for clients to show instead of Stream's generated thumbnail
+pub const CUSTOM_THUMBNAIL_KEY: &str = "custom-thumbnail-url";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSource {
+    /// frame at this fraction of the video's duration, 0 to 1
+    TimestampPct(f32),
+    /// an image the client already uploaded, must be https
+    ImageUrl(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetThumbnailRequest {
+    pub video_uid: String,
+    pub delegated_identity_wire: DelegatedIdentityWire,
+    pub thumbnail: ThumbnailSource,
+}
+
+pub async fn set_thumbnail_impl(
+    streams: &StreamAccounts,
+    req: SetThumbnailRequest,
+) -> Result<(), UploadError> {
+    // rejects identities whose delegation doesn't verify
+    DelegatedIdentity::try_from(req.delegated_identity_wire.clone())
+        .map_err(UploadError::identity)?;
+    let caller = Principal::self_authenticating(&req.delegated_identity_wire.from_key);
+
+    let cloudflare_stream = streams
+        .for_video(&req.video_uid)
+        .await
+        .map_err(UploadError::stream)?;
+    let video = cloudflare_stream
+        .get_video_details(&req.video_uid)
+        .await
+        .map_err(UploadError::stream)?;
+    if video.uploader() != Some(caller) {
+        return Err(UploadError::Forbidden(
+            "only the uploader can change the thumbnail".into(),
+        ));
+    }
+
+    match req.thumbnail {
+        ThumbnailSource::TimestampPct(pct) => {
+            if !(0.0..=1.0).contains(&pct) {
+                return Err(UploadError::Validation(
+                    "timestamp_pct must be between 0 and 1".into(),
+                ));
+            }
+            cloudflare_stream
+                .set_thumbnail_timestamp_pct(&req.video_uid, pct)
+                .await
+                .map_err(UploadError::stream)?;
+        }
+        ThumbnailSource::ImageUrl(image_url) => {
+            let image = Url::parse(&image_url).map_err(UploadError::validation)?;
+            if image.scheme() != "https" {
+                return Err(UploadError::Validation("image_url must be https".into()));
+            }
+            // edits replace the whole meta, keep what update_metadata attached
+            let mut meta = video.meta.unwrap_or_default();
+            meta.insert(CUSTOM_THUMBNAIL_KEY.to_string(), image_url);
+            cloudflare_stream
+                .add_meta_to_video(&req.video_uid, meta)
+                .await
+                .map_err(UploadError::stream)?;
+        }
+    }
+
+    Ok(())
+}
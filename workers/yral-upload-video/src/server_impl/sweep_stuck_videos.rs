@@ -0,0 +1,226 @@
+//! periodic sweep over Stream for uploads the queue lost track of
+//!
+//! videos stay `queued`/`inprogress` on Stream's side when transcoding stalls, and a ready or
+//! errored video is never processed if its `UploadVideo` message ran out of attempts before
+//! Stream settled. `swept:` markers in `FAILED_UPLOADS` keep a video from being handled twice.
+
+use chrono::{DateTime, SecondsFormat};
+use worker::{console_error, console_log, Env, Queue};
+use worker_utils::{
+    analytics::AnalyticsEvent,
+    metrics::Metrics,
+    notification::{Notification, NotificationJob},
+    time::now_millis,
+    trace::{TraceId, Traced},
+};
+
+use crate::{
+    error::UploadError,
+    utils::{
+        cloudflare_stream::CloudflareStream,
+        failed_uploads::FAILED_UPLOADS_KV,
+        types::{Video, POST_DETAILS_KEY},
+        upload_latency::rfc3339_millis,
+    },
+    UploadVideoQueueMessage,
+};
+
+/// only videos created within this window are looked at
+const SWEEP_WINDOW_MS: u64 = 24 * 3600 * 1000;
+/// longer than an `UploadVideo` message lives on the queue (12 deliveries, 2 min apart)
+const STUCK_AFTER_MS: u64 = 3600 * 1000;
+const SWEPT_PREFIX: &str = "swept:";
+/// outlives `SWEEP_WINDOW_MS` so a video leaves the window before its marker expires
+const SWEPT_TTL_SECS: u64 = 48 * 3600;
+const DEFAULT_FAILURE_REASON: &str = "Something went wrong while processing your video";
+
+fn rfc3339(millis: u64) -> Result<String, UploadError> {
+    let at = DateTime::from_timestamp_millis(millis as i64)
+        .ok_or_else(|| UploadError::Internal("timestamp out of range".into()))?;
+    Ok(at.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// `true` if Stream took longer than `STUCK_AFTER_MS` to settle on `settled_at`
+fn settled_late(video: &Video, settled_at: Option<&String>) -> bool {
+    match (
+        rfc3339_millis(video.created.as_ref()),
+        rfc3339_millis(settled_at),
+    ) {
+        (Some(created), Some(settled)) => settled.saturating_sub(created) > STUCK_AFTER_MS,
+        _ => false,
+    }
+}
+
+/// only videos uploaded through `update_metadata` carry post details
+fn has_post_details(video: &Video) -> bool {
+    video
+        .meta
+        .as_ref()
+        .is_some_and(|meta| meta.contains_key(POST_DETAILS_KEY))
+}
+
+pub struct StuckVideoSweep<'a> {
+    env: &'a Env,
+    stream: &'a CloudflareStream,
+    upload_queue: &'a Queue,
+    metrics: Metrics,
+}
+
+impl<'a> StuckVideoSweep<'a> {
+    pub fn new(env: &'a Env, stream: &'a CloudflareStream, upload_queue: &'a Queue) -> Self {
+        Self {
+            env,
+            stream,
+            upload_queue,
+            metrics: Metrics::new(env, "yral-upload-video"),
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), UploadError> {
+        let now = now_millis();
+        let window_start = rfc3339(now.saturating_sub(SWEEP_WINDOW_MS))?;
+        let stuck_before = rfc3339(now.saturating_sub(STUCK_AFTER_MS))?;
+        let window_end = rfc3339(now)?;
+
+        self.report_stuck(&window_start, &stuck_before).await?;
+        self.requeue_ready(&window_start, &window_end).await?;
+        self.report_failed(&window_start, &window_end).await?;
+
+        Ok(())
+    }
+
+    async fn is_swept(&self, video_uid: &str) -> Result<bool, UploadError> {
+        let marker = self
+            .env
+            .kv(FAILED_UPLOADS_KV)?
+            .get(&format!("{SWEPT_PREFIX}{video_uid}"))
+            .text()
+            .await?;
+        Ok(marker.is_some())
+    }
+
+    async fn mark_swept(&self, video_uid: &str) -> Result<(), UploadError> {
+        self.env
+            .kv(FAILED_UPLOADS_KV)?
+            .put(&format!("{SWEPT_PREFIX}{video_uid}"), now_millis())?
+            .expiration_ttl(SWEPT_TTL_SECS)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// nothing can be done for these until Stream finishes, they're only surfaced
+    async fn report_stuck(&self, after: &str, before: &str) -> Result<(), UploadError> {
+        let mut stuck = 0;
+        for status in ["queued", "inprogress"] {
+            let videos = self
+                .stream
+                .list_videos(status, after, before)
+                .await
+                .map_err(UploadError::stream)?;
+            for video in videos {
+                console_log!(
+                    "video {} is still {status} on stream, created at {}",
+                    video.uid.as_deref().unwrap_or_default(),
+                    video.created.as_deref().unwrap_or_default()
+                );
+                stuck += 1;
+            }
+        }
+        self.metrics.count("stuck_video_sweep", &["stuck"], stuck);
+
+        Ok(())
+    }
+
+    async fn requeue_ready(&self, after: &str, before: &str) -> Result<(), UploadError> {
+        let mut requeued = 0;
+        let videos = self
+            .stream
+            .list_videos("ready", after, before)
+            .await
+            .map_err(UploadError::stream)?;
+        for video in videos {
+            let Some(video_uid) = video.uid.clone() else {
+                continue;
+            };
+            if !has_post_details(&video)
+                || !settled_late(&video, video.ready_to_stream_at.as_ref())
+                || self.is_swept(&video_uid).await?
+            {
+                continue;
+            }
+
+            let trace = TraceId::new();
+            let message = UploadVideoQueueMessage::UploadVideo(video_uid.clone());
+            self.upload_queue.send(Traced::new(message, &trace)).await?;
+            self.mark_swept(&video_uid).await?;
+
+            console_log!("requeued video {video_uid} which became ready late, trace {trace}");
+            requeued += 1;
+        }
+        self.metrics
+            .count("stuck_video_sweep", &["requeued"], requeued);
+
+        Ok(())
+    }
+
+    async fn report_failed(&self, after: &str, before: &str) -> Result<(), UploadError> {
+        let mut failed = 0;
+        let videos = self
+            .stream
+            .list_videos("error", after, before)
+            .await
+            .map_err(UploadError::stream)?;
+        for video in videos {
+            let Some(video_uid) = video.uid.clone() else {
+                continue;
+            };
+            if !has_post_details(&video)
+                || !settled_late(&video, video.modified.as_ref())
+                || self.is_swept(&video_uid).await?
+            {
+                continue;
+            }
+            let Some(user_principal) = video.uploader() else {
+                console_error!("video {video_uid} failed but its uploader is unknown");
+                continue;
+            };
+
+            let reason = video
+                .status
+                .as_ref()
+                .map_or(DEFAULT_FAILURE_REASON, |status| status.user_facing_error())
+                .to_string();
+
+            let trace = TraceId::new();
+            let event = AnalyticsEvent::VideoUploadFailed {
+                user_principal: user_principal.to_text(),
+                video_uid: video_uid.clone(),
+                reason: reason.clone(),
+                at: now_millis(),
+                trace_id: Some(trace.clone()),
+            };
+            if let Err(e) = event.send(self.env).await {
+                console_error!("Error sending video upload failed analytics event: {e}");
+            }
+
+            let job = NotificationJob::new(
+                user_principal,
+                Notification::VideoUploadError {
+                    video_uid: video_uid.clone(),
+                    reason,
+                },
+            )
+            .with_trace(&trace);
+            if let Err(e) = job.enqueue(self.env).await {
+                console_error!("Error queueing video upload error notification: {e}");
+            }
+
+            self.mark_swept(&video_uid).await?;
+            failed += 1;
+        }
+        self.metrics.count("stuck_video_sweep", &["failed"], failed);
+
+        Ok(())
+    }
+}
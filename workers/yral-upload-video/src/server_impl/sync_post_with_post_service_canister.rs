@@ -1,7 +1,7 @@
 use candid::Principal;
 use ic_agent::Agent;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use worker::Env;
 use yral_canisters_client::{
     ic::USER_POST_SERVICE_ID,
     individual_user_template::{IndividualUserTemplate, Post, PostStatus, Result4},
@@ -11,7 +11,12 @@ use yral_canisters_client::{
     },
 };
 
-use crate::utils::service_canister_post_mapping_redis_rest_client::RedisRestClient;
+use crate::{
+    error::UploadError,
+    utils::{
+        service_canister_post_mapping_redis_rest_client::RedisRestClient, video_posts::VideoPost,
+    },
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SyncPostToPostServiceRequest {
@@ -24,7 +29,7 @@ pub async fn fetch_post_from_individual_canister(
     agent: &Agent,
     canister_id: Principal,
     post_id: u64,
-) -> Result<Post, Box<dyn Error>> {
+) -> Result<Post, UploadError> {
     let individual_user_service = IndividualUserTemplate(canister_id, agent);
 
     let entire_post_result = individual_user_service
@@ -33,15 +38,18 @@ pub async fn fetch_post_from_individual_canister(
 
     match entire_post_result {
         Result4::Ok(post) => Ok(post),
-        Result4::Err => Err("Unauthorized".into()),
+        Result4::Err => Err(UploadError::Canister(format!(
+            "unauthorized to read post {post_id} on {canister_id}"
+        ))),
     }
 }
 
 pub async fn sync_post_with_post_service_canister_impl(
+    env: &Env,
     agent: &Agent,
     sync_post_req: SyncPostToPostServiceRequest,
     service_canister_post_mapping_client: &RedisRestClient,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), UploadError> {
     let post_from_individual_canister = fetch_post_from_individual_canister(
         agent,
         sync_post_req.canister_id,
@@ -82,7 +90,7 @@ pub async fn sync_post_with_post_service_canister_impl(
     let sync_post_from_individual_canister = PostForPostServiceSync {
         id: uuid.clone(),
         creator_principal: sync_post_req.user_principal,
-        video_uid: post_from_individual_canister.video_uid,
+        video_uid: post_from_individual_canister.video_uid.clone(),
         description: post_from_individual_canister.description,
         hashtags: post_from_individual_canister.hashtags,
         created_at: sync_post_created_at,
@@ -99,6 +107,16 @@ pub async fn sync_post_with_post_service_canister_impl(
     service_canister_post_mapping_client
         .set_value(sync_post_req.canister_id, sync_post_req.post_id, uuid)
         .await?;
+    // the mapping is keyed by the canister post, account deletion finds it through the index
+    VideoPost::new(
+        post_from_individual_canister.video_uid,
+        sync_post_req.canister_id,
+        sync_post_req.post_id.to_string(),
+        sync_post_req.user_principal,
+    )
+    .index(env)
+    .await
+    .map_err(UploadError::internal)?;
 
     Ok(())
 }
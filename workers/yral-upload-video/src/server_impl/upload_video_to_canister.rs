@@ -1,10 +1,11 @@
-use std::error::Error;
-
 use candid::Principal;
 use ic_agent::{identity::DelegatedIdentity, Agent, Identity};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use worker::{console_error, console_log};
+use worker::Env;
+use worker_utils::{
+    metrics::Metrics, search::PostDocument, trace::TraceId, trace_error, trace_log,
+};
 use yral_canisters_client::{
     ic::USER_POST_SERVICE_ID,
     individual_user_template::{
@@ -20,7 +21,14 @@ use yral_canisters_client::{
 };
 
 use crate::{
-    utils::{cloudflare_stream::CloudflareStream, events::EventService},
+    error::UploadError,
+    utils::{
+        cloudflare_stream::StreamAccounts,
+        events::{EventService, OffChainEvent},
+        moderation::{publish_gate, PublishGate},
+        upload_latency::record_draft_published,
+        video_posts::post_for_video,
+    },
     MarkPostAsPublishedRequest,
 };
 #[derive(Serialize, Deserialize)]
@@ -34,7 +42,7 @@ pub async fn upload_ai_video_to_canister_as_draft(
     user_id: Principal,
     post_id: String,
     video_uid: String,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), UploadError> {
     let user_info_service = UserInfoService(USER_INFO_SERVICE_ID, admin_ic_agent);
 
     let user_details = user_info_service.get_user_profile_details(user_id).await?;
@@ -53,86 +61,146 @@ pub async fn upload_ai_video_to_canister_as_draft(
 
         match post_service.add_post_v_1(post_details_for_frontend).await? {
             Result_::Ok => Ok(()),
-            Result_::Err(e) => Err(format!("{e:?}").into()),
+            Result_::Err(e) => Err(UploadError::Canister(format!("{e:?}"))),
         }
     } else {
-        Err("User details not found".into())
+        Err(UploadError::NotFound("User details".into()))
     }
 }
 
 pub async fn mark_post_as_published_and_emit_events(
+    env: &Env,
     admin_agent: &Agent,
+    cloudflare_stream: &StreamAccounts,
     events: &EventService,
     request: MarkPostAsPublishedRequest,
-) -> Result<(), Box<dyn Error>> {
-    let delegated_identity = DelegatedIdentity::try_from(request.delegated_identity_wire)?;
+    trace: &TraceId,
+) -> Result<(), UploadError> {
+    let delegated_identity = DelegatedIdentity::try_from(request.delegated_identity_wire)
+        .map_err(UploadError::identity)?;
 
     let user_post_service = UserPostService(USER_POST_SERVICE_ID, admin_agent);
 
-    let result = user_post_service
-        .update_post_status(request.post_id.clone(), PostStatus::Uploaded)
-        .await;
-
     let post_details = match user_post_service
         .get_individual_post_details_by_id(request.post_id.clone())
         .await?
     {
-        Result2::Ok(post_details) => Ok::<_, Box<dyn Error>>(post_details),
-        Result2::Err(e) => Err(format!("{e:?}").into()),
+        Result2::Ok(post_details) => Ok(post_details),
+        Result2::Err(e) => Err(UploadError::Canister(format!("{e:?}"))),
     }?;
 
-    let delegated_identity_principal = delegated_identity.sender()?;
+    let delegated_identity_principal =
+        delegated_identity.sender().map_err(UploadError::identity)?;
 
     if delegated_identity_principal != post_details.creator_principal {
-        return Err("Delegated identity principal does not match creator principal".into());
+        return Err(UploadError::Forbidden(
+            "Delegated identity principal does not match creator principal".into(),
+        ));
+    }
+
+    let held_status = match publish_gate(env, cloudflare_stream, &post_details.video_uid)
+        .await
+        .map_err(UploadError::stream)?
+    {
+        PublishGate::Allowed => None,
+        PublishGate::Pending => {
+            return Err(UploadError::NotReady("video is still being checked".into()))
+        }
+        PublishGate::CheckingExplicitness => Some(PostStatus::CheckingExplicitness),
+        PublishGate::Banned => Some(PostStatus::BannedForExplicitness),
+    };
+    if let Some(status) = held_status {
+        trace_log!(trace, "post {} held by moderation", request.post_id);
+        user_post_service
+            .update_post_status(request.post_id.clone(), status)
+            .await?;
+        return Err(UploadError::Forbidden(
+            "video was flagged by moderation".into(),
+        ));
     }
 
+    let result = user_post_service
+        .update_post_status(request.post_id.clone(), PostStatus::Uploaded)
+        .await;
+
     let creator_principal = delegated_identity_principal;
     let post_id = request.post_id;
 
     match result {
         Ok(()) => {
-            console_log!("video upload to canister successful");
-
+            trace_log!(trace, "video upload to canister successful");
+
+            match post_for_video(env, &post_details.video_uid).await {
+                Ok(Some(video_post)) => {
+                    record_draft_published(&Metrics::new(env, "yral-upload-video"), &video_post)
+                }
+                Ok(None) => {}
+                Err(e) => trace_error!(
+                    trace,
+                    "Error looking up post of video {}: {}",
+                    post_details.video_uid,
+                    e
+                ),
+            }
+
+            // AI drafts aren't flagged at upload, moderation removes them from search if needed
+            let post = PostDocument {
+                canister_id: USER_POST_SERVICE_ID.to_text(),
+                post_id: post_id.clone(),
+                creator_principal: creator_principal.to_text(),
+                video_uid: post_details.video_uid.clone(),
+                description: post_details.description.clone(),
+                hashtags: post_details.hashtags.clone(),
+                is_nsfw: false,
+            };
+            let _ = events.index_post(post, trace).await.inspect_err(|e| {
+                trace_error!(trace, "Error queueing post for search. Error {}", e)
+            });
+
+            let event = OffChainEvent::video_upload_successful(
+                post_details.video_uid.clone(),
+                post_details.hashtags.len(),
+                false,
+                false,
+                post_id,
+                creator_principal,
+                Principal::anonymous(),
+                String::new(),
+                None,
+            );
             let _ = events
-                .send_video_upload_successful_event(
-                    post_details.video_uid.clone(),
-                    post_details.hashtags.len(),
-                    false,
-                    false,
-                    post_id,
-                    creator_principal,
-                    Principal::anonymous(),
-                    String::new(),
-                    None,
-                )
+                .record(creator_principal, event, trace)
                 .await
                 .inspect_err(|e| {
-                    console_error!(
-                        "Error sending video successful event. Error {}",
+                    trace_error!(
+                        trace,
+                        "Error recording video successful event. Error {}",
                         e.to_string()
                     )
                 });
         }
         Err(e) => {
-            console_error!(
+            trace_error!(
+                trace,
                 "video upload to canister unsuccessful.Error {}",
                 e.to_string()
             );
+            let event = OffChainEvent::video_upload_unsuccessful(
+                e.to_string(),
+                0,
+                false,
+                false,
+                creator_principal,
+                String::new(),
+                Principal::anonymous(),
+            );
             let _ = events
-                .send_video_event_unsuccessful(
-                    e.to_string(),
-                    0,
-                    false,
-                    false,
-                    creator_principal,
-                    String::new(),
-                    Principal::anonymous(),
-                )
+                .record(creator_principal, event, trace)
                 .await
                 .inspect_err(|e| {
-                    console_error!(
-                        "Error sending video unsuccessful event. Error {}",
+                    trace_error!(
+                        trace,
+                        "Error recording video unsuccessful event. Error {}",
                         e.to_string()
                     )
                 });
@@ -142,18 +210,24 @@ pub async fn mark_post_as_published_and_emit_events(
     Ok(())
 }
 
+/// returns the canister the post was added to and its id there
 pub async fn upload_video_to_canister_impl(
     user_ic_agent: &Agent,
     admin_ic_agent: &Agent,
     post_details: PostDetailsFromFrontend,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<(Principal, String), UploadError> {
     let yral_metadata_client = yral_metadata_client::MetadataClient::default();
 
+    let user_principal = user_ic_agent
+        .get_principal()
+        .map_err(UploadError::identity)?;
     let user_details_res = yral_metadata_client
-        .get_user_metadata_v2(user_ic_agent.get_principal()?.to_string())
-        .await?;
+        .get_user_metadata_v2(user_principal.to_string())
+        .await
+        .map_err(UploadError::internal)?;
 
-    let user_details = user_details_res.ok_or("User details not found")?;
+    let user_details =
+        user_details_res.ok_or_else(|| UploadError::NotFound("User details".into()))?;
 
     if user_details.user_canister_id != USER_INFO_SERVICE_ID {
         let individual_user_service =
@@ -161,25 +235,24 @@ pub async fn upload_video_to_canister_impl(
 
         let post_id =
             upload_video_to_individual_canister(&individual_user_service, post_details).await?;
-        Ok(post_id.to_string())
+        Ok((user_details.user_canister_id, post_id.to_string()))
     } else {
-        return upload_video_to_service_canister(
+        let post_id = upload_video_to_service_canister(
             admin_ic_agent,
             PostServicePostDetailsFromFrontend {
                 hashtags: post_details.hashtags,
                 description: post_details.description,
                 video_uid: post_details.video_uid,
-                creator_principal: user_ic_agent.get_principal().map_err(|e| {
-                    console_error!("Error getting creator principal. Error {}", e);
-                    e
-                })?,
+                creator_principal: user_principal,
                 id: Uuid::new_v4().to_string(),
             },
         )
-        .await;
+        .await?;
+        Ok((USER_POST_SERVICE_ID, post_id))
     }
 }
 
+/// returns the canister the post was added to and its id there
 pub async fn upload_video(
     events: &EventService,
     video_uid: String,
@@ -187,52 +260,77 @@ pub async fn upload_video(
     admin_ic_agent: &Agent,
     post_details: PostDetailsFromFrontend,
     country: Option<String>,
-) -> Result<(), Box<dyn Error>> {
+    trace: &TraceId,
+) -> Result<(Principal, String), UploadError> {
     match upload_video_to_canister(user_ic_agent, admin_ic_agent, post_details.clone()).await {
-        Ok(post_id) => {
-            console_log!("video upload to canister successful");
-
+        Ok((canister_id, post_id)) => {
+            trace_log!(trace, "video upload to canister successful");
+
+            let user_principal = user_ic_agent
+                .get_principal()
+                .map_err(UploadError::identity)?;
+            let post = PostDocument {
+                canister_id: canister_id.to_text(),
+                post_id: post_id.clone(),
+                creator_principal: user_principal.to_text(),
+                video_uid: video_uid.clone(),
+                description: post_details.description.clone(),
+                hashtags: post_details.hashtags.clone(),
+                is_nsfw: post_details.is_nsfw,
+            };
+            let _ = events.index_post(post, trace).await.inspect_err(|e| {
+                trace_error!(trace, "Error queueing post for search. Error {}", e)
+            });
+
+            let event = OffChainEvent::video_upload_successful(
+                video_uid,
+                post_details.hashtags.len(),
+                post_details.is_nsfw,
+                post_details.creator_consent_for_inclusion_in_hot_or_not,
+                post_id.clone(),
+                user_principal,
+                Principal::anonymous(),
+                String::new(),
+                country,
+            );
             let _ = events
-                .send_video_upload_successful_event(
-                    video_uid,
-                    post_details.hashtags.len(),
-                    post_details.is_nsfw,
-                    post_details.creator_consent_for_inclusion_in_hot_or_not,
-                    post_id,
-                    user_ic_agent.get_principal()?,
-                    Principal::anonymous(),
-                    String::new(),
-                    country,
-                )
+                .record(user_principal, event, trace)
                 .await
                 .inspect_err(|e| {
-                    console_error!(
-                        "Error sending video successful event. Error {}",
+                    trace_error!(
+                        trace,
+                        "Error recording video successful event. Error {}",
                         e.to_string()
                     )
                 });
 
-            Ok(())
+            Ok((canister_id, post_id))
         }
         Err(e) => {
-            console_error!(
+            trace_error!(
+                trace,
                 "video upload to canister unsuccessful.Error {}",
                 e.to_string()
             );
+            let user_principal = user_ic_agent
+                .get_principal()
+                .map_err(UploadError::identity)?;
+            let event = OffChainEvent::video_upload_unsuccessful(
+                e.to_string(),
+                post_details.hashtags.len(),
+                post_details.is_nsfw,
+                post_details.creator_consent_for_inclusion_in_hot_or_not,
+                user_principal,
+                String::new(),
+                Principal::anonymous(),
+            );
             let _ = events
-                .send_video_event_unsuccessful(
-                    e.to_string(),
-                    post_details.hashtags.len(),
-                    post_details.is_nsfw,
-                    post_details.creator_consent_for_inclusion_in_hot_or_not,
-                    user_ic_agent.get_principal()?,
-                    String::new(),
-                    Principal::anonymous(),
-                )
+                .record(user_principal, event, trace)
                 .await
                 .inspect_err(|e| {
-                    console_error!(
-                        "Error sending video unsuccessful event. Error {}",
+                    trace_error!(
+                        trace,
+                        "Error recording video unsuccessful event. Error {}",
                         e.to_string()
                     )
                 });
@@ -246,38 +344,38 @@ async fn upload_video_to_canister(
     user_ic_agent: &Agent,
     admin_ic_agent: &Agent,
     post_details: PostDetailsFromFrontend,
-) -> Result<String, Box<dyn Error>> {
-    let post_id =
-        upload_video_to_canister_impl(user_ic_agent, admin_ic_agent, post_details).await?;
-
-    Ok(post_id)
+) -> Result<(Principal, String), UploadError> {
+    upload_video_to_canister_impl(user_ic_agent, admin_ic_agent, post_details).await
 }
 
 pub async fn mark_video_as_downloadable(
-    cloudflare_stream: &CloudflareStream,
+    cloudflare_stream: &StreamAccounts,
     video_uid: &str,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), UploadError> {
     cloudflare_stream
+        .for_video(video_uid)
+        .await
+        .map_err(UploadError::stream)?
         .mark_video_as_downloadable(video_uid)
-        .await?;
-    Ok(())
+        .await
+        .map_err(UploadError::stream)
 }
 
 async fn upload_video_to_individual_canister(
     individual_user_canister: &IndividualUserCanisterService<'_>,
     post_details: PostDetailsFromFrontend,
-) -> Result<u64, Box<dyn Error>> {
+) -> Result<u64, UploadError> {
     let result = individual_user_canister.add_post_v_2(post_details).await?;
     match result {
         AddPostResult::Ok(post_id) => Ok(post_id),
-        AddPostResult::Err(err) => Err(err.into()),
+        AddPostResult::Err(err) => Err(UploadError::Canister(err)),
     }
 }
 
 async fn upload_video_to_service_canister(
     admin_ic_agent: &Agent,
     post_details: PostServicePostDetailsFromFrontend,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, UploadError> {
     let user_info_service = UserInfoService(USER_INFO_SERVICE_ID, admin_ic_agent);
 
     let user_details = user_info_service
@@ -300,9 +398,9 @@ async fn upload_video_to_service_canister(
 
         match result {
             Result_::Ok => Ok(post_id),
-            Result_::Err(e) => Err(format!("{e:?}").into()),
+            Result_::Err(e) => Err(UploadError::Canister(format!("{e:?}"))),
         }
     } else {
-        Err("User details not found".into())
+        Err(UploadError::NotFound("User details".into()))
     }
 }
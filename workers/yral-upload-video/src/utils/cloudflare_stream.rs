@@ -1,8 +1,9 @@
 use std::{collections::HashMap, error::Error, ops::Add, time::Duration};
 
 use axum::http::{header, HeaderMap};
+use base64::{prelude::BASE64_STANDARD, Engine};
 use chrono::DateTime;
-use ic_agent::export::reqwest;
+use ic_agent::export::reqwest::{self, StatusCode};
 use serde::{Deserialize, Serialize};
 use worker::{Date, Url};
 
@@ -11,7 +12,13 @@ use crate::utils::types::{
     POST_ID, USER_ID,
 };
 
-use super::types::{CreateDownloadResult, CreateDownloads, DirectUploadResult, Video};
+use super::http_retry::send_with_retry;
+use super::types::{
+    CreateDownloadResult, CreateDownloads, DirectUploadResult, TusUploadResult, Video,
+};
+
+const TUS_RESUMABLE_HEADER: &str = "Tus-Resumable";
+const TUS_VERSION: &str = "1.0.0";
 
 #[derive(Clone)]
 pub struct CloudflareStream {
@@ -21,6 +28,15 @@ pub struct CloudflareStream {
 
 impl CloudflareStream {
     pub fn new(account_id: String, api_token: String) -> Result<Self, Box<dyn Error>> {
+        let base_url = Url::parse(&format!(
+            "https://api.cloudflare.com/client/v4/accounts/{account_id}/stream/"
+        ))?;
+
+        Self::with_base_url(base_url, api_token)
+    }
+
+    /// a Stream compatible API at `base_url`, e.g. the e2e harness's mock backend
+    pub fn with_base_url(base_url: Url, api_token: String) -> Result<Self, Box<dyn Error>> {
         let mut headers = HeaderMap::new();
         headers.insert(
             header::AUTHORIZATION,
@@ -29,9 +45,6 @@ impl CloudflareStream {
         let client = reqwest::ClientBuilder::new()
             .default_headers(headers)
             .build()?;
-        let base_url = Url::parse(&format!(
-            "https://api.cloudflare.com/client/v4/accounts/{account_id}/stream/"
-        ))?;
 
         Ok(Self { base_url, client })
     }
@@ -55,7 +68,8 @@ impl CloudflareStream {
             max_duration_seconds: Duration::from_secs(60).as_secs(),
             ..Default::default()
         };
-        let response = self.client.post(url).json(&request_data).send().await?;
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&request_data)).await?;
         let response_data: DirectUploadResponseType = response.json().await?;
 
         if response_data.success {
@@ -88,6 +102,7 @@ impl CloudflareStream {
     pub async fn get_upload_url_for_ai_draft_video(
         &self,
         user_principal: String,
+        watermark_uid: Option<&str>,
     ) -> Result<DirectUploadResult, Box<dyn Error>> {
         type DirectUploadResponseType = StreamResponseType<DirectUploadResult>;
         let url = Url::join(&self.base_url, "direct_upload")?;
@@ -101,9 +116,13 @@ impl CloudflareStream {
                     .into_iter()
                     .collect(),
             ),
+            watermark: watermark_uid.map(|uid| WatermarkRequest {
+                uid: Some(uid.to_owned()),
+            }),
             ..Default::default()
         };
-        let response = self.client.post(url).json(&request_data).send().await?;
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&request_data)).await?;
         let response_data: DirectUploadResponseType = response.json().await?;
 
         if response_data.success {
@@ -133,7 +152,11 @@ impl CloudflareStream {
         }
     }
 
-    pub async fn get_upload_url_v2(&self) -> Result<DirectUploadResult, Box<dyn Error>> {
+    /// renditions carry the `watermark_uid` profile if set
+    pub async fn get_upload_url_v2(
+        &self,
+        watermark_uid: Option<&str>,
+    ) -> Result<DirectUploadResult, Box<dyn Error>> {
         type DirectUploadResponseType = StreamResponseType<DirectUploadResult>;
         let url = Url::join(&self.base_url, "direct_upload")?;
 
@@ -147,9 +170,13 @@ impl CloudflareStream {
                 scheduled_deletion.format("%Y-%m-%dT%H:%M:%SZ")
             )),
             max_duration_seconds: Duration::from_secs(60).as_secs(),
+            watermark: watermark_uid.map(|uid| WatermarkRequest {
+                uid: Some(uid.to_owned()),
+            }),
             ..Default::default()
         };
-        let response = self.client.post(url).json(&request_data).send().await?;
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&request_data)).await?;
         let response_data: DirectUploadResponseType = response.json().await?;
 
         if response_data.success {
@@ -179,10 +206,71 @@ impl CloudflareStream {
         }
     }
 
+    /// creates a tus upload session for a file of `upload_length` bytes
+    ///
+    /// carries the same limits as `get_upload_url`, Stream only accepts them as `Upload-Metadata`
+    pub async fn create_tus_upload(
+        &self,
+        upload_length: u64,
+    ) -> Result<TusUploadResult, Box<dyn Error>> {
+        let mut url = self.base_url.clone();
+        url.set_path(self.base_url.path().trim_end_matches('/'));
+        url.query_pairs_mut().append_pair("direct_user", "true");
+
+        let scheduled_deletion = DateTime::from_timestamp_millis(Date::now().as_millis() as i64)
+            .ok_or("invalid system date")?
+            .add(Duration::from_secs(60 * 60 * 24 * 30)) // 30 days
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string();
+
+        let upload_metadata = [
+            ("maxdurationseconds", "60"),
+            ("scheduleddeletion", scheduled_deletion.as_str()),
+            ("watermark", CF_WATERMARK_UID),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{key} {}", BASE64_STANDARD.encode(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+
+        let response = send_with_retry(|| {
+            self.client
+                .post(url.clone())
+                .header(TUS_RESUMABLE_HEADER, TUS_VERSION)
+                .header("Upload-Length", upload_length)
+                .header("Upload-Metadata", upload_metadata.clone())
+        })
+        .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("tus upload creation failed with {status}: {body}").into());
+        }
+
+        let response_header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+                .ok_or(format!("{name} header not found"))
+        };
+
+        Ok(TusUploadResult {
+            uid: response_header("stream-media-id")?,
+            upload_url: response_header(header::LOCATION.as_str())?,
+            upload_offset: 0,
+            upload_length,
+            tus_resumable: TUS_VERSION.to_string(),
+            scheduled_deletion: Some(scheduled_deletion),
+        })
+    }
+
     pub async fn get_video_details(&self, video_uid: &str) -> Result<Video, Box<dyn Error>> {
         let url = Url::join(&self.base_url, video_uid)?;
 
-        let response = self.client.get(url).send().await?;
+        let response = send_with_retry(|| self.client.get(url.clone())).await?;
 
         let response_data: StreamResponseType<Video> = response.json().await?;
 
@@ -194,6 +282,54 @@ impl CloudflareStream {
         }
     }
 
+    /// `None` if the video isn't on this account
+    pub async fn find_video(&self, video_uid: &str) -> Result<Option<Video>, Box<dyn Error>> {
+        let url = Url::join(&self.base_url, video_uid)?;
+
+        let response = send_with_retry(|| self.client.get(url.clone())).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let response_data: StreamResponseType<Video> = response.json().await?;
+
+        if response_data.success {
+            Ok(response_data.result)
+        } else {
+            let error = response_data.errors.first().ok_or("Unknown error")?;
+            Err(format!("{} {}", error.code, error.message).into())
+        }
+    }
+
+    /// videos in `status` created between `after` and `before` (RFC 3339), oldest first
+    ///
+    /// Stream returns at most 1000 videos per call
+    pub async fn list_videos(
+        &self,
+        status: &str,
+        after: &str,
+        before: &str,
+    ) -> Result<Vec<Video>, Box<dyn Error>> {
+        let mut url = self.base_url.clone();
+        url.set_path(self.base_url.path().trim_end_matches('/'));
+        url.query_pairs_mut()
+            .append_pair("status", status)
+            .append_pair("after", after)
+            .append_pair("before", before)
+            .append_pair("asc", "true");
+
+        let response = send_with_retry(|| self.client.get(url.clone())).await?;
+
+        let response_data: StreamResponseType<Vec<Video>> = response.json().await?;
+
+        if response_data.success {
+            Ok(response_data.result.unwrap_or_default())
+        } else {
+            let error = response_data.errors.first().ok_or("Unknown error")?;
+            Err(format!("{} {}", error.code, error.message).into())
+        }
+    }
+
     pub async fn add_meta_to_video(
         &self,
         video_uid: &str,
@@ -215,15 +351,12 @@ impl CloudflareStream {
             video: Option<Video>,
         }
 
-        let response = self
-            .client
-            .post(url)
-            .json(&EditVideoRequestType {
-                meta,
-                scheduled_deletion: None,
-            })
-            .send()
-            .await?;
+        let request_data = EditVideoRequestType {
+            meta,
+            scheduled_deletion: None,
+        };
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&request_data)).await?;
 
         let response_data: EditVideoResponseType = response.json().await?;
 
@@ -253,15 +386,74 @@ impl CloudflareStream {
         }
     }
 
+    /// picks the frame at `pct` (0 to 1) of the video's duration as its thumbnail
+    pub async fn set_thumbnail_timestamp_pct(
+        &self,
+        video_uid: &str,
+        pct: f32,
+    ) -> Result<(), Box<dyn Error>> {
+        let url = Url::join(&self.base_url, video_uid)?;
+
+        #[derive(Serialize)]
+        struct EditThumbnailRequestType {
+            #[serde(rename = "thumbnailTimestampPct")]
+            thumbnail_timestamp_pct: f32,
+        }
+
+        let request_data = EditThumbnailRequestType {
+            thumbnail_timestamp_pct: pct,
+        };
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&request_data)).await?;
+
+        let response_data: StreamResponseType<Video> = response.json().await?;
+
+        if response_data.success {
+            Ok(())
+        } else {
+            let error = response_data.errors.first().ok_or("Unknown error")?;
+            Err(format!("{} {}", error.code, error.message).into())
+        }
+    }
+
+    /// a signed token playing `video_uid` until `expires_at` (unix seconds), even if it requires
+    /// signed URLs
+    pub async fn create_playback_token(
+        &self,
+        video_uid: &str,
+        expires_at: u64,
+    ) -> Result<String, Box<dyn Error>> {
+        let url = Url::join(&self.base_url, &format!("{video_uid}/token"))?;
+
+        #[derive(Serialize)]
+        struct TokenRequestType {
+            exp: u64,
+        }
+
+        #[derive(Deserialize)]
+        struct TokenResult {
+            token: String,
+        }
+
+        let request_data = TokenRequestType { exp: expires_at };
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&request_data)).await?;
+
+        let response_data: StreamResponseType<TokenResult> = response.json().await?;
+
+        if response_data.success {
+            Ok(response_data.result.ok_or("token not found")?.token)
+        } else {
+            let error = response_data.errors.first().ok_or("Unknown error")?;
+            Err(format!("{} {}", error.code, error.message).into())
+        }
+    }
+
     pub async fn mark_video_as_downloadable(&self, video_uid: &str) -> Result<(), Box<dyn Error>> {
         let url = Url::join(&self.base_url, &format!("{video_uid}/downloads"))?;
 
-        let response = self
-            .client
-            .post(url)
-            .json(&CreateDownloads {})
-            .send()
-            .await?;
+        let response =
+            send_with_retry(|| self.client.post(url.clone()).json(&CreateDownloads {})).await?;
 
         let response_data: StreamResponseType<CreateDownloadResult> = response.json().await?;
 
@@ -291,3 +483,52 @@ impl CloudflareStream {
         }
     }
 }
+
+/// the Stream accounts in use while videos are migrated from `secondary` to `primary`
+///
+/// new uploads always go to `primary`
+#[derive(Clone)]
+pub struct StreamAccounts {
+    pub primary: CloudflareStream,
+    pub secondary: Option<CloudflareStream>,
+}
+
+impl StreamAccounts {
+    pub fn new(primary: CloudflareStream, secondary: Option<CloudflareStream>) -> Self {
+        Self { primary, secondary }
+    }
+
+    /// the account holding `video_uid`, `primary` if neither does
+    pub async fn for_video(&self, video_uid: &str) -> Result<&CloudflareStream, Box<dyn Error>> {
+        let Some(secondary) = &self.secondary else {
+            return Ok(&self.primary);
+        };
+        if self.primary.find_video(video_uid).await?.is_some() {
+            return Ok(&self.primary);
+        }
+        if secondary.find_video(video_uid).await?.is_some() {
+            return Ok(secondary);
+        }
+
+        Ok(&self.primary)
+    }
+
+    /// the video from whichever account holds it
+    pub async fn get_video_details(&self, video_uid: &str) -> Result<Video, Box<dyn Error>> {
+        let Some(secondary) = &self.secondary else {
+            return self.primary.get_video_details(video_uid).await;
+        };
+        if let Some(video) = self.primary.find_video(video_uid).await? {
+            return Ok(video);
+        }
+
+        secondary
+            .find_video(video_uid)
+            .await?
+            .ok_or_else(|| format!("video {video_uid} not found").into())
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &CloudflareStream> {
+        std::iter::once(&self.primary).chain(self.secondary.as_ref())
+    }
+}
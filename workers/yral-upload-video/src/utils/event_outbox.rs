@@ -0,0 +1,93 @@
+use std::cell::RefCell;
+
+use worker::*;
+use worker_utils::{
+    api_error::error_resp, outbox::Outbox, retry::RetryPolicy, storage::SafeStorage,
+    time::now_millis,
+};
+
+use crate::utils::events::{EventSink, OffChainEvent};
+
+pub const EVENT_OUTBOX: &str = "EVENT_OUTBOX";
+
+/// delivery attempts before an event is dead lettered, backing off up to an hour
+const EVENT_DELIVERY_POLICY: RetryPolicy = RetryPolicy::new(12).with_delays(5_000, 3_600_000);
+
+/// off chain events of a single user, kept until the off chain agent accepts them
+#[durable_object]
+pub struct EventOutbox {
+    state: State,
+    env: Env,
+    outbox: RefCell<Outbox<OffChainEvent>>,
+}
+
+// SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+// because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+#[allow(clippy::await_holding_refcell_ref)]
+impl EventOutbox {
+    fn storage(&self) -> SafeStorage {
+        self.state.storage().into()
+    }
+
+    /// moves the alarm earlier if `at` (unix millis) is before the scheduled one
+    async fn schedule_drain(&self, at: u64) -> Result<()> {
+        let storage = self.state.storage();
+        let at = at as i64;
+        if let Some(scheduled) = storage.get_alarm().await? {
+            if scheduled <= at {
+                return Ok(());
+            }
+        }
+        let offset = (at - now_millis() as i64).max(0);
+
+        storage.set_alarm(offset).await
+    }
+
+    async fn record(&self, event: OffChainEvent) -> Result<()> {
+        let mut storage = self.storage();
+        self.outbox.borrow_mut().record(&mut storage, event).await?;
+
+        self.schedule_drain(now_millis()).await
+    }
+}
+
+// SAFETY: See comment on first impl block for safety rationale
+#[allow(clippy::await_holding_refcell_ref)]
+impl DurableObject for EventOutbox {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            state,
+            env,
+            outbox: RefCell::new(Outbox::new("events", EVENT_DELIVERY_POLICY)),
+        }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        if req.method() != Method::Post || req.path() != "/record" {
+            return error_resp("not found", 404);
+        }
+
+        let event: OffChainEvent = req.json().await?;
+        self.record(event).await?;
+
+        Response::from_json(&())
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        let sink =
+            EventSink::with_auth_token(self.env.secret("OFF_CHAIN_GRPC_AUTH_TOKEN")?.to_string());
+        let mut storage = self.storage();
+        let report = self.outbox.borrow_mut().drain(&mut storage, &sink).await?;
+        if report.dead > 0 {
+            console_error!(
+                "dead lettered {} off chain events after repeated failures",
+                report.dead
+            );
+        }
+        if let Some(at) = report.next_attempt_at {
+            self.schedule_drain(at).await?;
+        }
+
+        Response::ok("done")
+    }
+}
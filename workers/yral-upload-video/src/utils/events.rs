@@ -1,12 +1,22 @@
 use axum::http::{HeaderMap, HeaderValue};
 use candid::Principal;
 use ic_agent::export::reqwest::{header, Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::error::Error;
 use tonic::metadata::MetadataValue;
 use tonic_web_wasm_client::Client as GrpcClient;
 use warehouse_event::warehouse_events_client::WarehouseEventsClient;
-use worker::Url;
+use worker::{Env, Url};
+use worker_utils::{
+    do_client::DoClient,
+    outbox::OutboxSink,
+    search::{PostDocument, SearchIndexEvent},
+    time::now_millis,
+    trace::{TraceId, TRACE_HEADER},
+};
+
+use crate::utils::event_outbox::EVENT_OUTBOX;
 
 pub mod warehouse_event {
     include!(concat!(env!("OUT_DIR"), "/warehouse_events.rs"));
@@ -18,32 +28,20 @@ pub struct Warehouse {
     off_chain_agent_grpc_auth_token: String,
 }
 
-#[derive(Clone)]
-pub struct EventService {
-    base_url: Url,
-    reqwest_client: Client,
+/// an event for the off chain agent's `api/v2/events`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OffChainEvent {
+    pub event: String,
+    /// json encoded
+    pub params: String,
+    /// trace of the request that caused the event, forwarded to the off chain agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<TraceId>,
 }
 
-impl EventService {
-    pub fn with_auth_token(auth_token: String) -> Self {
-        let base_url = "https://offchain.yral.com/";
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            header::AUTHORIZATION,
-            HeaderValue::from_str(format!("Bearer {}", &auth_token).as_ref()).unwrap(),
-        );
-        Self {
-            reqwest_client: ClientBuilder::new()
-                .default_headers(headers)
-                .build()
-                .expect("Invalid event service client config"),
-            base_url: Url::parse(base_url).unwrap(),
-        }
-    }
-
+impl OffChainEvent {
     #[allow(clippy::too_many_arguments)]
-    pub async fn send_video_upload_successful_event(
-        &self,
+    pub fn video_upload_successful(
         video_uid: String,
         hashtags_len: usize,
         is_nsfw: bool,
@@ -53,7 +51,7 @@ impl EventService {
         canister_id: Principal,
         user_name: String,
         country: Option<String>,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Self {
         let params = json!({
             "user_id": user_principal,
             "publisher_user_id": user_principal,
@@ -70,33 +68,15 @@ impl EventService {
         })
         .to_string();
 
-        let path = "api/v2/events";
-
-        let response = self
-            .reqwest_client
-            .post(self.base_url.join(path).unwrap())
-            .json(&json!({
-                "event": "video_upload_successful".to_owned(),
-                "params": params
-            }))
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            Ok(())
-        } else {
-            let status = response.status();
-            let error = response.text().await?;
-            Err(
-                format!("error sending video_upload_successful event. Error {status} {error}",)
-                    .into(),
-            )
+        Self {
+            event: "video_upload_successful".to_owned(),
+            params,
+            trace_id: None,
         }
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub async fn send_video_event_unsuccessful(
-        &self,
+    pub fn video_upload_unsuccessful(
         error: String,
         hashtags_len: usize,
         is_nsfw: bool,
@@ -104,7 +84,7 @@ impl EventService {
         user_principal: Principal,
         user_name: String,
         user_canister: Principal,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Self {
         let params = json!({
             "user_id": user_principal,
             "display_name": user_name,
@@ -117,31 +97,123 @@ impl EventService {
         })
         .to_string();
 
+        Self {
+            event: "video_upload_unsuccessful".to_owned(),
+            params,
+            trace_id: None,
+        }
+    }
+}
+
+/// records events in the user's `EventOutbox`, which delivers them to the off chain agent
+#[derive(Clone)]
+pub struct EventService {
+    env: Env,
+}
+
+impl EventService {
+    pub fn new(env: Env) -> Self {
+        Self { env }
+    }
+
+    pub async fn record(
+        &self,
+        user_principal: Principal,
+        mut event: OffChainEvent,
+        trace: &TraceId,
+    ) -> Result<(), Box<dyn Error>> {
+        event.trace_id = Some(trace.clone());
+        DoClient::new(&self.env)
+            .with_trace(trace)
+            .call::<_, ()>(EVENT_OUTBOX, &user_principal.to_text(), "record", &event)
+            .await?
+            .map_err(|(status, e)| {
+                format!(
+                    "error recording {} event. Error {status} {}",
+                    event.event, e.message
+                )
+            })?;
+
+        Ok(())
+    }
+
+    /// queues the published post for `yral-search`
+    pub async fn index_post(
+        &self,
+        post: PostDocument,
+        trace: &TraceId,
+    ) -> Result<(), Box<dyn Error>> {
+        let event = SearchIndexEvent::PostPublished {
+            post,
+            at: now_millis(),
+            trace_id: Some(trace.clone()),
+        };
+        event.send(&self.env).await?;
+
+        Ok(())
+    }
+}
+
+/// posts events to the off chain agent, used by `EventOutbox` to drain
+pub struct EventSink {
+    base_url: Url,
+    reqwest_client: Client,
+}
+
+impl EventSink {
+    pub fn with_auth_token(auth_token: String) -> Self {
+        let base_url = "https://offchain.yral.com/";
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(format!("Bearer {}", &auth_token).as_ref()).unwrap(),
+        );
+        Self {
+            reqwest_client: ClientBuilder::new()
+                .default_headers(headers)
+                .build()
+                .expect("Invalid event service client config"),
+            base_url: Url::parse(base_url).unwrap(),
+        }
+    }
+
+    pub async fn send_event(&self, event: &OffChainEvent) -> Result<(), Box<dyn Error>> {
         let path = "api/v2/events";
 
-        let response = self
+        let mut request = self
             .reqwest_client
             .post(self.base_url.join(path).unwrap())
             .json(&json!({
-                "event": "video_upload_unsuccessful".to_owned(),
-                "params": params
-            }))
-            .send()
-            .await?;
+                "event": event.event,
+                "params": event.params
+            }));
+        if let Some(trace) = &event.trace_id {
+            request = request.header(TRACE_HEADER, trace.as_str());
+        }
+        let response = request.send().await?;
 
         if response.status().is_success() {
             Ok(())
         } else {
             let status = response.status();
             let error = response.text().await?;
-            Err(
-                format!("error sending video_upload_successful event. Error {status} {error}",)
-                    .into(),
+            Err(format!(
+                "error sending {} event. Error {status} {error}",
+                event.event
             )
+            .into())
         }
     }
 }
 
+impl OutboxSink<OffChainEvent> for EventSink {
+    async fn deliver(&self, event: &OffChainEvent) -> worker::Result<()> {
+        self.send_event(event)
+            .await
+            .map_err(|e| worker::Error::RustError(e.to_string()))
+    }
+}
+
 impl Warehouse {
     pub fn with_auth_token(auth_token: String) -> Self {
         let off_chain_agent_url = "https://pr-161-yral-dapp-off-chain-agent.fly.dev:443";
@@ -0,0 +1,160 @@
+//! upload queue messages that kept failing, dead lettered with the reason of their last failure
+//!
+//! the record in `FAILED_UPLOADS` keeps the message so ops can re-drive it onto the upload
+//! queue once the cause is fixed
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+use worker_utils::{time::now_millis, trace::Traced, trace_error};
+
+use crate::UploadVideoQueueMessage;
+
+pub const FAILED_UPLOADS_KV: &str = "FAILED_UPLOADS";
+pub const UPLOAD_VIDEO_DLQ: &str = "UPLOAD_VIDEO_DLQ";
+
+/// deliveries before a message is dead lettered, `max_retries` in wrangler.toml must be higher
+pub const MAX_UPLOAD_ATTEMPTS: u32 = 10;
+/// failure records outlive the DLQ's own retention
+const FAILED_UPLOAD_TTL_SECS: u64 = 14 * 24 * 3600;
+const FAILED_UPLOAD_PREFIX: &str = "failed-upload:";
+
+pub const DEFAULT_FAILED_UPLOADS_LIMIT: usize = 20;
+pub const MAX_FAILED_UPLOADS_LIMIT: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FailedUpload {
+    /// id of the queue message
+    pub id: String,
+    pub kind: String,
+    pub video_uid: Option<String>,
+    pub reason: String,
+    pub attempts: u32,
+    /// unix millis
+    pub failed_at: u64,
+    /// not listed, it may carry the uploader's delegated identity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<Traced<UploadVideoQueueMessage>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FailedUploadsPage {
+    pub failed_uploads: Vec<FailedUpload>,
+    pub cursor: Option<String>,
+}
+
+fn failed_upload_key(id: &str) -> String {
+    format!("{FAILED_UPLOAD_PREFIX}{id}")
+}
+
+/// settles failed deliveries, retrying them until `MAX_UPLOAD_ATTEMPTS`
+pub struct DeadLetters {
+    dlq: Queue,
+    failures: KvStore,
+}
+
+impl DeadLetters {
+    pub fn new(env: &Env) -> Result<Self> {
+        Ok(Self {
+            dlq: env.queue(UPLOAD_VIDEO_DLQ)?,
+            failures: env.kv(FAILED_UPLOADS_KV)?,
+        })
+    }
+
+    /// retries `message`, or dead letters it with `reason` once it's out of attempts
+    pub async fn retry(&self, message: &Message<Traced<UploadVideoQueueMessage>>, reason: &str) {
+        if message.attempts() < MAX_UPLOAD_ATTEMPTS {
+            message.retry();
+            return;
+        }
+
+        let trace = message.body().trace();
+        match self.dead_letter(message, reason).await {
+            Ok(()) => {
+                trace_error!(
+                    trace,
+                    "dead lettered {} message {} after {} attempts: {reason}",
+                    message.body().body.kind(),
+                    message.id(),
+                    message.attempts()
+                );
+                message.ack();
+            }
+            Err(e) => {
+                trace_error!(trace, "failed to dead letter message {}: {e}", message.id());
+                message.retry();
+            }
+        }
+    }
+
+    async fn dead_letter(
+        &self,
+        message: &Message<Traced<UploadVideoQueueMessage>>,
+        reason: &str,
+    ) -> Result<()> {
+        let body = message.body();
+        let record = FailedUpload {
+            id: message.id(),
+            kind: body.body.kind().to_string(),
+            video_uid: body.body.video_uid().map(String::from),
+            reason: reason.to_string(),
+            attempts: message.attempts(),
+            failed_at: now_millis(),
+            message: Some(body.clone()),
+        };
+        self.failures
+            .put(&failed_upload_key(&record.id), &record)?
+            .expiration_ttl(FAILED_UPLOAD_TTL_SECS)
+            .execute()
+            .await?;
+
+        self.dlq.send(body).await
+    }
+}
+
+/// newest records aren't necessarily first, KV lists keys alphabetically
+pub async fn list_failed_uploads(
+    env: &Env,
+    cursor: Option<String>,
+    limit: usize,
+) -> Result<FailedUploadsPage> {
+    let kv = env.kv(FAILED_UPLOADS_KV)?;
+    let mut list = kv
+        .list()
+        .prefix(FAILED_UPLOAD_PREFIX.to_string())
+        .limit(limit.clamp(1, MAX_FAILED_UPLOADS_LIMIT) as u64);
+    if let Some(cursor) = cursor {
+        list = list.cursor(cursor);
+    }
+    let page = list.execute().await?;
+
+    let mut failed_uploads = Vec::with_capacity(page.keys.len());
+    for key in &page.keys {
+        let record = kv.get(&key.name).json::<FailedUpload>().await?;
+        // expired between the list and the read
+        let Some(mut record) = record else {
+            continue;
+        };
+        record.message = None;
+        failed_uploads.push(record);
+    }
+
+    Ok(FailedUploadsPage {
+        failed_uploads,
+        cursor: page.cursor.filter(|_| !page.list_complete),
+    })
+}
+
+/// puts the dead lettered message back on the upload queue, `false` if there's no such record
+pub async fn redrive_failed_upload_impl(env: &Env, upload_queue: &Queue, id: &str) -> Result<bool> {
+    let kv = env.kv(FAILED_UPLOADS_KV)?;
+    let key = failed_upload_key(id);
+    let record = kv.get(&key).json::<FailedUpload>().await?;
+    let Some(message) = record.and_then(|r| r.message) else {
+        return Ok(false);
+    };
+
+    upload_queue.send(message).await?;
+    kv.delete(&key).await?;
+
+    Ok(true)
+}
@@ -0,0 +1,34 @@
+use reqwest::{RequestBuilder, Response, StatusCode};
+use worker_utils::retry::{with_backoff, RetryPolicy};
+
+/// dedupes repeated attempts of a call that isn't idempotent
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// sends the request built by `build`, retrying transport errors, 429s and 5xxs
+/// other responses are returned as is for the caller to inspect
+///
+/// only for calls that are safe to repeat, anything else goes through `send_with_dedupe_key`
+pub async fn send_with_retry<F>(build: F) -> Result<Response, reqwest::Error>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let build = &build;
+    with_backoff(RetryPolicy::default(), || async move {
+        let res = build().send().await?;
+        let status = res.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return res.error_for_status();
+        }
+
+        Ok(res)
+    })
+    .await
+}
+
+/// `send_with_retry` with every attempt carrying `key`, so the receiver applies the call once
+pub async fn send_with_dedupe_key<F>(key: &str, build: F) -> Result<Response, reqwest::Error>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_with_retry(|| build().header(IDEMPOTENCY_KEY_HEADER, key)).await
+}
@@ -1,7 +1,13 @@
 pub mod cloudflare_stream;
+pub mod event_outbox;
 pub mod events;
-pub mod notification_client;
+pub mod failed_uploads;
+pub mod http_retry;
+pub mod moderation;
 pub mod service_canister_post_mapping_redis_rest_client;
 pub mod storj_interface;
 pub mod types;
+pub mod upload_latency;
+pub mod upload_url_limiter;
 pub mod user_ic_agent;
+pub mod video_posts;
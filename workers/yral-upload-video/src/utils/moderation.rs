@@ -0,0 +1,170 @@
+//! NSFW gate between a ready video and its publication
+//!
+//! `ModerateVideo` messages score the video with the service at `MODERATION_SERVICE_URL` and
+//! keep the verdict in the video's Stream meta, `mark_post_as_published` refuses flagged ones.
+//! without `MODERATION_SERVICE_URL` the gate is off and every post can be published
+
+use std::error::Error;
+
+use ic_agent::export::reqwest;
+use serde::{Deserialize, Serialize};
+use worker::Env;
+use worker_utils::time::now_millis;
+
+use super::{
+    cloudflare_stream::{CloudflareStream, StreamAccounts},
+    types::Video,
+};
+
+pub const MODERATION_VERDICT_KEY: &str = "moderation-verdict";
+
+const MODERATION_SERVICE_URL_VAR: &str = "MODERATION_SERVICE_URL";
+/// optional bearer token for the moderation service
+const MODERATION_SERVICE_TOKEN: &str = "MODERATION_SERVICE_TOKEN";
+
+/// scores at or above are held for review
+const NSFW_FLAG_THRESHOLD: f64 = 0.7;
+/// scores at or above are banned outright
+const NSFW_BAN_THRESHOLD: f64 = 0.95;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModerationVerdict {
+    /// 0 to 1
+    pub nsfw_score: f64,
+    /// an admin allowed the video regardless of its score
+    #[serde(default)]
+    pub overridden: bool,
+    /// unix millis
+    pub at: u64,
+}
+
+/// what publishing a post with this verdict should do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishGate {
+    Allowed,
+    /// not scored yet
+    Pending,
+    CheckingExplicitness,
+    Banned,
+}
+
+impl ModerationVerdict {
+    pub fn new(nsfw_score: f64) -> Self {
+        Self {
+            nsfw_score,
+            overridden: false,
+            at: now_millis(),
+        }
+    }
+
+    /// an admin's verdict, keeping the score if the video was scored
+    pub fn overridden(previous: Option<Self>) -> Self {
+        Self {
+            overridden: true,
+            at: now_millis(),
+            ..previous.unwrap_or_else(|| Self::new(0.0))
+        }
+    }
+
+    pub fn is_flagged(&self) -> bool {
+        !self.overridden && self.nsfw_score >= NSFW_FLAG_THRESHOLD
+    }
+
+    pub fn gate(&self) -> PublishGate {
+        if !self.is_flagged() {
+            PublishGate::Allowed
+        } else if self.nsfw_score >= NSFW_BAN_THRESHOLD {
+            PublishGate::Banned
+        } else {
+            PublishGate::CheckingExplicitness
+        }
+    }
+}
+
+pub fn verdict_of(video: &Video) -> Option<ModerationVerdict> {
+    let verdict = video.meta.as_ref()?.get(MODERATION_VERDICT_KEY)?;
+    serde_json::from_str(verdict).ok()
+}
+
+/// the publish gate of `video_uid`, always `Allowed` while moderation is off
+pub async fn publish_gate(
+    env: &Env,
+    cloudflare_stream: &StreamAccounts,
+    video_uid: &str,
+) -> Result<PublishGate, Box<dyn Error>> {
+    if !moderation_enabled(env) {
+        return Ok(PublishGate::Allowed);
+    }
+
+    let video = cloudflare_stream.get_video_details(video_uid).await?;
+    Ok(verdict_of(&video).map_or(PublishGate::Pending, |v| v.gate()))
+}
+
+pub async fn store_verdict(
+    cloudflare_stream: &CloudflareStream,
+    video: &Video,
+    video_uid: &str,
+    verdict: &ModerationVerdict,
+) -> Result<(), Box<dyn Error>> {
+    // edits replace the whole meta, keep what update_metadata attached
+    let mut meta = video.meta.clone().unwrap_or_default();
+    meta.insert(
+        MODERATION_VERDICT_KEY.to_string(),
+        serde_json::to_string(verdict)?,
+    );
+
+    cloudflare_stream.add_meta_to_video(video_uid, meta).await
+}
+
+pub fn moderation_enabled(env: &Env) -> bool {
+    env.var(MODERATION_SERVICE_URL_VAR).is_ok()
+}
+
+#[derive(Serialize)]
+struct ClassifyRequest<'a> {
+    video_uid: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ClassifyResponse {
+    nsfw_score: f64,
+}
+
+pub struct ModerationService {
+    url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl ModerationService {
+    /// `None` while moderation is off
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let url = env.var(MODERATION_SERVICE_URL_VAR).ok()?.to_string();
+        Some(Self {
+            url,
+            token: env
+                .secret(MODERATION_SERVICE_TOKEN)
+                .ok()
+                .map(|t| t.to_string()),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub async fn classify(&self, video_uid: &str) -> Result<ModerationVerdict, Box<dyn Error>> {
+        let url = format!("{}/classify", self.url.trim_end_matches('/'));
+        let mut req = self.client.post(url).json(&ClassifyRequest { video_uid });
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+
+        let res = req.send().await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            return Err(format!("moderation service returned {status}: {body}").into());
+        }
+        let ClassifyResponse { nsfw_score } = res.json().await?;
+
+        Ok(ModerationVerdict::new(nsfw_score))
+    }
+}
@@ -1,8 +1,11 @@
-use reqwest::{multipart, Client};
+use bytes::Bytes;
+use reqwest::{header::CONTENT_TYPE, Client};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
 
+use super::http_retry::{send_with_dedupe_key, send_with_retry};
+
 #[derive(Clone)]
 pub struct StorjInterface {
     base_url: String,
@@ -14,6 +17,29 @@ pub struct FinalizeRequest {
     pub metadata: HashMap<String, String>,
 }
 
+/// a multipart form with the video as its `file` field, returned with its content type
+///
+/// built once, every attempt resends the same bytes
+fn video_form(video_bytes: Vec<u8>) -> (String, Bytes) {
+    let boundary = format!("yral-{}", uuid::Uuid::new_v4().simple());
+    let mut body = Vec::with_capacity(video_bytes.len() + 256);
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"video.mp4\"\r\n\
+            Content-Type: video/mp4\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(&video_bytes);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    (
+        format!("multipart/form-data; boundary={boundary}"),
+        Bytes::from(body),
+    )
+}
+
 impl StorjInterface {
     pub fn new(base_url: String) -> Result<Self, Box<dyn Error>> {
         let client = Client::new();
@@ -33,7 +59,7 @@ impl StorjInterface {
             video_id
         );
 
-        let response = self.client.get(&download_url).send().await?;
+        let response = send_with_retry(|| self.client.get(&download_url)).await?;
 
         if !response.status().is_success() {
             return Err(format!(
@@ -59,12 +85,15 @@ impl StorjInterface {
             self.base_url, publisher_user_id, video_id, is_nsfw
         );
 
-        let part = multipart::Part::bytes(video_bytes)
-            .file_name("video.mp4")
-            .mime_str("video/mp4")?;
-        let form = multipart::Form::new().part("file", part);
-
-        let response = self.client.post(&url).multipart(form).send().await?;
+        let (content_type, body) = video_form(video_bytes);
+        let dedupe_key = format!("storj-upload-{video_id}");
+        let response = send_with_dedupe_key(&dedupe_key, || {
+            self.client
+                .post(&url)
+                .header(CONTENT_TYPE, content_type.as_str())
+                .body(body.clone())
+        })
+        .await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -99,13 +128,14 @@ impl StorjInterface {
             serde_json::to_string(&finalize_request).unwrap_or_default()
         );
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .json(&finalize_request)
-            .send()
-            .await?;
+        let dedupe_key = format!("storj-finalize-{video_id}");
+        let response = send_with_dedupe_key(&dedupe_key, || {
+            self.client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .json(&finalize_request)
+        })
+        .await?;
 
         if !response.status().is_success() {
             let status = response.status();
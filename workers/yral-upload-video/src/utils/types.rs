@@ -1,5 +1,6 @@
 use std::{collections::HashMap, error::Error};
 
+use candid::Principal;
 use ic_agent::identity::{DelegatedIdentity, Secp256k1Identity, SignedDelegation};
 use k256::elliptic_curve::JwkEcKey;
 use serde::{Deserialize, Serialize};
@@ -64,6 +65,20 @@ pub struct NotifyStatusType {
     pub err_reason_text: Option<String>,
 }
 
+impl NotifyStatusType {
+    /// Stream's failure reason in words fit for the uploader, without internal details
+    pub fn user_facing_error(&self) -> &'static str {
+        match self.err_reason_code.as_deref() {
+            Some("ERR_NON_VIDEO") => "The file you uploaded isn't a video",
+            Some("ERR_DURATION_EXCEED_CONSTRAINT") => "Your video is longer than 60 seconds",
+            Some("ERR_DURATION_TOO_SHORT") => "Your video is too short",
+            Some("ERR_MALFORMED_VIDEO") => "Your video file appears to be damaged",
+            Some("ERR_FETCH_ORIGIN_ERROR") => "Your video didn't finish uploading",
+            _ => "Something went wrong while processing your video",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NotifyRequestPayload {
     pub uid: String,
@@ -150,6 +165,23 @@ pub struct Video {
     pub watermark: Option<Watermark>, // Watermark
 }
 
+impl Video {
+    /// the principal whose delegated identity was attached by `update_metadata`
+    pub fn uploader(&self) -> Option<Principal> {
+        let identity = self.meta.as_ref()?.get(DELEGATED_IDENTITY_KEY)?;
+        let identity: DelegatedIdentityWire = serde_json::from_str(identity).ok()?;
+        Some(Principal::self_authenticating(identity.from_key))
+    }
+
+    /// the uploader, or the user an AI draft was generated for
+    pub fn owner(&self) -> Option<Principal> {
+        self.uploader().or_else(|| {
+            let user_id = self.meta.as_ref()?.get(USER_ID)?;
+            Principal::from_text(user_id).ok()
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PublicDetails {
     title: String,
@@ -279,6 +311,23 @@ pub struct DirectUploadResult {
     pub watermark: Option<Watermark>,
 }
 
+/// a resumable upload session, the client PATCHes chunks to `upload_url` with the tus protocol
+///
+/// after an interrupted upload, a HEAD on `upload_url` returns the `Upload-Offset` to resume from
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TusUploadResult {
+    pub uid: String,
+    #[serde(rename = "uploadURL")]
+    pub upload_url: String,
+    /// bytes already received, always 0 for a new session
+    pub upload_offset: u64,
+    pub upload_length: u64,
+    /// value of the `Tus-Resumable` header every request must carry
+    pub tus_resumable: String,
+    #[serde(rename = "scheduledDeletion")]
+    pub scheduled_deletion: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct StreamResponseType<T> {
     pub errors: Vec<ResponseInfo>,
@@ -0,0 +1,109 @@
+//! time spent in each stage of the upload pipeline, written as `upload_stage_ms` histograms
+//!
+//! an upload goes URL issued (Stream's `created`) → metadata set → stream ready → canister post
+//! created → published. posts made by the queue are published as they're created, AI drafts once
+//! `mark_post_as_published` is called for them
+
+use chrono::DateTime;
+use worker_utils::{metrics::Metrics, time::now_millis};
+
+use super::{types::Video, video_posts::VideoPost};
+
+/// unix millis `update_metadata` ran at, kept in the video's Stream meta
+pub const METADATA_SET_AT_KEY: &str = "metadata-set-at";
+
+const STAGE_METRIC: &str = "upload_stage_ms";
+/// deliveries the `UploadVideo` message took to create the post
+const STAGE_ATTEMPTS_METRIC: &str = "upload_stage_attempts";
+/// URL issued to published
+const TIME_TO_PUBLISH_METRIC: &str = "upload_time_to_publish_ms";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadStage {
+    MetadataSet,
+    StreamReady,
+    PostCreated,
+    Published,
+}
+
+impl UploadStage {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            UploadStage::MetadataSet => "metadata_set",
+            UploadStage::StreamReady => "stream_ready",
+            UploadStage::PostCreated => "post_created",
+            UploadStage::Published => "published",
+        }
+    }
+}
+
+/// unix millis of an RFC 3339 timestamp from Stream
+pub fn rfc3339_millis(timestamp: Option<&String>) -> Option<u64> {
+    let at = DateTime::parse_from_rfc3339(timestamp?).ok()?;
+    u64::try_from(at.timestamp_millis()).ok()
+}
+
+/// time from `from` to `to`, skipped if either end wasn't recorded
+fn record_stage(metrics: &Metrics, stage: UploadStage, from: Option<u64>, to: Option<u64>) {
+    if let (Some(from), Some(to)) = (from, to) {
+        metrics.histogram(
+            STAGE_METRIC,
+            &[stage.as_str()],
+            to.saturating_sub(from) as f64,
+        );
+    }
+}
+
+fn record_time_to_publish(metrics: &Metrics, url_issued_at: Option<u64>, published_at: u64) {
+    if let Some(url_issued_at) = url_issued_at {
+        metrics.histogram(
+            TIME_TO_PUBLISH_METRIC,
+            &[],
+            published_at.saturating_sub(url_issued_at) as f64,
+        );
+    }
+}
+
+/// every stage of `video`, whose post the queue just created and published
+pub fn record_queue_upload(metrics: &Metrics, video: &Video, attempts: u32) {
+    let now = now_millis();
+    let url_issued_at = rfc3339_millis(video.created.as_ref());
+    let metadata_set_at = video
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.get(METADATA_SET_AT_KEY))
+        .and_then(|at| at.parse().ok());
+    let ready_at = rfc3339_millis(video.ready_to_stream_at.as_ref());
+
+    record_stage(
+        metrics,
+        UploadStage::MetadataSet,
+        url_issued_at,
+        metadata_set_at,
+    );
+    record_stage(
+        metrics,
+        UploadStage::StreamReady,
+        metadata_set_at.or(url_issued_at),
+        ready_at,
+    );
+    record_stage(metrics, UploadStage::PostCreated, ready_at, Some(now));
+    metrics.histogram(
+        STAGE_ATTEMPTS_METRIC,
+        &[UploadStage::PostCreated.as_str()],
+        attempts as f64,
+    );
+    record_time_to_publish(metrics, url_issued_at, now);
+}
+
+/// the draft `post` was published
+pub fn record_draft_published(metrics: &Metrics, post: &VideoPost) {
+    let now = now_millis();
+    record_stage(
+        metrics,
+        UploadStage::Published,
+        Some(post.created_at),
+        Some(now),
+    );
+    record_time_to_publish(metrics, post.url_issued_at, now);
+}
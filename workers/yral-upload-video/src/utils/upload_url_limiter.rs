@@ -0,0 +1,65 @@
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::*;
+use worker_utils::{
+    api_error::{error_resp, ApiError},
+    storage::{rate_limit::RateLimit, SafeStorage},
+};
+
+pub const UPLOAD_URL_LIMITER: &str = "UPLOAD_URL_LIMITER";
+
+const DEFAULT_MAX_UPLOAD_URLS_PER_HOUR: u32 = 30;
+const WINDOW_MS: u64 = 60 * 60 * 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct AcquireUploadUrls {
+    pub count: u32,
+}
+
+/// caps the upload URLs issued to one user
+/// one instance per user principal
+#[durable_object]
+pub struct UploadUrlLimiter {
+    state: State,
+    limit: RefCell<RateLimit>,
+}
+
+impl DurableObject for UploadUrlLimiter {
+    fn new(state: State, env: Env) -> Self {
+        let max_urls = env
+            .var("MAX_UPLOAD_URLS_PER_HOUR")
+            .ok()
+            .and_then(|v| v.to_string().parse().ok())
+            .unwrap_or(DEFAULT_MAX_UPLOAD_URLS_PER_HOUR);
+        Self {
+            state,
+            limit: RefCell::new(RateLimit::new("upload-url-limit", max_urls, WINDOW_MS)),
+        }
+    }
+
+    // SAFETY: RefCell borrows held across await points are safe in Cloudflare Workers
+    // because Workers run in a single-threaded JavaScript runtime with no concurrent access.
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        if req.method() != Method::Post || req.path() != "/acquire" {
+            return error_resp("not found", 404);
+        }
+
+        let AcquireUploadUrls { count } = req.json().await?;
+        let mut storage: SafeStorage = self.state.storage().into();
+        let mut limit = self.limit.borrow_mut();
+        if let Err(retry_after_ms) = limit.try_acquire_many(&mut storage, count).await? {
+            return ApiError::from_status(429, "upload URL limit reached")
+                .with_details(json!({
+                    "max_urls": limit.max_requests(),
+                    "window_ms": limit.window_ms(),
+                    "retry_after_ms": retry_after_ms,
+                }))
+                .into_response(429);
+        }
+
+        Response::from_json(&())
+    }
+}
@@ -0,0 +1,80 @@
+//! which post each uploaded video became, written once the post is added to its canister
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use worker::{Env, Result};
+use worker_utils::time::now_millis;
+
+pub const VIDEO_POSTS_KV: &str = "VIDEO_POSTS";
+/// every post of a creator, listed by yral-admin when it erases their account
+pub const CREATOR_PREFIX: &str = "creator-";
+
+/// `{CREATOR_PREFIX}{creator_principal}/{canister_id}:{post_id}`
+pub fn creator_key(creator_principal: Principal, canister_id: Principal, post_id: &str) -> String {
+    format!("{CREATOR_PREFIX}{creator_principal}/{canister_id}:{post_id}")
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VideoPost {
+    pub video_uid: String,
+    /// the creator's individual canister or the user post service
+    pub canister_id: Principal,
+    pub post_id: String,
+    pub creator_principal: Principal,
+    /// unix millis
+    pub created_at: u64,
+    /// unix millis the video's upload URL was issued at, if known
+    #[serde(default)]
+    pub url_issued_at: Option<u64>,
+}
+
+impl VideoPost {
+    pub fn new(
+        video_uid: String,
+        canister_id: Principal,
+        post_id: String,
+        creator_principal: Principal,
+    ) -> Self {
+        Self {
+            video_uid,
+            canister_id,
+            post_id,
+            creator_principal,
+            created_at: now_millis(),
+            url_issued_at: None,
+        }
+    }
+
+    pub fn with_url_issued_at(mut self, url_issued_at: Option<u64>) -> Self {
+        self.url_issued_at = url_issued_at;
+        self
+    }
+
+    pub async fn record(&self, env: &Env) -> Result<()> {
+        env.kv(VIDEO_POSTS_KV)?
+            .put(&self.video_uid, self)?
+            .execute()
+            .await?;
+
+        self.index(env).await
+    }
+
+    /// adds the post to its creator's index only, used for canister posts synced to the post
+    /// service, whose video already points at the synced post
+    ///
+    /// the post is the key's metadata, so listing the index needs no reads
+    pub async fn index(&self, env: &Env) -> Result<()> {
+        let key = creator_key(self.creator_principal, self.canister_id, &self.post_id);
+        env.kv(VIDEO_POSTS_KV)?
+            .put(&key, "")?
+            .metadata(self)?
+            .execute()
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub async fn post_for_video(env: &Env, video_uid: &str) -> Result<Option<VideoPost>> {
+    Ok(env.kv(VIDEO_POSTS_KV)?.get(video_uid).json().await?)
+}
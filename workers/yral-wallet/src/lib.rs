@@ -0,0 +1,107 @@
+mod sources;
+mod stream;
+mod wallet;
+
+use std::result::Result as StdResult;
+
+use candid::Principal;
+use sources::Credentials;
+use stream::wallet_stream;
+use wallet::Wallet;
+use worker::*;
+use worker_utils::{
+    api_error::ApiError,
+    cors::cors_for_env,
+    health::{Dependency, HealthCheck},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    principal::parse_principal,
+    principals,
+    trace::{with_trace, TraceId},
+};
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-wallet",
+    &[
+        Dependency::Service("YRAL_HOT_OR_NOT"),
+        Dependency::Service("YRAL_PUMP_N_DUMP"),
+        Dependency::Service("YRAL_COIN"),
+    ],
+);
+
+/// pump-n-dump balances are keyed by the user's canister, passed as `?user_canister=`
+fn user_canister(req: &Request) -> Result<StdResult<Option<Principal>, ApiError>> {
+    let raw = req
+        .url()?
+        .query_pairs()
+        .find(|(k, _)| k == "user_canister")
+        .map(|(_, v)| v.into_owned());
+
+    Ok(raw
+        .map(|raw| parse_principal("user_canister", Some(&raw)))
+        .transpose())
+}
+
+async fn user_wallet(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+    let user_canister = match user_canister(&req)? {
+        Ok(user_canister) => user_canister,
+        Err(e) => return e.into_response(400),
+    };
+    let creds = Credentials::from_request(&req)?;
+    let trace = TraceId::from_request(&req);
+
+    let wallet = Wallet::load(&ctx.env, user_principal, user_canister, &creds, &trace).await;
+    let metrics = Metrics::new(&ctx.env, "yral-wallet");
+    for err in &wallet.errors {
+        metrics.counter("wallet_source_errors", &[err.source.name()]);
+    }
+
+    Response::from_json(&wallet)
+}
+
+async fn user_wallet_ws(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_principal = principals!(ctx, "user_principal");
+    if req.headers().get("Upgrade")?.as_deref() != Some("websocket") {
+        return ApiError::new("ExpectedWebsocket", "expected websocket").into_response(400);
+    }
+    let user_canister = match user_canister(&req)? {
+        Ok(user_canister) => user_canister,
+        Err(e) => return e.into_response(400),
+    };
+    let creds = Credentials::from_request(&req)?;
+    let trace = TraceId::from_request(&req);
+
+    wallet_stream(ctx.env, user_principal, user_canister, creds, trace).await
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let (req, trace) = with_trace(req)?;
+    let cors = cors_for_env(&env);
+    let path = req.path();
+    let origin = req.headers().get("Origin")?;
+
+    let method = req.method();
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-wallet", method.as_ref(), &path, &[]).await
+    {
+        return cors.apply(&path, origin.as_deref(), notice.into_response()?);
+    }
+
+    let res = Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/wallet/:user_principal", user_wallet)
+        .get_async("/ws/wallet/:user_principal", user_wallet_ws)
+        .options("/*catchall", |_, _| Response::empty())
+        .run(req, env)
+        .await?;
+
+    cors.apply(&path, origin.as_deref(), trace.tag(res)?)
+}
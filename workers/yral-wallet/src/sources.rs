@@ -0,0 +1,180 @@
+use std::{pin::pin, str::FromStr, time::Duration};
+
+use futures::future::{select, Either};
+use num_bigint::BigUint;
+use serde::Serialize;
+use serde_json::Value;
+use worker::*;
+use worker_utils::{trace::TraceId, RequestInitBuilder};
+
+/// a slow source is reported as an error instead of holding up the others
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// query params of yral-coin's signed balance stream
+const SIGNED_QUERY_PARAMS: &[&str] = &["signature", "expires_at"];
+
+/// what the app sent to prove who it is, forwarded to every source
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    /// for balance reads gated behind a JWT
+    pub authorization: Option<String>,
+    /// required by yral-coin's balance stream
+    pub signed_query: Vec<(String, String)>,
+}
+
+impl Credentials {
+    pub fn from_request(req: &Request) -> Result<Self> {
+        let signed_query = req
+            .url()?
+            .query_pairs()
+            .filter(|(k, _)| SIGNED_QUERY_PARAMS.contains(&k.as_ref()))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        Ok(Self {
+            authorization: req.headers().get("Authorization")?,
+            signed_query,
+        })
+    }
+
+    pub fn apply(&self, init: &mut RequestInitBuilder) -> Result<()> {
+        if let Some(auth) = &self.authorization {
+            init.header("Authorization", auth)?;
+        }
+        let query: Vec<_> = self
+            .signed_query
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        init.query(&query);
+
+        Ok(())
+    }
+}
+
+/// a token balance owned by another worker, reached through a service binding
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// SATS in yral-hot-or-not
+    Sats,
+    /// cents/DOLR in yral-pump-n-dump
+    Cents,
+    /// YRAL in yral-coin
+    Yral,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sats => "sats",
+            Self::Cents => "cents",
+            Self::Yral => "yral",
+        }
+    }
+
+    pub fn binding(self) -> &'static str {
+        match self {
+            Self::Sats => "YRAL_HOT_OR_NOT",
+            Self::Cents => "YRAL_PUMP_N_DUMP",
+            Self::Yral => "YRAL_COIN",
+        }
+    }
+
+    /// balance route, pump-n-dump is keyed by the user's canister instead of their principal
+    fn balance_path(self, key: &str) -> String {
+        match self {
+            Self::Sats => format!("/v2/balance/{key}"),
+            Self::Cents => format!("/balance_v2/{key}"),
+            Self::Yral => format!("/balance/{key}"),
+        }
+    }
+
+    /// balance stream route, `None` if the source doesn't push updates
+    pub fn ws_path(self, key: &str) -> Option<String> {
+        match self {
+            Self::Sats | Self::Yral => Some(format!("/ws/balance/{key}")),
+            Self::Cents => None,
+        }
+    }
+
+    /// request to the bound worker, the host is ignored by service bindings
+    pub fn request(self, path: &str, init: &RequestInitBuilder) -> Result<Request> {
+        init.request(&format!("https://{}{path}", self.binding()))
+    }
+
+    pub async fn fetch(self, env: &Env, req: Request) -> Result<Response> {
+        env.service(self.binding())?.fetch_request(req).await
+    }
+
+    pub async fn balance(
+        self,
+        env: &Env,
+        key: &str,
+        creds: &Credentials,
+        trace: &TraceId,
+    ) -> Result<Balance> {
+        let mut init = RequestInitBuilder::default();
+        init.method(Method::Get).trace(trace)?;
+        creds.apply(&mut init)?;
+        let req = self.request(&self.balance_path(key), &init)?;
+
+        let fetch = pin!(self.fetch(env, req));
+        let delay = pin!(Delay::from(SOURCE_TIMEOUT));
+        let mut res = match select(fetch, delay).await {
+            Either::Left((res, _)) => res?,
+            Either::Right(_) => {
+                return Err(Error::RustError(format!(
+                    "timed out after {}ms",
+                    SOURCE_TIMEOUT.as_millis()
+                )))
+            }
+        };
+        if res.status_code() != 200 {
+            let status = res.status_code();
+            let body = res.text().await.unwrap_or_default();
+            return Err(Error::RustError(format!("status {status}: {body}")));
+        }
+
+        Ok(self.parse(res.json().await?))
+    }
+
+    /// normalizes a balance payload, from a balance route or a balance stream
+    pub fn parse(self, details: Value) -> Balance {
+        let balance = details["balance"].clone();
+        let withdrawable = match self {
+            // SATS withdrawals are moving to a separate worker, nothing is withdrawable here
+            Self::Sats => None,
+            Self::Cents => Some(details["withdrawable"].clone()),
+            Self::Yral => yral_withdrawable(&details).map(|w| Value::String(w.to_string())),
+        };
+
+        Balance {
+            balance,
+            withdrawable,
+            details,
+        }
+    }
+}
+
+/// held and promotional coins are part of the balance but can't be withdrawn
+fn yral_withdrawable(details: &Value) -> Option<BigUint> {
+    let amount = |key: &str| BigUint::from_str(details[key].as_str()?).ok();
+    let balance = amount("balance")?;
+    let locked = amount("held")? + amount("promotional")?;
+
+    Some(if locked > balance {
+        BigUint::ZERO
+    } else {
+        balance - locked
+    })
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Balance {
+    pub balance: Value,
+    /// `None` if the token can't be withdrawn yet
+    pub withdrawable: Option<Value>,
+    /// the source's payload, unchanged
+    pub details: Value,
+}
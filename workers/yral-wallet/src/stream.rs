@@ -0,0 +1,194 @@
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use candid::Principal;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use wasm_bindgen_futures::spawn_local;
+use worker::*;
+use worker_utils::{trace::TraceId, trace_error, RequestInitBuilder};
+
+use crate::{
+    sources::{Balance, Credentials, Source},
+    wallet::Wallet,
+};
+
+/// sources without a balance stream are polled this often
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// messages sent to the app
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WalletEvent<'a> {
+    /// sent once, right after connecting
+    Snapshot {
+        wallet: &'a Wallet,
+    },
+    Update {
+        source: Source,
+        balance: Balance,
+    },
+}
+
+/// what a connection needs after the handler returns
+#[derive(Clone)]
+struct Conn {
+    env: Env,
+    client: WebSocket,
+    trace: TraceId,
+    closed: Rc<Cell<bool>>,
+}
+
+impl Conn {
+    fn send(&self, event: &WalletEvent) {
+        if self.closed.get() {
+            return;
+        }
+        if let Err(e) = self.client.send(event) {
+            trace_error!(self.trace, "failed to send wallet event: {e}");
+        }
+    }
+
+    /// the app reconnects and gets a fresh snapshot
+    fn close(&self, reason: String) {
+        if !self.closed.replace(true) {
+            _ = self.client.close(Some(1011), Some(reason));
+        }
+    }
+}
+
+async fn connect(
+    env: &Env,
+    source: Source,
+    path: &str,
+    creds: &Credentials,
+    trace: &TraceId,
+) -> Result<WebSocket> {
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Get)
+        .header("Upgrade", "websocket")?
+        .trace(trace)?;
+    creds.apply(&mut init)?;
+    let res = source.fetch(env, source.request(path, &init)?).await?;
+    let Some(upstream) = res.websocket() else {
+        return Err(Error::RustError(format!(
+            "{} refused the websocket",
+            source.name()
+        )));
+    };
+    upstream.accept()?;
+
+    Ok(upstream)
+}
+
+async fn forward(conn: Conn, source: Source, upstream: WebSocket) {
+    let mut events = match upstream.events() {
+        Ok(events) => events,
+        Err(e) => return conn.close(format!("{} stream failed: {e}", source.name())),
+    };
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(WebsocketEvent::Message(msg)) => {
+                let Ok(details) = msg.json::<Value>() else {
+                    continue;
+                };
+                conn.send(&WalletEvent::Update {
+                    source,
+                    balance: source.parse(details),
+                });
+            }
+            Ok(WebsocketEvent::Close(_)) => break,
+            Err(e) => {
+                trace_error!(conn.trace, "{} stream failed: {e}", source.name());
+                break;
+            }
+        }
+    }
+
+    conn.close(format!("{} stream closed", source.name()));
+}
+
+/// sends an update whenever the polled balance changes
+async fn poll(conn: Conn, source: Source, key: String, creds: Credentials) {
+    let mut last = None;
+    while !conn.closed.get() {
+        Delay::from(POLL_INTERVAL).await;
+        let balance = match source.balance(&conn.env, &key, &creds, &conn.trace).await {
+            Ok(balance) => balance,
+            Err(e) => {
+                trace_error!(conn.trace, "failed to poll {}: {e}", source.name());
+                continue;
+            }
+        };
+        if last.as_ref() != Some(&balance) {
+            last = Some(balance.clone());
+            conn.send(&WalletEvent::Update { source, balance });
+        }
+    }
+}
+
+/// closes the upstream streams once the app goes away
+async fn watch_client(conn: Conn, upstreams: Vec<WebSocket>) {
+    if let Ok(mut events) = conn.client.events() {
+        while let Some(event) = events.next().await {
+            if matches!(event, Ok(WebsocketEvent::Close(_)) | Err(_)) {
+                break;
+            }
+        }
+    }
+
+    conn.closed.set(true);
+    for upstream in upstreams {
+        _ = upstream.close(Some(1000), Some("client left"));
+    }
+}
+
+/// one websocket for the whole wallet: a snapshot, then updates from every source
+pub async fn wallet_stream(
+    env: Env,
+    user_principal: Principal,
+    user_canister: Option<Principal>,
+    creds: Credentials,
+    trace: TraceId,
+) -> Result<Response> {
+    let user_key = user_principal.to_text();
+    let mut upstreams = vec![];
+    for source in [Source::Sats, Source::Cents, Source::Yral] {
+        let Some(path) = source.ws_path(&user_key) else {
+            continue;
+        };
+        upstreams.push((source, connect(&env, source, &path, &creds, &trace).await?));
+    }
+
+    let pair = WebSocketPair::new()?;
+    pair.server.accept()?;
+    let conn = Conn {
+        env,
+        client: pair.server,
+        trace,
+        closed: Rc::new(Cell::new(false)),
+    };
+
+    let wallet = Wallet::load(
+        &conn.env,
+        user_principal,
+        user_canister,
+        &creds,
+        &conn.trace,
+    )
+    .await;
+    conn.send(&WalletEvent::Snapshot { wallet: &wallet });
+
+    spawn_local(watch_client(
+        conn.clone(),
+        upstreams.iter().map(|(_, ws)| ws.clone()).collect(),
+    ));
+    for (source, upstream) in upstreams {
+        spawn_local(forward(conn.clone(), source, upstream));
+    }
+    if let Some(canister) = user_canister {
+        spawn_local(poll(conn.clone(), Source::Cents, canister.to_text(), creds));
+    }
+
+    Response::from_websocket(pair.client)
+}
@@ -0,0 +1,72 @@
+use candid::Principal;
+use futures::join;
+use serde::Serialize;
+use worker::{Env, Result};
+use worker_utils::trace::TraceId;
+
+use crate::sources::{Balance, Credentials, Source};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SourceError {
+    pub source: Source,
+    pub error: String,
+}
+
+/// every balance of a user, a failing source is reported in `errors` instead of failing the wallet
+#[derive(Serialize, Clone, Debug)]
+pub struct Wallet {
+    pub user_principal: Principal,
+    pub sats: Option<Balance>,
+    /// only read when the user's canister is known
+    pub cents: Option<Balance>,
+    pub yral: Option<Balance>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<SourceError>,
+}
+
+impl Wallet {
+    pub async fn load(
+        env: &Env,
+        user_principal: Principal,
+        user_canister: Option<Principal>,
+        creds: &Credentials,
+        trace: &TraceId,
+    ) -> Self {
+        let user_key = user_principal.to_text();
+        let canister_key = user_canister.map(|c| c.to_text());
+        let cents = async {
+            match &canister_key {
+                Some(key) => Some(Source::Cents.balance(env, key, creds, trace).await),
+                None => None,
+            }
+        };
+        let (sats, cents, yral) = join!(
+            Source::Sats.balance(env, &user_key, creds, trace),
+            cents,
+            Source::Yral.balance(env, &user_key, creds, trace),
+        );
+
+        let mut errors = vec![];
+        let mut settle = |source: Source, res: Result<Balance>| match res {
+            Ok(balance) => Some(balance),
+            Err(e) => {
+                errors.push(SourceError {
+                    source,
+                    error: e.to_string(),
+                });
+                None
+            }
+        };
+        let sats = settle(Source::Sats, sats);
+        let cents = cents.and_then(|res| settle(Source::Cents, res));
+        let yral = settle(Source::Yral, yral);
+
+        Self {
+            user_principal,
+            sats,
+            cents,
+            yral,
+            errors,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+-- partner endpoints, managed through /endpoints
+CREATE TABLE IF NOT EXISTS webhook_endpoints (
+    id TEXT PRIMARY KEY,
+    partner TEXT NOT NULL,
+    url TEXT NOT NULL,
+    -- hmac key deliveries are signed with, only returned when it's created or rotated
+    secret TEXT NOT NULL,
+    -- comma separated event kinds, e.g. `post_published,referral_credited`
+    events TEXT NOT NULL,
+    -- disabled endpoints get no new deliveries, pending ones are cancelled
+    active INTEGER NOT NULL DEFAULT 1,
+    updated_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- one row per event and endpoint, the id dedupes redelivered events
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    -- `{event_id}:{endpoint_id}`
+    id TEXT PRIMARY KEY,
+    endpoint_id TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    -- the exact body that's signed and sent
+    payload TEXT NOT NULL,
+    -- pending, delivered, failed or cancelled
+    status TEXT NOT NULL,
+    -- since the delivery was created or last replayed
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_status_code INTEGER,
+    last_error TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    delivered_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_endpoint
+    ON webhook_deliveries (endpoint_id, created_at);
+
+-- every attempt of every delivery, the delivery log partners are debugged with
+CREATE TABLE IF NOT EXISTS webhook_attempts (
+    delivery_id TEXT NOT NULL,
+    attempted_at INTEGER NOT NULL,
+    -- null when the request didn't get a response
+    status_code INTEGER,
+    error TEXT,
+    duration_ms INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_attempts_delivery ON webhook_attempts (delivery_id, attempted_at);
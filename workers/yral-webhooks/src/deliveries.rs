@@ -0,0 +1,384 @@
+//! deliveries of events to partner endpoints, one queue message per delivery
+//!
+//! attempts are capped and backed off here, the queue's retry delay isn't used
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::{
+    webhooks::{WebhookEvent, WEBHOOK_EVENT_ID_HEADER, WEBHOOK_SIGNATURE_HEADER},
+    RequestInitBuilder,
+};
+
+use crate::endpoints::{endpoint, num, subscribers};
+
+pub const WEBHOOK_DELIVERIES_QUEUE: &str = "WEBHOOK_DELIVERIES";
+
+/// attempts before a delivery is given up on, about a day with the backoff below
+pub const MAX_ATTEMPTS: u32 = 12;
+const BASE_RETRY_DELAY_SECS: u64 = 30;
+const MAX_RETRY_DELAY_SECS: u64 = 6 * 60 * 60;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// characters of partner response bodies kept in the log
+const MAX_ERROR_LEN: usize = 512;
+/// failed deliveries requeued per replay request
+pub const MAX_REPLAY: u32 = 500;
+/// cloudflare's limit on messages per `send_batch`
+const SEND_BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryMessage {
+    pub delivery_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// out of attempts, replayed by hand once the partner is fixed
+    Failed,
+    /// the endpoint was disabled or removed before it was delivered
+    Cancelled,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// a row of `webhook_deliveries`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Delivery {
+    pub id: String,
+    pub endpoint_id: String,
+    pub event_id: String,
+    pub event: String,
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    /// unix millis
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub delivered_at: Option<u64>,
+}
+
+/// a row of `webhook_attempts`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attempt {
+    /// unix millis
+    pub attempted_at: u64,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// what the consumer does with the message after an attempt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Delivered,
+    /// retried after this many seconds
+    Retry(u32),
+    Failed,
+    /// nothing to send, already delivered, cancelled or unknown
+    Skipped,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::Retry(_) => "retried",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+fn opt_num(v: Option<u64>) -> JsValue {
+    v.map(num).unwrap_or(JsValue::NULL)
+}
+
+fn opt_str(v: Option<&str>) -> JsValue {
+    v.map(JsValue::from_str).unwrap_or(JsValue::NULL)
+}
+
+/// doubles from `BASE_RETRY_DELAY_SECS` after each failed attempt
+fn retry_delay_secs(attempts: u32) -> u32 {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (BASE_RETRY_DELAY_SECS << doublings).min(MAX_RETRY_DELAY_SECS) as u32
+}
+
+/// `time=<millis>,sig1=<hex hmac-sha256 of "{time}.{body}">`, as documented in worker-utils
+fn signature_header(secret: &str, time: u64, body: &str) -> Result<String> {
+    type HmacSha256 = Hmac<Sha256>;
+
+    let mut hmac = HmacSha256::new_from_slice(secret.as_bytes())
+        .map_err(|e| Error::RustError(e.to_string()))?;
+    hmac.update(format!("{time}.{body}").as_bytes());
+    let digest = hex::encode(hmac.finalize().into_bytes());
+
+    Ok(format!("time={time},sig1={digest}"))
+}
+
+async fn enqueue(env: &Env, delivery_ids: &[String]) -> Result<()> {
+    let queue = env.queue(WEBHOOK_DELIVERIES_QUEUE)?;
+    for chunk in delivery_ids.chunks(SEND_BATCH_SIZE) {
+        let messages = chunk.iter().map(|id| DeliveryMessage {
+            delivery_id: id.clone(),
+        });
+        queue.send_batch(messages).await?;
+    }
+
+    Ok(())
+}
+
+/// records a delivery per subscribed endpoint and queues them
+///
+/// a redelivered event finds its deliveries already recorded, sending them again is a no-op
+/// for the ones that went out
+pub async fn fan_out(env: &Env, db: &D1Database, event: &WebhookEvent, now: u64) -> Result<usize> {
+    let endpoints = subscribers(db, event.kind()).await?;
+    if endpoints.is_empty() {
+        return Ok(0);
+    }
+
+    let payload = serde_json::to_string(event)?;
+    let mut delivery_ids = Vec::with_capacity(endpoints.len());
+    let mut statements = Vec::with_capacity(endpoints.len());
+    for endpoint in &endpoints {
+        let id = format!("{}:{}", event.id, endpoint.id);
+        statements.push(
+            db.prepare(
+                "INSERT OR IGNORE INTO webhook_deliveries \
+                (id, endpoint_id, event_id, event, payload, status, created_at, updated_at) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+            )
+            .bind(&[
+                id.as_str().into(),
+                endpoint.id.as_str().into(),
+                event.id.as_str().into(),
+                event.kind().as_str().into(),
+                payload.as_str().into(),
+                DeliveryStatus::Pending.as_str().into(),
+                num(now),
+            ])?,
+        );
+        delivery_ids.push(id);
+    }
+    db.batch(statements).await?;
+    enqueue(env, &delivery_ids).await?;
+
+    Ok(delivery_ids.len())
+}
+
+pub async fn delivery(db: &D1Database, id: &str) -> Result<Option<Delivery>> {
+    db.prepare("SELECT * FROM webhook_deliveries WHERE id = ?1")
+        .bind(&[id.into()])?
+        .first(None)
+        .await
+}
+
+pub async fn attempts(db: &D1Database, delivery_id: &str) -> Result<Vec<Attempt>> {
+    db.prepare(
+        "SELECT attempted_at, status_code, error, duration_ms FROM webhook_attempts \
+        WHERE delivery_id = ?1 ORDER BY attempted_at",
+    )
+    .bind(&[delivery_id.into()])?
+    .all()
+    .await?
+    .results()
+}
+
+/// the endpoint's deliveries created before `before`, newest first
+pub async fn list_deliveries(
+    db: &D1Database,
+    endpoint_id: &str,
+    status: Option<DeliveryStatus>,
+    before: Option<u64>,
+    limit: u32,
+) -> Result<Vec<Delivery>> {
+    db.prepare(
+        "SELECT * FROM webhook_deliveries WHERE endpoint_id = ?1 \
+        AND (?2 IS NULL OR status = ?2) AND (?3 IS NULL OR created_at < ?3) \
+        ORDER BY created_at DESC LIMIT ?4",
+    )
+    .bind(&[
+        endpoint_id.into(),
+        opt_str(status.map(DeliveryStatus::as_str)),
+        opt_num(before),
+        limit.into(),
+    ])?
+    .all()
+    .await?
+    .results()
+}
+
+async fn set_status(db: &D1Database, id: &str, status: DeliveryStatus, now: u64) -> Result<()> {
+    db.prepare("UPDATE webhook_deliveries SET status = ?2, updated_at = ?3 WHERE id = ?1")
+        .bind(&[id.into(), status.as_str().into(), num(now)])?
+        .run()
+        .await?;
+
+    Ok(())
+}
+
+/// makes one attempt at the delivery and logs it
+pub async fn deliver(db: &D1Database, delivery_id: &str, now: u64) -> Result<Outcome> {
+    let Some(row) = delivery(db, delivery_id).await? else {
+        return Ok(Outcome::Skipped);
+    };
+    if row.status != DeliveryStatus::Pending {
+        return Ok(Outcome::Skipped);
+    }
+    let endpoint = match endpoint(db, &row.endpoint_id).await? {
+        Some(endpoint) if endpoint.is_active() => endpoint,
+        _ => {
+            set_status(db, delivery_id, DeliveryStatus::Cancelled, now).await?;
+            return Ok(Outcome::Skipped);
+        }
+    };
+
+    let signature = signature_header(&endpoint.secret, now, &row.payload)?;
+    let mut init = RequestInitBuilder::default();
+    init.method(Method::Post)
+        .header(WEBHOOK_SIGNATURE_HEADER, &signature)?
+        .header(WEBHOOK_EVENT_ID_HEADER, &row.event_id)?
+        .body(row.payload.clone(), "application/json")?
+        .timeout(DELIVERY_TIMEOUT);
+    let (status_code, error) = match init.fetch(&endpoint.url).await {
+        Ok(res) if (200..300).contains(&res.status_code()) => (Some(res.status_code()), None),
+        Ok(mut res) => {
+            let body = res.text().await.unwrap_or_default();
+            (
+                Some(res.status_code()),
+                Some(body.chars().take(MAX_ERROR_LEN).collect()),
+            )
+        }
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let finished_at = Date::now().as_millis();
+
+    let attempts = row.attempts + 1;
+    let (status, outcome) = match &error {
+        None => (DeliveryStatus::Delivered, Outcome::Delivered),
+        Some(_) if attempts >= MAX_ATTEMPTS => (DeliveryStatus::Failed, Outcome::Failed),
+        Some(_) => (
+            DeliveryStatus::Pending,
+            Outcome::Retry(retry_delay_secs(attempts)),
+        ),
+    };
+    let delivered_at = (status == DeliveryStatus::Delivered).then_some(finished_at);
+    db.batch(vec![
+        db.prepare(
+            "INSERT INTO webhook_attempts \
+            (delivery_id, attempted_at, status_code, error, duration_ms) \
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(&[
+            delivery_id.into(),
+            num(now),
+            opt_num(status_code.map(u64::from)),
+            opt_str(error.as_deref()),
+            num(finished_at.saturating_sub(now)),
+        ])?,
+        db.prepare(
+            "UPDATE webhook_deliveries SET status = ?2, attempts = ?3, last_status_code = ?4, \
+            last_error = ?5, updated_at = ?6, delivered_at = ?7 WHERE id = ?1",
+        )
+        .bind(&[
+            delivery_id.into(),
+            status.as_str().into(),
+            attempts.into(),
+            opt_num(status_code.map(u64::from)),
+            opt_str(error.as_deref()),
+            num(finished_at),
+            opt_num(delivered_at),
+        ])?,
+    ])
+    .await?;
+
+    Ok(outcome)
+}
+
+/// sends the delivery again with fresh attempts, whatever its status
+pub async fn replay(env: &Env, db: &D1Database, id: &str, now: u64) -> Result<Option<Delivery>> {
+    let replayed: Option<Delivery> = db
+        .prepare(
+            "UPDATE webhook_deliveries SET status = ?2, attempts = 0, updated_at = ?3 \
+            WHERE id = ?1 RETURNING *",
+        )
+        .bind(&[id.into(), DeliveryStatus::Pending.as_str().into(), num(now)])?
+        .first(None)
+        .await?;
+    if replayed.is_some() {
+        enqueue(env, &[id.to_string()]).await?;
+    }
+
+    Ok(replayed)
+}
+
+/// replays up to `MAX_REPLAY` of the endpoint's failed deliveries created since `since`,
+/// oldest first, a full page means there may be more
+pub async fn replay_failed(
+    env: &Env,
+    db: &D1Database,
+    endpoint_id: &str,
+    since: u64,
+    now: u64,
+) -> Result<usize> {
+    #[derive(Deserialize)]
+    struct Id {
+        id: String,
+    }
+
+    let ids: Vec<Id> = db
+        .prepare(
+            "SELECT id FROM webhook_deliveries WHERE endpoint_id = ?1 AND status = ?2 \
+            AND created_at >= ?3 ORDER BY created_at LIMIT ?4",
+        )
+        .bind(&[
+            endpoint_id.into(),
+            DeliveryStatus::Failed.as_str().into(),
+            num(since),
+            MAX_REPLAY.into(),
+        ])?
+        .all()
+        .await?
+        .results()?;
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<String> = ids.into_iter().map(|row| row.id).collect();
+    let statements = ids
+        .iter()
+        .map(|id| {
+            db.prepare(
+                "UPDATE webhook_deliveries SET status = ?2, attempts = 0, updated_at = ?3 \
+                WHERE id = ?1",
+            )
+            .bind(&[
+                id.as_str().into(),
+                DeliveryStatus::Pending.as_str().into(),
+                num(now),
+            ])
+        })
+        .collect::<Result<Vec<_>>>()?;
+    db.batch(statements).await?;
+    enqueue(env, &ids).await?;
+
+    Ok(ids.len())
+}
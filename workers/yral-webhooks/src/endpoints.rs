@@ -0,0 +1,197 @@
+//! partner endpoints, registered and managed by operators
+
+use serde::{Deserialize, Serialize};
+use worker::{wasm_bindgen::JsValue, *};
+use worker_utils::{
+    environment::{env_kind, RunEnv},
+    webhooks::WebhookEventKind,
+};
+
+pub const WEBHOOKS_DB: &str = "WEBHOOKS_DB";
+
+const MAX_PARTNER_LEN: usize = 64;
+const MAX_URL_LEN: usize = 2048;
+
+pub(crate) fn num(v: u64) -> JsValue {
+    JsValue::from_f64(v as f64)
+}
+
+/// a row of `webhook_endpoints`, the secret is never serialized
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Endpoint {
+    pub id: String,
+    pub partner: String,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// comma separated event kinds
+    pub events: String,
+    /// 0 once disabled
+    pub active: u32,
+    pub updated_by: String,
+    /// unix millis
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Endpoint {
+    pub fn is_active(&self) -> bool {
+        self.active != 0
+    }
+
+    pub fn subscribes_to(&self, kind: WebhookEventKind) -> bool {
+        self.events.split(',').any(|e| e == kind.as_str())
+    }
+}
+
+/// body of `POST /endpoints` and `PUT /endpoints/:endpoint_id`
+#[derive(Deserialize, Clone, Debug)]
+pub struct EndpointRequest {
+    pub partner: String,
+    pub url: String,
+    pub events: Vec<WebhookEventKind>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl EndpointRequest {
+    /// what's wrong with the registration, if anything
+    pub fn invalid(&self) -> Option<&'static str> {
+        let partner = self.partner.trim();
+        if partner.is_empty() || partner.len() > MAX_PARTNER_LEN {
+            return Some("partner must be 1 to 64 characters");
+        }
+        if self.events.is_empty() {
+            return Some("subscribe to at least one event");
+        }
+        if self.url.len() > MAX_URL_LEN {
+            return Some("url is too long");
+        }
+        let Ok(url) = Url::parse(&self.url) else {
+            return Some("url is invalid");
+        };
+        // e2e tests point endpoints at a local mock backend
+        let insecure_ok = env_kind() == RunEnv::Mock && url.scheme() == "http";
+        if url.scheme() != "https" && !insecure_ok {
+            return Some("url must be https");
+        }
+
+        None
+    }
+
+    fn events(&self) -> String {
+        let mut events: Vec<_> = self.events.iter().map(|e| e.as_str()).collect();
+        events.sort_unstable();
+        events.dedup();
+        events.join(",")
+    }
+}
+
+/// 256 random bits, hex encoded
+pub fn new_secret() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("no randomness available");
+    hex::encode(bytes)
+}
+
+pub async fn create_endpoint(
+    db: &D1Database,
+    req: &EndpointRequest,
+    secret: &str,
+    admin: &str,
+    now: u64,
+) -> Result<Endpoint> {
+    let id = uuid::Uuid::new_v4().to_string();
+    db.prepare(
+        "INSERT INTO webhook_endpoints \
+        (id, partner, url, secret, events, active, updated_by, created_at, updated_at) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8) RETURNING *",
+    )
+    .bind(&[
+        id.as_str().into(),
+        req.partner.trim().into(),
+        req.url.as_str().into(),
+        secret.into(),
+        req.events().into(),
+        num(req.active as u64),
+        admin.into(),
+        num(now),
+    ])?
+    .first(None)
+    .await?
+    .ok_or_else(|| Error::RustError(format!("endpoint {id} wasn't written")))
+}
+
+/// `None` if there's no such endpoint
+pub async fn update_endpoint(
+    db: &D1Database,
+    id: &str,
+    req: &EndpointRequest,
+    admin: &str,
+    now: u64,
+) -> Result<Option<Endpoint>> {
+    db.prepare(
+        "UPDATE webhook_endpoints SET partner = ?2, url = ?3, events = ?4, active = ?5, \
+        updated_by = ?6, updated_at = ?7 WHERE id = ?1 RETURNING *",
+    )
+    .bind(&[
+        id.into(),
+        req.partner.trim().into(),
+        req.url.as_str().into(),
+        req.events().into(),
+        num(req.active as u64),
+        admin.into(),
+        num(now),
+    ])?
+    .first(None)
+    .await
+}
+
+/// the old secret stops working right away, partners verify with the new one from then on
+pub async fn rotate_secret(
+    db: &D1Database,
+    id: &str,
+    secret: &str,
+    admin: &str,
+    now: u64,
+) -> Result<Option<Endpoint>> {
+    db.prepare(
+        "UPDATE webhook_endpoints SET secret = ?2, updated_by = ?3, updated_at = ?4 \
+        WHERE id = ?1 RETURNING *",
+    )
+    .bind(&[id.into(), secret.into(), admin.into(), num(now)])?
+    .first(None)
+    .await
+}
+
+pub async fn list_endpoints(db: &D1Database) -> Result<Vec<Endpoint>> {
+    db.prepare("SELECT * FROM webhook_endpoints ORDER BY created_at DESC")
+        .all()
+        .await?
+        .results()
+}
+
+pub async fn endpoint(db: &D1Database, id: &str) -> Result<Option<Endpoint>> {
+    db.prepare("SELECT * FROM webhook_endpoints WHERE id = ?1")
+        .bind(&[id.into()])?
+        .first(None)
+        .await
+}
+
+/// active endpoints subscribed to `kind`
+pub async fn subscribers(db: &D1Database, kind: WebhookEventKind) -> Result<Vec<Endpoint>> {
+    let endpoints: Vec<Endpoint> = db
+        .prepare("SELECT * FROM webhook_endpoints WHERE active = 1")
+        .all()
+        .await?
+        .results()?;
+
+    Ok(endpoints
+        .into_iter()
+        .filter(|e| e.subscribes_to(kind))
+        .collect())
+}
@@ -0,0 +1,346 @@
+mod deliveries;
+mod endpoints;
+mod jwt;
+
+use deliveries::{
+    attempts, deliver, delivery, fan_out, list_deliveries, replay, replay_failed, DeliveryMessage,
+    DeliveryStatus, Outcome, MAX_REPLAY, WEBHOOK_DELIVERIES_QUEUE,
+};
+use endpoints::{
+    create_endpoint, endpoint, list_endpoints, new_secret, rotate_secret, update_endpoint,
+    EndpointRequest, WEBHOOKS_DB,
+};
+use jwt::JWT_PUBKEY;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use worker::*;
+use worker_utils::{
+    api_error::error_resp,
+    health::{Dependency, HealthCheck},
+    json_body,
+    jwt::{authorize, JwtKeys, Role},
+    maintenance::MaintenanceNotice,
+    metrics::Metrics,
+    time::now_millis,
+    webhooks::WebhookEvent,
+};
+
+const WEBHOOK_EVENTS_QUEUE_NAME: &str = "yral-webhook-events";
+const WEBHOOK_DELIVERIES_QUEUE_NAME: &str = "yral-webhook-deliveries";
+
+const DEFAULT_DELIVERIES_LIMIT: u32 = 100;
+const MAX_DELIVERIES_LIMIT: u32 = 500;
+
+static HEALTH: HealthCheck = HealthCheck::new(
+    "yral-webhooks",
+    &[
+        Dependency::D1(WEBHOOKS_DB),
+        Dependency::Queue(WEBHOOK_DELIVERIES_QUEUE),
+    ],
+);
+
+macro_rules! admin {
+    ($ctx:expr, $req:expr, $role:expr) => {
+        match authorize(
+            &JwtKeys::from_env(&$ctx.env, JWT_PUBKEY),
+            &$req,
+            "Authorization",
+            $role,
+        )
+        .await
+        {
+            Ok(claims) => claims,
+            Err((e, code)) => return e.into_response(code),
+        }
+    };
+}
+
+/// the endpoint and its secret, only returned when the secret is created or rotated
+fn with_secret(endpoint: &endpoints::Endpoint) -> Result<Response> {
+    let mut body = serde_json::to_value(endpoint)?;
+    body["secret"] = endpoint.secret.clone().into();
+    Response::from_json(&body)
+}
+
+async fn endpoints(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    Response::from_json(&list_endpoints(&db).await?)
+}
+
+/// registers a partner endpoint, the response carries its signing secret
+async fn register_endpoint(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let body: EndpointRequest = json_body!(req);
+    if let Some(msg) = body.invalid() {
+        return error_resp(msg, 400);
+    }
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    let endpoint = create_endpoint(&db, &body, &new_secret(), &admin.sub, now_millis()).await?;
+    Ok(with_secret(&endpoint)?.with_status(201))
+}
+
+async fn get_endpoint(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let Some(endpoint_id) = ctx.param("endpoint_id") else {
+        return error_resp("endpoint_id is required", 400);
+    };
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    match endpoint(&db, endpoint_id).await? {
+        Some(endpoint) => Response::from_json(&endpoint),
+        None => error_resp("endpoint not found", 404),
+    }
+}
+
+/// changes the url, subscriptions or status, disabling it cancels its pending deliveries
+async fn put_endpoint(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let Some(endpoint_id) = ctx.param("endpoint_id").cloned() else {
+        return error_resp("endpoint_id is required", 400);
+    };
+    let body: EndpointRequest = json_body!(req);
+    if let Some(msg) = body.invalid() {
+        return error_resp(msg, 400);
+    }
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    match update_endpoint(&db, &endpoint_id, &body, &admin.sub, now_millis()).await? {
+        Some(endpoint) => Response::from_json(&endpoint),
+        None => error_resp("endpoint not found", 404),
+    }
+}
+
+async fn rotate_endpoint_secret(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let Some(endpoint_id) = ctx.param("endpoint_id") else {
+        return error_resp("endpoint_id is required", 400);
+    };
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    match rotate_secret(&db, endpoint_id, &new_secret(), &admin.sub, now_millis()).await? {
+        Some(endpoint) => with_secret(&endpoint),
+        None => error_resp("endpoint not found", 404),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeliveriesQuery {
+    status: Option<DeliveryStatus>,
+    /// `created_at` of the last delivery of the previous page
+    before: Option<u64>,
+    limit: Option<u32>,
+}
+
+/// `GET /endpoints/:endpoint_id/deliveries?status=&before=&limit=`, newest first
+async fn endpoint_deliveries(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let Some(endpoint_id) = ctx.param("endpoint_id") else {
+        return error_resp("endpoint_id is required", 400);
+    };
+    let Ok(query) = req.query::<DeliveriesQuery>() else {
+        return error_resp("invalid query", 400);
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DELIVERIES_LIMIT)
+        .min(MAX_DELIVERIES_LIMIT);
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    let deliveries = list_deliveries(&db, endpoint_id, query.status, query.before, limit).await?;
+    // a short page is the last one
+    let next = if deliveries.len() == limit as usize {
+        deliveries.last().map(|d| d.created_at)
+    } else {
+        None
+    };
+
+    Response::from_json(&json!({ "deliveries": deliveries, "next": next }))
+}
+
+#[derive(Deserialize)]
+struct ReplayFailedRequest {
+    /// unix millis, failed deliveries created since then are replayed
+    since: u64,
+}
+
+/// replays the endpoint's failed deliveries, e.g. after the partner fixed an outage
+async fn replay_endpoint(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let Some(endpoint_id) = ctx.param("endpoint_id").cloned() else {
+        return error_resp("endpoint_id is required", 400);
+    };
+    let body: ReplayFailedRequest = json_body!(req);
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    if endpoint(&db, &endpoint_id).await?.is_none() {
+        return error_resp("endpoint not found", 404);
+    }
+    let replayed = replay_failed(&ctx.env, &db, &endpoint_id, body.since, now_millis()).await?;
+    console_log!(
+        "{} replayed {replayed} failed deliveries of endpoint {endpoint_id}",
+        admin.sub
+    );
+
+    Response::from_json(&json!({
+        "replayed": replayed,
+        "more": replayed == MAX_REPLAY as usize,
+    }))
+}
+
+/// a delivery with every attempt made at it
+async fn get_delivery(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    admin!(ctx, req, Role::Viewer);
+    let Some(delivery_id) = ctx.param("delivery_id") else {
+        return error_resp("delivery_id is required", 400);
+    };
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    let Some(delivery) = delivery(&db, delivery_id).await? else {
+        return error_resp("delivery not found", 404);
+    };
+    let attempts = attempts(&db, delivery_id).await?;
+
+    Response::from_json(&json!({ "delivery": delivery, "attempts": attempts }))
+}
+
+/// sends the delivery again with the endpoint's current url and secret, whatever its status
+async fn replay_delivery(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = admin!(ctx, req, Role::Operator);
+    let Some(delivery_id) = ctx.param("delivery_id") else {
+        return error_resp("delivery_id is required", 400);
+    };
+
+    let db = ctx.env.d1(WEBHOOKS_DB)?;
+    match replay(&ctx.env, &db, delivery_id, now_millis()).await? {
+        Some(delivery) => {
+            console_log!("{} replayed delivery {delivery_id}", admin.sub);
+            Ok(Response::from_json(&delivery)?.with_status(202))
+        }
+        None => error_resp("delivery not found", 404),
+    }
+}
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let (method, path) = (req.method(), req.path());
+    if let Some(notice) =
+        MaintenanceNotice::check(&env, "yral-webhooks", method.as_ref(), &path, &[]).await
+    {
+        return notice.into_response();
+    }
+
+    Router::new()
+        .get("/healthz", |_, _| HEALTH.healthz())
+        .get_async(
+            "/readyz",
+            |_, ctx| async move { HEALTH.readyz(&ctx.env).await },
+        )
+        .get_async("/endpoints", endpoints)
+        .post_async("/endpoints", register_endpoint)
+        .get_async("/endpoints/:endpoint_id", get_endpoint)
+        .put_async("/endpoints/:endpoint_id", put_endpoint)
+        .post_async(
+            "/endpoints/:endpoint_id/rotate_secret",
+            rotate_endpoint_secret,
+        )
+        .get_async("/endpoints/:endpoint_id/deliveries", endpoint_deliveries)
+        .post_async("/endpoints/:endpoint_id/replay", replay_endpoint)
+        .get_async("/deliveries/:delivery_id", get_delivery)
+        .post_async("/deliveries/:delivery_id/replay", replay_delivery)
+        .run(req, env)
+        .await
+}
+
+/// records and queues the deliveries of each event, a failed event is retried alone
+async fn run_events_batch(batch: MessageBatch<Value>, env: &Env) -> Result<()> {
+    let db = env.d1(WEBHOOKS_DB)?;
+    let metrics = Metrics::new(env, "yral-webhooks");
+    for message in batch.messages()? {
+        let event = match serde_json::from_value::<WebhookEvent>(message.body().clone()) {
+            Ok(event) => event,
+            Err(e) => {
+                console_error!("dropping malformed webhook event {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+        match fan_out(env, &db, &event, now_millis()).await {
+            Ok(deliveries) => {
+                metrics.counter("webhook_events", &[event.kind().as_str()]);
+                metrics.count(
+                    "webhook_deliveries_created",
+                    &[event.kind().as_str()],
+                    deliveries as u64,
+                );
+                message.ack();
+            }
+            Err(e) => {
+                console_error!("failed to fan out webhook event {}: {e}", event.id);
+                message.retry();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// attempts each delivery once, failures come back after their backoff
+async fn run_deliveries_batch(batch: MessageBatch<Value>, env: &Env) -> Result<()> {
+    let db = env.d1(WEBHOOKS_DB)?;
+    let metrics = Metrics::new(env, "yral-webhooks");
+    for message in batch.messages()? {
+        let msg = match serde_json::from_value::<DeliveryMessage>(message.body().clone()) {
+            Ok(msg) => msg,
+            Err(e) => {
+                console_error!("dropping malformed webhook delivery {}: {e}", message.id());
+                message.ack();
+                continue;
+            }
+        };
+        let outcome = match deliver(&db, &msg.delivery_id, now_millis()).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                // the attempt wasn't logged, e.g. d1 was unavailable
+                console_error!("webhook delivery {} errored: {e}", msg.delivery_id);
+                message.retry();
+                continue;
+            }
+        };
+        metrics.counter("webhook_deliveries", &[outcome.as_str()]);
+        match outcome {
+            Outcome::Retry(delay_seconds) => {
+                let options = QueueRetryOptionsBuilder::new()
+                    .with_delay_seconds(delay_seconds)
+                    .build();
+                message.retry_with_options(&options);
+            }
+            Outcome::Failed => {
+                console_warn!("webhook delivery {} ran out of attempts", msg.delivery_id);
+                message.ack();
+            }
+            Outcome::Delivered | Outcome::Skipped => message.ack(),
+        }
+    }
+
+    Ok(())
+}
+
+#[event(queue)]
+async fn queue(batch: MessageBatch<Value>, env: Env, _ctx: Context) -> Result<()> {
+    console_error_panic_hook::set_once();
+
+    match batch.queue().as_str() {
+        WEBHOOK_EVENTS_QUEUE_NAME => run_events_batch(batch, &env).await,
+        WEBHOOK_DELIVERIES_QUEUE_NAME => run_deliveries_batch(batch, &env).await,
+        queue => {
+            console_error!("unexpected queue {queue}");
+            batch.retry_all();
+            Ok(())
+        }
+    }
+}